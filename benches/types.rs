@@ -0,0 +1,95 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Benchmarks of type construction, type hashing, and GCSE on a type-heavy
+//! module.
+//!
+//! Run with `cargo bench --bench types`. Each benchmark prints the average
+//! time per iteration, and the construction benchmark also how many distinct
+//! allocations the constructed types occupy.
+
+use llhd::{
+    assembly::parse_module,
+    opt::{Pass, PassContext},
+    pass::GlobalCommonSubexprElim,
+    ty::*,
+};
+use std::{
+    collections::HashSet,
+    hint::black_box,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Run a closure repeatedly and print the average time per iteration.
+fn bench(name: &str, iters: u32, mut f: impl FnMut()) {
+    f();
+    let start = Instant::now();
+    for _ in 0..iters {
+        f();
+    }
+    let per_iter = start.elapsed() / iters;
+    println!("{:<24} {:>12?}/iter", name, per_iter);
+}
+
+/// Construct a deeply nested aggregate type.
+fn nested_ty(depth: usize) -> Type {
+    let mut ty = struct_ty(vec![int_ty(32), signal_ty(int_ty(8))]);
+    for i in 0..depth {
+        ty = struct_ty(vec![array_ty(4, ty.clone()), int_ty(i + 1), ty]);
+    }
+    ty
+}
+
+/// Generate a module whose values carry large aggregate types.
+fn type_heavy_module(units: usize, values: usize) -> String {
+    let ty = "{i32, {i16, [4 x i8]}, [2 x {i1, i64}]}";
+    let mut src = String::new();
+    for u in 0..units {
+        src += &format!("func @f{} ({} %x) void {{\n%entry:\n", u, ty);
+        for v in 0..values {
+            // Every value appears twice, such that GCSE has work to do.
+            src += &format!("    %a{} = [4 x {} %x]\n", v, ty);
+            src += &format!("    %b{} = [4 x {} %x]\n", v, ty);
+        }
+        src += "    ret\n}\n";
+    }
+    src
+}
+
+fn main() {
+    // Constructing the same type repeatedly yields a single allocation.
+    let mut tys = vec![];
+    bench("construct nested type", 1000, || tys.push(nested_ty(8)));
+    let distinct: HashSet<_> = tys.iter().map(Arc::as_ptr).collect();
+    println!(
+        "{:<24} {} types in {} allocation(s)",
+        "",
+        tys.len(),
+        distinct.len()
+    );
+
+    // Hashing a type only looks at the types directly nested in it.
+    let ty = nested_ty(12);
+    bench("hash nested type", 100_000, || {
+        let mut set = HashSet::new();
+        set.insert(black_box(ty.clone()));
+        black_box(set);
+    });
+
+    // GCSE on a module with many aggregate-typed values.
+    let src = type_heavy_module(16, 200);
+    let ctx = PassContext::default();
+    let mut total = Duration::default();
+    let runs = 10;
+    for _ in 0..runs {
+        let mut module = parse_module(&src).unwrap();
+        let start = Instant::now();
+        GlobalCommonSubexprElim::run_on_module(&ctx, &mut module);
+        total += start.elapsed();
+    }
+    println!(
+        "{:<24} {:>12?}/iter",
+        "gcse type-heavy module",
+        total / runs
+    );
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+// C API of LLHD. Available if the library is built with the `llhd-capi`
+// feature; see the documentation of the `capi` module for details.
+
+#ifndef LLHD_H
+#define LLHD_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct llhd_module llhd_module;
+typedef struct llhd_netlist llhd_netlist;
+typedef uint32_t llhd_unit;
+typedef uint32_t llhd_value;
+
+#define LLHD_INVALID UINT32_MAX
+
+#define LLHD_UNIT_FUNCTION 0
+#define LLHD_UNIT_PROCESS 1
+#define LLHD_UNIT_ENTITY 2
+
+void llhd_string_free(char *s);
+
+llhd_module *llhd_module_new(void);
+void llhd_module_free(llhd_module *module);
+llhd_module *llhd_module_parse(const char *src, char **error);
+char *llhd_module_print(const llhd_module *module);
+bool llhd_module_verify(const llhd_module *module, char **error);
+void llhd_module_link(llhd_module *module);
+bool llhd_module_run_passes(llhd_module *module, const char *passes,
+                            char **error);
+bool llhd_module_declare(llhd_module *module, const char *name,
+                         const char *const *inputs, size_t num_inputs,
+                         const char *const *outputs, size_t num_outputs,
+                         char **error);
+size_t llhd_module_num_units(const llhd_module *module);
+llhd_unit llhd_module_unit(const llhd_module *module, size_t index);
+llhd_unit llhd_module_find_unit(const llhd_module *module, const char *name);
+
+char *llhd_unit_name(const llhd_module *module, llhd_unit unit);
+int llhd_unit_kind(const llhd_module *module, llhd_unit unit);
+size_t llhd_unit_num_insts(const llhd_module *module, llhd_unit unit);
+size_t llhd_unit_num_args(const llhd_module *module, llhd_unit unit);
+llhd_value llhd_unit_arg(const llhd_module *module, llhd_unit unit,
+                         size_t index);
+
+char *llhd_value_name(const llhd_module *module, llhd_unit unit,
+                      llhd_value value);
+char *llhd_value_type(const llhd_module *module, llhd_unit unit,
+                      llhd_value value);
+
+llhd_netlist *llhd_netlist_new(const char *name);
+void llhd_netlist_free(llhd_netlist *nl);
+bool llhd_netlist_input(llhd_netlist *nl, const char *name, const char *ty,
+                        char **error);
+bool llhd_netlist_output(llhd_netlist *nl, const char *name, const char *ty,
+                         char **error);
+bool llhd_netlist_net(llhd_netlist *nl, const char *name, const char *ty,
+                      char **error);
+bool llhd_netlist_cell(llhd_netlist *nl, const char *unit,
+                       const char *const *inputs, size_t num_inputs,
+                       const char *const *outputs, size_t num_outputs);
+bool llhd_netlist_connect(llhd_netlist *nl, const char *a, const char *b);
+llhd_unit llhd_netlist_build(const llhd_netlist *nl, llhd_module *module,
+                             char **error);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // LLHD_H
@@ -0,0 +1,297 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{
+    ir::{prelude::*, LinkedUnit},
+    ty::{Type, TypeKind},
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// A class of operations with similar hardware cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostClass {
+    /// Bitwise logic, such as `and` or `not`.
+    Logic,
+    /// Addition, subtraction, and negation.
+    Arithmetic,
+    /// Multiplication.
+    Multiply,
+    /// Division and remainder.
+    Divide,
+    /// Equality and relational comparisons.
+    Compare,
+    /// Shifts by a dynamic amount.
+    Shift,
+    /// Selection of an array element by a dynamic index.
+    Mux,
+}
+
+impl std::fmt::Display for CostClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CostClass::Logic => write!(f, "logic"),
+            CostClass::Arithmetic => write!(f, "arithmetic"),
+            CostClass::Multiply => write!(f, "multiply"),
+            CostClass::Divide => write!(f, "divide"),
+            CostClass::Compare => write!(f, "compare"),
+            CostClass::Shift => write!(f, "shift"),
+            CostClass::Mux => write!(f, "mux"),
+        }
+    }
+}
+
+/// Estimate the number of two-input gates needed to implement an instruction.
+///
+/// The estimate assumes the simplest implementation of each operation on
+/// `w`-bit operands: `w` gates for bitwise logic, ripple-carry adders of `5w`
+/// gates, array multipliers and dividers of `6w²` and `8w²` gates, and a
+/// stage of `3w` gates per bit of a shift amount or per array element of a
+/// mux. A minimum or maximum is a comparison followed by a two-way mux.
+/// Counting the one bits takes an adder tree of `5w` gates, and counting the
+/// leading or trailing zeros a chain of `w` two-way muxes as wide as the
+/// count. An `add` fused into a multiply-accumulate counts as part of the
+/// multiplier. Returns `None` for instructions which merely route bits, such as
+/// constants, field accesses, and signal operations, and for instructions
+/// accounted for separately, such as `reg`, `call`, and `inst`.
+pub fn gate_estimate(unit: &Unit, inst: Inst) -> Option<(CostClass, usize)> {
+    let data = &unit[inst];
+    let result_width = || bit_width(&unit.inst_type(inst));
+    let arg_width = |i: usize| bit_width(&unit.value_type(data.args()[i]));
+    Some(match data.opcode() {
+        Opcode::Not | Opcode::And | Opcode::Or | Opcode::Xor => (CostClass::Logic, result_width()),
+        Opcode::Neg => (CostClass::Arithmetic, 2 * result_width()),
+        Opcode::Popcount => (CostClass::Arithmetic, 5 * result_width()),
+        Opcode::Clz | Opcode::Ctz => {
+            let w = result_width();
+            let count_width = (usize::BITS - w.leading_zeros()) as usize;
+            (CostClass::Mux, 3 * w * count_width)
+        }
+        Opcode::Add if unit.inst_annotations(inst).is_mac() => {
+            (CostClass::Multiply, 5 * result_width())
+        }
+        Opcode::Add | Opcode::Sub => (CostClass::Arithmetic, 5 * result_width()),
+        Opcode::Uaddo | Opcode::Saddo | Opcode::Usubo | Opcode::Ssubo => {
+            (CostClass::Arithmetic, 5 * arg_width(0) + 2)
+        }
+        Opcode::Umul | Opcode::Smul | Opcode::Umulh | Opcode::Smulh => {
+            (CostClass::Multiply, 6 * result_width().pow(2))
+        }
+        Opcode::Udiv | Opcode::Sdiv | Opcode::Umod | Opcode::Smod | Opcode::Urem | Opcode::Srem => {
+            (CostClass::Divide, 8 * result_width().pow(2))
+        }
+        Opcode::Eq | Opcode::Neq => (CostClass::Compare, 2 * arg_width(0)),
+        Opcode::Ult
+        | Opcode::Ugt
+        | Opcode::Ule
+        | Opcode::Uge
+        | Opcode::Slt
+        | Opcode::Sgt
+        | Opcode::Sle
+        | Opcode::Sge => (CostClass::Compare, 5 * arg_width(0)),
+        Opcode::Umin | Opcode::Umax | Opcode::Smin | Opcode::Smax => {
+            (CostClass::Compare, 8 * result_width())
+        }
+        Opcode::Shl | Opcode::Shr => (CostClass::Shift, 3 * arg_width(0) * arg_width(2)),
+        Opcode::Mux => {
+            let ty = unit.value_type(data.args()[0]);
+            let n = ty.unwrap_array().0;
+            (CostClass::Mux, 3 * result_width() * n.saturating_sub(1))
+        }
+        _ => return None,
+    })
+}
+
+/// An estimate of the hardware cost of a single unit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitCost {
+    /// The estimated number of gates of the unit itself, by class of
+    /// operation. See `gate_estimate`.
+    pub gates: BTreeMap<CostClass, usize>,
+    /// The number of bits stored by `reg` instructions in the unit.
+    pub register_bits: usize,
+    /// The number of signals declared in the unit, excluding its ports.
+    pub num_signals: usize,
+    /// The number of bits of the signals declared in the unit.
+    pub signal_bits: usize,
+    /// The number of levels of `inst` and `call` below the unit.
+    pub depth: usize,
+    /// The estimated number of gates of the unit and all units it
+    /// instantiates or calls, counted once per `inst` or `call` and once per
+    /// copy of a replicated instance.
+    pub hierarchy_gates: usize,
+    /// The number of register bits of the unit and all units it instantiates
+    /// or calls, counted once per `inst` or `call` and once per copy of a
+    /// replicated instance.
+    pub hierarchy_register_bits: usize,
+}
+
+impl UnitCost {
+    /// Compute the cost of a unit on its own, excluding the hierarchy below.
+    pub fn new(unit: &Unit) -> Self {
+        let mut cost = Self::default();
+        for inst in unit.all_insts() {
+            if let Some((class, gates)) = gate_estimate(unit, inst) {
+                *cost.gates.entry(class).or_insert(0) += gates;
+            }
+            match unit[inst].opcode() {
+                Opcode::Reg => {
+                    cost.register_bits += bit_width(&unit.value_type(unit[inst].args()[0]))
+                }
+                Opcode::Sig => {
+                    cost.num_signals += 1;
+                    cost.signal_bits += bit_width(&unit.inst_type(inst));
+                }
+                _ => (),
+            }
+        }
+        cost
+    }
+
+    /// Return the estimated number of gates of the unit itself.
+    pub fn total_gates(&self) -> usize {
+        self.gates.values().sum()
+    }
+}
+
+/// Hardware cost estimates of all units in a module.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleCost {
+    units: HashMap<UnitId, UnitCost>,
+}
+
+impl ModuleCost {
+    /// Estimate the cost of all units in a module.
+    pub fn new(module: &Module) -> Self {
+        let mut units: HashMap<UnitId, UnitCost> = module
+            .units()
+            .map(|unit| (unit.id(), UnitCost::new(&unit)))
+            .collect();
+
+        // Accumulate the cost of the hierarchy below each unit. Recursive
+        // calls are not followed.
+        let mut done = HashMap::new();
+        for unit in module.units() {
+            accumulate(module, unit.id(), &mut units, &mut done, &mut vec![]);
+        }
+        Self { units }
+    }
+
+    /// Get the cost of a unit.
+    pub fn get(&self, unit: UnitId) -> Option<&UnitCost> {
+        self.units.get(&unit)
+    }
+}
+
+impl std::ops::Index<UnitId> for ModuleCost {
+    type Output = UnitCost;
+
+    fn index(&self, idx: UnitId) -> &UnitCost {
+        &self.units[&idx]
+    }
+}
+
+/// Compute the depth and hierarchy totals of a unit, see `ModuleCost::new`.
+///
+/// Returns the depth, hierarchy gates, and hierarchy register bits.
+fn accumulate(
+    module: &Module,
+    id: UnitId,
+    units: &mut HashMap<UnitId, UnitCost>,
+    done: &mut HashMap<UnitId, (usize, usize, usize)>,
+    stack: &mut Vec<UnitId>,
+) -> (usize, usize, usize) {
+    if let Some(&totals) = done.get(&id) {
+        return totals;
+    }
+    let own = &units[&id];
+    let mut totals = (0, own.total_gates(), own.register_bits);
+    if stack.contains(&id) {
+        return totals;
+    }
+    stack.push(id);
+    let unit = module.unit(id);
+    for inst in unit.all_insts() {
+        let ext = match unit[inst].opcode() {
+            Opcode::Inst | Opcode::Call => unit[inst].get_ext_unit().unwrap(),
+            _ => continue,
+        };
+        if let Some(LinkedUnit::Def(child)) = module.lookup(&unit[ext].name) {
+            let (depth, gates, bits) = accumulate(module, child, units, done, stack);
+            let copies = unit.inst_replication(inst).unwrap_or(1);
+            totals.0 = totals.0.max(depth + 1);
+            totals.1 += gates * copies;
+            totals.2 += bits * copies;
+        }
+    }
+    stack.pop();
+    let cost = units.get_mut(&id).unwrap();
+    cost.depth = totals.0;
+    cost.hierarchy_gates = totals.1;
+    cost.hierarchy_register_bits = totals.2;
+    done.insert(id, totals);
+    totals
+}
+
+/// Compute the number of bits needed to represent a value of a type.
+///
+/// Signals count as the type they carry. Types without a bit representation,
+/// such as time and pointers, have zero bits.
+fn bit_width(ty: &Type) -> usize {
+    match ty.as_ref() {
+        TypeKind::IntType(w) => *w,
+        TypeKind::EnumType(n) => n.next_power_of_two().trailing_zeros() as usize,
+        TypeKind::SignalType(ty) => bit_width(ty),
+        TypeKind::ArrayType(n, ty) => n * bit_width(ty),
+        TypeKind::StructType(fields) | TypeKind::NamedStructType(_, fields) => {
+            fields.iter().map(bit_width).sum()
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn adder_hierarchy() {
+        let module = parse_module(
+            "
+            entity @add (i8$ %a, i8$ %b) -> (i8$ %c) {
+                %ap = prb i8$ %a
+                %bp = prb i8$ %b
+                %sum = add i8 %ap, %bp
+                %eq = eq i8 %ap, %bp
+                %t = const time 0s 1e
+                drv i8$ %c, %sum, %t
+            }
+            entity @top (i1$ %clk, i8$ %a, i8$ %b) -> (i8$ %q) {
+                %zero = const i8 0
+                %c0 = sig i8 %zero
+                %c1 = sig i8 %zero
+                inst @add (i8$ %a, i8$ %b) -> (i8$ %c0)
+                inst @add (i8$ %a, i8$ %b) -> (i8$ %c1)
+                %clkp = prb i1$ %clk
+                %c0p = prb i8$ %c0
+                reg i8$ %q, [%c0p, rise %clkp]
+            }
+            ",
+        )
+        .unwrap();
+        let cost = ModuleCost::new(&module);
+        let add = module.units().find(|u| u.name().to_string() == "@add");
+        let top = module.units().find(|u| u.name().to_string() == "@top");
+        let add = &cost[add.unwrap().id()];
+        let top = &cost[top.unwrap().id()];
+        assert_eq!(add.gates[&CostClass::Arithmetic], 40);
+        assert_eq!(add.gates[&CostClass::Compare], 16);
+        assert_eq!(add.depth, 0);
+        assert_eq!(top.total_gates(), 0);
+        assert_eq!(top.register_bits, 8);
+        assert_eq!((top.num_signals, top.signal_bits), (2, 16));
+        assert_eq!(top.depth, 1);
+        assert_eq!(top.hierarchy_gates, 112);
+        assert_eq!(top.hierarchy_register_bits, 8);
+    }
+}
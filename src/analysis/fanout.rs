@@ -0,0 +1,95 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::ir::prelude::*;
+use std::collections::HashMap;
+
+/// The fan-out of a single value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fanout {
+    /// The number of instructions which use the value.
+    pub users: usize,
+    /// The number of operands which read the value, counting each copy of a
+    /// replicated instance. This approximates the number of sinks the net
+    /// carrying the value drives in hardware.
+    pub load: usize,
+}
+
+/// The fan-out of all values in a unit.
+#[derive(Debug, Clone, Default)]
+pub struct FanoutTable {
+    values: HashMap<Value, Fanout>,
+}
+
+impl FanoutTable {
+    /// Compute the fan-out of the arguments and instruction results of a
+    /// unit.
+    pub fn new(unit: &Unit) -> Self {
+        let mut values = HashMap::new();
+        let results = unit.all_insts().flat_map(|inst| unit.get_inst_result(inst));
+        for value in unit.args().chain(results) {
+            let mut fanout = Fanout::default();
+            for &inst in unit.uses(value) {
+                let copies = unit.inst_replication(inst).unwrap_or(1);
+                let operands = unit[inst].args().iter().filter(|&&arg| arg == value);
+                fanout.users += 1;
+                fanout.load += operands.count() * copies;
+            }
+            values.insert(value, fanout);
+        }
+        Self { values }
+    }
+
+    /// Get the fan-out of a value.
+    pub fn get(&self, value: Value) -> Fanout {
+        self.values.get(&value).cloned().unwrap_or_default()
+    }
+
+    /// Get the values whose load exceeds a threshold, highest load first.
+    pub fn above(&self, threshold: usize) -> Vec<(Value, Fanout)> {
+        let mut values: Vec<_> = self
+            .values
+            .iter()
+            .filter(|(_, fanout)| fanout.load > threshold)
+            .map(|(&value, &fanout)| (value, fanout))
+            .collect();
+        values.sort_by_key(|&(value, fanout)| (std::cmp::Reverse(fanout.load), value));
+        values
+    }
+
+    /// Return the highest load of any value in the unit.
+    pub fn max_load(&self) -> usize {
+        self.values.values().map(|f| f.load).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn loads() {
+        let module = parse_module(
+            "
+            entity @lane (i4$ %a) -> () {}
+            entity @top (i4$ %a, i4$ %b) -> () {
+                %ap = prb i4$ %a
+                %x = add i4 %ap, %ap
+                %y = xor i4 %x, %ap
+                %t = const time 0s 1e
+                drv i4$ %b, %y, %t
+                inst @lane (i4$ %a) -> () replicate 4
+            }
+            ",
+        )
+        .unwrap();
+        let unit = module.units().nth(1).unwrap();
+        let table = FanoutTable::new(&unit);
+        let a = unit.input_arg(0);
+        let ap = unit.inst_result(unit.all_insts().next().unwrap());
+        assert_eq!(table.get(a), Fanout { users: 2, load: 5 });
+        assert_eq!(table.get(ap), Fanout { users: 2, load: 3 });
+        assert_eq!(table.max_load(), 5);
+        assert_eq!(table.above(2), vec![(a, table.get(a)), (ap, table.get(ap))]);
+    }
+}
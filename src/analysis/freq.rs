@@ -0,0 +1,81 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::ir::prelude::*;
+use std::collections::HashMap;
+
+/// The estimated execution frequencies of the blocks of a function or process.
+///
+/// The frequencies are relative to the entry block, which has a frequency of
+/// one. Each block passes its frequency on to its successors, split according
+/// to `Unit::branch_probabilities` at conditional branches. Back edges are not
+/// followed, such that the frequency of a block is the probability of it
+/// being reached from the entry in one pass through the unit. Unreachable
+/// blocks have a frequency of zero.
+#[derive(Debug, Clone)]
+pub struct BlockFrequencies {
+    freqs: HashMap<Block, f64>,
+}
+
+impl BlockFrequencies {
+    /// Estimate the block frequencies of a function or process.
+    pub fn new(unit: &Unit) -> Self {
+        let dt = unit.domtree();
+        let mut freqs = HashMap::new();
+        freqs.insert(unit.entry(), 1.0);
+
+        // Visit the blocks in reverse post-order, such that all forward edges
+        // into a block have been accounted for before it is visited.
+        for &bb in dt.blocks_post_order().iter().rev() {
+            let freq = freqs.get(&bb).cloned().unwrap_or(0.0);
+            let term = unit.terminator(bb);
+            let probs = match unit[term].opcode() {
+                Opcode::BrCond => unit.branch_probabilities(term),
+                _ => [1.0, 0.0],
+            };
+            for (&succ, prob) in unit[term].blocks().iter().zip(probs.iter()) {
+                if dt.block_order(succ) >= dt.block_order(bb) {
+                    continue;
+                }
+                *freqs.entry(succ).or_insert(0.0) += freq * prob;
+            }
+        }
+        Self { freqs }
+    }
+
+    /// Get the estimated frequency of a block.
+    pub fn get(&self, bb: Block) -> f64 {
+        self.freqs.get(&bb).cloned().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn weighted_diamond_in_loop() {
+        let module = parse_module(
+            "
+            proc @p (i1$ %a) -> () {
+            %entry:
+                br %loop
+            %loop:
+                %c = prb i1$ %a
+                br %c, %cold, %hot weights [1, 3]
+            %cold:
+                br %join
+            %hot:
+                br %join
+            %join:
+                wait %loop, %a
+            }
+            ",
+        )
+        .unwrap();
+        let unit = module.units().next().unwrap();
+        let freqs = unit.block_frequencies();
+        let blocks: Vec<_> = unit.blocks().collect();
+        let freqs: Vec<_> = blocks.iter().map(|&bb| freqs.get(bb)).collect();
+        assert_eq!(freqs, vec![1.0, 1.0, 0.25, 0.75, 1.0]);
+    }
+}
@@ -0,0 +1,419 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{
+    ir::{prelude::*, TypeDef},
+    ty::{int_ty, Type},
+};
+use std::collections::{BTreeSet, HashSet};
+
+/// The roles of the fields of a ready/valid handshake bundle.
+///
+/// A handshake bundle is a struct with an `i1` field named `valid`, which the
+/// source raises when the payload carries data, an `i1` field named `ready`,
+/// which the sink raises when it accepts data, and any number of payload
+/// fields. A transfer happens whenever both `valid` and `ready` are high.
+/// Bundles are recognized through the named type of the struct, either as a
+/// signal of the struct, such as `%stream$` for `%stream = type {i1 %valid, i1
+/// %ready, i8 %data}`, or as the corresponding struct of signals, such as
+/// `{i1$, i1$, i8$}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    /// The index of the `valid` field.
+    pub valid: usize,
+    /// The index of the `ready` field.
+    pub ready: usize,
+    /// The indices of the payload fields.
+    pub payload: Vec<usize>,
+    /// Whether the bundle is a struct of signals rather than a signal.
+    pub split: bool,
+}
+
+impl Handshake {
+    /// Define a handshake bundle type.
+    ///
+    /// The struct has the `valid` and `ready` fields first, followed by the
+    /// payload fields.
+    pub fn type_def(name: impl Into<String>, payload: Vec<(Type, Option<String>)>) -> TypeDef {
+        let fields = vec![
+            (int_ty(1), Some("valid".to_string())),
+            (int_ty(1), Some("ready".to_string())),
+        ];
+        TypeDef::with_fields(name, fields.into_iter().chain(payload).collect())
+    }
+
+    /// Recognize the type of a handshake bundle.
+    ///
+    /// Returns `None` if `ty` is neither a signal of a named struct type with
+    /// `valid` and `ready` fields of type `i1`, nor the struct of signals
+    /// which bundle splitting turns such a signal into.
+    pub fn recognize(module: &Module, ty: &Type) -> Option<Self> {
+        // Bundle splitting drops the name of the struct type, so a struct of
+        // signals is matched against the fields of the named types.
+        let (ty, split) = if ty.is_signal() && ty.unwrap_signal().is_struct() {
+            (ty.unwrap_signal().clone(), false)
+        } else if ty.is_struct() && ty.unwrap_struct().iter().all(|ty| ty.is_signal()) {
+            let fields: Vec<_> = ty
+                .unwrap_struct()
+                .iter()
+                .map(|ty| ty.unwrap_signal().clone())
+                .collect();
+            let def = module
+                .types()
+                .find(|d| d.ty.struct_name().is_some() && d.ty.unwrap_struct() == &fields[..])?;
+            (def.ty.clone(), true)
+        } else {
+            return None;
+        };
+        let fields = ty.unwrap_struct();
+        let valid = module.field_index(&ty, "valid")?;
+        let ready = module.field_index(&ty, "ready")?;
+        if fields[valid] != int_ty(1) || fields[ready] != int_ty(1) {
+            return None;
+        }
+        Some(Self {
+            valid,
+            ready,
+            payload: (0..fields.len())
+                .filter(|&i| i != valid && i != ready)
+                .collect(),
+            split,
+        })
+    }
+
+    /// Bundle up the signals of a handshake as a struct of signals.
+    ///
+    /// The signals are arranged in the order of `type_def`.
+    pub fn bundle(
+        unit: &mut UnitBuilder,
+        valid: Value,
+        ready: Value,
+        payload: Vec<Value>,
+    ) -> Value {
+        let fields = vec![valid, ready];
+        unit.ins()
+            .strukt(fields.into_iter().chain(payload).collect())
+    }
+
+    /// Get the `valid` signal of a bundle.
+    pub fn valid_signal(&self, unit: &mut UnitBuilder, bundle: Value) -> Value {
+        unit.ins().ext_field(bundle, self.valid)
+    }
+
+    /// Get the `ready` signal of a bundle.
+    pub fn ready_signal(&self, unit: &mut UnitBuilder, bundle: Value) -> Value {
+        unit.ins().ext_field(bundle, self.ready)
+    }
+
+    /// Get the signals of the payload fields of a bundle.
+    pub fn payload_signals(&self, unit: &mut UnitBuilder, bundle: Value) -> Vec<Value> {
+        self.payload
+            .iter()
+            .map(|&i| unit.ins().ext_field(bundle, i))
+            .collect()
+    }
+
+    /// Probe whether a bundle transfers data, i.e. whether both `valid` and
+    /// `ready` are high.
+    pub fn transfer(&self, unit: &mut UnitBuilder, bundle: Value) -> Value {
+        let valid = self.valid_signal(unit, bundle);
+        let valid = unit.ins().prb(valid);
+        let ready = self.ready_signal(unit, bundle);
+        let ready = unit.ins().prb(ready);
+        unit.ins().and(valid, ready)
+    }
+}
+
+/// A handshake bundle which breaks the ready/valid protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeViolation {
+    /// The bundle.
+    pub bundle: Value,
+    /// The rule which the bundle breaks.
+    pub kind: HandshakeViolationKind,
+    /// The instructions involved, in layout order.
+    pub insts: Vec<Inst>,
+}
+
+/// The rules of the ready/valid protocol which `check_handshakes` enforces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeViolationKind {
+    /// The unit drives `ready` as well as `valid` or the payload, so it acts
+    /// as both the source and the sink. The instructions are the drives of
+    /// `ready`.
+    BothSides,
+    /// The value driven onto `valid` depends on `ready` without passing
+    /// through a register, such that the source waits for the sink. The
+    /// instructions are the drives of `valid`.
+    ValidDependsOnReady,
+}
+
+impl HandshakeViolation {
+    /// Describe the violation in a human-readable message.
+    pub fn message(&self, unit: &Unit) -> String {
+        let insts = self
+            .insts
+            .iter()
+            .map(|inst| format!("`{}`", inst.dump(unit)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self.kind {
+            HandshakeViolationKind::BothSides => format!(
+                "handshake {} is driven from both sides, with ready driven by {}",
+                self.bundle.dump(unit),
+                insts
+            ),
+            HandshakeViolationKind::ValidDependsOnReady => format!(
+                "valid of handshake {} depends combinationally on its ready in {}",
+                self.bundle.dump(unit),
+                insts
+            ),
+        }
+    }
+}
+
+/// Check the handshake bundles of a unit against the ready/valid protocol.
+///
+/// The check is structural: it considers the arguments and signals of the
+/// unit whose type `Handshake::recognize` accepts, and the `drv`, `del`, and
+/// `reg` instructions which target their fields. Signals driven by instances
+/// are not considered, since a unit may well connect a source to a sink. A
+/// dependency of `valid` on `ready` is only found through the data flow, not
+/// through control flow or memory.
+pub fn check_handshakes(module: &Module, unit: &Unit) -> Vec<HandshakeViolation> {
+    let mut bundles: Vec<_> = unit.args().collect();
+    bundles.extend(unit.all_insts().flat_map(|inst| unit.get_inst_result(inst)));
+    let mut violations = vec![];
+    for bundle in bundles {
+        let hs = match Handshake::recognize(module, &unit.value_type(bundle)) {
+            Some(hs) => hs,
+            None => continue,
+        };
+
+        // Find the drives of the fields. A drive of the entire bundle covers
+        // all of them.
+        let whole: BTreeSet<_> = Some(bundle).filter(|_| !hs.split).into_iter().collect();
+        let field = |index| projections(unit, field_roots(unit, bundle, index));
+        let ready = field(hs.ready);
+        let mut sources = drives(unit, &whole);
+        let mut sinks = sources.clone();
+        sinks.extend(drives(unit, &ready));
+        for &index in std::iter::once(&hs.valid).chain(&hs.payload) {
+            sources.extend(drives(unit, &field(index)));
+        }
+        if !sources.is_empty() && !sinks.is_empty() {
+            violations.push(HandshakeViolation {
+                bundle,
+                kind: HandshakeViolationKind::BothSides,
+                insts: in_layout_order(unit, sinks),
+            });
+        }
+
+        // Find the drives of `valid` whose value depends on a probe of
+        // `ready`, either directly or through a probe of the entire bundle.
+        let mut ready_values: HashSet<Value> = ready
+            .iter()
+            .flat_map(|&signal| probes(unit, signal))
+            .collect();
+        for value in whole.iter().flat_map(|&signal| probes(unit, signal)) {
+            for &inst in unit.uses(value) {
+                if unit[inst].opcode() == Opcode::ExtField && unit[inst].imms()[0] == hs.ready {
+                    ready_values.insert(unit.inst_result(inst));
+                }
+            }
+        }
+        let mut valid_drives = drives(unit, &whole);
+        valid_drives.extend(drives(unit, &field(hs.valid)));
+        let dependent: Vec<_> = in_layout_order(unit, valid_drives)
+            .into_iter()
+            .filter(|&inst| {
+                let args = unit[inst].args();
+                match unit[inst].opcode() {
+                    Opcode::Drv => depends_on(unit, vec![args[1]], &ready_values),
+                    Opcode::DrvCond => depends_on(unit, vec![args[1], args[3]], &ready_values),
+                    Opcode::Del => ready.contains(&args[1]),
+                    Opcode::DelCond => {
+                        ready.contains(&args[1]) || depends_on(unit, vec![args[3]], &ready_values)
+                    }
+                    _ => false,
+                }
+            })
+            .collect();
+        if !dependent.is_empty() {
+            violations.push(HandshakeViolation {
+                bundle,
+                kind: HandshakeViolationKind::ValidDependsOnReady,
+                insts: dependent,
+            });
+        }
+    }
+    violations
+}
+
+/// Find the values which refer to a field of a bundle directly, either as a
+/// projection out of the bundle, or as the field the bundle was built from.
+fn field_roots(unit: &Unit, bundle: Value, field: usize) -> Vec<Value> {
+    let mut roots: Vec<_> = unit
+        .uses(bundle)
+        .iter()
+        .filter(|&&inst| unit[inst].opcode() == Opcode::ExtField && unit[inst].imms()[0] == field)
+        .map(|&inst| unit.inst_result(inst))
+        .collect();
+    if let Some(inst) = unit.get_value_inst(bundle) {
+        if unit[inst].opcode() == Opcode::Struct {
+            roots.push(unit[inst].args()[field]);
+        }
+    }
+    roots
+}
+
+/// Find a set of signals and all signals projected out of them.
+fn projections(unit: &Unit, mut todo: Vec<Value>) -> BTreeSet<Value> {
+    let mut signals = BTreeSet::new();
+    while let Some(signal) = todo.pop() {
+        if !signals.insert(signal) {
+            continue;
+        }
+        for &inst in unit.uses(signal) {
+            let projects = matches!(
+                unit[inst].opcode(),
+                Opcode::ExtField | Opcode::ExtSlice | Opcode::Alias
+            );
+            if projects && unit[inst].args()[0] == signal {
+                todo.push(unit.inst_result(inst));
+            }
+        }
+    }
+    signals
+}
+
+/// Find the `drv`, `del`, and `reg` instructions which target a set of
+/// signals.
+fn drives(unit: &Unit, signals: &BTreeSet<Value>) -> BTreeSet<Inst> {
+    signals
+        .iter()
+        .flat_map(|&signal| unit.uses(signal).iter().cloned())
+        .filter(|&inst| {
+            let drives = matches!(
+                unit[inst].opcode(),
+                Opcode::Drv | Opcode::DrvCond | Opcode::Del | Opcode::DelCond | Opcode::Reg
+            );
+            drives && signals.contains(&unit[inst].args()[0])
+        })
+        .collect()
+}
+
+/// Find the probes of a signal.
+fn probes<'a>(unit: &'a Unit, signal: Value) -> impl Iterator<Item = Value> + 'a {
+    unit.uses(signal)
+        .iter()
+        .filter(move |&&inst| unit[inst].opcode() == Opcode::Prb)
+        .map(move |&inst| unit.inst_result(inst))
+}
+
+/// Check whether any of the values is computed from one of the targets,
+/// without passing through a probe of another signal.
+fn depends_on(unit: &Unit, mut todo: Vec<Value>, targets: &HashSet<Value>) -> bool {
+    let mut seen = HashSet::new();
+    while let Some(value) = todo.pop() {
+        if targets.contains(&value) {
+            return true;
+        }
+        if !seen.insert(value) {
+            continue;
+        }
+        if let Some(inst) = unit.get_value_inst(value) {
+            if unit[inst].opcode() != Opcode::Prb {
+                todo.extend(unit[inst].args().iter().filter(|v| !v.is_invalid()));
+            }
+        }
+    }
+    false
+}
+
+/// Sort a set of instructions in layout order.
+fn in_layout_order(unit: &Unit, insts: BTreeSet<Inst>) -> Vec<Inst> {
+    unit.all_insts()
+        .filter(|inst| insts.contains(inst))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::parse_module,
+        ty::{signal_ty, struct_ty},
+        value::TimeValue,
+    };
+    use num::{BigRational, Zero};
+
+    #[test]
+    fn build_and_recognize() {
+        let mut module = Module::new();
+        module.add_type(Handshake::type_def(
+            "stream",
+            vec![(int_ty(8), Some("data".to_string()))],
+        ));
+        let ty = module.lookup_type("stream").unwrap().ty.clone();
+        let hs = Handshake::recognize(&module, &signal_ty(ty.clone())).unwrap();
+        assert_eq!(
+            hs,
+            Handshake {
+                valid: 0,
+                ready: 1,
+                payload: vec![2],
+                split: false,
+            }
+        );
+        let fields = vec![int_ty(1), int_ty(1), int_ty(8)];
+        let split = struct_ty(fields.into_iter().map(signal_ty).collect());
+        assert!(Handshake::recognize(&module, &split).unwrap().split);
+        assert_eq!(Handshake::recognize(&module, &ty), None);
+
+        // Raising valid only once ready is high breaks the protocol.
+        let mut sig = Signature::new();
+        sig.add_output(signal_ty(ty));
+        let mut data = UnitData::new(UnitKind::Entity, UnitName::global("eager"), sig);
+        {
+            let mut unit = UnitBuilder::new_anonymous(&mut data);
+            let out = unit.output_arg(0);
+            let transfer = hs.transfer(&mut unit, out);
+            let valid = hs.valid_signal(&mut unit, out);
+            let delay = unit
+                .ins()
+                .const_time(TimeValue::new(BigRational::zero(), 0, 1));
+            unit.ins().drv(valid, transfer, delay);
+        }
+        let eager = module.add_unit(data);
+        let violations = check_handshakes(&module, &module.unit(eager));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].kind,
+            HandshakeViolationKind::ValidDependsOnReady
+        );
+    }
+
+    #[test]
+    fn drive_of_entire_bundle() {
+        let module = parse_module(
+            "
+            %stream = type {i1 %valid, i1 %ready, i8 %data}
+            entity @loop (%stream$ %in) -> (%stream$ %out) {
+                %v = prb %stream$ %in
+                %t = const time 0s 1e
+                drv %stream$ %out, %v, %t
+            }
+            ",
+        )
+        .unwrap();
+        let unit = module.units().next().unwrap();
+        let kinds: Vec<_> = check_handshakes(&module, &unit)
+            .into_iter()
+            .map(|v| (v.bundle, v.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![(unit.output_arg(0), HandshakeViolationKind::BothSides)]
+        );
+    }
+}
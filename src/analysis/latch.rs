@@ -0,0 +1,178 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{analysis::TemporalRegion, ir::prelude::*};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A signal which is driven in some paths through a temporal region, but not
+/// in others.
+///
+/// Such a signal retains its old value in the paths where it is not driven,
+/// which synthesizes to a latch. This is rarely intended in processes that
+/// describe combinational logic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InferredLatch {
+    /// The conditionally driven signal.
+    pub signal: Value,
+    /// The region in which the signal is driven.
+    pub region: TemporalRegion,
+    /// The blocks which contain a drive of the signal.
+    pub driven: Vec<Block>,
+    /// The blocks which end the region along a path without a drive of the
+    /// signal.
+    pub undriven: Vec<Block>,
+}
+
+impl InferredLatch {
+    /// Describe the latch in a human-readable message.
+    pub fn message(&self, unit: &Unit) -> String {
+        let blocks = |bbs: &[Block]| {
+            bbs.iter()
+                .map(|bb| bb.dump(unit).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "signal {} is driven in some paths of {} but not others (driven in {}, not driven when reaching {})",
+            self.signal.dump(unit),
+            self.region,
+            blocks(&self.driven),
+            blocks(&self.undriven),
+        )
+    }
+}
+
+/// Find the signals in a process which are driven in some paths through a
+/// temporal region, but not in others.
+///
+/// A signal is considered driven along a path if an unconditional `drv`
+/// targets it before the path reaches a `wait` that ends the region. Drives
+/// with a condition are ignored, since a set of them may well cover all
+/// paths. Units other than processes never infer latches.
+pub fn find_latches(unit: &Unit) -> Vec<InferredLatch> {
+    if !unit.is_process() {
+        return vec![];
+    }
+    let trg = unit.trg();
+    let pt = unit.predtbl();
+    let mut latches = vec![];
+
+    for tr in trg.regions() {
+        // Collect the drives in the region.
+        let mut drives = HashMap::<Block, BTreeSet<Value>>::new();
+        let mut signals = BTreeMap::<Value, BTreeSet<Block>>::new();
+        for bb in tr.blocks() {
+            for inst in unit.insts(bb) {
+                if unit[inst].opcode() == Opcode::Drv {
+                    let signal = unit[inst].args()[0];
+                    drives.entry(bb).or_default().insert(signal);
+                    signals.entry(signal).or_default().insert(bb);
+                }
+            }
+        }
+        if signals.is_empty() {
+            continue;
+        }
+
+        // Determine the signals which are driven along all paths from the
+        // head of the region to the end of each block.
+        let all: BTreeSet<Value> = signals.keys().cloned().collect();
+        let mut driven: HashMap<Block, BTreeSet<Value>> =
+            tr.blocks().map(|bb| (bb, all.clone())).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb in tr.blocks() {
+                let mut set = if tr.is_head(bb) {
+                    BTreeSet::new()
+                } else {
+                    pt.pred(bb)
+                        .filter(|&pred| trg[pred] == tr.id)
+                        .fold(all.clone(), |set, pred| {
+                            set.intersection(&driven[&pred]).cloned().collect()
+                        })
+                };
+                set.extend(drives.get(&bb).into_iter().flatten().cloned());
+                if set != driven[&bb] {
+                    driven.insert(bb, set);
+                    changed = true;
+                }
+            }
+        }
+
+        // Report the signals not driven when the region ends.
+        let mut undriven = BTreeMap::<Value, Vec<Block>>::new();
+        for bb in tr.tail_blocks() {
+            if unit[unit.terminator(bb)].opcode() == Opcode::Halt {
+                continue;
+            }
+            for &signal in all.difference(&driven[&bb]) {
+                undriven.entry(signal).or_default().push(bb);
+            }
+        }
+        for (signal, blocks) in undriven {
+            latches.push(InferredLatch {
+                signal,
+                region: tr.id,
+                driven: signals[&signal].iter().cloned().collect(),
+                undriven: blocks,
+            });
+        }
+    }
+    latches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn conditional_drive() {
+        let module = parse_module(
+            "
+            proc @latch (i1$ %en, i8$ %d) -> (i8$ %q) {
+            entry:
+                %enp = prb i1$ %en
+                %dp = prb i8$ %d
+                %t = const time 0s 1e
+                br %enp, %done, %active
+            active:
+                drv i8$ %q, %dp, %t
+                br %done
+            done:
+                wait %entry, %en, %d
+            }
+            proc @comb (i1$ %en, i8$ %d) -> (i8$ %q) {
+            entry:
+                %enp = prb i1$ %en
+                %dp = prb i8$ %d
+                %t = const time 0s 1e
+                %zero = const i8 0
+                br %enp, %idle, %active
+            idle:
+                drv i8$ %q, %zero, %t
+                br %done
+            active:
+                drv i8$ %q, %dp, %t
+                br %done
+            done:
+                wait %entry, %en, %d
+            }
+            ",
+        )
+        .unwrap();
+        let mut units = module.units();
+
+        let unit = units.next().unwrap();
+        let latches = find_latches(&unit);
+        assert_eq!(latches.len(), 1);
+        assert_eq!(latches[0].signal, unit.output_arg(0));
+        assert_eq!(
+            latches[0].message(&unit),
+            "signal %q is driven in some paths of t0 but not others (driven in %active, not driven when reaching %done)"
+        );
+
+        let unit = units.next().unwrap();
+        assert_eq!(find_latches(&unit), vec![]);
+    }
+}
@@ -4,10 +4,26 @@
 //!
 //! This module implements various analysis passes on the IR.
 
+mod cost;
 mod domtree;
+mod fanout;
+mod freq;
+mod handshake;
+mod latch;
 mod preds;
+mod purity;
+mod sharing;
+mod stats;
 mod trg;
 
+pub use self::cost::*;
 pub use self::domtree::*;
+pub use self::fanout::*;
+pub use self::freq::*;
+pub use self::handshake::*;
+pub use self::latch::*;
 pub use self::preds::*;
+pub use self::purity::*;
+pub use self::sharing::*;
+pub use self::stats::*;
 pub use self::trg::*;
@@ -0,0 +1,249 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{
+    ir::{prelude::*, ExtUnitData, LinkedUnit},
+    ty::{Type, TypeKind},
+};
+use std::collections::{HashMap, HashSet};
+
+/// The functions of a module whose calls are free of side effects.
+///
+/// A function is pure if its result only depends on its arguments, such that
+/// calls to it may be shared, moved, and removed like any other pure
+/// instruction. This is the case if none of its arguments or its return value
+/// contain pointers or signals, it does not interact with signals, and it only
+/// calls pure functions. Functions which are only declared are assumed to be
+/// impure. Recursive functions and functions containing a loop are impure as
+/// well, since the analysis cannot prove that a call to them returns, and a
+/// call which may never return must not be removed.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionPurity {
+    pure: HashSet<UnitId>,
+}
+
+impl FunctionPurity {
+    /// Infer which functions of a module are pure.
+    pub fn new(module: &Module) -> Self {
+        // Assume all functions are pure which do not break the rules on their
+        // own, and record the callees of each.
+        let mut callees = HashMap::<UnitId, Vec<Option<UnitId>>>::new();
+        for unit in module.functions() {
+            let sig = unit.sig();
+            if !sig.args().all(|arg| is_plain(&sig.arg_type(arg))) || !is_plain(&sig.return_type())
+            {
+                continue;
+            }
+            let mut targets = vec![];
+            let mut plain = true;
+            for inst in unit.all_insts() {
+                let opcode = unit[inst].opcode();
+                if opcode.touches_signals() || opcode.is_temporal() {
+                    plain = false;
+                    break;
+                }
+                if opcode == Opcode::Call {
+                    let name = &unit[unit[inst].get_ext_unit().unwrap()].name;
+                    targets.push(match module.lookup(name) {
+                        Some(LinkedUnit::Def(id)) if module.unit(id).is_function() => Some(id),
+                        _ => None,
+                    });
+                }
+            }
+            if plain && !has_loop(unit) {
+                callees.insert(unit.id(), targets);
+            }
+        }
+
+        // Discard the recursive functions, and then the functions which call
+        // impure ones, until no more functions are affected.
+        let mut pure: HashSet<UnitId> = callees
+            .keys()
+            .cloned()
+            .filter(|&id| !is_recursive(&callees, id))
+            .collect();
+        loop {
+            let impure: Vec<_> = pure
+                .iter()
+                .cloned()
+                .filter(|id| {
+                    !callees[id]
+                        .iter()
+                        .all(|target| target.map(|t| pure.contains(&t)).unwrap_or(false))
+                })
+                .collect();
+            if impure.is_empty() {
+                break;
+            }
+            for id in impure {
+                pure.remove(&id);
+            }
+        }
+        Self { pure }
+    }
+
+    /// Check whether a unit is a pure function.
+    pub fn is_pure(&self, unit: UnitId) -> bool {
+        self.pure.contains(&unit)
+    }
+
+    /// Stop treating a unit as a pure function.
+    ///
+    /// Returns whether the unit was considered pure before.
+    pub(crate) fn remove(&mut self, unit: UnitId) -> bool {
+        self.pure.remove(&unit)
+    }
+
+    /// Check whether an external unit refers to a pure function.
+    pub fn is_pure_ext_unit(&self, module: &Module, ext: &ExtUnitData) -> bool {
+        match module.lookup(&ext.name) {
+            Some(LinkedUnit::Def(id)) => self.is_pure(id),
+            _ => false,
+        }
+    }
+}
+
+/// Check whether a function may call itself, directly or through others.
+fn is_recursive(callees: &HashMap<UnitId, Vec<Option<UnitId>>>, id: UnitId) -> bool {
+    let mut seen = HashSet::new();
+    let mut todo = vec![id];
+    while let Some(caller) = todo.pop() {
+        for &callee in callees.get(&caller).into_iter().flatten().flatten() {
+            if callee == id {
+                return true;
+            }
+            if seen.insert(callee) {
+                todo.push(callee);
+            }
+        }
+    }
+    false
+}
+
+/// Check whether the control flow of a unit contains a loop.
+fn has_loop(unit: Unit) -> bool {
+    let successors = |bb| match unit.last_inst(bb) {
+        Some(term) => unit[term].blocks().to_vec(),
+        None => vec![],
+    };
+    let entry = match unit.first_block() {
+        Some(entry) => entry,
+        None => return false,
+    };
+
+    // Walk the blocks depth-first. An edge back to a block which is still on
+    // the stack closes a loop.
+    let mut on_stack = HashMap::new();
+    on_stack.insert(entry, true);
+    let mut stack = vec![(entry, successors(entry))];
+    while let Some((bb, succs)) = stack.last_mut() {
+        let bb = *bb;
+        match succs.pop() {
+            Some(succ) => match on_stack.get(&succ) {
+                Some(true) => return true,
+                Some(false) => (),
+                None => {
+                    on_stack.insert(succ, true);
+                    stack.push((succ, successors(succ)));
+                }
+            },
+            None => {
+                on_stack.insert(bb, false);
+                stack.pop();
+            }
+        }
+    }
+    false
+}
+
+/// Check whether a type contains neither pointers nor signals.
+fn is_plain(ty: &Type) -> bool {
+    match **ty {
+        TypeKind::PointerType(..) | TypeKind::SignalType(..) => false,
+        TypeKind::ArrayType(_, ref ty) => is_plain(ty),
+        TypeKind::StructType(ref fields) | TypeKind::NamedStructType(_, ref fields) => {
+            fields.iter().all(is_plain)
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn pure_and_impure_functions() {
+        let module = parse_module(
+            "
+            func @twice (i8 %x) i8 {
+            %entry:
+                %y = add i8 %x, %x
+                ret i8 %y
+            }
+            func @local (i8 %x) i8 {
+            %entry:
+                %p = var i8 %x
+                %y = call i8 @twice (i8 %x)
+                st i8* %p, %y
+                %z = ld i8* %p
+                ret i8 %z
+            }
+            func @even (i8 %x) i1 {
+            %entry:
+                %y = call i1 @odd (i8 %x)
+                ret i1 %y
+            }
+            func @odd (i8 %x) i1 {
+            %entry:
+                %y = call i1 @even (i8 %x)
+                ret i1 %y
+            }
+            func @spin (i8 %x) i8 {
+            %entry:
+                br %loop
+            %loop:
+                %c = eq i8 %x, %x
+                br %c, %loop, %exit
+            %exit:
+                ret i8 %x
+            }
+            func @store (i8* %p, i8 %x) void {
+            %entry:
+                st i8* %p, %x
+                ret
+            }
+            func @extern (i8 %x) i8 {
+            %entry:
+                %y = call i8 @ext (i8 %x)
+                ret i8 %y
+            }
+            func @indirect (i8 %x) i8 {
+            %entry:
+                %y = call i8 @extern (i8 %x)
+                ret i8 %y
+            }
+            declare @ext (i8) i8
+            ",
+        )
+        .unwrap();
+        let purity = FunctionPurity::new(&module);
+        let pure: Vec<_> = module
+            .units()
+            .filter(|unit| purity.is_pure(unit.id()))
+            .map(|unit| unit.name().to_string())
+            .collect();
+        assert_eq!(pure, vec!["@twice", "@local"]);
+
+        // Linking records the purity in the external units.
+        let pure_calls: Vec<_> = module
+            .units()
+            .flat_map(|unit| {
+                unit.extern_units()
+                    .map(|(_, data)| data.pure)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(pure_calls, vec![true, false, false, false, false]);
+    }
+}
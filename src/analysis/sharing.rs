@@ -0,0 +1,318 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{
+    analysis::{gate_estimate, UnitCost},
+    assembly::write_unit_string,
+    ir::prelude::*,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Opportunities to share identical logic across the units of a module.
+///
+/// Two kinds of sharing are reported: constant trees that are computed in more
+/// than one unit, and units whose bodies are identical apart from the names of
+/// the unit and its values. Units which duplicate an earlier unit do not
+/// contribute to the constant trees, since sharing the unit shares its
+/// constants as well.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleSharing {
+    /// The constant trees that occur in more than one unit, with the largest
+    /// savings first.
+    pub constants: Vec<SharedConstant>,
+    /// The groups of identical units, with the largest savings first.
+    pub units: Vec<SharedUnits>,
+}
+
+/// A constant tree that occurs in more than one unit.
+///
+/// A constant tree is a pure instruction whose arguments are all constants or
+/// constant trees themselves. Only trees of at least two instructions which
+/// are not part of a larger tree are considered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedConstant {
+    /// The tree, printed as an expression, e.g.
+    /// `add i8 (const i8 1), (const i8 2)`.
+    pub tree: String,
+    /// The number of instructions in one copy of the tree.
+    pub insts: usize,
+    /// The estimated number of gates of one copy of the tree. See
+    /// `gate_estimate`.
+    pub gates: usize,
+    /// The root instruction of the tree in each unit it occurs in.
+    pub occurrences: Vec<(UnitId, Inst)>,
+}
+
+/// A group of units with identical bodies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedUnits {
+    /// The units, in the order they appear in the module.
+    pub units: Vec<UnitId>,
+    /// The number of instructions of one of the units.
+    pub insts: usize,
+    /// The estimated number of gates of one of the units.
+    pub gates: usize,
+}
+
+impl SharedConstant {
+    /// The number of instructions saved by computing the tree only once.
+    pub fn saved_insts(&self) -> usize {
+        self.insts * (self.occurrences.len() - 1)
+    }
+
+    /// The number of gates saved by computing the tree only once.
+    pub fn saved_gates(&self) -> usize {
+        self.gates * (self.occurrences.len() - 1)
+    }
+}
+
+impl SharedUnits {
+    /// The number of instructions saved by keeping only one of the units.
+    pub fn saved_insts(&self) -> usize {
+        self.insts * (self.units.len() - 1)
+    }
+
+    /// The number of gates saved by keeping only one of the units.
+    pub fn saved_gates(&self) -> usize {
+        self.gates * (self.units.len() - 1)
+    }
+}
+
+impl ModuleSharing {
+    /// Find the sharing opportunities in a module.
+    pub fn new(module: &Module) -> Self {
+        // Group the units by their canonical body.
+        let mut bodies = HashMap::<String, Vec<UnitId>>::new();
+        let mut order = vec![];
+        for unit in module.units() {
+            let group = bodies.entry(canonical_body(unit)).or_default();
+            if group.is_empty() {
+                order.push(unit.id());
+            }
+            group.push(unit.id());
+        }
+        let duplicates: HashSet<UnitId> = bodies
+            .values()
+            .flat_map(|group| group.iter().skip(1).cloned())
+            .collect();
+        let mut units: Vec<_> = bodies
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|group| {
+                let unit = module.unit(group[0]);
+                SharedUnits {
+                    insts: body_insts(unit).count(),
+                    gates: UnitCost::new(&unit).total_gates(),
+                    units: group,
+                }
+            })
+            .collect();
+        units.sort_by_key(|group| {
+            (
+                std::cmp::Reverse(group.saved_gates()),
+                std::cmp::Reverse(group.saved_insts()),
+                order.iter().position(|&id| id == group.units[0]),
+            )
+        });
+
+        // Collect the maximal constant trees of each unit, and group them by
+        // their printed form.
+        let mut trees = BTreeMap::<String, SharedConstant>::new();
+        for unit in module.units() {
+            if duplicates.contains(&unit.id()) {
+                continue;
+            }
+            let mut finder = TreeFinder {
+                unit,
+                trees: HashMap::new(),
+            };
+            let insts: Vec<_> = body_insts(unit).collect();
+            for &inst in &insts {
+                finder.tree(inst);
+            }
+            // Trees used by other trees are not maximal.
+            let mut inner = HashSet::new();
+            for (&inst, tree) in &finder.trees {
+                if tree.is_some() {
+                    for &arg in unit[inst].args() {
+                        inner.extend(unit.get_value_inst(arg));
+                    }
+                }
+            }
+            let mut seen = HashSet::new();
+            for inst in insts {
+                let (expr, members) = match &finder.trees[&inst] {
+                    Some(tree) if !inner.contains(&inst) => tree.clone(),
+                    _ => continue,
+                };
+                if members.len() < 2 || !seen.insert(expr.clone()) {
+                    continue;
+                }
+                let shared = trees.entry(expr.clone()).or_insert_with(|| SharedConstant {
+                    tree: expr,
+                    insts: members.len(),
+                    gates: members
+                        .iter()
+                        .flat_map(|&inst| gate_estimate(&unit, inst))
+                        .map(|(_, gates)| gates)
+                        .sum(),
+                    occurrences: vec![],
+                });
+                shared.occurrences.push((unit.id(), inst));
+            }
+        }
+        let mut constants: Vec<_> = trees
+            .into_values()
+            .filter(|tree| tree.occurrences.len() > 1)
+            .collect();
+        constants.sort_by_key(|tree| {
+            (
+                std::cmp::Reverse(tree.saved_gates()),
+                std::cmp::Reverse(tree.saved_insts()),
+            )
+        });
+
+        Self { constants, units }
+    }
+}
+
+/// A constant tree, as its printed form and the instructions it consists of.
+type Tree = (String, HashSet<Inst>);
+
+/// Identifies the constant trees of a unit.
+struct TreeFinder<'a> {
+    unit: Unit<'a>,
+    /// The tree rooted at each instruction visited so far, or `None` if the
+    /// instruction is not constant.
+    trees: HashMap<Inst, Option<Tree>>,
+}
+
+impl TreeFinder<'_> {
+    /// Determine the constant tree rooted at an instruction.
+    fn tree(&mut self, inst: Inst) -> Option<Tree> {
+        if let Some(tree) = self.trees.get(&inst) {
+            return tree.clone();
+        }
+        let tree = self.compute(inst);
+        self.trees.insert(inst, tree.clone());
+        tree
+    }
+
+    /// Determine the constant tree rooted at an instruction, see `tree`.
+    fn compute(&mut self, inst: Inst) -> Option<Tree> {
+        let unit = self.unit;
+        let data = &unit[inst];
+        let opcode = data.opcode();
+        let mut members = HashSet::new();
+        members.insert(inst);
+        if let Some(imm) = data.get_const_int() {
+            return Some((format!("const {}", imm), members));
+        }
+        if let Some(imm) = data.get_const_time() {
+            return Some((format!("const time {}", imm), members));
+        }
+        if let Some(imm) = data.get_const_enum() {
+            return Some((format!("const {}", imm), members));
+        }
+        if !opcode.is_pure()
+            || data.get_ext_unit().is_some()
+            || !data.blocks().is_empty()
+            || data.args().is_empty()
+        {
+            return None;
+        }
+        let mut args = vec![];
+        for &arg in data.args() {
+            let (expr, inner) = self.tree(unit.get_value_inst(arg)?)?;
+            args.push(format!("({})", expr));
+            members.extend(inner);
+        }
+        let mut expr = format!("{} {} {}", opcode, unit.inst_type(inst), args.join(", "));
+        for imm in data.imms() {
+            expr.push_str(&format!(", {}", imm));
+        }
+        Some((expr, members))
+    }
+}
+
+/// The instructions of a unit, without the implicit `halt` of entities.
+fn body_insts<'a>(unit: Unit<'a>) -> impl Iterator<Item = Inst> + 'a {
+    unit.all_insts()
+        .filter(move |&inst| !(unit.is_entity() && unit[inst].opcode() == Opcode::Halt))
+}
+
+/// Print a unit without the names of the unit, its arguments, values, and
+/// blocks, such that units which only differ in these names print the same.
+fn canonical_body(unit: Unit) -> String {
+    let mut data = unit.data().clone();
+    data.name = UnitName::Anonymous(0);
+    let mut builder = UnitBuilder::new_anonymous(&mut data);
+    let values: Vec<_> = builder
+        .args()
+        .chain(
+            builder
+                .all_insts()
+                .filter_map(|inst| builder.get_inst_result(inst)),
+        )
+        .collect();
+    for value in values {
+        builder.clear_name(value);
+    }
+    let blocks: Vec<_> = builder.blocks().collect();
+    for bb in blocks {
+        builder.clear_block_name(bb);
+    }
+    write_unit_string(Unit::new_anonymous(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn shared_constants_and_units() {
+        let module = parse_module(
+            "
+            entity @a (i8$ %x) -> () {
+                %one = const i8 1
+                %two = const i8 2
+                %sum = add i8 %one, %two
+                %t = const time 0s 1e
+                drv i8$ %x, %sum, %t
+            }
+            entity @b (i8$ %y) -> () {
+                %1 = const i8 1
+                %2 = const i8 2
+                %3 = add i8 %1, %2
+                %4 = const time 0s 1e
+                drv i8$ %y, %3, %4
+            }
+            func @c (i8 %x) i8 {
+            %entry:
+                %k1 = const i8 1
+                %k2 = const i8 2
+                %k = add i8 %k1, %k2
+                %r = xor i8 %k, %x
+                ret i8 %r
+            }
+            ",
+        )
+        .unwrap();
+        let sharing = ModuleSharing::new(&module);
+        let ids: Vec<_> = module.units().map(|u| u.id()).collect();
+
+        assert_eq!(sharing.units.len(), 1);
+        assert_eq!(sharing.units[0].units, vec![ids[0], ids[1]]);
+        assert_eq!(sharing.units[0].insts, 5);
+        assert_eq!(sharing.units[0].saved_gates(), 40);
+
+        assert_eq!(sharing.constants.len(), 1);
+        let tree = &sharing.constants[0];
+        assert_eq!(tree.tree, "add i8 (const i8 1), (const i8 2)");
+        assert_eq!(tree.occurrences.len(), 2);
+        assert_eq!(tree.occurrences[0].0, ids[0]);
+        assert_eq!(tree.occurrences[1].0, ids[2]);
+        assert_eq!(tree.saved_insts(), 3);
+        assert_eq!(tree.saved_gates(), 40);
+    }
+}
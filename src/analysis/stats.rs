@@ -0,0 +1,83 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::ir::prelude::*;
+use std::collections::HashMap;
+
+/// Size statistics of a single unit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnitStats {
+    /// The number of instructions in the unit.
+    pub num_insts: usize,
+    /// The number of basic blocks in the unit.
+    pub num_blocks: usize,
+    /// The number of instances created by the `inst` instructions in the unit,
+    /// counting each copy of a replicated instance.
+    pub num_instances: usize,
+    /// The number of `call` instructions in the unit.
+    pub num_calls: usize,
+    /// The number of `inst` and `call` instructions in the module which refer
+    /// to the unit, counting each copy of a replicated instance.
+    pub fanout: usize,
+}
+
+/// Size statistics of all units in a module.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleStats {
+    units: HashMap<UnitId, UnitStats>,
+}
+
+impl ModuleStats {
+    /// Compute the statistics for all units in a module.
+    pub fn new(module: &Module) -> Self {
+        let ids: HashMap<&UnitName, UnitId> = module
+            .units()
+            .map(|unit| (unit.name(), unit.id()))
+            .collect();
+        let mut units: HashMap<UnitId, UnitStats> = HashMap::new();
+        let mut fanout: HashMap<UnitId, usize> = HashMap::new();
+        for unit in module.units() {
+            let mut stats = UnitStats::default();
+            for bb in unit.blocks() {
+                stats.num_blocks += 1;
+                for inst in unit.insts(bb) {
+                    stats.num_insts += 1;
+                    let copies = unit.inst_replication(inst).unwrap_or(1);
+                    match unit[inst].opcode() {
+                        Opcode::Inst => stats.num_instances += copies,
+                        Opcode::Call => stats.num_calls += 1,
+                        _ => continue,
+                    }
+                    let ext = unit[inst].get_ext_unit().unwrap();
+                    if let Some(&id) = ids.get(&unit[ext].name) {
+                        *fanout.entry(id).or_insert(0) += copies;
+                    }
+                }
+            }
+            units.insert(unit.id(), stats);
+        }
+        for (id, n) in fanout {
+            if let Some(stats) = units.get_mut(&id) {
+                stats.fanout = n;
+            }
+        }
+        Self { units }
+    }
+
+    /// Get the statistics of a unit.
+    pub fn get(&self, unit: UnitId) -> Option<&UnitStats> {
+        self.units.get(&unit)
+    }
+
+    /// Return the total number of instructions in the module.
+    pub fn total_insts(&self) -> usize {
+        self.units.values().map(|s| s.num_insts).sum()
+    }
+}
+
+impl std::ops::Index<UnitId> for ModuleStats {
+    type Output = UnitStats;
+
+    fn index(&self, idx: UnitId) -> &UnitStats {
+        &self.units[&idx]
+    }
+}
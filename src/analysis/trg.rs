@@ -1,8 +1,8 @@
 // Copyright (c) 2017-2020 Fabian Schuiki
 
-use crate::ir::prelude::*;
+use crate::{analysis::PredecessorTable, ir::prelude::*};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     ops::Index,
 };
 
@@ -10,77 +10,88 @@ use std::{
 #[derive(Debug)]
 pub struct TemporalRegionGraph {
     /// Map that assigns blocks into a region.
-    blocks: HashMap<Block, TemporalRegion>,
+    blocks: BTreeMap<Block, TemporalRegion>,
     /// Actual region information.
     regions: Vec<TemporalRegionData>,
 }
 
 impl TemporalRegionGraph {
     /// Compute the TRG of a process.
+    ///
+    /// The entry block and all blocks targeted by temporal instructions start
+    /// a region of their own. All other blocks inherit the region of their
+    /// predecessors. Blocks which are reached from multiple regions become
+    /// the head of a new region, and the assignment is repeated until it no
+    /// longer changes. Unreachable blocks are not assigned a region.
     #[deprecated(since = "0.13.0", note = "use unit.trg() instead")]
     pub fn new(unit: &Unit) -> Self {
-        // trace!("[TRG] Constructing TRG:");
-
-        // Populate the worklist with the entry block, as well as any blocks
-        // that are targeted by `wait` instructions.
-        let mut todo = VecDeque::new();
-        let mut seen = HashSet::new();
-        todo.push_back(unit.entry());
-        seen.insert(unit.entry());
-        // trace!("[TRG]   Root {:?} (entry)", unit.entry());
+        let pt = unit.predtbl();
+
+        // Populate the roots with the entry block, as well as any blocks that
+        // are targeted by `wait` instructions.
+        let mut roots = vec![unit.entry()];
+        let mut root_ids = HashMap::new();
+        root_ids.insert(unit.entry(), 0);
         for bb in unit.blocks() {
             let term = unit.terminator(bb);
             if unit[term].opcode().is_temporal() {
                 for &target in unit[term].blocks() {
-                    if seen.insert(target) {
-                        // trace!("[TRG]   Root {:?} (wait target)", target);
-                        todo.push_back(target);
+                    if let Entry::Vacant(entry) = root_ids.entry(target) {
+                        entry.insert(roots.len());
+                        roots.push(target);
                     }
                 }
             }
         }
 
-        // Assign the root temporal regions.
-        let mut next_id = 0;
-        let mut blocks = HashMap::<Block, TemporalRegion>::new();
-        let mut head_blocks = HashSet::new();
-        let mut tail_blocks = HashSet::new();
-        let mut breaks = vec![];
-        for &bb in &todo {
-            blocks.insert(bb, TemporalRegion(next_id));
-            head_blocks.insert(bb);
-            next_id += 1;
-        }
-
-        // Assign temporal regions to the blocks.
-        while let Some(bb) = todo.pop_front() {
-            let tr = blocks[&bb];
-            // trace!("[TRG]   Pushing {:?} ({})", bb, tr);
-            let term = unit.terminator(bb);
-            if unit[term].opcode().is_temporal() {
-                breaks.push(term);
-                tail_blocks.insert(bb);
-                continue;
-            }
-            for &target in unit[term].blocks() {
-                if seen.insert(target) {
-                    todo.push_back(target);
-                    // trace!("[TRG]     Assigning {:?} <- {:?}", target, tr);
-                    if blocks.insert(target, tr).is_some() {
-                        let tr = TemporalRegion(next_id);
-                        blocks.insert(target, tr);
-                        head_blocks.insert(target);
-                        tail_blocks.insert(bb);
-                        // trace!("[TRG]     Assigning {:?} <- {:?} (override)", target, tr);
-                        next_id += 1;
-                    }
+        // Determine the blocks reachable from the roots, in layout order.
+        let mut reachable: HashSet<_> = roots.iter().cloned().collect();
+        let mut todo = roots.clone();
+        while let Some(bb) = todo.pop() {
+            for succ in pt.succ(bb) {
+                if reachable.insert(succ) {
+                    todo.push(succ);
                 }
             }
         }
-        // trace!("[TRG]   Blocks: {:?}", blocks);
+        let order: Vec<_> = unit.blocks().filter(|bb| reachable.contains(bb)).collect();
+
+        // Propagate the regions to all blocks. Promote the first block that is
+        // reached from multiple regions to a root, and repeat until no such
+        // block remains.
+        let assignment = loop {
+            let assignment = assign_regions(&pt, &order, &root_ids);
+            let join = order.iter().cloned().find(|bb| {
+                !root_ids.contains_key(bb)
+                    && pt
+                        .pred(*bb)
+                        .flat_map(|pred| match assignment.get(&pred) {
+                            Some(&Assignment::Region(id)) => Some(id),
+                            _ => None,
+                        })
+                        .collect::<HashSet<_>>()
+                        .len()
+                        > 1
+            });
+            match join {
+                Some(bb) => {
+                    trace!("[TRG] Promoting {} to a root (join)", bb.dump(unit));
+                    root_ids.insert(bb, roots.len());
+                    roots.push(bb);
+                }
+                None => break assignment,
+            }
+        };
+        let blocks: BTreeMap<Block, TemporalRegion> = assignment
+            .into_iter()
+            .map(|(bb, a)| match a {
+                Assignment::Region(id) => (bb, TemporalRegion(id)),
+                _ => unreachable!("{} not assigned to a region", bb.dump(unit)),
+            })
+            .collect();
 
         // Create a data struct for each region.
-        let mut regions: Vec<_> = (0..next_id)
+        let mut regions: Vec<_> = (0..roots.len())
             .map(|id| TemporalRegionData {
                 id: TemporalRegion(id),
                 blocks: Default::default(),
@@ -91,63 +102,66 @@ impl TemporalRegionGraph {
                 tail_insts: Default::default(),
                 tail_blocks: Default::default(),
                 tail_tight: true,
+                violations: Default::default(),
             })
             .collect();
 
         // Mark the entry block.
         regions[blocks[&unit.entry()].0].entry = true;
 
-        // Build the predecessor table.
-        let pt = unit.predtbl();
-
         // Note the blocks in each region and build the head/tail information.
         for (&bb, &id) in &blocks {
-            let mut reg = &mut regions[id.0];
+            let reg = &mut regions[id.0];
             reg.blocks.insert(bb);
 
-            // Determine whether this is a head block.
-            let mut is_head = head_blocks.contains(&bb);
-            let mut is_tight = true;
-            for pred in pt.pred(bb) {
-                let diff_trs = blocks[&pred] != id;
-                is_head |= diff_trs;
-                is_tight &= diff_trs;
-            }
-            if is_head {
+            // Every root heads its own region. Note any predecessors in the
+            // same region, which keep the region from being tight.
+            if root_ids.contains_key(&bb) {
                 reg.head_blocks.insert(bb);
-                reg.head_tight &= is_tight;
+                for pred in pt.pred(bb) {
+                    match blocks.get(&pred) {
+                        Some(&pred_id) if pred_id == id => {
+                            reg.add_violation(TightnessViolation::Head { head: bb, pred });
+                        }
+                        Some(_) => {
+                            reg.head_insts.insert(unit.terminator(pred));
+                        }
+                        None => (),
+                    }
+                }
             }
 
-            // Determine whether this is a tail block.
-            let mut is_tail = tail_blocks.contains(&bb);
-            let mut is_tight = true;
-            for succ in pt.succ(bb) {
-                let diff_trs = blocks[&succ] != id;
-                is_tail |= diff_trs;
-                is_tight &= diff_trs;
+            // Blocks that end in a temporal instruction or branch into other
+            // regions are tail blocks. Note any successors in the same region,
+            // which keep the region from being tight.
+            let term = unit.terminator(bb);
+            let leaves = unit[term].blocks().iter().any(|bb| blocks[bb] != id);
+            if leaves {
+                reg.tail_insts.insert(term);
             }
-            if is_tail {
+            if leaves || unit[term].opcode().is_temporal() {
                 reg.tail_blocks.insert(bb);
-                reg.tail_tight &= is_tight;
-            }
-
-            // Note the head instructions.
-            for pred in pt.pred(bb) {
-                if blocks[&pred] != id {
-                    reg.head_insts.insert(unit.terminator(pred));
+                for succ in pt.succ(bb) {
+                    if blocks[&succ] == id {
+                        reg.add_violation(TightnessViolation::Tail { tail: bb, succ });
+                    }
                 }
             }
+        }
 
-            // Note the tail instructions.
-            let term = unit.terminator(bb);
-            if unit[term].blocks().iter().any(|bb| blocks[bb] != id) {
-                reg.tail_insts.insert(term);
-            }
+        // Sort the violations, since the predecessor table is unordered.
+        for reg in &mut regions {
+            reg.violations.sort();
         }
 
         Self { blocks, regions }
     }
 
+    /// Get the region of a block, or `None` if the block is unreachable.
+    pub fn region(&self, bb: Block) -> Option<TemporalRegion> {
+        self.blocks.get(&bb).cloned()
+    }
+
     /// Check if a block is a temporal head block.
     pub fn is_head(&self, bb: Block) -> bool {
         self[self[bb]].is_head(bb)
@@ -201,7 +215,7 @@ pub struct TemporalRegionData {
     pub id: TemporalRegion,
 
     /// The blocks in this region.
-    pub blocks: HashSet<Block>,
+    pub blocks: BTreeSet<Block>,
 
     /// Whether this is the initial temporal region upon entering the process.
     pub entry: bool,
@@ -210,13 +224,13 @@ pub struct TemporalRegionData {
     ///
     /// Note that these reside in blocks *outside* this region, namely in the
     /// predecessors of the `head_blocks`.
-    pub head_insts: HashSet<Inst>,
+    pub head_insts: BTreeSet<Inst>,
 
     /// The entry blocks into this region.
     ///
     /// These are the first blocks that are jumped into upon entering this
     /// region.
-    pub head_blocks: HashSet<Block>,
+    pub head_blocks: BTreeSet<Block>,
 
     /// The head blocks are only reachable via branches from *other* regions.
     pub head_tight: bool,
@@ -225,16 +239,19 @@ pub struct TemporalRegionData {
     ///
     /// Note that these reside in blocks *inside* this region, namely in the
     /// `tail_blocks`.
-    pub tail_insts: HashSet<Inst>,
+    pub tail_insts: BTreeSet<Inst>,
 
     /// The exit blocks out of this region.
     ///
     /// These are the last blocks in this region, where execution either ends
     /// in a `wait` or `halt` instruction.
-    pub tail_blocks: HashSet<Block>,
+    pub tail_blocks: BTreeSet<Block>,
 
     /// The tail blocks only branch to *other* regions.
     pub tail_tight: bool,
+
+    /// The edges that keep the head or tail blocks from being tight.
+    pub violations: Vec<TightnessViolation>,
 }
 
 impl TemporalRegionData {
@@ -272,4 +289,350 @@ impl TemporalRegionData {
     pub fn is_tail(&self, bb: Block) -> bool {
         self.tail_blocks.contains(&bb)
     }
+
+    /// Check if a tail block continues into a block of this region.
+    pub fn tail_continues(&self, bb: Block) -> bool {
+        self.violations
+            .iter()
+            .any(|v| matches!(*v, TightnessViolation::Tail { tail, .. } if tail == bb))
+    }
+
+    fn add_violation(&mut self, violation: TightnessViolation) {
+        match violation {
+            TightnessViolation::Head { .. } => self.head_tight = false,
+            TightnessViolation::Tail { .. } => self.tail_tight = false,
+        }
+        if !self.violations.contains(&violation) {
+            self.violations.push(violation);
+        }
+    }
+}
+
+/// A CFG edge that keeps a temporal region from being tight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TightnessViolation {
+    /// A head block is entered from a block in its own region.
+    Head { head: Block, pred: Block },
+    /// A tail block continues into a block in its own region.
+    Tail { tail: Block, succ: Block },
+}
+
+impl TightnessViolation {
+    /// Describe the violation in terms of the unit's block names.
+    pub fn describe(&self, unit: &Unit) -> String {
+        match *self {
+            TightnessViolation::Head { head, pred } => format!(
+                "head block {} is entered from {} in the same region",
+                head.dump(unit),
+                pred.dump(unit)
+            ),
+            TightnessViolation::Tail { tail, succ } => format!(
+                "tail block {} continues into {} in the same region",
+                tail.dump(unit),
+                succ.dump(unit)
+            ),
+        }
+    }
+}
+
+/// Split blocks such that all temporal regions of a unit are tight.
+///
+/// Edges out of tail blocks that also continue within their region are
+/// routed through new blocks, as are the edges from other regions into head
+/// blocks that are also entered from within their region. Returns whether the
+/// unit was modified.
+pub fn make_regions_tight(unit: &mut UnitBuilder) -> bool {
+    let mut modified = false;
+
+    // Every split removes at least one violation, but may expose another one
+    // in a newly formed region. Bound the number of rounds to be safe.
+    for _ in 0..2 * unit.blocks().count() + 2 {
+        let trg = unit.trg();
+        let violations: Vec<_> = trg
+            .regions()
+            .flat_map(|tr| tr.violations.iter().cloned())
+            .collect();
+        let tails: BTreeSet<_> = violations
+            .iter()
+            .flat_map(|v| match *v {
+                TightnessViolation::Tail { tail, .. } => Some(tail),
+                _ => None,
+            })
+            .collect();
+        let heads: BTreeSet<_> = violations
+            .iter()
+            .flat_map(|v| match *v {
+                TightnessViolation::Head { head, .. } => Some(head),
+                _ => None,
+            })
+            .collect();
+
+        // Fix the tails first, since they often resolve the head violations
+        // as well, e.g. for a `wait` back to the head of its own region.
+        if !tails.is_empty() {
+            for tail in tails {
+                tighten_tail(unit, &trg, tail);
+            }
+        } else if !heads.is_empty() {
+            for head in heads {
+                tighten_head(unit, &trg, head);
+            }
+        } else {
+            break;
+        }
+        modified = true;
+    }
+
+    modified
+}
+
+/// Route the offending edges out of a tail block through new blocks.
+///
+/// For temporal instructions these are the edges into the tail's own region,
+/// such that the target is entered from a separate region. For branches these
+/// are the edges into other regions, such that the tail block is no longer a
+/// tail.
+fn tighten_tail(unit: &mut UnitBuilder, trg: &TemporalRegionGraph, tail: Block) {
+    let term = unit.terminator(tail);
+    let temporal = unit[term].opcode().is_temporal();
+    let mut succs = unit[term].blocks().to_vec();
+    succs.sort();
+    succs.dedup();
+    for succ in succs {
+        if (trg[succ] == trg[tail]) != temporal {
+            continue;
+        }
+        let name = unit.derive_block_name(tail, "tail");
+        let aux_bb = unit.block();
+        if let Some(name) = name {
+            unit.set_block_name(aux_bb, name);
+        }
+        unit.detach_block(aux_bb);
+        unit.insert_block_after(aux_bb, tail);
+        unit.append_to(aux_bb);
+        unit.ins().br(succ);
+        trace!(
+            "[TRG] Routing {} -> {} through {}",
+            tail.dump(unit),
+            succ.dump(unit),
+            aux_bb.dump(unit)
+        );
+        unit.replace_block_within_inst(succ, aux_bb, term);
+        let phis: Vec<_> = unit
+            .insts(succ)
+            .filter(|&inst| unit[inst].opcode().is_phi())
+            .collect();
+        for phi in phis {
+            unit.replace_block_within_inst(tail, aux_bb, phi);
+        }
+    }
+}
+
+/// Route the edges from other regions into a head block through a new block.
+///
+/// The new block becomes the head of the region, and the phi nodes of the old
+/// head are split accordingly.
+fn tighten_head(unit: &mut UnitBuilder, trg: &TemporalRegionGraph, head: Block) {
+    let outside: BTreeSet<_> = unit
+        .predtbl()
+        .pred(head)
+        .filter(|&pred| trg.blocks.get(&pred).map(|&tr| tr != trg[head]) == Some(true))
+        .collect();
+    let name = unit.derive_block_name(head, "head");
+    let head_bb = unit.block();
+    if let Some(name) = name {
+        unit.set_block_name(head_bb, name);
+    }
+    unit.detach_block(head_bb);
+    unit.insert_block_before(head_bb, head);
+    trace!(
+        "[TRG] Routing edges into {} through {}",
+        head.dump(unit),
+        head_bb.dump(unit)
+    );
+    for &pred in &outside {
+        let term = unit.terminator(pred);
+        unit.replace_block_within_inst(head, head_bb, term);
+    }
+
+    // Split the phi nodes into one in the new block, which merges the values
+    // from the other regions, and one in the old head.
+    unit.append_to(head_bb);
+    let phis: Vec<_> = unit
+        .insts(head)
+        .filter(|&inst| unit[inst].opcode().is_phi())
+        .collect();
+    for phi in phis {
+        let (outer, inner): (Vec<_>, Vec<_>) = unit[phi]
+            .args()
+            .iter()
+            .cloned()
+            .zip(unit[phi].blocks().iter().cloned())
+            .partition(|(_, bb)| outside.contains(bb));
+        let (outer_args, outer_bbs) = outer.into_iter().unzip();
+        let (mut inner_args, mut inner_bbs): (Vec<_>, Vec<_>) = inner.into_iter().unzip();
+        inner_args.push(unit.ins().phi(outer_args, outer_bbs));
+        inner_bbs.push(head_bb);
+        unit.insert_before(phi);
+        let value = unit.ins().phi(inner_args, inner_bbs);
+        let old = unit.inst_result(phi);
+        unit.replace_use(old, value);
+        unit.delete_inst(phi);
+        unit.append_to(head_bb);
+    }
+    unit.ins().br(head);
+}
+
+/// The region assigned to a block during the fixed point iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assignment {
+    /// Not reached from any region yet.
+    Unassigned,
+    /// Reached from exactly one region.
+    Region(usize),
+    /// Reached from multiple regions.
+    Conflict,
+}
+
+impl Assignment {
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Assignment::Unassigned, x) | (x, Assignment::Unassigned) => x,
+            (Assignment::Region(a), Assignment::Region(b)) if a == b => self,
+            _ => Assignment::Conflict,
+        }
+    }
+}
+
+/// Propagate the regions of the roots to all blocks.
+fn assign_regions(
+    pt: &PredecessorTable,
+    order: &[Block],
+    roots: &HashMap<Block, usize>,
+) -> HashMap<Block, Assignment> {
+    let mut assignment: HashMap<_, _> = order
+        .iter()
+        .map(|bb| {
+            let a = match roots.get(bb) {
+                Some(&id) => Assignment::Region(id),
+                None => Assignment::Unassigned,
+            };
+            (*bb, a)
+        })
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in order {
+            if roots.contains_key(&bb) {
+                continue;
+            }
+            let a = pt.pred(bb).fold(Assignment::Unassigned, |a, pred| {
+                a.merge(
+                    assignment
+                        .get(&pred)
+                        .cloned()
+                        .unwrap_or(Assignment::Unassigned),
+                )
+            });
+            if assignment[&bb] != a {
+                assignment.insert(bb, a);
+                changed = true;
+            }
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{parse_module, write_module_string};
+
+    #[test]
+    fn join_heads_own_region() {
+        let module = parse_module(
+            "
+            proc @foo (i1$ %c) -> () {
+            entry:
+                %x = prb i1$ %c
+                br %x, %join, %idle
+            idle:
+                wait %resume, %c
+            resume:
+                br %join
+            join:
+                wait %entry, %c
+            }
+            ",
+        )
+        .unwrap();
+        let unit = module.units().next().unwrap();
+        let trg = unit.trg();
+        let bbs: Vec<_> = unit.blocks().collect();
+        let (entry, resume, join) = (bbs[0], bbs[2], bbs[3]);
+        assert_eq!(trg.regions().count(), 3);
+        assert_ne!(trg[join], trg[entry]);
+        assert_ne!(trg[join], trg[resume]);
+        assert!(trg.is_head(join));
+        assert!(trg.is_tail(entry));
+        assert!(trg.is_tail(resume));
+        assert!(trg[trg[join]].head_tight);
+        assert!(trg[trg[join]].tail_tight);
+    }
+
+    #[test]
+    fn split_loops_into_tight_regions() {
+        let mut module = parse_module(
+            "
+            proc @foo (i1$ %c) -> () {
+            entry:
+                %zero = const i8 0
+                br %loop
+            loop:
+                %i = phi i8 [%zero, %entry], [%j, %loop], [%zero, %done]
+                %one = const i8 1
+                %j = add i8 %i, %one
+                %x = prb i1$ %c
+                br %x, %loop, %done
+            done:
+                wait %loop, %c
+            }
+            ",
+        )
+        .unwrap();
+        let id = module.units().next().unwrap().id();
+        {
+            let unit = module.unit(id);
+            let trg = unit.trg();
+            let bbs: Vec<_> = unit.blocks().collect();
+            let (lp, done) = (bbs[1], bbs[2]);
+            assert_eq!(
+                trg[trg[lp]].violations,
+                vec![
+                    TightnessViolation::Head { head: lp, pred: lp },
+                    TightnessViolation::Head {
+                        head: lp,
+                        pred: done
+                    },
+                    TightnessViolation::Tail {
+                        tail: done,
+                        succ: lp
+                    },
+                ]
+            );
+        }
+
+        assert!(make_regions_tight(&mut module.unit_mut(id)));
+        module.verify();
+        let unit = module.unit(id);
+        let trg = unit.trg();
+        for tr in trg.regions() {
+            assert!(tr.head_tight && tr.tail_tight, "{:?}", tr);
+        }
+        let asm = write_module_string(&module);
+        assert!(asm.contains("wait %done.tail1, %c"), "{}", asm);
+        assert!(asm.contains("br %loop.head1\n"), "{}", asm);
+        assert!(!make_regions_tight(&mut module.unit_mut(id)));
+    }
 }
@@ -3,18 +3,33 @@
 //! Facilities to emit a module as human-readable assembly, or to parse such
 //! assembly back into a module.
 
-use crate::{ir::Module, ty::Type, value::TimeValue};
+use crate::{
+    ir::{Module, Opcode, OpcodeDescription, Unit, UnitData},
+    ty::Type,
+    value::TimeValue,
+    verifier::Verifier,
+};
+use lalrpop_util::ParseError;
 
 #[allow(unused_parens)]
 mod grammar;
 mod reader;
 mod writer;
 
+pub use writer::WriteOptions;
+
 /// Emit assembly for a module.
 pub fn write_module(sink: impl std::io::Write, module: &Module) {
     writer::Writer::new(sink).write_module(module).unwrap();
 }
 
+/// Emit assembly for a module with custom options.
+pub fn write_module_with(sink: impl std::io::Write, module: &Module, options: WriteOptions) {
+    writer::Writer::with_options(sink, options)
+        .write_module(module)
+        .unwrap();
+}
+
 /// Emit assembly for a module as string.
 pub fn write_module_string(module: &Module) -> String {
     let mut asm = vec![];
@@ -22,12 +37,24 @@ pub fn write_module_string(module: &Module) -> String {
     String::from_utf8(asm).expect("writer should emit proper utf8")
 }
 
+/// Emit assembly for a single unit.
+pub fn write_unit(sink: impl std::io::Write, unit: Unit) {
+    writer::Writer::new(sink).write_unit(unit).unwrap();
+}
+
+/// Emit assembly for a single unit as string.
+pub fn write_unit_string(unit: Unit) -> String {
+    let mut asm = vec![];
+    write_unit(&mut asm, unit);
+    String::from_utf8(asm).expect("writer should emit proper utf8")
+}
+
 /// Parse a type.
 ///
 /// Parses the `input` string into a type.
 pub fn parse_type(input: impl AsRef<str>) -> Result<Type, String> {
     reader::TypeParser::new()
-        .parse(input.as_ref())
+        .parse(&Default::default(), input.as_ref())
         .map_err(|e| format!("{}", e))
 }
 
@@ -36,7 +63,7 @@ pub fn parse_type(input: impl AsRef<str>) -> Result<Type, String> {
 /// Parses the `input` string into a time constant.
 pub fn parse_time(input: impl AsRef<str>) -> Result<TimeValue, String> {
     reader::TimeValueParser::new()
-        .parse(input.as_ref())
+        .parse(&Default::default(), input.as_ref())
         .map_err(|e| format!("{}", e))
 }
 
@@ -53,11 +80,175 @@ pub fn parse_module(input: impl AsRef<str>) -> Result<Module, String> {
 
 /// Parse a module without linking and verifying it.
 pub fn parse_module_unchecked(input: impl AsRef<str>) -> Result<Module, String> {
+    let input = input.as_ref();
     reader::ModuleParser::new()
-        .parse(input.as_ref())
+        .parse(&Default::default(), input)
         .map(|m| {
             debug!("Parsed module:\n{}", m.dump());
             m
         })
-        .map_err(|e| format!("{}", e))
+        .map_err(|e| {
+            let offset = match e {
+                ParseError::InvalidToken { location } => Some(location),
+                ParseError::UnrecognizedEOF { location, .. } => Some(location),
+                ParseError::UnrecognizedToken {
+                    token: (location, ..),
+                    ..
+                } => Some(location),
+                ParseError::ExtraToken {
+                    token: (location, ..),
+                } => Some(location),
+                ParseError::User { .. } => None,
+            };
+            let mut msg = format!("{}", e);
+            for desc in offset.into_iter().flat_map(|o| describe_inst_at(input, o)) {
+                msg.push_str(&format!("\nnote: {}", desc));
+            }
+            msg
+        })
+}
+
+/// Parse a single unit.
+///
+/// Parses the `input` string into a function, process, or entity, without an
+/// enclosing module. The input may contain type definitions and declarations
+/// besides the unit, which are used during parsing but not returned. The unit
+/// is verified, but the units it calls or instantiates are not resolved.
+pub fn parse_unit(input: impl AsRef<str>) -> Result<UnitData, String> {
+    let module = parse_module_unchecked(input)?;
+    let mut units = module.units();
+    let unit = match (units.next(), units.next()) {
+        (Some(unit), None) => unit,
+        (None, _) => return Err("expected a unit".to_string()),
+        (Some(_), Some(unit)) => return Err(format!("unexpected unit {}", unit.name())),
+    };
+    let mut verifier = Verifier::new();
+    verifier.verify_unit(unit);
+    verifier.finish().map_err(|errs| errs.to_string())?;
+    Ok(unit.data().clone())
+}
+
+/// Describe the operands of the instruction that precedes an offset in the
+/// input, to help the user fix a parse error.
+///
+/// Returns one description for every opcode with the instruction's mnemonic.
+fn describe_inst_at(input: &str, offset: usize) -> Vec<OpcodeDescription> {
+    let before = input[..offset.min(input.len())].trim_end();
+    let line = before.rsplit('\n').next().unwrap_or("").trim_start();
+    let line = match line.find('=') {
+        Some(i) if line.starts_with('%') => line[i + 1..].trim_start(),
+        _ => line,
+    };
+    let mnemonic = line.split_whitespace().next().unwrap_or("");
+    let mut descs: Vec<_> = Opcode::ALL
+        .iter()
+        .map(|op| op.describe())
+        .filter(|d| d.mnemonic == mnemonic)
+        .collect();
+    descs.dedup_by_key(|d| (d.operands, d.variadic));
+    descs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_round_trip() {
+        let input = "entity @foo (i8$ %a) -> (i8$ %z) {\n    %t = const time 0s\n    %x = prb i8$ %a\n    drv i8$ %z, %x, %t\n}\n";
+        let data = parse_unit(input).unwrap();
+        assert_eq!(data.to_string(), input.trim_end());
+        assert_eq!(write_unit_string(Unit::new_anonymous(&data)), input);
+
+        let func = "%T = type {i8, i8}\nfunc %f (%T %x) i8 {\n%entry:\n    %y = extf i8, %T %x, 1\n    ret i8 %y\n}\n";
+        let data = parse_unit(func).unwrap();
+        assert_eq!(
+            data.to_string(),
+            "func %f (%T %x) i8 {\nentry:\n    %y = extf i8, %T %x, 1\n    ret i8 %y\n}"
+        );
+    }
+
+    #[test]
+    fn regions_are_annotated() {
+        let input = indoc::indoc! {"
+            proc @foo (i1$ %c) -> (i1$ %q) {
+            entry:
+                br %wait
+            wait:
+                wait %check, %c
+            check:
+                %cp = prb i1$ %c
+                br %cp, %wait, %drive
+            drive:
+                %t = const time 0s 1e
+                drv i1$ %q, %cp, %t
+                br %wait
+            dead:
+                br %wait
+            }
+        "};
+        let module = parse_module(input).unwrap();
+        assert_eq!(write_module_string(&module), input);
+        let mut asm = vec![];
+        write_module_with(&mut asm, &module, WriteOptions { regions: true });
+        assert_eq!(
+            String::from_utf8(asm).unwrap(),
+            indoc::indoc! {"
+                proc @foo (i1$ %c) -> (i1$ %q) {
+                entry: ; tr=t0 head tail
+                    br %wait
+                wait: ; tr=t2 head tail
+                    wait %check, %c
+                check: ; tr=t1 head tail
+                    %cp = prb i1$ %c
+                    br %cp, %wait, %drive
+                drive: ; tr=t1 tail
+                    %t = const time 0s 1e
+                    drv i1$ %q, %cp, %t
+                    br %wait
+                dead:
+                    br %wait
+                }
+            "}
+        );
+    }
+
+    #[test]
+    fn named_structs_are_nominal() {
+        let input = indoc::indoc! {"
+            %a = type {i8 %lo, i32 %hi}
+            %b = type {i8 %x, i32 %y}
+
+            func @convert (%a %p) %b {
+            entry:
+                %lo = extf i8, %a %p, 0
+                %hi = extf i32, %a %p, 1
+                %q = %b {i8 %lo, i32 %hi}
+                ret %b %q
+            }
+        "};
+        let module = parse_module(input).unwrap();
+        assert_eq!(write_module_string(&module), input);
+
+        let a = module.lookup_type("a").unwrap().ty.clone();
+        let b = module.lookup_type("b").unwrap().ty.clone();
+        assert_ne!(a, b);
+        assert_eq!(a.unwrap_struct(), b.unwrap_struct());
+        assert_eq!(module.field_index(&a, "hi"), Some(1));
+        assert_eq!(module.field_index(&b, "hi"), None);
+        assert_eq!(module.field_index(&b, "y"), Some(1));
+
+        // A value of one type cannot stand in for the other.
+        let mixed = parse_module_unchecked(input.replace("ret %b %q", "ret %a %p")).unwrap();
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&mixed);
+        assert!(verifier.finish().is_err());
+    }
+
+    #[test]
+    fn unit_requires_exactly_one_unit() {
+        assert!(parse_unit("declare @foo () -> ()").is_err());
+        assert!(parse_unit("entity @a () -> () {}\nentity @b () -> () {}").is_err());
+        assert!(parse_unit("func @f () i8 {\n%entry:\n    ret void\n}").is_err());
+    }
 }
@@ -5,10 +5,10 @@
 use crate::{
     ir::{self, Opcode, Signature, UnitBuilder, UnitName},
     ty::Type,
-    value::{IntValue, TimeValue},
+    value::{EnumValue, IntValue, TimeUnit, TimeValue},
 };
 use num::{BigInt, BigRational};
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap};
 
 #[derive(Default)]
 pub struct Context<'a> {
@@ -18,11 +18,16 @@ pub struct Context<'a> {
 
 pub enum Unit {
     Data(ir::UnitData, usize),
-    Declare(ir::UnitName, ir::Signature, usize),
+    Declare(ir::DeclData),
+    Type(ir::TypeDef),
 }
 
+/// The named types defined so far while parsing a module.
+pub type TypeTable = RefCell<HashMap<String, Type>>;
+
 pub struct Block<'a> {
     pub name: LocalName<'a>,
+    pub annotations: ir::Annotations,
     pub insts: Vec<Inst<'a>>,
 }
 
@@ -40,6 +45,7 @@ impl<'a> Block<'a> {
             LocalName::Anonymous(index) => builder.set_anonymous_block_hint(bb, index),
             LocalName::Named(name) => builder.set_block_name(bb, name.to_owned()),
         }
+        builder.annotate_block(bb, self.annotations);
         builder.append_to(bb);
         for inst in self.insts {
             inst.build(builder, context);
@@ -52,12 +58,18 @@ pub struct Inst<'a> {
     pub name: Option<LocalName<'a>>,
     pub data: InstData<'a>,
     pub loc: Option<usize>,
+    pub annotations: ir::Annotations,
+    pub weights: Option<[u32; 2]>,
+    pub resolution: Option<ir::Resolution>,
+    pub replication: Option<usize>,
 }
 
 pub enum InstData<'a> {
     ConstInt(IntValue),
     ConstTime(TimeValue),
+    ConstEnum(EnumValue),
     Aggregate(usize, Vec<TypedValue<'a>>),
+    NamedStruct(Type, Vec<TypedValue<'a>>),
     Nullary,
     Unary(TypedValue<'a>),
     Binary(TypedValue<'a>, TypedValue<'a>),
@@ -70,6 +82,7 @@ pub enum InstData<'a> {
     ),
     Reg(
         TypedValue<'a>,
+        RegControls<'a>,
         Vec<(
             TypedValue<'a>,
             ir::RegMode,
@@ -86,6 +99,13 @@ pub enum InstData<'a> {
     Wait(Label<'a>, Option<TypedValue<'a>>, Vec<Value<'a>>),
 }
 
+pub struct RegControls<'a> {
+    pub init: Option<TypedValue<'a>>,
+    pub enable: Option<TypedValue<'a>>,
+    pub clear: Option<(ir::RegMode, TypedValue<'a>)>,
+    pub preset: Option<(ir::RegMode, TypedValue<'a>)>,
+}
+
 impl<'a> Inst<'a> {
     pub fn new(opcode: Opcode) -> Self {
         Self {
@@ -93,6 +113,10 @@ impl<'a> Inst<'a> {
             name: None,
             data: InstData::Nullary,
             loc: None,
+            annotations: Default::default(),
+            weights: None,
+            resolution: None,
+            replication: None,
         }
     }
 
@@ -108,6 +132,30 @@ impl<'a> Inst<'a> {
         x
     }
 
+    pub fn annotations(self, annotations: ir::Annotations) -> Self {
+        let mut x = self;
+        x.annotations = annotations;
+        x
+    }
+
+    pub fn weights(self, weights: Option<[u32; 2]>) -> Self {
+        let mut x = self;
+        x.weights = weights;
+        x
+    }
+
+    pub fn resolution(self, resolution: Option<ir::Resolution>) -> Self {
+        let mut x = self;
+        x.resolution = resolution;
+        x
+    }
+
+    pub fn replication(self, replication: Option<usize>) -> Self {
+        let mut x = self;
+        x.replication = replication;
+        x
+    }
+
     pub fn data(self, data: InstData<'a>) -> Self {
         let mut x = self;
         x.data = data;
@@ -118,6 +166,7 @@ impl<'a> Inst<'a> {
         let result: InstOrValue = match self.data {
             InstData::ConstInt(imm) => builder.ins().const_int(imm).into(),
             InstData::ConstTime(imm) => builder.ins().const_time(imm).into(),
+            InstData::ConstEnum(imm) => builder.ins().const_enum(imm).into(),
             InstData::Aggregate(size, args) => {
                 let args = args
                     .into_iter()
@@ -130,6 +179,13 @@ impl<'a> Inst<'a> {
                     x => unreachable!("aggregate {:?}", x),
                 }
             }
+            InstData::NamedStruct(ty, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|a| a.build(builder, context))
+                    .collect();
+                builder.ins().named_strukt(ty, args).into()
+            }
             InstData::Nullary => match self.opcode {
                 Opcode::Halt => builder.ins().halt().into(),
                 Opcode::Ret => builder.ins().ret().into(),
@@ -141,6 +197,9 @@ impl<'a> Inst<'a> {
                     Opcode::Alias => builder.ins().alias(arg).into(),
                     Opcode::Not => builder.ins().not(arg).into(),
                     Opcode::Neg => builder.ins().neg(arg).into(),
+                    Opcode::Popcount => builder.ins().popcount(arg).into(),
+                    Opcode::Clz => builder.ins().clz(arg).into(),
+                    Opcode::Ctz => builder.ins().ctz(arg).into(),
                     Opcode::RetValue => builder.ins().ret_value(arg).into(),
                     Opcode::Sig => builder.ins().sig(arg).into(),
                     Opcode::Prb => builder.ins().prb(arg).into(),
@@ -159,13 +218,23 @@ impl<'a> Inst<'a> {
                     Opcode::Or => builder.ins().or(arg0, arg1).into(),
                     Opcode::Xor => builder.ins().xor(arg0, arg1).into(),
                     Opcode::Smul => builder.ins().smul(arg0, arg1).into(),
+                    Opcode::Smulh => builder.ins().smulh(arg0, arg1).into(),
                     Opcode::Sdiv => builder.ins().sdiv(arg0, arg1).into(),
                     Opcode::Smod => builder.ins().smod(arg0, arg1).into(),
                     Opcode::Srem => builder.ins().srem(arg0, arg1).into(),
                     Opcode::Umul => builder.ins().umul(arg0, arg1).into(),
+                    Opcode::Umulh => builder.ins().umulh(arg0, arg1).into(),
                     Opcode::Udiv => builder.ins().udiv(arg0, arg1).into(),
                     Opcode::Umod => builder.ins().umod(arg0, arg1).into(),
                     Opcode::Urem => builder.ins().urem(arg0, arg1).into(),
+                    Opcode::Umin => builder.ins().umin(arg0, arg1).into(),
+                    Opcode::Umax => builder.ins().umax(arg0, arg1).into(),
+                    Opcode::Smin => builder.ins().smin(arg0, arg1).into(),
+                    Opcode::Smax => builder.ins().smax(arg0, arg1).into(),
+                    Opcode::Uaddo => builder.ins().uaddo(arg0, arg1).into(),
+                    Opcode::Saddo => builder.ins().saddo(arg0, arg1).into(),
+                    Opcode::Usubo => builder.ins().usubo(arg0, arg1).into(),
+                    Opcode::Ssubo => builder.ins().ssubo(arg0, arg1).into(),
                     Opcode::Eq => builder.ins().eq(arg0, arg1).into(),
                     Opcode::Neq => builder.ins().neq(arg0, arg1).into(),
                     Opcode::Slt => builder.ins().slt(arg0, arg1).into(),
@@ -191,6 +260,7 @@ impl<'a> Inst<'a> {
                     Opcode::Shl => builder.ins().shl(arg0, arg1, arg2).into(),
                     Opcode::Shr => builder.ins().shr(arg0, arg1, arg2).into(),
                     Opcode::Del => builder.ins().del(arg0, arg1, arg2).into(),
+                    Opcode::StCond => builder.ins().st_cond(arg0, arg1, arg2).into(),
                     x => unreachable!("ternary {:?}", x),
                 }
             }
@@ -201,11 +271,22 @@ impl<'a> Inst<'a> {
                 let arg3 = arg3.build(builder, context);
                 match self.opcode {
                     Opcode::DrvCond => builder.ins().drv_cond(arg0, arg1, arg2, arg3).into(),
+                    Opcode::DelCond => builder.ins().del_cond(arg0, arg1, arg2, arg3).into(),
                     x => unreachable!("quaternary {:?}", x),
                 }
             }
-            InstData::Reg(init, triggers) => {
+            InstData::Reg(init, controls, triggers) => {
                 let init = init.build(builder, context);
+                let controls = ir::RegControls {
+                    init: controls.init.map(|v| v.build(builder, context)),
+                    enable: controls.enable.map(|v| v.build(builder, context)),
+                    clear: controls
+                        .clear
+                        .map(|(mode, v)| (mode, v.build(builder, context))),
+                    preset: controls
+                        .preset
+                        .map(|(mode, v)| (mode, v.build(builder, context))),
+                };
                 let triggers = triggers
                     .into_iter()
                     .map(|(data, mode, trigger, gate)| ir::RegTrigger {
@@ -215,7 +296,7 @@ impl<'a> Inst<'a> {
                         gate: gate.map(|g| g.build(builder, context)),
                     })
                     .collect();
-                builder.ins().reg(init, triggers).into()
+                builder.ins().reg_ctrl(init, triggers, controls).into()
             }
             InstData::Ins(target, value, imm) => {
                 let target = target.build(builder, context);
@@ -250,7 +331,11 @@ impl<'a> Inst<'a> {
                     .into_iter()
                     .map(|v| v.build(builder, context))
                     .collect();
-                builder.ins().call(ext, args).into()
+                let inst = builder.ins().call(ext, args);
+                match builder.get_inst_result(inst) {
+                    Some(value) => value.into(),
+                    None => inst.into(),
+                }
             }
             InstData::Inst(unit, input_args, output_args) => {
                 let mut sig = Signature::new();
@@ -322,13 +407,23 @@ impl<'a> Inst<'a> {
                 LocalName::Named(name) => builder.set_name(value, name.to_owned()),
             }
         }
+        let inst = match result {
+            InstOrValue::Inst(inst) => inst,
+            InstOrValue::Value(value) => builder.value_inst(value),
+        };
         if let Some(loc) = self.loc {
-            let inst = match result {
-                InstOrValue::Inst(inst) => inst,
-                InstOrValue::Value(value) => builder.value_inst(value),
-            };
             builder.set_location_hint(inst, loc);
         }
+        builder.annotate_inst(inst, self.annotations);
+        if let Some(weights) = self.weights {
+            builder.set_branch_weights(inst, weights);
+        }
+        if let Some(resolution) = self.resolution {
+            builder.set_signal_resolution(inst, resolution);
+        }
+        if let Some(count) = self.replication {
+            builder.set_inst_replication(inst, count);
+        }
     }
 }
 
@@ -376,6 +471,16 @@ impl<'a> From<&'a str> for LocalName<'a> {
     }
 }
 
+impl LocalName<'_> {
+    /// Get the name without the leading `%`.
+    pub fn to_plain_string(&self) -> String {
+        match self {
+            LocalName::Anonymous(x) => x.to_string(),
+            LocalName::Named(x) => x.to_string(),
+        }
+    }
+}
+
 impl std::fmt::Display for LocalName<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -472,27 +577,13 @@ pub fn parse_time_triple(
     let epsilon = epsilon.map(|epsilon| &epsilon[..epsilon.len() - 1]);
 
     // Determine the SI prefix for the time.
-    let scale = match &time[time.len() - 1..] {
-        "a" => Some(-18),
-        "f" => Some(-15),
-        "p" => Some(-12),
-        "n" => Some(-9),
-        "u" => Some(-6),
-        "m" => Some(-3),
-        "k" => Some(3),
-        "M" => Some(6),
-        "G" => Some(9),
-        "T" => Some(12),
-        "P" => Some(15),
-        "E" => Some(18),
-        _ => None,
-    };
-    let time = if scale.is_some() {
+    let unit = TimeUnit::from_prefix(&time[time.len() - 1..]);
+    let time = if unit.is_some() {
         &time[..time.len() - 1]
     } else {
         time
     };
-    let scale = scale.unwrap_or(0);
+    let scale = unit.unwrap_or(TimeUnit::Second).exponent() as isize;
 
     // Split the time into integer and fractional parts.
     let mut split = time.split('.');
@@ -2,27 +2,51 @@
 
 //! Emitting LLHD IR assembly.
 
-use crate::ir::{prelude::*, UnitKind};
+use crate::{
+    analysis::TemporalRegionGraph,
+    ir::{prelude::*, UnitKind},
+    ty::{Type, TypeKind},
+};
 use std::{
     collections::{HashMap, HashSet},
     io::{Result, Write},
     rc::Rc,
 };
 
+/// Options of the assembly writer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Annotate the blocks of processes and functions with a comment naming
+    /// their temporal region, and whether they are its head or tail, such as
+    /// `; tr=t3 head`. Blocks which are unreachable belong to no region and
+    /// are not annotated.
+    pub regions: bool,
+}
+
 /// Temporary object to emit LLHD IR assembly.
 pub struct Writer<T> {
     sink: T,
+    options: WriteOptions,
 }
 
 impl<T: Write> Writer<T> {
     /// Create a new assembly writer.
     pub fn new(sink: T) -> Self {
-        Self { sink }
+        Self::with_options(sink, Default::default())
+    }
+
+    /// Create a new assembly writer with custom options.
+    pub fn with_options(sink: T, options: WriteOptions) -> Self {
+        Self { sink, options }
     }
 
     /// Emit assembly for a module.
     pub fn write_module(&mut self, module: &Module) -> Result<()> {
         let mut separate = false;
+        for def in module.types() {
+            self.write_type_def(def)?;
+            separate = true;
+        }
         for unit in module.units() {
             if separate {
                 write!(self.sink, "\n")?;
@@ -36,7 +60,7 @@ impl<T: Write> Writer<T> {
             }
             separate = false;
             let data = &module[decl];
-            self.write_declaration(&data.sig, &data.name)?;
+            self.write_declaration(data)?;
         }
         Ok(())
     }
@@ -44,6 +68,9 @@ impl<T: Write> Writer<T> {
     /// Emit assembly for a unit.
     pub fn write_unit(&mut self, data: Unit) -> Result<()> {
         let mut uw = UnitWriter::new(self, data);
+        if data.is_sim_only() {
+            write!(uw.writer.sink, "sim ")?;
+        }
         write!(uw.writer.sink, "{} {} (", data.kind(), data.name())?;
         let mut comma = false;
         for arg in data.sig().inputs() {
@@ -51,11 +78,14 @@ impl<T: Write> Writer<T> {
                 write!(uw.writer.sink, ", ")?;
             }
             comma = true;
-            write!(uw.writer.sink, "{} ", data.sig().arg_type(arg))?;
+            uw.writer.write_type(&data.sig().arg_type(arg))?;
+            write!(uw.writer.sink, " ")?;
             uw.write_value_name(data.arg_value(arg))?;
         }
         if data.kind() == UnitKind::Function {
-            write!(uw.writer.sink, ") {} {{\n", data.sig().return_type())?;
+            write!(uw.writer.sink, ") ")?;
+            uw.writer.write_type(&data.sig().return_type())?;
+            write!(uw.writer.sink, " {{\n")?;
         } else {
             write!(uw.writer.sink, ") -> (")?;
             let mut comma = false;
@@ -64,15 +94,29 @@ impl<T: Write> Writer<T> {
                     write!(uw.writer.sink, ", ")?;
                 }
                 comma = true;
-                write!(uw.writer.sink, "{} ", data.sig().arg_type(arg))?;
+                uw.writer.write_type(&data.sig().arg_type(arg))?;
+                write!(uw.writer.sink, " ")?;
                 uw.write_value_name(data.arg_value(arg))?;
             }
             write!(uw.writer.sink, ") {{\n")?;
         }
+        let trg = match data.kind() {
+            UnitKind::Entity => None,
+            _ if uw.writer.options.regions => Some(data.trg()),
+            _ => None,
+        };
         for block in data.blocks() {
             if data.kind() != UnitKind::Entity {
                 uw.write_block_name(block)?;
-                write!(uw.writer.sink, ":\n")?;
+                write!(uw.writer.sink, ":")?;
+                let annotations = data.block_annotations(block);
+                if !annotations.is_empty() {
+                    write!(uw.writer.sink, " {}", annotations)?;
+                }
+                if let Some(trg) = &trg {
+                    uw.write_block_region(trg, block)?;
+                }
+                write!(uw.writer.sink, "\n")?;
             }
             for inst in data.insts(block) {
                 if data[inst].opcode().is_terminator() && data.is_entity() {
@@ -80,6 +124,10 @@ impl<T: Write> Writer<T> {
                 }
                 write!(uw.writer.sink, "    ")?;
                 uw.write_inst(inst)?;
+                let annotations = data.inst_annotations(inst) - data.block_annotations(block);
+                if !annotations.is_empty() {
+                    write!(uw.writer.sink, " {}", annotations)?;
+                }
                 write!(uw.writer.sink, "\n")?;
             }
         }
@@ -87,9 +135,67 @@ impl<T: Write> Writer<T> {
         Ok(())
     }
 
+    /// Emit assembly for a named type definition.
+    pub fn write_type_def(&mut self, def: &TypeDef) -> Result<()> {
+        write!(self.sink, "%{} = type ", escape_name(&def.name))?;
+        if def.ty.struct_name().is_none() {
+            self.write_type(&def.ty)?;
+        } else {
+            write!(self.sink, "{{")?;
+            for (i, ty) in def.ty.unwrap_struct().iter().enumerate() {
+                if i > 0 {
+                    write!(self.sink, ", ")?;
+                }
+                self.write_type(ty)?;
+                if let Some(field) = def.field_name(i) {
+                    write!(self.sink, " %{}", escape_name(field))?;
+                }
+            }
+            write!(self.sink, "}}")?;
+        }
+        write!(self.sink, "\n")?;
+        Ok(())
+    }
+
+    /// Emit a type, referring to named struct types by their name.
+    pub fn write_type(&mut self, ty: &Type) -> Result<()> {
+        match ty.as_ref() {
+            TypeKind::NamedStructType(name, _) => write!(self.sink, "%{}", escape_name(name)),
+            TypeKind::PointerType(ty) => {
+                self.write_type(ty)?;
+                write!(self.sink, "*")
+            }
+            TypeKind::SignalType(ty) => {
+                self.write_type(ty)?;
+                write!(self.sink, "$")
+            }
+            TypeKind::ArrayType(len, ty) => {
+                write!(self.sink, "[{} x ", len)?;
+                self.write_type(ty)?;
+                write!(self.sink, "]")
+            }
+            TypeKind::StructType(tys) => {
+                write!(self.sink, "{{")?;
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.sink, ", ")?;
+                    }
+                    self.write_type(ty)?;
+                }
+                write!(self.sink, "}}")
+            }
+            _ => write!(self.sink, "{}", ty),
+        }
+    }
+
     /// Emit assembly for a declaration.
-    pub fn write_declaration(&mut self, sig: &Signature, name: &UnitName) -> Result<()> {
-        write!(self.sink, "declare {} {}\n", name, sig)?;
+    pub fn write_declaration(&mut self, data: &DeclData) -> Result<()> {
+        write!(
+            self.sink,
+            "declare {} {}\n",
+            data.name,
+            data.sig.dump_named(&data.arg_names)
+        )?;
         Ok(())
     }
 }
@@ -118,6 +224,22 @@ impl<'a, T: Write> UnitWriter<'a, T> {
         }
     }
 
+    /// Emit a comment naming the temporal region of a block, if it has one.
+    pub fn write_block_region(&mut self, trg: &TemporalRegionGraph, block: Block) -> Result<()> {
+        let tr = match trg.region(block) {
+            Some(tr) => tr,
+            None => return Ok(()),
+        };
+        write!(self.writer.sink, " ; tr={}", tr)?;
+        if trg.is_head(block) {
+            write!(self.writer.sink, " head")?;
+        }
+        if trg.is_tail(block) {
+            write!(self.writer.sink, " tail")?;
+        }
+        Ok(())
+    }
+
     /// Emit the name of a value.
     pub fn write_value_name(&mut self, value: Value) -> Result<()> {
         // If we have already picked a name for the value, use that.
@@ -188,11 +310,25 @@ impl<'a, T: Write> UnitWriter<'a, T> {
     /// Emit the use of a value.
     pub fn write_value_use(&mut self, value: Value, with_type: bool) -> Result<()> {
         if with_type {
-            write!(self.writer.sink, "{} ", self.unit.value_type(value))?;
+            self.writer.write_type(&self.unit.value_type(value))?;
+            write!(self.writer.sink, " ")?;
         }
         self.write_value_name(value)
     }
 
+    /// Emit a value connected to a port of an instance, annotated with the
+    /// port's type if one is given, or the value's own type otherwise.
+    fn write_inst_port(&mut self, value: Value, port: Option<&Type>) -> Result<()> {
+        match port {
+            Some(ty) => {
+                self.writer.write_type(ty)?;
+                write!(self.writer.sink, " ")?;
+                self.write_value_name(value)
+            }
+            None => self.write_value_use(value, true),
+        }
+    }
+
     /// Emit an instruction.
     pub fn write_inst(&mut self, inst: Inst) -> Result<()> {
         let unit = self.unit;
@@ -215,6 +351,12 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 data.opcode(),
                 data.get_const_time().unwrap()
             )?,
+            Opcode::ConstEnum => write!(
+                self.writer.sink,
+                "{} {}",
+                data.opcode(),
+                data.get_const_enum().unwrap()
+            )?,
             Opcode::ArrayUniform => {
                 write!(self.writer.sink, "[{} x ", data.imms()[0])?;
                 self.write_value_use(data.args()[0], true)?;
@@ -233,6 +375,9 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 write!(self.writer.sink, "]")?;
             }
             Opcode::Struct => {
+                if let Some(name) = unit.inst_type(inst).struct_name() {
+                    write!(self.writer.sink, "%{} ", escape_name(name))?;
+                }
                 write!(self.writer.sink, "{{")?;
                 let mut first = true;
                 for &arg in data.args() {
@@ -247,19 +392,32 @@ impl<'a, T: Write> UnitWriter<'a, T> {
             Opcode::Alias
             | Opcode::Not
             | Opcode::Neg
+            | Opcode::Popcount
+            | Opcode::Clz
+            | Opcode::Ctz
             | Opcode::Add
             | Opcode::Sub
             | Opcode::And
             | Opcode::Or
             | Opcode::Xor
             | Opcode::Smul
+            | Opcode::Smulh
             | Opcode::Sdiv
             | Opcode::Smod
             | Opcode::Srem
             | Opcode::Umul
+            | Opcode::Umulh
             | Opcode::Udiv
             | Opcode::Umod
             | Opcode::Urem
+            | Opcode::Umin
+            | Opcode::Umax
+            | Opcode::Smin
+            | Opcode::Smax
+            | Opcode::Uaddo
+            | Opcode::Saddo
+            | Opcode::Usubo
+            | Opcode::Ssubo
             | Opcode::Eq
             | Opcode::Neq
             | Opcode::Slt
@@ -277,8 +435,7 @@ impl<'a, T: Write> UnitWriter<'a, T> {
             | Opcode::Drv
             | Opcode::Var
             | Opcode::Ld
-            | Opcode::St
-            | Opcode::RetValue => {
+            | Opcode::St => {
                 write!(self.writer.sink, "{} ", data.opcode())?;
                 let mut first = true;
                 for &arg in data.args() {
@@ -288,17 +445,27 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                     self.write_value_use(arg, first)?;
                     first = false;
                 }
+                if let Some(resolution) = self.unit.signal_resolution(inst) {
+                    write!(self.writer.sink, " resolve {}", resolution)?;
+                }
+            }
+            Opcode::RetValue => {
+                let arg = data.args()[0];
+                write!(self.writer.sink, "{} ", data.opcode())?;
+                self.writer.write_type(&unit.value_type(arg))?;
+                write!(self.writer.sink, " ")?;
+                self.write_value_name(arg)?;
             }
-            Opcode::DrvCond => {
+            Opcode::DrvCond | Opcode::DelCond | Opcode::StCond => {
                 write!(self.writer.sink, "{} ", data.opcode())?;
-                let args = data.args();
+                let (&cond, args) = data.args().split_last().unwrap();
                 self.write_value_use(args[0], true)?;
                 write!(self.writer.sink, " if ")?;
-                self.write_value_use(args[3], false)?;
-                write!(self.writer.sink, ", ")?;
-                self.write_value_use(args[1], false)?;
-                write!(self.writer.sink, ", ")?;
-                self.write_value_use(args[2], false)?;
+                self.write_value_use(cond, false)?;
+                for &arg in &args[1..] {
+                    write!(self.writer.sink, ", ")?;
+                    self.write_value_use(arg, false)?;
+                }
             }
             Opcode::Shl | Opcode::Shr | Opcode::Mux => {
                 write!(self.writer.sink, "{} ", data.opcode())?;
@@ -314,6 +481,10 @@ impl<'a, T: Write> UnitWriter<'a, T> {
             Opcode::Reg => {
                 write!(self.writer.sink, "{} ", data.opcode())?;
                 self.write_value_use(data.args()[0], true)?;
+                for (control, arg) in data.control_args() {
+                    write!(self.writer.sink, " {} ", control)?;
+                    self.write_value_use(arg, false)?;
+                }
                 for t in data.triggers() {
                     write!(self.writer.sink, ", [")?;
                     self.write_value_use(t.data, false)?;
@@ -341,12 +512,8 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 }
             }
             Opcode::ExtField | Opcode::ExtSlice => {
-                write!(
-                    self.writer.sink,
-                    "{} {}",
-                    data.opcode(),
-                    unit.inst_type(inst)
-                )?;
+                write!(self.writer.sink, "{} ", data.opcode())?;
+                self.writer.write_type(&unit.inst_type(inst))?;
                 for &arg in data.args() {
                     write!(self.writer.sink, ", ")?;
                     self.write_value_use(arg, true)?;
@@ -356,16 +523,16 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 }
             }
             Opcode::Call => {
+                write!(self.writer.sink, "{} ", data.opcode())?;
+                self.writer.write_type(&if unit.has_result(inst) {
+                    unit.value_type(unit.inst_result(inst))
+                } else {
+                    crate::void_ty()
+                })?;
                 write!(
                     self.writer.sink,
-                    "{} {} {} (",
-                    data.opcode(),
-                    if unit.has_result(inst) {
-                        unit.value_type(unit.inst_result(inst))
-                    } else {
-                        crate::void_ty()
-                    },
-                    unit[data.get_ext_unit().unwrap()].name,
+                    " {} (",
+                    unit[data.get_ext_unit().unwrap()].name
                 )?;
                 let mut comma = false;
                 for &arg in data.input_args() {
@@ -378,39 +545,42 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 write!(self.writer.sink, ")")?;
             }
             Opcode::Inst => {
-                write!(
-                    self.writer.sink,
-                    "{} {} (",
-                    data.opcode(),
-                    unit[data.get_ext_unit().unwrap()].name,
-                )?;
+                let ext = &unit[data.get_ext_unit().unwrap()];
+                write!(self.writer.sink, "{} {} (", data.opcode(), ext.name)?;
+                // The arguments of a replicated instance are annotated with
+                // the type of the port they connect to, which may differ from
+                // their own type.
+                let replication = unit.inst_replication(inst);
+                let ports: Vec<_> = ext.sig.args().map(|arg| ext.sig.arg_type(arg)).collect();
                 let mut comma = false;
-                for &arg in data.input_args() {
+                for (index, &arg) in data.input_args().iter().enumerate() {
                     if comma {
                         write!(self.writer.sink, ", ")?;
                     }
                     comma = true;
-                    self.write_value_use(arg, true)?;
+                    self.write_inst_port(arg, replication.and(ports.get(index)))?;
                 }
                 write!(self.writer.sink, ") -> (")?;
                 let mut comma = false;
-                for &arg in data.output_args() {
+                for (index, &arg) in data.output_args().iter().enumerate() {
                     if comma {
                         write!(self.writer.sink, ", ")?;
                     }
                     comma = true;
-                    self.write_value_use(arg, true)?;
+                    let port = ports.get(data.input_args().len() + index);
+                    self.write_inst_port(arg, replication.and(port))?;
                 }
                 write!(self.writer.sink, ")")?;
+                if let Some(count) = replication {
+                    write!(self.writer.sink, " replicate {}", count)?;
+                }
             }
             Opcode::Halt | Opcode::Ret => write!(self.writer.sink, "{}", data.opcode())?,
             Opcode::Phi => {
-                write!(
-                    self.writer.sink,
-                    "{} {} ",
-                    data.opcode(),
-                    unit.value_type(unit.inst_result(inst))
-                )?;
+                write!(self.writer.sink, "{} ", data.opcode())?;
+                self.writer
+                    .write_type(&unit.value_type(unit.inst_result(inst)))?;
+                write!(self.writer.sink, " ")?;
                 let mut comma = false;
                 for (&arg, &block) in data.args().iter().zip(data.blocks().iter()) {
                     if comma {
@@ -435,6 +605,9 @@ impl<'a, T: Write> UnitWriter<'a, T> {
                 self.write_block_value(data.blocks()[0])?;
                 write!(self.writer.sink, ", ")?;
                 self.write_block_value(data.blocks()[1])?;
+                if let Some([w0, w1]) = self.unit.branch_weights(inst) {
+                    write!(self.writer.sink, " weights [{}, {}]", w0, w1)?;
+                }
             }
             Opcode::Wait => {
                 write!(self.writer.sink, "{} ", data.opcode())?;
@@ -5,9 +5,14 @@ extern crate clap;
 #[macro_use]
 extern crate log;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{Arg, ArgMatches};
-use llhd::{assembly::parse_module_unchecked, verifier::Verifier};
+use llhd::{
+    analysis::{check_handshakes, find_latches, HandshakeViolationKind},
+    assembly::parse_module_unchecked,
+    verifier::{Verifier, VerifierError},
+};
+use serde_json::json;
 
 fn main() {
     let matches = app_from_crate!()
@@ -34,6 +39,24 @@ fn main() {
                 .long("emit-trg")
                 .help("Analyze and emit the temporal regions"),
         )
+        .arg(
+            Arg::with_name("warn-latches")
+                .long("warn-latches")
+                .help("Warn about signals driven in only some paths of a process"),
+        )
+        .arg(
+            Arg::with_name("warn-handshakes")
+                .long("warn-handshakes")
+                .help("Warn about ready/valid handshakes which break the protocol"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Format of the reported errors; json prints one object per line"),
+        )
         .get_matches();
 
     // Configure the logger.
@@ -45,22 +68,35 @@ fn main() {
         .init()
         .unwrap();
 
+    let json = matches.value_of("format") == Some("json");
     let mut num_errors = 0;
     for path in matches.values_of("inputs").into_iter().flat_map(|x| x) {
         debug!("Parsing {}", path);
-        match process_input(path, &matches) {
+        let mut diags = vec![];
+        match process_input(path, &matches, &mut diags) {
             Ok(()) => (),
             Err(e) => {
-                println!("{}: {:#}", path, e);
+                if !json {
+                    println!("{}: {:#}", path, e);
+                } else if diags.is_empty() {
+                    println!("{}", json!({ "file": path, "message": format!("{:#}", e) }));
+                }
                 num_errors += 1;
             }
         }
+        for diag in diags {
+            println!("{}", diag);
+        }
     }
 
     std::process::exit(num_errors);
 }
 
-fn process_input(path: &str, matches: &ArgMatches) -> Result<()> {
+fn process_input(
+    path: &str,
+    matches: &ArgMatches,
+    diags: &mut Vec<serde_json::Value>,
+) -> Result<()> {
     // Parse the input.
     let input = std::fs::read_to_string(path).context("Reading file failed")?;
     let module = parse_module_unchecked(&input)
@@ -76,9 +112,66 @@ fn process_input(path: &str, matches: &ArgMatches) -> Result<()> {
     // Verify the module.
     let mut verifier = Verifier::new();
     verifier.verify_module(&module);
-    verifier
-        .finish()
-        .map_err(|errs| anyhow!("Verification failed:\n{}", errs))?;
+    if let Err(errs) = verifier.finish() {
+        if matches.value_of("format") == Some("json") {
+            diags.extend(errs.iter().map(|err| json_error(path, &input, err)));
+            bail!("Verification failed");
+        }
+        bail!("Verification failed:\n{}", errs);
+    }
+
+    // Report inferred latches if requested by the user.
+    if matches.is_present("warn-latches") {
+        for unit in module.units() {
+            for latch in find_latches(&unit) {
+                let message = latch.message(&unit);
+                if matches.value_of("format") == Some("json") {
+                    let blocks = |bbs: &[llhd::ir::Block]| {
+                        bbs.iter()
+                            .map(|bb| bb.dump(&unit).to_string())
+                            .collect::<Vec<_>>()
+                    };
+                    diags.push(json!({
+                        "file": path,
+                        "severity": "warning",
+                        "kind": "InferredLatch",
+                        "unit": unit.name().to_string(),
+                        "object": latch.signal.dump(&unit).to_string(),
+                        "driven": blocks(&latch.driven),
+                        "undriven": blocks(&latch.undriven),
+                        "message": message,
+                    }));
+                } else {
+                    println!("warning: {}: {}", unit.name(), message);
+                }
+            }
+        }
+    }
+
+    // Report handshake protocol violations if requested by the user.
+    if matches.is_present("warn-handshakes") {
+        for unit in module.units() {
+            for violation in check_handshakes(&module, &unit) {
+                let message = violation.message(&unit);
+                if matches.value_of("format") == Some("json") {
+                    let kind = match violation.kind {
+                        HandshakeViolationKind::BothSides => "HandshakeBothSides",
+                        HandshakeViolationKind::ValidDependsOnReady => "HandshakeValidOnReady",
+                    };
+                    diags.push(json!({
+                        "file": path,
+                        "severity": "warning",
+                        "kind": kind,
+                        "unit": unit.name().to_string(),
+                        "object": violation.bundle.dump(&unit).to_string(),
+                        "message": message,
+                    }));
+                } else {
+                    println!("warning: {}: {}", unit.name(), message);
+                }
+            }
+        }
+    }
 
     // Dump the temporal regions if requested by the user.
     if matches.is_present("emit-trg") {
@@ -122,9 +215,37 @@ fn process_input(path: &str, matches: &ArgMatches) -> Result<()> {
                     println!("        - {}", inst.dump(&u));
                 }
                 println!("      Tail tight: {}", tr.tail_tight);
+                if !tr.violations.is_empty() {
+                    println!("      Violations:");
+                }
+                for violation in &tr.violations {
+                    println!("        - {}", violation.describe(&u));
+                }
             }
         }
     }
 
     Ok(())
 }
+
+/// Convert a verifier error into a JSON object.
+///
+/// Resolves the error's byte offset into a line and column in the input.
+fn json_error(path: &str, input: &str, err: &VerifierError) -> serde_json::Value {
+    let mut value = json!({
+        "file": path,
+        "code": err.code.code(),
+        "kind": err.code,
+        "unit": err.unit,
+        "object": err.object,
+        "message": err.message,
+    });
+    if let Some(offset) = err.location {
+        let before = &input[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = offset - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        value["line"] = json!(line);
+        value["column"] = json!(column);
+    }
+    value
+}
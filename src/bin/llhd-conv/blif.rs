@@ -0,0 +1,1034 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! BLIF output writer
+//!
+//! This module lowers entities to a bit-level and-inverter graph (AIG), and
+//! emits that as a netlist in the Berkeley Logic Interchange Format, which can
+//! be read by logic synthesis tools such as ABC. Registers become latches,
+//! which act as the boundaries of the combinational logic.
+
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use llhd::{
+    ir::{prelude::*, RegMode},
+    Type,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+/// Emit a module as a BLIF netlist.
+///
+/// Each entity becomes a separate model. Instances are not supported, such
+/// that the module should be flattened beforehand.
+pub fn write(output: &mut impl Write, module: &Module) -> Result<()> {
+    debug!("Emitting BLIF netlist");
+    let mut skipped = vec![];
+    for unit in module.units() {
+        if unit.is_sim_only() {
+            info!("Skipping simulation-only unit {}", unit.name());
+        } else if unit.is_entity() {
+            let model = Lowering::new(unit)
+                .lower()
+                .map_err(|e| anyhow!("{}: {}", unit.name(), e))?;
+            model.write(output)?;
+        } else {
+            let name = unit.name();
+            error!("Unit {} not supported", name);
+            skipped.push(name);
+        }
+    }
+    if !skipped.is_empty() {
+        bail!(
+            "Units not supported in BLIF output: {}",
+            skipped.iter().format(", ")
+        );
+    }
+    Ok(())
+}
+
+/// A literal in an and-inverter graph.
+///
+/// The upper bits select a node, and the lowest bit indicates whether the
+/// node's output is inverted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Lit(usize);
+
+impl Lit {
+    const FALSE: Lit = Lit(0);
+    const TRUE: Lit = Lit(1);
+
+    fn node(self) -> usize {
+        self.0 >> 1
+    }
+
+    fn is_inverted(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    fn is_const(self) -> bool {
+        self.node() == 0
+    }
+
+    fn from_bool(value: bool) -> Lit {
+        if value {
+            Lit::TRUE
+        } else {
+            Lit::FALSE
+        }
+    }
+}
+
+impl std::ops::Not for Lit {
+    type Output = Lit;
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+/// A node in an and-inverter graph.
+#[derive(Debug, Clone)]
+enum Node {
+    /// The constant zero.
+    False,
+    /// A named input, such as a primary input or a latch output.
+    Input(String),
+    /// The conjunction of two literals.
+    And(Lit, Lit),
+}
+
+/// An and-inverter graph.
+///
+/// Identical AND nodes are shared, and trivial ones are simplified away as
+/// they are created.
+#[derive(Debug)]
+struct Aig {
+    nodes: Vec<Node>,
+    cache: HashMap<(Lit, Lit), Lit>,
+}
+
+impl Aig {
+    fn new() -> Self {
+        Self {
+            nodes: vec![Node::False],
+            cache: HashMap::new(),
+        }
+    }
+
+    fn input(&mut self, name: String) -> Lit {
+        self.nodes.push(Node::Input(name));
+        Lit((self.nodes.len() - 1) << 1)
+    }
+
+    fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (a, b) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+        if a == Lit::FALSE || a == !b {
+            return Lit::FALSE;
+        }
+        if a == Lit::TRUE || a == b {
+            return b;
+        }
+        if let Some(&lit) = self.cache.get(&(a, b)) {
+            return lit;
+        }
+        self.nodes.push(Node::And(a, b));
+        let lit = Lit((self.nodes.len() - 1) << 1);
+        self.cache.insert((a, b), lit);
+        lit
+    }
+
+    fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        !self.and(!a, !b)
+    }
+
+    fn xor(&mut self, a: Lit, b: Lit) -> Lit {
+        let x = self.and(a, !b);
+        let y = self.and(!a, b);
+        self.or(x, y)
+    }
+
+    /// Select `a` if `sel` is one, and `b` otherwise.
+    fn mux(&mut self, sel: Lit, a: Lit, b: Lit) -> Lit {
+        let x = self.and(sel, a);
+        let y = self.and(!sel, b);
+        self.or(x, y)
+    }
+
+    /// Select between two vectors bit by bit.
+    fn mux_bits(&mut self, sel: Lit, a: &[Lit], b: &[Lit]) -> Vec<Lit> {
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| self.mux(sel, a, b))
+            .collect()
+    }
+
+    /// Add two vectors, returning the sum and the carry out.
+    fn add(&mut self, a: &[Lit], b: &[Lit], mut carry: Lit) -> (Vec<Lit>, Lit) {
+        let mut sum = Vec::with_capacity(a.len());
+        for (&a, &b) in a.iter().zip(b) {
+            let t = self.xor(a, b);
+            sum.push(self.xor(t, carry));
+            let x = self.and(a, b);
+            let y = self.and(t, carry);
+            carry = self.or(x, y);
+        }
+        (sum, carry)
+    }
+
+    /// Subtract two vectors, returning the difference and whether `a < b` if
+    /// both are interpreted as unsigned.
+    fn sub(&mut self, a: &[Lit], b: &[Lit]) -> (Vec<Lit>, Lit) {
+        let nb: Vec<_> = b.iter().map(|&b| !b).collect();
+        let (diff, carry) = self.add(a, &nb, Lit::TRUE);
+        (diff, !carry)
+    }
+
+    /// Check whether two vectors are equal.
+    fn eq(&mut self, a: &[Lit], b: &[Lit]) -> Lit {
+        let mut acc = Lit::TRUE;
+        for (&a, &b) in a.iter().zip(b) {
+            let x = self.xor(a, b);
+            acc = self.and(acc, !x);
+        }
+        acc
+    }
+
+    /// Count the one bits of a vector with a tree of adders, yielding a count
+    /// as wide as the vector.
+    fn popcount(&mut self, a: &[Lit]) -> Vec<Lit> {
+        let mut terms: Vec<Vec<Lit>> = a
+            .iter()
+            .map(|&bit| {
+                let mut term = vec![Lit::FALSE; a.len()];
+                term[0] = bit;
+                term
+            })
+            .collect();
+        while terms.len() > 1 {
+            terms = terms
+                .chunks(2)
+                .map(|pair| match pair {
+                    [x, y] => self.add(x, y, Lit::FALSE).0,
+                    [x] => x.clone(),
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        terms.pop().unwrap_or_default()
+    }
+
+    /// Multiply two vectors, truncating the product to their width.
+    fn mul(&mut self, a: &[Lit], b: &[Lit]) -> Vec<Lit> {
+        let mut acc = vec![Lit::FALSE; a.len()];
+        for (i, &b) in b.iter().enumerate() {
+            let mut partial = vec![Lit::FALSE; i];
+            partial.extend(a.iter().map(|&a| self.and(a, b)));
+            partial.truncate(a.len());
+            acc = self.add(&acc, &partial, Lit::FALSE).0;
+        }
+        acc
+    }
+}
+
+/// A latch in a lowered entity.
+#[derive(Debug)]
+struct Latch {
+    /// The latch output.
+    output: Lit,
+    /// The value stored when the latch is triggered.
+    next: Lit,
+    /// The trigger of the latch.
+    control: Lit,
+    /// When the latch is triggered.
+    mode: RegMode,
+    /// The initial value of the latch, if known.
+    init: Option<bool>,
+}
+
+/// An entity lowered to an and-inverter graph.
+#[derive(Debug)]
+struct Model {
+    name: String,
+    aig: Aig,
+    inputs: Vec<Lit>,
+    outputs: Vec<(String, Lit)>,
+    latches: Vec<Latch>,
+}
+
+impl Model {
+    /// Determine which nodes the outputs and latches depend on.
+    fn used_nodes(&self) -> Vec<bool> {
+        let mut used = vec![false; self.aig.nodes.len()];
+        let roots = self.outputs.iter().map(|&(_, lit)| lit).chain(
+            self.latches
+                .iter()
+                .flat_map(|latch| vec![latch.next, latch.control]),
+        );
+        for lit in roots {
+            used[lit.node()] = true;
+        }
+        // The operands of a node always precede it.
+        for index in (0..used.len()).rev() {
+            if let (true, Node::And(a, b)) = (used[index], &self.aig.nodes[index]) {
+                used[a.node()] = true;
+                used[b.node()] = true;
+            }
+        }
+        used
+    }
+
+    /// Emit the model in BLIF.
+    fn write(&self, output: &mut impl Write) -> Result<()> {
+        let mut names = Names::new(&self.aig);
+        writeln!(output, ".model {}", self.name)?;
+        write!(output, ".inputs")?;
+        for &input in &self.inputs {
+            write!(output, " {}", names.node(input.node()))?;
+        }
+        writeln!(output)?;
+        write!(output, ".outputs")?;
+        for (name, _) in &self.outputs {
+            write!(output, " {}", name)?;
+        }
+        writeln!(output)?;
+        for latch in &self.latches {
+            let kind = match latch.mode {
+                RegMode::Rise => "re",
+                RegMode::Fall => "fe",
+                RegMode::High => "ah",
+                RegMode::Low => "al",
+                RegMode::Both => unreachable!(),
+            };
+            let init = match latch.init {
+                Some(false) => 0,
+                Some(true) => 1,
+                None => 3,
+            };
+            writeln!(
+                output,
+                ".latch {} {} {} {} {}",
+                names.lit(latch.next),
+                names.node(latch.output.node()),
+                kind,
+                names.lit(latch.control),
+                init
+            )?;
+        }
+        let used = self.used_nodes();
+        for (index, node) in self.aig.nodes.iter().enumerate() {
+            if !used[index] {
+                continue;
+            }
+            if let Node::And(a, b) = *node {
+                writeln!(
+                    output,
+                    ".names {} {} {}",
+                    names.node(a.node()),
+                    names.node(b.node()),
+                    names.node(index)
+                )?;
+                writeln!(
+                    output,
+                    "{}{} 1",
+                    if a.is_inverted() { 0 } else { 1 },
+                    if b.is_inverted() { 0 } else { 1 }
+                )?;
+            }
+        }
+        for (name, lit) in &self.outputs {
+            if !lit.is_const() && !lit.is_inverted() && names.node(lit.node()) == *name {
+                continue;
+            }
+            write_buffer(output, &names, *lit, name)?;
+        }
+        for (lit, name) in std::mem::take(&mut names.extra) {
+            write_buffer(output, &names, lit, &name)?;
+        }
+        writeln!(output, ".end")?;
+        writeln!(output)?;
+        Ok(())
+    }
+}
+
+/// Emit a single-input `.names` table that drives a net from a literal.
+fn write_buffer(output: &mut impl Write, names: &Names, lit: Lit, name: &str) -> Result<()> {
+    if lit.is_const() {
+        writeln!(output, ".names {}", name)?;
+        if lit == Lit::TRUE {
+            writeln!(output, "1")?;
+        }
+    } else {
+        writeln!(output, ".names {} {}", names.node(lit.node()), name)?;
+        writeln!(output, "{} 1", if lit.is_inverted() { 0 } else { 1 })?;
+    }
+    Ok(())
+}
+
+/// The net names of the nodes in an and-inverter graph.
+struct Names<'a> {
+    aig: &'a Aig,
+    /// Additional nets for inverted and constant literals.
+    extra: Vec<(Lit, String)>,
+}
+
+impl<'a> Names<'a> {
+    fn new(aig: &'a Aig) -> Self {
+        Self { aig, extra: vec![] }
+    }
+
+    /// Get the net name of a node.
+    fn node(&self, index: usize) -> String {
+        match &self.aig.nodes[index] {
+            Node::Input(name) => name.clone(),
+            _ => format!("_n{}", index),
+        }
+    }
+
+    /// Get the net name of a literal, creating an additional net if needed.
+    fn lit(&mut self, lit: Lit) -> String {
+        if !lit.is_const() && !lit.is_inverted() {
+            return self.node(lit.node());
+        }
+        if let Some((_, name)) = self.extra.iter().find(|(l, _)| *l == lit) {
+            return name.clone();
+        }
+        let name = match lit {
+            Lit::FALSE => "_false".to_string(),
+            Lit::TRUE => "_true".to_string(),
+            _ => format!("_n{}_inv", lit.node()),
+        };
+        self.extra.push((lit, name.clone()));
+        name
+    }
+}
+
+/// What drives a bit of a signal.
+#[derive(Debug, Clone, Copy)]
+enum Driver {
+    /// A bit of a value driven onto the signal with `drv`.
+    Value(Value, usize),
+    /// The output of a latch.
+    Latch(Lit),
+}
+
+/// The lowering of an entity to an and-inverter graph.
+struct Lowering<'a> {
+    unit: Unit<'a>,
+    aig: Aig,
+    /// The bits of the values lowered so far.
+    values: HashMap<Value, Vec<Lit>>,
+    /// The values currently being lowered, to detect combinational loops.
+    pending: HashSet<Value>,
+    /// The driver of each signal bit, by root signal and bit offset.
+    drivers: HashMap<(Value, usize), Driver>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(unit: Unit<'a>) -> Self {
+        Self {
+            unit,
+            aig: Aig::new(),
+            values: HashMap::new(),
+            pending: HashSet::new(),
+            drivers: HashMap::new(),
+        }
+    }
+
+    fn lower(mut self) -> Result<Model> {
+        let unit = self.unit;
+
+        // Create the primary inputs.
+        let mut inputs = vec![];
+        for arg in unit.input_args() {
+            let name = self.signal_name(arg);
+            let bits: Vec<_> = bit_names(&name, bit_width(&unit.value_type(arg))?)
+                .into_iter()
+                .map(|name| self.aig.input(name))
+                .collect();
+            inputs.extend(bits.iter().cloned());
+            self.values.insert(arg, bits);
+        }
+
+        // Find the drivers of all signals, creating latches for registers.
+        let mut regs = vec![];
+        for inst in unit.all_insts() {
+            let data = &unit[inst];
+            match data.opcode() {
+                Opcode::Drv => {
+                    let (root, offset, width) = self.signal_target(data.args()[0])?;
+                    for i in 0..width {
+                        self.add_driver(root, offset + i, Driver::Value(data.args()[1], i))?;
+                    }
+                }
+                Opcode::Reg => {
+                    let (root, offset, width) = self.signal_target(data.args()[0])?;
+                    let name = self.signal_name(root);
+                    let names = bit_names(&name, bit_width(&unit.value_type(root))?);
+                    let mut bits = vec![];
+                    for i in 0..width {
+                        let lit = self.aig.input(names[offset + i].clone());
+                        self.add_driver(root, offset + i, Driver::Latch(lit))?;
+                        bits.push(lit);
+                    }
+                    regs.push((inst, root, offset, bits));
+                }
+                Opcode::DrvCond => bail!("conditional drives not supported"),
+                Opcode::Inst => bail!("instances not supported; flatten the module first"),
+                Opcode::Con => bail!("signal connections not supported"),
+                Opcode::Del | Opcode::DelCond => bail!("delayed signals not supported"),
+                _ => (),
+            }
+        }
+
+        // Compute the primary outputs.
+        let mut outputs = vec![];
+        for arg in unit.output_args() {
+            let name = self.signal_name(arg);
+            let width = bit_width(&unit.value_type(arg))?;
+            let bits = self.signal_bits(arg, 0, width)?;
+            outputs.extend(bit_names(&name, width).into_iter().zip(bits));
+        }
+
+        // Compute the next state of the latches.
+        let mut latches = vec![];
+        for (inst, root, offset, bits) in regs {
+            latches.extend(self.lower_reg(inst, root, offset, bits)?);
+        }
+
+        Ok(Model {
+            name: sanitize_unit_name(unit.name()),
+            aig: self.aig,
+            inputs,
+            outputs,
+            latches,
+        })
+    }
+
+    /// Record the driver of a signal bit.
+    fn add_driver(&mut self, root: Value, bit: usize, driver: Driver) -> Result<()> {
+        if self.unit.get_value_arg(root).is_some() && self.unit.input_args().any(|a| a == root) {
+            bail!("input {} is driven", root.dump(&self.unit));
+        }
+        if self.drivers.insert((root, bit), driver).is_some() {
+            bail!(
+                "bit {} of signal {} has multiple drivers",
+                bit,
+                root.dump(&self.unit)
+            );
+        }
+        Ok(())
+    }
+
+    /// Compute the latches of a register.
+    fn lower_reg(
+        &mut self,
+        inst: Inst,
+        root: Value,
+        offset: usize,
+        bits: Vec<Lit>,
+    ) -> Result<Vec<Latch>> {
+        let unit = self.unit;
+        let data = &unit[inst];
+        let triggers: Vec<_> = data.triggers().collect();
+        let trigger = match triggers.as_slice() {
+            [trigger] => *trigger,
+            _ => bail!("registers with multiple triggers not supported"),
+        };
+        if trigger.mode == RegMode::Both {
+            bail!("registers triggered on both edges not supported");
+        }
+        let ctrl = data.reg_controls();
+        if ctrl.clear.is_some() || ctrl.preset.is_some() {
+            bail!("registers with asynchronous clear or preset not supported");
+        }
+
+        // Determine the initial value, either from the register itself or the
+        // signal it drives.
+        let init = match (ctrl.init, self.unit.get_value_inst(root)) {
+            (Some(init), _) => Some(self.lower_value(init)?[..].to_vec()),
+            (None, Some(sig)) => Some(
+                self.lower_value(self.unit[sig].args()[0])?[offset..offset + bits.len()].to_vec(),
+            ),
+            (None, None) => None,
+        };
+
+        // Compute the next state, which retains the current state unless the
+        // register is enabled.
+        let mut next = self.lower_value(trigger.data)?;
+        let mut enable = Lit::TRUE;
+        for cond in trigger.gate.into_iter().chain(ctrl.enable) {
+            let cond = self.lower_value(cond)?[0];
+            enable = self.aig.and(enable, cond);
+        }
+        next = self.aig.mux_bits(enable, &next, &bits);
+        let control = self.lower_value(trigger.trigger)?[0];
+
+        Ok(bits
+            .into_iter()
+            .enumerate()
+            .map(|(i, output)| Latch {
+                output,
+                next: next[i],
+                control,
+                mode: trigger.mode,
+                init: init
+                    .as_ref()
+                    .map(|init| init[i])
+                    .filter(|lit| lit.is_const())
+                    .map(|lit| lit == Lit::TRUE),
+            })
+            .collect())
+    }
+
+    /// Find the root signal and bit range a signal value refers to.
+    fn signal_target(&self, value: Value) -> Result<(Value, usize, usize)> {
+        let unit = self.unit;
+        let width = bit_width(&unit.value_type(value))?;
+        let inst = match unit.get_value_inst(value) {
+            Some(inst) => inst,
+            None => return Ok((value, 0, width)),
+        };
+        let data = &unit[inst];
+        match data.opcode() {
+            Opcode::Sig => match unit.signal_resolution(inst) {
+                None | Some(Resolution::LastWrite) => Ok((value, 0, width)),
+                Some(resolution) => bail!(
+                    "signal {} with {} resolution cannot be lowered",
+                    value.dump(&unit),
+                    resolution
+                ),
+            },
+            Opcode::ExtSlice | Opcode::ExtField => {
+                let (root, offset, _) = self.signal_target(data.args()[0])?;
+                let ty = unit.value_type(data.args()[0]);
+                let (start, _) = field_range(ty.unwrap_signal(), data.opcode(), data.imms())?;
+                Ok((root, offset + start, width))
+            }
+            _ => bail!("cannot determine signal {}", value.dump(&unit)),
+        }
+    }
+
+    /// Get the name of a signal.
+    fn signal_name(&self, value: Value) -> String {
+        match self.unit.get_name(value) {
+            Some(name) => sanitize_name(name),
+            None => format!("_{}", value),
+        }
+    }
+
+    /// Get the current value of a range of signal bits.
+    fn signal_bits(&mut self, value: Value, offset: usize, width: usize) -> Result<Vec<Lit>> {
+        let (root, root_offset, _) = self.signal_target(value)?;
+        if let Some(bits) = self.values.get(&root) {
+            return Ok(bits[root_offset + offset..root_offset + offset + width].to_vec());
+        }
+        let mut bits = vec![];
+        for i in root_offset + offset..root_offset + offset + width {
+            bits.push(match self.drivers.get(&(root, i)).cloned() {
+                Some(Driver::Value(value, bit)) => self.lower_value(value)?[bit],
+                Some(Driver::Latch(lit)) => lit,
+                None => match self.unit.get_value_inst(root) {
+                    Some(sig) => self.lower_value(self.unit[sig].args()[0])?[i],
+                    None => {
+                        warn!("Output {} is not driven", root.dump(&self.unit));
+                        Lit::FALSE
+                    }
+                },
+            });
+        }
+        Ok(bits)
+    }
+
+    /// Get the bits of a value.
+    fn lower_value(&mut self, value: Value) -> Result<Vec<Lit>> {
+        if let Some(bits) = self.values.get(&value) {
+            return Ok(bits.clone());
+        }
+        if !self.pending.insert(value) {
+            bail!("combinational loop through {}", value.dump(&self.unit));
+        }
+        let inst = self
+            .unit
+            .get_value_inst(value)
+            .ok_or_else(|| anyhow!("cannot lower {}", value.dump(&self.unit)))?;
+        let bits = self.lower_inst(inst)?;
+        self.pending.remove(&value);
+        self.values.insert(value, bits.clone());
+        Ok(bits)
+    }
+
+    /// Compute the bits of an instruction's result.
+    fn lower_inst(&mut self, inst: Inst) -> Result<Vec<Lit>> {
+        let unit = self.unit;
+        let data = &unit[inst];
+        let ty = unit.inst_type(inst);
+        let args = data.args();
+        let bits = match data.opcode() {
+            Opcode::ConstInt => {
+                let imm = data.get_const_int().unwrap();
+                (0..imm.width)
+                    .map(|i| Lit::from_bool(imm.extract_slice(i, 1).is_one()))
+                    .collect()
+            }
+            Opcode::ConstEnum => {
+                let imm = data.get_const_enum().unwrap();
+                (0..bit_width(&ty)?)
+                    .map(|i| Lit::from_bool(imm.value >> i & 1 != 0))
+                    .collect()
+            }
+            Opcode::Alias => self.lower_value(args[0])?,
+            Opcode::ArrayUniform => {
+                let bits = self.lower_value(args[0])?;
+                bits.repeat(data.imms()[0])
+            }
+            Opcode::Array | Opcode::Struct => {
+                let mut bits = vec![];
+                for &arg in args {
+                    bits.extend(self.lower_value(arg)?);
+                }
+                bits
+            }
+            Opcode::Not => {
+                let a = self.lower_value(args[0])?;
+                a.into_iter().map(|a| !a).collect()
+            }
+            Opcode::Neg => {
+                let a = self.lower_value(args[0])?;
+                let zero = vec![Lit::FALSE; a.len()];
+                self.aig.sub(&zero, &a).0
+            }
+            Opcode::Popcount => {
+                let a = self.lower_value(args[0])?;
+                self.aig.popcount(&a)
+            }
+            // Start with the count for a zero value, and let each one bit
+            // override the count of the bits further from the start.
+            Opcode::Clz | Opcode::Ctz => {
+                let a = self.lower_value(args[0])?;
+                let width = a.len();
+                let mut count = constant_bits(width, width);
+                for i in 0..width {
+                    let (bit, zeros) = match data.opcode() {
+                        Opcode::Clz => (i, width - 1 - i),
+                        _ => (width - 1 - i, width - 1 - i),
+                    };
+                    let zeros = constant_bits(zeros, width);
+                    count = self.aig.mux_bits(a[bit], &zeros, &count);
+                }
+                count
+            }
+            Opcode::And | Opcode::Or | Opcode::Xor => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| match data.opcode() {
+                        Opcode::And => self.aig.and(a, b),
+                        Opcode::Or => self.aig.or(a, b),
+                        _ => self.aig.xor(a, b),
+                    })
+                    .collect()
+            }
+            Opcode::Add => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                self.aig.add(&a, &b, Lit::FALSE).0
+            }
+            Opcode::Sub => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                self.aig.sub(&a, &b).0
+            }
+            // The flag is appended as the second field of the result.
+            Opcode::Uaddo | Opcode::Saddo | Opcode::Usubo | Opcode::Ssubo => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                let is_add = [Opcode::Uaddo, Opcode::Saddo].contains(&data.opcode());
+                let (mut bits, carry) = if is_add {
+                    self.aig.add(&a, &b, Lit::FALSE)
+                } else {
+                    self.aig.sub(&a, &b)
+                };
+                let flag = match data.opcode() {
+                    Opcode::Uaddo | Opcode::Usubo => carry,
+                    _ if a.is_empty() => Lit::FALSE,
+                    // Signed overflow occurs if the operands added have the
+                    // same sign, and the result has the opposite one.
+                    _ => {
+                        let msb = a.len() - 1;
+                        let sb = if is_add { b[msb] } else { !b[msb] };
+                        let same = !self.aig.xor(a[msb], sb);
+                        let flipped = self.aig.xor(a[msb], bits[msb]);
+                        self.aig.and(same, flipped)
+                    }
+                };
+                bits.push(flag);
+                bits
+            }
+            // The truncated product is the same for signed and unsigned
+            // operands.
+            Opcode::Umul | Opcode::Smul => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                self.aig.mul(&a, &b)
+            }
+            // The upper half is taken from the product of the operands
+            // extended to twice their width.
+            Opcode::Umulh | Opcode::Smulh => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                let width = a.len();
+                let signed = data.opcode() == Opcode::Smulh;
+                let extend = |mut x: Vec<Lit>| {
+                    let fill = match x.last() {
+                        Some(&msb) if signed => msb,
+                        _ => Lit::FALSE,
+                    };
+                    x.resize(2 * width, fill);
+                    x
+                };
+                self.aig.mul(&extend(a), &extend(b)).split_off(width)
+            }
+            Opcode::Eq | Opcode::Neq => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                let eq = self.aig.eq(&a, &b);
+                vec![if data.opcode() == Opcode::Eq { eq } else { !eq }]
+            }
+            Opcode::Ult
+            | Opcode::Ugt
+            | Opcode::Ule
+            | Opcode::Uge
+            | Opcode::Slt
+            | Opcode::Sgt
+            | Opcode::Sle
+            | Opcode::Sge => {
+                let mut a = self.lower_value(args[0])?;
+                let mut b = self.lower_value(args[1])?;
+                // Signed comparisons are unsigned ones with flipped sign bits.
+                let signed =
+                    [Opcode::Slt, Opcode::Sgt, Opcode::Sle, Opcode::Sge].contains(&data.opcode());
+                if signed && !a.is_empty() {
+                    let msb = a.len() - 1;
+                    a[msb] = !a[msb];
+                    b[msb] = !b[msb];
+                }
+                let lit = match data.opcode() {
+                    Opcode::Ult | Opcode::Slt => self.aig.sub(&a, &b).1,
+                    Opcode::Ugt | Opcode::Sgt => self.aig.sub(&b, &a).1,
+                    Opcode::Ule | Opcode::Sle => !self.aig.sub(&b, &a).1,
+                    _ => !self.aig.sub(&a, &b).1,
+                };
+                vec![lit]
+            }
+            // Select the first operand if it is less than (for the minimum) or
+            // not less than (for the maximum) the second one.
+            Opcode::Umin | Opcode::Umax | Opcode::Smin | Opcode::Smax => {
+                let a = self.lower_value(args[0])?;
+                let b = self.lower_value(args[1])?;
+                let (mut sa, mut sb) = (a.clone(), b.clone());
+                if [Opcode::Smin, Opcode::Smax].contains(&data.opcode()) && !a.is_empty() {
+                    let msb = a.len() - 1;
+                    sa[msb] = !sa[msb];
+                    sb[msb] = !sb[msb];
+                }
+                let lt = self.aig.sub(&sa, &sb).1;
+                match data.opcode() {
+                    Opcode::Umin | Opcode::Smin => self.aig.mux_bits(lt, &a, &b),
+                    _ => self.aig.mux_bits(lt, &b, &a),
+                }
+            }
+            Opcode::Shl | Opcode::Shr => {
+                let base = self.lower_value(args[0])?;
+                let hidden = self.lower_value(args[1])?;
+                let amount = self.lower_value(args[2])?;
+                self.lower_shift(data.opcode() == Opcode::Shl, base, hidden, amount)
+            }
+            Opcode::Mux => {
+                let choices = self.lower_value(args[0])?;
+                let sel = self.lower_value(args[1])?;
+                let width = bit_width(&ty)?;
+                let mut options: Vec<Vec<Lit>> =
+                    choices.chunks(width.max(1)).map(|c| c.to_vec()).collect();
+                // Select among pairs of options, one bit of the selector at a
+                // time. Out-of-range selections yield the last option.
+                for &bit in &sel {
+                    if options.len() <= 1 {
+                        break;
+                    }
+                    options = options
+                        .chunks(2)
+                        .map(|pair| match pair {
+                            [a, b] => self.aig.mux_bits(bit, b, a),
+                            [a] => a.clone(),
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                }
+                options.swap_remove(0)
+            }
+            Opcode::ExtField | Opcode::ExtSlice => {
+                let bits = self.lower_value(args[0])?;
+                let (start, len) =
+                    field_range(&unit.value_type(args[0]), data.opcode(), data.imms())?;
+                bits[start..start + len].to_vec()
+            }
+            Opcode::InsField | Opcode::InsSlice => {
+                let mut bits = self.lower_value(args[0])?;
+                let value = self.lower_value(args[1])?;
+                let opcode = if data.opcode() == Opcode::InsField {
+                    Opcode::ExtField
+                } else {
+                    Opcode::ExtSlice
+                };
+                let (start, len) = field_range(&ty, opcode, data.imms())?;
+                bits[start..start + len].copy_from_slice(&value);
+                bits
+            }
+            Opcode::Prb => {
+                let width = bit_width(&ty)?;
+                self.signal_bits(args[0], 0, width)?
+            }
+            _ => bail!("{} not supported", inst.dump(&unit)),
+        };
+        Ok(bits)
+    }
+
+    /// Compute the bits of a shift instruction.
+    ///
+    /// The base and hidden values are concatenated, shifted by the amount, and
+    /// the bits in the position of the base are extracted.
+    fn lower_shift(
+        &mut self,
+        left: bool,
+        base: Vec<Lit>,
+        hidden: Vec<Lit>,
+        amount: Vec<Lit>,
+    ) -> Vec<Lit> {
+        let (bw, hw) = (base.len(), hidden.len());
+        let mut full = if left {
+            let mut full = hidden;
+            full.extend(base);
+            full
+        } else {
+            let mut full = base;
+            full.extend(hidden);
+            full
+        };
+
+        // Shifting by more than the width of the hidden value is equivalent to
+        // shifting by exactly that width.
+        let max: Vec<_> = (0..amount.len())
+            .map(|i| Lit::from_bool(i < 64 && hw >> i & 1 != 0))
+            .collect();
+        let over = if amount.len() < 64 && hw >> amount.len() != 0 {
+            Lit::FALSE
+        } else {
+            self.aig.sub(&max, &amount).1
+        };
+        let amount = self.aig.mux_bits(over, &max, &amount);
+
+        // Shift in stages of powers of two.
+        for (i, &bit) in amount.iter().enumerate() {
+            let step = match 1usize.checked_shl(i as u32) {
+                Some(step) if step < full.len() => step,
+                _ => break,
+            };
+            let shifted: Vec<_> = (0..full.len())
+                .map(|j| {
+                    if left {
+                        j.checked_sub(step).map(|k| full[k])
+                    } else {
+                        full.get(j + step).cloned()
+                    }
+                    .unwrap_or(Lit::FALSE)
+                })
+                .collect();
+            full = self.aig.mux_bits(bit, &shifted, &full);
+        }
+        if left {
+            full[hw..hw + bw].to_vec()
+        } else {
+            full[0..bw].to_vec()
+        }
+    }
+}
+
+/// Get the bits of a constant.
+fn constant_bits(value: usize, width: usize) -> Vec<Lit> {
+    (0..width)
+        .map(|i| Lit::from_bool(i < 64 && value >> i & 1 != 0))
+        .collect()
+}
+
+/// Compute the number of bits in a type.
+fn bit_width(ty: &Type) -> Result<usize> {
+    match ty.as_ref() {
+        llhd::IntType(w) => Ok(*w),
+        llhd::EnumType(n) => Ok(enum_width(*n)),
+        llhd::SignalType(ty) => bit_width(ty),
+        llhd::ArrayType(n, ty) => Ok(n * bit_width(ty)?),
+        llhd::StructType(tys) | llhd::NamedStructType(_, tys) => tys.iter().map(bit_width).sum(),
+        _ => bail!("type {} not supported", ty),
+    }
+}
+
+/// Compute the number of bits needed to encode the states of an enum.
+fn enum_width(n: usize) -> usize {
+    (usize::BITS - n.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Compute the bit range accessed by an `extf` or `exts` instruction.
+fn field_range(ty: &Type, opcode: Opcode, imms: &[usize]) -> Result<(usize, usize)> {
+    let ty = if ty.is_signal() {
+        ty.unwrap_signal()
+    } else {
+        ty
+    };
+    Ok(match (ty.as_ref(), opcode) {
+        (llhd::IntType(_), Opcode::ExtSlice) => (imms[0], imms[1]),
+        (llhd::ArrayType(_, elem), Opcode::ExtSlice) => {
+            let w = bit_width(elem)?;
+            (imms[0] * w, imms[1] * w)
+        }
+        (llhd::ArrayType(_, elem), Opcode::ExtField) => {
+            let w = bit_width(elem)?;
+            (imms[0] * w, w)
+        }
+        (llhd::StructType(fields), Opcode::ExtField)
+        | (llhd::NamedStructType(_, fields), Opcode::ExtField) => {
+            let mut start = 0;
+            for field in &fields[..imms[0]] {
+                start += bit_width(field)?;
+            }
+            (start, bit_width(&fields[imms[0]])?)
+        }
+        _ => bail!("cannot access {} of type {}", opcode, ty),
+    })
+}
+
+/// Generate the net names of the bits of a value.
+fn bit_names(name: &str, width: usize) -> Vec<String> {
+    if width == 1 {
+        vec![name.to_string()]
+    } else {
+        (0..width).map(|i| format!("{}[{}]", name, i)).collect()
+    }
+}
+
+/// Make a unit name printable in BLIF.
+fn sanitize_unit_name(name: &UnitName) -> String {
+    match name {
+        UnitName::Global(s) => sanitize_name(s),
+        UnitName::Local(s) => format!("_{}", sanitize_name(s)),
+        UnitName::Anonymous(i) => format!("_{}", i),
+    }
+}
+
+/// Make a name printable in BLIF.
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
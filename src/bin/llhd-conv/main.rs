@@ -8,8 +8,9 @@
 extern crate log;
 
 use anyhow::{anyhow, bail, Context, Result};
-use clap::{App, Arg};
-use llhd::ir::Module;
+use clap::{App, Arg, ArgMatches};
+use llhd::{config::Config, constraints::Constraints, ir::Module, opt::prelude::*};
+use serde::Serialize;
 use std::{
     fs::File,
     io::{BufReader, BufWriter, Read, Write},
@@ -17,6 +18,7 @@ use std::{
     str::FromStr,
 };
 
+mod blif;
 mod liberty;
 pub mod verilog;
 
@@ -49,9 +51,71 @@ fn main() -> Result<()> {
         .arg(
             Arg::with_name("output-format")
                 .long("output-format")
+                .visible_alias("emit")
                 .takes_value(true)
                 .help("Format of the output; auto-detected if omitted"),
         )
+        .arg(
+            Arg::with_name("graph")
+                .long("graph")
+                .takes_value(true)
+                .possible_values(&["cfg", "dfg", "hierarchy"])
+                .default_value("cfg")
+                .help("Graph to emit for dot output"),
+        )
+        .arg(
+            Arg::with_name("unit")
+                .long("unit")
+                .takes_value(true)
+                .help("Unit to emit the cfg or dfg of for dot output, e.g. `@foo`"),
+        )
+        .arg(
+            Arg::with_name("value")
+                .long("value")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Value to emit the dfg of for dot output, e.g. `%x`"),
+        )
+        .arg(
+            Arg::with_name("registers")
+                .long("registers")
+                .takes_value(true)
+                .possible_values(&["behavioral", "structural"])
+                .help("Emit registers as always blocks or as the cells in the configuration for Verilog output"),
+        )
+        .arg(
+            Arg::with_name("name-map")
+                .long("name-map")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Record the LLHD names of the identifiers in Verilog output in a JSON file, or in comments if `comment`"),
+        )
+        .arg(
+            Arg::with_name("escape-names")
+                .long("escape-names")
+                .help("Emit invalid names as escaped identifiers in Verilog output"),
+        )
+        .arg(
+            Arg::with_name("annotate-regions")
+                .long("annotate-regions")
+                .help("Annotate the blocks in LLHD output with their temporal region"),
+        )
+        .arg(
+            Arg::with_name("constraints")
+                .long("constraints")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Timing constraints in SDC format to check against the design and include in reports"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Read passes and output options from a TOML or JSON file"),
+        )
         .arg(
             Arg::with_name("dump")
                 .long("--dump")
@@ -64,6 +128,12 @@ fn main() -> Result<()> {
         .format_timestamp(None)
         .init();
 
+    // Read the configuration file.
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(path).map_err(|e| anyhow!(e))?,
+        None => Config::default(),
+    };
+
     // Setup the input reader.
     let input_path = matches.value_of("input").map(Path::new);
     let input_stream: Box<dyn Read> = match input_path {
@@ -127,7 +197,10 @@ fn main() -> Result<()> {
     debug!("Input format `{}`", input_format);
 
     // Detect the output format.
-    let output_format = match matches.value_of("output-format") {
+    let output_format = match matches
+        .value_of("output-format")
+        .or(config.output.format.as_deref())
+    {
         Some(fmt) => {
             Format::from_str(fmt).map_err(|_| anyhow!("Unknown output format `{}`", fmt))?
         }
@@ -149,12 +222,29 @@ fn main() -> Result<()> {
     debug!("Output format `{}`", output_format);
 
     // Process the input.
-    let module = read_input(
+    let mut module = read_input(
         &mut BufReader::with_capacity(1 << 20, input_stream),
         input_format,
     )
     .with_context(|| format!("Failed to read input from {}", input_name))?;
 
+    // Run the passes requested by the configuration.
+    if let Some(passes) = &config.passes {
+        let mut ctx = PassContext::default();
+        config.apply(&mut ctx).map_err(|e| anyhow!(e))?;
+        for pass in passes {
+            debug!("Running pass {}", pass);
+            config
+                .run_pass(pass, &mut ctx, &mut module)
+                .ok_or_else(|| anyhow!("Unknown pass `{}`", pass))?;
+        }
+        let mut verifier = llhd::verifier::Verifier::new();
+        verifier.verify_module(&module);
+        verifier
+            .finish()
+            .map_err(|errs| anyhow!("Verification failed after passes:\n{}", errs))?;
+    }
+
     // Dump the IR if requested.
     if matches.is_present("dump") {
         eprintln!("{}", module.dump());
@@ -165,6 +255,8 @@ fn main() -> Result<()> {
         &module,
         &mut BufWriter::with_capacity(1 << 20, output_stream),
         output_format,
+        &matches,
+        &config,
     )
     .with_context(|| format!("Failed to write output to {}", output_name))?;
 
@@ -180,6 +272,14 @@ enum Format {
     Firrtl,
     Edif,
     Liberty,
+    Dot,
+    GraphMl,
+    EdgeList,
+    Blif,
+    Report,
+    Json,
+    Sharing,
+    Fanout,
 }
 
 impl FromStr for Format {
@@ -193,6 +293,14 @@ impl FromStr for Format {
             "fir" => Ok(Format::Firrtl),
             "edif" => Ok(Format::Edif),
             "lib" => Ok(Format::Liberty),
+            "dot" => Ok(Format::Dot),
+            "graphml" => Ok(Format::GraphMl),
+            "edges" => Ok(Format::EdgeList),
+            "blif" => Ok(Format::Blif),
+            "report" => Ok(Format::Report),
+            "json" => Ok(Format::Json),
+            "sharing" => Ok(Format::Sharing),
+            "fanout" => Ok(Format::Fanout),
             _ => Err(()),
         }
     }
@@ -208,6 +316,14 @@ impl std::fmt::Display for Format {
             Format::Firrtl => write!(f, "FIRRTL"),
             Format::Edif => write!(f, "EDIF netlist"),
             Format::Liberty => write!(f, "LIB file"),
+            Format::Dot => write!(f, "Graphviz"),
+            Format::GraphMl => write!(f, "GraphML data flow graph"),
+            Format::EdgeList => write!(f, "data flow edge list"),
+            Format::Blif => write!(f, "BLIF netlist"),
+            Format::Report => write!(f, "area report"),
+            Format::Json => write!(f, "JSON area report"),
+            Format::Sharing => write!(f, "sharing report"),
+            Format::Fanout => write!(f, "fan-out report"),
         }
     }
 }
@@ -230,16 +346,260 @@ fn read_input(input: &mut impl Read, format: Format) -> Result<llhd::ir::Module>
     }
 }
 
-fn write_output(module: &llhd::ir::Module, output: &mut impl Write, format: Format) -> Result<()> {
+fn write_output(
+    module: &llhd::ir::Module,
+    output: &mut impl Write,
+    format: Format,
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<()> {
     match format {
         Format::Assembly => {
-            llhd::assembly::write_module(output, module);
+            let options = llhd::assembly::WriteOptions {
+                regions: config.output.annotate_regions || matches.is_present("annotate-regions"),
+            };
+            llhd::assembly::write_module_with(output, module, options);
             Ok(())
         }
         Format::Verilog => {
-            crate::verilog::write(output, module)?;
+            // Options on the command line take precedence over the configuration.
+            let mut options = config.output.clone();
+            if let Some(registers) = matches.value_of("registers") {
+                options.registers = Some(registers.to_string());
+            }
+            if let Some(name_map) = matches.value_of("name-map") {
+                options.name_map = Some(name_map.to_string());
+            }
+            options.escape_names |= matches.is_present("escape-names");
+            let names = crate::verilog::write(output, module, &options)?;
+            match options.name_map.as_deref() {
+                None | Some("comment") => (),
+                Some(path) => {
+                    let file = File::create(path)
+                        .with_context(|| format!("Failed to create name map `{}`", path))?;
+                    serde_json::to_writer_pretty(BufWriter::new(file), &names)?;
+                }
+            }
             Ok(())
         }
+        Format::Dot => write_dot(module, output, matches, config),
+        Format::GraphMl => {
+            llhd::graph::write_graphml(output, module)?;
+            Ok(())
+        }
+        Format::EdgeList => {
+            llhd::graph::write_edge_list(output, module)?;
+            Ok(())
+        }
+        Format::Blif => {
+            crate::blif::write(output, module)?;
+            Ok(())
+        }
+        Format::Report | Format::Json => {
+            let constraints = match matches
+                .value_of("constraints")
+                .or(config.output.constraints.as_deref())
+            {
+                Some(path) => Some(read_constraints(path)?),
+                None => None,
+            };
+            write_report(module, output, format == Format::Json, constraints.as_ref())
+        }
+        Format::Sharing => write_sharing(module, output),
+        Format::Fanout => write_fanout(module, output),
         f => bail!("{} outputs not supported", f),
     }
 }
+
+/// Read timing constraints from an SDC file.
+fn read_constraints(path: &str) -> Result<Constraints> {
+    let input = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read constraints from `{}`", path))?;
+    Constraints::from_sdc(&input).map_err(|e| anyhow!("{}: {}", path, e))
+}
+
+/// The cost estimate of a unit in a JSON area report.
+#[derive(Serialize)]
+struct UnitReport<'a> {
+    unit: String,
+    kind: String,
+    #[serde(flatten)]
+    cost: &'a llhd::analysis::UnitCost,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    constraints: Option<&'a Constraints>,
+}
+
+/// Emit a per-unit breakdown of the estimated hardware cost of a module, as
+/// text or JSON, together with the timing constraints of the constrained
+/// entity.
+fn write_report(
+    module: &llhd::ir::Module,
+    output: &mut impl Write,
+    json: bool,
+    constraints: Option<&Constraints>,
+) -> Result<()> {
+    let cost = llhd::analysis::ModuleCost::new(module);
+    let design = match constraints {
+        Some(constraints) => {
+            let design = constraints.design(module).map_err(|e| anyhow!(e))?;
+            constraints.anchors(&design).map_err(|e| anyhow!(e))?;
+            Some(design.id())
+        }
+        None => None,
+    };
+    if json {
+        let reports: Vec<_> = module
+            .units()
+            .map(|unit| UnitReport {
+                unit: unit.name().to_string(),
+                kind: unit.kind().to_string(),
+                cost: &cost[unit.id()],
+                constraints: constraints.filter(|_| design == Some(unit.id())),
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *output, &reports)?;
+        writeln!(output)?;
+        return Ok(());
+    }
+    for unit in module.units() {
+        let c = &cost[unit.id()];
+        let mut lines = vec![("gates".to_string(), c.total_gates().to_string())];
+        for (class, gates) in &c.gates {
+            lines.push((format!("  {}", class), gates.to_string()));
+        }
+        lines.push(("register bits".into(), c.register_bits.to_string()));
+        lines.push((
+            "signals".into(),
+            format!("{} ({} bits)", c.num_signals, c.signal_bits),
+        ));
+        lines.push(("depth".into(), c.depth.to_string()));
+        lines.push(("hierarchy gates".into(), c.hierarchy_gates.to_string()));
+        lines.push((
+            "hierarchy register bits".into(),
+            c.hierarchy_register_bits.to_string(),
+        ));
+        writeln!(output, "{} {}", unit.kind(), unit.name())?;
+        for (label, value) in lines {
+            writeln!(output, "  {:<24} {}", label, value)?;
+        }
+        if let Some(constraints) = constraints.filter(|_| design == Some(unit.id())) {
+            writeln!(output, "  timing constraints")?;
+            for line in constraints.to_string().lines() {
+                writeln!(output, "    {}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Emit the constant trees and units which are replicated across the units of
+/// a module, with the estimated savings of sharing them.
+fn write_sharing(module: &llhd::ir::Module, output: &mut impl Write) -> Result<()> {
+    let sharing = llhd::analysis::ModuleSharing::new(module);
+    for group in &sharing.units {
+        writeln!(output, "identical units")?;
+        writeln!(
+            output,
+            "  {:<24} {}",
+            "units",
+            unit_names(module, group.units.iter().cloned())
+        )?;
+        writeln!(
+            output,
+            "  {:<24} {}",
+            "saved instructions",
+            group.saved_insts()
+        )?;
+        writeln!(output, "  {:<24} {}", "saved gates", group.saved_gates())?;
+    }
+    for tree in &sharing.constants {
+        writeln!(output, "constant {}", tree.tree)?;
+        writeln!(
+            output,
+            "  {:<24} {}",
+            "units",
+            unit_names(module, tree.occurrences.iter().map(|&(id, _)| id))
+        )?;
+        writeln!(
+            output,
+            "  {:<24} {}",
+            "saved instructions",
+            tree.saved_insts()
+        )?;
+        writeln!(output, "  {:<24} {}", "saved gates", tree.saved_gates())?;
+    }
+    Ok(())
+}
+
+/// Emit the values of each unit which drive more than one operand, highest
+/// load first.
+fn write_fanout(module: &llhd::ir::Module, output: &mut impl Write) -> Result<()> {
+    for unit in module.units() {
+        let table = llhd::analysis::FanoutTable::new(&unit);
+        writeln!(output, "{} {}", unit.kind(), unit.name())?;
+        for (value, fanout) in table.above(1) {
+            writeln!(
+                output,
+                "  {:<24} load {}, users {}",
+                value.dump(&unit).to_string(),
+                fanout.load,
+                fanout.users
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// List the names of some units of a module, separated by commas.
+fn unit_names(module: &llhd::ir::Module, units: impl Iterator<Item = llhd::ir::UnitId>) -> String {
+    units
+        .map(|id| module.unit(id).name().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Emit one of the graphs in `llhd::dot`, as selected by the `--graph` option.
+fn write_dot(
+    module: &llhd::ir::Module,
+    output: &mut impl Write,
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<()> {
+    // Options on the command line take precedence over the configuration.
+    let graph = match config.output.graph.as_deref() {
+        Some(graph) if matches.occurrences_of("graph") == 0 => graph,
+        _ => matches.value_of("graph").unwrap(),
+    };
+    if graph == "hierarchy" {
+        llhd::dot::write_hierarchy(output, module)?;
+        return Ok(());
+    }
+    let unit = match matches.value_of("unit").or(config.output.unit.as_deref()) {
+        Some(name) => module
+            .units()
+            .find(|u| u.name().to_string() == name)
+            .ok_or_else(|| anyhow!("Unknown unit `{}`", name))?,
+        None => match module.units().next() {
+            Some(unit) => unit,
+            None => bail!("Module contains no units"),
+        },
+    };
+    if graph == "dfg" {
+        let mut values = vec![];
+        for name in matches.values_of("value").into_iter().flatten() {
+            let value = unit
+                .args()
+                .chain(unit.all_insts().flat_map(|inst| unit.get_inst_result(inst)))
+                .find(|&v| v.dump(&unit).to_string() == name)
+                .ok_or_else(|| anyhow!("Unknown value `{}` in {}", name, unit.name()))?;
+            values.push(value);
+        }
+        if values.is_empty() {
+            bail!("`--value` must be specified for the dfg");
+        }
+        llhd::dot::write_dfg(output, unit, &values)?;
+    } else {
+        llhd::dot::write_cfg(output, unit)?;
+    }
+    Ok(())
+}
@@ -2,25 +2,82 @@
 
 //! Verilog output writer
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use itertools::Itertools;
-use llhd::ir::UnitKind;
+use llhd::{
+    config::OutputOptions,
+    ir::{RegMode, UnitKind},
+};
+use serde::Serialize;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     io::Write,
     iter::repeat,
     rc::Rc,
 };
 
+/// The Verilog identifiers emitted for an LLHD unit.
+#[derive(Debug, Serialize)]
+pub struct ModuleNames {
+    /// The name of the unit, e.g. `@foo`.
+    pub unit: String,
+    /// The name of the Verilog module.
+    pub module: String,
+    /// The identifiers emitted for the values of the unit, mapped to the name
+    /// of the value, e.g. `%x`.
+    pub values: BTreeMap<String, String>,
+}
+
 /// Emit a module as Verilog code.
-pub fn write(output: &mut impl Write, module: &llhd::ir::Module) -> Result<()> {
+///
+/// Registers are emitted as `always` blocks, or as instances of the cells in
+/// the output options if `registers` is set to `structural`. Returns the
+/// identifiers emitted for each unit, which are also listed in a comment above
+/// each module if `name_map` is set to `comment`.
+pub fn write(
+    output: &mut impl Write,
+    module: &llhd::ir::Module,
+    options: &OutputOptions,
+) -> Result<Vec<ModuleNames>> {
     debug!("Emitting Verilog code");
+    match options.registers.as_deref() {
+        None | Some("behavioral") | Some("structural") => (),
+        Some(s) => bail!("Unknown register style `{}`", s),
+    }
     let mut skipped = vec![];
+    let mut names = vec![];
     for unit in module.units() {
         if unit.kind() == UnitKind::Entity {
             if unit.name().is_global() {
-                write_entity(output, unit, &mut Context::default())?;
+                // Simulation-only entities are hidden from synthesis tools.
+                if unit.is_sim_only() {
+                    writeln!(output, "`ifndef SYNTHESIS")?;
+                }
+                // The names are only known once the module has been emitted.
+                let mut ctx = Context {
+                    escape_names: options.escape_names,
+                    ..Default::default()
+                };
+                let mut body = vec![];
+                write_entity(&mut body, unit, &mut ctx, options)?;
+                let module_names = ctx.module_names(unit);
+                if ctx.uses_delays {
+                    writeln!(output, "`timescale 1ps / 1fs")?;
+                }
+                if options.name_map.as_deref() == Some("comment") {
+                    writeln!(output, "// {} = {}", module_names.module, module_names.unit)?;
+                    for (ident, name) in &module_names.values {
+                        writeln!(output, "//   {} = {}", ident, name)?;
+                    }
+                }
+                output.write_all(&body)?;
+                names.push(module_names);
+                if unit.is_sim_only() {
+                    writeln!(output, "`endif")?;
+                }
             }
+        } else if unit.is_sim_only() {
+            info!("Skipping simulation-only unit {}", unit.name());
         } else {
             let name = unit.name();
             error!("Unit {} not supported", name);
@@ -33,7 +90,7 @@ pub fn write(output: &mut impl Write, module: &llhd::ir::Module) -> Result<()> {
             skipped.iter().format(", ")
         );
     }
-    Ok(())
+    Ok(names)
 }
 
 type UnitValue = (llhd::ir::UnitId, llhd::ir::Value);
@@ -42,33 +99,72 @@ type UnitValue = (llhd::ir::UnitId, llhd::ir::Value);
 struct Context {
     name_map: HashMap<UnitValue, Rc<String>>,
     name_set: HashSet<Rc<String>>,
+    /// The number of unnamed values named so far.
+    num_temps: usize,
+    /// Whether to emit invalid names as escaped identifiers.
+    escape_names: bool,
+    /// Whether delays have been emitted, which are given in picoseconds.
+    uses_delays: bool,
 }
 
 impl Context {
     /// Generate a printable name for a value.
+    ///
+    /// Unnamed values are numbered `_t0`, `_t1`, and so on in the order in
+    /// which they are first emitted, such that the numbering only depends on
+    /// the order of the output.
     fn value_name(&mut self, unit: llhd::ir::Unit, value: UnitValue) -> Rc<String> {
         if let Some(name) = self.name_map.get(&value).cloned() {
             return name;
         }
-        let base_name: Rc<String> = Rc::new(match unit.get_name(value.1) {
-            Some(name) => sanitize_name(name).collect(),
-            None => format!("__{}", value.1),
-        });
-        let mut name = base_name.clone();
+        let base_name = match unit.get_name(value.1) {
+            Some(name) => name.to_string(),
+            None => {
+                self.num_temps += 1;
+                format!("_t{}", self.num_temps - 1)
+            }
+        };
+        let name = self.unique_name(base_name);
+        self.name_map.insert(value, name.clone());
+        name
+    }
+
+    /// Generate an identifier which is not in use yet, based on a given name.
+    fn unique_name(&mut self, base_name: String) -> Rc<String> {
+        let mut name = Rc::new(identifier(&base_name, self.escape_names));
         let mut i = 2;
         while self.name_set.contains(&name) {
-            name = Rc::new(format!("{}_{}", base_name, i));
+            let next = format!("{}_{}", base_name, i);
+            name = Rc::new(identifier(&next, self.escape_names));
             i += 1;
         }
-        self.name_map.insert(value, name.clone());
         self.name_set.insert(name.clone());
         name
     }
+
+    /// List the identifiers emitted for the values of a unit.
+    fn module_names(&self, unit: llhd::ir::Unit) -> ModuleNames {
+        ModuleNames {
+            unit: unit.name().to_string(),
+            module: sanitize_unit_name(unit.name(), self.escape_names),
+            values: self
+                .name_map
+                .iter()
+                .filter(|&(&(id, _), _)| id == unit.id())
+                .map(|(&(_, value), name)| (name.to_string(), value.dump(&unit).to_string()))
+                .collect(),
+        }
+    }
 }
 
 /// Emit an LLHD entity as a new Verilog module.
-fn write_entity(output: &mut impl Write, entity: llhd::ir::Unit, ctx: &mut Context) -> Result<()> {
-    let name = sanitize_unit_name(entity.name());
+fn write_entity(
+    output: &mut impl Write,
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    options: &OutputOptions,
+) -> Result<()> {
+    let name = sanitize_unit_name(entity.name(), ctx.escape_names);
     debug!("Creating entity {} as `{}`", entity.name(), name);
 
     // Emit the module header.
@@ -77,13 +173,22 @@ fn write_entity(output: &mut impl Write, entity: llhd::ir::Unit, ctx: &mut Conte
         .map(|v| ctx.value_name(entity, (entity.id(), v)));
     write!(output, "module {} ({});\n", name, ports.format(", "))?;
 
-    // Emit the port declarations.
+    // Emit the port declarations. Outputs with an initial value or driven
+    // within the entity must be declared as `reg` to be assigned in an
+    // `initial` or `always` statement.
+    let inits = initial_values(entity)?;
+    let driven = driven_signals(entity);
     let ports = entity
         .input_args()
         .zip(repeat("input"))
         .chain(entity.output_args().zip(repeat("output")));
     for (v, dir) in ports {
         let n = ctx.value_name(entity, (entity.id(), v));
+        let dir = if inits.iter().any(|&(signal, _)| signal == v) || driven.contains(&v) {
+            "output reg"
+        } else {
+            dir
+        };
         write!(
             output,
             "    {} {} {};\n",
@@ -93,7 +198,7 @@ fn write_entity(output: &mut impl Write, entity: llhd::ir::Unit, ctx: &mut Conte
         )?;
     }
 
-    write_entity_body(output, entity, ctx, Default::default())?;
+    write_entity_body(output, entity, ctx, options, &inits, Default::default())?;
     write!(output, "\nendmodule\n\n")?;
     Ok(())
 }
@@ -102,30 +207,790 @@ fn write_entity(output: &mut impl Write, entity: llhd::ir::Unit, ctx: &mut Conte
 fn write_entity_body(
     output: &mut impl Write,
     entity: llhd::ir::Unit,
-    _ctx: &mut Context,
+    ctx: &mut Context,
+    options: &OutputOptions,
+    inits: &[(llhd::ir::Value, llhd::ir::Value)],
     _bound: HashMap<llhd::ir::Value, llhd::ir::Value>,
 ) -> Result<()> {
     debug!("Emitting entity {}", entity.name());
     write!(output, "\n    // Entity {}\n", entity.name())?;
+
+    // Declare the local signals and assign their initial values.
     for inst in entity.all_insts() {
-        write!(output, "    // {}\n", inst.dump(&entity))?;
+        if entity[inst].opcode() == llhd::ir::Opcode::Sig {
+            let v = entity.inst_result(inst);
+            match entity.signal_resolution(inst) {
+                None | Some(llhd::ir::Resolution::LastWrite) => (),
+                Some(resolution) => bail!(
+                    "Signal {} with {} resolution cannot be emitted",
+                    v.dump(&entity),
+                    resolution
+                ),
+            }
+            let n = ctx.value_name(entity, (entity.id(), v));
+            writeln!(
+                output,
+                "    reg {} {};",
+                flatten_type(&entity.value_type(v))?,
+                n
+            )?;
+        }
+    }
+    for &(signal, init) in inits {
+        let n = ctx.value_name(entity, (entity.id(), signal));
+        let value = match entity.get_const(init) {
+            Some(value) => const_literal(&value)?,
+            None => bail!(
+                "Initial value {} of {} is not a constant",
+                init.dump(&entity),
+                signal.dump(&entity)
+            ),
+        };
+        writeln!(output, "    initial {} = {};", n, value)?;
+    }
+
+    for inst in levelize(entity)? {
+        write_inst(output, entity, ctx, options, inst)?;
     }
     Ok(())
 }
 
+/// Order the instructions of an entity such that values are defined before
+/// they are used.
+///
+/// Each instruction is assigned a level one above the highest level of its
+/// operands, and the instructions are emitted level by level, keeping their
+/// original order within a level. Signals are declared upfront and thus break
+/// the cycles through `prb` and `drv`. Terminators and `sig` instructions are
+/// omitted.
+fn levelize(entity: llhd::ir::Unit) -> Result<Vec<llhd::ir::Inst>> {
+    let mut levels = HashMap::new();
+    let mut insts = vec![];
+    for inst in entity.all_insts() {
+        let opcode = entity[inst].opcode();
+        if opcode.is_terminator() || opcode == llhd::ir::Opcode::Sig {
+            continue;
+        }
+        insts.push((inst_level(entity, inst, &mut levels, &mut vec![])?, inst));
+    }
+    insts.sort_by_key(|&(level, _)| level);
+    Ok(insts.into_iter().map(|(_, inst)| inst).collect())
+}
+
+/// Compute the level of an instruction, see `levelize`.
+fn inst_level(
+    entity: llhd::ir::Unit,
+    inst: llhd::ir::Inst,
+    levels: &mut HashMap<llhd::ir::Inst, usize>,
+    stack: &mut Vec<llhd::ir::Inst>,
+) -> Result<usize> {
+    if let Some(&level) = levels.get(&inst) {
+        return Ok(level);
+    }
+    if stack.contains(&inst) {
+        bail!("Combinational loop through {}", inst.dump(&entity));
+    }
+    stack.push(inst);
+    let mut level = 0;
+    for &arg in entity[inst].args() {
+        if arg.is_invalid() {
+            continue;
+        }
+        let def = match entity.get_value_inst(arg) {
+            Some(def) if entity[def].opcode() != llhd::ir::Opcode::Sig => def,
+            _ => continue,
+        };
+        level = level.max(inst_level(entity, def, levels, stack)? + 1);
+    }
+    stack.pop();
+    levels.insert(inst, level);
+    Ok(level)
+}
+
+/// Emit an instruction as a wire declaration or an assignment.
+///
+/// Instructions without a Verilog counterpart yet are emitted as comments.
+/// Drive delays are not represented in the output.
+fn write_inst(
+    output: &mut impl Write,
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    options: &OutputOptions,
+    inst: llhd::ir::Inst,
+) -> Result<()> {
+    use llhd::ir::Opcode;
+    let data = &entity[inst];
+    let mut arg = |i: usize| ctx.value_name(entity, (entity.id(), data.args()[i]));
+    let signed =
+        |i: usize, arg: &mut dyn FnMut(usize) -> Rc<String>| format!("$signed({})", arg(i));
+
+    let expr = match data.opcode() {
+        Opcode::ConstInt | Opcode::ConstEnum => {
+            const_literal(&entity.get_const(entity.inst_result(inst)).unwrap())?
+        }
+        Opcode::ConstTime => return Ok(()),
+        Opcode::Alias => arg(0).to_string(),
+        Opcode::ArrayUniform => format!("{{{}{{{}}}}}", data.imms()[0], arg(0)),
+        Opcode::Array | Opcode::Struct => format!(
+            "{{{}}}",
+            (0..data.args().len()).rev().map(&mut arg).format(", ")
+        ),
+        Opcode::Not => format!("~{}", arg(0)),
+        Opcode::Neg => format!("-{}", arg(0)),
+        // Counting the zeros checks the bits one after the other, starting
+        // from the top or bottom.
+        Opcode::Popcount | Opcode::Clz | Opcode::Ctz => {
+            let width = sizeof_type(&entity.value_type(data.args()[0]))?;
+            let a = arg(0);
+            let bit = |i: usize| part_select(&a, i, 1, width);
+            match data.opcode() {
+                Opcode::Popcount if width == 0 => "0".to_string(),
+                Opcode::Popcount => (0..width).map(bit).format(" + ").to_string(),
+                _ => {
+                    let mut expr = width.to_string();
+                    for zeros in (0..width).rev() {
+                        let i = match data.opcode() {
+                            Opcode::Clz => width - 1 - zeros,
+                            _ => zeros,
+                        };
+                        expr = format!("{} ? {} : {}", bit(i), zeros, expr);
+                    }
+                    expr
+                }
+            }
+        }
+        Opcode::Add => format!("{} + {}", arg(0), arg(1)),
+        Opcode::Sub => format!("{} - {}", arg(0), arg(1)),
+        // The flag is the upper bit of the result. Signed overflow is the
+        // difference of the upper two bits of the sign-extended sum.
+        Opcode::Uaddo => format!("{{1'b0, {}}} + {{1'b0, {}}}", arg(0), arg(1)),
+        Opcode::Usubo => format!("{{{a} < {b}, {a} - {b}}}", a = arg(0), b = arg(1)),
+        Opcode::Saddo | Opcode::Ssubo => {
+            let width = sizeof_type(&entity.value_type(data.args()[0]))?;
+            let op = if data.opcode() == Opcode::Saddo {
+                "+"
+            } else {
+                "-"
+            };
+            let (a, b) = (arg(0), arg(1));
+            let msb = |name: &str| part_select(name, width - 1, 1, width);
+            format!(
+                "{{^(({{{}, {a}}} {op} {{{}, {b}}}) >> {}), {a} {op} {b}}}",
+                msb(&a),
+                msb(&b),
+                width - 1,
+                a = a,
+                b = b,
+                op = op,
+            )
+        }
+        Opcode::And => format!("{} & {}", arg(0), arg(1)),
+        Opcode::Or => format!("{} | {}", arg(0), arg(1)),
+        Opcode::Xor => format!("{} ^ {}", arg(0), arg(1)),
+        Opcode::Umul => format!("{} * {}", arg(0), arg(1)),
+        // The operands are extended to twice their width, such that the
+        // product is computed at that width.
+        Opcode::Umulh | Opcode::Smulh => {
+            let width = sizeof_type(&entity.value_type(data.args()[0]))?;
+            let (a, b) = (arg(0), arg(1));
+            let fill = |name: &str| match data.opcode() {
+                Opcode::Umulh => "1'b0".to_string(),
+                _ => part_select(name, width - 1, 1, width),
+            };
+            format!(
+                "({{{{{w}{{{}}}}}, {a}}} * {{{{{w}{{{}}}}}, {b}}}) >> {w}",
+                fill(&a),
+                fill(&b),
+                a = a,
+                b = b,
+                w = width,
+            )
+        }
+        Opcode::Udiv => format!("{} / {}", arg(0), arg(1)),
+        Opcode::Umod | Opcode::Urem => format!("{} % {}", arg(0), arg(1)),
+        Opcode::Smul => format!("{} * {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Sdiv => format!("{} / {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Srem => format!("{} % {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Eq => format!("{} == {}", arg(0), arg(1)),
+        Opcode::Neq => format!("{} != {}", arg(0), arg(1)),
+        Opcode::Ult => format!("{} < {}", arg(0), arg(1)),
+        Opcode::Ugt => format!("{} > {}", arg(0), arg(1)),
+        Opcode::Ule => format!("{} <= {}", arg(0), arg(1)),
+        Opcode::Uge => format!("{} >= {}", arg(0), arg(1)),
+        Opcode::Slt => format!("{} < {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Sgt => format!("{} > {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Sle => format!("{} <= {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Sge => format!("{} >= {}", signed(0, &mut arg), signed(1, &mut arg)),
+        Opcode::Umin | Opcode::Umax => {
+            let op = if data.opcode() == Opcode::Umin {
+                "<"
+            } else {
+                ">"
+            };
+            format!("{a} {} {b} ? {a} : {b}", op, a = arg(0), b = arg(1))
+        }
+        Opcode::Smin | Opcode::Smax => {
+            let op = if data.opcode() == Opcode::Smin {
+                "<"
+            } else {
+                ">"
+            };
+            let (a, b) = (arg(0), arg(1));
+            format!("$signed({a}) {} $signed({b}) ? {a} : {b}", op, a = a, b = b)
+        }
+        // The hidden bits are shifted in, and the assignment truncates the
+        // result to the width of the base.
+        Opcode::Shl if !entity.value_type(data.args()[0]).is_signal() => {
+            let hidden = sizeof_type(&entity.value_type(data.args()[1]))?;
+            format!("{{{}, {}}} << {} >> {}", arg(0), arg(1), arg(2), hidden)
+        }
+        Opcode::Shr if !entity.value_type(data.args()[0]).is_signal() => {
+            format!("{{{}, {}}} >> {}", arg(1), arg(0), arg(2))
+        }
+        Opcode::Mux => {
+            let width = sizeof_type(&entity.inst_type(inst))?;
+            format!("{}[{} * {} +: {}]", arg(0), arg(1), width, width)
+        }
+        Opcode::ExtField | Opcode::ExtSlice if !entity.inst_type(inst).is_signal() => {
+            let ty = entity.value_type(data.args()[0]);
+            let (lo, width) = bit_range(&ty, data.opcode(), data.imms())?;
+            part_select(&arg(0), lo, width, sizeof_type(&ty)?)
+        }
+        Opcode::InsField | Opcode::InsSlice => {
+            let ty = entity.value_type(data.args()[0]);
+            let (lo, width) = bit_range(&ty, data.opcode(), data.imms())?;
+            let total = sizeof_type(&ty)?;
+            let target = arg(0);
+            let mut parts = vec![];
+            if lo + width < total {
+                parts.push(part_select(&target, lo + width, total - lo - width, total));
+            }
+            parts.push(arg(1).to_string());
+            if lo > 0 {
+                parts.push(part_select(&target, 0, lo, total));
+            }
+            format!("{{{}}}", parts.join(", "))
+        }
+        Opcode::Prb => signal_ref(entity, ctx, data.args()[0])?,
+        // Projections of signals are folded into their uses.
+        Opcode::ExtField | Opcode::ExtSlice => return Ok(()),
+        Opcode::Drv | Opcode::DrvCond | Opcode::Del | Opcode::DelCond => {
+            return write_drive(output, entity, ctx, inst);
+        }
+        Opcode::Reg if options.registers.as_deref() == Some("structural") => {
+            return write_reg_cell(output, entity, ctx, options, inst);
+        }
+        Opcode::Reg => return write_reg_always(output, entity, ctx, inst),
+        Opcode::Smod | Opcode::Shl | Opcode::Shr => bail!(
+            "Instruction `{}` not supported in Verilog output",
+            inst.dump(&entity)
+        ),
+        _ => {
+            writeln!(output, "    // {}", inst.dump(&entity))?;
+            return Ok(());
+        }
+    };
+    let value = entity.inst_result(inst);
+    let ty = flatten_type(&entity.value_type(value))?;
+    let name = ctx.value_name(entity, (entity.id(), value));
+    // Ask synthesis to map multiply-accumulates onto DSP blocks.
+    let attr = if entity.inst_annotations(inst).is_mac() {
+        "(* use_dsp = \"yes\" *) "
+    } else {
+        ""
+    };
+    if ty.is_empty() {
+        writeln!(output, "    {}wire {} = {};", attr, name, expr)?;
+    } else {
+        writeln!(output, "    {}wire {} {} = {};", attr, ty, name, expr)?;
+    }
+    Ok(())
+}
+
+/// Emit a `drv` or `del` as an assignment in an `always` block.
+///
+/// Transport delays become intra-assignment delays of a nonblocking
+/// assignment, which reproduces every change. Inertial delays become a delayed
+/// wire, whose continuous assignment cancels pending changes. Delays without a
+/// real time part are dropped.
+fn write_drive(
+    output: &mut impl Write,
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    inst: llhd::ir::Inst,
+) -> Result<()> {
+    use llhd::ir::Opcode;
+    let data = &entity[inst];
+    let args = data.args();
+    let target = signal_ref(entity, ctx, args[0])?;
+    let mut value = match data.opcode() {
+        Opcode::Del | Opcode::DelCond => signal_ref(entity, ctx, args[1])?,
+        _ => ctx.value_name(entity, (entity.id(), args[1])).to_string(),
+    };
+    let mut cond = match data.opcode() {
+        Opcode::DrvCond | Opcode::DelCond => {
+            Some(ctx.value_name(entity, (entity.id(), args[3])).to_string())
+        }
+        _ => None,
+    };
+    let delay = match entity.get_const_time(args[2]) {
+        Some(time) => delay_literal(time)?,
+        None => bail!("Delay of `{}` is not a constant", inst.dump(&entity)),
+    };
+
+    let mut assign = "=".to_string();
+    if let Some(delay) = delay {
+        ctx.uses_delays = true;
+        if entity.inst_annotations(inst).is_inertial() {
+            // The value and the condition are delayed together.
+            let base = base_name(entity, ctx, signal_root(entity, args[0]));
+            let ty = flatten_type(&entity.value_type(args[0]))?;
+            let name = ctx.unique_name(format!("{}_delayed", base));
+            writeln!(
+                output,
+                "    {} = {};",
+                delayed_wire(&ty, &delay, &name),
+                value
+            )?;
+            value = name.to_string();
+            if let Some(c) = cond {
+                let name = ctx.unique_name(format!("{}_delayed_cond", base));
+                writeln!(output, "    {} = {};", delayed_wire("", &delay, &name), c)?;
+                cond = Some(name.to_string());
+            }
+        } else {
+            assign = format!("<= #{}", delay);
+        }
+    }
+    match cond {
+        Some(cond) => writeln!(
+            output,
+            "    always @* if ({}) {} {} {};",
+            cond, target, assign, value
+        )?,
+        None => writeln!(output, "    always @* {} {} {};", target, assign, value)?,
+    }
+    Ok(())
+}
+
+/// Declare a wire whose assignment is delayed.
+fn delayed_wire(ty: &str, delay: &str, name: &str) -> String {
+    if ty.is_empty() {
+        format!("wire #{} {}", delay, name)
+    } else {
+        format!("wire {} #{} {}", ty, delay, name)
+    }
+}
+
+/// Emit the real time part of a delay in picoseconds, or `None` if it is zero.
+fn delay_literal(time: &llhd::TimeValue) -> Result<Option<String>> {
+    use num::{BigInt, BigRational, Integer, Zero};
+    if time.time().is_zero() {
+        return Ok(None);
+    }
+    let fs = time.time() * BigRational::from_integer(BigInt::from(1_000_000_000_000_000u64));
+    if !fs.is_integer() {
+        bail!("Delay {} is not a multiple of 1fs", time);
+    }
+    let (ps, frac) = fs.to_integer().div_rem(&BigInt::from(1000));
+    Ok(Some(if frac.is_zero() {
+        ps.to_string()
+    } else {
+        format!("{}.{:03}", ps, frac)
+    }))
+}
+
+/// Emit a register as one `always` block per trigger.
+///
+/// Edge-triggered registers use nonblocking assignments in a block sensitive
+/// to the edges of the trigger and the asynchronous controls. Level-sensitive
+/// registers become latches in a combinational block.
+fn write_reg_always(
+    output: &mut impl Write,
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    inst: llhd::ir::Inst,
+) -> Result<()> {
+    let data = &entity[inst];
+    let target = signal_ref(entity, ctx, data.args()[0])?;
+    let width = sizeof_type(&entity.value_type(data.args()[0]))?;
+    let ctrl = data.reg_controls();
+    let mut name = |v| ctx.value_name(entity, (entity.id(), v));
+    for trigger in data.triggers() {
+        let clock = name(trigger.trigger);
+        let mut events = vec![];
+        let mut conds = vec![];
+        let assign = match trigger.mode {
+            RegMode::Rise => {
+                events.push(format!("posedge {}", clock));
+                "<="
+            }
+            RegMode::Fall => {
+                events.push(format!("negedge {}", clock));
+                "<="
+            }
+            RegMode::Both => {
+                events.push(format!("posedge {}", clock));
+                events.push(format!("negedge {}", clock));
+                "<="
+            }
+            RegMode::High => {
+                conds.push(clock.to_string());
+                "="
+            }
+            RegMode::Low => {
+                conds.push(format!("!{}", clock));
+                "="
+            }
+        };
+        conds.extend(
+            trigger
+                .gate
+                .into_iter()
+                .chain(ctrl.enable)
+                .map(|v| name(v).to_string()),
+        );
+
+        // The asynchronous controls take precedence over the trigger.
+        let mut stmt = String::new();
+        let asyncs = ctrl.clear.map(|(m, v)| (m, v, 0));
+        let asyncs = asyncs
+            .into_iter()
+            .chain(ctrl.preset.map(|(m, v)| (m, v, 1)));
+        for (mode, value, fill) in asyncs {
+            let (edge, level) = active_level(mode, &name(value));
+            if !events.is_empty() {
+                events.push(format!("{} {}", edge, name(value)));
+            }
+            stmt.push_str(&format!(
+                "if ({}) {} {} {{{}{{1'b{}}}}}; else ",
+                level, target, assign, width, fill
+            ));
+        }
+        if !conds.is_empty() {
+            stmt.push_str(&format!("if ({}) ", conds.join(" && ")));
+        }
+        stmt.push_str(&format!("{} {} {};", target, assign, name(trigger.data)));
+
+        if events.is_empty() {
+            writeln!(output, "    always @* {}", stmt)?;
+        } else {
+            writeln!(output, "    always @({}) {}", events.join(" or "), stmt)?;
+        }
+    }
+    Ok(())
+}
+
+/// Emit a register as an instance of one of the cells in the output options.
+///
+/// The cell drives a wire, which is assigned to the register's signal.
+fn write_reg_cell(
+    output: &mut impl Write,
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    options: &OutputOptions,
+    inst: llhd::ir::Inst,
+) -> Result<()> {
+    let data = &entity[inst];
+    let signal = data.args()[0];
+    let trigger = match data.triggers().collect::<Vec<_>>()[..] {
+        [trigger] => trigger,
+        _ => bail!(
+            "Register `{}` with multiple triggers not supported in structural output",
+            inst.dump(&entity)
+        ),
+    };
+    let cell = options
+        .cells
+        .iter()
+        .find(|cell| cell.mode == trigger.mode.to_string())
+        .ok_or_else(|| anyhow!("No cell given for `{}` registers", trigger.mode))?;
+    let target = signal_ref(entity, ctx, signal)?;
+    let base = base_name(entity, ctx, signal_root(entity, signal));
+    let inst_name = ctx.unique_name(format!("{}_reg", base));
+    let wire = ctx.unique_name(format!("{}_q", inst_name));
+    let ctrl = data.reg_controls();
+    let mut name = |v| ctx.value_name(entity, (entity.id(), v));
+
+    let mut ports = vec![
+        (cell.clock.as_str(), name(trigger.trigger).to_string()),
+        (cell.output.as_str(), wire.to_string()),
+    ];
+    let conds: Vec<_> = trigger
+        .gate
+        .into_iter()
+        .chain(ctrl.enable)
+        .map(|v| name(v).to_string())
+        .collect();
+    let value = name(trigger.data).to_string();
+    // Without an enable port, the register holds its value by feeding the
+    // output back into the data input.
+    let value = match (cell.enable.as_deref(), conds.is_empty()) {
+        (Some(port), true) => {
+            ports.push((port, "1'b1".to_string()));
+            value
+        }
+        (Some(port), false) => {
+            ports.push((port, conds.join(" && ")));
+            value
+        }
+        (None, true) => value,
+        (None, false) => format!("{} ? {} : {}", conds.join(" && "), value, wire),
+    };
+    ports.push((cell.data.as_str(), value));
+    let asyncs = vec![
+        ("clear", cell.clear.as_deref(), ctrl.clear),
+        ("preset", cell.preset.as_deref(), ctrl.preset),
+    ];
+    for (what, port, control) in asyncs {
+        match (port, control) {
+            (Some(port), Some((mode, value))) => {
+                ports.push((port, active_level(mode, &name(value)).1))
+            }
+            (Some(port), None) => ports.push((port, "1'b0".to_string())),
+            (None, Some(_)) => bail!(
+                "Cell `{}` has no {} port for register `{}`",
+                cell.name,
+                what,
+                inst.dump(&entity)
+            ),
+            (None, None) => (),
+        }
+    }
+
+    let ty = flatten_type(&entity.value_type(signal))?;
+    if ty.is_empty() {
+        writeln!(output, "    wire {};", wire)?;
+    } else {
+        writeln!(output, "    wire {} {};", ty, wire)?;
+    }
+    writeln!(
+        output,
+        "    {} {} ({});",
+        cell.name,
+        inst_name,
+        ports
+            .iter()
+            .map(|(port, value)| format!(".{}({})", port, value))
+            .format(", ")
+    )?;
+    writeln!(output, "    always @* {} = {};", target, wire)?;
+    Ok(())
+}
+
+/// Determine the edge on which an asynchronous control becomes active, and
+/// the condition under which it is active.
+fn active_level(mode: RegMode, name: &str) -> (&'static str, String) {
+    match mode {
+        RegMode::Low | RegMode::Fall => ("negedge", format!("!{}", name)),
+        _ => ("posedge", name.to_string()),
+    }
+}
+
+/// Determine the signals driven within an entity.
+///
+/// Drives to projections of a signal count towards the projected signal.
+fn driven_signals(entity: llhd::ir::Unit) -> HashSet<llhd::ir::Value> {
+    entity
+        .all_insts()
+        .filter(|&inst| {
+            matches!(
+                entity[inst].opcode(),
+                llhd::ir::Opcode::Drv
+                    | llhd::ir::Opcode::DrvCond
+                    | llhd::ir::Opcode::Del
+                    | llhd::ir::Opcode::DelCond
+                    | llhd::ir::Opcode::Reg
+            )
+        })
+        .map(|inst| signal_root(entity, entity[inst].args()[0]))
+        .collect()
+}
+
+/// Determine the name a value's identifier is based on, for deriving the names
+/// of helper wires and instances from it.
+fn base_name(entity: llhd::ir::Unit, ctx: &mut Context, value: llhd::ir::Value) -> String {
+    match entity.get_name(value) {
+        Some(name) => name.to_string(),
+        None => ctx.value_name(entity, (entity.id(), value)).to_string(),
+    }
+}
+
+/// Find the signal a projection of a signal is extracted from.
+fn signal_root(entity: llhd::ir::Unit, mut signal: llhd::ir::Value) -> llhd::ir::Value {
+    while let Some(inst) = entity.get_value_inst(signal) {
+        match entity[inst].opcode() {
+            llhd::ir::Opcode::ExtField | llhd::ir::Opcode::ExtSlice => {
+                signal = entity[inst].args()[0]
+            }
+            _ => break,
+        }
+    }
+    signal
+}
+
+/// Refer to a signal, or to the bits of a projection of a signal.
+fn signal_ref(
+    entity: llhd::ir::Unit,
+    ctx: &mut Context,
+    signal: llhd::ir::Value,
+) -> Result<String> {
+    let mut lo = 0;
+    let mut width = sizeof_type(&entity.value_type(signal))?;
+    let mut root = signal;
+    while let Some(inst) = entity.get_value_inst(root) {
+        let data = &entity[inst];
+        match data.opcode() {
+            llhd::ir::Opcode::ExtField | llhd::ir::Opcode::ExtSlice => {
+                root = data.args()[0];
+                let ty = entity.value_type(root);
+                lo += bit_range(ty.unwrap_signal(), data.opcode(), data.imms())?.0;
+            }
+            llhd::ir::Opcode::Sig => break,
+            _ => bail!("Signal {} not supported", signal.dump(&entity)),
+        }
+    }
+    let total = sizeof_type(&entity.value_type(root))?;
+    width = width.min(total);
+    let name = ctx.value_name(entity, (entity.id(), root));
+    Ok(part_select(&name, lo, width, total))
+}
+
+/// Compute the bits accessed by a field or slice instruction, as offset and
+/// width.
+fn bit_range(ty: &llhd::Type, opcode: llhd::ir::Opcode, imms: &[usize]) -> Result<(usize, usize)> {
+    Ok(match (opcode, ty.as_ref()) {
+        (llhd::ir::Opcode::ExtField, llhd::StructType(fields))
+        | (llhd::ir::Opcode::InsField, llhd::StructType(fields))
+        | (llhd::ir::Opcode::ExtField, llhd::NamedStructType(_, fields))
+        | (llhd::ir::Opcode::InsField, llhd::NamedStructType(_, fields)) => {
+            let lo = fields[..imms[0]]
+                .iter()
+                .map(sizeof_type)
+                .sum::<Result<usize>>()?;
+            (lo, sizeof_type(&fields[imms[0]])?)
+        }
+        (_, llhd::ArrayType(_, elem)) => {
+            let width = sizeof_type(elem)?;
+            match opcode {
+                llhd::ir::Opcode::ExtField | llhd::ir::Opcode::InsField => (imms[0] * width, width),
+                _ => (imms[0] * width, imms[1] * width),
+            }
+        }
+        (_, llhd::IntType(_)) => (imms[0], imms[1]),
+        _ => bail!("Access to `{}` not supported", ty),
+    })
+}
+
+/// Select a range of bits of a value, if it is not the entire value.
+fn part_select(name: &str, lo: usize, width: usize, total: usize) -> String {
+    if lo == 0 && width == total {
+        name.to_string()
+    } else if width == 1 {
+        format!("{}[{}]", name, lo)
+    } else {
+        format!("{}[{}:{}]", name, lo + width - 1, lo)
+    }
+}
+
+/// Determine the initial values of the signals in an entity.
+///
+/// A local signal starts out with the initial value of its `sig` instruction.
+/// The `init` control of a register takes precedence over the initial value
+/// of the signal it stores into, and also applies to output ports.
+fn initial_values(entity: llhd::ir::Unit) -> Result<Vec<(llhd::ir::Value, llhd::ir::Value)>> {
+    let mut reg_inits = HashMap::new();
+    let mut inits = vec![];
+    for inst in entity.all_insts() {
+        if entity[inst].opcode() != llhd::ir::Opcode::Reg {
+            continue;
+        }
+        let init = match entity[inst].reg_controls().init {
+            Some(init) => init,
+            None => continue,
+        };
+        let signal = entity[inst].args()[0];
+        if entity.get_value_arg(signal).is_some() {
+            inits.push((signal, init));
+        } else if entity
+            .get_value_inst(signal)
+            .map(|inst| entity[inst].opcode() != llhd::ir::Opcode::Sig)
+            .unwrap_or(true)
+        {
+            bail!(
+                "Initial value of register on {} not supported",
+                signal.dump(&entity)
+            );
+        }
+        reg_inits.insert(signal, init);
+    }
+    for inst in entity.all_insts() {
+        if entity[inst].opcode() == llhd::ir::Opcode::Sig {
+            let signal = entity.inst_result(inst);
+            let init = reg_inits
+                .get(&signal)
+                .cloned()
+                .unwrap_or(entity[inst].args()[0]);
+            inits.push((signal, init));
+        }
+    }
+    Ok(inits)
+}
+
+/// Emit a constant as a Verilog literal.
+///
+/// Aggregates are flattened into a concatenation, with the first element in
+/// the least significant bits.
+fn const_literal(value: &llhd::Value) -> Result<String> {
+    Ok(match value {
+        llhd::Value::Int(v) => format!("{}'d{}", v.width, v.value),
+        llhd::Value::Enum(v) => format!("{}'d{}", sizeof_type(&v.ty())?, v.value),
+        llhd::Value::Array(llhd::ArrayValue(elems))
+        | llhd::Value::Struct(llhd::StructValue(elems)) => format!(
+            "{{{}}}",
+            elems
+                .iter()
+                .rev()
+                .map(const_literal)
+                .collect::<Result<Vec<_>>>()?
+                .join(", ")
+        ),
+        _ => bail!("Constant `{}` not supported", value),
+    })
+}
+
 /// Make a unit name printable in Verilog.
-fn sanitize_unit_name(name: &llhd::ir::UnitName) -> String {
+fn sanitize_unit_name(name: &llhd::ir::UnitName, escape: bool) -> String {
     let mut out = String::new();
     if !name.is_global() {
         out.push('_');
     }
     match name {
-        llhd::ir::UnitName::Global(s) | llhd::ir::UnitName::Local(s) => {
-            out.extend(sanitize_name(s))
-        }
+        llhd::ir::UnitName::Global(s) | llhd::ir::UnitName::Local(s) => out.push_str(s),
         llhd::ir::UnitName::Anonymous(i) => out.push_str(&i.to_string()),
     }
-    out
+    identifier(&out, escape)
+}
+
+/// Make a name printable in Verilog, either by replacing invalid characters
+/// or as an escaped identifier.
+fn identifier(name: &str, escape: bool) -> String {
+    let valid = name
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if !escape {
+        sanitize_name(name).collect()
+    } else if valid {
+        name.to_string()
+    } else {
+        // Escaped identifiers are terminated by whitespace.
+        format!("\\{} ", name)
+    }
 }
 
 /// Make a name printable in Verilog.
@@ -154,7 +1019,9 @@ fn sizeof_type(ty: &llhd::Type) -> Result<usize> {
         }
         llhd::SignalType(ty) => Ok(sizeof_type(ty)?),
         llhd::ArrayType(w, ty) => Ok(w * sizeof_type(ty)?),
-        llhd::StructType(tys) => tys.iter().map(|ty| sizeof_type(ty)).sum(),
+        llhd::StructType(tys) | llhd::NamedStructType(_, tys) => {
+            tys.iter().map(|ty| sizeof_type(ty)).sum()
+        }
         _ => bail!("Type `{}` not supported", ty),
     }
 }
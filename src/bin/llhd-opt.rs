@@ -6,7 +6,10 @@ extern crate clap;
 extern crate log;
 
 use clap::Arg;
-use llhd::{assembly::parse_module, opt::prelude::*, verifier::Verifier};
+use llhd::{
+    assembly::parse_module_unchecked, assembly::WriteOptions, config::Config, opt::prelude::*,
+    opt::Profile, pass::ExtensionPolicy, sim::Differential, verifier::Verifier,
+};
 use std::{
     fs::File,
     io::{BufWriter, Read},
@@ -46,6 +49,11 @@ fn main_inner() -> Result<(), String> {
                 .takes_value(true)
                 .help("File to write output to; stdout if omitted"),
         )
+        .arg(
+            Arg::with_name("annotate-regions")
+                .long("annotate-regions")
+                .help("Annotate the blocks in the output with their temporal region"),
+        )
         .arg(
             Arg::with_name("time-passes")
                 .short("t")
@@ -69,6 +77,132 @@ fn main_inner() -> Result<(), String> {
                 .long_help(HELP_PASSES)
                 .conflicts_with("lower"),
         )
+        .arg(
+            Arg::with_name("probe")
+                .long("probe")
+                .value_name("FILTER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Select instructions for the dbgprobe pass by value name (%x) or opcode (add)",
+                ),
+        )
+        .arg(
+            Arg::with_name("flatten-max-insts")
+                .long("flatten-max-insts")
+                .value_name("N")
+                .takes_value(true)
+                .help("Only flatten entities or inline functions with at most N instructions"),
+        )
+        .arg(
+            Arg::with_name("flatten-max-fanout")
+                .long("flatten-max-fanout")
+                .value_name("N")
+                .takes_value(true)
+                .help("Only flatten entities or inline functions used at most N times"),
+        )
+        .arg(
+            Arg::with_name("flatten-max-size")
+                .long("flatten-max-size")
+                .value_name("N")
+                .takes_value(true)
+                .help("Do not grow units beyond N instructions by flattening or inlining"),
+        )
+        .arg(
+            Arg::with_name("partition-max-insts")
+                .long("partition-max-insts")
+                .value_name("N")
+                .takes_value(true)
+                .help("Partition entities with more than N instructions into entities of at most N"),
+        )
+        .arg(
+            Arg::with_name("fanout-max-load")
+                .long("fanout-max-load")
+                .value_name("N")
+                .takes_value(true)
+                .help("Duplicate instructions whose result drives more than N operands"),
+        )
+        .arg(
+            Arg::with_name("fanout-max-gates")
+                .long("fanout-max-gates")
+                .value_name("N")
+                .takes_value(true)
+                .help("Only duplicate instructions estimated at most N gates"),
+        )
+        .arg(
+            Arg::with_name("word-width")
+                .long("word-width")
+                .value_name("N")
+                .takes_value(true)
+                .help("Lower integer operations wider than N bits to N-bit words in wordlower"),
+        )
+        .arg(
+            Arg::with_name("gcse-max-insts")
+                .long("gcse-max-insts")
+                .value_name("N")
+                .takes_value(true)
+                .help("Skip units with more than N instructions in gcse"),
+        )
+        .arg(
+            Arg::with_name("tcm-max-insts")
+                .long("tcm-max-insts")
+                .value_name("N")
+                .takes_value(true)
+                .help("Skip units with more than N instructions in tcm"),
+        )
+        .arg(
+            Arg::with_name("tcm-max-dom-steps")
+                .long("tcm-max-dom-steps")
+                .value_name("N")
+                .takes_value(true)
+                .help("Stop moving drives in a unit after N dominator tree steps in tcm"),
+        )
+        .arg(
+            Arg::with_name("extend")
+                .long("extend")
+                .value_name("POLICY")
+                .takes_value(true)
+                .possible_values(&["report", "zero", "sign", "opcode"])
+                .help("How the widthlegal pass extends operands of mismatching width"),
+        )
+        .arg(
+            Arg::with_name("block-order")
+                .long("block-order")
+                .value_name("ORDER")
+                .takes_value(true)
+                .possible_values(&["rpo", "regions"])
+                .help("How the blockorder pass arranges blocks, optionally grouped by temporal region"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Read execution counts from a simulation to guide inlining and sinking"),
+        )
+        .arg(
+            Arg::with_name("speculate")
+                .long("speculate")
+                .value_name("PASS")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Apply a pass (or all) to a copy of the module, and roll back results which fail to verify"),
+        )
+        .arg(
+            Arg::with_name("speculate-sim")
+                .long("speculate-sim")
+                .help("Also roll back speculatively applied passes which change the simulated behaviour"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Read passes and options from a TOML or JSON file"),
+        )
         .arg(
             Arg::with_name("lower")
                 .short("l")
@@ -99,7 +233,76 @@ fn main_inner() -> Result<(), String> {
     let mut times = vec![];
     let tinit = time::precise_time_ns();
 
-    // Read the input.
+    // Read the configuration file, and apply the options given on the command
+    // line on top of it.
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let mut ctx = PassContext::default();
+    config.apply(&mut ctx)?;
+    if let Some(extend) = matches.value_of("extend") {
+        ctx.extension_policy = extend.parse()?;
+    }
+    if let Some(order) = matches.value_of("block-order") {
+        ctx.block_order = order.parse()?;
+    }
+    if let Some(path) = matches.value_of("profile") {
+        ctx.profile = Profile::load(path)?;
+    }
+    for filter in matches.values_of("probe").into_iter().flatten() {
+        ctx.probe_filter.add(filter);
+    }
+    let mut speculation = config.speculation();
+    for pass in matches.values_of("speculate").into_iter().flatten() {
+        speculation.passes.push(pass.to_string());
+    }
+    if matches.is_present("speculate-sim") {
+        speculation.differential = Some(Differential::new(&[]));
+    }
+    if matches.is_present("flatten-max-insts") {
+        ctx.flatten_limits.max_unit_insts =
+            value_t!(matches, "flatten-max-insts", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("flatten-max-fanout") {
+        ctx.flatten_limits.max_fanout =
+            value_t!(matches, "flatten-max-fanout", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("flatten-max-size") {
+        ctx.flatten_limits.max_parent_insts =
+            value_t!(matches, "flatten-max-size", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("partition-max-insts") {
+        ctx.partition_limits.max_unit_insts =
+            value_t!(matches, "partition-max-insts", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("fanout-max-load") {
+        ctx.fanout_limits.max_load =
+            value_t!(matches, "fanout-max-load", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("fanout-max-gates") {
+        ctx.fanout_limits.max_gates =
+            value_t!(matches, "fanout-max-gates", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("word-width") {
+        ctx.word_width =
+            Some(value_t!(matches, "word-width", usize).map_err(|e| format!("{}", e))?);
+    }
+    if matches.is_present("gcse-max-insts") {
+        ctx.pass_limits.gcse_max_insts =
+            value_t!(matches, "gcse-max-insts", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("tcm-max-insts") {
+        ctx.pass_limits.tcm_max_insts =
+            value_t!(matches, "tcm-max-insts", usize).map_err(|e| format!("{}", e))?;
+    }
+    if matches.is_present("tcm-max-dom-steps") {
+        ctx.pass_limits.tcm_max_dom_steps =
+            value_t!(matches, "tcm-max-dom-steps", usize).map_err(|e| format!("{}", e))?;
+    }
+
+    // Read the input. If an extension policy is given, legalize the operand
+    // widths before verifying the module.
     let t0 = time::precise_time_ns();
     let mut module = {
         let path = matches.value_of("input").unwrap();
@@ -108,7 +311,11 @@ fn main_inner() -> Result<(), String> {
         input
             .read_to_string(&mut contents)
             .map_err(|e| format!("{}", e))?;
-        let module = parse_module(&contents).map_err(|e| format!("{}", e))?;
+        let mut module = parse_module_unchecked(&contents).map_err(|e| format!("{}", e))?;
+        module.link();
+        if ctx.extension_policy != ExtensionPolicy::Report {
+            config.run_pass("widthlegal", &mut ctx, &mut module);
+        }
         let mut verifier = Verifier::new();
         verifier.verify_module(&module);
         verifier.finish().map_err(|errs| format!("{}", errs))?;
@@ -121,10 +328,13 @@ fn main_inner() -> Result<(), String> {
     let passes: Vec<_> = if let Some(passes) = matches.values_of("passes") {
         passes.collect()
     } else {
-        let mut v = vec![
-            "cf", "vtpp", "dce", "gcse", "ecm", "tcm", "ecm", "tcm", "gcse", "tcm", "cf", "ecm",
-            "gcse", "insim", "dce", "cfs", "insim", "dce",
-        ];
+        let mut v = match &config.passes {
+            Some(passes) => passes.iter().map(String::as_str).collect(),
+            None => vec![
+                "cf", "vtpp", "dce", "gcse", "ecm", "tcm", "ecm", "tcm", "gcse", "tcm", "cf",
+                "ecm", "gcse", "insim", "dce", "cfs", "insim", "dce",
+            ],
+        };
         if matches.is_present("lower") {
             v.extend(["proclower", "deseq"].iter().copied());
         }
@@ -133,21 +343,10 @@ fn main_inner() -> Result<(), String> {
 
     // Apply optimization passes.
     debug!("Running {:?}", passes);
-    let ctx = PassContext;
     for &pass in &passes {
         trace!("Running pass {}", pass);
         let t0 = time::precise_time_ns();
         let _changes = match pass {
-            "cf" => llhd::pass::ConstFolding::run_on_module(&ctx, &mut module),
-            "cfs" => llhd::pass::ControlFlowSimplification::run_on_module(&ctx, &mut module),
-            "dce" => llhd::pass::DeadCodeElim::run_on_module(&ctx, &mut module),
-            "deseq" => llhd::pass::Desequentialization::run_on_module(&ctx, &mut module),
-            "ecm" => llhd::pass::EarlyCodeMotion::run_on_module(&ctx, &mut module),
-            "gcse" => llhd::pass::GlobalCommonSubexprElim::run_on_module(&ctx, &mut module),
-            "insim" => llhd::pass::InstSimplification::run_on_module(&ctx, &mut module),
-            "proclower" => llhd::pass::ProcessLowering::run_on_module(&ctx, &mut module),
-            "tcm" => llhd::pass::TemporalCodeMotion::run_on_module(&ctx, &mut module),
-            "vtpp" => llhd::pass::VarToPhiPromotion::run_on_module(&ctx, &mut module),
             "verify" => {
                 let mut verifier = Verifier::new();
                 verifier.verify_module(&module);
@@ -157,15 +356,42 @@ fn main_inner() -> Result<(), String> {
                 }
                 false // no changes
             }
-            _ => {
-                error!("Unknown pass `{}`", pass);
-                continue;
-            }
+            _ => match config.run_pass_speculatively(pass, &mut ctx, &mut module, &mut speculation)
+            {
+                Some(changes) => changes,
+                None => {
+                    error!("Unknown pass `{}`", pass);
+                    continue;
+                }
+            },
         };
         let t1 = time::precise_time_ns();
         times.push((pass.to_owned(), t1 - t0));
     }
 
+    // Report the units which passes skipped due to their limits.
+    let skipped = ctx.skipped_units();
+    if !skipped.is_empty() {
+        warn!("Units skipped due to pass limits:");
+        for s in &skipped {
+            warn!("  {} in {}: {}", s.unit, s.pass, s.reason);
+        }
+    }
+
+    // Report the pass results which were rolled back, with the modules which
+    // reproduce the failures.
+    let rollbacks = speculation.rollbacks();
+    if !rollbacks.is_empty() {
+        warn!("Pass results rolled back:");
+        for r in rollbacks {
+            match &r.unit {
+                Some(unit) => warn!("  {} in {}: {}", unit, r.pass, r.reason),
+                None => warn!("  {}: {}", r.pass, r.reason),
+            }
+            warn!("  Reproduced by:\n{}", r.test_case);
+        }
+    }
+
     // Verify modified module.
     let t0 = time::precise_time_ns();
     let mut failed = false;
@@ -201,12 +427,15 @@ fn main_inner() -> Result<(), String> {
 
     // Write the output.
     let t0 = time::precise_time_ns();
+    let options = WriteOptions {
+        regions: config.output.annotate_regions || matches.is_present("annotate-regions"),
+    };
     if let Some(path) = matches.value_of("output") {
         let output = File::create(path).map_err(|e| format!("{}", e))?;
         let output = BufWriter::with_capacity(1 << 20, output);
-        llhd::assembly::write_module(output, &module);
+        llhd::assembly::write_module_with(output, &module, options);
     } else {
-        llhd::assembly::write_module(std::io::stdout().lock(), &module);
+        llhd::assembly::write_module_with(std::io::stdout().lock(), &module, options);
     }
     let t1 = time::precise_time_ns();
     times.push(("output".to_owned(), t1 - t0));
@@ -255,15 +484,36 @@ static HELP_PASSES: &str = "Exact order of passes to run
 This option specifies the exact order of passes to be executed. The admissible \
 passes are as follows:
 
+blockorder  Block Ordering (see --block-order)
+bundlemerge Bundle Merging (struct of signals to signal of struct)
+bundlesplit Bundle Splitting (signal of struct to struct of signals)
 cf          Constant folding
 cfs         Control Flow Simplification
+cpool       Constant Pooling
+dbgprobe    Debug Probe Insertion (see --probe)
 dce         Dead Code Elimination
+declelim    Declaration Elimination
 deseq       Desequentialization
+drvpeep     Drive Peephole Optimization (after tcm)
 ecm         Early Code Motion
-gcse        Global Common Subexpression Elimination
+entlower    Entity Lowering
+fanoutdup   Fan-out Duplication (see --fanout-*)
+flatten     Hierarchy Flattening (see --flatten-*)
+gcse        Global Common Subexpression Elimination (see --gcse-max-insts)
+hscheck     Handshake Checking (warn about ready/valid protocol violations)
+ifcvt       If Conversion
+inline      Function Inlining (see --flatten-*)
 insim       Instruction Simplification
+macfuse     Multiply-Accumulate Fusion
+partition   Entity Partitioning (see --partition-max-insts)
 proclower   Process Lowering
-tcm         Temporal Code Motion
+reassoc     Reassociation (before gcse)
+replicate   Instance Replication (identical instances to replicated ones)
+sink        Code Sinking
+tcm         Temporal Code Motion (see --tcm-max-insts)
 vtpp        Var-to-Phi Promotion
+waitrefine  Wait Refinement
+widthlegal  Width Legalization (see --extend)
+wordlower   Word Lowering (see --word-width)
 verify      Verify the IR
 ";
@@ -0,0 +1,171 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{AppSettings, Arg};
+use llhd::{
+    assembly::{parse_module_unchecked, write_module_string},
+    reduce::Reducer,
+};
+use regex::Regex;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn main() {
+    match main_inner() {
+        Ok(()) => (),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_inner() -> Result<()> {
+    let matches = app_from_crate!()
+        .about("Reduces LLHD assembly to a small module for which a command still fails.")
+        .setting(AppSettings::TrailingVarArg)
+        .arg(
+            Arg::with_name("verbosity")
+                .short("v")
+                .multiple(true)
+                .help("Increase message verbosity"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .help("LLHD file to reduce")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("File to write the reduced module to; stdout if omitted"),
+        )
+        .arg(
+            Arg::with_name("success")
+                .long("success")
+                .help("Keep modules for which the command succeeds, rather than fails"),
+        )
+        .arg(
+            Arg::with_name("stderr")
+                .long("stderr")
+                .takes_value(true)
+                .value_name("REGEX")
+                .help("Only keep modules for which the command's stderr matches a regex"),
+        )
+        .arg(
+            Arg::with_name("command")
+                .multiple(true)
+                .required(true)
+                .help("Command to test candidates with; `{}` is replaced by the candidate file, which is appended if absent"),
+        )
+        .get_matches();
+
+    // Configure the logger.
+    let verbose = matches.occurrences_of("verbosity") as usize + 1;
+    stderrlog::new()
+        .module("llhd")
+        .module("llhd_reduce")
+        .verbosity(verbose)
+        .init()
+        .unwrap();
+
+    // Read the input.
+    let path = matches.value_of("input").unwrap();
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path))?;
+    let mut module = parse_module_unchecked(&contents).map_err(|e| anyhow!("{}", e))?;
+    module.link();
+
+    // Assemble the predicate.
+    let predicate = Predicate {
+        command: matches
+            .values_of("command")
+            .unwrap()
+            .map(String::from)
+            .collect(),
+        success: matches.is_present("success"),
+        stderr: match matches.value_of("stderr") {
+            Some(re) => Some(Regex::new(re).with_context(|| format!("Invalid regex `{}`", re))?),
+            None => None,
+        },
+        candidate: std::env::temp_dir().join(format!("llhd-reduce-{}.llhd", std::process::id())),
+    };
+    if !predicate.test(&contents)? {
+        return Err(anyhow!("Input is not interesting to begin with"));
+    }
+
+    // Reduce the module. Errors running the command count as uninteresting.
+    let mut reducer = Reducer::new(|module: &_| {
+        predicate
+            .test(&write_module_string(module))
+            .unwrap_or_else(|e| {
+                warn!("{:#}", e);
+                false
+            })
+    });
+    let reduced = reducer.reduce(module);
+    let _ = std::fs::remove_file(&predicate.candidate);
+    info!("Tested {} candidates", reducer.tests());
+
+    // Write the output.
+    let output = write_module_string(&reduced);
+    match matches.value_of("output") {
+        Some(path) => {
+            std::fs::write(path, output).with_context(|| format!("Cannot write {}", path))?
+        }
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+/// An external command which decides whether a candidate is interesting.
+struct Predicate {
+    command: Vec<String>,
+    success: bool,
+    stderr: Option<Regex>,
+    candidate: PathBuf,
+}
+
+impl Predicate {
+    /// Run the command on a candidate module in assembly form.
+    fn test(&self, module: &str) -> Result<bool> {
+        std::fs::write(&self.candidate, module)
+            .with_context(|| format!("Cannot write {}", self.candidate.display()))?;
+        let output = self
+            .build_command(&self.candidate)
+            .output()
+            .with_context(|| format!("Cannot run `{}`", self.command[0]))?;
+        if output.status.success() != self.success {
+            return Ok(false);
+        }
+        Ok(match &self.stderr {
+            Some(re) => re.is_match(&String::from_utf8_lossy(&output.stderr)),
+            None => true,
+        })
+    }
+
+    /// Build the command to run on a candidate file.
+    fn build_command(&self, path: &Path) -> Command {
+        let path = path.to_str().unwrap();
+        let mut args: Vec<String> = self
+            .command
+            .iter()
+            .map(|arg| arg.replace("{}", path))
+            .collect();
+        if !self.command.iter().any(|arg| arg.contains("{}")) {
+            args.push(path.to_string());
+        }
+        let mut cmd = Command::new(&args[0]);
+        cmd.args(&args[1..]);
+        cmd
+    }
+}
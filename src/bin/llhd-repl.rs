@@ -0,0 +1,246 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use llhd::{
+    assembly::{parse_module_unchecked, write_module_string, write_unit_string},
+    config::UnitPattern,
+    ir::{prelude::*, LinkedUnit},
+    opt::prelude::*,
+    pass::run_by_name,
+    verifier::Verifier,
+};
+use std::{
+    fs::File,
+    io::{BufRead, IsTerminal, Read, Write},
+    result::Result,
+};
+
+fn main() {
+    match main_inner() {
+        Ok(_) => (),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_inner() -> Result<(), String> {
+    let matches = app_from_crate!()
+        .about("Explores and transforms LLHD assembly interactively.")
+        .arg(
+            Arg::with_name("input")
+                .help("LLHD file to load")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("exec")
+                .short("e")
+                .long("exec")
+                .value_name("COMMAND")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Execute a command instead of reading commands from stdin"),
+        )
+        .after_help(HELP_COMMANDS)
+        .get_matches();
+
+    // Read the input.
+    let module = {
+        let path = matches.value_of("input").unwrap();
+        let mut input = File::open(path).map_err(|e| format!("{}", e))?;
+        let mut contents = String::new();
+        input
+            .read_to_string(&mut contents)
+            .map_err(|e| format!("{}", e))?;
+        let mut module = parse_module_unchecked(&contents)?;
+        module.link();
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&module);
+        verifier.finish().map_err(|errs| format!("{}", errs))?;
+        module
+    };
+    let mut repl = Repl {
+        module,
+        history: vec![],
+    };
+
+    // Execute the commands given on the command line, stopping at the first
+    // error.
+    if let Some(commands) = matches.values_of("exec") {
+        for command in commands {
+            if !repl.execute(command)? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    // Otherwise read commands from stdin until it is closed.
+    let stdin = std::io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("llhd> ");
+            std::io::stdout().flush().map_err(|e| format!("{}", e))?;
+        }
+        line.clear();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| format!("{}", e))?
+            == 0
+        {
+            break;
+        }
+        match repl.execute(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// The state of an interactive session.
+struct Repl {
+    /// The module being explored.
+    module: Module,
+    /// Snapshots of the module before each modification, most recent last.
+    history: Vec<Module>,
+}
+
+impl Repl {
+    /// Execute a command.
+    ///
+    /// Returns `false` if the session should end.
+    fn execute(&mut self, line: &str) -> Result<bool, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => (),
+            ["help"] => print!("{}", HELP_COMMANDS),
+            ["quit"] | ["exit"] => return Ok(false),
+            ["units"] => {
+                for unit in self.module.units() {
+                    println!("{} {} {}", unit.kind(), unit.name(), unit.sig().dump(&unit));
+                }
+                for decl in self.module.decls() {
+                    let data = &self.module[decl];
+                    println!(
+                        "declare {} {}",
+                        data.name,
+                        data.sig.dump_named(&data.arg_names)
+                    );
+                }
+            }
+            ["dump"] => print!("{}", write_module_string(&self.module)),
+            ["dump", unit] => {
+                let unit = self.find_unit(unit)?;
+                print!("{}", write_unit_string(self.module.unit(unit)));
+            }
+            ["pass", name] => self.run_pass(name, None)?,
+            ["pass", name, unit] => {
+                let unit = self.find_unit(unit)?;
+                self.run_pass(name, Some(unit))?;
+            }
+            ["uses", unit, value] => {
+                let unit = self.find_unit(unit)?;
+                self.show_uses(unit, value)?;
+            }
+            ["undo"] => {
+                self.module = self.history.pop().ok_or("nothing to undo")?;
+                println!("undone");
+            }
+            ["verify"] => {
+                let mut verifier = Verifier::new();
+                verifier.verify_module(&self.module);
+                match verifier.finish() {
+                    Ok(()) => println!("ok"),
+                    Err(errs) => print!("{}", errs),
+                }
+            }
+            ["write", path] => {
+                let mut file = File::create(path).map_err(|e| format!("{}", e))?;
+                file.write_all(write_module_string(&self.module).as_bytes())
+                    .map_err(|e| format!("{}", e))?;
+            }
+            _ => return Err(format!("unknown command `{}`; try `help`", line.trim())),
+        }
+        Ok(true)
+    }
+
+    /// Find a unit defined in the module by name.
+    fn find_unit(&self, name: &str) -> Result<UnitId, String> {
+        let name: UnitName = name.parse()?;
+        match self.module.lookup(&name) {
+            Some(LinkedUnit::Def(unit)) => Ok(unit),
+            Some(LinkedUnit::Decl(..)) => Err(format!("unit {} is only declared", name)),
+            None => Err(format!("unknown unit {}", name)),
+        }
+    }
+
+    /// Run a pass on the module, or only on one of its units.
+    fn run_pass(&mut self, name: &str, unit: Option<UnitId>) -> Result<(), String> {
+        let mut ctx = PassContext::default();
+        if let Some(unit) = unit {
+            ctx.frozen_units = self
+                .module
+                .units()
+                .filter(|other| other.id() != unit)
+                .map(|other| UnitPattern::new(other.name().to_string()))
+                .collect();
+        }
+        let snapshot = self.module.clone();
+        match run_by_name(name, &ctx, &mut self.module) {
+            Some(true) => {
+                self.history.push(snapshot);
+                println!("{}: modified", name);
+            }
+            Some(false) => println!("{}: unchanged", name),
+            None => return Err(format!("unknown pass `{}`", name)),
+        }
+        Ok(())
+    }
+
+    /// Print the definition and uses of a value.
+    fn show_uses(&self, unit: UnitId, name: &str) -> Result<(), String> {
+        let unit = self.module.unit(unit);
+        let value = unit
+            .args()
+            .chain(
+                unit.all_insts()
+                    .filter_map(|inst| unit.get_inst_result(inst)),
+            )
+            .find(|value| value.dump(&unit).to_string() == name)
+            .ok_or_else(|| format!("unknown value {} in {}", name, unit.name()))?;
+        match unit.get_value_inst(value) {
+            Some(inst) => println!("def: {}", inst.dump(&unit)),
+            None => println!("def: {} {} argument", unit.value_type(value), name),
+        }
+        let mut uses: Vec<_> = unit.uses(value).iter().cloned().collect();
+        uses.sort();
+        for inst in uses {
+            println!("use: {}", inst.dump(&unit));
+        }
+        Ok(())
+    }
+}
+
+static HELP_COMMANDS: &str = "COMMANDS:
+    units               List the units and declarations in the module
+    dump [UNIT]         Print the module, or only one of its units
+    pass PASS [UNIT]    Run a pass on the module, or only on one of its units
+    uses UNIT VALUE     Print the definition and uses of a value in a unit
+    undo                Revert the last pass that modified the module
+    verify              Verify the module
+    write FILE          Write the module to a file
+    help                Print this list of commands
+    quit                End the session
+
+The passes are the ones accepted by `llhd-opt --pass`.
+";
@@ -0,0 +1,861 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! A C API for embedding LLHD in other tools.
+//!
+//! This module exposes the parser, a netlist builder, the optimization passes,
+//! the verifier, and the writer through `extern "C"` functions, such that
+//! tools written in C, C++, or any language with a C FFI can use LLHD without
+//! shelling out to `llhd-opt` and `llhd-conv`. It is only available with the
+//! `llhd-capi` feature. The declarations for C are in `include/llhd.h`; a
+//! shared library can be built with:
+//!
+//! ```text
+//! cargo rustc --release --lib --features llhd-capi --crate-type cdylib
+//! ```
+//!
+//! Modules are passed around as opaque pointers, which are owned by the caller
+//! and released with `llhd_module_free`. Units and values are identified by
+//! integer handles, which remain valid as long as the unit or value they refer
+//! to is not removed from the module; `LLHD_INVALID` denotes the absence of a
+//! unit or value. Strings returned by the API are owned by the caller and
+//! released with `llhd_string_free`. Functions that may fail take an optional
+//! `error` out-parameter which receives a message describing the failure.
+//! Panics inside LLHD, for example in a pass, do not unwind into the caller;
+//! they are reported through `error` like any other failure, and functions
+//! without one return their failure value.
+
+use crate::{
+    assembly::{parse_module, parse_type, write_module_string},
+    ir::{prelude::*, LinkedUnit, ValueData},
+    netlist::NetlistBuilder,
+    opt::prelude::*,
+    pass::run_by_name,
+    table::TableKey,
+    ty::Type,
+    verifier::Verifier,
+};
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+/// A handle to a unit in a module.
+pub type LlhdUnit = u32;
+
+/// A handle to a value in a unit.
+pub type LlhdValue = u32;
+
+/// The handle denoting the absence of a unit or value.
+pub const LLHD_INVALID: u32 = u32::MAX;
+
+/// The kind of a function unit, as returned by `llhd_unit_kind`.
+pub const LLHD_UNIT_FUNCTION: c_int = 0;
+/// The kind of a process unit, as returned by `llhd_unit_kind`.
+pub const LLHD_UNIT_PROCESS: c_int = 1;
+/// The kind of an entity unit, as returned by `llhd_unit_kind`.
+pub const LLHD_UNIT_ENTITY: c_int = 2;
+
+/// Convert a string into a C string owned by the caller.
+fn to_c_string(s: impl Into<Vec<u8>>) -> *mut c_char {
+    match CString::new(s) {
+        Ok(s) => s.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Convert a C string into a string slice.
+unsafe fn from_c_str<'a>(s: *const c_char) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err("unexpected null string".to_string());
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| format!("invalid UTF-8 in string: {}", e))
+}
+
+/// Store an error message in an optional out-parameter.
+unsafe fn set_error(error: *mut *mut c_char, message: impl Into<Vec<u8>>) {
+    if !error.is_null() {
+        *error = to_c_string(message);
+    }
+}
+
+/// Run the body of an entry point, turning a panic into an error.
+///
+/// A panic must not unwind into the C caller, since that aborts the host
+/// process. Instead, the panic message is stored in `error` and `failed` is
+/// returned. The module or netlist the body worked on may be left in an
+/// inconsistent state and should be released.
+unsafe fn guard<T>(error: *mut *mut c_char, failed: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_error(error, format!("panic: {}", message));
+            failed
+        }
+    }
+}
+
+/// Parse a unit name such as `@foo` or `%foo`. Names without a sigil are
+/// global.
+fn parse_unit_name(name: &str) -> UnitName {
+    if let Some(name) = name.strip_prefix('@') {
+        UnitName::global(name)
+    } else if let Some(name) = name.strip_prefix('%') {
+        UnitName::local(name)
+    } else {
+        UnitName::global(name)
+    }
+}
+
+/// Look up the unit referred to by a handle.
+fn find_unit(module: &Module, unit: LlhdUnit) -> Option<Unit<'_>> {
+    module.get_unit(UnitId::new(unit as usize))
+}
+
+/// Look up the value referred to by a handle.
+fn find_value(unit: Unit, value: LlhdValue) -> Option<Value> {
+    let value = Value::new(value as usize);
+    if !unit.has_value(value) {
+        return None;
+    }
+    match unit[value] {
+        ValueData::Inst { .. } | ValueData::Arg { .. } => Some(value),
+        _ => None,
+    }
+}
+
+/// Release a string returned by the API.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by this API which has not been
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Create a new empty module.
+#[no_mangle]
+pub extern "C" fn llhd_module_new() -> *mut Module {
+    Box::into_raw(Box::new(Module::new()))
+}
+
+/// Release a module.
+///
+/// # Safety
+///
+/// `module` must be null or a module returned by this API which has not been
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_free(module: *mut Module) {
+    if !module.is_null() {
+        drop(Box::from_raw(module));
+    }
+}
+
+/// Parse a module in assembly format.
+///
+/// Returns null and sets `error` if the input cannot be parsed.
+///
+/// # Safety
+///
+/// `src` must be a valid null-terminated string. `error` must be null or point
+/// to writable storage for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_parse(
+    src: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut Module {
+    guard(error, ptr::null_mut(), || {
+        match from_c_str(src).and_then(parse_module) {
+            Ok(module) => Box::into_raw(Box::new(module)),
+            Err(e) => {
+                set_error(error, e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Print a module in assembly format.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_print(module: *const Module) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        to_c_string(write_module_string(&*module))
+    })
+}
+
+/// Verify the integrity of a module.
+///
+/// Returns whether the module is valid, and sets `error` to the list of
+/// problems otherwise.
+///
+/// # Safety
+///
+/// `module` must be a valid module. `error` must be null or point to writable
+/// storage for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_verify(
+    module: *const Module,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&*module);
+        match verifier.finish() {
+            Ok(()) => true,
+            Err(errs) => {
+                set_error(error, errs.to_string());
+                false
+            }
+        }
+    })
+}
+
+/// Resolve the references between the units of a module.
+///
+/// This is necessary after adding units which refer to each other.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_link(module: *mut Module) {
+    guard(ptr::null_mut(), (), || {
+        (*module).link();
+    })
+}
+
+/// Run a pipeline of passes on a module.
+///
+/// The passes are given by their `llhd-opt` name, e.g. `cf` or `dce`, in a
+/// comma-separated list. Returns false and sets `error` if a pass does not
+/// exist; the passes before it have been run in that case.
+///
+/// # Safety
+///
+/// `module` must be a valid module and `passes` a valid null-terminated
+/// string. `error` must be null or point to writable storage for a string
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_run_passes(
+    module: *mut Module,
+    passes: *const c_char,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        let passes = match from_c_str(passes) {
+            Ok(passes) => passes,
+            Err(e) => {
+                set_error(error, e);
+                return false;
+            }
+        };
+        let ctx = PassContext::default();
+        for name in passes.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if run_by_name(name, &ctx, &mut *module).is_none() {
+                set_error(error, format!("unknown pass `{}`", name));
+                return false;
+            }
+        }
+        true
+    })
+}
+
+/// Declare an external entity with ports of the given types.
+///
+/// The types are given in assembly format, e.g. `i8$`. Returns false and sets
+/// `error` if a type cannot be parsed.
+///
+/// # Safety
+///
+/// `module` must be a valid module and `name` a valid null-terminated string.
+/// `inputs` and `outputs` must point to `num_inputs` and `num_outputs` valid
+/// null-terminated strings. `error` must be null or point to writable storage
+/// for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_declare(
+    module: *mut Module,
+    name: *const c_char,
+    inputs: *const *const c_char,
+    num_inputs: usize,
+    outputs: *const *const c_char,
+    num_outputs: usize,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        let result: Result<(), String> = (|| {
+            let name = parse_unit_name(from_c_str(name)?);
+            let mut sig = Signature::new();
+            for i in 0..num_inputs {
+                sig.add_input(parse_type(from_c_str(*inputs.add(i))?)?);
+            }
+            for i in 0..num_outputs {
+                sig.add_output(parse_type(from_c_str(*outputs.add(i))?)?);
+            }
+            (*module).declare(name, sig);
+            Ok(())
+        })();
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, e);
+                false
+            }
+        }
+    })
+}
+
+/// Get the number of units in a module.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_num_units(module: *const Module) -> usize {
+    guard(ptr::null_mut(), 0, || (*module).units().count())
+}
+
+/// Get the unit at an index in a module.
+///
+/// Returns `LLHD_INVALID` if the index is out of bounds.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_unit(module: *const Module, index: usize) -> LlhdUnit {
+    guard(ptr::null_mut(), LLHD_INVALID, || {
+        match (*module).units().nth(index) {
+            Some(unit) => unit.id().index() as LlhdUnit,
+            None => LLHD_INVALID,
+        }
+    })
+}
+
+/// Find a unit in a module by name, e.g. `@foo`.
+///
+/// Returns `LLHD_INVALID` if there is no such unit, or only a declaration.
+///
+/// # Safety
+///
+/// `module` must be a valid module and `name` a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_module_find_unit(
+    module: *const Module,
+    name: *const c_char,
+) -> LlhdUnit {
+    guard(ptr::null_mut(), LLHD_INVALID, || {
+        let name = match from_c_str(name) {
+            Ok(name) => parse_unit_name(name),
+            Err(_) => return LLHD_INVALID,
+        };
+        match (*module).lookup(&name) {
+            Some(LinkedUnit::Def(id)) => id.index() as LlhdUnit,
+            _ => LLHD_INVALID,
+        }
+    })
+}
+
+/// Get the name of a unit, e.g. `@foo`.
+///
+/// Returns null if the unit does not exist.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_unit_name(module: *const Module, unit: LlhdUnit) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        match find_unit(&*module, unit) {
+            Some(unit) => to_c_string(unit.name().to_string()),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Get the kind of a unit, one of the `LLHD_UNIT_*` constants.
+///
+/// Returns -1 if the unit does not exist.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_unit_kind(module: *const Module, unit: LlhdUnit) -> c_int {
+    guard(ptr::null_mut(), -1, || {
+        match find_unit(&*module, unit).map(|u| u.kind()) {
+            Some(UnitKind::Function) => LLHD_UNIT_FUNCTION,
+            Some(UnitKind::Process) => LLHD_UNIT_PROCESS,
+            Some(UnitKind::Entity) => LLHD_UNIT_ENTITY,
+            None => -1,
+        }
+    })
+}
+
+/// Get the number of instructions in a unit.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_unit_num_insts(module: *const Module, unit: LlhdUnit) -> usize {
+    guard(ptr::null_mut(), 0, || {
+        find_unit(&*module, unit)
+            .map(|u| u.all_insts().count())
+            .unwrap_or(0)
+    })
+}
+
+/// Get the number of arguments of a unit.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_unit_num_args(module: *const Module, unit: LlhdUnit) -> usize {
+    guard(ptr::null_mut(), 0, || {
+        find_unit(&*module, unit)
+            .map(|u| u.args().count())
+            .unwrap_or(0)
+    })
+}
+
+/// Get the value of an argument of a unit.
+///
+/// Inputs come before outputs. Returns `LLHD_INVALID` if the unit or argument
+/// does not exist.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_unit_arg(
+    module: *const Module,
+    unit: LlhdUnit,
+    index: usize,
+) -> LlhdValue {
+    guard(ptr::null_mut(), LLHD_INVALID, || {
+        find_unit(&*module, unit)
+            .and_then(|u| u.args().nth(index))
+            .map(|v| v.index() as LlhdValue)
+            .unwrap_or(LLHD_INVALID)
+    })
+}
+
+/// Get the name of a value, without the `%` sigil.
+///
+/// Returns null if the value does not exist or is unnamed.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_value_name(
+    module: *const Module,
+    unit: LlhdUnit,
+    value: LlhdValue,
+) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        let unit = match find_unit(&*module, unit) {
+            Some(unit) => unit,
+            None => return ptr::null_mut(),
+        };
+        match find_value(unit, value).and_then(|v| unit.get_name(v)) {
+            Some(name) => to_c_string(name),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Get the type of a value in assembly format, e.g. `i8$`.
+///
+/// Returns null if the value does not exist.
+///
+/// # Safety
+///
+/// `module` must be a valid module.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_value_type(
+    module: *const Module,
+    unit: LlhdUnit,
+    value: LlhdValue,
+) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        let unit = match find_unit(&*module, unit) {
+            Some(unit) => unit,
+            None => return ptr::null_mut(),
+        };
+        match find_value(unit, value) {
+            Some(v) => to_c_string(unit.value_type(v).to_string()),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Create a netlist for an entity with the given name, e.g. `@top`.
+///
+/// Returns null if the name is not a valid string.
+///
+/// # Safety
+///
+/// `name` must be a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_new(name: *const c_char) -> *mut NetlistBuilder {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        match from_c_str(name) {
+            Ok(name) => Box::into_raw(Box::new(NetlistBuilder::new(parse_unit_name(name)))),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Release a netlist.
+///
+/// # Safety
+///
+/// `nl` must be null or a netlist returned by this API which has not been
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_free(nl: *mut NetlistBuilder) {
+    if !nl.is_null() {
+        drop(Box::from_raw(nl));
+    }
+}
+
+/// Add a port or local net of the given type to a netlist.
+unsafe fn netlist_add(
+    name: *const c_char,
+    ty: *const c_char,
+    error: *mut *mut c_char,
+    add: impl FnOnce(&str, Type),
+) -> bool {
+    let name = from_c_str(name);
+    let ty = from_c_str(ty).and_then(parse_type);
+    match name.and_then(|name| ty.map(|ty| (name, ty))) {
+        Ok((name, ty)) => {
+            add(name, ty);
+            true
+        }
+        Err(e) => {
+            set_error(error, e);
+            false
+        }
+    }
+}
+
+/// Add an input port to a netlist.
+///
+/// The type is the one of the values carried by the port, e.g. `i8`. Returns
+/// false and sets `error` if the type cannot be parsed.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist, and `name` and `ty` valid null-terminated
+/// strings. `error` must be null or point to writable storage for a string
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_input(
+    nl: *mut NetlistBuilder,
+    name: *const c_char,
+    ty: *const c_char,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        netlist_add(name, ty, error, |name, ty| {
+            (*nl).input(name, ty);
+        })
+    })
+}
+
+/// Add an output port to a netlist.
+///
+/// The type is the one of the values carried by the port, e.g. `i8`. Returns
+/// false and sets `error` if the type cannot be parsed.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist, and `name` and `ty` valid null-terminated
+/// strings. `error` must be null or point to writable storage for a string
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_output(
+    nl: *mut NetlistBuilder,
+    name: *const c_char,
+    ty: *const c_char,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        netlist_add(name, ty, error, |name, ty| {
+            (*nl).output(name, ty);
+        })
+    })
+}
+
+/// Declare a local net in a netlist.
+///
+/// The type is the one of the values carried by the net, e.g. `i8`. Returns
+/// false and sets `error` if the type cannot be parsed.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist, and `name` and `ty` valid null-terminated
+/// strings. `error` must be null or point to writable storage for a string
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_net(
+    nl: *mut NetlistBuilder,
+    name: *const c_char,
+    ty: *const c_char,
+    error: *mut *mut c_char,
+) -> bool {
+    guard(error, false, || {
+        netlist_add(name, ty, error, |name, ty| {
+            (*nl).net(name, ty);
+        })
+    })
+}
+
+/// Instantiate a cell in a netlist, connecting its ports to the given nets.
+///
+/// Returns false if a string is invalid.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist and `unit` a valid null-terminated string.
+/// `inputs` and `outputs` must point to `num_inputs` and `num_outputs` valid
+/// null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_cell(
+    nl: *mut NetlistBuilder,
+    unit: *const c_char,
+    inputs: *const *const c_char,
+    num_inputs: usize,
+    outputs: *const *const c_char,
+    num_outputs: usize,
+) -> bool {
+    guard(ptr::null_mut(), false, || {
+        let strs = |ptrs: *const *const c_char, n: usize| {
+            (0..n)
+                .map(|i| from_c_str(*ptrs.add(i)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let (unit, inputs, outputs) = match (
+            from_c_str(unit),
+            strs(inputs, num_inputs),
+            strs(outputs, num_outputs),
+        ) {
+            (Ok(unit), Ok(inputs), Ok(outputs)) => (unit, inputs, outputs),
+            _ => return false,
+        };
+        (*nl).cell(parse_unit_name(unit), &inputs, &outputs);
+        true
+    })
+}
+
+/// Connect two nets in a netlist, such that they become one.
+///
+/// Returns false if a string is invalid.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist, and `a` and `b` valid null-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_connect(
+    nl: *mut NetlistBuilder,
+    a: *const c_char,
+    b: *const c_char,
+) -> bool {
+    guard(ptr::null_mut(), false, || {
+        match (from_c_str(a), from_c_str(b)) {
+            (Ok(a), Ok(b)) => {
+                (*nl).connect(a, b);
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Build the entity described by a netlist and add it to a module.
+///
+/// Returns the new unit, or `LLHD_INVALID` and sets `error` if the netlist is
+/// inconsistent. The netlist remains owned by the caller.
+///
+/// # Safety
+///
+/// `nl` must be a valid netlist and `module` a valid module. `error` must be
+/// null or point to writable storage for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn llhd_netlist_build(
+    nl: *const NetlistBuilder,
+    module: *mut Module,
+    error: *mut *mut c_char,
+) -> LlhdUnit {
+    guard(error, LLHD_INVALID, || match (*nl).build(&mut *module) {
+        Ok(id) => id.index() as LlhdUnit,
+        Err(e) => {
+            set_error(error, e);
+            LLHD_INVALID
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn take(s: *mut c_char) -> String {
+        assert!(!s.is_null());
+        let owned = CStr::from_ptr(s).to_str().unwrap().to_string();
+        llhd_string_free(s);
+        owned
+    }
+
+    #[test]
+    fn parse_and_inspect() {
+        unsafe {
+            let src = c("
+                func @foo (i8 %x) i8 {
+                entry:
+                    %a = const i8 1
+                    %b = add i8 %a, %a
+                    %y = add i8 %x, %b
+                    ret i8 %y
+                }
+            ");
+            let mut error = ptr::null_mut();
+            let module = llhd_module_parse(src.as_ptr(), &mut error);
+            assert!(!module.is_null());
+            assert_eq!(llhd_module_num_units(module), 1);
+            let unit = llhd_module_find_unit(module, c("@foo").as_ptr());
+            assert_eq!(llhd_module_unit(module, 0), unit);
+            assert_eq!(llhd_module_unit(module, 1), LLHD_INVALID);
+            assert_eq!(take(llhd_unit_name(module, unit)), "@foo");
+            assert_eq!(llhd_unit_kind(module, unit), LLHD_UNIT_FUNCTION);
+            assert_eq!(llhd_unit_kind(module, LLHD_INVALID), -1);
+            assert!(llhd_unit_name(module, 1000).is_null());
+            assert_eq!(llhd_unit_num_insts(module, unit), 4);
+            assert_eq!(llhd_unit_num_args(module, unit), 1);
+            let x = llhd_unit_arg(module, unit, 0);
+            assert_eq!(take(llhd_value_name(module, unit, x)), "x");
+            assert_eq!(take(llhd_value_type(module, unit, x)), "i8");
+            assert!(llhd_value_name(module, unit, LLHD_INVALID).is_null());
+            assert!(llhd_value_type(module, unit, 1000).is_null());
+
+            assert!(llhd_module_run_passes(
+                module,
+                c("cf, dce").as_ptr(),
+                ptr::null_mut()
+            ));
+            assert_eq!(llhd_unit_num_insts(module, unit), 3);
+            assert!(!llhd_module_run_passes(
+                module,
+                c("nope").as_ptr(),
+                &mut error
+            ));
+            assert_eq!(take(error), "unknown pass `nope`");
+            assert!(llhd_module_verify(module, ptr::null_mut()));
+            assert!(take(llhd_module_print(module)).contains("%b = const i8 2"));
+            llhd_module_free(module);
+
+            let module = llhd_module_parse(c("func @foo").as_ptr(), &mut error);
+            assert!(module.is_null());
+            assert!(!take(error).is_empty());
+        }
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            assert!(!guard(&mut error, false, || panic!("boom")));
+            assert_eq!(take(error), "panic: boom");
+            assert_eq!(guard(ptr::null_mut(), 1, || 2), 2);
+        }
+    }
+
+    #[test]
+    fn build_netlist() {
+        unsafe {
+            let module = llhd_module_new();
+            let i1 = c("i1$");
+            assert!(llhd_module_declare(
+                module,
+                c("@inv").as_ptr(),
+                &i1.as_ptr(),
+                1,
+                &i1.as_ptr(),
+                1,
+                ptr::null_mut()
+            ));
+
+            let nl = llhd_netlist_new(c("@top").as_ptr());
+            let (a, n, z) = (c("a"), c("n"), c("z"));
+            assert!(llhd_netlist_input(
+                nl,
+                a.as_ptr(),
+                c("i1").as_ptr(),
+                ptr::null_mut()
+            ));
+            assert!(llhd_netlist_output(
+                nl,
+                z.as_ptr(),
+                c("i1").as_ptr(),
+                ptr::null_mut()
+            ));
+            let mut error = ptr::null_mut();
+            assert!(!llhd_netlist_net(
+                nl,
+                n.as_ptr(),
+                c("x").as_ptr(),
+                &mut error
+            ));
+            take(error);
+            assert!(llhd_netlist_cell(
+                nl,
+                c("@inv").as_ptr(),
+                &a.as_ptr(),
+                1,
+                &n.as_ptr(),
+                1
+            ));
+            assert!(llhd_netlist_cell(
+                nl,
+                c("@inv").as_ptr(),
+                &n.as_ptr(),
+                1,
+                &z.as_ptr(),
+                1
+            ));
+            let unit = llhd_netlist_build(nl, module, ptr::null_mut());
+            llhd_netlist_free(nl);
+            assert_ne!(unit, LLHD_INVALID);
+            assert_eq!(llhd_unit_kind(module, unit), LLHD_UNIT_ENTITY);
+            assert_eq!(llhd_unit_num_args(module, unit), 2);
+            llhd_module_link(module);
+            assert!(llhd_module_verify(module, ptr::null_mut()));
+            llhd_module_free(module);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Tool configuration files.
+//!
+//! This module implements the configuration files accepted by `llhd-opt` and
+//! `llhd-conv` via `--config`, such that build systems do not have to encode
+//! long command lines. A configuration names the pass pipeline to run, the
+//! options of individual passes, the units which passes must leave untouched,
+//! and the options of the output backend. It may be written in TOML or JSON;
+//! options given on the command line take precedence.
+//!
+//! ```
+//! use llhd::{config::Config, ir::UnitName};
+//!
+//! let config = Config::from_toml(r#"
+//!     passes = ["cf", "dce", "gcse"]
+//!
+//!     [options]
+//!     extend = "zero"
+//!     flatten_max_insts = 100
+//!
+//!     [[units]]
+//!     match = "@debug_*"
+//!
+//!     [[units]]
+//!     match = "@keep_*"
+//!     skip = ["dce"]
+//!
+//!     [output]
+//!     format = "v"
+//! "#).unwrap();
+//!
+//! let frozen = config.frozen_units("dce");
+//! assert!(frozen.iter().any(|p| p.matches(&UnitName::global("keep_regs"))));
+//! assert!(config.frozen_units("cf").len() == 1);
+//! ```
+
+use crate::{
+    ir::prelude::*, opt::prelude::*, opt::Speculation, pass::run_by_name, sim::Differential,
+};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// A configuration of the LLHD tools.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The passes to run, in order. The tool's default pipeline is used if
+    /// omitted.
+    pub passes: Option<Vec<String>>,
+    /// The options of individual passes.
+    pub options: PassOptions,
+    /// Overrides for the units whose name matches a pattern.
+    pub units: Vec<UnitOverride>,
+    /// The options of the output backend.
+    pub output: OutputOptions,
+}
+
+/// The options of individual passes.
+///
+/// These correspond to the command line options of `llhd-opt` of the same
+/// name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PassOptions {
+    /// How the widthlegal pass extends operands of mismatching width, one of
+    /// `report`, `zero`, `sign`, or `opcode`.
+    pub extend: Option<String>,
+    /// The instructions to be probed by the dbgprobe pass, given by value name
+    /// (`%x`) or mnemonic (`add`).
+    pub probe: Vec<String>,
+    /// Only flatten entities with at most this many instructions.
+    pub flatten_max_insts: Option<usize>,
+    /// Only flatten entities instantiated at most this many times.
+    pub flatten_max_fanout: Option<usize>,
+    /// Do not grow entities beyond this many instructions by flattening.
+    pub flatten_max_size: Option<usize>,
+    /// Partition entities with more than this many instructions.
+    pub partition_max_insts: Option<usize>,
+    /// Duplicate instructions whose result drives more than this many
+    /// operands in the fanoutdup pass.
+    pub fanout_max_load: Option<usize>,
+    /// Only duplicate instructions of at most this many gates in the
+    /// fanoutdup pass.
+    pub fanout_max_gates: Option<usize>,
+    /// The width of the words the wordlower pass lowers wider integer
+    /// operations to.
+    pub word_width: Option<usize>,
+    /// Skip units with more than this many instructions in GCSE.
+    pub gcse_max_insts: Option<usize>,
+    /// Skip units with more than this many instructions in TCM.
+    pub tcm_max_insts: Option<usize>,
+    /// Stop moving drives in a unit after this many dominator tree steps in
+    /// TCM.
+    pub tcm_max_dom_steps: Option<usize>,
+    /// How the blockorder pass arranges blocks, either `rpo` or `regions`.
+    pub block_order: Option<String>,
+    /// The passes to apply speculatively, rolling back results which fail to
+    /// verify, or `all`.
+    pub speculate: Vec<String>,
+    /// Also roll back speculatively applied passes which change the simulated
+    /// behaviour of the design.
+    pub speculate_sim: bool,
+}
+
+/// An override for the units whose name matches a pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitOverride {
+    /// The pattern matched against the unit names.
+    #[serde(rename = "match")]
+    pub pattern: UnitPattern,
+    /// The passes which must not modify the units. All passes if omitted.
+    #[serde(default)]
+    pub skip: Option<Vec<String>>,
+}
+
+/// The options of the output backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputOptions {
+    /// The format of the output of `llhd-conv`, e.g. `v` or `llhd`.
+    pub format: Option<String>,
+    /// The graph to emit for dot output, one of `cfg`, `dfg`, or `hierarchy`.
+    pub graph: Option<String>,
+    /// The unit to emit the graph of for dot output, e.g. `@foo`.
+    pub unit: Option<String>,
+    /// How Verilog output emits registers, either `behavioral` as `always`
+    /// blocks or `structural` as instances of the `cells`.
+    pub registers: Option<String>,
+    /// The cells instantiated for registers in structural Verilog output.
+    pub cells: Vec<RegisterCell>,
+    /// Emit names which are not valid Verilog identifiers as escaped
+    /// identifiers, instead of replacing the invalid characters.
+    pub escape_names: bool,
+    /// Where Verilog output records the LLHD name of each identifier, either
+    /// `comment` for a comment above each module, or the path of a JSON file.
+    pub name_map: Option<String>,
+    /// Annotate the blocks in LLHD assembly output with their temporal region.
+    pub annotate_regions: bool,
+    /// The SDC file with the timing constraints of the design, which are
+    /// checked against the design and included in reports.
+    pub constraints: Option<String>,
+}
+
+/// A cell which implements a register in structural Verilog output.
+///
+/// The cell is instantiated for registers with a matching trigger mode, and
+/// its ports are connected by name. The optional ports are active high, and
+/// are tied off if the register does not use them. Registers which use a
+/// control for which the cell has no port cannot be mapped onto the cell,
+/// except for enables, which are then implemented by feeding the output back
+/// into the data input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterCell {
+    /// The trigger mode the cell implements, one of `rise`, `fall`, `both`,
+    /// `high`, or `low`.
+    pub mode: String,
+    /// The name of the cell module.
+    pub name: String,
+    /// The port receiving the trigger.
+    pub clock: String,
+    /// The port receiving the data to be stored.
+    pub data: String,
+    /// The port providing the stored data.
+    pub output: String,
+    /// The port enabling the trigger.
+    #[serde(default)]
+    pub enable: Option<String>,
+    /// The asynchronous clear port.
+    #[serde(default)]
+    pub clear: Option<String>,
+    /// The asynchronous preset port.
+    #[serde(default)]
+    pub preset: Option<String>,
+}
+
+impl Config {
+    /// Parse a configuration in JSON format.
+    pub fn from_json(input: &str) -> Result<Self, String> {
+        serde_json::from_str(input).map_err(|e| format!("invalid configuration: {}", e))
+    }
+
+    /// Parse a configuration in TOML format.
+    pub fn from_toml(input: &str) -> Result<Self, String> {
+        toml::from_str(input).map_err(|e| format!("invalid configuration: {}", e))
+    }
+
+    /// Read a configuration from a file.
+    ///
+    /// Files with a `.toml` extension are parsed as TOML, all others as JSON.
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let config = if path.extension().map(|e| e == "toml").unwrap_or(false) {
+            Self::from_toml(&input)
+        } else {
+            Self::from_json(&input)
+        };
+        config.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Apply the pass options to a pass context.
+    pub fn apply(&self, ctx: &mut PassContext) -> Result<(), String> {
+        let opts = &self.options;
+        if let Some(extend) = &opts.extend {
+            ctx.extension_policy = extend.parse()?;
+        }
+        for filter in &opts.probe {
+            ctx.probe_filter.add(filter);
+        }
+        if let Some(n) = opts.flatten_max_insts {
+            ctx.flatten_limits.max_unit_insts = n;
+        }
+        if let Some(n) = opts.flatten_max_fanout {
+            ctx.flatten_limits.max_fanout = n;
+        }
+        if let Some(n) = opts.flatten_max_size {
+            ctx.flatten_limits.max_parent_insts = n;
+        }
+        if let Some(n) = opts.partition_max_insts {
+            ctx.partition_limits.max_unit_insts = n;
+        }
+        if let Some(n) = opts.fanout_max_load {
+            ctx.fanout_limits.max_load = n;
+        }
+        if let Some(n) = opts.fanout_max_gates {
+            ctx.fanout_limits.max_gates = n;
+        }
+        if let Some(n) = opts.word_width {
+            ctx.word_width = Some(n);
+        }
+        if let Some(n) = opts.gcse_max_insts {
+            ctx.pass_limits.gcse_max_insts = n;
+        }
+        if let Some(n) = opts.tcm_max_insts {
+            ctx.pass_limits.tcm_max_insts = n;
+        }
+        if let Some(n) = opts.tcm_max_dom_steps {
+            ctx.pass_limits.tcm_max_dom_steps = n;
+        }
+        if let Some(order) = &opts.block_order {
+            ctx.block_order = order.parse()?;
+        }
+        Ok(())
+    }
+
+    /// Create the speculative pass application configured by the pass options.
+    pub fn speculation(&self) -> Speculation {
+        let mut speculation = Speculation::default();
+        speculation.passes = self.options.speculate.clone();
+        if self.options.speculate_sim {
+            speculation.differential = Some(Differential::new(&[]));
+        }
+        speculation
+    }
+
+    /// Get the patterns of the units which a pass must leave untouched.
+    pub fn frozen_units(&self, pass: &str) -> Vec<UnitPattern> {
+        self.units
+            .iter()
+            .filter(|o| match &o.skip {
+                Some(passes) => passes.iter().any(|p| p == pass),
+                None => true,
+            })
+            .map(|o| o.pattern.clone())
+            .collect()
+    }
+
+    /// Run a pass on a module, given its command line name, honoring the unit
+    /// overrides.
+    ///
+    /// Returns whether the pass modified the module, or `None` if there is no
+    /// pass with the given name.
+    pub fn run_pass(&self, name: &str, ctx: &mut PassContext, module: &mut Module) -> Option<bool> {
+        ctx.frozen_units = self.frozen_units(name);
+        let result = run_by_name(name, ctx, module);
+        ctx.frozen_units.clear();
+        result
+    }
+
+    /// Run a pass on a module like `run_pass`, but speculatively if the pass
+    /// is among the ones given to `speculation`.
+    pub fn run_pass_speculatively(
+        &self,
+        name: &str,
+        ctx: &mut PassContext,
+        module: &mut Module,
+        speculation: &mut Speculation,
+    ) -> Option<bool> {
+        if !speculation.applies(name) {
+            return self.run_pass(name, ctx, module);
+        }
+        ctx.frozen_units = self.frozen_units(name);
+        let result = speculation.run_pass(name, ctx, module);
+        ctx.frozen_units.clear();
+        result
+    }
+}
+
+/// A pattern matched against unit names, such as `@debug_*`.
+///
+/// The pattern is matched against the full name including its sigil. A `*`
+/// matches any sequence of characters, and a `?` matches any single
+/// character.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UnitPattern(pub String);
+
+impl UnitPattern {
+    /// Create a new pattern.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        UnitPattern(pattern.into())
+    }
+
+    /// Check if a unit name matches the pattern.
+    pub fn matches(&self, name: &UnitName) -> bool {
+        let pattern: Vec<char> = self.0.chars().collect();
+        let name: Vec<char> = name.to_string().chars().collect();
+        glob_match(&pattern, &name)
+    }
+}
+
+/// Match a string against a pattern with `*` and `?` wildcards.
+fn glob_match(pattern: &[char], s: &[char]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some(('*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((&c, rest)) => match s.split_first() {
+            Some((&d, s)) if c == '?' || c == d => glob_match(rest, s),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::{parse_module, write_module_string},
+        pass::ExtensionPolicy,
+    };
+
+    #[test]
+    fn patterns() {
+        let p = UnitPattern::new("@debug_*");
+        assert!(p.matches(&UnitName::global("debug_")));
+        assert!(p.matches(&UnitName::global("debug_foo")));
+        assert!(!p.matches(&UnitName::global("foo_debug_bar")));
+        assert!(!p.matches(&UnitName::local("debug_foo")));
+        assert!(UnitPattern::new("@a?c").matches(&UnitName::global("abc")));
+        assert!(!UnitPattern::new("@a?c").matches(&UnitName::global("ac")));
+    }
+
+    #[test]
+    fn json_and_overrides() {
+        let config = Config::from_json(
+            r#"{
+                "passes": ["cf"],
+                "options": { "extend": "sign", "probe": ["%x", "add"] },
+                "units": [{ "match": "@debug_*" }]
+            }"#,
+        )
+        .unwrap();
+        let mut ctx = PassContext::default();
+        config.apply(&mut ctx).unwrap();
+        assert_eq!(ctx.extension_policy, ExtensionPolicy::SignExtend);
+        assert_eq!(ctx.probe_filter.names, vec!["x"]);
+        assert_eq!(ctx.probe_filter.opcodes, vec!["add"]);
+        assert!(Config::from_json(r#"{ "pases": [] }"#).is_err());
+
+        let input = "
+            func @debug_foo () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                ret i8 %b
+            }
+            func @foo () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                ret i8 %b
+            }
+        ";
+        let mut module = parse_module(input).unwrap();
+        assert_eq!(config.run_pass("cf", &mut ctx, &mut module), Some(true));
+        let output = write_module_string(&module);
+        assert!(output.contains("%b = add i8 %a, %a"));
+        assert!(output.contains("%b = const i8 2"));
+        assert!(ctx.frozen_units.is_empty());
+    }
+
+    #[test]
+    fn register_cells() {
+        let config = Config::from_toml(
+            r#"
+            [output]
+            registers = "structural"
+
+            [[output.cells]]
+            mode = "rise"
+            name = "DFF"
+            clock = "CK"
+            data = "D"
+            output = "Q"
+            clear = "R"
+            "#,
+        )
+        .unwrap();
+        let cell = &config.output.cells[0];
+        assert_eq!(config.output.registers.as_deref(), Some("structural"));
+        assert_eq!(cell.name, "DFF");
+        assert_eq!(cell.clear.as_deref(), Some("R"));
+        assert_eq!(cell.enable, None);
+        assert!(Config::from_toml("[[output.cells]]\nmode = \"rise\"").is_err());
+    }
+}
@@ -0,0 +1,755 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Timing constraints of a design.
+//!
+//! This module holds the constraints that timing-driven backends and reports
+//! consume: clock definitions, the delays outside the design at its inputs and
+//! outputs, and false paths. They are read from a small subset of SDC:
+//!
+//! - `current_design top` selects the entity the constraints apply to.
+//! - `create_clock -name clk -period 10 -waveform {0 5} [get_ports clk]`
+//!   defines a clock, which is virtual if no port is given.
+//! - `set_input_delay -clock clk 2 [get_ports {a b}]` and `set_output_delay`
+//!   set the delay at ports relative to a clock.
+//! - `set_false_path -from [get_ports rst] -to [get_cells q]` excludes paths
+//!   from timing analysis.
+//!
+//! Times are in nanoseconds. Ports are the arguments of the entity, and cells
+//! are its registers, named after the signal their `reg` instruction stores
+//! into. Constraints refer to both by name, which passes preserve, and are
+//! attached to the IR by `Constraints::anchors`.
+//!
+//! ```
+//! use llhd::{assembly::parse_module, constraints::*, ir::Opcode};
+//!
+//! let module = parse_module("
+//!     entity @top (i1$ %clk, i8$ %d) -> (i8$ %q) {
+//!         %clkp = prb i1$ %clk
+//!         %dp = prb i8$ %d
+//!         reg i8$ %q, [%dp, rise %clkp]
+//!     }
+//! ").unwrap();
+//! let constraints = Constraints::from_sdc("
+//!     create_clock -name clk -period 10 [get_ports clk]
+//!     set_input_delay -clock clk 2 [get_ports d]
+//!     set_false_path -to [get_cells q]
+//! ").unwrap();
+//! let top = constraints.design(&module).unwrap();
+//! let anchors = constraints.anchors(&top).unwrap();
+//! let reg = top.all_insts().find(|&inst| top[inst].opcode() == Opcode::Reg);
+//! assert_eq!(anchors[&Target::Cell("q".into())], Anchor::Reg(reg.unwrap()));
+//! ```
+
+use crate::ir::{prelude::*, LinkedUnit};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter, Result as FmtResult},
+    iter::Peekable,
+    str::Chars,
+};
+
+/// The timing constraints of a design.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Constraints {
+    /// The entity the constraints apply to. If omitted, the only entity which
+    /// is not instantiated by another.
+    pub design: Option<UnitName>,
+    /// The clocks.
+    pub clocks: Vec<Clock>,
+    /// The delays before the signals arrive at input ports.
+    pub input_delays: Vec<PortDelay>,
+    /// The delays after the signals leave output ports.
+    pub output_delays: Vec<PortDelay>,
+    /// The paths excluded from timing analysis.
+    pub false_paths: Vec<FalsePath>,
+}
+
+/// A clock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clock {
+    /// The name other constraints refer to the clock by.
+    pub name: String,
+    /// The period in nanoseconds.
+    pub period: f64,
+    /// The times of the rising and the falling edge within the period.
+    pub waveform: (f64, f64),
+    /// The port the clock arrives at, or `None` for a virtual clock.
+    pub port: Option<String>,
+}
+
+/// The delay outside the design at an input or output port.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortDelay {
+    /// The port.
+    pub port: String,
+    /// The clock the delay is relative to.
+    pub clock: String,
+    /// The delay in nanoseconds.
+    pub delay: f64,
+}
+
+/// A set of paths excluded from timing analysis.
+///
+/// All paths from any of the start points to any of the end points are false.
+/// Empty start or end points match any.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FalsePath {
+    /// The start points.
+    pub from: Vec<Target>,
+    /// The end points.
+    pub to: Vec<Target>,
+}
+
+/// A port or register of the design, by name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Target {
+    /// An argument of the entity, as selected by `get_ports`.
+    Port(String),
+    /// A register, as selected by `get_cells`, named after the signal its
+    /// `reg` instruction stores into.
+    Cell(String),
+}
+
+/// The object in a unit that a constraint is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Anchor {
+    /// An argument of the unit.
+    Port(Value),
+    /// A `reg` instruction.
+    Reg(Inst),
+}
+
+impl Constraints {
+    /// Parse constraints in SDC format.
+    pub fn from_sdc(input: &str) -> Result<Self, String> {
+        let mut constraints = Self::default();
+        let mut chars = input.chars().peekable();
+        let mut line = 1;
+        while chars.peek().is_some() {
+            let start = line;
+            parse_words(&mut chars, &mut line, false)
+                .and_then(|words| constraints.add_command(words))
+                .map_err(|e| format!("line {}: {}", start, e))?;
+        }
+        Ok(constraints)
+    }
+
+    /// Get the ports and registers the constraints refer to.
+    pub fn targets(&self) -> impl Iterator<Item = Target> + '_ {
+        let clocks = self.clocks.iter().flat_map(|clock| clock.port.clone());
+        let delays = self.input_delays.iter().chain(&self.output_delays);
+        let ports = clocks.chain(delays.map(|delay| delay.port.clone()));
+        let paths = self
+            .false_paths
+            .iter()
+            .flat_map(|path| path.from.iter().chain(&path.to).cloned());
+        ports.map(Target::Port).chain(paths)
+    }
+
+    /// Find the entity the constraints apply to.
+    pub fn design<'a>(&self, module: &'a Module) -> Result<Unit<'a>, String> {
+        if let Some(name) = &self.design {
+            return match module.lookup(name) {
+                Some(LinkedUnit::Def(id)) if module.unit(id).is_entity() => Ok(module.unit(id)),
+                _ => Err(format!("entity {} not found", name)),
+            };
+        }
+        let instantiated: HashSet<&UnitName> = module
+            .entities()
+            .flat_map(|unit| unit.extern_units().map(|(_, data)| &data.name))
+            .collect();
+        let tops: Vec<_> = module
+            .entities()
+            .filter(|unit| !instantiated.contains(unit.name()))
+            .collect();
+        match tops.as_slice() {
+            [top] => Ok(*top),
+            [] => Err("no entity to constrain".to_string()),
+            _ => Err(format!(
+                "multiple candidates for the constrained entity ({}); select one with \
+                 `current_design`",
+                tops.iter()
+                    .map(|unit| unit.name().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Attach the constraints to the ports and registers of a unit.
+    ///
+    /// Fails if a port or register does not exist.
+    pub fn anchors(&self, unit: &Unit) -> Result<HashMap<Target, Anchor>, String> {
+        let mut anchors = HashMap::new();
+        for target in self.targets() {
+            let anchor = match target.resolve(unit) {
+                Some(anchor) => anchor,
+                None => return Err(format!("{} not found in {}", target, unit.name())),
+            };
+            anchors.insert(target, anchor);
+        }
+        Ok(anchors)
+    }
+
+    /// Add an SDC command.
+    fn add_command(&mut self, words: Vec<Word>) -> Result<(), String> {
+        let mut words = words.into_iter();
+        let name = match words.next() {
+            Some(Word::Text(name)) => name,
+            Some(_) => return Err("expected a command name".to_string()),
+            None => return Ok(()),
+        };
+        match name.as_str() {
+            "current_design" => {
+                let (_, mut args) = parse_args(&name, words, &[])?;
+                let design = match (args.pop(), args.is_empty()) {
+                    (Some(Word::Text(design)), true) => design,
+                    _ => return Err("`current_design` expects a name".to_string()),
+                };
+                self.design = Some(match design.chars().next() {
+                    Some('@') | Some('%') => design.parse()?,
+                    _ => UnitName::global(design),
+                });
+            }
+            "create_clock" => {
+                let (mut opts, args) =
+                    parse_args(&name, words, &["-name", "-period", "-waveform"])?;
+                let port = match args.len() {
+                    0 => None,
+                    1 => match ports(args.into_iter().next().unwrap())?.as_slice() {
+                        [port] => Some(port.clone()),
+                        _ => return Err("a clock must be defined on one port".to_string()),
+                    },
+                    _ => return Err("`create_clock` expects at most one port".to_string()),
+                };
+                let name = match (opts.remove("-name"), &port) {
+                    (Some(word), _) => text(word)?,
+                    (None, Some(port)) => port.clone(),
+                    (None, None) => return Err("a virtual clock needs a `-name`".to_string()),
+                };
+                let period = match opts.remove("-period") {
+                    Some(word) => number(word)?,
+                    None => return Err("`create_clock` needs a `-period`".to_string()),
+                };
+                let waveform = match opts.remove("-waveform") {
+                    Some(Word::List(edges)) if edges.len() == 2 => {
+                        (parse_number(&edges[0])?, parse_number(&edges[1])?)
+                    }
+                    Some(_) => return Err("`-waveform` expects two edges".to_string()),
+                    None => (0.0, period / 2.0),
+                };
+                if self.clock(&name).is_some() {
+                    return Err(format!("clock `{}` defined twice", name));
+                }
+                self.clocks.push(Clock {
+                    name,
+                    period,
+                    waveform,
+                    port,
+                });
+            }
+            "set_input_delay" | "set_output_delay" => {
+                let (mut opts, args) = parse_args(&name, words, &["-clock"])?;
+                let clock = match opts.remove("-clock") {
+                    Some(word) => text(word)?,
+                    None => return Err(format!("`{}` needs a `-clock`", name)),
+                };
+                if self.clock(&clock).is_none() {
+                    return Err(format!("unknown clock `{}`", clock));
+                }
+                let mut args = args.into_iter();
+                let (delay, ports) = match (args.next(), args.next(), args.next()) {
+                    (Some(delay), Some(ports), None) => (number(delay)?, self::ports(ports)?),
+                    _ => return Err(format!("`{}` expects a delay and ports", name)),
+                };
+                let delays = match name.as_str() {
+                    "set_input_delay" => &mut self.input_delays,
+                    _ => &mut self.output_delays,
+                };
+                delays.extend(ports.into_iter().map(|port| PortDelay {
+                    port,
+                    clock: clock.clone(),
+                    delay,
+                }));
+            }
+            "set_false_path" => {
+                let (mut opts, args) = parse_args(&name, words, &["-from", "-to"])?;
+                if !args.is_empty() {
+                    return Err("`set_false_path` expects `-from` and `-to` only".to_string());
+                }
+                let from = opts.remove("-from").map(targets).transpose()?;
+                let to = opts.remove("-to").map(targets).transpose()?;
+                if from.is_none() && to.is_none() {
+                    return Err("a false path needs a `-from` or `-to`".to_string());
+                }
+                self.false_paths.push(FalsePath {
+                    from: from.unwrap_or_default(),
+                    to: to.unwrap_or_default(),
+                });
+            }
+            _ => return Err(format!("unsupported command `{}`", name)),
+        }
+        Ok(())
+    }
+
+    /// Find a clock by name.
+    fn clock(&self, name: &str) -> Option<&Clock> {
+        self.clocks.iter().find(|clock| clock.name == name)
+    }
+}
+
+/// Emits the constraints in SDC format, which `Constraints::from_sdc` reads
+/// back.
+impl Display for Constraints {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if let Some(design) = &self.design {
+            match design {
+                UnitName::Global(name) => writeln!(f, "current_design {}", quote(name))?,
+                _ => writeln!(f, "current_design {}", quote(&design.to_string()))?,
+            }
+        }
+        for clock in &self.clocks {
+            write!(
+                f,
+                "create_clock -name {} -period {}",
+                quote(&clock.name),
+                clock.period
+            )?;
+            if clock.waveform != (0.0, clock.period / 2.0) {
+                write!(
+                    f,
+                    " -waveform {{{} {}}}",
+                    clock.waveform.0, clock.waveform.1
+                )?;
+            }
+            if let Some(port) = &clock.port {
+                write!(f, " [get_ports {}]", quote(port))?;
+            }
+            writeln!(f)?;
+        }
+        for (name, delays) in &[
+            ("set_input_delay", &self.input_delays),
+            ("set_output_delay", &self.output_delays),
+        ] {
+            for delay in delays.iter() {
+                writeln!(
+                    f,
+                    "{} -clock {} {} [get_ports {}]",
+                    name,
+                    quote(&delay.clock),
+                    delay.delay,
+                    quote(&delay.port)
+                )?;
+            }
+        }
+        for path in &self.false_paths {
+            // A single command selects either ports or cells on each end, so
+            // mixed ends are split into one command per combination.
+            let from = collections(&path.from);
+            let to = collections(&path.to);
+            for from in &from {
+                for to in &to {
+                    write!(f, "set_false_path")?;
+                    if let Some(from) = from {
+                        write!(f, " -from {}", from)?;
+                    }
+                    if let Some(to) = to {
+                        write!(f, " -to {}", to)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Target {
+    /// Find the port or register in a unit.
+    pub fn resolve(&self, unit: &Unit) -> Option<Anchor> {
+        match self {
+            Target::Port(name) => unit
+                .args()
+                .find(|&arg| unit.get_name(arg) == Some(name))
+                .map(Anchor::Port),
+            Target::Cell(name) => unit
+                .all_insts()
+                .find(|&inst| {
+                    unit[inst].opcode() == Opcode::Reg
+                        && unit.get_name(unit[inst].args()[0]) == Some(name)
+                })
+                .map(Anchor::Reg),
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Target::Port(name) => write!(f, "port `{}`", name),
+            Target::Cell(name) => write!(f, "register `{}`", name),
+        }
+    }
+}
+
+/// Format targets as SDC collections, one per kind, or a single `None` if
+/// there are no targets.
+fn collections(targets: &[Target]) -> Vec<Option<String>> {
+    let ports: Vec<_> = targets
+        .iter()
+        .flat_map(|target| match target {
+            Target::Port(name) => Some(quote(name)),
+            _ => None,
+        })
+        .collect();
+    let cells: Vec<_> = targets
+        .iter()
+        .flat_map(|target| match target {
+            Target::Cell(name) => Some(quote(name)),
+            _ => None,
+        })
+        .collect();
+    let mut collections = vec![];
+    for (command, names) in &[("get_ports", ports), ("get_cells", cells)] {
+        match names.as_slice() {
+            [] => (),
+            [name] => collections.push(Some(format!("[{} {}]", command, name))),
+            _ => collections.push(Some(format!("[{} {{{}}}]", command, names.join(" ")))),
+        }
+    }
+    if collections.is_empty() {
+        collections.push(None);
+    }
+    collections
+}
+
+/// Wrap a name in braces if it is not a single plain word.
+fn quote(name: &str) -> String {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "[]{}\";#\\".contains(c)) {
+        format!("{{{}}}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// A word of an SDC command.
+#[derive(Debug)]
+enum Word {
+    /// A plain or quoted word.
+    Text(String),
+    /// A list in braces, split into its elements.
+    List(Vec<String>),
+    /// A nested command in brackets.
+    Command(Vec<Word>),
+}
+
+/// Parse the words of a command, up to the end of the line or a `;`, or up to
+/// the closing `]` of a nested command.
+fn parse_words(
+    chars: &mut Peekable<Chars>,
+    line: &mut usize,
+    nested: bool,
+) -> Result<Vec<Word>, String> {
+    let mut words = vec![];
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' if nested => {
+                chars.next();
+                *line += 1;
+            }
+            '\n' | ';' => {
+                chars.next();
+                if c == '\n' {
+                    *line += 1;
+                }
+                return Ok(words);
+            }
+            ']' if nested => {
+                chars.next();
+                return Ok(words);
+            }
+            ']' | '}' => return Err(format!("unexpected `{}`", c)),
+            '#' if words.is_empty() && !nested => {
+                while chars.peek().map(|&c| c != '\n').unwrap_or(false) {
+                    chars.next();
+                }
+            }
+            '\\' => {
+                chars.next();
+                match chars.next() {
+                    Some('\n') => *line += 1,
+                    _ => return Err("unexpected `\\`".to_string()),
+                }
+            }
+            '[' => {
+                chars.next();
+                words.push(Word::Command(parse_words(chars, line, true)?));
+            }
+            '{' => {
+                chars.next();
+                let mut text = String::new();
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        Some('}') if depth == 0 => break,
+                        Some(c) => {
+                            match c {
+                                '{' => depth += 1,
+                                '}' => depth -= 1,
+                                '\n' => *line += 1,
+                                _ => (),
+                            }
+                            text.push(c);
+                        }
+                        None => return Err("missing `}`".to_string()),
+                    }
+                }
+                words.push(Word::List(
+                    text.split_whitespace().map(String::from).collect(),
+                ));
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err("missing `\"`".to_string()),
+                    }
+                }
+                words.push(Word::Text(text));
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]{}\";".contains(c) {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                words.push(Word::Text(text));
+            }
+        }
+    }
+    if nested {
+        return Err("missing `]`".to_string());
+    }
+    Ok(words)
+}
+
+/// Split the arguments of a command into options with a value and positional
+/// arguments.
+fn parse_args(
+    command: &str,
+    words: impl Iterator<Item = Word>,
+    options: &[&str],
+) -> Result<(HashMap<String, Word>, Vec<Word>), String> {
+    let mut opts = HashMap::new();
+    let mut args = vec![];
+    let mut words = words.peekable();
+    while let Some(word) = words.next() {
+        let option = match &word {
+            Word::Text(text)
+                if text.starts_with('-') && text[1..].starts_with(char::is_alphabetic) =>
+            {
+                text.clone()
+            }
+            _ => {
+                args.push(word);
+                continue;
+            }
+        };
+        if !options.contains(&option.as_str()) {
+            return Err(format!("unsupported option `{}` of `{}`", option, command));
+        }
+        match words.next() {
+            Some(value) => {
+                opts.insert(option, value);
+            }
+            None => return Err(format!("option `{}` expects a value", option)),
+        }
+    }
+    Ok((opts, args))
+}
+
+/// Get the text of a word.
+fn text(word: Word) -> Result<String, String> {
+    match word {
+        Word::Text(text) => Ok(text),
+        _ => Err("expected a name".to_string()),
+    }
+}
+
+/// Get the number in a word.
+fn number(word: Word) -> Result<f64, String> {
+    match word {
+        Word::Text(text) => parse_number(&text),
+        _ => Err("expected a number".to_string()),
+    }
+}
+
+/// Parse a finite number.
+fn parse_number(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("expected a number, found `{}`", text)),
+    }
+}
+
+/// Get the ports and registers selected by a `get_ports` or `get_cells`
+/// command.
+fn targets(word: Word) -> Result<Vec<Target>, String> {
+    let mut words = match word {
+        Word::Command(words) => words.into_iter(),
+        _ => return Err("expected `[get_ports ...]` or `[get_cells ...]`".to_string()),
+    };
+    let kind: fn(String) -> Target = match words.next() {
+        Some(Word::Text(command)) if command == "get_ports" => Target::Port,
+        Some(Word::Text(command)) if command == "get_cells" => Target::Cell,
+        _ => return Err("expected `[get_ports ...]` or `[get_cells ...]`".to_string()),
+    };
+    let names = match (words.next(), words.next()) {
+        (Some(Word::Text(name)), None) => vec![name],
+        (Some(Word::List(names)), None) => names,
+        _ => return Err("expected one name or a list of names".to_string()),
+    };
+    Ok(names.into_iter().map(kind).collect())
+}
+
+/// Get the ports selected by a `get_ports` command.
+fn ports(word: Word) -> Result<Vec<String>, String> {
+    targets(word)?
+        .into_iter()
+        .map(|target| match target {
+            Target::Port(name) => Ok(name),
+            Target::Cell(name) => Err(format!("expected a port, found register `{}`", name)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn sdc_round_trip() {
+        let input = indoc::indoc! {"
+            # Clocks
+            current_design top
+            create_clock -name clk -period 10 [get_ports clk]
+            create_clock -name vclk -period 4 -waveform {1 3}
+            set_input_delay -clock clk 2.5 [get_ports {a b}]; set_output_delay -clock vclk 1 \\
+                [get_ports y]
+            set_false_path -from [get_ports rst] -to [get_cells {q r}]
+        "};
+        let constraints = Constraints::from_sdc(input).unwrap();
+        assert_eq!(constraints.design, Some(UnitName::global("top")));
+        assert_eq!(
+            constraints.clocks[1],
+            Clock {
+                name: "vclk".into(),
+                period: 4.0,
+                waveform: (1.0, 3.0),
+                port: None,
+            }
+        );
+        assert_eq!(constraints.input_delays.len(), 2);
+        assert_eq!(constraints.input_delays[1].port, "b");
+        assert_eq!(constraints.input_delays[1].delay, 2.5);
+        let output = indoc::indoc! {"
+            current_design top
+            create_clock -name clk -period 10 [get_ports clk]
+            create_clock -name vclk -period 4 -waveform {1 3}
+            set_input_delay -clock clk 2.5 [get_ports a]
+            set_input_delay -clock clk 2.5 [get_ports b]
+            set_output_delay -clock vclk 1 [get_ports y]
+            set_false_path -from [get_ports rst] -to [get_cells {q r}]
+        "};
+        assert_eq!(constraints.to_string(), output);
+        assert_eq!(Constraints::from_sdc(output).unwrap(), constraints);
+    }
+
+    #[test]
+    fn sdc_errors() {
+        let error = |input| Constraints::from_sdc(input).unwrap_err();
+        assert_eq!(
+            error("\nset_multicycle_path 2"),
+            "line 2: unsupported command `set_multicycle_path`"
+        );
+        assert_eq!(
+            error("set_input_delay -clock clk 1 [get_ports a]"),
+            "line 1: unknown clock `clk`"
+        );
+        assert_eq!(
+            error("create_clock -period 1 -add [get_ports a]"),
+            "line 1: unsupported option `-add` of `create_clock`"
+        );
+        assert_eq!(
+            error("create_clock -period 1"),
+            "line 1: a virtual clock needs a `-name`"
+        );
+        assert_eq!(
+            error("set_false_path -to [get_ports a"),
+            "line 1: missing `]`"
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        let module = parse_module(
+            "
+            entity @reg (i1$ %clk, i8$ %d) -> (i8$ %q) {
+                %clkp = prb i1$ %clk
+                %dp = prb i8$ %d
+                %zero = const i8 0
+                %r = sig i8 %zero
+                reg i8$ %r, [%dp, rise %clkp]
+                %rp = prb i8$ %r
+                %t = const time 0s 1e
+                drv i8$ %q, %rp, %t
+            }
+            entity @top (i1$ %clk, i8$ %d) -> (i8$ %q) {
+                inst @reg (i1$ %clk, i8$ %d) -> (i8$ %q)
+            }
+            ",
+        )
+        .unwrap();
+        let mut constraints = Constraints::from_sdc(
+            "
+            create_clock -period 10 [get_ports clk]
+            set_false_path -from [get_ports d] -to [get_cells r]
+            ",
+        )
+        .unwrap();
+        assert_eq!(constraints.clocks[0].name, "clk");
+        assert_eq!(
+            constraints.design(&module).unwrap().name(),
+            &UnitName::global("top")
+        );
+        let top = constraints.design(&module).unwrap();
+        assert_eq!(
+            constraints.anchors(&top).unwrap_err(),
+            "register `r` not found in @top"
+        );
+
+        constraints.design = Some(UnitName::global("reg"));
+        let unit = constraints.design(&module).unwrap();
+        let anchors = constraints.anchors(&unit).unwrap();
+        let reg = unit
+            .all_insts()
+            .find(|&inst| unit[inst].opcode() == Opcode::Reg)
+            .unwrap();
+        assert_eq!(anchors.len(), 3);
+        assert_eq!(
+            anchors[&Target::Port("clk".into())],
+            Anchor::Port(unit.input_arg(0))
+        );
+        assert_eq!(anchors[&Target::Cell("r".into())], Anchor::Reg(reg));
+    }
+}
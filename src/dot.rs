@@ -0,0 +1,192 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Graphviz output
+//!
+//! This module implements rendering of units and modules as graphs in the DOT
+//! language of Graphviz, which is useful to visualize the control flow,
+//! temporal regions, data flow, and instantiation hierarchy while debugging.
+
+use crate::{ir::prelude::*, table::TableKey};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Result, Write},
+};
+
+/// The fill colors assigned to temporal regions, in order.
+static REGION_COLORS: &[&str] = &[
+    "#a6cee3", "#b2df8a", "#fb9a99", "#fdbf6f", "#cab2d6", "#ffff99", "#8dd3c7", "#bebada",
+];
+
+/// Write the control flow graph of a unit.
+///
+/// Each block becomes a node listing its instructions, and each branch an
+/// edge. In functions and processes, blocks are colored by the temporal region
+/// they belong to.
+pub fn write_cfg(out: &mut impl Write, unit: Unit) -> Result<()> {
+    writeln!(out, "digraph \"{}\" {{", escape(&unit.name().to_string()))?;
+    writeln!(
+        out,
+        "    node [shape=box, style=filled, fontname=monospace];"
+    )?;
+    let regions = if unit.is_entity() {
+        None
+    } else {
+        let trg = unit.trg();
+        let colors: HashMap<_, _> = trg
+            .regions()
+            .enumerate()
+            .map(|(i, tr)| (tr.id, REGION_COLORS[i % REGION_COLORS.len()]))
+            .collect();
+        Some((trg, colors))
+    };
+    for bb in unit.blocks() {
+        let mut label = format!("{}:\\l", escape(&bb.dump(&unit).to_string()));
+        for inst in unit.insts(bb) {
+            label.push_str(&format!("  {}\\l", escape(&inst.dump(&unit).to_string())));
+        }
+        let (tr, color) = match &regions {
+            Some((trg, colors)) => (format!(" ({})", trg[bb]), colors[&trg[bb]]),
+            None => (String::new(), "white"),
+        };
+        writeln!(
+            out,
+            "    {} [label=\"{}\", fillcolor=\"{}\", tooltip=\"{}{}\"];",
+            block_id(bb),
+            label,
+            color,
+            escape(&bb.dump(&unit).to_string()),
+            tr,
+        )?;
+    }
+    for bb in unit.blocks() {
+        let term = match unit.last_inst(bb) {
+            Some(term) => term,
+            None => continue,
+        };
+        let data = &unit[term];
+        let cond = data.opcode() == Opcode::BrCond;
+        for (i, &target) in data.blocks().iter().enumerate() {
+            write!(out, "    {} -> {}", block_id(bb), block_id(target))?;
+            if cond {
+                write!(out, " [label=\"{}\"]", i)?;
+            } else if data.opcode().is_temporal() {
+                write!(out, " [style=dashed]")?;
+            }
+            writeln!(out, ";")?;
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Write the data flow graph of selected values in a unit.
+///
+/// Renders the given values and, transitively, all values they are computed
+/// from. Each value becomes a node, and each use of a value as an argument an
+/// edge towards the instruction that uses it.
+pub fn write_dfg(out: &mut impl Write, unit: Unit, values: &[Value]) -> Result<()> {
+    writeln!(out, "digraph \"{}\" {{", escape(&unit.name().to_string()))?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+    let mut seen = HashSet::new();
+    let mut todo: Vec<Value> = values.to_vec();
+    while let Some(value) = todo.pop() {
+        if !seen.insert(value) {
+            continue;
+        }
+        let label = match unit.get_value_inst(value) {
+            Some(inst) => inst.dump(&unit).to_string(),
+            None => format!("{} {}", unit.value_type(value), value.dump(&unit)),
+        };
+        let shape = if values.contains(&value) {
+            ", style=bold"
+        } else if unit.get_value_arg(value).is_some() {
+            ", shape=ellipse"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "    {} [label=\"{}\"{}];",
+            value_id(value),
+            escape(&label),
+            shape
+        )?;
+        if let Some(inst) = unit.get_value_inst(value) {
+            for &arg in unit[inst].args() {
+                writeln!(out, "    {} -> {};", value_id(arg), value_id(value))?;
+                todo.push(arg);
+            }
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Write the instantiation hierarchy of a module.
+///
+/// Each unit and declaration becomes a node, and each unit that instantiates
+/// or calls another an edge, labeled with the number of `inst` and `call`
+/// instructions if there is more than one.
+pub fn write_hierarchy(out: &mut impl Write, module: &Module) -> Result<()> {
+    writeln!(out, "digraph module {{")?;
+    writeln!(out, "    node [fontname=monospace];")?;
+    let mut nodes = BTreeMap::new();
+    for unit in module.units() {
+        let shape = match unit.kind() {
+            UnitKind::Entity => "box",
+            UnitKind::Process => "ellipse",
+            UnitKind::Function => "hexagon",
+        };
+        let style = if unit.is_sim_only() {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        nodes.insert(unit.name().to_string(), format!("shape={}{}", shape, style));
+    }
+    for decl in module.decls() {
+        nodes
+            .entry(module[decl].name.to_string())
+            .or_insert_with(|| "shape=plaintext".to_string());
+    }
+    for (name, attrs) in &nodes {
+        writeln!(out, "    \"{}\" [{}];", escape(name), attrs)?;
+    }
+    for unit in module.units() {
+        let mut edges = BTreeMap::new();
+        for inst in unit.all_insts() {
+            if let Some(ext) = unit[inst].get_ext_unit() {
+                *edges.entry(unit[ext].name.to_string()).or_insert(0) += 1;
+            }
+        }
+        for (target, count) in edges {
+            write!(
+                out,
+                "    \"{}\" -> \"{}\"",
+                escape(&unit.name().to_string()),
+                escape(&target)
+            )?;
+            if count > 1 {
+                write!(out, " [label=\"{}\"]", count)?;
+            }
+            writeln!(out, ";")?;
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Escape a string for use within a quoted DOT identifier.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The DOT node identifier of a block.
+fn block_id(bb: Block) -> String {
+    format!("bb{}", bb.index())
+}
+
+/// The DOT node identifier of a value.
+fn value_id(value: Value) -> String {
+    format!("v{}", value.index())
+}
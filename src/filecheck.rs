@@ -0,0 +1,152 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Golden-file tests of pass output.
+//!
+//! This module implements a harness for tests that consist of an LLHD input
+//! with embedded directives, in the same format used by the regression test
+//! script. A `; RUN: llhd-opt %s -p <pass>...` line names the pass pipeline to
+//! run on the input, and each `; CHECK: <line>` directive must match a line of
+//! the printed output, in order. Only available with the `filecheck` feature.
+//!
+//! ```
+//! let test = "; RUN: llhd-opt %s -p cf -p dce
+//! func @foo () i32 {
+//! entry:
+//!     %a = const i32 1
+//!     %b = add i32 %a, %a
+//!     ret i32 %b
+//! }
+//! ; CHECK: %b = const i32 2
+//! ; CHECK: ret i32 %b
+//! ";
+//! llhd::filecheck::run(test).unwrap();
+//! ```
+
+use crate::{
+    assembly::{parse_module_unchecked, write_module_string},
+    opt::prelude::*,
+    pass::run_by_name,
+    verifier::Verifier,
+};
+use std::path::Path;
+
+/// Run the test in a file.
+pub fn run_file(path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    run(&source).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Run a test.
+///
+/// Parses the input, runs the passes named in the `RUN` line, and checks the
+/// printed module against the `CHECK` directives.
+pub fn run(source: &str) -> Result<(), String> {
+    let passes = parse_run_line(source)?;
+    let mut module = parse_module_unchecked(source)?;
+    module.link();
+    verify(&module).map_err(|e| format!("input does not verify:\n{}", e))?;
+    let ctx = PassContext::default();
+    for pass in passes {
+        run_by_name(&pass, &ctx, &mut module).ok_or_else(|| format!("unknown pass `{}`", pass))?;
+    }
+    verify(&module).map_err(|e| format!("output does not verify:\n{}", e))?;
+    let output = write_module_string(&module);
+    check(source, &output).map_err(|e| format!("{}\noutput:\n{}", e, output))
+}
+
+/// Check an output against the `CHECK` directives in a source.
+///
+/// Each directive must match a line of the output after the line matched by
+/// the previous directive. Lines are compared with comments and surrounding
+/// whitespace removed. All failing directives are reported.
+pub fn check(source: &str, output: &str) -> Result<(), String> {
+    let lines: Vec<_> = output
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .collect();
+    let mut pos = 0;
+    let mut errors = String::new();
+    for (name, expected) in directives(source) {
+        if name != "CHECK" {
+            errors.push_str(&format!("error: unknown directive `{}`\n", name));
+            continue;
+        }
+        match lines[pos..].iter().position(|&line| line == expected) {
+            Some(offset) => pos += offset + 1,
+            None => errors.push_str(&format!(
+                "error: CHECK: {}\n  No matching line found. Scanning from:\n  {}\n",
+                expected,
+                lines.get(pos).unwrap_or(&"<end of file>")
+            )),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Find the `CHECK` directives in a source.
+fn directives(source: &str) -> impl Iterator<Item = (&str, &str)> {
+    source.lines().filter_map(|line| {
+        let line = line.trim_start().strip_prefix(';')?.trim_start();
+        if !line.starts_with("CHECK") {
+            return None;
+        }
+        let colon = line.find(':')?;
+        Some((&line[..colon], line[colon + 1..].trim()))
+    })
+}
+
+/// Parse the pass pipeline from the `RUN` line of a source.
+fn parse_run_line(source: &str) -> Result<Vec<String>, String> {
+    let line = source
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("; RUN:"))
+        .ok_or_else(|| "no `; RUN:` line".to_string())?;
+    let mut words = line.split_whitespace();
+    if words.next() != Some("llhd-opt") || words.next() != Some("%s") {
+        return Err(format!("unsupported RUN line `{}`", line.trim()));
+    }
+    let mut passes = vec![];
+    while let Some(word) = words.next() {
+        match word {
+            "-p" | "--pass" => passes.push(
+                words
+                    .next()
+                    .ok_or_else(|| format!("missing pass after `{}`", word))?
+                    .to_string(),
+            ),
+            _ => return Err(format!("unsupported option `{}`", word)),
+        }
+    }
+    Ok(passes)
+}
+
+/// Verify a module.
+fn verify(module: &crate::ir::Module) -> Result<(), String> {
+    let mut verifier = Verifier::new();
+    verifier.verify_module(module);
+    verifier.finish().map_err(|errs| errs.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives_in_order() {
+        let source = "; CHECK: a\n; CHECK: c\n";
+        assert!(check(source, "a\nb\nc ; comment\n").is_ok());
+        assert!(check(source, "c\nb\na\n").is_err());
+        assert!(check("; CHECK-NEXT: a\n", "a\n").is_err());
+        assert_eq!(
+            parse_run_line("; RUN: llhd-opt %s -p cf --pass dce\n").unwrap(),
+            vec!["cf", "dce"]
+        );
+        assert!(parse_run_line("; RUN: llhd-opt %s --extend zero\n").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Data flow graph export
+//!
+//! This module implements the export of the data flow graphs of the units in a
+//! module as edge lists or GraphML, which allows LLHD designs to be used as
+//! datasets for graph analysis and machine learning tools.
+
+use crate::{ir::prelude::*, Type};
+use std::{
+    collections::HashMap,
+    io::{Result, Write},
+};
+
+/// The data flow graph of a unit.
+///
+/// Each argument and each instruction of the unit becomes a node, numbered
+/// in that order, and each use of a value as an argument of an instruction an
+/// edge from the node that defines the value to the node of the instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFlowGraph {
+    /// The name of the unit.
+    pub unit: UnitName,
+    /// The nodes of the graph.
+    pub nodes: Vec<DataFlowNode>,
+    /// The edges of the graph.
+    pub edges: Vec<DataFlowEdge>,
+}
+
+/// A node in a `DataFlowGraph`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFlowNode {
+    /// The opcode of the instruction, or `arg` for unit arguments.
+    pub opcode: String,
+    /// The type of the value defined by the node, or `void`.
+    pub ty: Type,
+    /// The name of the value defined by the node, if any.
+    pub name: Option<String>,
+}
+
+/// An edge in a `DataFlowGraph`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataFlowEdge {
+    /// The node that defines the value.
+    pub from: usize,
+    /// The node of the instruction that uses the value.
+    pub to: usize,
+    /// The position of the value among the instruction's arguments.
+    pub operand: usize,
+}
+
+impl DataFlowGraph {
+    /// Compute the data flow graph of a unit.
+    pub fn new(unit: Unit) -> Self {
+        let mut nodes = vec![];
+        let mut defs = HashMap::new();
+        for arg in unit.args() {
+            defs.insert(arg, nodes.len());
+            nodes.push(DataFlowNode {
+                opcode: "arg".to_string(),
+                ty: unit.value_type(arg),
+                name: unit.get_name(arg).map(|n| format!("%{}", n)),
+            });
+        }
+        // Entities carry an implicit terminator, which is not part of the
+        // design.
+        let insts: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| !(unit.is_entity() && unit[inst].opcode() == Opcode::Halt))
+            .collect();
+        let base = nodes.len();
+        for &inst in &insts {
+            let result = unit.get_inst_result(inst);
+            if let Some(value) = result {
+                defs.insert(value, nodes.len());
+            }
+            nodes.push(DataFlowNode {
+                opcode: unit[inst].opcode().to_string(),
+                ty: unit.inst_type(inst),
+                name: result
+                    .and_then(|v| unit.get_name(v))
+                    .map(|n| format!("%{}", n)),
+            });
+        }
+        let mut edges = vec![];
+        for (index, &inst) in insts.iter().enumerate() {
+            for (operand, arg) in unit[inst].args().iter().enumerate() {
+                if let Some(&from) = defs.get(arg) {
+                    edges.push(DataFlowEdge {
+                        from,
+                        to: base + index,
+                        operand,
+                    });
+                }
+            }
+        }
+        Self {
+            unit: unit.name().clone(),
+            nodes,
+            edges,
+        }
+    }
+}
+
+/// Write the data flow graphs of all units in a module as edge lists.
+///
+/// Each unit starts with a `unit` line, followed by a `node` line with the
+/// index, opcode, type, and optional name of each node, and an `edge` line
+/// with the source, target, and operand position of each edge. The fields are
+/// separated by tabs.
+pub fn write_edge_list(out: &mut impl Write, module: &Module) -> Result<()> {
+    for unit in module.units() {
+        let dfg = DataFlowGraph::new(unit);
+        writeln!(out, "unit\t{}", dfg.unit)?;
+        for (index, node) in dfg.nodes.iter().enumerate() {
+            write!(out, "node\t{}\t{}\t{}", index, node.opcode, node.ty)?;
+            if let Some(name) = &node.name {
+                write!(out, "\t{}", name)?;
+            }
+            writeln!(out)?;
+        }
+        for edge in &dfg.edges {
+            writeln!(out, "edge\t{}\t{}\t{}", edge.from, edge.to, edge.operand)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the data flow graphs of all units in a module as GraphML.
+///
+/// Each unit becomes a directed graph. The nodes carry the opcode, type, and
+/// name as data, and the edges the operand position.
+pub fn write_graphml(out: &mut impl Write, module: &Module) -> Result<()> {
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    for (key, domain, ty) in &[
+        ("opcode", "node", "string"),
+        ("type", "node", "string"),
+        ("name", "node", "string"),
+        ("operand", "edge", "int"),
+    ] {
+        writeln!(
+            out,
+            "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"{2}\"/>",
+            key, domain, ty
+        )?;
+    }
+    for (graph, unit) in module.units().enumerate() {
+        let dfg = DataFlowGraph::new(unit);
+        writeln!(out, "  <graph id=\"g{}\" edgedefault=\"directed\">", graph)?;
+        writeln!(out, "    <desc>{}</desc>", escape(&dfg.unit.to_string()))?;
+        for (index, node) in dfg.nodes.iter().enumerate() {
+            write!(
+                out,
+                "    <node id=\"g{}n{}\"><data key=\"opcode\">{}</data><data key=\"type\">{}</data>",
+                graph,
+                index,
+                node.opcode,
+                escape(&node.ty.to_string())
+            )?;
+            if let Some(name) = &node.name {
+                write!(out, "<data key=\"name\">{}</data>", escape(name))?;
+            }
+            writeln!(out, "</node>")?;
+        }
+        for edge in &dfg.edges {
+            writeln!(
+                out,
+                "    <edge source=\"g{0}n{1}\" target=\"g{0}n{2}\"><data key=\"operand\">{3}</data></edge>",
+                graph, edge.from, edge.to, edge.operand
+            )?;
+        }
+        writeln!(out, "  </graph>")?;
+    }
+    writeln!(out, "</graphml>")?;
+    Ok(())
+}
+
+/// Escape a string for use within XML character data.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -7,7 +7,7 @@
 
 use crate::{
     impl_table_indexing,
-    ir::{Block, BlockData},
+    ir::{Annotations, Block, BlockData},
     table::PrimaryTable2,
 };
 use std::collections::HashMap;
@@ -16,12 +16,14 @@ use std::collections::HashMap;
 ///
 /// This is the main container for BBs and control flow related information.
 /// Every `Function` and `Process` has an associated control flow graph.
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(super) struct ControlFlowGraph {
     /// The basic blocks in the graph.
     pub blocks: PrimaryTable2<Block, BlockData>,
     /// The anonymous name hints assigned to basic blocks.
     pub anonymous_hints: HashMap<Block, u32>,
+    /// The annotations attached to basic blocks.
+    pub annotations: HashMap<Block, Annotations>,
 }
 
 impl_table_indexing!(ControlFlowGraph, blocks, Block, BlockData);
@@ -7,17 +7,19 @@
 
 use crate::{
     impl_table_indexing,
-    ir::{Arg, Block, ExtUnit, ExtUnitData, Inst, InstData, Value, ValueData},
+    ir::{
+        Annotations, Arg, Block, ExtUnit, ExtUnitData, Inst, InstData, Resolution, Value, ValueData,
+    },
     table::{PrimaryTable2, SecondaryTable},
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 /// A data flow graph.
 ///
 /// This is the main container for instructions, values, and the relationship
 /// between them. Every `Function`, `Process`, and `Entity` has an associated
 /// data flow graph.
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub(super) struct DataFlowGraph {
     /// The instructions in the graph.
     pub insts: PrimaryTable2<Inst, InstData>,
@@ -35,6 +37,20 @@ pub(super) struct DataFlowGraph {
     pub anonymous_hints: HashMap<Value, u32>,
     /// The location hints assigned to instructions.
     pub location_hints: HashMap<Inst, usize>,
+    /// The location hints of the original instructions each instruction has
+    /// been derived from.
+    pub origins: HashMap<Inst, BTreeSet<usize>>,
+    /// The annotations attached to instructions.
+    pub annotations: HashMap<Inst, Annotations>,
+    /// The weights of the destinations of conditional branches.
+    #[serde(default)]
+    pub branch_weights: HashMap<Inst, [u32; 2]>,
+    /// The resolutions of signals with multiple drivers.
+    #[serde(default)]
+    pub resolutions: HashMap<Inst, Resolution>,
+    /// The number of copies of replicated `inst` instructions.
+    #[serde(default)]
+    pub replications: HashMap<Inst, usize>,
     /// The value use lookup table.
     pub value_uses: HashMap<Value, HashSet<Inst>>,
     /// The block use lookup table.
@@ -6,9 +6,9 @@
 //! representation.
 
 use crate::{
-    ir::{Block, ExtUnit, Inst, Unit, UnitBuilder, Value},
+    ir::{Block, ExtUnit, Inst, Unit, UnitBuilder, UnitName, Value},
     ty::{array_ty, int_ty, pointer_ty, signal_ty, struct_ty, void_ty, Type},
-    value::{IntValue, TimeValue},
+    value::{EnumValue, IntValue, TimeValue},
 };
 use bitflags::bitflags;
 use std::borrow::Cow;
@@ -46,6 +46,7 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         match ty.as_ref() {
             TimeType => self.const_time(TimeValue::zero()),
             IntType(w) => self.const_int(IntValue::zero(*w)),
+            EnumType(n) => self.const_enum(EnumValue::zero(*n)),
             ArrayType(l, ty) => {
                 let name = self.name.take();
                 let elem = self.const_zero(ty);
@@ -58,6 +59,12 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
                 self.name = name;
                 self.strukt(elems)
             }
+            NamedStructType(_, tys) => {
+                let name = self.name.take();
+                let elems = tys.iter().map(|ty| self.const_zero(ty)).collect();
+                self.name = name;
+                self.named_strukt(ty.clone(), elems)
+            }
             _ => panic!("no zero value for {}", ty),
         }
     }
@@ -84,6 +91,17 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    pub fn const_enum(&mut self, value: impl Into<EnumValue>) -> Value {
+        let value = value.into();
+        let ty = value.ty();
+        let data = InstData::ConstEnum {
+            opcode: Opcode::ConstEnum,
+            imm: value,
+        };
+        let inst = self.build(data, ty);
+        self.inst_result(inst)
+    }
+
     pub fn alias(&mut self, x: Value) -> Value {
         let ty = self.value_type(x);
         let inst = self.build_unary(Opcode::Alias, ty, x);
@@ -133,6 +151,19 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    /// Construct a value of a named struct type from its fields.
+    pub fn named_strukt(&mut self, ty: Type, args: Vec<Value>) -> Value {
+        assert!(ty.struct_name().is_some(), "{} is not a named struct", ty);
+        let inst = self.build(
+            InstData::Aggregate {
+                opcode: Opcode::Struct,
+                args,
+            },
+            ty,
+        );
+        self.inst_result(inst)
+    }
+
     pub fn not(&mut self, x: Value) -> Value {
         let ty = self.value_type(x);
         let inst = self.build_unary(Opcode::Not, ty, x);
@@ -145,6 +176,29 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    /// `a = popcount type x`, yielding the number of one bits in `x`.
+    pub fn popcount(&mut self, x: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_unary(Opcode::Popcount, ty, x);
+        self.inst_result(inst)
+    }
+
+    /// `a = clz type x`, yielding the number of zero bits above the highest
+    /// one bit in `x`.
+    pub fn clz(&mut self, x: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_unary(Opcode::Clz, ty, x);
+        self.inst_result(inst)
+    }
+
+    /// `a = ctz type x`, yielding the number of zero bits below the lowest
+    /// one bit in `x`.
+    pub fn ctz(&mut self, x: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_unary(Opcode::Ctz, ty, x);
+        self.inst_result(inst)
+    }
+
     pub fn add(&mut self, x: Value, y: Value) -> Value {
         let ty = self.value_type(x);
         let inst = self.build_binary(Opcode::Add, ty, x, y);
@@ -181,6 +235,14 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    /// `a = smulh type x, y`, yielding the upper half of the double-width
+    /// product of the signed values.
+    pub fn smulh(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Smulh, ty, x, y);
+        self.inst_result(inst)
+    }
+
     pub fn sdiv(&mut self, x: Value, y: Value) -> Value {
         let ty = self.value_type(x);
         let inst = self.build_binary(Opcode::Sdiv, ty, x, y);
@@ -205,6 +267,14 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    /// `a = umulh type x, y`, yielding the upper half of the double-width
+    /// product of the unsigned values.
+    pub fn umulh(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Umulh, ty, x, y);
+        self.inst_result(inst)
+    }
+
     pub fn udiv(&mut self, x: Value, y: Value) -> Value {
         let ty = self.value_type(x);
         let inst = self.build_binary(Opcode::Udiv, ty, x, y);
@@ -223,6 +293,62 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.inst_result(inst)
     }
 
+    /// `a = umin type x, y`, yielding the smaller of the unsigned values.
+    pub fn umin(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Umin, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = umax type x, y`, yielding the larger of the unsigned values.
+    pub fn umax(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Umax, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = smin type x, y`, yielding the smaller of the signed values.
+    pub fn smin(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Smin, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = smax type x, y`, yielding the larger of the signed values.
+    pub fn smax(&mut self, x: Value, y: Value) -> Value {
+        let ty = self.value_type(x);
+        let inst = self.build_binary(Opcode::Smax, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = uaddo type x, y`, yielding `{type, i1}` with the carry out.
+    pub fn uaddo(&mut self, x: Value, y: Value) -> Value {
+        let ty = struct_ty(vec![self.value_type(x), int_ty(1)]);
+        let inst = self.build_binary(Opcode::Uaddo, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = saddo type x, y`, yielding `{type, i1}` with the overflow flag.
+    pub fn saddo(&mut self, x: Value, y: Value) -> Value {
+        let ty = struct_ty(vec![self.value_type(x), int_ty(1)]);
+        let inst = self.build_binary(Opcode::Saddo, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = usubo type x, y`, yielding `{type, i1}` with the borrow out.
+    pub fn usubo(&mut self, x: Value, y: Value) -> Value {
+        let ty = struct_ty(vec![self.value_type(x), int_ty(1)]);
+        let inst = self.build_binary(Opcode::Usubo, ty, x, y);
+        self.inst_result(inst)
+    }
+
+    /// `a = ssubo type x, y`, yielding `{type, i1}` with the overflow flag.
+    pub fn ssubo(&mut self, x: Value, y: Value) -> Value {
+        let ty = struct_ty(vec![self.value_type(x), int_ty(1)]);
+        let inst = self.build_binary(Opcode::Ssubo, ty, x, y);
+        self.inst_result(inst)
+    }
+
     pub fn eq(&mut self, x: Value, y: Value) -> Value {
         let inst = self.build_binary(Opcode::Eq, int_ty(1), x, y);
         self.inst_result(inst)
@@ -294,6 +420,10 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
     }
 
     pub fn reg(&mut self, x: Value, data: Vec<RegTrigger>) -> Inst {
+        self.reg_ctrl(x, data, RegControls::default())
+    }
+
+    pub fn reg_ctrl(&mut self, x: Value, data: Vec<RegTrigger>, ctrl: RegControls) -> Inst {
         let mut args = vec![x];
         let mut modes = vec![];
         args.extend(data.iter().map(|x| x.data));
@@ -301,11 +431,17 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         args.extend(data.iter().map(|x| x.gate.unwrap_or(Value::invalid())));
         modes.extend(data.iter().map(|x| x.mode));
         assert_eq!(args.len(), modes.len() * 3 + 1);
+        let mut controls = vec![];
+        for (control, value) in ctrl.iter() {
+            controls.push(control);
+            args.push(value);
+        }
         self.build(
             InstData::Reg {
                 opcode: Opcode::Reg,
                 args,
                 modes,
+                controls,
             },
             void_ty(),
         )
@@ -389,6 +525,10 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.build_ternary(Opcode::Del, void_ty(), target, source, delay)
     }
 
+    pub fn del_cond(&mut self, target: Value, source: Value, delay: Value, cond: Value) -> Inst {
+        self.build_quaternary(Opcode::DelCond, void_ty(), target, source, delay, cond)
+    }
+
     pub fn call(&mut self, unit: ExtUnit, args: Vec<Value>) -> Inst {
         let ty = self.builder.extern_sig(unit).return_type();
         let data = InstData::Call {
@@ -435,6 +575,29 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.build_quaternary(Opcode::DrvCond, void_ty(), signal, value, delay, cond)
     }
 
+    /// Probe a slice of a signal.
+    ///
+    /// Emits the canonical `exts` of the signal followed by a `prb`.
+    pub fn prb_slice(&mut self, signal: Value, offset: usize, length: usize) -> Value {
+        let slice = self.ext_slice(signal, offset, length);
+        self.prb(slice)
+    }
+
+    /// Drive a slice of a signal.
+    ///
+    /// Emits the canonical `exts` of the signal followed by a `drv`.
+    pub fn drv_slice(
+        &mut self,
+        signal: Value,
+        offset: usize,
+        length: usize,
+        value: Value,
+        delay: Value,
+    ) -> Inst {
+        let slice = self.ext_slice(signal, offset, length);
+        self.drv(slice, value, delay)
+    }
+
     pub fn var(&mut self, x: Value) -> Value {
         let ty = pointer_ty(self.value_type(x));
         let inst = self.build_unary(Opcode::Var, ty, x);
@@ -453,6 +616,10 @@ impl<'a, 'b> InstBuilder<'a, 'b> {
         self.build_binary(Opcode::St, void_ty(), x, y)
     }
 
+    pub fn st_cond(&mut self, x: Value, y: Value, cond: Value) -> Inst {
+        self.build_ternary(Opcode::StCond, void_ty(), x, y, cond)
+    }
+
     pub fn halt(&mut self) -> Inst {
         self.build_nullary(Opcode::Halt)
     }
@@ -614,6 +781,8 @@ pub enum InstData {
     ConstInt { opcode: Opcode, imm: IntValue },
     /// `a = const time imm`
     ConstTime { opcode: Opcode, imm: TimeValue },
+    /// `a = const nN imm`
+    ConstEnum { opcode: Opcode, imm: EnumValue },
     /// `opcode imm, type x`
     Array {
         opcode: Opcode,
@@ -665,11 +834,12 @@ pub enum InstData {
         args: [Value; 2],
         imms: [usize; 2],
     },
-    /// `a = reg type x (, data mode trigger)*`
+    /// `a = reg type x (control)* (, data mode trigger)*`
     Reg {
         opcode: Opcode,
         args: Vec<Value>,
         modes: Vec<RegMode>,
+        controls: Vec<RegControl>,
     },
 }
 
@@ -679,6 +849,7 @@ impl InstData {
         match *self {
             InstData::ConstInt { opcode, .. } => opcode,
             InstData::ConstTime { opcode, .. } => opcode,
+            InstData::ConstEnum { opcode, .. } => opcode,
             InstData::Array { opcode, .. } => opcode,
             InstData::Aggregate { opcode, .. } => opcode,
             InstData::Nullary { opcode, .. } => opcode,
@@ -701,6 +872,7 @@ impl InstData {
         match self {
             InstData::ConstInt { .. } => &[],
             InstData::ConstTime { .. } => &[],
+            InstData::ConstEnum { .. } => &[],
             InstData::Array { args, .. } => args,
             InstData::Aggregate { args, .. } => args,
             InstData::Nullary { .. } => &[],
@@ -734,6 +906,7 @@ impl InstData {
         match self {
             InstData::ConstInt { .. } => &mut [],
             InstData::ConstTime { .. } => &mut [],
+            InstData::ConstEnum { .. } => &mut [],
             InstData::Array { args, .. } => args,
             InstData::Aggregate { args, .. } => args,
             InstData::Nullary { .. } => &mut [],
@@ -761,11 +934,58 @@ impl InstData {
         }
     }
 
+    /// Get the role of the argument at `index` in the instruction.
+    pub fn use_kind(&self, index: usize) -> UseKind {
+        match *self {
+            InstData::Call { ins, .. } if index < ins as usize => return UseKind::Input(index),
+            InstData::Call { ins, .. } => return UseKind::Output(index - ins as usize),
+            InstData::Phi { ref bbs, .. } => return UseKind::Incoming(bbs[index]),
+            InstData::Reg {
+                ref modes,
+                ref controls,
+                ..
+            } => {
+                let n = modes.len();
+                return match index {
+                    0 => UseKind::Target,
+                    i if i <= n => UseKind::RegData(i - 1),
+                    i if i <= 2 * n => UseKind::RegTrigger(i - 1 - n),
+                    i if i <= 3 * n => UseKind::RegGate(i - 1 - 2 * n),
+                    i => UseKind::RegControl(controls[i - 1 - 3 * n]),
+                };
+            }
+            _ => (),
+        }
+        match (self.opcode(), index) {
+            (Opcode::Drv, 0)
+            | (Opcode::DrvCond, 0)
+            | (Opcode::Del, 0)
+            | (Opcode::DelCond, 0)
+            | (Opcode::St, 0)
+            | (Opcode::StCond, 0) => UseKind::Target,
+            (Opcode::Drv, 1)
+            | (Opcode::DrvCond, 1)
+            | (Opcode::Del, 1)
+            | (Opcode::DelCond, 1)
+            | (Opcode::St, 1)
+            | (Opcode::StCond, 1) => UseKind::Source,
+            (Opcode::Drv, 2) | (Opcode::DrvCond, 2) | (Opcode::Del, 2) | (Opcode::DelCond, 2) => {
+                UseKind::Delay
+            }
+            (Opcode::DrvCond, 3) | (Opcode::DelCond, 3) | (Opcode::StCond, 2) => UseKind::Condition,
+            (Opcode::BrCond, _) => UseKind::BranchCondition,
+            (Opcode::WaitTime, 0) => UseKind::WaitTime,
+            (Opcode::Wait, _) | (Opcode::WaitTime, _) => UseKind::Sensitivity,
+            _ => UseKind::Operand,
+        }
+    }
+
     /// Get the immediates of an instruction.
     pub fn imms(&self) -> &[usize] {
         match self {
             InstData::ConstInt { .. } => &[],
             InstData::ConstTime { .. } => &[],
+            InstData::ConstEnum { .. } => &[],
             InstData::Array { imms, .. } => imms,
             InstData::Aggregate { .. } => &[],
             InstData::Nullary { .. } => &[],
@@ -832,13 +1052,41 @@ impl InstData {
     /// Get the gating arguments of a register instruction.
     pub fn gating_args(&self) -> impl Iterator<Item = Option<Value>> + '_ {
         match self {
-            InstData::Reg { args, modes, .. } => &args[1 + 2 * modes.len()..],
+            InstData::Reg { args, modes, .. } => &args[1 + 2 * modes.len()..1 + 3 * modes.len()],
             _ => &[],
         }
         .iter()
         .map(|&v| if v == Value::invalid() { None } else { Some(v) })
     }
 
+    /// Get the control arguments of a register instruction.
+    pub fn control_args(&self) -> impl Iterator<Item = (RegControl, Value)> + '_ {
+        let (controls, args) = match self {
+            InstData::Reg {
+                args,
+                modes,
+                controls,
+                ..
+            } => (controls.as_slice(), &args[1 + 3 * modes.len()..]),
+            _ => (&[][..], &[][..]),
+        };
+        controls.iter().cloned().zip(args.iter().cloned())
+    }
+
+    /// Get the register controls.
+    pub fn reg_controls(&self) -> RegControls {
+        let mut ctrl = RegControls::default();
+        for (control, value) in self.control_args() {
+            match control {
+                RegControl::Init => ctrl.init = Some(value),
+                RegControl::Enable => ctrl.enable = Some(value),
+                RegControl::Clear(mode) => ctrl.clear = Some((mode, value)),
+                RegControl::Preset(mode) => ctrl.preset = Some((mode, value)),
+            }
+        }
+        ctrl
+    }
+
     /// Get the modes of a register instruction.
     pub fn mode_args(&self) -> impl Iterator<Item = RegMode> + '_ {
         match self {
@@ -868,6 +1116,7 @@ impl InstData {
         match self {
             InstData::ConstInt { .. } => &[],
             InstData::ConstTime { .. } => &[],
+            InstData::ConstEnum { .. } => &[],
             InstData::Array { .. } => &[],
             InstData::Aggregate { .. } => &[],
             InstData::Nullary { .. } => &[],
@@ -891,6 +1140,7 @@ impl InstData {
         match self {
             InstData::ConstInt { .. } => &mut [],
             InstData::ConstTime { .. } => &mut [],
+            InstData::ConstEnum { .. } => &mut [],
             InstData::Array { .. } => &mut [],
             InstData::Aggregate { .. } => &mut [],
             InstData::Nullary { .. } => &mut [],
@@ -975,6 +1225,14 @@ impl InstData {
         }
     }
 
+    /// Return the const enum constructed by this instruction.
+    pub fn get_const_enum(&self) -> Option<&EnumValue> {
+        match self {
+            InstData::ConstEnum { imm, .. } => Some(imm),
+            _ => None,
+        }
+    }
+
     /// Return the external unit being called or instantiated by this
     /// instruction.
     pub fn get_ext_unit(&self) -> Option<ExtUnit> {
@@ -1004,6 +1262,88 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// A set of annotations attached to an instruction or block.
+    ///
+    /// Most annotations restrict how passes may transform the annotated
+    /// object. Annotations of a block apply to all its instructions.
+    #[derive(Default, Serialize, Deserialize)]
+    pub struct Annotations: u8 {
+        /// Do not remove the instruction or block, even if it is unused or
+        /// unreachable.
+        const KEEP = 0b001;
+        /// Do not remove, move, merge, or rewrite the instruction or block.
+        const DONT_OPTIMIZE = 0b010;
+        /// The delay of a `drv` or `del` is inertial rather than transport: a
+        /// change cancels the pending changes to a different value, such that
+        /// pulses shorter than the delay are suppressed.
+        const INERTIAL = 0b100;
+        /// The instruction is part of a multiply-accumulate: a `umul` or
+        /// `smul` whose product is only used by an `add`, or that `add`.
+        /// Backends may implement the two as one fused operation.
+        const MAC = 0b1000;
+        /// The value of the constant does not matter: each use of it may
+        /// assume any value of its type. Unlike an `X` in simulation, the
+        /// constant simulates as the value it is written with, but passes may
+        /// substitute whichever value simplifies the logic around it.
+        const DONT_CARE = 0b10000;
+    }
+}
+
+impl Annotations {
+    /// Check whether passes must not remove the annotated object.
+    pub fn is_kept(self) -> bool {
+        self.intersects(Annotations::KEEP | Annotations::DONT_OPTIMIZE)
+    }
+
+    /// Check whether passes may transform the annotated object.
+    pub fn is_optimizable(self) -> bool {
+        !self.contains(Annotations::DONT_OPTIMIZE)
+    }
+
+    /// Check whether the delay of the annotated drive is inertial.
+    pub fn is_inertial(self) -> bool {
+        self.contains(Annotations::INERTIAL)
+    }
+
+    /// Check whether the annotated instruction is part of a
+    /// multiply-accumulate.
+    pub fn is_mac(self) -> bool {
+        self.contains(Annotations::MAC)
+    }
+
+    /// Check whether the annotated constant is a don't-care value.
+    pub fn is_dont_care(self) -> bool {
+        self.contains(Annotations::DONT_CARE)
+    }
+}
+
+impl std::fmt::Display for Annotations {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut sep = "";
+        if self.contains(Annotations::KEEP) {
+            write!(f, "#keep")?;
+            sep = " ";
+        }
+        if self.contains(Annotations::DONT_OPTIMIZE) {
+            write!(f, "{}#dont_optimize", sep)?;
+            sep = " ";
+        }
+        if self.contains(Annotations::INERTIAL) {
+            write!(f, "{}#inertial", sep)?;
+            sep = " ";
+        }
+        if self.contains(Annotations::MAC) {
+            write!(f, "{}#mac", sep)?;
+            sep = " ";
+        }
+        if self.contains(Annotations::DONT_CARE) {
+            write!(f, "{}#dont_care", sep)?;
+        }
+        Ok(())
+    }
+}
+
 /// The trigger modes for register data acquisition.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RegMode {
@@ -1031,6 +1371,80 @@ impl std::fmt::Display for RegMode {
     }
 }
 
+/// How the values of the drivers of a signal are combined.
+///
+/// Signals without a resolution, or with `LastWrite`, carry the value of the
+/// most recent drive.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Resolution {
+    /// The bitwise or of the values of all drivers.
+    WiredOr,
+    /// The bitwise and of the values of all drivers.
+    WiredAnd,
+    /// The value of the most recent drive.
+    LastWrite,
+    /// A function of signature `(T, T) T` which combines the values of two
+    /// drivers.
+    Function(UnitName),
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Resolution::WiredOr => write!(f, "wired_or"),
+            Resolution::WiredAnd => write!(f, "wired_and"),
+            Resolution::LastWrite => write!(f, "last_write"),
+            Resolution::Function(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The role in which an instruction uses a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UseKind {
+    /// A plain operand without a more specific role.
+    Operand,
+    /// The signal or pointer written by `drv`, `del`, `st`, or `reg`.
+    Target,
+    /// The value written by `drv`, `del`, or `st`.
+    Source,
+    /// The delay of a `drv` or `del`.
+    Delay,
+    /// The condition of a conditional `drv`, `del`, or `st`.
+    Condition,
+    /// The condition of a `br`.
+    BranchCondition,
+    /// The value of the nth trigger of a `reg`.
+    RegData(usize),
+    /// The trigger signal of the nth trigger of a `reg`.
+    RegTrigger(usize),
+    /// The gating condition of the nth trigger of a `reg`.
+    RegGate(usize),
+    /// A control input of a `reg`.
+    RegControl(RegControl),
+    /// A signal in the sensitivity list of a `wait`.
+    Sensitivity,
+    /// The time of a `wait`.
+    WaitTime,
+    /// The nth input argument of a `call` or `inst`.
+    Input(usize),
+    /// The nth output argument of an `inst`.
+    Output(usize),
+    /// The value flowing into a `phi` from a block.
+    Incoming(Block),
+}
+
+/// A use of a value by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Use {
+    /// The instruction using the value.
+    pub inst: Inst,
+    /// The index of the value among the instruction's arguments.
+    pub index: usize,
+    /// The role of the value in the instruction.
+    pub kind: UseKind,
+}
+
 /// The trigger for register data acquisition.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RegTrigger {
@@ -1044,6 +1458,55 @@ pub struct RegTrigger {
     pub gate: Option<Value>,
 }
 
+/// An optional control input of a register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RegControl {
+    /// The initial value of the register.
+    Init,
+    /// The enable condition of the triggers.
+    Enable,
+    /// The asynchronous clear, active at the given level.
+    Clear(RegMode),
+    /// The asynchronous preset, active at the given level.
+    Preset(RegMode),
+}
+
+impl std::fmt::Display for RegControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RegControl::Init => write!(f, "init"),
+            RegControl::Enable => write!(f, "enable"),
+            RegControl::Clear(mode) => write!(f, "clear {}", mode),
+            RegControl::Preset(mode) => write!(f, "preset {}", mode),
+        }
+    }
+}
+
+/// The optional controls of a register.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RegControls {
+    /// The value the register holds before any trigger fires.
+    pub init: Option<Value>,
+    /// The condition under which the triggers may fire.
+    pub enable: Option<Value>,
+    /// The level-sensitive asynchronous clear, which sets the register to zero.
+    pub clear: Option<(RegMode, Value)>,
+    /// The level-sensitive asynchronous preset, which sets all bits of the
+    /// register to one.
+    pub preset: Option<(RegMode, Value)>,
+}
+
+impl RegControls {
+    /// Iterate over the controls that are present, in canonical order.
+    pub fn iter(&self) -> impl Iterator<Item = (RegControl, Value)> {
+        let init = self.init.map(|v| (RegControl::Init, v));
+        let enable = self.enable.map(|v| (RegControl::Enable, v));
+        let clear = self.clear.map(|(m, v)| (RegControl::Clear(m), v));
+        let preset = self.preset.map(|(m, v)| (RegControl::Preset(m), v));
+        init.into_iter().chain(enable).chain(clear).chain(preset)
+    }
+}
+
 /// An instruction opcode.
 ///
 /// This enum represents the actual instruction, whereas `InstData` covers the
@@ -1052,6 +1515,7 @@ pub struct RegTrigger {
 pub enum Opcode {
     ConstInt,
     ConstTime,
+    ConstEnum,
     Alias,
     ArrayUniform,
     Array,
@@ -1059,6 +1523,9 @@ pub enum Opcode {
 
     Not,
     Neg,
+    Popcount,
+    Clz,
+    Ctz,
 
     Add,
     Sub,
@@ -1066,13 +1533,23 @@ pub enum Opcode {
     Or,
     Xor,
     Smul,
+    Smulh,
     Sdiv,
     Smod,
     Srem,
     Umul,
+    Umulh,
     Udiv,
     Umod,
     Urem,
+    Umin,
+    Umax,
+    Smin,
+    Smax,
+    Uaddo,
+    Saddo,
+    Usubo,
+    Ssubo,
 
     Eq,
     Neq,
@@ -1095,6 +1572,7 @@ pub enum Opcode {
     ExtSlice,
     Con,
     Del,
+    DelCond,
 
     Call,
     Inst,
@@ -1107,6 +1585,7 @@ pub enum Opcode {
     Var,
     Ld,
     St,
+    StCond,
 
     Halt,
     Ret,
@@ -1120,91 +1599,584 @@ pub enum Opcode {
 
 impl std::fmt::Display for Opcode {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Opcode::ConstInt => "const",
-                Opcode::ConstTime => "const",
-                Opcode::Alias => "alias",
-                Opcode::ArrayUniform => "array",
-                Opcode::Array => "array",
-                Opcode::Struct => "struct",
-                Opcode::Not => "not",
-                Opcode::Neg => "neg",
-                Opcode::Add => "add",
-                Opcode::Sub => "sub",
-                Opcode::And => "and",
-                Opcode::Or => "or",
-                Opcode::Xor => "xor",
-                Opcode::Smul => "smul",
-                Opcode::Sdiv => "sdiv",
-                Opcode::Smod => "smod",
-                Opcode::Srem => "srem",
-                Opcode::Umul => "umul",
-                Opcode::Udiv => "udiv",
-                Opcode::Umod => "umod",
-                Opcode::Urem => "urem",
-                Opcode::Eq => "eq",
-                Opcode::Neq => "neq",
-                Opcode::Slt => "slt",
-                Opcode::Sgt => "sgt",
-                Opcode::Sle => "sle",
-                Opcode::Sge => "sge",
-                Opcode::Ult => "ult",
-                Opcode::Ugt => "ugt",
-                Opcode::Ule => "ule",
-                Opcode::Uge => "uge",
-                Opcode::Shl => "shl",
-                Opcode::Shr => "shr",
-                Opcode::Mux => "mux",
-                Opcode::Reg => "reg",
-                Opcode::InsField => "insf",
-                Opcode::InsSlice => "inss",
-                Opcode::ExtField => "extf",
-                Opcode::ExtSlice => "exts",
-                Opcode::Con => "con",
-                Opcode::Del => "del",
-                Opcode::Call => "call",
-                Opcode::Inst => "inst",
-                Opcode::Sig => "sig",
-                Opcode::Drv => "drv",
-                Opcode::DrvCond => "drv",
-                Opcode::Prb => "prb",
-                Opcode::Var => "var",
-                Opcode::Ld => "ld",
-                Opcode::St => "st",
-                Opcode::Halt => "halt",
-                Opcode::Ret => "ret",
-                Opcode::RetValue => "ret",
-                Opcode::Phi => "phi",
-                Opcode::Br => "br",
-                Opcode::BrCond => "br",
-                Opcode::Wait => "wait",
-                Opcode::WaitTime => "wait",
-            }
-        )
+        write!(f, "{}", self.describe().mnemonic)
+    }
+}
+
+/// A description of an opcode, for documentation and diagnostics.
+///
+/// Returned by `Opcode::describe()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeDescription {
+    /// The mnemonic of the instruction in assembly, e.g. `drv`.
+    pub mnemonic: &'static str,
+    /// The kinds of value operands the instruction expects, in order.
+    pub operands: &'static [&'static str],
+    /// Whether the last operand may be repeated any number of times,
+    /// including zero.
+    pub variadic: bool,
+    /// A short summary of the semantics of the instruction.
+    pub summary: &'static str,
+}
+
+impl OpcodeDescription {
+    /// Return the minimum number of value operands.
+    pub fn min_operands(&self) -> usize {
+        self.operands.len() - self.variadic as usize
+    }
+
+    /// Return the maximum number of value operands, or `None` if unbounded.
+    pub fn max_operands(&self) -> Option<usize> {
+        if self.variadic {
+            None
+        } else {
+            Some(self.operands.len())
+        }
+    }
+}
+
+impl std::fmt::Display for OpcodeDescription {
+    /// Format the operands the instruction expects, e.g. "`drv` expects
+    /// signal, value, time".
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "`{}` expects ", self.mnemonic)?;
+        if self.operands.is_empty() {
+            return write!(f, "no operands");
+        }
+        write!(f, "{}", self.operands.join(", "))?;
+        if self.variadic {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+bitflags! {
+    /// A set of flags describing the semantics of an opcode.
+    #[derive(Default)]
+    pub struct OpcodeFlags: u16 {
+        /// The result depends only on the arguments, and there are no side
+        /// effects.
+        const PURE = 0b000001;
+        /// The instruction has an effect beyond producing its result.
+        const SIDE_EFFECTS = 0b000010;
+        /// The instruction terminates a basic block.
+        const TERMINATOR = 0b000100;
+        /// The instruction suspends execution.
+        const TEMPORAL = 0b001000;
+        /// The arguments may be swapped without changing the result.
+        const COMMUTATIVE = 0b010000;
+        /// Nested applications may be regrouped without changing the result.
+        const ASSOCIATIVE = 0b100000;
+        /// The instruction may read from memory.
+        const READS_MEMORY = 0b1000000;
+        /// The instruction may write to memory.
+        const WRITES_MEMORY = 0b10000000;
+        /// The instruction may observe the value of signals.
+        const READS_SIGNALS = 0b100000000;
+        /// The instruction may change the value of signals.
+        const WRITES_SIGNALS = 0b1000000000;
+        /// The instruction creates a new signal or memory location on every
+        /// execution.
+        const ALLOCATES = 0b10000000000;
+    }
+}
+
+/// The properties of an opcode.
+///
+/// Returned by `Opcode::properties()`. Passes should consult these rather than
+/// matching on individual opcodes, such that new opcodes are handled properly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeProperties {
+    /// The units in which the instruction may appear.
+    pub valid_in: UnitFlags,
+    /// The semantic flags of the instruction.
+    pub flags: OpcodeFlags,
+}
+
+impl OpcodeProperties {
+    /// Check if the instruction is pure.
+    pub fn is_pure(&self) -> bool {
+        self.flags.contains(OpcodeFlags::PURE)
+    }
+
+    /// Check if the instruction has side effects.
+    pub fn has_side_effects(&self) -> bool {
+        self.flags.contains(OpcodeFlags::SIDE_EFFECTS)
+    }
+
+    /// Check if the instruction is a terminator.
+    pub fn is_terminator(&self) -> bool {
+        self.flags.contains(OpcodeFlags::TERMINATOR)
+    }
+
+    /// Check if the instruction is temporal.
+    pub fn is_temporal(&self) -> bool {
+        self.flags.contains(OpcodeFlags::TEMPORAL)
+    }
+
+    /// Check if the instruction is commutative.
+    pub fn is_commutative(&self) -> bool {
+        self.flags.contains(OpcodeFlags::COMMUTATIVE)
+    }
+
+    /// Check if the instruction is associative.
+    pub fn is_associative(&self) -> bool {
+        self.flags.contains(OpcodeFlags::ASSOCIATIVE)
+    }
+
+    /// Check if the instruction may read from memory.
+    pub fn may_read_memory(&self) -> bool {
+        self.flags.contains(OpcodeFlags::READS_MEMORY)
+    }
+
+    /// Check if the instruction may write to memory.
+    pub fn may_write_memory(&self) -> bool {
+        self.flags.contains(OpcodeFlags::WRITES_MEMORY)
+    }
+
+    /// Check if the instruction may observe the value of signals.
+    pub fn may_read_signals(&self) -> bool {
+        self.flags.contains(OpcodeFlags::READS_SIGNALS)
+    }
+
+    /// Check if the instruction may change the value of signals.
+    pub fn may_write_signals(&self) -> bool {
+        self.flags.contains(OpcodeFlags::WRITES_SIGNALS)
+    }
+
+    /// Check if the instruction creates a new signal or memory location.
+    pub fn allocates(&self) -> bool {
+        self.flags.contains(OpcodeFlags::ALLOCATES)
     }
 }
 
 impl Opcode {
+    /// Return the properties of this instruction.
+    ///
+    /// This is the single table that describes the semantics of each opcode.
+    pub fn properties(self) -> OpcodeProperties {
+        let all = UnitFlags::ALL;
+        let (f, p, e) = (UnitFlags::FUNCTION, UnitFlags::PROCESS, UnitFlags::ENTITY);
+        let none = OpcodeFlags::empty();
+        let pure = OpcodeFlags::PURE;
+        let effect = OpcodeFlags::SIDE_EFFECTS;
+        let term = OpcodeFlags::TERMINATOR;
+        let temporal = term | OpcodeFlags::TEMPORAL;
+        let comm = pure | OpcodeFlags::COMMUTATIVE;
+        let assoc = comm | OpcodeFlags::ASSOCIATIVE;
+        let alloc = OpcodeFlags::ALLOCATES;
+        let ld = OpcodeFlags::READS_MEMORY;
+        let st = effect | OpcodeFlags::WRITES_MEMORY;
+        let prb = OpcodeFlags::READS_SIGNALS;
+        let drv = effect | OpcodeFlags::WRITES_SIGNALS;
+        let (valid_in, flags) = match self {
+            Opcode::ConstInt => (all, pure),
+            Opcode::ConstTime => (all, pure),
+            Opcode::ConstEnum => (all, pure),
+            Opcode::Alias => (all, pure),
+            Opcode::ArrayUniform => (all, pure),
+            Opcode::Array => (all, pure),
+            Opcode::Struct => (all, pure),
+            Opcode::Not => (all, pure),
+            Opcode::Neg => (all, pure),
+            Opcode::Popcount => (all, pure),
+            Opcode::Clz => (all, pure),
+            Opcode::Ctz => (all, pure),
+            Opcode::Add => (all, assoc),
+            Opcode::Sub => (all, pure),
+            Opcode::And => (all, assoc),
+            Opcode::Or => (all, assoc),
+            Opcode::Xor => (all, assoc),
+            Opcode::Smul => (all, assoc),
+            Opcode::Smulh => (all, comm),
+            Opcode::Sdiv => (all, pure),
+            Opcode::Smod => (all, pure),
+            Opcode::Srem => (all, pure),
+            Opcode::Umul => (all, assoc),
+            Opcode::Umulh => (all, comm),
+            Opcode::Udiv => (all, pure),
+            Opcode::Umod => (all, pure),
+            Opcode::Urem => (all, pure),
+            Opcode::Umin => (all, assoc),
+            Opcode::Umax => (all, assoc),
+            Opcode::Smin => (all, assoc),
+            Opcode::Smax => (all, assoc),
+            Opcode::Uaddo => (all, comm),
+            Opcode::Saddo => (all, comm),
+            Opcode::Usubo => (all, pure),
+            Opcode::Ssubo => (all, pure),
+            Opcode::Eq => (all, comm),
+            Opcode::Neq => (all, comm),
+            Opcode::Slt => (all, pure),
+            Opcode::Sgt => (all, pure),
+            Opcode::Sle => (all, pure),
+            Opcode::Sge => (all, pure),
+            Opcode::Ult => (all, pure),
+            Opcode::Ugt => (all, pure),
+            Opcode::Ule => (all, pure),
+            Opcode::Uge => (all, pure),
+            Opcode::Shl => (all, pure),
+            Opcode::Shr => (all, pure),
+            Opcode::Mux => (all, pure),
+            Opcode::Reg => (e, drv),
+            Opcode::InsField => (all, pure),
+            Opcode::InsSlice => (all, pure),
+            Opcode::ExtField => (all, pure),
+            Opcode::ExtSlice => (all, pure),
+            Opcode::Con => (e, drv | prb),
+            Opcode::Del => (e, drv | prb),
+            Opcode::DelCond => (e, drv | prb),
+            // Functions cannot interact with signals or suspend execution, but
+            // may access memory through pointer arguments. Calls to functions
+            // inferred to be pure are treated as such, see `FunctionPurity`.
+            Opcode::Call => (all, ld | st),
+            Opcode::Inst => (e, drv | prb),
+            Opcode::Sig => (all, alloc),
+            Opcode::Prb => (all, prb),
+            Opcode::Drv => (all, drv),
+            Opcode::DrvCond => (all, drv),
+            Opcode::Var => (all, alloc),
+            Opcode::Ld => (all, ld),
+            Opcode::St => (all, st),
+            Opcode::StCond => (all, st),
+            Opcode::Halt => (p | e, temporal),
+            Opcode::Ret => (f, term),
+            Opcode::RetValue => (f, term),
+            Opcode::Phi => (f | p, none),
+            Opcode::Br => (f | p, term),
+            Opcode::BrCond => (f | p, term),
+            Opcode::Wait => (p, temporal | prb),
+            Opcode::WaitTime => (p, temporal),
+        };
+        OpcodeProperties { valid_in, flags }
+    }
+
+    /// All opcodes.
+    pub const ALL: [Opcode; 74] = [
+        Opcode::ConstInt,
+        Opcode::ConstTime,
+        Opcode::ConstEnum,
+        Opcode::Alias,
+        Opcode::ArrayUniform,
+        Opcode::Array,
+        Opcode::Struct,
+        Opcode::Not,
+        Opcode::Neg,
+        Opcode::Popcount,
+        Opcode::Clz,
+        Opcode::Ctz,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::And,
+        Opcode::Or,
+        Opcode::Xor,
+        Opcode::Smul,
+        Opcode::Smulh,
+        Opcode::Sdiv,
+        Opcode::Smod,
+        Opcode::Srem,
+        Opcode::Umul,
+        Opcode::Umulh,
+        Opcode::Udiv,
+        Opcode::Umod,
+        Opcode::Urem,
+        Opcode::Umin,
+        Opcode::Umax,
+        Opcode::Smin,
+        Opcode::Smax,
+        Opcode::Uaddo,
+        Opcode::Saddo,
+        Opcode::Usubo,
+        Opcode::Ssubo,
+        Opcode::Eq,
+        Opcode::Neq,
+        Opcode::Slt,
+        Opcode::Sgt,
+        Opcode::Sle,
+        Opcode::Sge,
+        Opcode::Ult,
+        Opcode::Ugt,
+        Opcode::Ule,
+        Opcode::Uge,
+        Opcode::Shl,
+        Opcode::Shr,
+        Opcode::Mux,
+        Opcode::Reg,
+        Opcode::InsField,
+        Opcode::InsSlice,
+        Opcode::ExtField,
+        Opcode::ExtSlice,
+        Opcode::Con,
+        Opcode::Del,
+        Opcode::DelCond,
+        Opcode::Call,
+        Opcode::Inst,
+        Opcode::Sig,
+        Opcode::Prb,
+        Opcode::Drv,
+        Opcode::DrvCond,
+        Opcode::Var,
+        Opcode::Ld,
+        Opcode::St,
+        Opcode::StCond,
+        Opcode::Halt,
+        Opcode::Ret,
+        Opcode::RetValue,
+        Opcode::Phi,
+        Opcode::Br,
+        Opcode::BrCond,
+        Opcode::Wait,
+        Opcode::WaitTime,
+    ];
+
+    /// Return a description of this instruction.
+    ///
+    /// This is the single table that documents the mnemonic, operands, and
+    /// semantics of each opcode.
+    pub fn describe(self) -> OpcodeDescription {
+        let (mnemonic, operands, variadic, summary): (_, &[_], _, _) = match self {
+            Opcode::ConstInt => ("const", &[], false, "Integer constant."),
+            Opcode::ConstTime => ("const", &[], false, "Time constant."),
+            Opcode::ConstEnum => ("const", &[], false, "Enum constant."),
+            Opcode::Alias => ("alias", &["value"], false, "Another name for a value."),
+            Opcode::ArrayUniform => (
+                "array",
+                &["value"],
+                false,
+                "Array with all elements set to the same value.",
+            ),
+            Opcode::Array => ("array", &["value"], true, "Array of the given elements."),
+            Opcode::Struct => ("struct", &["value"], true, "Struct of the given fields."),
+            Opcode::Not => ("not", &["value"], false, "Bitwise complement."),
+            Opcode::Neg => ("neg", &["value"], false, "Two's complement negation."),
+            Opcode::Popcount => ("popcount", &["value"], false, "Number of one bits."),
+            Opcode::Clz => ("clz", &["value"], false, "Number of leading zero bits."),
+            Opcode::Ctz => ("ctz", &["value"], false, "Number of trailing zero bits."),
+            Opcode::Add => ("add", &["value", "value"], false, "Addition."),
+            Opcode::Sub => ("sub", &["value", "value"], false, "Subtraction."),
+            Opcode::And => ("and", &["value", "value"], false, "Bitwise and."),
+            Opcode::Or => ("or", &["value", "value"], false, "Bitwise or."),
+            Opcode::Xor => ("xor", &["value", "value"], false, "Bitwise exclusive or."),
+            Opcode::Smul => ("smul", &["value", "value"], false, "Signed multiplication."),
+            Opcode::Smulh => (
+                "smulh",
+                &["value", "value"],
+                false,
+                "Upper half of a signed multiplication.",
+            ),
+            Opcode::Sdiv => ("sdiv", &["value", "value"], false, "Signed division."),
+            Opcode::Smod => ("smod", &["value", "value"], false, "Signed modulo."),
+            Opcode::Srem => ("srem", &["value", "value"], false, "Signed remainder."),
+            Opcode::Umul => (
+                "umul",
+                &["value", "value"],
+                false,
+                "Unsigned multiplication.",
+            ),
+            Opcode::Umulh => (
+                "umulh",
+                &["value", "value"],
+                false,
+                "Upper half of an unsigned multiplication.",
+            ),
+            Opcode::Udiv => ("udiv", &["value", "value"], false, "Unsigned division."),
+            Opcode::Umod => ("umod", &["value", "value"], false, "Unsigned modulo."),
+            Opcode::Urem => ("urem", &["value", "value"], false, "Unsigned remainder."),
+            Opcode::Umin => ("umin", &["value", "value"], false, "Unsigned minimum."),
+            Opcode::Umax => ("umax", &["value", "value"], false, "Unsigned maximum."),
+            Opcode::Smin => ("smin", &["value", "value"], false, "Signed minimum."),
+            Opcode::Smax => ("smax", &["value", "value"], false, "Signed maximum."),
+            Opcode::Uaddo => (
+                "uaddo",
+                &["value", "value"],
+                false,
+                "Unsigned addition with carry out.",
+            ),
+            Opcode::Saddo => (
+                "saddo",
+                &["value", "value"],
+                false,
+                "Signed addition with overflow flag.",
+            ),
+            Opcode::Usubo => (
+                "usubo",
+                &["value", "value"],
+                false,
+                "Unsigned subtraction with borrow out.",
+            ),
+            Opcode::Ssubo => (
+                "ssubo",
+                &["value", "value"],
+                false,
+                "Signed subtraction with overflow flag.",
+            ),
+            Opcode::Eq => ("eq", &["value", "value"], false, "Equality."),
+            Opcode::Neq => ("neq", &["value", "value"], false, "Inequality."),
+            Opcode::Slt => ("slt", &["value", "value"], false, "Signed less than."),
+            Opcode::Sgt => ("sgt", &["value", "value"], false, "Signed greater than."),
+            Opcode::Sle => (
+                "sle",
+                &["value", "value"],
+                false,
+                "Signed less than or equal.",
+            ),
+            Opcode::Sge => (
+                "sge",
+                &["value", "value"],
+                false,
+                "Signed greater than or equal.",
+            ),
+            Opcode::Ult => ("ult", &["value", "value"], false, "Unsigned less than."),
+            Opcode::Ugt => ("ugt", &["value", "value"], false, "Unsigned greater than."),
+            Opcode::Ule => (
+                "ule",
+                &["value", "value"],
+                false,
+                "Unsigned less than or equal.",
+            ),
+            Opcode::Uge => (
+                "uge",
+                &["value", "value"],
+                false,
+                "Unsigned greater than or equal.",
+            ),
+            Opcode::Shl => (
+                "shl",
+                &["value", "value", "integer"],
+                false,
+                "Shift left, filling in bits from the hidden value.",
+            ),
+            Opcode::Shr => (
+                "shr",
+                &["value", "value", "integer"],
+                false,
+                "Shift right, filling in bits from the hidden value.",
+            ),
+            Opcode::Mux => (
+                "mux",
+                &["array", "integer"],
+                false,
+                "Select an element of an array.",
+            ),
+            Opcode::Reg => (
+                "reg",
+                &["signal", "value"],
+                true,
+                "Store a value in a signal when a trigger fires.",
+            ),
+            Opcode::InsField => (
+                "insf",
+                &["value", "value"],
+                false,
+                "Replace a field or element.",
+            ),
+            Opcode::InsSlice => ("inss", &["value", "value"], false, "Replace a slice."),
+            Opcode::ExtField => ("extf", &["value"], false, "Extract a field or element."),
+            Opcode::ExtSlice => ("exts", &["value"], false, "Extract a slice."),
+            Opcode::Con => ("con", &["signal", "signal"], false, "Connect two signals."),
+            Opcode::Del => (
+                "del",
+                &["signal", "signal", "time"],
+                false,
+                "Drive a signal with the delayed value of another.",
+            ),
+            Opcode::DelCond => (
+                "del",
+                &["signal", "signal", "time", "i1"],
+                false,
+                "Drive a signal with the delayed value of another, if a condition holds.",
+            ),
+            Opcode::Call => ("call", &["value"], true, "Call a function."),
+            Opcode::Inst => (
+                "inst",
+                &["signal"],
+                true,
+                "Instantiate an entity or process.",
+            ),
+            Opcode::Sig => (
+                "sig",
+                &["value"],
+                false,
+                "Create a signal with an initial value.",
+            ),
+            Opcode::Prb => (
+                "prb",
+                &["signal"],
+                false,
+                "Read the current value of a signal.",
+            ),
+            Opcode::Drv => (
+                "drv",
+                &["signal", "value", "time"],
+                false,
+                "Drive a signal after a delay.",
+            ),
+            Opcode::DrvCond => (
+                "drv",
+                &["signal", "value", "time", "i1"],
+                false,
+                "Drive a signal after a delay, if a condition holds.",
+            ),
+            Opcode::Var => (
+                "var",
+                &["value"],
+                false,
+                "Allocate memory with an initial value.",
+            ),
+            Opcode::Ld => ("ld", &["pointer"], false, "Load a value from memory."),
+            Opcode::St => (
+                "st",
+                &["pointer", "value"],
+                false,
+                "Store a value to memory.",
+            ),
+            Opcode::StCond => (
+                "st",
+                &["pointer", "value", "i1"],
+                false,
+                "Store a value to memory, if a condition holds.",
+            ),
+            Opcode::Halt => ("halt", &[], false, "Suspend execution forever."),
+            Opcode::Ret => ("ret", &[], false, "Return from a function."),
+            Opcode::RetValue => ("ret", &["value"], false, "Return a value from a function."),
+            Opcode::Phi => (
+                "phi",
+                &["value"],
+                true,
+                "Select a value depending on the predecessor block.",
+            ),
+            Opcode::Br => ("br", &[], false, "Branch to a block."),
+            Opcode::BrCond => (
+                "br",
+                &["i1"],
+                false,
+                "Branch to one of two blocks depending on a condition.",
+            ),
+            Opcode::Wait => (
+                "wait",
+                &["signal"],
+                true,
+                "Suspend execution until a signal changes.",
+            ),
+            Opcode::WaitTime => (
+                "wait",
+                &["time", "signal"],
+                true,
+                "Suspend execution until a signal changes or a time has passed.",
+            ),
+        };
+        OpcodeDescription {
+            mnemonic,
+            operands,
+            variadic,
+            summary,
+        }
+    }
+
     /// Return a set of flags where this instruction is valid.
     pub fn valid_in(self) -> UnitFlags {
-        match self {
-            Opcode::Halt => UnitFlags::PROCESS | UnitFlags::ENTITY,
-            Opcode::Wait => UnitFlags::PROCESS,
-            Opcode::WaitTime => UnitFlags::PROCESS,
-            Opcode::Ret => UnitFlags::FUNCTION,
-            Opcode::RetValue => UnitFlags::FUNCTION,
-            Opcode::Phi => UnitFlags::FUNCTION | UnitFlags::PROCESS,
-            Opcode::Br => UnitFlags::FUNCTION | UnitFlags::PROCESS,
-            Opcode::BrCond => UnitFlags::FUNCTION | UnitFlags::PROCESS,
-            Opcode::Con => UnitFlags::ENTITY,
-            Opcode::Del => UnitFlags::ENTITY,
-            Opcode::Reg => UnitFlags::ENTITY,
-            Opcode::Inst => UnitFlags::ENTITY,
-            _ => UnitFlags::ALL,
-        }
+        self.properties().valid_in
     }
 
     /// Check if this instruction can appear in a `Function`.
@@ -1227,6 +2199,7 @@ impl Opcode {
         match self {
             Opcode::ConstInt => true,
             Opcode::ConstTime => true,
+            Opcode::ConstEnum => true,
             _ => false,
         }
     }
@@ -1241,16 +2214,85 @@ impl Opcode {
 
     /// Check if this instruction is a terminator.
     pub fn is_terminator(self) -> bool {
-        match self {
-            Opcode::Halt
-            | Opcode::Ret
-            | Opcode::RetValue
-            | Opcode::Br
-            | Opcode::BrCond
-            | Opcode::Wait
-            | Opcode::WaitTime => true,
-            _ => false,
-        }
+        self.properties().is_terminator()
+    }
+
+    /// Check if this instruction is pure.
+    ///
+    /// Pure instructions have no side effects and their result only depends
+    /// on their arguments. They may be freely moved, shared, and removed.
+    pub fn is_pure(self) -> bool {
+        self.properties().is_pure()
+    }
+
+    /// Check if this instruction has side effects.
+    pub fn has_side_effects(self) -> bool {
+        self.properties().has_side_effects()
+    }
+
+    /// Check if this instruction may read from memory.
+    pub fn may_read_memory(self) -> bool {
+        self.properties().may_read_memory()
+    }
+
+    /// Check if this instruction may write to memory.
+    pub fn may_write_memory(self) -> bool {
+        self.properties().may_write_memory()
+    }
+
+    /// Check if this instruction may observe the value of signals.
+    pub fn may_read_signals(self) -> bool {
+        self.properties().may_read_signals()
+    }
+
+    /// Check if this instruction may change the value of signals.
+    pub fn may_write_signals(self) -> bool {
+        self.properties().may_write_signals()
+    }
+
+    /// Check if this instruction creates, reads, or changes signals.
+    pub fn touches_signals(self) -> bool {
+        self == Opcode::Sig || self.may_read_signals() || self.may_write_signals()
+    }
+
+    /// Check if this instruction creates a new signal or memory location on
+    /// every execution.
+    ///
+    /// The results of two such instructions are never interchangeable, even
+    /// if their arguments are identical.
+    pub fn allocates(self) -> bool {
+        self.properties().allocates()
+    }
+
+    /// Check if this instruction may be executed speculatively.
+    ///
+    /// This is the case for instructions without side effects which do not
+    /// allocate, terminate a block, or depend on their position in the
+    /// control flow.
+    pub fn is_speculatable(self) -> bool {
+        let props = self.properties();
+        !props.has_side_effects() && !props.allocates() && !props.is_terminator() && !self.is_phi()
+    }
+
+    /// Check if this instruction is commutative.
+    pub fn is_commutative(self) -> bool {
+        self.properties().is_commutative()
+    }
+
+    /// Check if this instruction is associative.
+    pub fn is_associative(self) -> bool {
+        self.properties().is_associative()
+    }
+
+    /// Check if this is an arithmetic instruction with an overflow flag.
+    ///
+    /// These instructions return a `{iN, i1}` struct of the result and the
+    /// carry, borrow, or overflow flag.
+    pub fn is_overflow(self) -> bool {
+        matches!(
+            self,
+            Opcode::Uaddo | Opcode::Saddo | Opcode::Usubo | Opcode::Ssubo
+        )
     }
 
     /// Check if this is a return instruction.
@@ -1261,13 +2303,40 @@ impl Opcode {
         }
     }
 
-    /// Check if this is a temporal instruction.
-    pub fn is_temporal(self) -> bool {
+    /// Check if this instruction is the conditional form of a side-effecting
+    /// instruction.
+    ///
+    /// Conditional instructions carry an `i1` condition as their last
+    /// argument, and act as a no-op if the condition is 0.
+    pub fn is_conditional(self) -> bool {
+        self.unconditional().is_some()
+    }
+
+    /// Return the conditional form of this instruction, if it has one.
+    pub fn conditional(self) -> Option<Opcode> {
         match self {
-            Opcode::Halt | Opcode::Wait | Opcode::WaitTime => true,
-            _ => false,
+            Opcode::Drv => Some(Opcode::DrvCond),
+            Opcode::St => Some(Opcode::StCond),
+            Opcode::Del => Some(Opcode::DelCond),
+            _ => None,
         }
     }
+
+    /// Return the unconditional form of this instruction, if it is
+    /// conditional.
+    pub fn unconditional(self) -> Option<Opcode> {
+        match self {
+            Opcode::DrvCond => Some(Opcode::Drv),
+            Opcode::StCond => Some(Opcode::St),
+            Opcode::DelCond => Some(Opcode::Del),
+            _ => None,
+        }
+    }
+
+    /// Check if this is a temporal instruction.
+    pub fn is_temporal(self) -> bool {
+        self.properties().is_temporal()
+    }
 }
 
 impl Inst {
@@ -1322,6 +2391,9 @@ impl std::fmt::Display for InstDumper<'_> {
             }
         } else if let InstData::Reg { .. } = *data {
             write!(f, " {}", data.args()[0])?;
+            for (control, arg) in data.control_args() {
+                write!(f, " {} {}", control, arg.dump(&unit))?;
+            }
             for arg in data.data_args() {
                 write!(f, ", {}", arg.dump(&unit))?;
             }
@@ -1367,6 +2439,7 @@ impl std::fmt::Display for InstDumper<'_> {
             match data {
                 InstData::ConstInt { imm, .. } => write!(f, " {}", imm.value)?,
                 InstData::ConstTime { imm, .. } => write!(f, " {}", imm)?,
+                InstData::ConstEnum { imm, .. } => write!(f, " {}", imm.value)?,
                 InstData::Array { imms, .. } => write!(f, ", {}", imms[0])?,
                 _ => (),
             }
@@ -1384,3 +2457,79 @@ fn with_unpacked_sigptr(ty: Type, f: impl FnOnce(Type) -> Type) -> Type {
         f(ty)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effect_classification() {
+        let ops = [
+            Opcode::Add,
+            Opcode::Call,
+            Opcode::Sig,
+            Opcode::Prb,
+            Opcode::Drv,
+            Opcode::Con,
+            Opcode::Var,
+            Opcode::Ld,
+            Opcode::St,
+            Opcode::Wait,
+            Opcode::Phi,
+        ];
+        for &op in &ops {
+            let touches = op.may_read_memory()
+                || op.may_write_memory()
+                || op.touches_signals()
+                || op.allocates();
+            assert!(!(op.is_pure() && touches), "{} is pure", op);
+            if op.may_write_memory() || op.may_write_signals() {
+                assert!(op.has_side_effects(), "{} writes without side effects", op);
+            }
+        }
+        let speculatable: Vec<_> = ops.iter().filter(|op| op.is_speculatable()).collect();
+        assert_eq!(speculatable, vec![&Opcode::Add, &Opcode::Prb, &Opcode::Ld]);
+        assert!(Opcode::Sig.touches_signals() && !Opcode::Sig.may_read_signals());
+        assert!(Opcode::Con.may_read_signals() && Opcode::Con.may_write_signals());
+    }
+
+    #[test]
+    fn descriptions() {
+        let drv = Opcode::Drv.describe();
+        assert_eq!(drv.to_string(), "`drv` expects signal, value, time");
+        assert_eq!((drv.min_operands(), drv.max_operands()), (3, Some(3)));
+        let wait = Opcode::WaitTime.describe();
+        assert_eq!(wait.to_string(), "`wait` expects time, signal...");
+        assert_eq!((wait.min_operands(), wait.max_operands()), (1, None));
+        assert_eq!(
+            Opcode::Halt.describe().to_string(),
+            "`halt` expects no operands"
+        );
+        for op in &Opcode::ALL {
+            assert_eq!(op.to_string(), op.describe().mnemonic);
+            assert!(!op.describe().summary.is_empty());
+        }
+    }
+
+    #[test]
+    fn parse_error_describes_operands() {
+        let err = crate::assembly::parse_module(
+            "
+            entity @foo (i8$ %x) -> () {
+                %t = const time 0s
+                drv i8$ %x, %t
+            }
+            ",
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.ends_with(
+                "note: `drv` expects signal, value, time\n\
+                 note: `drv` expects signal, value, time, i1"
+            ),
+            "{}",
+            err
+        );
+    }
+}
@@ -75,11 +75,22 @@ pub enum ValueData {
     /// The invalid value placeholder.
     Invalid,
     /// The value is the result of an instruction.
-    Inst { ty: Type, inst: Inst },
+    Inst {
+        #[serde(deserialize_with = "crate::ty::deserialize")]
+        ty: Type,
+        inst: Inst,
+    },
     /// The value is an argument of the `Function`, `Process`, or `Entity`.
-    Arg { ty: Type, arg: Arg },
+    Arg {
+        #[serde(deserialize_with = "crate::ty::deserialize")]
+        ty: Type,
+        arg: Arg,
+    },
     /// The value is a placeholder. Used during PHI node construction.
-    Placeholder { ty: Type },
+    Placeholder {
+        #[serde(deserialize_with = "crate::ty::deserialize")]
+        ty: Type,
+    },
 }
 
 impl ValueData {
@@ -115,6 +126,10 @@ pub struct ExtUnitData {
     pub name: UnitName,
     /// The signature of the referenced unit.
     pub sig: Signature,
+    /// Whether the referenced unit is a pure function, as inferred by
+    /// `FunctionPurity` when the module was last linked.
+    #[serde(default)]
+    pub pure: bool,
 }
 
 impl Default for ExtUnitData {
@@ -122,6 +137,7 @@ impl Default for ExtUnitData {
         ExtUnitData {
             name: UnitName::Anonymous(0),
             sig: Signature::default(),
+            pure: false,
         }
     }
 }
@@ -8,19 +8,26 @@
 //! ingested by the reader and emitted by the writer.
 
 use crate::{
+    analysis::FunctionPurity,
     impl_table_key,
-    ir::{ExtUnit, Signature, Unit, UnitBuilder, UnitData, UnitName},
+    ir::{Arg, ExtUnit, Resolution, Signature, Unit, UnitBuilder, UnitData, UnitName},
     table::{PrimaryTable, TableKey},
+    ty::Type,
     verifier::Verifier,
 };
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
-use std::collections::{BTreeSet, HashMap};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 /// A module.
 ///
 /// This is the root node of an LLHD intermediate representation. Contains
-/// `Function`, `Process`, and `Entity` declarations and definitions.
-#[derive(Serialize, Deserialize)]
+/// `Function`, `Process`, and `Entity` declarations and definitions. Cloning a
+/// module takes a snapshot of it, for example to undo a transformation.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Module {
     /// The units in this module.
     pub(crate) units: PrimaryTable<UnitId, UnitData>,
@@ -30,13 +37,54 @@ pub struct Module {
     pub(crate) decls: PrimaryTable<DeclId, DeclData>,
     /// The order of declarations in the module.
     decl_order: BTreeSet<DeclId>,
+    /// The named types in this module, in definition order.
+    types: Vec<TypeDef>,
     /// The local link table. Maps an external unit declared within a unit to a
-    /// unit in the module.
-    link_table: Option<HashMap<(UnitId, ExtUnit), LinkedUnit>>,
+    /// unit in the module. Kept across modifications, such that `relink` only
+    /// has to resolve the references affected by them.
+    link_table: HashMap<(UnitId, ExtUnit), LinkedUnit>,
+    /// Whether the link table is up to date.
+    linked: bool,
     /// The location of units in the input file. If the module was read from a
     /// file, this table *may* contain additional hints on the byte offsets
     /// where the units were located.
     location_hints: HashMap<UnitId, usize>,
+    /// The symbol table. Maps the name of each unit and declaration to the
+    /// unit it refers to, with definitions taking precedence.
+    symbols: HashMap<UnitName, LinkedUnit>,
+    /// The aliases registered in the module. Maps an alternative name to the
+    /// name of the unit it refers to.
+    aliases: HashMap<UnitName, UnitName>,
+    /// Whether units or declarations may have been renamed through `IndexMut`
+    /// since the symbol table was last rebuilt.
+    #[serde(skip)]
+    symbols_stale: bool,
+    /// Whether a `UnitBuilder` handed out by the module may have renamed its
+    /// unit since the symbol table was last rebuilt, see `UnitBuilder::data`.
+    #[serde(skip)]
+    units_renamed: RenameFlag,
+    /// The units which have been added or modified since the last call to
+    /// `clear_dirty`.
+    #[serde(skip)]
+    dirty: BTreeSet<UnitId>,
+    /// The units referring to each name, including the names passed through
+    /// while resolving aliases. Used to find the references that a change to
+    /// a name affects.
+    #[serde(skip)]
+    link_deps: HashMap<UnitName, BTreeSet<UnitId>>,
+    /// Whether `link_deps` covers all references in the module.
+    #[serde(skip)]
+    link_deps_valid: bool,
+    /// The units whose references have to be resolved again.
+    #[serde(skip)]
+    relink_units: BTreeSet<UnitId>,
+    /// The names which may refer to a different unit or signature than when
+    /// the module was last linked.
+    #[serde(skip)]
+    relink_names: HashSet<UnitName>,
+    /// The pure functions, as inferred when the module was last linked.
+    #[serde(skip)]
+    purity: FunctionPurity,
 }
 
 impl Module {
@@ -47,8 +95,20 @@ impl Module {
             unit_order: BTreeSet::new(),
             decls: PrimaryTable::new(),
             decl_order: BTreeSet::new(),
-            link_table: None,
+            types: Vec::new(),
+            link_table: Default::default(),
+            linked: false,
             location_hints: Default::default(),
+            symbols: Default::default(),
+            aliases: Default::default(),
+            symbols_stale: false,
+            units_renamed: Default::default(),
+            dirty: BTreeSet::new(),
+            link_deps: Default::default(),
+            link_deps_valid: false,
+            relink_units: BTreeSet::new(),
+            relink_names: HashSet::new(),
+            purity: Default::default(),
         }
     }
 
@@ -59,16 +119,35 @@ impl Module {
 
     /// Add a unit to the module.
     pub fn add_unit(&mut self, data: UnitData) -> UnitId {
+        let name = data.name.clone();
         let unit = self.units.add(data);
         self.unit_order.insert(unit);
-        self.link_table = None;
+        self.symbols.insert(name.clone(), LinkedUnit::Def(unit));
+        self.unlink_name(name);
+        self.unlink_unit(unit);
+        self.dirty.insert(unit);
         unit
     }
 
     /// Remove a unit from the module.
     pub fn remove_unit(&mut self, unit: UnitId) {
+        let name = self.units[unit].name.clone();
         self.units.remove(unit);
         self.unit_order.remove(&unit);
+        self.dirty.remove(&unit);
+        self.unlink_unit(unit);
+        self.unlink_name(name.clone());
+        self.reindex_symbol(name);
+    }
+
+    /// Rename a unit.
+    pub fn rename_unit(&mut self, unit: UnitId, name: UnitName) {
+        let old = std::mem::replace(&mut self.units[unit].name, name.clone());
+        self.unlink_name(old.clone());
+        self.unlink_name(name.clone());
+        self.dirty.insert(unit);
+        self.reindex_symbol(old);
+        self.symbols.insert(name, LinkedUnit::Def(unit));
     }
 
     /// Declare an external unit.
@@ -77,21 +156,258 @@ impl Module {
             name,
             sig,
             loc: None,
+            arg_names: HashMap::new(),
         })
     }
 
     /// Declare an external unit.
     pub fn add_decl(&mut self, data: DeclData) -> DeclId {
+        let name = data.name.clone();
         let decl = self.decls.add(data);
         self.decl_order.insert(decl);
-        self.link_table = None;
+        self.symbols
+            .entry(name.clone())
+            .or_insert_with(|| LinkedUnit::Decl(decl));
+        self.unlink_name(name);
         decl
     }
 
     /// Remove a declaration from the module.
     pub fn remove_decl(&mut self, decl: DeclId) {
+        let name = self.decls[decl].name.clone();
         self.decls.remove(decl);
         self.decl_order.remove(&decl);
+        self.unlink_name(name.clone());
+        self.reindex_symbol(name);
+    }
+
+    /// Remove redundant declarations from the module.
+    ///
+    /// A declaration is redundant if the module defines a unit of the same
+    /// name and signature, or if an earlier declaration has the same name and
+    /// signature. References to the removed declarations link against the
+    /// remaining unit or declaration instead. Declarations with a conflicting
+    /// signature are kept, such that linking reports them.
+    ///
+    /// Returns the number of removed declarations.
+    pub fn dedup_decls(&mut self) -> usize {
+        let mut sigs: HashMap<_, _> = self
+            .units()
+            .map(|unit| (unit.name().clone(), unit.sig().clone()))
+            .collect();
+        let mut redundant = vec![];
+        for decl in self.decls() {
+            let data = &self[decl];
+            match sigs.get(&data.name) {
+                Some(sig) if *sig == data.sig => redundant.push(decl),
+                Some(_) => (),
+                None => {
+                    sigs.insert(data.name.clone(), data.sig.clone());
+                }
+            }
+        }
+        for &decl in &redundant {
+            self.remove_decl(decl);
+        }
+        redundant.len()
+    }
+
+    /// Remove the declarations that no unit in the module refers to.
+    ///
+    /// A declaration is referred to if a unit calls or instantiates a unit, or
+    /// uses a resolution function, whose name, after resolving aliases, links
+    /// against the declaration.
+    /// Declarations shadowed by a definition or an earlier declaration of the
+    /// same name are thus removed as well.
+    ///
+    /// Returns the number of removed declarations.
+    pub fn remove_unused_decls(&mut self) -> usize {
+        let mut used = HashSet::new();
+        for unit in self.units() {
+            let resolutions = unit.data().dfg.resolutions.values();
+            let names =
+                unit.extern_units()
+                    .map(|(_, data)| &data.name)
+                    .chain(resolutions.filter_map(|res| match res {
+                        Resolution::Function(name) => Some(name),
+                        _ => None,
+                    }));
+            for name in names {
+                if let Some(LinkedUnit::Decl(decl)) = self.lookup(name) {
+                    used.insert(decl);
+                }
+            }
+        }
+        let unused: Vec<_> = self.decls().filter(|decl| !used.contains(decl)).collect();
+        for &decl in &unused {
+            self.remove_decl(decl);
+        }
+        unused.len()
+    }
+
+    /// Rename a declaration.
+    pub fn rename_decl(&mut self, decl: DeclId, name: UnitName) {
+        let old = std::mem::replace(&mut self.decls[decl].name, name.clone());
+        self.unlink_name(old.clone());
+        self.unlink_name(name.clone());
+        self.reindex_symbol(old);
+        self.reindex_symbol(name);
+    }
+
+    /// Register an alternative name for a unit.
+    ///
+    /// Looking up or linking against `alias` resolves to the unit named
+    /// `target`. Replaces any previous alias with the same name.
+    pub fn add_alias(&mut self, alias: UnitName, target: UnitName) {
+        self.aliases.insert(alias.clone(), target);
+        self.unlink_name(alias);
+    }
+
+    /// Remove an alternative name for a unit.
+    pub fn remove_alias(&mut self, alias: &UnitName) {
+        self.aliases.remove(alias);
+        self.unlink_name(alias.clone());
+    }
+
+    /// Return an iterator over the aliases in the module, and the names they
+    /// refer to.
+    pub fn aliases<'a>(&'a self) -> impl Iterator<Item = (&'a UnitName, &'a UnitName)> + 'a {
+        self.aliases.iter()
+    }
+
+    /// Find the unit or declaration with a given name.
+    ///
+    /// Aliases are resolved, and definitions take precedence over
+    /// declarations. Returns `None` if no unit has the name.
+    pub fn lookup(&self, name: &UnitName) -> Option<LinkedUnit> {
+        self.lookup_via(name, |_| ())
+    }
+
+    /// Find the unit or declaration with a given name, calling `visit` for
+    /// each name passed through while resolving aliases.
+    fn lookup_via(&self, name: &UnitName, mut visit: impl FnMut(&UnitName)) -> Option<LinkedUnit> {
+        let mut name = name;
+        for _ in 0..=self.aliases.len() {
+            visit(name);
+            if let Some(found) = self.lookup_symbol(name) {
+                return Some(found);
+            }
+            name = self.aliases.get(name)?;
+        }
+        None
+    }
+
+    /// Find the unit or declaration with a given name, without resolving
+    /// aliases.
+    fn lookup_symbol(&self, name: &UnitName) -> Option<LinkedUnit> {
+        let found = self.symbols.get(name).cloned();
+        if !self.are_symbols_stale() {
+            return found;
+        }
+        // Units may have been renamed behind our back, so only trust the
+        // table if the entry is a definition that still has the name.
+        match found {
+            Some(LinkedUnit::Def(unit)) if self.units[unit].name == *name => found,
+            _ => self.scan_symbol(name),
+        }
+    }
+
+    /// Check whether units or declarations may have been renamed since the
+    /// symbol table was last rebuilt.
+    fn are_symbols_stale(&self) -> bool {
+        self.symbols_stale || self.units_renamed.get()
+    }
+
+    /// Find the unit or declaration with a given name by scanning the module.
+    fn scan_symbol(&self, name: &UnitName) -> Option<LinkedUnit> {
+        self.units()
+            .find(|unit| unit.name() == name)
+            .map(|unit| LinkedUnit::Def(unit.id()))
+            .or_else(|| {
+                self.decls()
+                    .find(|&decl| self[decl].name == *name)
+                    .map(LinkedUnit::Decl)
+            })
+    }
+
+    /// Update the symbol table entry for a name.
+    fn reindex_symbol(&mut self, name: UnitName) {
+        match self.scan_symbol(&name) {
+            Some(found) => self.symbols.insert(name, found),
+            None => self.symbols.remove(&name),
+        };
+    }
+
+    /// Rebuild the symbol table from the names of the units and declarations.
+    pub fn reindex_symbols(&mut self) {
+        let mut symbols = HashMap::new();
+        for decl in self.decls() {
+            symbols
+                .entry(self[decl].name.clone())
+                .or_insert(LinkedUnit::Decl(decl));
+        }
+        for unit in self.units() {
+            symbols.insert(unit.name().clone(), LinkedUnit::Def(unit.id()));
+        }
+        self.symbols = symbols;
+        self.symbols_stale = false;
+        self.units_renamed.clear();
+    }
+
+    /// Get the name of a unit or declaration.
+    pub fn symbol_name(&self, unit: LinkedUnit) -> &UnitName {
+        match unit {
+            LinkedUnit::Def(unit) => &self[unit].name,
+            LinkedUnit::Decl(decl) => &self[decl].name,
+        }
+    }
+
+    /// Get the signature of a unit or declaration.
+    pub fn symbol_sig(&self, unit: LinkedUnit) -> &Signature {
+        match unit {
+            LinkedUnit::Def(unit) => &self[unit].sig,
+            LinkedUnit::Decl(decl) => &self[decl].sig,
+        }
+    }
+
+    /// Define a named type.
+    ///
+    /// Replaces any previous definition with the same name.
+    pub fn add_type(&mut self, def: TypeDef) {
+        match self.types.iter_mut().find(|d| d.name == def.name) {
+            Some(existing) => *existing = def,
+            None => self.types.push(def),
+        }
+    }
+
+    /// Remove a named type from the module.
+    pub fn remove_type(&mut self, name: &str) {
+        self.types.retain(|d| d.name != name);
+    }
+
+    /// Return an iterator over the named types in this module.
+    pub fn types<'a>(&'a self) -> impl Iterator<Item = &'a TypeDef> + 'a {
+        self.types.iter()
+    }
+
+    /// Find the definition of a named type.
+    pub fn lookup_type(&self, name: &str) -> Option<&TypeDef> {
+        self.types.iter().find(|d| d.name == name)
+    }
+
+    /// Find the name of a struct type, if it is a named struct type.
+    pub fn type_name<'a>(&self, ty: &'a Type) -> Option<&'a str> {
+        ty.struct_name()
+    }
+
+    /// Find the index of a named field in a named struct type.
+    pub fn field_index(&self, ty: &Type, field: &str) -> Option<usize> {
+        self.named_struct(ty).and_then(|d| d.field_index(field))
+    }
+
+    /// Find the definition of a named struct type.
+    fn named_struct(&self, ty: &Type) -> Option<&TypeDef> {
+        self.lookup_type(ty.struct_name()?).filter(|d| d.ty == *ty)
     }
 
     /// Return an iterator over the units in this module.
@@ -100,24 +416,59 @@ impl Module {
     }
 
     /// Return a mutable iterator over the units in this module.
+    ///
+    /// Marks all units as dirty.
     pub fn units_mut<'a>(&'a mut self) -> impl Iterator<Item = UnitBuilder<'a>> + 'a {
+        self.dirty.extend(self.unit_order.iter().cloned());
+        self.unlink_all_units();
+        self.units_mut_untracked()
+    }
+
+    /// Return a mutable iterator over the units in this module, without
+    /// marking them as dirty.
+    ///
+    /// The caller is responsible for calling `mark_dirty` on every unit it
+    /// modifies.
+    pub(crate) fn units_mut_untracked<'a>(
+        &'a mut self,
+    ) -> impl Iterator<Item = UnitBuilder<'a>> + 'a {
+        let renamed = &self.units_renamed.0;
         self.units
             .storage
             .iter_mut()
-            .map(|(&id, data)| UnitBuilder::new(UnitId::new(id), data))
+            .map(move |(&id, data)| UnitBuilder::new(UnitId::new(id), data).track_renames(renamed))
     }
 
     /// Return a parallel iterator over the units in this module.
+    #[cfg(feature = "parallel")]
     pub fn par_units<'a>(&'a self) -> impl ParallelIterator<Item = Unit<'a>> + 'a {
         self.unit_order.par_iter().map(move |&id| self.unit(id))
     }
 
     /// Return a parallel mutable iterator over the units in this module.
+    ///
+    /// Marks all units as dirty.
+    #[cfg(feature = "parallel")]
     pub fn par_units_mut<'a>(&'a mut self) -> impl ParallelIterator<Item = UnitBuilder<'a>> + 'a {
+        self.dirty.extend(self.unit_order.iter().cloned());
+        self.unlink_all_units();
+        self.par_units_mut_untracked()
+    }
+
+    /// Return a parallel mutable iterator over the units in this module,
+    /// without marking them as dirty.
+    ///
+    /// The caller is responsible for calling `mark_dirty` on every unit it
+    /// modifies.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn par_units_mut_untracked<'a>(
+        &'a mut self,
+    ) -> impl ParallelIterator<Item = UnitBuilder<'a>> + 'a {
+        let renamed = &self.units_renamed.0;
         self.units
             .storage
             .par_iter_mut()
-            .map(|(&id, data)| UnitBuilder::new(UnitId::new(id), data))
+            .map(move |(&id, data)| UnitBuilder::new(UnitId::new(id), data).track_renames(renamed))
     }
 
     /// Return an iterator over the functions in this module.
@@ -145,10 +496,16 @@ impl Module {
         Unit::new(unit, &self[unit])
     }
 
+    /// Return a unit in the module, or `None` if it does not exist.
+    pub fn get_unit(&self, unit: UnitId) -> Option<Unit<'_>> {
+        self.units.get(unit).map(|data| Unit::new(unit, data))
+    }
+
     /// Return a mutable unit in the module.
     pub fn unit_mut(&mut self, unit: UnitId) -> UnitBuilder {
-        self.link_table = None;
-        UnitBuilder::new(unit, &mut self[unit])
+        self.unlink_unit(unit);
+        self.dirty.insert(unit);
+        UnitBuilder::new(unit, &mut self.units[unit]).track_renames(&self.units_renamed.0)
     }
 
     /// Return an iterator over the symbols in the module.
@@ -179,21 +536,40 @@ impl Module {
     ///
     /// Adding or modifying a unit invalidates the linkage within the module.
     pub fn is_linked(&self) -> bool {
-        self.link_table.is_some()
+        self.linked
     }
 
     /// Locally link the module.
     pub fn link(&mut self) {
         let mut failed = false;
 
-        // Collect a table of symbols that we can resolve against.
-        let mut symbols = HashMap::new();
+        // Make sure the symbol table reflects the current names, and merge
+        // duplicate declarations.
+        if self.are_symbols_stale() {
+            self.reindex_symbols();
+        }
+        self.dedup_decls();
+
+        // Check that no name is defined twice, and that all declarations of a
+        // name agree on the signature.
+        let mut seen = HashMap::<_, (LinkedUnit, _)>::new();
         for (name, unit, sig) in self.symbols() {
-            if let Some((existing, _)) = symbols.insert(name, (unit, sig)) {
-                if !existing.is_decl() {
-                    eprintln!("unit {} declared multiple times", name);
+            match seen.get(name) {
+                Some(&(existing, _)) if !existing.is_decl() && !unit.is_decl() => {
+                    eprintln!("unit {} defined multiple times", name);
+                    failed = true;
+                }
+                Some(&(_, existing_sig)) if existing_sig != sig => {
+                    eprintln!(
+                        "signature mismatch: {} has {}, but is also declared as {}",
+                        name, existing_sig, sig
+                    );
                     failed = true;
                 }
+                Some(_) => (),
+                None => {
+                    seen.insert(name, (unit, sig));
+                }
             }
         }
         if failed {
@@ -201,39 +577,216 @@ impl Module {
         }
 
         // Resolve the external units in each unit.
-        let mut linked = HashMap::new();
-        for unit in self.units() {
+        self.link_table.clear();
+        self.link_deps.clear();
+        let units = self.unit_order.clone();
+        for (_, error) in self.resolve_units(&units) {
+            eprintln!("{}", error);
+            failed = true;
+        }
+        if failed {
+            panic!("linking failed; unresolved references");
+        }
+        self.link_deps_valid = true;
+        self.linked = true;
+        self.relink_units.clear();
+        self.relink_names.clear();
+        self.infer_purity();
+    }
+
+    /// Incrementally link the module.
+    ///
+    /// Only resolves the references in the units that have been added or
+    /// modified since the module was last linked, and the references to names
+    /// that may now refer to a different unit or signature, such as the names
+    /// of modified, renamed, or removed units. Resolves all references if the
+    /// module has not been linked before. Unlike `link`, this does not check
+    /// for units defined multiple times.
+    ///
+    /// Returns the units with references that cannot be resolved, for example
+    /// because the signature of the unit they refer to has changed. The
+    /// references in these units are resolved again by the next call to
+    /// `relink`, such that the module is linked once all of them are fixed.
+    pub fn relink(&mut self) -> Result<(), Vec<UnitId>> {
+        // Find the names that refer to a different unit now, e.g. because
+        // units have been renamed through `unit_mut`, and merge duplicate
+        // declarations.
+        if self.are_symbols_stale() {
+            let old = std::mem::take(&mut self.symbols);
+            self.reindex_symbols();
+            let new = &self.symbols;
+            let changed = old
+                .iter()
+                .filter(|&(name, unit)| new.get(name) != Some(unit))
+                .chain(new.iter().filter(|&(name, _)| !old.contains_key(name)))
+                .map(|(name, _)| name.clone());
+            self.relink_names.extend(changed);
+        }
+        self.dedup_decls();
+
+        // Find the units whose references are affected by the changes.
+        let mut units = std::mem::take(&mut self.relink_units);
+        for &unit in &units {
+            if self.unit_order.contains(&unit) {
+                self.relink_names.insert(self.units[unit].name.clone());
+            }
+        }
+        if self.link_deps_valid {
+            for name in self.relink_names.drain() {
+                if let Some(deps) = self.link_deps.get(&name) {
+                    units.extend(deps);
+                }
+            }
+        } else {
+            self.relink_names.clear();
+            self.link_table.clear();
+            self.link_deps.clear();
+            units.extend(&self.unit_order);
+        }
+
+        // Resolve the references in these units.
+        let mut failed: Vec<_> = self
+            .resolve_units(&units)
+            .into_iter()
+            .map(|(unit, _)| unit)
+            .collect();
+        failed.dedup();
+        self.link_deps_valid = true;
+        self.linked = failed.is_empty();
+        self.relink_units.extend(&failed);
+        if failed.is_empty() {
+            self.infer_purity();
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+
+    /// Resolve the external units in a set of units.
+    ///
+    /// Replaces the entries of the units in the link table, and records the
+    /// names they depend on. Returns the units with references that cannot be
+    /// resolved, in order, together with a description of the problem.
+    fn resolve_units(&mut self, units: &BTreeSet<UnitId>) -> Vec<(UnitId, String)> {
+        self.link_table.retain(|(unit, _), _| !units.contains(unit));
+        let mut linked = vec![];
+        let mut deps = vec![];
+        let mut errors = vec![];
+        for unit in units.iter().filter(|unit| self.unit_order.contains(unit)) {
+            let unit = self.unit(*unit);
             for (ext_unit, data) in unit.extern_units() {
-                let (to, to_sig) = match symbols.get(&data.name).cloned() {
+                let to = self.lookup_via(&data.name, |name| deps.push((name.clone(), unit.id())));
+                let to = match to {
                     Some(to) => to,
                     None => {
-                        eprintln!(
-                            "unit {} not found; referenced in {}",
-                            data.name,
-                            unit.name()
-                        );
-                        failed = true;
+                        errors.push((
+                            unit.id(),
+                            format!(
+                                "unit {} not found; referenced in {}",
+                                data.name,
+                                unit.name()
+                            ),
+                        ));
                         continue;
                     }
                 };
+                let to_sig = self.symbol_sig(to);
                 if to_sig != &data.sig {
-                    eprintln!(
-                        "signature mismatch: {} has {}, but reference in {} expects {}",
-                        data.name,
-                        to_sig,
-                        unit.name(),
-                        data.sig
-                    );
-                    failed = true;
+                    errors.push((
+                        unit.id(),
+                        format!(
+                            "signature mismatch: {} has {}, but reference in {} expects {}",
+                            data.name,
+                            to_sig,
+                            unit.name(),
+                            data.sig
+                        ),
+                    ));
                     continue;
                 }
-                linked.insert((unit.id(), ext_unit), to);
+                linked.push(((unit.id(), ext_unit), to));
             }
         }
-        if failed {
-            panic!("linking failed; unresolved references");
+        self.link_table.extend(linked);
+        for (name, unit) in deps {
+            self.link_deps.entry(name).or_default().insert(unit);
+        }
+        errors
+    }
+
+    /// Infer which functions are pure, and record for each external unit
+    /// whether it refers to one.
+    ///
+    /// Does not mark the units as modified, since the calls in them retain
+    /// their meaning.
+    fn infer_purity(&mut self) {
+        let purity = FunctionPurity::new(self);
+        let mut exts = vec![];
+        for unit in self.units() {
+            for (ext, data) in unit.extern_units() {
+                exts.push((unit.id(), ext, purity.is_pure_ext_unit(self, data)));
+            }
+        }
+        for (unit, ext, pure) in exts {
+            self.units[unit].dfg.ext_units[ext].pure = pure;
         }
-        self.link_table = Some(linked);
+        self.purity = purity;
+    }
+
+    /// Get the pure functions, as inferred when the module was last linked.
+    ///
+    /// Passes which work on individual units find the same information in the
+    /// `pure` field of the external units, see `Unit::is_pure_inst`.
+    pub fn function_purity(&self) -> &FunctionPurity {
+        &self.purity
+    }
+
+    /// Mark the references in a unit to be resolved by the next `relink`.
+    fn unlink_unit(&mut self, unit: UnitId) {
+        self.linked = false;
+        self.relink_units.insert(unit);
+        self.forget_purity(unit);
+    }
+
+    /// Stop treating a function and the functions calling it as pure.
+    ///
+    /// The function may no longer be pure once it has been modified, and
+    /// neither are the functions which call it. Their calls are treated as
+    /// impure until purity is inferred again by the next `link` or `relink`.
+    fn forget_purity(&mut self, unit: UnitId) {
+        if !self.purity.remove(unit) {
+            return;
+        }
+        let mut impure = vec![unit];
+        while let Some(callee) = impure.pop() {
+            let callers: Vec<_> = self
+                .link_table
+                .iter()
+                .filter(|&(_, &to)| to == LinkedUnit::Def(callee))
+                .map(|(&key, _)| key)
+                .collect();
+            for (caller, ext) in callers {
+                if !self.unit_order.contains(&caller) {
+                    continue;
+                }
+                self.units[caller].dfg.ext_units[ext].pure = false;
+                if self.purity.remove(caller) {
+                    impure.push(caller);
+                }
+            }
+        }
+    }
+
+    /// Mark the references in all units to be resolved by the next `relink`.
+    fn unlink_all_units(&mut self) {
+        self.linked = false;
+        self.relink_units.extend(&self.unit_order);
+    }
+
+    /// Mark the references to a name to be resolved by the next `relink`.
+    fn unlink_name(&mut self, name: UnitName) {
+        self.linked = false;
+        self.relink_names.insert(name);
     }
 
     /// Panic if the module is not well-formed.
@@ -258,10 +811,10 @@ impl Module {
     ///
     /// The module must be linked for this to work.
     pub fn lookup_ext_unit(&self, ext_unit: ExtUnit, within: UnitId) -> Option<LinkedUnit> {
-        self.link_table
-            .as_ref()
-            .and_then(|lt| lt.get(&(within, ext_unit)))
-            .cloned()
+        if !self.linked {
+            return None;
+        }
+        self.link_table.get(&(within, ext_unit)).cloned()
     }
 
     /// Add a location hint to a unit.
@@ -271,6 +824,150 @@ impl Module {
         self.location_hints.insert(mod_unit, loc);
     }
 
+    /// Mark a unit as modified.
+    ///
+    /// The references in the unit are resolved again by the next `relink`.
+    pub fn mark_dirty(&mut self, unit: UnitId) {
+        self.dirty.insert(unit);
+        self.unlink_unit(unit);
+    }
+
+    /// Check whether a unit has been added or modified since the last call to
+    /// `clear_dirty`.
+    pub fn is_dirty(&self, unit: UnitId) -> bool {
+        self.dirty.contains(&unit)
+    }
+
+    /// Return an iterator over the units that have been added or modified
+    /// since the last call to `clear_dirty`.
+    ///
+    /// Pass these to `Verifier::verify_changed` to only re-verify the parts of
+    /// the module that have been touched.
+    pub fn dirty_units<'a>(&'a self) -> impl Iterator<Item = UnitId> + 'a {
+        self.dirty.iter().cloned()
+    }
+
+    /// Mark all units as unmodified.
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Import all units of another module.
+    ///
+    /// Local and anonymous names in `other` are prefixed with `prefix`, such
+    /// that they do not collide with the local names of this module, and the
+    /// references within the imported units are updated accordingly. Global
+    /// names are kept, such that the imported units link against the global
+    /// units of this module and vice versa. Declarations and aliases are only
+    /// imported if their name is not already taken, and named types only if
+    /// no type with the same name exists. Declarations made redundant by the
+    /// imported units are removed, see `dedup_decls`.
+    ///
+    /// Returns a map from the units of `other` to the imported units.
+    pub fn absorb(&mut self, mut other: Module, prefix: &str) -> HashMap<UnitId, UnitId> {
+        let rename = |name: &UnitName| match name {
+            UnitName::Anonymous(id) => UnitName::local(format!("{}{}", prefix, id)),
+            UnitName::Local(name) => UnitName::local(format!("{}{}", prefix, name)),
+            UnitName::Global(..) => name.clone(),
+        };
+
+        // Move the units over.
+        let mut mapping = HashMap::new();
+        for unit in std::mem::take(&mut other.unit_order) {
+            let mut data = other.units.remove(unit);
+            data.name = rename(&data.name);
+            for ext in data.dfg.ext_units.values_mut() {
+                ext.name = rename(&ext.name);
+            }
+            for res in data.dfg.resolutions.values_mut() {
+                if let Resolution::Function(name) = res {
+                    *name = rename(name);
+                }
+            }
+            let new = self.add_unit(data);
+            if let Some(&loc) = other.location_hints.get(&unit) {
+                self.location_hints.insert(new, loc);
+            }
+            mapping.insert(unit, new);
+        }
+
+        // Import the declarations, aliases, and types that do not clash.
+        for decl in std::mem::take(&mut other.decl_order) {
+            let mut data = other.decls.remove(decl);
+            data.name = rename(&data.name);
+            if self.lookup_symbol(&data.name).is_none() {
+                self.add_decl(data);
+            }
+        }
+        for (alias, target) in other.aliases {
+            let alias = rename(&alias);
+            if self.lookup(&alias).is_none() {
+                self.add_alias(alias, rename(&target));
+            }
+        }
+        for def in other.types {
+            if self.lookup_type(&def.name).is_none() {
+                self.types.push(def);
+            }
+        }
+        self.dedup_decls();
+        mapping
+    }
+
+    /// Renumber the units and declarations of the module, and the contents of
+    /// each unit, densely in their current order.
+    ///
+    /// Drops the storage left behind by removed units, declarations,
+    /// instructions, values, and blocks. Modules which only differ in the
+    /// order their contents were created in, for example because passes ran
+    /// in a different order, are identical after compaction, down to the ids
+    /// shown in debug output. All ids obtained before the call are
+    /// invalidated. A linked module remains linked.
+    pub fn compact(&mut self) {
+        let mut units = PrimaryTable::new();
+        let mut unit_map = HashMap::new();
+        for unit in std::mem::take(&mut self.unit_order) {
+            let mut data = self.units.remove(unit);
+            data.compact();
+            let new = units.add(data);
+            self.unit_order.insert(new);
+            unit_map.insert(unit, new);
+        }
+        self.units = units;
+
+        let mut decls = PrimaryTable::new();
+        for decl in std::mem::take(&mut self.decl_order) {
+            let new = decls.add(self.decls.remove(decl));
+            self.decl_order.insert(new);
+        }
+        self.decls = decls;
+
+        self.location_hints = std::mem::take(&mut self.location_hints)
+            .into_iter()
+            .flat_map(|(unit, loc)| Some((*unit_map.get(&unit)?, loc)))
+            .collect();
+        self.dirty = self
+            .dirty
+            .iter()
+            .flat_map(|unit| unit_map.get(unit))
+            .cloned()
+            .collect();
+        self.reindex_symbols();
+
+        // Resolve all references again under the new ids.
+        let linked = self.linked;
+        self.link_table.clear();
+        self.link_deps.clear();
+        self.link_deps_valid = false;
+        self.relink_units.clear();
+        self.relink_names.clear();
+        self.purity = Default::default();
+        self.linked = false;
+        if linked {
+            self.link();
+        }
+    }
+
     /// Get the location hint associated with a unit.
     ///
     /// Returns the byte offset of the unit in the input file, or None if there
@@ -289,7 +986,9 @@ impl std::ops::Index<UnitId> for Module {
 
 impl std::ops::IndexMut<UnitId> for Module {
     fn index_mut(&mut self, idx: UnitId) -> &mut UnitData {
-        self.link_table = None;
+        self.unlink_unit(idx);
+        self.symbols_stale = true;
+        self.dirty.insert(idx);
         &mut self.units[idx]
     }
 }
@@ -303,7 +1002,8 @@ impl std::ops::Index<DeclId> for Module {
 
 impl std::ops::IndexMut<DeclId> for Module {
     fn index_mut(&mut self, idx: DeclId) -> &mut DeclData {
-        self.link_table = None;
+        self.unlink_name(self.decls[idx].name.clone());
+        self.symbols_stale = true;
         &mut self.decls[idx]
     }
 }
@@ -332,7 +1032,12 @@ impl std::fmt::Display for ModuleDumper<'_> {
             }
             newline = true;
             let data = &self.0[decl];
-            write!(f, "declare {} {}", data.name, data.sig)?;
+            write!(
+                f,
+                "declare {} {}",
+                data.name,
+                data.sig.dump_named(&data.arg_names)
+            )?;
         }
         Ok(())
     }
@@ -346,7 +1051,7 @@ impl_table_key! {
 }
 
 /// A unit declaration.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeclData {
     /// The unit signature.
     pub sig: Signature,
@@ -354,6 +1059,84 @@ pub struct DeclData {
     pub name: UnitName,
     /// The location in the source file.
     pub loc: Option<usize>,
+    /// The names of the arguments, if any.
+    #[serde(default)]
+    pub arg_names: HashMap<Arg, String>,
+}
+
+/// A named type definition.
+///
+/// Named struct types are nominal: two of them are only the same type if they
+/// have the same name, and they may carry names for their fields. All other
+/// named types are aliases for structural types.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeDef {
+    /// The name of the type.
+    pub name: String,
+    /// The type being named.
+    #[serde(deserialize_with = "crate::ty::deserialize")]
+    pub ty: Type,
+    /// The names of the struct fields, if any. Either empty or one entry per
+    /// field.
+    pub fields: Vec<Option<String>>,
+}
+
+impl TypeDef {
+    /// Create a new type alias.
+    pub fn new(name: impl Into<String>, ty: Type) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            fields: vec![],
+        }
+    }
+
+    /// Create a new named struct type with optionally named fields.
+    pub fn with_fields(name: impl Into<String>, fields: Vec<(Type, Option<String>)>) -> Self {
+        let name = name.into();
+        let (tys, fields) = fields.into_iter().unzip();
+        Self {
+            ty: crate::ty::named_struct_ty(name.clone(), tys),
+            name,
+            fields,
+        }
+    }
+
+    /// Get the index of a named field.
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields
+            .iter()
+            .position(|f| f.as_ref().map(String::as_str) == Some(field))
+    }
+
+    /// Get the name of a field.
+    pub fn field_name(&self, index: usize) -> Option<&str> {
+        self.fields
+            .get(index)
+            .and_then(|f| f.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// A flag set by the `UnitBuilder`s a module hands out when they may rename
+/// their unit.
+#[derive(Default)]
+struct RenameFlag(AtomicBool);
+
+impl RenameFlag {
+    fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn clear(&mut self) {
+        *self.0.get_mut() = false;
+    }
+}
+
+impl Clone for RenameFlag {
+    fn clone(&self) -> Self {
+        Self(AtomicBool::new(self.get()))
+    }
 }
 
 /// A linked unit.
@@ -382,3 +1165,361 @@ impl LinkedUnit {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::parse_module,
+        opt::{Pass, PassContext},
+        pass::{ConstFolding, DeadCodeElim},
+        verifier::Verifier,
+    };
+
+    #[test]
+    fn dirty_units_after_pass() {
+        let input = "func @foo () i32 {\n%entry:\n    %a = const i32 1\n    %b = const i32 2\n    %c = add i32 %a, %b\n    ret i32 %c\n}\n\nfunc @bar (i32 %x) i32 {\n%entry:\n    ret i32 %x\n}\n";
+        let mut module = parse_module(input).unwrap();
+        assert_eq!(module.dirty_units().count(), 2);
+        module.clear_dirty();
+
+        ConstFolding::run_on_module(&PassContext::default(), &mut module);
+        let dirty: Vec<_> = module.dirty_units().collect();
+        assert_eq!(dirty.len(), 1);
+        assert_eq!(module.unit(dirty[0]).name().to_string(), "@foo");
+
+        let mut verifier = Verifier::new();
+        verifier.verify_changed(&module, &dirty);
+        assert!(verifier.finish().is_ok());
+    }
+
+    #[test]
+    fn compact() {
+        let ids = |module: &Module| {
+            module
+                .units()
+                .map(|unit| {
+                    let insts: Vec<_> = unit.all_insts().collect();
+                    let values: Vec<_> = insts
+                        .iter()
+                        .flat_map(|&inst| unit.get_inst_result(inst))
+                        .collect();
+                    (unit.id(), unit.blocks().collect::<Vec<_>>(), insts, values)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Fold the constants of one module, and write down the folded ones in
+        // the other.
+        let mut folded = parse_module(
+            "
+            proc @gone () -> () {
+            %entry:
+                halt
+            }
+            func @foo (i32 %x) i32 {
+            %entry:
+                %a = const i32 1
+                %b = const i32 2
+                %c = add i32 %a, %b
+                %d = call i32 @bar (i32 %c)
+                %y = add i32 %d, %x
+                ret i32 %y
+            }
+            func @bar (i32 %x) i32 {
+            %entry:
+                ret i32 %x
+            }
+            ",
+        )
+        .unwrap();
+        let gone = folded.units().next().unwrap().id();
+        folded.remove_unit(gone);
+        ConstFolding::run_on_module(&PassContext::default(), &mut folded);
+        DeadCodeElim::run_on_module(&PassContext::default(), &mut folded);
+        folded.link();
+        let mut written = parse_module(
+            "
+            func @foo (i32 %x) i32 {
+            %entry:
+                %c = const i32 3
+                %d = call i32 @bar (i32 %c)
+                %y = add i32 %d, %x
+                ret i32 %y
+            }
+            func @bar (i32 %x) i32 {
+            %entry:
+                ret i32 %x
+            }
+            ",
+        )
+        .unwrap();
+        assert_ne!(folded.dump().to_string(), written.dump().to_string());
+        assert_ne!(ids(&folded), ids(&written));
+
+        folded.compact();
+        written.compact();
+        assert_eq!(folded.dump().to_string(), written.dump().to_string());
+        assert_eq!(ids(&folded), ids(&written));
+        assert!(folded.is_linked());
+        folded.verify();
+        let foo = folded.units().next().unwrap();
+        let (ext, _) = foo.extern_units().next().unwrap();
+        assert_eq!(
+            folded.lookup_ext_unit(ext, foo.id()),
+            Some(LinkedUnit::Def(folded.units().nth(1).unwrap().id()))
+        );
+    }
+
+    #[test]
+    fn symbol_lookup() {
+        let input = "proc @foo () -> () {\n%entry:\n    halt\n}\n\nproc %bar () -> () {\n%entry:\n    halt\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let foo = module.units().next().unwrap().id();
+        let bar = module.units().nth(1).unwrap().id();
+        let decl = module.declare(UnitName::global("foo"), module[foo].sig.clone());
+        assert_eq!(
+            module.lookup(&UnitName::global("foo")),
+            Some(LinkedUnit::Def(foo))
+        );
+        assert_eq!(
+            module.lookup(&UnitName::local("bar")),
+            Some(LinkedUnit::Def(bar))
+        );
+        assert_eq!(module.lookup(&UnitName::global("bar")), None);
+
+        // Aliases resolve to the unit they name.
+        module.add_alias(UnitName::global("baz"), UnitName::local("bar"));
+        assert_eq!(
+            module.lookup(&UnitName::global("baz")),
+            Some(LinkedUnit::Def(bar))
+        );
+
+        // Renaming and removal keep the table consistent.
+        module.rename_unit(bar, UnitName::global("qux"));
+        assert_eq!(module.lookup(&UnitName::local("bar")), None);
+        assert_eq!(module.lookup(&UnitName::global("baz")), None);
+        assert_eq!(
+            module.lookup(&UnitName::global("qux")),
+            Some(LinkedUnit::Def(bar))
+        );
+        module[bar].name = UnitName::local("bar");
+        assert_eq!(module.lookup(&UnitName::global("qux")), None);
+        assert_eq!(
+            module.lookup(&UnitName::global("baz")),
+            Some(LinkedUnit::Def(bar))
+        );
+        module.remove_unit(foo);
+        assert_eq!(
+            module.lookup(&UnitName::global("foo")),
+            Some(LinkedUnit::Decl(decl))
+        );
+        module.remove_decl(decl);
+        assert_eq!(module.lookup(&UnitName::global("foo")), None);
+    }
+
+    #[test]
+    fn symbol_table_survives_unit_edits() {
+        let input = "proc @foo () -> () {\n%entry:\n    halt\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let foo = module.units().next().unwrap().id();
+        assert!(!module.are_symbols_stale());
+
+        // Editing the body of units leaves the table intact.
+        for mut unit in module.units_mut() {
+            let entry = unit.entry();
+            unit.append_to(entry);
+        }
+        module.unit_mut(foo).entry();
+        assert!(!module.are_symbols_stale());
+
+        // Renaming through the builder is still picked up.
+        module.unit_mut(foo).data().name = UnitName::global("bar");
+        assert!(module.are_symbols_stale());
+        assert_eq!(module.lookup(&UnitName::global("foo")), None);
+        assert_eq!(
+            module.lookup(&UnitName::global("bar")),
+            Some(LinkedUnit::Def(foo))
+        );
+        module.link();
+        assert!(!module.are_symbols_stale());
+    }
+
+    #[test]
+    fn absorb() {
+        let mut module = parse_module(
+            "
+            entity %leaf () -> () {}
+            entity @foo () -> () {
+                inst %leaf () -> ()
+            }
+            ",
+        )
+        .unwrap();
+        let other = parse_module(
+            "
+            entity %leaf () -> () {}
+            entity @bar () -> () {
+                inst %leaf () -> ()
+                inst @foo () -> ()
+            }
+            declare @foo () -> ()
+            ",
+        )
+        .unwrap();
+        let find = |name| other.units().find(|u| *u.name() == name).unwrap().id();
+        let leaf = find(UnitName::local("leaf"));
+        let bar = find(UnitName::global("bar"));
+        let mapping = module.absorb(other, "b.");
+        module.link();
+        module.verify();
+
+        assert_eq!(module[mapping[&leaf]].name, UnitName::local("b.leaf"));
+        assert_eq!(module.units().count(), 4);
+        assert_eq!(module.decls().count(), 0);
+        let bar = module.unit(mapping[&bar]);
+        let insts: Vec<_> = bar
+            .extern_units()
+            .map(|(ext, _)| module.lookup_ext_unit(ext, bar.id()).unwrap())
+            .map(|linked| module.symbol_name(linked).to_string())
+            .collect();
+        assert_eq!(insts, vec!["%b.leaf", "@foo"]);
+    }
+
+    #[test]
+    fn absorb_drops_redundant_decls() {
+        let mut module = parse_module(
+            "
+            entity @top () -> () {
+                inst @foo () -> ()
+            }
+            declare @foo () -> ()
+            declare @foo () -> ()
+            declare @bar () -> ()
+            ",
+        )
+        .unwrap();
+        let other = parse_module("entity @foo () -> () {}").unwrap();
+        module.absorb(other, "b.");
+        module.link();
+        module.verify();
+        let decls: Vec<_> = module
+            .decls()
+            .map(|decl| module[decl].name.to_string())
+            .collect();
+        assert_eq!(decls, vec!["@bar"]);
+        assert_eq!(module.remove_unused_decls(), 1);
+        assert_eq!(module.decls().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "linking failed")]
+    fn link_rejects_conflicting_decls() {
+        let mut module = parse_module(
+            "
+            declare @foo () -> ()
+            declare @foo (i1$) -> ()
+            ",
+        )
+        .unwrap();
+        module.link();
+    }
+
+    #[test]
+    fn modifying_function_forgets_purity() {
+        let mut module = parse_module(
+            "
+            func @f (i8 %x) i8 {
+            %entry:
+                ret i8 %x
+            }
+            func @g (i8 %x) i8 {
+            %entry:
+                %y = call i8 @f (i8 %x)
+                ret i8 %y
+            }
+            func @h (i8 %x) i8 {
+            %entry:
+                %y = call i8 @g (i8 %x)
+                ret i8 %y
+            }
+            declare @ext (i8) i8
+            ",
+        )
+        .unwrap();
+        let id = |name| match module.lookup(&UnitName::global(name)) {
+            Some(LinkedUnit::Def(id)) => id,
+            _ => unreachable!(),
+        };
+        let (f, g, h) = (id("f"), id("g"), id("h"));
+        let sig = match module.lookup(&UnitName::global("ext")) {
+            Some(LinkedUnit::Decl(decl)) => module[decl].sig.clone(),
+            _ => unreachable!(),
+        };
+        module.link();
+        let pure_calls = |module: &Module| {
+            [g, h]
+                .iter()
+                .map(|&unit| module.unit(unit).extern_units().next().unwrap().1.pure)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pure_calls(&module), vec![true, true]);
+
+        // Making @f impure affects the calls to @f and to @g, which calls it.
+        {
+            let mut unit = module.unit_mut(f);
+            let ext = unit.add_extern(UnitName::global("ext"), sig);
+            let entry = unit.entry();
+            let x = unit.input_arg(0);
+            unit.prepend_to(entry);
+            unit.ins().call(ext, vec![x]);
+        }
+        assert_eq!(pure_calls(&module), vec![false, false]);
+        assert!(!module.function_purity().is_pure(g));
+        module.link();
+        assert_eq!(pure_calls(&module), vec![false, false]);
+    }
+
+    #[test]
+    fn relink_reports_signature_changes() {
+        let mut module = parse_module(
+            "
+            entity @a () -> () {
+                inst @leaf () -> ()
+            }
+            entity @b () -> () {
+                inst @leaf () -> ()
+            }
+            entity @c () -> () {
+                inst @other () -> ()
+            }
+            entity @leaf () -> () {}
+            entity @other () -> () {}
+            ",
+        )
+        .unwrap();
+        let id = |name| match module.lookup(&UnitName::global(name)) {
+            Some(LinkedUnit::Def(id)) => id,
+            _ => unreachable!(),
+        };
+        let (a, b, leaf) = (id("a"), id("b"), id("leaf"));
+        module.link();
+        assert_eq!(module.relink(), Ok(()));
+
+        // Only the references to the modified unit are affected.
+        module
+            .unit_mut(leaf)
+            .add_output(crate::ty::signal_ty(crate::ty::int_ty(1)));
+        assert!(!module.is_linked());
+        assert_eq!(module.relink(), Err(vec![a, b]));
+        assert!(!module.is_linked());
+
+        // Renaming the unit and declaring the old signature fixes them.
+        module.rename_unit(leaf, UnitName::global("leaf2"));
+        let decl = module.declare(UnitName::global("leaf"), Signature::new());
+        assert_eq!(module.relink(), Ok(()));
+        assert!(module.is_linked());
+        let ext = module.unit(a).extern_units().next().unwrap().0;
+        assert_eq!(module.lookup_ext_unit(ext, a), Some(LinkedUnit::Decl(decl)));
+    }
+}
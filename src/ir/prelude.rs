@@ -4,6 +4,7 @@
 
 #[allow(deprecated)]
 pub use crate::ir::{
-    Arg, Block, DeclData, DeclId, Inst, Module, Opcode, RegMode, RegTrigger, Signature, Unit,
-    UnitBuilder, UnitData, UnitId, UnitKind, UnitName, Value,
+    Annotations, Arg, Block, DeclData, DeclId, Inst, Module, Opcode, RegControl, RegControls,
+    RegMode, RegTrigger, Resolution, Signature, TypeDef, Unit, UnitBuilder, UnitData, UnitId,
+    UnitKind, UnitName, Value,
 };
@@ -6,8 +6,9 @@
 use crate::{
     ir::{Arg, Unit},
     table::PrimaryTable,
-    ty::Type,
+    ty::{signal_ty, Type},
 };
+use std::collections::HashMap;
 
 /// A description of the input and output arguments of a unit.
 #[derive(Default, Clone, Serialize, Deserialize)]
@@ -15,6 +16,7 @@ pub struct Signature {
     args: PrimaryTable<Arg, ArgData>,
     inp: Vec<Arg>,
     oup: Vec<Arg>,
+    #[serde(deserialize_with = "crate::ty::deserialize_opt")]
     retty: Option<Type>,
 }
 
@@ -28,6 +30,7 @@ enum ArgDir {
 /// A single argument of a `Function`, `Process`, or `Entity`.
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct ArgData {
+    #[serde(deserialize_with = "crate::ty::deserialize")]
     ty: Type,
     dir: ArgDir,
     num: u16,
@@ -61,6 +64,41 @@ impl Signature {
         arg
     }
 
+    /// Insert an argument before another one, in the same direction.
+    pub fn insert_arg_before(&mut self, before: Arg, ty: Type) -> Arg {
+        let dir = self.args[before].dir.clone();
+        let arg = self.args.add(ArgData { ty, dir, num: 0 });
+        let list = self.list_mut(before);
+        let pos = list.iter().position(|&a| a == before).unwrap();
+        list.insert(pos, arg);
+        self.renumber();
+        arg
+    }
+
+    /// Remove an argument.
+    pub fn remove_arg(&mut self, arg: Arg) {
+        self.list_mut(arg).retain(|&a| a != arg);
+        self.args.remove(arg);
+        self.renumber();
+    }
+
+    /// Get the list of inputs or outputs an argument belongs to.
+    fn list_mut(&mut self, arg: Arg) -> &mut Vec<Arg> {
+        match self.args[arg].dir {
+            ArgDir::Input => &mut self.inp,
+            ArgDir::Output => &mut self.oup,
+        }
+    }
+
+    /// Update the position of each argument among the inputs or outputs.
+    fn renumber(&mut self) {
+        for list in [&self.inp, &self.oup] {
+            for (i, &arg) in list.iter().enumerate() {
+                self.args[arg].num = i as u16;
+            }
+        }
+    }
+
     /// Set the return type of the signature.
     pub fn set_return_type(&mut self, ty: Type) {
         self.retty = Some(ty);
@@ -118,10 +156,58 @@ impl Signature {
         self.args[arg].dir == ArgDir::Output
     }
 
+    /// Convert the signature into one suitable for an entity.
+    ///
+    /// Inputs which are not signals are wrapped into signals, and the return
+    /// type, if any, becomes an additional output signal after all other
+    /// outputs. The arguments keep their identity, such that `Arg`s of the
+    /// original signature refer to the corresponding converted arguments.
+    pub fn to_entity_like(&self) -> Self {
+        let mut sig = self.clone();
+        for arg in self.args() {
+            if !sig.args[arg].ty.is_signal() {
+                sig.args[arg].ty = signal_ty(sig.args[arg].ty.clone());
+            }
+        }
+        if let Some(retty) = sig.retty.take() {
+            sig.add_output(signal_ty(retty));
+        }
+        sig
+    }
+
+    /// Convert the signature into one suitable for a process.
+    ///
+    /// Processes and entities share the same kind of signature; see
+    /// `to_entity_like` for the details of the conversion.
+    pub fn to_process_like(&self) -> Self {
+        self.to_entity_like()
+    }
+
+    /// Get the output which carries the return value after conversion.
+    ///
+    /// Returns the output argument that `to_entity_like` or `to_process_like`
+    /// add for the return type of this signature, or `None` if the signature
+    /// has no return type.
+    pub fn converted_return_arg(&self, converted: &Signature) -> Option<Arg> {
+        if self.has_return_type() {
+            converted.outputs().nth(self.oup.len())
+        } else {
+            None
+        }
+    }
+
     /// Dump the signature in human-readable form.
     pub fn dump<'a>(&'a self, unit: &Unit<'a>) -> SignatureDumper<'a> {
         SignatureDumper(self, *unit)
     }
+
+    /// Dump the signature in human-readable form, with the arguments named
+    /// according to `names`, e.g. `(i1$ %clk) -> (i32$ %q)`.
+    ///
+    /// Arguments without a name are printed as their type only.
+    pub fn dump_named<'a>(&'a self, names: &'a HashMap<Arg, String>) -> NamedSignatureDumper<'a> {
+        NamedSignatureDumper(self, names)
+    }
 }
 
 impl Eq for Signature {}
@@ -138,66 +224,71 @@ impl PartialEq for Signature {
 
 impl std::fmt::Display for Signature {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use std::iter::{once, repeat};
+        self.write_args(f, |f, arg| write!(f, "{}", self.arg_type(arg)))
+    }
+}
+
+impl std::fmt::Debug for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Signature {
+    /// Write the arguments and return type of the signature, using `arg` to
+    /// write each argument.
+    fn write_args(
+        &self,
+        f: &mut std::fmt::Formatter,
+        mut arg: impl FnMut(&mut std::fmt::Formatter, Arg) -> std::fmt::Result,
+    ) -> std::fmt::Result {
         write!(f, "(")?;
-        for (arg, sep) in self.inputs().zip(once("").chain(repeat(", "))) {
-            write!(f, "{}{}", sep, self.arg_type(arg))?;
-        }
-        if self.has_outputs() {
-            write!(f, ") -> (")?;
-            for (arg, sep) in self.outputs().zip(once("").chain(repeat(", "))) {
-                write!(f, "{}{}", sep, self.arg_type(arg))?;
+        for (i, a) in self.inputs().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
             }
+            arg(f, a)?;
         }
         write!(f, ")")?;
         if let Some(ref retty) = self.retty {
-            write!(f, " {}", retty)?;
+            return write!(f, " {}", retty);
         }
-        Ok(())
+        write!(f, " -> (")?;
+        for (i, a) in self.outputs().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            arg(f, a)?;
+        }
+        write!(f, ")")
     }
 }
 
-impl std::fmt::Debug for Signature {
+/// Temporary object to dump a `Signature` in human-readable form for debugging.
+pub struct SignatureDumper<'a>(&'a Signature, Unit<'a>);
+
+impl std::fmt::Display for SignatureDumper<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self)
+        let unit = self.1;
+        self.0.write_args(f, |f, arg| {
+            let value = unit.arg_value(arg);
+            write!(f, "{} {}", unit.value_type(value), value.dump(&unit))
+        })
     }
 }
 
-/// Temporary object to dump a `Signature` in human-readable form for debugging.
-pub struct SignatureDumper<'a>(&'a Signature, Unit<'a>);
+/// Temporary object to dump a `Signature` with argument names in
+/// human-readable form.
+pub struct NamedSignatureDumper<'a>(&'a Signature, &'a HashMap<Arg, String>);
 
-impl std::fmt::Display for SignatureDumper<'_> {
+impl std::fmt::Display for NamedSignatureDumper<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use std::iter::{once, repeat};
-        write!(f, "(")?;
-        for (arg, sep) in self.0.inputs().zip(once("").chain(repeat(", "))) {
-            let value = self.1.arg_value(arg);
-            write!(
-                f,
-                "{}{} {}",
-                sep,
-                self.1.value_type(value),
-                value.dump(&self.1)
-            )?;
-        }
-        write!(f, ")")?;
-        if self.0.has_outputs() {
-            write!(f, " -> (")?;
-            for (arg, sep) in self.0.outputs().zip(once("").chain(repeat(", "))) {
-                let value = self.1.arg_value(arg);
-                write!(
-                    f,
-                    "{}{} {}",
-                    sep,
-                    self.1.value_type(value),
-                    value.dump(&self.1)
-                )?;
+        self.0.write_args(f, |f, arg| {
+            write!(f, "{}", self.0.arg_type(arg))?;
+            if let Some(name) = self.1.get(&arg) {
+                write!(f, " %{}", name)?;
             }
-            write!(f, ")")?;
-        }
-        if self.0.has_return_type() {
-            write!(f, " {}", self.0.return_type())?;
-        }
-        Ok(())
+            Ok(())
+        })
     }
 }
@@ -3,18 +3,22 @@
 // #![deny(missing_docs)]
 
 use crate::{
-    analysis::{DominatorTree, PredecessorTable, TemporalRegionGraph},
+    analysis::{BlockFrequencies, DominatorTree, PredecessorTable, TemporalRegionGraph},
     ir::{
         layout::BlockNode, prelude::*, BlockData, ControlFlowGraph, DataFlowGraph, ExtUnit,
-        ExtUnitData, FunctionLayout, InstBuilder, InstData, UnitId, ValueData,
+        ExtUnitData, FunctionLayout, InstBuilder, InstData, UnitId, Use, ValueData,
     },
     table::TableKey,
+    ty::time_ty,
+    value::{EnumValue, IntValue, TimeValue},
     verifier::Verifier,
     void_ty, Type,
 };
 use std::{
-    collections::HashSet,
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    hash::Hash,
     ops::{Deref, Index, IndexMut},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 /// A name of a function, process, or entity.
@@ -72,18 +76,132 @@ impl UnitName {
             _ => None,
         }
     }
+
+    /// Create a new local unit name, which must be a valid identifier.
+    ///
+    /// Unlike `local`, this rejects names that are not identifiers or which
+    /// consist only of digits, and would thus be printed in quotes or read
+    /// back as an anonymous name.
+    pub fn try_local(name: impl Into<String>) -> Result<Self, String> {
+        let name = name.into();
+        if !Self::is_identifier(&name) || is_anonymous(&name) {
+            return Err(format!("`{}` is not a valid local unit name", name));
+        }
+        Ok(UnitName::Local(name))
+    }
+
+    /// Create a new global unit name, which must be a valid identifier.
+    ///
+    /// Unlike `global`, this rejects names that are not identifiers, and would
+    /// thus be printed in quotes.
+    pub fn try_global(name: impl Into<String>) -> Result<Self, String> {
+        let name = name.into();
+        if !Self::is_identifier(&name) {
+            return Err(format!("`{}` is not a valid global unit name", name));
+        }
+        Ok(UnitName::Global(name))
+    }
+
+    /// Check whether a name is a valid identifier.
+    ///
+    /// Identifiers consist of ASCII letters, digits, `_`, and `.`, and are
+    /// printed without quotes. All other names are printed in quotes, such as
+    /// `@"foo bar"`.
+    pub fn is_identifier(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    }
+}
+
+/// Check whether a name would be read as an anonymous name, like `%42`.
+fn is_anonymous(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Write a unit name, in quotes if it is not an identifier.
+///
+/// Quotes, backslashes, and control characters are escaped within quotes.
+fn write_unit_name(f: &mut std::fmt::Formatter, name: &str, local: bool) -> std::fmt::Result {
+    if UnitName::is_identifier(name) && !(local && is_anonymous(name)) {
+        return write!(f, "{}", name);
+    }
+    write!(f, "\"")?;
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            c if c.is_control() && (c as u32) < 0x100 => write!(f, "\\{:02x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
 }
 
 impl std::fmt::Display for UnitName {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             UnitName::Anonymous(id) => write!(f, "%{}", id),
-            UnitName::Local(n) => write!(f, "%{}", n),
-            UnitName::Global(n) => write!(f, "@{}", n),
+            UnitName::Local(n) => {
+                write!(f, "%")?;
+                write_unit_name(f, n, true)
+            }
+            UnitName::Global(n) => {
+                write!(f, "@")?;
+                write_unit_name(f, n, false)
+            }
         }
     }
 }
 
+impl std::str::FromStr for UnitName {
+    type Err = String;
+
+    /// Parse a unit name as printed, e.g. `@foo`, `%42`, or `@"foo bar"`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (global, tail) = match s.chars().next() {
+            Some('@') => (true, &s[1..]),
+            Some('%') => (false, &s[1..]),
+            _ => return Err(format!("`{}` is not a unit name", s)),
+        };
+        let quoted = tail.len() >= 2 && tail.starts_with('"') && tail.ends_with('"');
+        let name = if quoted {
+            unescape_unit_name(&tail[1..tail.len() - 1])
+                .ok_or_else(|| format!("invalid escape in unit name `{}`", s))?
+        } else if UnitName::is_identifier(tail) {
+            tail.to_string()
+        } else {
+            return Err(format!("`{}` is not a valid unit name", s));
+        };
+        Ok(match name.parse() {
+            _ if global => UnitName::Global(name),
+            Ok(id) if !quoted && is_anonymous(&name) => UnitName::Anonymous(id),
+            _ => UnitName::Local(name),
+        })
+    }
+}
+
+/// Resolve the escapes within a quoted unit name.
+fn unescape_unit_name(s: &str) -> Option<String> {
+    let mut name = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c @ '"' | c @ '\\' => name.push(c),
+                hi => {
+                    let lo = chars.next()?;
+                    let code = u32::from_str_radix(&format!("{}{}", hi, lo), 16).ok()?;
+                    name.push(std::char::from_u32(code)?);
+                }
+            },
+            '"' => return None,
+            c => name.push(c),
+        }
+    }
+    Some(name)
+}
+
 /// The three different units that may appear in LLHD IR.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UnitKind {
@@ -106,10 +224,13 @@ impl std::fmt::Display for UnitKind {
 }
 
 /// A function, process, or entity.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UnitData {
     pub kind: UnitKind,
     pub name: UnitName,
+    /// Whether the unit is only meaningful in simulation, e.g. a testbench.
+    #[serde(default)]
+    pub sim_only: bool,
     pub(super) sig: Signature,
     pub(super) dfg: DataFlowGraph,
     pub(super) cfg: ControlFlowGraph,
@@ -131,6 +252,7 @@ impl UnitData {
         let mut data = Self {
             kind,
             name,
+            sim_only: false,
             sig,
             dfg: Default::default(),
             cfg: Default::default(),
@@ -145,6 +267,169 @@ impl UnitData {
         unit.make_args_for_signature(&unit.sig().clone());
         data
     }
+
+    /// Create an empty process or entity from an existing unit.
+    ///
+    /// The new unit has the name and simulation-only flag of `unit`, a
+    /// signature converted with `Signature::to_entity_like` or
+    /// `to_process_like`, and its arguments carry the same names. Lowering
+    /// passes use this to create the replacement of a unit of another kind.
+    pub fn new_converted(kind: UnitKind, unit: Unit) -> Self {
+        let sig = match kind {
+            UnitKind::Process => unit.sig().to_process_like(),
+            UnitKind::Entity => unit.sig().to_entity_like(),
+            UnitKind::Function => panic!("cannot convert {} to a function", unit.kind()),
+        };
+        let mut data = Self::new(kind, unit.name().clone(), sig);
+        data.sim_only = unit.is_sim_only();
+        let mut builder = UnitBuilder::new_anonymous(&mut data);
+        for arg in unit.sig().args() {
+            if let Some(name) = unit.get_name(unit.arg_value(arg)) {
+                let value = builder.arg_value(arg);
+                builder.set_name(value, name.to_string());
+            }
+        }
+        data
+    }
+
+    /// Renumber the blocks, instructions, values, and external units densely
+    /// in layout order.
+    ///
+    /// Drops the storage left behind by removed entries, such that two units
+    /// which only differ in the order their contents were created in end up
+    /// identical.
+    pub(super) fn compact(&mut self) {
+        // Determine the new order of everything, with the entries that are
+        // not part of the layout at the end.
+        let unit = Unit::new_anonymous(self);
+        let layout: Vec<(Block, Vec<Inst>)> = unit
+            .blocks()
+            .map(|bb| (bb, unit.insts(bb).collect()))
+            .collect();
+        let (blocks, block_map) = renumber(unit.blocks(), self.cfg.blocks.keys());
+        let (insts, inst_map) = renumber(unit.all_insts(), self.dfg.insts.keys());
+        let (values, value_map) = renumber(
+            unit.args()
+                .chain(insts.iter().flat_map(|&inst| unit.get_inst_result(inst))),
+            self.dfg.values.keys(),
+        );
+        let (exts, ext_map) = renumber(
+            insts.iter().flat_map(|&inst| self.dfg[inst].get_ext_unit()),
+            self.dfg.ext_units.keys(),
+        );
+        let value = |v: Value| if v.is_invalid() { v } else { value_map[&v] };
+        let block = |bb: Block| if bb.is_invalid() { bb } else { block_map[&bb] };
+
+        // Rebuild the tables under the new keys.
+        let mut cfg = ControlFlowGraph::default();
+        for &bb in &blocks {
+            cfg.blocks.add(self.cfg.blocks[bb].clone());
+        }
+        cfg.anonymous_hints = rekey(std::mem::take(&mut self.cfg.anonymous_hints), &block_map);
+        cfg.annotations = rekey(std::mem::take(&mut self.cfg.annotations), &block_map);
+
+        let mut dfg = DataFlowGraph::default();
+        for &v in &values {
+            dfg.values.add(match self.dfg.values[v].clone() {
+                ValueData::Inst { ty, inst } => ValueData::Inst {
+                    ty,
+                    inst: inst_map[&inst],
+                },
+                data => data,
+            });
+        }
+        for &ext in &exts {
+            dfg.ext_units.add(self.dfg.ext_units[ext].clone());
+        }
+        for &inst in &insts {
+            let mut data = self.dfg.insts[inst].clone();
+            #[allow(deprecated)]
+            for arg in data.args_mut() {
+                *arg = value(*arg);
+            }
+            #[allow(deprecated)]
+            for bb in data.blocks_mut() {
+                *bb = block(*bb);
+            }
+            if let InstData::Call { unit, .. } = &mut data {
+                *unit = ext_map[unit];
+            }
+            let new = dfg.insts.add(data);
+            if let Some(&result) = self.dfg.results.get(inst) {
+                dfg.results.add(new, value(result));
+            }
+        }
+        for arg in self.sig.args() {
+            dfg.args.add(arg, value(self.dfg.args[arg]));
+        }
+        let old = std::mem::take(&mut self.dfg);
+        dfg.names = rekey(old.names, &value_map);
+        dfg.anonymous_hints = rekey(old.anonymous_hints, &value_map);
+        dfg.location_hints = rekey(old.location_hints, &inst_map);
+        dfg.origins = rekey(old.origins, &inst_map);
+        dfg.annotations = rekey(old.annotations, &inst_map);
+        dfg.branch_weights = rekey(old.branch_weights, &inst_map);
+        dfg.resolutions = rekey(old.resolutions, &inst_map);
+        dfg.replications = rekey(old.replications, &inst_map);
+        for v in dfg.values.keys() {
+            dfg.value_uses.insert(v, Default::default());
+        }
+        for (inst, data) in dfg.insts.iter() {
+            for &arg in data.args() {
+                dfg.value_uses.entry(arg).or_default().insert(inst);
+            }
+            for &bb in data.blocks() {
+                dfg.block_uses.entry(bb).or_default().insert(inst);
+            }
+        }
+        self.dfg = dfg;
+        self.cfg = cfg;
+
+        // Lay out the blocks and instructions again.
+        self.layout = Default::default();
+        let mut builder = UnitBuilder::new_anonymous(self);
+        for (bb, insts) in layout {
+            builder.append_block(block_map[&bb]);
+            for inst in insts {
+                builder.append_inst(inst_map[&inst], block_map[&bb]);
+            }
+        }
+    }
+}
+
+/// Assign consecutive keys to the entries in `first`, followed by the
+/// remaining entries in `all`.
+///
+/// Returns the old keys in their new order, and the new key of each old one.
+fn renumber<K: TableKey + Eq + Hash>(
+    first: impl IntoIterator<Item = K>,
+    all: impl IntoIterator<Item = K>,
+) -> (Vec<K>, HashMap<K, K>) {
+    let mut order = vec![];
+    let mut map = HashMap::new();
+    for key in first.into_iter().chain(all) {
+        if let Entry::Vacant(entry) = map.entry(key) {
+            entry.insert(K::new(order.len()));
+            order.push(key);
+        }
+    }
+    (order, map)
+}
+
+/// Move the entries of a side table over to the new keys, dropping the ones
+/// whose key no longer exists.
+fn rekey<K: Copy + Eq + Hash, V>(map: HashMap<K, V>, keys: &HashMap<K, K>) -> HashMap<K, V> {
+    map.into_iter()
+        .filter_map(|(key, value)| Some((*keys.get(&key)?, value)))
+        .collect()
+}
+
+/// Emits the unit as assembly, which `assembly::parse_unit` reads back.
+impl std::fmt::Display for UnitData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let asm = crate::assembly::write_unit_string(Unit::new_anonymous(self));
+        write!(f, "{}", asm.trim_end())
+    }
 }
 
 /// An immutable function, process, or entity.
@@ -232,6 +517,11 @@ impl<'a> Unit<'a> {
         self.kind() == UnitKind::Entity
     }
 
+    /// Check if this unit is simulation-only and cannot be synthesized.
+    pub fn is_sim_only(self) -> bool {
+        self.data.sim_only
+    }
+
     /// Return an iterator over the unit's input arguments.
     pub fn input_args(self) -> impl Iterator<Item = Value> + 'a {
         self.sig().inputs().map(move |arg| self.arg_value(arg))
@@ -319,6 +609,11 @@ impl<'a> Unit<'a> {
         #[allow(deprecated)]
         DominatorTree::new(&self, pt)
     }
+
+    /// Estimate the unit's block execution frequencies.
+    pub fn block_frequencies(self) -> BlockFrequencies {
+        BlockFrequencies::new(&self)
+    }
 }
 
 /// # Control Flow Graph
@@ -332,6 +627,16 @@ impl<'a> Unit<'a> {
     pub fn get_anonymous_block_hint(self, bb: Block) -> Option<u32> {
         self.data.cfg.anonymous_hints.get(&bb).cloned()
     }
+
+    /// Return the annotations attached to a BB.
+    pub fn block_annotations(self, bb: Block) -> Annotations {
+        self.data
+            .cfg
+            .annotations
+            .get(&bb)
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 /// # Data Flow Graph
@@ -361,6 +666,11 @@ impl<'a> Unit<'a> {
         self.data.dfg.args[arg]
     }
 
+    /// Check whether a value exists in the unit.
+    pub fn has_value(self, value: Value) -> bool {
+        self.data.dfg.values.contains(value)
+    }
+
     /// Returns the type of a value.
     pub fn value_type(self, value: Value) -> Type {
         match &self[value] {
@@ -427,6 +737,32 @@ impl<'a> Unit<'a> {
         &self.data.dfg.value_uses[&value]
     }
 
+    /// Get all uses of a value, together with the role in which the value is
+    /// used.
+    ///
+    /// A value used multiple times by the same instruction yields one `Use`
+    /// per argument. The uses are sorted by instruction and argument index.
+    pub fn uses_detailed(self, value: Value) -> Vec<Use> {
+        let mut uses: Vec<Use> = self
+            .uses(value)
+            .iter()
+            .flat_map(|&inst| {
+                let data = &self[inst];
+                data.args()
+                    .iter()
+                    .enumerate()
+                    .filter(move |&(_, &arg)| arg == value)
+                    .map(move |(index, _)| Use {
+                        inst,
+                        index,
+                        kind: data.use_kind(index),
+                    })
+            })
+            .collect();
+        uses.sort_by_key(|u| (u.inst, u.index));
+        uses
+    }
+
     /// Check if a value is used.
     pub fn has_uses(self, value: Value) -> bool {
         !self.uses(value).is_empty()
@@ -447,12 +783,26 @@ impl<'a> Unit<'a> {
         match self[inst].opcode() {
             Opcode::ConstInt => self.get_const_int(value).cloned().map(Into::into),
             Opcode::ConstTime => self.get_const_time(value).cloned().map(Into::into),
+            Opcode::ConstEnum => self.get_const_enum(value).cloned().map(Into::into),
             Opcode::Array | Opcode::ArrayUniform => self.get_const_array(value).map(Into::into),
             Opcode::Struct => self.get_const_struct(value).map(Into::into),
             _ => None,
         }
     }
 
+    /// Check whether a value is a constant annotated as don't-care.
+    ///
+    /// Each use of such a value may assume any value of its type. See
+    /// `Annotations::DONT_CARE`.
+    pub fn is_dont_care(self, value: Value) -> bool {
+        match self.get_value_inst(value) {
+            Some(inst) => {
+                self[inst].opcode().is_const() && self.inst_annotations(inst).is_dont_care()
+            }
+            None => false,
+        }
+    }
+
     /// Resolve a constant time value.
     ///
     /// Returns `None` if the value is not constant. Note that this *does not*
@@ -473,6 +823,16 @@ impl<'a> Unit<'a> {
         self.data.dfg[inst].get_const_int()
     }
 
+    /// Resolve a constant enum value.
+    ///
+    /// Returns `None` if the value is not constant. Note that this *does not*
+    /// perform constant folding. Rather, the value must resolve to an
+    /// instruction which produces a constant value.
+    pub fn get_const_enum(self, value: Value) -> Option<&'a crate::EnumValue> {
+        let inst = self.get_value_inst(value)?;
+        self.data.dfg[inst].get_const_enum()
+    }
+
     /// Resolve a constant array value.
     ///
     /// Returns `None` if the value is not constant. Note that this *does not*
@@ -525,6 +885,117 @@ impl<'a> Unit<'a> {
         self.data.dfg.location_hints.get(&inst).cloned()
     }
 
+    /// Get the origins of an instruction.
+    ///
+    /// Returns the location hints of the original instructions from which the
+    /// instruction has been derived during optimization, including its own
+    /// location hint.
+    pub fn origins(self, inst: Inst) -> BTreeSet<usize> {
+        let mut origins = self
+            .data
+            .dfg
+            .origins
+            .get(&inst)
+            .cloned()
+            .unwrap_or_default();
+        origins.extend(self.location_hint(inst));
+        origins
+    }
+
+    /// Get the origins of a value.
+    ///
+    /// Returns the origins of the instruction producing the value, or an empty
+    /// set if the value is not the result of an instruction.
+    pub fn value_origins(self, value: Value) -> BTreeSet<usize> {
+        self.get_value_inst(value)
+            .map(|inst| self.origins(inst))
+            .unwrap_or_default()
+    }
+
+    /// Check whether an instruction is pure.
+    ///
+    /// This is the case for instructions with a pure opcode, and for calls to
+    /// functions which were found to be pure when the module was last linked,
+    /// and have not been modified since. See `FunctionPurity`.
+    pub fn is_pure_inst(self, inst: Inst) -> bool {
+        self[inst].opcode().is_pure() || self.is_pure_call(inst)
+    }
+
+    /// Check whether an instruction has side effects.
+    ///
+    /// Unlike other calls, calls to pure functions have no side effects.
+    pub fn inst_has_side_effects(self, inst: Inst) -> bool {
+        self[inst].opcode().has_side_effects() && !self.is_pure_call(inst)
+    }
+
+    /// Check whether an instruction is a call to a pure function.
+    fn is_pure_call(self, inst: Inst) -> bool {
+        self[inst].opcode() == Opcode::Call && self[self[inst].get_ext_unit().unwrap()].pure
+    }
+
+    /// Get the annotations of an instruction.
+    ///
+    /// Returns the annotations attached to the instruction itself, together
+    /// with the ones attached to the block it resides in.
+    pub fn inst_annotations(self, inst: Inst) -> Annotations {
+        let own = self
+            .data
+            .dfg
+            .annotations
+            .get(&inst)
+            .cloned()
+            .unwrap_or_default();
+        match self.inst_block(inst) {
+            Some(bb) => own | self.block_annotations(bb),
+            None => own,
+        }
+    }
+
+    /// Get the branch weights of a conditional branch.
+    ///
+    /// The weights give the relative likelihood of the branch going to each
+    /// of its destinations, in the order of `InstData::blocks`, for example
+    /// as measured in a simulation. Returns `None` if the branch carries no
+    /// weights.
+    pub fn branch_weights(self, inst: Inst) -> Option<[u32; 2]> {
+        self.data.dfg.branch_weights.get(&inst).cloned()
+    }
+
+    /// Get the resolution of a signal created by a `sig` instruction.
+    ///
+    /// Returns `None` if the signal carries no resolution, in which case it
+    /// takes the value of the most recent drive.
+    pub fn signal_resolution(self, inst: Inst) -> Option<&'a Resolution> {
+        self.data.dfg.resolutions.get(&inst)
+    }
+
+    /// Get the number of copies of the entity created by a replicated `inst`
+    /// instruction.
+    ///
+    /// Copy `k` of a replicated instance is connected to element `k` of each
+    /// argument whose type is a signal of an array of the port's type, e.g.
+    /// `[4 x i8]$` for an `i8$` port, and to the argument itself otherwise.
+    /// Returns `None` if the instruction is not replicated.
+    pub fn inst_replication(self, inst: Inst) -> Option<usize> {
+        self.data.dfg.replications.get(&inst).cloned()
+    }
+
+    /// Get the probabilities of a conditional branch going to each of its
+    /// destinations.
+    ///
+    /// The probabilities are derived from the branch weights. Branches
+    /// without weights, or with weights of zero, are assumed to go either way
+    /// with equal probability.
+    pub fn branch_probabilities(self, inst: Inst) -> [f64; 2] {
+        match self.branch_weights(inst) {
+            Some([w0, w1]) if w0 as u64 + w1 as u64 > 0 => {
+                let total = w0 as f64 + w1 as f64;
+                [w0 as f64 / total, w1 as f64 / total]
+            }
+            _ => [0.5, 0.5],
+        }
+    }
+
     /// Get the block ID bound.
     ///
     /// This function is useful for creating dense vectors to associate data
@@ -534,6 +1005,123 @@ impl<'a> Unit<'a> {
     }
 }
 
+/// The transitive fan-in of a value, extracted into a function with
+/// `Unit::extract_cone`.
+pub struct Cone {
+    /// The function which computes the value.
+    pub data: UnitData,
+    /// The value in the original unit which each argument and instruction
+    /// result of the function stands for.
+    pub values: HashMap<Value, Value>,
+    /// The instruction in the original unit which each instruction of the
+    /// function was copied from.
+    pub insts: HashMap<Inst, Inst>,
+}
+
+/// # Cone Extraction
+///
+/// The following functions are used to slice a unit into smaller pieces.
+impl<'a> Unit<'a> {
+    /// Extract the transitive fan-in of a value into a standalone function.
+    ///
+    /// The function is named after the unit and the value, e.g. `@foo.x`, and
+    /// returns the value. It contains a copy of every pure instruction the
+    /// value depends on. Arguments, phi nodes, probes, loads, calls to impure
+    /// functions, and signals or pointers form the boundary of the cone and
+    /// become arguments of the function, in the order they are reached.
+    /// Debugging and formal tools work much better on such small cones than
+    /// on whole processes.
+    pub fn extract_cone(self, value: Value) -> Cone {
+        let is_inside = |inst: Inst| {
+            let ty = self.inst_type(inst);
+            self.is_pure_inst(inst) && !ty.is_signal() && !ty.is_pointer()
+        };
+
+        // Order the instructions of the cone such that each one follows its
+        // operands, and collect the values at its boundary.
+        let mut insts = vec![];
+        let mut inputs = vec![];
+        let mut seen = HashSet::new();
+        let mut stack = vec![(value, false)];
+        while let Some((value, done)) = stack.pop() {
+            if done {
+                insts.push(self.value_inst(value));
+                continue;
+            }
+            if !seen.insert(value) {
+                continue;
+            }
+            match self.get_value_inst(value) {
+                Some(inst) if is_inside(inst) => {
+                    stack.push((value, true));
+                    for &arg in self[inst].args().iter().rev() {
+                        if !seen.contains(&arg) {
+                            stack.push((arg, false));
+                        }
+                    }
+                }
+                _ => inputs.push(value),
+            }
+        }
+
+        // Build the function.
+        let mut sig = Signature::new();
+        for &input in &inputs {
+            sig.add_input(self.value_type(input));
+        }
+        sig.set_return_type(self.value_type(value));
+        let name = format!(
+            "{}.{}",
+            self.name().get_name().unwrap_or("cone"),
+            self.get_name(value).unwrap_or("cone")
+        );
+        let mut data = UnitData::new(UnitKind::Function, UnitName::global(name), sig);
+        let mut values = HashMap::new();
+        let mut inst_map = HashMap::new();
+        let mut mapped = HashMap::new();
+        let mut builder = UnitBuilder::new_anonymous(&mut data);
+        let entry = builder.named_block("entry");
+        builder.append_to(entry);
+        for (&input, arg) in inputs.iter().zip(builder.args().collect::<Vec<_>>()) {
+            if let Some(name) = self.get_name(input) {
+                builder.set_name(arg, name.to_string());
+            }
+            values.insert(arg, input);
+            mapped.insert(input, arg);
+        }
+        let mut ext_units = HashMap::new();
+        for inst in insts {
+            let mut data = self[inst].clone();
+            #[allow(deprecated)]
+            for arg in data.args_mut() {
+                *arg = mapped[arg];
+            }
+            if let InstData::Call { unit: ext, .. } = &mut data {
+                let target = &self[*ext];
+                *ext = *ext_units
+                    .entry(*ext)
+                    .or_insert_with(|| builder.add_extern(target.name.clone(), target.sig.clone()));
+            }
+            let new = builder.ins().build(data, self.inst_type(inst));
+            builder.add_origins(new, self.origins(inst));
+            inst_map.insert(new, inst);
+            let result = self.inst_result(inst);
+            let new_result = builder.inst_result(new);
+            if let Some(name) = self.get_name(result) {
+                builder.set_name(new_result, name.to_string());
+            }
+            values.insert(new_result, result);
+            mapped.insert(result, new_result);
+        }
+        builder.ins().ret_value(mapped[&value]);
+        Cone {
+            data,
+            values,
+            insts: inst_map,
+        }
+    }
+}
+
 /// # Basic Block Layout
 ///
 /// The following functions are used to query the basic block layout.
@@ -670,6 +1258,8 @@ pub struct UnitBuilder<'a> {
     data: &'a mut UnitData,
     /// The position where we are currently inserting instructions.
     pos: InsertPos,
+    /// The flag to set when the unit may be renamed through this builder.
+    rename_flag: Option<&'a AtomicBool>,
 }
 
 // Ensure the UnitBuilder can be used like a Unit.
@@ -700,9 +1290,18 @@ impl<'a> UnitBuilder<'a> {
             // builder itself.
             data: data,
             pos,
+            rename_flag: None,
         }
     }
 
+    /// Set a flag when the unit may be renamed through this builder.
+    ///
+    /// Used by `Module` to keep its symbol table up to date.
+    pub(crate) fn track_renames(mut self, flag: &'a AtomicBool) -> Self {
+        self.rename_flag = Some(flag);
+        self
+    }
+
     /// Create a new builder for a unit that has not yet been added to a module.
     pub fn new_anonymous(data: &'a mut UnitData) -> Self {
         Self::new(UnitId::invalid(), data)
@@ -714,8 +1313,14 @@ impl<'a> UnitBuilder<'a> {
     }
 
     /// Get the unit's mutable data.
+    ///
+    /// Since the unit may be renamed through the data, the module the unit
+    /// belongs to checks its symbol table against the unit names again.
     #[inline(always)]
     pub fn data(&mut self) -> &mut UnitData {
+        if let Some(flag) = self.rename_flag {
+            flag.store(true, Ordering::Relaxed);
+        }
         self.data
     }
 
@@ -777,6 +1382,16 @@ impl<'a> UnitBuilder<'a> {
         self.remove_inst(inst);
     }
 
+    /// Replace the data of an instruction.
+    ///
+    /// The instruction keeps its position and result value. The caller must
+    /// ensure the new data produces a result of the same type.
+    pub fn replace_inst_data(&mut self, inst: Inst, data: InstData) {
+        let old = std::mem::replace(&mut self.data.dfg[inst], data);
+        self.remove_uses(inst, old);
+        self.update_uses(inst);
+    }
+
     // Create a new BB.
     pub fn block(&mut self) -> Block {
         let bb = self.data.cfg.blocks.add(BlockData { name: None });
@@ -791,6 +1406,89 @@ impl<'a> UnitBuilder<'a> {
         bb
     }
 
+    /// Split a block before an instruction.
+    ///
+    /// Moves `inst` and all following instructions into a new block placed
+    /// after the original one, which is terminated with a branch to the new
+    /// block. Phi nodes in the successors are updated accordingly. If the
+    /// original block is named, the new block receives a derived name, such
+    /// as `aux.split1`.
+    pub fn split_block(&mut self, inst: Inst) -> Block {
+        let bb = self.inst_block(inst).unwrap();
+        let name = self.derive_block_name(bb, "split");
+        let split_bb = self.data.cfg.blocks.add(BlockData { name });
+        self.insert_block_after(split_bb, bb);
+
+        // Move the instructions over.
+        let mut next = Some(inst);
+        while let Some(inst) = next {
+            next = self.next_inst(inst);
+            self.remove_inst(inst);
+            self.append_inst(inst, split_bb);
+        }
+
+        // Update the phi nodes in the successors, which are now reached from
+        // the new block.
+        if let Some(term) = self.last_inst(split_bb) {
+            for succ in self[term].blocks().to_vec() {
+                let phis: Vec<_> = self
+                    .insts(succ)
+                    .filter(|&inst| self[inst].opcode().is_phi())
+                    .collect();
+                for phi in phis {
+                    self.replace_block_within_inst(bb, split_bb, phi);
+                }
+            }
+        }
+
+        // Branch from the original block into the new one.
+        let pos = self.pos;
+        self.pos = InsertPos::Append(bb);
+        self.ins().br(split_bb);
+        self.pos = pos;
+        split_bb
+    }
+
+    /// Derive the name of a block created from another block.
+    ///
+    /// Returns a name of the form `<name>.<suffix><N>` which is not yet used
+    /// by any block in the unit, or `None` if `bb` is unnamed.
+    pub(crate) fn derive_block_name(&self, bb: Block, suffix: &str) -> Option<String> {
+        let name = self.get_block_name(bb)?;
+        // Strip a previously derived suffix, such that repeated splits do not
+        // produce ever longer names.
+        let base = match name.rfind(&format!(".{}", suffix)) {
+            Some(pos)
+                if name[pos + suffix.len() + 1..]
+                    .chars()
+                    .all(|c| c.is_ascii_digit()) =>
+            {
+                &name[..pos]
+            }
+            _ => name,
+        };
+        let used: HashSet<_> = self
+            .blocks()
+            .flat_map(|bb| self.get_block_name(bb))
+            .collect();
+        (1..)
+            .map(|index| format!("{}.{}{}", base, suffix, index))
+            .find(|name| !used.contains(name.as_str()))
+    }
+
+    /// Transfer the name of a block that is merged into another block.
+    ///
+    /// If `into` is unnamed, it takes over the name of `from`. Passes should
+    /// call this when merging or replacing blocks, such that block names
+    /// survive the optimization pipeline where possible.
+    pub fn inherit_block_name(&mut self, into: Block, from: Block) {
+        if self.get_block_name(into).is_none() {
+            if let Some(name) = self.clear_block_name(from) {
+                self.set_block_name(into, name);
+            }
+        }
+    }
+
     /// Delete a block.
     ///
     /// Removes the block, and all its instructions, from the layout and control
@@ -798,8 +1496,9 @@ impl<'a> UnitBuilder<'a> {
     pub fn delete_block(&mut self, bb: Block) {
         let insts: Vec<_> = self.insts(bb).collect();
         self.remove_block_use(bb);
-        self.remove_block(bb);
+        self.detach_block(bb);
         self.data.cfg.blocks.remove(bb);
+        self.data.cfg.annotations.remove(&bb);
         for inst in insts {
             if self.has_result(inst) {
                 let value = self.inst_result(inst);
@@ -809,6 +1508,95 @@ impl<'a> UnitBuilder<'a> {
         }
     }
 
+    /// Remove a block and its instructions, and clean up the edges into it.
+    ///
+    /// Unlike `delete_block`, this never leaves dangling references to the
+    /// block or its values behind. Conditional branches to the block become
+    /// unconditional branches to their other destination, and phi nodes drop
+    /// their entries for the block. If other instructions refer to the block
+    /// or to values defined in it, for example a `br` or `wait` to the block,
+    /// the unit is left unchanged and these instructions are returned as an
+    /// error. Analyses such as the dominator tree have to be recomputed
+    /// afterwards.
+    pub fn remove_block(&mut self, bb: Block) -> Result<(), Vec<Inst>> {
+        let outside = |unit: &Self, inst: Inst| unit.inst_block(inst) != Some(bb);
+        let branches: BTreeSet<Inst> = self
+            .data
+            .dfg
+            .block_uses
+            .get(&bb)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .filter(|&inst| outside(self, inst))
+            .collect();
+        let mut dangling = BTreeSet::new();
+        for &inst in &branches {
+            let data = &self[inst];
+            let rewritable = match data.opcode() {
+                Opcode::Phi => true,
+                Opcode::BrCond => data.blocks()[0] != data.blocks()[1],
+                _ => false,
+            };
+            if !rewritable {
+                dangling.insert(inst);
+            }
+        }
+        for inst in self.insts(bb).collect::<Vec<_>>() {
+            let value = match self.get_inst_result(inst) {
+                Some(value) => value,
+                None => continue,
+            };
+            for &user in self.uses(value) {
+                if !outside(self, user) {
+                    continue;
+                }
+                // Phi nodes may use the value on the edge from the block.
+                let data = &self[user];
+                let on_edge = data.opcode().is_phi()
+                    && data
+                        .args()
+                        .iter()
+                        .zip(data.blocks())
+                        .all(|(&arg, &from)| arg != value || from == bb);
+                if !on_edge {
+                    dangling.insert(user);
+                }
+            }
+        }
+        if !dangling.is_empty() {
+            return Err(dangling.into_iter().collect());
+        }
+
+        // Replace the conditional branches, keeping the insertion position
+        // where possible.
+        let mut pos = self.pos;
+        for inst in branches {
+            if self[inst].opcode() != Opcode::BrCond {
+                continue;
+            }
+            let other = self[inst].blocks().iter().cloned().find(|&b| b != bb);
+            self.insert_before(inst);
+            let br = self.ins().br(other.unwrap());
+            self.transfer_origins(inst, br);
+            self.delete_inst(inst);
+            pos = match pos {
+                InsertPos::After(i) if i == inst => InsertPos::After(br),
+                InsertPos::Before(i) if i == inst => InsertPos::Before(br),
+                pos => pos,
+            };
+        }
+        self.pos = match pos {
+            InsertPos::Append(b) | InsertPos::Prepend(b) if b == bb => InsertPos::None,
+            InsertPos::After(i) | InsertPos::Before(i) if self.inst_block(i) == Some(bb) => {
+                InsertPos::None
+            }
+            pos => pos,
+        };
+        self.delete_block(bb);
+        Ok(())
+    }
+
     /// Append all following instructions at the end of the unit.
     pub fn insert_at_end(&mut self) {
         self.pos = InsertPos::Append(self.entry());
@@ -840,15 +1628,41 @@ impl<'a> UnitBuilder<'a> {
     }
 
     /// Import an external unit for use within this unit.
+    ///
+    /// The unit is assumed not to be a pure function until the module is
+    /// linked again.
     pub fn add_extern(&mut self, name: UnitName, sig: Signature) -> ExtUnit {
-        self.data.dfg.ext_units.add(ExtUnitData { sig, name })
+        self.data.dfg.ext_units.add(ExtUnitData {
+            sig,
+            name,
+            pure: false,
+        })
+    }
+
+    /// Remove an external unit which is no longer called.
+    ///
+    /// Panics if an instruction still refers to the unit.
+    pub fn remove_extern(&mut self, ext: ExtUnit) {
+        assert!(
+            !self
+                .all_insts()
+                .any(|inst| self[inst].get_ext_unit() == Some(ext)),
+            "external unit {} is still called",
+            self[ext].name
+        );
+        self.data.dfg.ext_units.remove(ext);
     }
 
     /// Remove an instruction if its value is not being read.
     ///
-    /// Returns true if the instruction was removed.
+    /// Instructions annotated with `keep` or `dont_optimize` are never
+    /// removed. Returns true if the instruction was removed.
     pub fn prune_if_unused(&mut self, inst: Inst) -> bool {
-        if self.has_result(inst) && !self.has_uses(self.inst_result(inst)) {
+        if self.has_result(inst)
+            && !self.has_uses(self.inst_result(inst))
+            && !self.inst_has_side_effects(inst)
+            && !self.inst_annotations(inst).is_kept()
+        {
             #[allow(unreachable_patterns)]
             let inst_args: Vec<_> = self[inst]
                 .args()
@@ -865,6 +1679,69 @@ impl<'a> UnitBuilder<'a> {
             false
         }
     }
+
+    /// Get a constant integer from the unit's constant pool.
+    ///
+    /// The constant pool consists of the constants at the very beginning of
+    /// the entry block, which dominate all other instructions. Returns an
+    /// existing constant of the pool if there is one with the same value, or
+    /// adds a new one to the pool otherwise. Don't-care constants are never
+    /// reused.
+    pub fn pooled_const_int(&mut self, value: impl Into<IntValue>) -> Value {
+        let value = value.into();
+        let ty = value.ty();
+        self.pooled_const(
+            InstData::ConstInt {
+                opcode: Opcode::ConstInt,
+                imm: value,
+            },
+            ty,
+        )
+    }
+
+    /// Get a constant time from the unit's constant pool.
+    ///
+    /// See `pooled_const_int` for details.
+    pub fn pooled_const_time(&mut self, value: TimeValue) -> Value {
+        self.pooled_const(
+            InstData::ConstTime {
+                opcode: Opcode::ConstTime,
+                imm: value,
+            },
+            time_ty(),
+        )
+    }
+
+    /// Get a constant enum from the unit's constant pool.
+    ///
+    /// See `pooled_const_int` for details.
+    pub fn pooled_const_enum(&mut self, value: EnumValue) -> Value {
+        let ty = value.ty();
+        self.pooled_const(
+            InstData::ConstEnum {
+                opcode: Opcode::ConstEnum,
+                imm: value,
+            },
+            ty,
+        )
+    }
+
+    fn pooled_const(&mut self, data: InstData, ty: Type) -> Value {
+        let entry = self.entry();
+        let existing = self
+            .insts(entry)
+            .take_while(|&inst| self[inst].opcode().is_const())
+            .find(|&inst| self[inst] == data && !self.inst_annotations(inst).is_dont_care());
+        let inst = match existing {
+            Some(inst) => inst,
+            None => {
+                let inst = self.add_inst_dfg(data, ty);
+                self.prepend_inst(inst, entry);
+                inst
+            }
+        };
+        self.inst_result(inst)
+    }
 }
 
 /// # Control Flow Graph
@@ -888,6 +1765,13 @@ impl<'a> UnitBuilder<'a> {
     pub fn clear_anonymous_block_hint(&mut self, bb: Block) -> Option<u32> {
         self.data.cfg.anonymous_hints.remove(&bb)
     }
+
+    /// Attach annotations to a BB.
+    pub fn annotate_block(&mut self, bb: Block, annotations: Annotations) {
+        if !annotations.is_empty() {
+            *self.data.cfg.annotations.entry(bb).or_default() |= annotations;
+        }
+    }
 }
 
 /// # Data Flow Graph
@@ -917,6 +1801,8 @@ impl<'a> UnitBuilder<'a> {
     fn remove_value(&mut self, value: Value) -> ValueData {
         let data = self.data.dfg.values.remove(value);
         self.data.dfg.value_uses.remove(&value);
+        self.data.dfg.names.remove(&value);
+        self.data.dfg.anonymous_hints.remove(&value);
         data
     }
 
@@ -981,6 +1867,46 @@ impl<'a> UnitBuilder<'a> {
         let data = self.data.dfg.insts.remove(inst);
         self.remove_uses(inst, data);
         self.data.dfg.results.remove(inst);
+        self.data.dfg.location_hints.remove(&inst);
+        self.data.dfg.origins.remove(&inst);
+        self.data.dfg.annotations.remove(&inst);
+        self.data.dfg.branch_weights.remove(&inst);
+        self.data.dfg.resolutions.remove(&inst);
+        self.data.dfg.replications.remove(&inst);
+    }
+
+    /// Add an output argument to the unit's signature.
+    ///
+    /// Returns the value of the new argument. Instantiations of the unit are
+    /// not updated.
+    pub fn add_output(&mut self, ty: Type) -> Value {
+        assert!(!self.is_function(), "functions cannot have outputs");
+        let arg = self.data.sig.add_output(ty.clone());
+        let value = self.add_value(ValueData::Arg { ty, arg });
+        self.data.dfg.args.add(arg, value);
+        value
+    }
+
+    /// Insert an argument into the unit's signature before another one.
+    ///
+    /// The new argument has the same direction as `before`. Returns the value
+    /// of the new argument. Instantiations of the unit are not updated.
+    pub fn insert_arg_before(&mut self, before: Arg, ty: Type) -> Value {
+        let arg = self.data.sig.insert_arg_before(before, ty.clone());
+        let value = self.add_value(ValueData::Arg { ty, arg });
+        self.data.dfg.args.add(arg, value);
+        value
+    }
+
+    /// Remove an unused argument from the unit's signature.
+    ///
+    /// Instantiations of the unit are not updated.
+    pub fn remove_arg(&mut self, arg: Arg) {
+        let value = self.arg_value(arg);
+        assert!(!self.has_uses(value), "argument is still in use");
+        self.remove_value(value);
+        self.data.dfg.args.remove(arg);
+        self.data.sig.remove_arg(arg);
     }
 
     /// Create values for the arguments in a signature.
@@ -1016,8 +1942,24 @@ impl<'a> UnitBuilder<'a> {
 
     /// Replace all uses of a value with another.
     ///
-    /// Returns how many uses were replaced.
+    /// The origins of `from` are added to the origins of `to`. If `from` is
+    /// named and `to` is not, the name is moved over to `to`, such that the
+    /// value remains recognizable. Returns how many uses were replaced.
     pub fn replace_use(&mut self, from: Value, to: Value) -> usize {
+        if !from.is_invalid() && !to.is_invalid() {
+            if let (Some(from_inst), Some(to_inst)) =
+                (self.get_value_inst(from), self.get_value_inst(to))
+            {
+                if from_inst != to_inst {
+                    self.transfer_origins(from_inst, to_inst);
+                }
+            }
+            if from != to && self.get_name(to).is_none() {
+                if let Some(name) = self.clear_name(from) {
+                    self.set_name(to, name);
+                }
+            }
+        }
         let mut count = 0;
         for inst in self
             .data
@@ -1128,6 +2070,70 @@ impl<'a> UnitBuilder<'a> {
     pub fn set_location_hint(&mut self, inst: Inst, loc: usize) {
         self.data.dfg.location_hints.insert(inst, loc);
     }
+
+    /// Add origins to an instruction.
+    ///
+    /// Records that the instruction has been derived from the original
+    /// instructions with the given location hints.
+    pub fn add_origins(&mut self, inst: Inst, origins: impl IntoIterator<Item = usize>) {
+        let mut origins = origins.into_iter().peekable();
+        if origins.peek().is_some() {
+            self.data
+                .dfg
+                .origins
+                .entry(inst)
+                .or_default()
+                .extend(origins);
+        }
+    }
+
+    /// Add the origins of one instruction to another.
+    ///
+    /// Passes call this when `to` replaces `from`, such that `to` can be traced
+    /// back to the instructions `from` was derived from.
+    pub fn transfer_origins(&mut self, from: Inst, to: Inst) {
+        let origins = self.origins(from);
+        self.add_origins(to, origins);
+    }
+
+    /// Attach annotations to an instruction.
+    pub fn annotate_inst(&mut self, inst: Inst, annotations: Annotations) {
+        if !annotations.is_empty() {
+            *self.data.dfg.annotations.entry(inst).or_default() |= annotations;
+        }
+    }
+
+    /// Attach branch weights to a conditional branch.
+    ///
+    /// See `Unit::branch_weights`.
+    pub fn set_branch_weights(&mut self, inst: Inst, weights: [u32; 2]) {
+        assert_eq!(self[inst].opcode(), Opcode::BrCond);
+        self.data.dfg.branch_weights.insert(inst, weights);
+    }
+
+    /// Remove the branch weights from a conditional branch.
+    ///
+    /// Returns the weights that were removed, if any.
+    pub fn clear_branch_weights(&mut self, inst: Inst) -> Option<[u32; 2]> {
+        self.data.dfg.branch_weights.remove(&inst)
+    }
+
+    /// Attach a resolution to a signal created by a `sig` instruction.
+    ///
+    /// See `Unit::signal_resolution`.
+    pub fn set_signal_resolution(&mut self, inst: Inst, resolution: Resolution) {
+        assert_eq!(self[inst].opcode(), Opcode::Sig);
+        self.data.dfg.resolutions.insert(inst, resolution);
+    }
+
+    /// Replicate the entity created by an `inst` instruction.
+    ///
+    /// See `Unit::inst_replication`.
+    pub fn set_inst_replication(&mut self, inst: Inst, count: usize) {
+        assert_eq!(self[inst].opcode(), Opcode::Inst);
+        assert!(count > 0, "replication count must be positive");
+        self.data.dfg.replications.insert(inst, count);
+    }
 }
 
 /// # Basic Block Layout
@@ -1216,8 +2222,10 @@ impl<'a> UnitBuilder<'a> {
         }
     }
 
-    /// Remove a BB from the function.
-    pub fn remove_block(&mut self, bb: Block) {
+    /// Detach a BB from the layout of the function, without deleting it.
+    ///
+    /// Use `remove_block` to delete a block and the edges into it.
+    pub fn detach_block(&mut self, bb: Block) {
         let layout = &mut self.data.layout;
         let node = layout.bbs.remove(bb).unwrap();
         if let Some(next) = node.next {
@@ -1441,3 +2449,234 @@ mod static_checks {
         (u, ub)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembly::parse_module,
+        ir::{RegControl, Unit, UnitData, UnitKind, UnitName, UseKind},
+        opt::{Pass, PassContext},
+        pass::ConstFolding,
+    };
+
+    #[test]
+    fn origins_survive_folding() {
+        let input = "func @foo () i32 {\n%entry:\n    %a = const i32 1\n    %b = const i32 2\n    %c = add i32 %a, %b\n    ret i32 %c\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let unit = module.units().next().unwrap();
+        let add = unit.all_insts().nth(2).unwrap();
+        let add_loc = unit.location_hint(add).unwrap();
+        assert_eq!(&input[add_loc..add_loc + 2], "%c");
+
+        ConstFolding::run_on_module(&PassContext::default(), &mut module);
+        let unit = module.units().next().unwrap();
+        let ret = unit.terminator(unit.entry());
+        let value = unit[ret].args()[0];
+        assert!(unit.get_const_int(value).is_some());
+        assert!(unit.value_origins(value).contains(&add_loc));
+    }
+
+    #[test]
+    fn names_follow_replacement() {
+        let input = "func @foo (i32 %x) i32 {\n%entry:\n    %a = const i32 1\n    %b = add i32 %x, %a\n    %c = not i32 %b\n    ret i32 %c\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let id = module.units().next().unwrap().id();
+        let mut unit = module.unit_mut(id);
+        let insts: Vec<_> = unit.all_insts().collect();
+        let (a, b, c) = (
+            unit.inst_result(insts[0]),
+            unit.inst_result(insts[1]),
+            unit.inst_result(insts[2]),
+        );
+
+        // The name moves over to an unnamed replacement.
+        unit.insert_before(insts[1]);
+        let x = unit.input_arg(0);
+        let mul = unit.ins().umul(x, a);
+        unit.replace_use(b, mul);
+        assert_eq!(unit.get_name(mul), Some("b"));
+        assert_eq!(unit.get_name(b), None);
+
+        // Replacements which are already named keep their name.
+        unit.replace_use(c, a);
+        assert_eq!(unit.get_name(a), Some("a"));
+        assert_eq!(unit.get_name(c), Some("c"));
+    }
+
+    #[test]
+    fn detailed_uses() {
+        let input = "entity @foo (i1$ %clk, i8$ %d) -> (i8$ %q) {\n    %init = const i8 0\n    %t = const time 1ns\n    %c = prb i1$ %clk\n    %v = prb i8$ %d\n    reg i8$ %q init %init, [%v, rise %c, if %c]\n    drv i8$ %d, %v, %t\n}\n";
+        let module = parse_module(input).unwrap();
+        let unit = module.units().next().unwrap();
+        let kinds = |value| {
+            unit.uses_detailed(value)
+                .into_iter()
+                .map(|u| u.kind)
+                .collect::<Vec<_>>()
+        };
+        let insts: Vec<_> = unit.all_insts().collect();
+        let (init, t, c, v) = (
+            unit.inst_result(insts[0]),
+            unit.inst_result(insts[1]),
+            unit.inst_result(insts[2]),
+            unit.inst_result(insts[3]),
+        );
+        assert_eq!(kinds(init), vec![UseKind::RegControl(RegControl::Init)]);
+        assert_eq!(kinds(t), vec![UseKind::Delay]);
+        assert_eq!(kinds(c), vec![UseKind::RegTrigger(0), UseKind::RegGate(0)]);
+        assert_eq!(kinds(v), vec![UseKind::RegData(0), UseKind::Source]);
+        assert_eq!(
+            kinds(unit.input_arg(1)),
+            vec![UseKind::Operand, UseKind::Target]
+        );
+        assert_eq!(kinds(unit.output_arg(0)), vec![UseKind::Target]);
+    }
+
+    #[test]
+    fn convert_function_to_entity() {
+        let input = "func @foo (i32 %a, i8$ %b) i32 {\n%entry:\n    ret i32 %a\n}\n";
+        let module = parse_module(input).unwrap();
+        let unit = module.units().next().unwrap();
+        let entity = UnitData::new_converted(UnitKind::Entity, unit);
+        assert_eq!(entity.sig.to_string(), "(i32$, i8$) -> (i32$)");
+        let ret = unit.sig().converted_return_arg(&entity.sig).unwrap();
+        assert!(entity.sig.is_output(ret));
+        let entity = crate::ir::Unit::new_anonymous(&entity);
+        assert_eq!(entity.get_name(entity.input_arg(0)), Some("a"));
+        assert_eq!(entity.get_name(entity.input_arg(1)), Some("b"));
+        assert_eq!(entity.get_name(entity.output_arg(0)), None);
+    }
+
+    #[test]
+    fn split_block_names() {
+        let input = "func @foo (i32 %a) i32 {\naux:\n    %b = not i32 %a\n    %c = neg i32 %b\n    ret i32 %c\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let id = module.units().next().unwrap().id();
+        let mut unit = module.unit_mut(id);
+        let insts: Vec<_> = unit.all_insts().collect();
+        let bb1 = unit.split_block(insts[1]);
+        let bb2 = unit.split_block(insts[2]);
+        assert_eq!(unit.get_block_name(bb1), Some("aux.split1"));
+        assert_eq!(unit.get_block_name(bb2), Some("aux.split2"));
+        assert_eq!(unit.blocks().count(), 3);
+
+        // The names survive a round trip through the printer and parser.
+        let output = crate::assembly::write_module_string(&module);
+        let module = parse_module(&output).unwrap();
+        let unit = module.units().next().unwrap();
+        let names: Vec<_> = unit
+            .blocks()
+            .map(|bb| unit.get_block_name(bb).unwrap())
+            .collect();
+        assert_eq!(names, vec!["aux", "aux.split1", "aux.split2"]);
+    }
+
+    #[test]
+    fn remove_block_cleans_up_edges() {
+        let input = "func @foo (i1 %c, i32 %x, i32 %y) i32 {\n%entry:\n    br %c, %a, %b\n%a:\n    %z = not i32 %x\n    br %join\n%b:\n    br %join\n%join:\n    %r = phi i32 [%z, %a], [%y, %b]\n    ret i32 %r\n}\n";
+        let mut module = parse_module(input).unwrap();
+        let id = module.units().next().unwrap().id();
+        let mut unit = module.unit_mut(id);
+        let blocks: Vec<_> = unit.blocks().collect();
+        let (a, b, join) = (blocks[1], blocks[2], blocks[3]);
+
+        // Unconditional branches cannot be rewritten.
+        let before = unit.to_string();
+        let branches = vec![unit.terminator(a), unit.terminator(b)];
+        assert_eq!(unit.remove_block(join), Err(branches));
+        assert_eq!(unit.to_string(), before);
+
+        // Conditional branches and phi nodes drop the edge.
+        assert_eq!(unit.remove_block(a), Ok(()));
+        assert_eq!(
+            unit.to_string(),
+            "func @foo (i1 %c, i32 %x, i32 %y) i32 {\n%entry:\n    br %b\n%b:\n    br %join\n%join:\n    %r = phi i32 [%y, %b]\n    ret %r\n}"
+        );
+    }
+
+    #[test]
+    fn unit_names_round_trip() {
+        let names = vec![
+            (UnitName::global("foo.bar_1"), "@foo.bar_1"),
+            (UnitName::anonymous(42), "%42"),
+            (UnitName::local("42"), "%\"42\""),
+            (UnitName::global("foo bar"), "@\"foo bar\""),
+            (UnitName::local("mü"), "%\"mü\""),
+            (UnitName::global("a\"b\\c\n"), "@\"a\\\"b\\\\c\\0a\""),
+            (UnitName::global(""), "@\"\""),
+        ];
+        for (name, printed) in names {
+            assert_eq!(name.to_string(), printed);
+            assert_eq!(printed.parse::<UnitName>(), Ok(name));
+        }
+        assert!("foo".parse::<UnitName>().is_err());
+        assert!("@foo bar".parse::<UnitName>().is_err());
+        assert!("@\"\\x\"".parse::<UnitName>().is_err());
+
+        assert_eq!(UnitName::try_global("foo"), Ok(UnitName::global("foo")));
+        assert!(UnitName::try_global("foo bar").is_err());
+        assert!(UnitName::try_local("42").is_err());
+        assert!(UnitName::try_local("").is_err());
+    }
+
+    #[test]
+    fn extract_cone() {
+        let module = parse_module(
+            "
+            proc @foo (i8$ %a, i8$ %b) -> (i8$ %q) {
+            %entry:
+                %ap = prb i8$ %a
+                %bp = prb i8$ %b
+                %one = const i8 1
+                %sum = add i8 %ap, %one
+                %prod = umul i8 %sum, %bp
+                %other = sub i8 %ap, %bp
+                %x = xor i8 %prod, %sum
+                %t = const time 1ns
+                drv i8$ %q, %x, %t
+                wait %entry, %a, %b
+            }
+            ",
+        )
+        .unwrap();
+        let unit = module.units().next().unwrap();
+        let x = unit
+            .all_insts()
+            .filter_map(|inst| unit.get_inst_result(inst))
+            .find(|&v| unit.get_name(v) == Some("x"))
+            .unwrap();
+        let cone = unit.extract_cone(x);
+        assert_eq!(
+            cone.data.to_string(),
+            indoc::indoc!(
+                "
+                func @foo.x (i8 %ap, i8 %bp) i8 {
+                entry:
+                    %one = const i8 1
+                    %sum = add i8 %ap, %one
+                    %prod = umul i8 %sum, %bp
+                    %x = xor i8 %prod, %sum
+                    ret i8 %x
+                }"
+            )
+        );
+        let cone_unit = Unit::new_anonymous(&cone.data);
+        cone_unit.verify();
+        let origin = |name| {
+            let value = cone_unit
+                .args()
+                .chain(
+                    cone_unit
+                        .all_insts()
+                        .filter_map(|i| cone_unit.get_inst_result(i)),
+                )
+                .find(|&v| cone_unit.get_name(v) == Some(name))
+                .unwrap();
+            unit.get_name(cone.values[&value])
+        };
+        assert_eq!(origin("ap"), Some("ap"));
+        assert_eq!(origin("x"), Some("x"));
+        assert_eq!(cone.insts.len(), 4);
+        assert!(cone.insts.values().all(|&inst| unit.is_pure_inst(inst)));
+    }
+}
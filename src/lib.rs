@@ -13,12 +13,31 @@ extern crate serde;
 #[macro_use]
 pub mod assembly;
 pub mod analysis;
+#[cfg(feature = "llhd-capi")]
+pub mod capi;
+pub mod config;
+pub mod constraints;
+pub mod dot;
+#[cfg(feature = "filecheck")]
+pub mod filecheck;
+pub mod graph;
 pub mod ir;
+pub mod netlist;
 pub mod opt;
 pub mod pass;
+pub mod patch;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod reduce;
+pub mod sim;
+pub mod stdcells;
 pub mod table;
+pub mod trace;
 pub mod ty;
 pub mod value;
 pub mod verifier;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use crate::{ty::*, value::*};
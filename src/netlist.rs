@@ -0,0 +1,422 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Construction of entities from netlists.
+//!
+//! This module provides a high-level way of creating an entity from a list of
+//! ports, cell instantiations, and connections between nets. Signals, `inst`,
+//! and `con` instructions, as well as value names, are created automatically.
+//!
+//! ```
+//! use llhd::{int_ty, ir::{Module, Signature, UnitName}, netlist::NetlistBuilder};
+//!
+//! let mut module = Module::new();
+//! let mut sig = Signature::new();
+//! sig.add_input(llhd::signal_ty(int_ty(1)));
+//! sig.add_output(llhd::signal_ty(int_ty(1)));
+//! module.declare(UnitName::global("inv"), sig);
+//!
+//! let mut nl = NetlistBuilder::new(UnitName::global("top"));
+//! nl.input("a", int_ty(1))
+//!     .output("z", int_ty(1))
+//!     .cell(UnitName::global("inv"), &["a"], &["n"])
+//!     .cell(UnitName::global("inv"), &["n"], &["z"]);
+//! nl.build(&mut module).unwrap();
+//! ```
+
+use crate::{
+    ir::{prelude::*, LinkedUnit},
+    ty::{has_zero, signal_ty, Type},
+};
+use std::collections::HashMap;
+
+/// A builder for an entity described as a netlist.
+///
+/// Nets are identified by name. Ports of the entity and nets declared with
+/// `net()` are available under their name; any other net referenced by a cell
+/// or connection becomes a local signal whose type is inferred from its use.
+#[derive(Debug, Clone)]
+pub struct NetlistBuilder {
+    name: UnitName,
+    inputs: Vec<(String, Type)>,
+    outputs: Vec<(String, Type)>,
+    nets: Vec<(String, Type)>,
+    cells: Vec<Cell>,
+    connections: Vec<(String, String)>,
+}
+
+/// An instantiation of a cell in a netlist.
+#[derive(Debug, Clone)]
+struct Cell {
+    unit: UnitName,
+    ports: Ports,
+}
+
+/// The nets connected to the ports of a cell.
+#[derive(Debug, Clone)]
+enum Ports {
+    /// The nets connected to the inputs and outputs, in order.
+    Positional {
+        inputs: Vec<String>,
+        outputs: Vec<String>,
+    },
+    /// The nets connected to each port, by port name.
+    Named(Vec<(String, String)>),
+}
+
+impl NetlistBuilder {
+    /// Create a new netlist for an entity with the given name.
+    pub fn new(name: UnitName) -> Self {
+        Self {
+            name,
+            inputs: vec![],
+            outputs: vec![],
+            nets: vec![],
+            cells: vec![],
+            connections: vec![],
+        }
+    }
+
+    /// Add an input port carrying values of type `ty`.
+    pub fn input(&mut self, name: impl Into<String>, ty: Type) -> &mut Self {
+        self.inputs.push((name.into(), ty));
+        self
+    }
+
+    /// Add an output port carrying values of type `ty`.
+    pub fn output(&mut self, name: impl Into<String>, ty: Type) -> &mut Self {
+        self.outputs.push((name.into(), ty));
+        self
+    }
+
+    /// Declare a local net carrying values of type `ty`.
+    ///
+    /// This is only necessary for nets whose type cannot be inferred from the
+    /// cells they connect to.
+    pub fn net(&mut self, name: impl Into<String>, ty: Type) -> &mut Self {
+        self.nets.push((name.into(), ty));
+        self
+    }
+
+    /// Instantiate a cell, connecting its ports to the given nets in order.
+    ///
+    /// The cell must be defined or declared in the module the netlist is built
+    /// into.
+    pub fn cell(&mut self, unit: UnitName, inputs: &[&str], outputs: &[&str]) -> &mut Self {
+        self.cells.push(Cell {
+            unit,
+            ports: Ports::Positional {
+                inputs: inputs.iter().map(|s| s.to_string()).collect(),
+                outputs: outputs.iter().map(|s| s.to_string()).collect(),
+            },
+        });
+        self
+    }
+
+    /// Instantiate a cell, connecting its ports to the given nets by name.
+    ///
+    /// Each entry of `ports` is the name of a port and the net it connects to.
+    /// The port names are resolved against the argument names of the cell,
+    /// which must be defined or declared with argument names in the module
+    /// the netlist is built into. Each port must be connected exactly once.
+    pub fn cell_by_name(&mut self, unit: UnitName, ports: &[(&str, &str)]) -> &mut Self {
+        self.cells.push(Cell {
+            unit,
+            ports: Ports::Named(
+                ports
+                    .iter()
+                    .map(|(port, net)| (port.to_string(), net.to_string()))
+                    .collect(),
+            ),
+        });
+        self
+    }
+
+    /// Connect two nets, such that they become one.
+    pub fn connect(&mut self, a: impl Into<String>, b: impl Into<String>) -> &mut Self {
+        self.connections.push((a.into(), b.into()));
+        self
+    }
+
+    /// Build the entity and add it to a module.
+    pub fn build(&self, module: &mut Module) -> Result<UnitId, String> {
+        let sigs: HashMap<UnitName, Signature> = module
+            .symbols()
+            .map(|(name, _, sig)| (name.clone(), sig.clone()))
+            .collect();
+
+        // Create the entity with the requested ports.
+        let mut sig = Signature::new();
+        let input_args: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|(_, ty)| sig.add_input(signal_ty(ty.clone())))
+            .collect();
+        let output_args: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|(_, ty)| sig.add_output(signal_ty(ty.clone())))
+            .collect();
+        let mut data = UnitData::new(UnitKind::Entity, self.name.clone(), sig);
+        let mut builder = UnitBuilder::new_anonymous(&mut data);
+        let mut nets = Nets {
+            builder: &mut builder,
+            values: HashMap::new(),
+        };
+        let ports = self.inputs.iter().zip(input_args);
+        let ports = ports.chain(self.outputs.iter().zip(output_args));
+        for ((name, _), arg) in ports {
+            let value = nets.builder.arg_value(arg);
+            nets.define(name, value)?;
+        }
+        for (name, ty) in &self.nets {
+            let value = nets.signal(name, &signal_ty(ty.clone()))?;
+            nets.define(name, value)?;
+        }
+
+        // Instantiate the cells.
+        let mut ext_units = HashMap::new();
+        for cell in &self.cells {
+            let cell_sig = sigs
+                .get(&cell.unit)
+                .ok_or_else(|| format!("unknown cell {}", cell.unit))?;
+            let (cell_inputs, cell_outputs) = match &cell.ports {
+                Ports::Positional { inputs, outputs } => (inputs.clone(), outputs.clone()),
+                Ports::Named(ports) => resolve_ports(module, &cell.unit, ports)?,
+            };
+            if cell_sig.inputs().count() != cell_inputs.len()
+                || cell_sig.outputs().count() != cell_outputs.len()
+            {
+                return Err(format!(
+                    "cell {} has signature {}, but is connected to {} inputs and {} outputs",
+                    cell.unit,
+                    cell_sig,
+                    cell_inputs.len(),
+                    cell_outputs.len()
+                ));
+            }
+            let inputs = cell_sig
+                .inputs()
+                .zip(cell_inputs.iter())
+                .map(|(arg, net)| nets.get(net, &cell_sig.arg_type(arg)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let outputs = cell_sig
+                .outputs()
+                .zip(cell_outputs.iter())
+                .map(|(arg, net)| nets.get(net, &cell_sig.arg_type(arg)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let ext_unit = *ext_units
+                .entry(cell.unit.clone())
+                .or_insert_with(|| nets.builder.add_extern(cell.unit.clone(), cell_sig.clone()));
+            nets.builder.ins().inst(ext_unit, inputs, outputs);
+        }
+
+        // Connect the nets.
+        for (a, b) in &self.connections {
+            let ty = match (nets.values.get(a), nets.values.get(b)) {
+                (Some(&v), _) | (_, Some(&v)) => nets.builder.value_type(v),
+                (None, None) => return Err(format!("cannot infer type of nets {} and {}", a, b)),
+            };
+            let a = nets.get(a, &ty)?;
+            let b = nets.get(b, &ty)?;
+            nets.builder.ins().con(a, b);
+        }
+
+        Ok(module.add_unit(data))
+    }
+}
+
+/// The nets of a netlist under construction.
+struct Nets<'a, 'b> {
+    builder: &'a mut UnitBuilder<'b>,
+    values: HashMap<String, Value>,
+}
+
+impl Nets<'_, '_> {
+    /// Associate a net name with a value.
+    fn define(&mut self, name: &str, value: Value) -> Result<(), String> {
+        if self.values.insert(name.to_string(), value).is_some() {
+            return Err(format!("net {} defined multiple times", name));
+        }
+        self.builder.set_name(value, name.to_string());
+        Ok(())
+    }
+
+    /// Get the value of a net, creating a local signal if necessary.
+    fn get(&mut self, name: &str, ty: &Type) -> Result<Value, String> {
+        if let Some(&value) = self.values.get(name) {
+            let actual = self.builder.value_type(value);
+            if actual != *ty {
+                return Err(format!(
+                    "net {} is of type {}, but used as {}",
+                    name, actual, ty
+                ));
+            }
+            return Ok(value);
+        }
+        let value = self.signal(name, ty)?;
+        self.define(name, value)?;
+        Ok(value)
+    }
+
+    /// Create a local signal initialized to zero.
+    fn signal(&mut self, name: &str, ty: &Type) -> Result<Value, String> {
+        if !ty.is_signal() || !has_zero(ty.unwrap_signal()) {
+            return Err(format!("net {} cannot be of type {}", name, ty));
+        }
+        let init = self.builder.ins().const_zero(ty.unwrap_signal());
+        Ok(self.builder.ins().sig(init))
+    }
+}
+
+/// Order the nets connected to the ports of a cell by name.
+///
+/// Returns the nets connected to the inputs and outputs of the cell, in order.
+fn resolve_ports(
+    module: &Module,
+    unit: &UnitName,
+    ports: &[(String, String)],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let (sig, names): (_, Vec<_>) = match module.lookup(unit) {
+        Some(LinkedUnit::Def(id)) => {
+            let cell = module.unit(id);
+            let names = cell
+                .sig()
+                .args()
+                .map(|arg| cell.get_name(cell.arg_value(arg)))
+                .collect();
+            (cell.sig(), names)
+        }
+        Some(LinkedUnit::Decl(id)) => {
+            let decl = &module[id];
+            let names = decl
+                .sig
+                .args()
+                .map(|arg| decl.arg_names.get(&arg).map(String::as_str))
+                .collect();
+            (&decl.sig, names)
+        }
+        None => return Err(format!("unknown cell {}", unit)),
+    };
+    let mut nets = vec![None; names.len()];
+    for (port, net) in ports {
+        let index = names
+            .iter()
+            .position(|&name| name == Some(port.as_str()))
+            .ok_or_else(|| format!("cell {} has no port {}", unit, port))?;
+        if nets[index].is_some() {
+            return Err(format!(
+                "port {} of cell {} connected multiple times",
+                port, unit
+            ));
+        }
+        nets[index] = Some(net.clone());
+    }
+    let unconnected: Vec<_> = names
+        .iter()
+        .zip(nets.iter())
+        .enumerate()
+        .filter(|(_, (_, net))| net.is_none())
+        .map(|(index, (name, _))| match name {
+            Some(name) => name.to_string(),
+            None => format!("#{}", index),
+        })
+        .collect();
+    if !unconnected.is_empty() {
+        return Err(format!(
+            "ports {} of cell {} not connected",
+            unconnected.join(", "),
+            unit
+        ));
+    }
+    let mut nets: Vec<String> = nets.into_iter().flatten().collect();
+    let outputs = nets.split_off(sig.inputs().count());
+    Ok((nets, outputs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{int_ty, verifier::Verifier};
+
+    #[test]
+    fn build_inverter_chain() {
+        let mut module = Module::new();
+        let mut sig = Signature::new();
+        sig.add_input(signal_ty(int_ty(1)));
+        sig.add_output(signal_ty(int_ty(1)));
+        module.declare(UnitName::global("inv"), sig);
+
+        let mut nl = NetlistBuilder::new(UnitName::global("top"));
+        nl.input("a", int_ty(1))
+            .output("z", int_ty(1))
+            .net("m", int_ty(1))
+            .cell(UnitName::global("inv"), &["a"], &["n"])
+            .cell(UnitName::global("inv"), &["n"], &["m"])
+            .connect("m", "z");
+        nl.build(&mut module).unwrap();
+        module.link();
+
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&module);
+        verifier.finish().unwrap();
+        let text = module.dump().to_string();
+        assert!(text.contains("entity @top (i1$ %a) -> (i1$ %z) {"));
+        assert!(text.contains("%n = sig i1$ %"));
+        assert!(text.contains("inst @inv (%a) -> (%n)"));
+        assert!(text.contains("inst @inv (%n) -> (%m)"));
+        assert!(text.contains("con %m, %z"));
+    }
+
+    #[test]
+    fn reject_mismatched_cell() {
+        let mut module = Module::new();
+        let mut sig = Signature::new();
+        sig.add_input(signal_ty(int_ty(1)));
+        sig.add_output(signal_ty(int_ty(1)));
+        module.declare(UnitName::global("inv"), sig);
+
+        let mut nl = NetlistBuilder::new(UnitName::global("top"));
+        nl.input("a", int_ty(8))
+            .cell(UnitName::global("inv"), &["a"], &["n"]);
+        assert!(nl.build(&mut module).is_err());
+        let mut nl = NetlistBuilder::new(UnitName::global("top"));
+        nl.cell(UnitName::global("nand"), &["a", "b"], &["n"]);
+        assert!(nl.build(&mut module).is_err());
+    }
+
+    #[test]
+    fn connect_ports_by_name() {
+        let mut module = crate::assembly::parse_module(
+            "entity @and (i1$ %a, i1$ %b) -> (i1$ %z) {}\ndeclare @inv (i1$ %a) -> (i1$)",
+        )
+        .unwrap();
+        let cell = UnitName::global("and");
+
+        let mut nl = NetlistBuilder::new(UnitName::global("top"));
+        nl.input("x", int_ty(1))
+            .input("y", int_ty(1))
+            .output("q", int_ty(1))
+            .cell_by_name(cell.clone(), &[("z", "q"), ("b", "x"), ("a", "y")]);
+        nl.build(&mut module).unwrap();
+        let text = module.dump().to_string();
+        assert!(text.contains("inst @and (%y, %x) -> (%q)"));
+
+        let mut check = |ports: &[(&str, &str)], error: &str| {
+            let mut nl = NetlistBuilder::new(UnitName::global("bad"));
+            nl.cell_by_name(cell.clone(), ports);
+            assert_eq!(nl.build(&mut module), Err(error.to_string()));
+        };
+        check(&[("a", "x"), ("c", "y")], "cell @and has no port c");
+        check(
+            &[("a", "x"), ("a", "y")],
+            "port a of cell @and connected multiple times",
+        );
+        check(&[("b", "x")], "ports a, z of cell @and not connected");
+        let mut nl = NetlistBuilder::new(UnitName::global("bad"));
+        nl.cell_by_name(UnitName::global("inv"), &[("a", "x")]);
+        assert_eq!(
+            nl.build(&mut module),
+            Err("ports #1 of cell @inv not connected".to_string())
+        );
+    }
+}
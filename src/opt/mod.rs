@@ -6,8 +6,12 @@
 //! operates on LLHD IR.
 
 mod pass;
+mod profile;
+mod speculate;
 
 pub use pass::*;
+pub use profile::*;
+pub use speculate::*;
 
 pub mod prelude {
     pub use super::pass::*;
@@ -1,7 +1,17 @@
 // Copyright (c) 2017-2020 Fabian Schuiki
 
-use crate::ir::prelude::*;
+use crate::{
+    analysis::ModuleStats,
+    config::UnitPattern,
+    ir::prelude::*,
+    opt::Profile,
+    pass::{
+        BlockOrder, ExtensionPolicy, FanoutLimits, FlattenLimits, PartitionLimits, ProbeFilter,
+    },
+};
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
+use std::sync::Mutex;
 
 /// An optimization pass.
 ///
@@ -11,11 +21,43 @@ use rayon::prelude::*;
 /// `run_*()` functions.
 pub trait Pass {
     /// Run this pass on an entire module.
+    ///
+    /// With the `tracing` feature, each unit is processed within a `unit` span
+    /// which records the name of the unit and whether it was modified.
     fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
-        module
-            .par_units_mut()
-            .map(|mut unit| Self::run_on_unit(ctx, &mut unit))
-            .reduce(|| false, |a, b| a || b)
+        #[cfg(feature = "tracing")]
+        let parent = tracing::Span::current();
+        #[cfg(feature = "parallel")]
+        let units = module.par_units_mut_untracked();
+        #[cfg(not(feature = "parallel"))]
+        let units = module.units_mut_untracked();
+        let modified: Vec<_> = units
+            .filter_map(|mut unit| {
+                if !ctx.may_modify(unit.name()) {
+                    return None;
+                }
+                #[cfg(feature = "tracing")]
+                let span = tracing::info_span!(
+                    parent: &parent,
+                    "unit",
+                    name = %unit.name(),
+                    modified = tracing::field::Empty
+                )
+                .entered();
+                let modified = Self::run_on_unit(ctx, &mut unit);
+                #[cfg(feature = "tracing")]
+                span.record("modified", modified);
+                if modified {
+                    Some(unit.id())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for &unit in &modified {
+            module.mark_dirty(unit);
+        }
+        !modified.is_empty()
     }
 
     /// Run this pass on an entire unit.
@@ -23,7 +65,11 @@ pub trait Pass {
         Self::run_on_cfg(ctx, data)
     }
 
-    /// Run this pass on an entire function or process.
+    /// Run this pass on an entire function, process, or entity.
+    ///
+    /// All kinds of units are represented by the same `UnitBuilder`, such
+    /// that passes only need to check `UnitBuilder::kind` where they treat
+    /// the kinds differently.
     fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
         let mut modified = false;
         let insts: Vec<_> = unit.all_insts().collect();
@@ -41,4 +87,121 @@ pub trait Pass {
 }
 
 /// Additional context and configuration for optimizations.
-pub struct PassContext;
+#[derive(Debug, Default)]
+pub struct PassContext {
+    /// The instructions to be probed by debug probe insertion.
+    pub probe_filter: ProbeFilter,
+    /// The thresholds for hierarchy flattening and function inlining.
+    pub flatten_limits: FlattenLimits,
+    /// The thresholds for entity partitioning.
+    pub partition_limits: PartitionLimits,
+    /// The thresholds for fan-out duplication.
+    pub fanout_limits: FanoutLimits,
+    /// How width legalization extends operands of mismatching width.
+    pub extension_policy: ExtensionPolicy,
+    /// The width of the words which word lowering decomposes wider integer
+    /// operations into. Defaults to 64 bits.
+    pub word_width: Option<usize>,
+    /// The thresholds beyond which expensive passes skip a unit.
+    pub pass_limits: PassLimits,
+    /// How block ordering arranges the blocks of a unit.
+    pub block_order: BlockOrder,
+    /// The units which the pass must leave untouched.
+    pub frozen_units: Vec<UnitPattern>,
+    /// The execution counts which guide profile-aware passes. Empty unless
+    /// loaded, e.g. with `Profile::load`.
+    pub profile: Profile,
+    /// The units skipped by passes so far.
+    skipped_units: Mutex<Vec<SkippedUnit>>,
+}
+
+/// Thresholds beyond which expensive passes skip a unit.
+///
+/// Generated units may be large enough for the superlinear parts of some
+/// passes to run for hours. Such units are left untouched by the pass with a
+/// warning instead, and recorded in the `PassContext` to be reported.
+#[derive(Debug, Clone)]
+pub struct PassLimits {
+    /// Maximum number of instructions of a unit for GCSE to run on it.
+    pub gcse_max_insts: usize,
+    /// Maximum number of instructions of a unit for TCM to run on it.
+    pub tcm_max_insts: usize,
+    /// Maximum number of steps up the dominator tree TCM takes in a unit while
+    /// looking for the conditions under which its drives execute. The
+    /// remaining drives stay in place once the limit is reached.
+    pub tcm_max_dom_steps: usize,
+}
+
+impl Default for PassLimits {
+    fn default() -> Self {
+        Self {
+            gcse_max_insts: 200_000,
+            tcm_max_insts: 200_000,
+            tcm_max_dom_steps: 10_000_000,
+        }
+    }
+}
+
+/// A unit which a pass skipped, entirely or in part, due to `PassLimits`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedUnit {
+    /// The name of the unit.
+    pub unit: UnitName,
+    /// The command line name of the pass, e.g. `gcse`.
+    pub pass: &'static str,
+    /// The limit which the unit exceeds.
+    pub reason: String,
+}
+
+impl PassContext {
+    /// Compute the size statistics of the units in a module.
+    ///
+    /// Module-level passes use these to decide whether a transformation which
+    /// duplicates units, such as flattening, is worthwhile.
+    pub fn stats(&self, module: &Module) -> ModuleStats {
+        ModuleStats::new(module)
+    }
+
+    /// Check whether the pass may modify a unit.
+    pub fn may_modify(&self, name: &UnitName) -> bool {
+        !self
+            .frozen_units
+            .iter()
+            .any(|pattern| pattern.matches(name))
+    }
+
+    /// Check whether a unit has more instructions than a pass allows.
+    ///
+    /// If it does, the unit is recorded as skipped by the pass.
+    pub fn exceeds_inst_limit(&self, unit: &Unit, pass: &'static str, limit: usize) -> bool {
+        let num_insts = unit.all_insts().count();
+        if num_insts <= limit {
+            return false;
+        }
+        self.skip_unit(
+            unit.name(),
+            pass,
+            format!("{} instructions exceed limit of {}", num_insts, limit),
+        );
+        true
+    }
+
+    /// Record that a pass skipped a unit, and emit a warning.
+    pub fn skip_unit(&self, unit: &UnitName, pass: &'static str, reason: String) {
+        warn!("Skipping {} in {}: {}", unit, pass, reason);
+        let skipped = SkippedUnit {
+            unit: unit.clone(),
+            pass,
+            reason,
+        };
+        let mut units = self.skipped_units.lock().unwrap();
+        if !units.contains(&skipped) {
+            units.push(skipped);
+        }
+    }
+
+    /// Get the units skipped by passes so far, in the order they were skipped.
+    pub fn skipped_units(&self) -> Vec<SkippedUnit> {
+        self.skipped_units.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Execution profiles
+//!
+//! This module implements execution counts recorded by a simulator, which
+//! passes may consult to make profile-aware decisions, and a small text format
+//! to read them from a file.
+
+use crate::ir::prelude::*;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Execution counts of the units, blocks, and instructions of a module.
+///
+/// Blocks are identified by the name of their unit and their own name. Counts
+/// recorded for instructions are keyed by their location in the input file,
+/// and are found through the origins of an instruction, such that they remain
+/// attached to the instructions derived from the original ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The number of times each unit has executed, e.g. how often a function
+    /// has been called.
+    pub units: HashMap<UnitName, u64>,
+    /// The number of times each block has executed, by unit and block name.
+    pub blocks: HashMap<(UnitName, String), u64>,
+    /// The number of times the instruction at each location in the input has
+    /// executed.
+    pub locations: HashMap<usize, u64>,
+}
+
+impl Profile {
+    /// Create an empty profile.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Check whether the profile contains no counts at all.
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty() && self.blocks.is_empty() && self.locations.is_empty()
+    }
+
+    /// Read a profile from a file.
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        parse_profile(&input).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Get the number of times a unit has executed.
+    pub fn unit_count(&self, name: &UnitName) -> Option<u64> {
+        self.units.get(name).cloned()
+    }
+
+    /// Get the number of times a block has executed.
+    ///
+    /// Uses the count recorded for the block's name if there is one, and
+    /// otherwise the highest count of the block's instructions.
+    pub fn block_count(&self, unit: &Unit, bb: Block) -> Option<u64> {
+        if let Some(name) = unit.get_block_name(bb) {
+            let key = (unit.name().clone(), name.to_string());
+            if let Some(&count) = self.blocks.get(&key) {
+                return Some(count);
+            }
+        }
+        unit.insts(bb)
+            .flat_map(|inst| self.location_count(unit, inst))
+            .max()
+    }
+
+    /// Get the number of times an instruction has executed.
+    ///
+    /// Uses the highest count recorded for the origins of the instruction,
+    /// and otherwise the count of the block it resides in.
+    pub fn inst_count(&self, unit: &Unit, inst: Inst) -> Option<u64> {
+        self.location_count(unit, inst).or_else(|| {
+            unit.inst_block(inst)
+                .and_then(|bb| self.block_count(unit, bb))
+        })
+    }
+
+    /// Get the highest count recorded for the origins of an instruction.
+    fn location_count(&self, unit: &Unit, inst: Inst) -> Option<u64> {
+        unit.origins(inst)
+            .into_iter()
+            .flat_map(|loc| self.locations.get(&loc).cloned())
+            .max()
+    }
+}
+
+/// Parse a profile.
+///
+/// Each line of the `input` contains one of the following, where `#` starts a
+/// comment that extends to the end of the line:
+///
+/// - `@<unit> <count>` gives the number of times a unit has executed.
+/// - `@<unit> %<block> <count>` gives the number of times a block has
+///   executed.
+/// - `loc <offset> <count>` gives the number of times the instruction at a
+///   byte offset in the input file has executed.
+///
+/// For example:
+///
+/// ```text
+/// @abs 1000
+/// @abs %flip 12
+/// loc 184 988
+/// ```
+pub fn parse_profile(input: impl AsRef<str>) -> Result<Profile, String> {
+    let mut profile = Profile::new();
+    for (index, line) in input.as_ref().lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let words: Vec<_> = line.split_whitespace().collect();
+        let err = |msg: String| format!("line {}: {}", index + 1, msg);
+        let count = |word: &str| {
+            word.parse::<u64>()
+                .map_err(|_| err(format!("invalid count `{}`", word)))
+        };
+        match words.as_slice() {
+            [] => (),
+            ["loc", offset, n] => {
+                let offset = offset
+                    .parse()
+                    .map_err(|_| err(format!("invalid location `{}`", offset)))?;
+                profile.locations.insert(offset, count(n)?);
+            }
+            [unit, n] => {
+                let unit = unit.parse().map_err(err)?;
+                profile.units.insert(unit, count(n)?);
+            }
+            [unit, block, n] => {
+                let unit = unit.parse().map_err(err)?;
+                let block = match block.strip_prefix('%') {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => return Err(err(format!("expected `%name`, found `{}`", block))),
+                };
+                profile.blocks.insert((unit, block), count(n)?);
+            }
+            _ => return Err(err(format!("cannot parse `{}`", line.trim()))),
+        }
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn counts_by_name_and_location() {
+        let input = "func @f (i1 %c) i1 {\n%entry:\n    br %c, %a, %b\n%a:\n    %x = not i1 %c\n    ret i1 %x\n%b:\n    ret i1 %c\n}\n";
+        let module = parse_module(input).unwrap();
+        let unit = module.units().next().unwrap();
+        let not = unit.all_insts().nth(1).unwrap();
+        let loc = input.find("%x =").unwrap();
+        let profile =
+            parse_profile(format!("# calls\n@f 10\n@f %b 3  # else\nloc {} 7\n", loc)).unwrap();
+
+        assert_eq!(profile.unit_count(unit.name()), Some(10));
+        let blocks: Vec<_> = unit.blocks().collect();
+        assert_eq!(profile.block_count(&unit, blocks[0]), None);
+        assert_eq!(profile.block_count(&unit, blocks[1]), Some(7));
+        assert_eq!(profile.block_count(&unit, blocks[2]), Some(3));
+        assert_eq!(profile.inst_count(&unit, not), Some(7));
+        assert_eq!(
+            profile.inst_count(&unit, unit.terminator(blocks[2])),
+            Some(3)
+        );
+
+        assert!(parse_profile("@f %b x").is_err());
+        assert!(parse_profile("f 1").is_err());
+        assert!(parse_profile("loc 1").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+use crate::{
+    assembly::write_module_string, config::UnitPattern, ir::prelude::*, opt::PassContext,
+    pass::run_by_name, sim::Differential, verifier::Verifier,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Speculative application of passes.
+///
+/// A pass is run on a copy of the module, and its result is only kept if it
+/// passes the verifier and, if `differential` is set, behaves the same as the
+/// original in simulation. Units whose result fails to verify are rolled back
+/// by running the pass again with these units frozen. A difference in
+/// behaviour cannot be attributed to a unit and rolls back the entire pass.
+/// Each rollback is logged and recorded together with a reduced test case.
+#[derive(Debug, Clone, Default)]
+pub struct Speculation {
+    /// The passes which are applied speculatively, by their `llhd-opt` name.
+    /// The name `all` covers all passes.
+    pub passes: Vec<String>,
+    /// The differential test run on the result of each pass, if any. Its
+    /// passes are ignored.
+    pub differential: Option<Differential>,
+    /// The pass results rolled back so far.
+    rollbacks: Vec<Rollback>,
+}
+
+/// A pass result which was rolled back by speculative pass application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rollback {
+    /// The command line name of the pass, e.g. `tcm`.
+    pub pass: String,
+    /// The unit whose result was rolled back, or `None` if the result of the
+    /// entire pass was.
+    pub unit: Option<UnitName>,
+    /// Why the result was rejected.
+    pub reason: String,
+    /// The smallest module found on which the pass fails the same way, in
+    /// assembly form.
+    pub test_case: String,
+}
+
+impl Speculation {
+    /// Check whether a pass is applied speculatively.
+    pub fn applies(&self, pass: &str) -> bool {
+        self.passes.iter().any(|p| p == pass || p == "all")
+    }
+
+    /// Run a pass speculatively, given its command line name.
+    ///
+    /// Returns whether the pass modified the module, or `None` if there is no
+    /// pass with the given name.
+    pub fn run_pass(
+        &mut self,
+        name: &str,
+        ctx: &mut PassContext,
+        module: &mut Module,
+    ) -> Option<bool> {
+        self.run(name, ctx, module, |ctx, module| {
+            run_by_name(name, ctx, module)
+        })
+    }
+
+    /// Run a pass speculatively, applying it with a custom function.
+    ///
+    /// The function must honor `PassContext::may_modify`.
+    pub fn run(
+        &mut self,
+        name: &str,
+        ctx: &mut PassContext,
+        module: &mut Module,
+        mut pass: impl FnMut(&PassContext, &mut Module) -> Option<bool>,
+    ) -> Option<bool> {
+        let frozen = ctx.frozen_units.clone();
+        let result = loop {
+            let mut trial = module.clone();
+            let modified = match pass(ctx, &mut trial) {
+                Some(modified) => modified,
+                None => break None,
+            };
+            if !modified {
+                break Some(false);
+            }
+
+            // Freeze the units whose result fails to verify, and try again.
+            // The pass may modify frozen units regardless, in which case there
+            // is nothing left to freeze.
+            let failed = match failed_units(module, &trial) {
+                Ok(failed) if failed.iter().all(|(unit, _)| ctx.may_modify(unit)) => failed,
+                Ok(_) => {
+                    let reason = "output does not verify in frozen units".to_string();
+                    self.roll_back(name, None, reason, write_module_string(module));
+                    break Some(false);
+                }
+                Err(reason) => {
+                    self.roll_back(name, None, reason, write_module_string(module));
+                    break Some(false);
+                }
+            };
+            if !failed.is_empty() {
+                for (unit, reason) in failed {
+                    let test_case = reduce(module, &unit, |module| {
+                        pass(ctx, module) == Some(true) && verify(module).is_err()
+                    });
+                    self.roll_back(name, Some(unit.clone()), reason, test_case);
+                    ctx.frozen_units.push(UnitPattern::new(unit.to_string()));
+                }
+                continue;
+            }
+
+            // Compare the behaviour of the result to the original.
+            if let Some(differential) = &self.differential {
+                match differential.compare(module, &trial) {
+                    Ok(None) => (),
+                    Ok(Some(mismatch)) => {
+                        let reason = format!("behaviour differs\n{}", mismatch);
+                        self.roll_back(name, None, reason, write_module_string(module));
+                        break Some(false);
+                    }
+                    Err(e) => warn!("Cannot compare behaviour after {}: {}", name, e),
+                }
+            }
+            *module = trial;
+            break Some(true);
+        };
+        ctx.frozen_units = frozen;
+        result
+    }
+
+    /// Get the pass results rolled back so far, in the order they were.
+    pub fn rollbacks(&self) -> &[Rollback] {
+        &self.rollbacks
+    }
+
+    /// Record that a pass result was rolled back, and emit a warning.
+    fn roll_back(&mut self, pass: &str, unit: Option<UnitName>, reason: String, test_case: String) {
+        match &unit {
+            Some(unit) => warn!("Rolling back {} in {}: {}", unit, pass, reason),
+            None => warn!("Rolling back {}: {}", pass, reason),
+        }
+        self.rollbacks.push(Rollback {
+            pass: pass.to_string(),
+            unit,
+            reason,
+            test_case,
+        });
+    }
+}
+
+/// Verify a module.
+fn verify(module: &Module) -> Result<(), String> {
+    let mut verifier = Verifier::new();
+    verifier.verify_module(module);
+    verifier.finish().map_err(|errs| errs.to_string())
+}
+
+/// Find the units of the original module whose result fails to verify, and
+/// describe their errors.
+///
+/// Fails if an error cannot be attributed to such a unit, for example if it
+/// is in a unit created by the pass.
+fn failed_units(original: &Module, trial: &Module) -> Result<Vec<(UnitName, String)>, String> {
+    let mut verifier = Verifier::new();
+    verifier.verify_module(trial);
+    let errs = match verifier.finish() {
+        Ok(()) => return Ok(vec![]),
+        Err(errs) => errs,
+    };
+    let names: HashMap<String, &UnitName> = trial
+        .units()
+        .map(|unit| (format!("{} {}", unit.kind(), unit.name()), unit.name()))
+        .collect();
+    let existing: HashSet<&UnitName> = original.units().map(|unit| unit.name()).collect();
+    let mut failed: Vec<(UnitName, String)> = vec![];
+    for err in errs.iter() {
+        let name = match err.unit.as_ref().and_then(|unit| names.get(unit)) {
+            Some(&name) if existing.contains(name) => name,
+            _ => return Err(format!("output does not verify:\n{}", errs)),
+        };
+        match failed.iter_mut().find(|(unit, _)| unit == name) {
+            Some((_, reason)) => reason.push_str(&format!("\n{}", err)),
+            None => failed.push((name.clone(), err.to_string())),
+        }
+    }
+    Ok(failed)
+}
+
+/// Reduce a module to a single unit and declarations of the units it uses.
+///
+/// Returns the reduced module in assembly form if it still fails, or the
+/// entire module otherwise.
+fn reduce(module: &Module, name: &UnitName, fails: impl FnOnce(&mut Module) -> bool) -> String {
+    let unit = match module.units().find(|unit| unit.name() == name) {
+        Some(unit) => unit,
+        None => return write_module_string(module),
+    };
+    let mut reduced = Module::new();
+    let mut declared = HashSet::new();
+    for (_, ext) in unit.extern_units() {
+        if ext.name != *name && declared.insert(&ext.name) {
+            reduced.declare(ext.name.clone(), ext.sig.clone());
+        }
+    }
+    reduced.add_unit(unit.data().clone());
+    reduced.link();
+    let test_case = write_module_string(&reduced);
+    if fails(&mut reduced) {
+        test_case
+    } else {
+        write_module_string(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembly::parse_module, opt::Pass, pass::ConstFolding};
+
+    /// Fold constants, and break the units whose name starts with `bad` by
+    /// removing their terminators.
+    fn fold_and_break(ctx: &PassContext, module: &mut Module) -> Option<bool> {
+        ConstFolding::run_on_module(ctx, module);
+        for mut unit in module.units_mut() {
+            if ctx.may_modify(unit.name()) && unit.name().to_string().starts_with("@bad") {
+                let term = unit.terminator(unit.blocks().last().unwrap());
+                unit.delete_inst(term);
+            }
+        }
+        Some(true)
+    }
+
+    #[test]
+    fn failing_units_are_rolled_back() {
+        let mut module = parse_module(
+            "
+            func @good () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                ret i8 %b
+            }
+            declare @ext (i8 %x) i8
+            func @bad () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                %c = call i8 @ext (i8 %b)
+                ret i8 %c
+            }
+            ",
+        )
+        .unwrap();
+        let mut ctx = PassContext::default();
+        let mut speculation = Speculation::default();
+        let modified = speculation.run("cf", &mut ctx, &mut module, fold_and_break);
+        assert_eq!(modified, Some(true));
+        assert!(ctx.frozen_units.is_empty());
+        module.verify();
+        let text = write_module_string(&module);
+        assert!(text.contains("%b = const i8 2"));
+        assert!(text.contains("%b = add i8 %a, %a"));
+
+        let rollbacks = speculation.rollbacks();
+        assert_eq!(rollbacks.len(), 1);
+        assert_eq!(rollbacks[0].pass, "cf");
+        assert_eq!(rollbacks[0].unit, Some(UnitName::global("bad")));
+        assert!(rollbacks[0].test_case.contains("declare @ext (i8) i8"));
+        assert!(rollbacks[0].test_case.contains("func @bad"));
+        assert!(!rollbacks[0].test_case.contains("func @good"));
+    }
+
+    #[test]
+    fn behaviour_changes_are_rolled_back() {
+        let input = "
+            entity @top (i4$ %a, i4$ %b) -> (i4$ %y) {
+                %ap = prb i4$ %a
+                %bp = prb i4$ %b
+                %x = and i4 %ap, %bp
+                %t = const time 1ns
+                drv i4$ %y, %x, %t
+            }
+        ";
+        let mut module = parse_module(input).unwrap();
+        let mut ctx = PassContext::default();
+        let mut speculation = Speculation {
+            passes: vec!["all".to_string()],
+            differential: Some(Differential::new(&[])),
+            ..Default::default()
+        };
+        assert!(speculation.applies("tcm"));
+
+        // Drive one of the operands instead of the conjunction.
+        let modified = speculation.run("bogus", &mut ctx, &mut module, |_, module| {
+            let mut unit = module.units_mut().next().unwrap();
+            let insts: Vec<_> = unit.all_insts().collect();
+            let (ap, x) = (unit.inst_result(insts[0]), unit.inst_result(insts[2]));
+            unit.replace_use(x, ap);
+            Some(true)
+        });
+        assert_eq!(modified, Some(false));
+        assert_eq!(
+            write_module_string(&module),
+            write_module_string(&parse_module(input).unwrap())
+        );
+        let rollbacks = speculation.rollbacks();
+        assert_eq!(rollbacks.len(), 1);
+        assert_eq!(rollbacks[0].unit, None);
+        assert!(rollbacks[0].reason.starts_with("behaviour differs"));
+
+        // Passes which preserve the behaviour are kept.
+        let modified = speculation.run_pass("insim", &mut ctx, &mut module);
+        assert_eq!(modified, Some(false));
+        assert_eq!(speculation.run_pass("nope", &mut ctx, &mut module), None);
+        assert_eq!(speculation.rollbacks().len(), 1);
+    }
+}
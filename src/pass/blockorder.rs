@@ -0,0 +1,117 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Block Ordering
+
+use crate::{ir::prelude::*, opt::prelude::*};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Block Ordering
+///
+/// This pass reorders the blocks of functions and processes into a canonical
+/// layout, such that the order no longer depends on the passes that created
+/// or moved the blocks. The blocks are placed in reverse post-order, visiting
+/// the successors of a block in the order they appear in its terminator. If
+/// the `BlockOrder` of the pass context asks for it, the blocks of each
+/// temporal region are additionally kept together, with the regions in the
+/// order they are first reached. Unreachable blocks retain their relative
+/// order at the end of the unit.
+pub struct BlockOrdering;
+
+/// How the blocks of a unit are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockOrder {
+    /// Place the blocks in reverse post-order.
+    #[default]
+    ReversePostOrder,
+    /// Place the blocks in reverse post-order, but keep the blocks of each
+    /// temporal region together.
+    Regions,
+}
+
+impl std::str::FromStr for BlockOrder {
+    type Err = String;
+
+    /// Parse an order as given on the command line, e.g. `rpo`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "rpo" => Ok(BlockOrder::ReversePostOrder),
+            "regions" => Ok(BlockOrder::Regions),
+            _ => Err(format!("unknown block order `{}`", s)),
+        }
+    }
+}
+
+impl Pass for BlockOrdering {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("BlockOrder [{}]", unit.name());
+        let mut order = reverse_post_order(unit);
+
+        // Group the blocks by temporal region, in the order in which the
+        // regions are first reached.
+        if ctx.block_order == BlockOrder::Regions {
+            let trg = unit.trg();
+            let mut rank = HashMap::new();
+            for &bb in &order {
+                let next = rank.len();
+                rank.entry(trg[bb]).or_insert(next);
+            }
+            let mut groups = BTreeMap::<usize, Vec<Block>>::new();
+            for bb in order {
+                groups.entry(rank[&trg[bb]]).or_default().push(bb);
+            }
+            order = groups.into_values().flatten().collect();
+        }
+
+        // Append the unreachable blocks.
+        let reachable: HashSet<_> = order.iter().cloned().collect();
+        order.extend(unit.blocks().filter(|bb| !reachable.contains(bb)));
+
+        // Move the blocks into place.
+        let mut current: Vec<_> = unit.blocks().collect();
+        let mut index: HashMap<_, _> = current.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let mut modified = false;
+        for (i, bb) in order.into_iter().enumerate() {
+            let other = current[i];
+            if other == bb {
+                continue;
+            }
+            trace!("Moving {} before {}", bb.dump(unit), other.dump(unit));
+            unit.swap_blocks(other, bb);
+            let j = index[&bb];
+            current.swap(i, j);
+            index.insert(other, j);
+            index.insert(bb, i);
+            modified = true;
+        }
+        modified
+    }
+}
+
+/// Compute the reverse post-order of the blocks reachable from the entry.
+///
+/// Successors are visited from last to first, such that they appear in the
+/// order of the terminator where possible, e.g. the `false` block of a
+/// conditional branch before the `true` block.
+fn reverse_post_order(unit: &Unit) -> Vec<Block> {
+    let mut order = vec![];
+    let mut seen = HashSet::new();
+    let mut stack = vec![(unit.entry(), false)];
+    while let Some((bb, done)) = stack.pop() {
+        if done {
+            order.push(bb);
+            continue;
+        }
+        if !seen.insert(bb) {
+            continue;
+        }
+        stack.push((bb, true));
+        let term = unit.terminator(bb);
+        for &succ in unit[term].blocks() {
+            if !seen.contains(&succ) {
+                stack.push((succ, false));
+            }
+        }
+    }
+    order.reverse();
+    order
+}
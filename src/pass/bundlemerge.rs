@@ -0,0 +1,347 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Bundle Merging
+
+use crate::{
+    ir::{prelude::*, Signature},
+    opt::prelude::*,
+    ty::{signal_ty, struct_ty},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Bundle Merging
+///
+/// This pass converts structs of local signals, such as `{i8$, i1$}`, into
+/// signals of struct type, such as `{i8, i1}$`. The individual field signals
+/// are replaced with `extf` projections of the merged signal. Signals with a
+/// resolution are not merged. This is the inverse of bundle splitting.
+///
+/// Structs of ports of entities and processes are merged into one port as
+/// well, provided that every instantiation of the unit connects the ports to
+/// the fields of one signal of the merged type, in order. Units instantiated
+/// by frozen units or replicated instances keep their ports.
+pub struct BundleMerging;
+
+impl Pass for BundleMerging {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        // Merging local signals turns the signals passed to instances into
+        // projections, which may allow the ports of the instantiated unit to
+        // be merged, and in turn the ports of the units it instantiates.
+        let mut modified = false;
+        for mut unit in module.units_mut() {
+            if ctx.may_modify(unit.name()) {
+                modified |= Self::run_on_cfg(ctx, &mut unit);
+            }
+        }
+        while merge_ports(ctx, module) {
+            modified = true;
+        }
+        modified
+    }
+
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        let bundles: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].opcode() == Opcode::Struct)
+            .collect();
+        let mut modified = false;
+        for inst in bundles {
+            modified |= merge_bundle(ctx, inst, unit);
+        }
+        modified
+    }
+}
+
+/// Merge the structs of ports of the units in a module.
+fn merge_ports(ctx: &PassContext, module: &mut Module) -> bool {
+    // Find the structs which bundle up ports, as the positions of the bundled
+    // ports among the arguments of each unit.
+    let fixed = super::bundlesplit::fixed_port_units(ctx, module);
+    let mut candidates = HashMap::<UnitName, Vec<(Inst, Vec<usize>)>>::new();
+    for unit in module.units() {
+        if unit.is_function() || !ctx.may_modify(unit.name()) {
+            continue;
+        }
+        let mut used = HashSet::new();
+        for inst in unit.all_insts() {
+            let positions = match bundled_ports(inst, &unit) {
+                Some(positions) => positions,
+                None => continue,
+            };
+            if let Some(reason) = fixed.get(unit.name()) {
+                ctx.skip_unit(unit.name(), "bundlemerge", reason.clone());
+                break;
+            }
+            if positions.iter().all(|&pos| used.insert(pos)) {
+                candidates
+                    .entry(unit.name().clone())
+                    .or_default()
+                    .push((inst, positions));
+            }
+        }
+    }
+
+    // Only keep the bundles whose ports every instantiation connects to the
+    // fields of one signal.
+    for unit in module.units() {
+        for inst in unit.all_insts() {
+            if unit[inst].opcode() != Opcode::Inst {
+                continue;
+            }
+            let name = &unit[unit[inst].get_ext_unit().unwrap()].name;
+            if let Some(bundles) = candidates.get_mut(name) {
+                bundles.retain(|(_, positions)| bundled_signal(inst, positions, &unit).is_some());
+            }
+        }
+    }
+    candidates.retain(|_, bundles| !bundles.is_empty());
+    if candidates.is_empty() {
+        return false;
+    }
+
+    // Merge the ports, and remember the positions of the arguments of the
+    // original signature which have been merged.
+    let mut merged = HashMap::new();
+    for mut unit in module.units_mut() {
+        let bundles = match candidates.remove(unit.name()) {
+            Some(bundles) => bundles,
+            None => continue,
+        };
+        let args: Vec<_> = unit.sig().args().collect();
+        for (inst, positions) in &bundles {
+            merge_port(*inst, positions, &args, &mut unit);
+        }
+        let positions: Vec<_> = bundles.into_iter().map(|(_, p)| p).collect();
+        merged.insert(unit.name().clone(), (unit.sig().clone(), positions));
+    }
+
+    // Connect the instantiations to the merged ports.
+    for mut unit in module.units_mut() {
+        connect_ports(&mut unit, &merged);
+    }
+    let decls: Vec<_> = module.decls().collect();
+    for decl in decls {
+        if let Some((sig, _)) = merged.get(&module[decl].name) {
+            module[decl].sig = sig.clone();
+        }
+    }
+    true
+}
+
+/// Check if a struct bundles up distinct ports of the same direction.
+///
+/// Returns the positions of the ports among the unit's arguments, in the
+/// order of the struct fields.
+fn bundled_ports(inst: Inst, unit: &Unit) -> Option<Vec<usize>> {
+    if unit[inst].opcode() != Opcode::Struct || unit[inst].args().is_empty() {
+        return None;
+    }
+    let sig = unit.sig();
+    let args: Vec<_> = sig.args().collect();
+    let mut positions = vec![];
+    for &field in unit[inst].args() {
+        let arg = unit.get_value_arg(field)?;
+        if !sig.arg_type(arg).is_signal() || positions.contains(&arg) {
+            return None;
+        }
+        positions.push(arg);
+    }
+    let input = sig.is_input(positions[0]);
+    if positions.iter().any(|&arg| sig.is_input(arg) != input) {
+        return None;
+    }
+    Some(
+        positions
+            .into_iter()
+            .map(|arg| args.iter().position(|&a| a == arg).unwrap())
+            .collect(),
+    )
+}
+
+/// Find the signal whose fields an instantiation connects to bundled ports.
+fn bundled_signal(inst: Inst, positions: &[usize], unit: &Unit) -> Option<Value> {
+    let mut signal = None;
+    for (field, &pos) in positions.iter().enumerate() {
+        let proj = unit.get_value_inst(unit[inst].args()[pos])?;
+        if unit[proj].opcode() != Opcode::ExtField || unit[proj].imms()[0] != field {
+            return None;
+        }
+        let root = unit[proj].args()[0];
+        if signal.get_or_insert(root) != &root {
+            return None;
+        }
+    }
+    let signal = signal?;
+    let fields = positions
+        .iter()
+        .map(|&pos| {
+            unit.value_type(unit[inst].args()[pos])
+                .unwrap_signal()
+                .clone()
+        })
+        .collect();
+    if unit.value_type(signal) != signal_ty(struct_ty(fields)) {
+        return None;
+    }
+    Some(signal)
+}
+
+/// Merge the ports bundled up in a struct into one port.
+///
+/// `args` are the arguments of the unit before any ports were merged.
+fn merge_port(inst: Inst, positions: &[usize], args: &[Arg], unit: &mut UnitBuilder) {
+    info!("BundleMerge [{}] {}", unit.name(), inst.dump(unit));
+    let fields: Vec<Value> = unit[inst].args().to_vec();
+    let ty = struct_ty(
+        fields
+            .iter()
+            .map(|&f| unit.value_type(f).unwrap_signal().clone())
+            .collect(),
+    );
+
+    // Create the merged port in place of the first bundled one.
+    let first = args[*positions.iter().min().unwrap()];
+    let merged = unit.insert_arg_before(first, signal_ty(ty));
+    if let Some(name) = unit.get_name(unit.inst_result(inst)).map(String::from) {
+        unit.set_name(merged, name);
+    }
+
+    // Replace each field port with a projection of the merged port.
+    let entry = unit.entry();
+    unit.prepend_to(entry);
+    for (i, (&field, &pos)) in fields.iter().zip(positions).enumerate() {
+        let proj = unit.ins().ext_field(merged, i);
+        if let Some(name) = unit.get_name(field).map(String::from) {
+            unit.set_name(proj, name);
+        }
+        unit.replace_use(field, proj);
+        unit.remove_arg(args[pos]);
+    }
+}
+
+/// Connect the instantiations of units whose ports have been merged.
+fn connect_ports(unit: &mut UnitBuilder, merged: &HashMap<UnitName, (Signature, Vec<Vec<usize>>)>) {
+    let ext_units: Vec<_> = unit
+        .extern_units()
+        .filter(|(_, data)| merged.contains_key(&data.name))
+        .map(|(ext_unit, _)| ext_unit)
+        .collect();
+    for ext_unit in ext_units {
+        let (sig, bundles) = merged[&unit[ext_unit].name].clone();
+        unit[ext_unit].sig = sig;
+        let insts: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].get_ext_unit() == Some(ext_unit))
+            .collect();
+        for inst in insts {
+            unit.insert_before(inst);
+            let num_inputs = unit[inst].input_args().len();
+            let mut inputs = vec![];
+            let mut outputs = vec![];
+            for (i, &arg) in unit[inst].args().to_vec().iter().enumerate() {
+                let args = if i < num_inputs {
+                    &mut inputs
+                } else {
+                    &mut outputs
+                };
+                match bundles.iter().find(|b| b.contains(&i)) {
+                    Some(b) if b.iter().min() == Some(&i) => {
+                        args.push(bundled_signal(inst, b, unit).unwrap())
+                    }
+                    Some(_) => (),
+                    None => args.push(arg),
+                }
+            }
+            let new = unit.ins().inst(ext_unit, inputs, outputs);
+            unit.transfer_origins(inst, new);
+            unit.delete_inst(inst);
+        }
+    }
+}
+
+/// Merge the signals bundled up in a struct into one signal.
+fn merge_bundle(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    let last = match is_suitable(ctx, inst, unit) {
+        Some(last) => last,
+        None => return false,
+    };
+    info!("BundleMerge [{}] {}", unit.name(), inst.dump(unit));
+    let fields: Vec<Value> = unit[inst].args().to_vec();
+    let field_insts: Vec<Inst> = fields
+        .iter()
+        .map(|&f| unit.get_value_inst(f).unwrap())
+        .collect();
+
+    // Create the merged signal after the last field signal, such that all
+    // initial values are available.
+    unit.insert_after(last);
+    let inits = field_insts.iter().map(|&i| unit[i].args()[0]).collect();
+    let init = unit.ins().strukt(inits);
+    let merged = unit.ins().sig(init);
+    if let Some(name) = unit.get_name(unit.inst_result(inst)).map(String::from) {
+        unit.set_name(merged, name);
+    }
+
+    // Replace each field signal with a projection of the merged signal.
+    for (i, (&field, &field_inst)) in fields.iter().zip(field_insts.iter()).enumerate() {
+        let proj = unit.ins().ext_field(merged, i);
+        if let Some(name) = unit.get_name(field).map(String::from) {
+            unit.set_name(proj, name);
+        }
+        unit.replace_use(field, proj);
+        unit.delete_inst(field_inst);
+    }
+    true
+}
+
+/// Check if a struct bundles up distinct local signals which can be merged.
+///
+/// Returns the last field signal instruction, after which the merged signal
+/// is to be inserted.
+fn is_suitable(_ctx: &PassContext, inst: Inst, unit: &Unit) -> Option<Inst> {
+    let fields = unit[inst].args();
+    if fields.is_empty() {
+        return None;
+    }
+
+    // Ensure all fields are distinct signals in the same block.
+    let mut seen = HashSet::new();
+    let mut block = None;
+    for &field in fields {
+        let field_inst = unit.get_value_inst(field)?;
+        if unit[field_inst].opcode() != Opcode::Sig || !seen.insert(field_inst) {
+            return None;
+        }
+        if unit.signal_resolution(field_inst).is_some() {
+            trace!(
+                "Skipping {} ({} has a resolution)",
+                inst.dump(unit),
+                field.dump(unit)
+            );
+            return None;
+        }
+        let bb = unit.inst_block(field_inst)?;
+        if block.get_or_insert(bb) != &bb {
+            trace!("Skipping {} (fields in different blocks)", inst.dump(unit));
+            return None;
+        }
+    }
+
+    // Find the last field signal and ensure it precedes all uses of the fields.
+    let last = unit.insts(block?).filter(|i| seen.contains(i)).last()?;
+    let domtree = unit.domtree();
+    for &field in fields {
+        for &user in unit.uses(field) {
+            if !domtree.inst_dominates_inst(unit, last, user) || user == last {
+                trace!(
+                    "Skipping {} ({} used before {})",
+                    inst.dump(unit),
+                    field.dump(unit),
+                    last.dump(unit)
+                );
+                return None;
+            }
+        }
+    }
+    Some(last)
+}
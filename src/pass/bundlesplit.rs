@@ -0,0 +1,321 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Bundle Splitting
+
+use crate::{
+    ir::{prelude::*, Signature, UseKind},
+    opt::prelude::*,
+    ty::{signal_ty, Type},
+};
+use std::collections::HashMap;
+
+/// Bundle Splitting
+///
+/// This pass converts local signals of struct type, such as `{i8, i1}$`, into
+/// structs of signals, such as `{i8$, i1$}`. Probes, drives, and sensitivity
+/// lists are updated to operate on the individual field signals. Signals with
+/// a resolution are left untouched. This is the inverse of bundle merging.
+///
+/// Ports of entities and processes of struct signal type are split into one
+/// port per field as well, and the instantiations of the unit are connected
+/// to projections of the signal they passed for the port. Units instantiated
+/// by frozen units or replicated instances keep their ports.
+pub struct BundleSplitting;
+
+impl Pass for BundleSplitting {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        // Splitting the ports of a unit turns the signals passed to them into
+        // projections, which may in turn allow the ports of the instantiating
+        // unit to be split.
+        let mut modified = false;
+        while split_ports(ctx, module) {
+            modified = true;
+        }
+        for mut unit in module.units_mut() {
+            if ctx.may_modify(unit.name()) {
+                modified |= Self::run_on_cfg(ctx, &mut unit);
+            }
+        }
+        modified
+    }
+
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        let signals: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].opcode() == Opcode::Sig)
+            .collect();
+        let mut modified = false;
+        for inst in signals {
+            modified |= split_signal(ctx, inst, unit);
+        }
+        modified
+    }
+}
+
+/// Find the units whose ports cannot be changed, since the instantiations of
+/// the unit could not be updated accordingly.
+///
+/// Maps the name of each such unit to the reason.
+pub(super) fn fixed_port_units(ctx: &PassContext, module: &Module) -> HashMap<UnitName, String> {
+    let mut fixed = HashMap::new();
+    for unit in module.units() {
+        for inst in unit.all_insts() {
+            if unit[inst].opcode() != Opcode::Inst {
+                continue;
+            }
+            let reason = if !ctx.may_modify(unit.name()) {
+                format!("instantiated by frozen unit {}", unit.name())
+            } else if unit.inst_replication(inst).is_some() {
+                format!("replicated by {}", unit.name())
+            } else {
+                continue;
+            };
+            let name = &unit[unit[inst].get_ext_unit().unwrap()].name;
+            fixed.entry(name.clone()).or_insert(reason);
+        }
+    }
+    fixed
+}
+
+/// Split the ports of struct signal type of the units in a module.
+fn split_ports(ctx: &PassContext, module: &mut Module) -> bool {
+    // Split the ports, and remember into how many fields each argument of
+    // the original signature has been split.
+    let fixed = fixed_port_units(ctx, module);
+    let mut split = HashMap::new();
+    for mut unit in module.units_mut() {
+        if unit.is_function() || !ctx.may_modify(unit.name()) {
+            continue;
+        }
+        let args: Vec<_> = unit.sig().args().collect();
+        let mut num_fields = vec![0; args.len()];
+        for (i, &arg) in args.iter().enumerate() {
+            if !is_splittable(unit.arg_value(arg), &unit) {
+                continue;
+            }
+            if let Some(reason) = fixed.get(unit.name()) {
+                ctx.skip_unit(unit.name(), "bundlesplit", reason.clone());
+                break;
+            }
+            num_fields[i] = split_port(arg, &mut unit);
+        }
+        if num_fields.iter().any(|&n| n > 0) {
+            split.insert(unit.name().clone(), (unit.sig().clone(), num_fields));
+        }
+    }
+    if split.is_empty() {
+        return false;
+    }
+
+    // Connect the instantiations to the new ports.
+    for mut unit in module.units_mut() {
+        connect_ports(&mut unit, &split);
+    }
+    let decls: Vec<_> = module.decls().collect();
+    for decl in decls {
+        if let Some((sig, _)) = split.get(&module[decl].name) {
+            module[decl].sig = sig.clone();
+        }
+    }
+    true
+}
+
+/// Split a port of struct signal type into one port per field.
+///
+/// Returns the number of fields.
+fn split_port(arg: Arg, unit: &mut UnitBuilder) -> usize {
+    let signal = unit.arg_value(arg);
+    info!("BundleSplit [{}] {}", unit.name(), signal.dump(unit));
+    let ty = unit.value_type(signal).unwrap_signal().clone();
+
+    // Create one port per field, and bundle them up in a struct.
+    let fields: Vec<_> = ty
+        .unwrap_struct()
+        .iter()
+        .map(|field_ty| unit.insert_arg_before(arg, signal_ty(field_ty.clone())))
+        .collect();
+    let entry = unit.entry();
+    unit.prepend_to(entry);
+    let bundle = unit.ins().strukt(fields.clone());
+    if let Some(name) = unit.get_name(signal).map(String::from) {
+        for (i, &field) in fields.iter().enumerate() {
+            unit.set_name(field, format!("{}.{}", name, i));
+        }
+        unit.set_name(bundle, name);
+    }
+    split_uses(signal, &ty, &fields, bundle, unit);
+    unit.remove_arg(arg);
+    fields.len()
+}
+
+/// Connect the instantiations of units whose ports have been split.
+fn connect_ports(unit: &mut UnitBuilder, split: &HashMap<UnitName, (Signature, Vec<usize>)>) {
+    let ext_units: Vec<_> = unit
+        .extern_units()
+        .filter(|(_, data)| split.contains_key(&data.name))
+        .map(|(ext_unit, _)| ext_unit)
+        .collect();
+    for ext_unit in ext_units {
+        let (sig, num_fields) = split[&unit[ext_unit].name].clone();
+        unit[ext_unit].sig = sig;
+        let insts: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].get_ext_unit() == Some(ext_unit))
+            .collect();
+        for inst in insts {
+            unit.insert_before(inst);
+            let num_inputs = unit[inst].input_args().len();
+            let mut inputs = vec![];
+            let mut outputs = vec![];
+            for (i, &arg) in unit[inst].args().to_vec().iter().enumerate() {
+                let args = if i < num_inputs {
+                    &mut inputs
+                } else {
+                    &mut outputs
+                };
+                if num_fields[i] == 0 {
+                    args.push(arg);
+                    continue;
+                }
+                for field in 0..num_fields[i] {
+                    args.push(unit.ins().ext_field(arg, field));
+                }
+            }
+            let new = unit.ins().inst(ext_unit, inputs, outputs);
+            unit.transfer_origins(inst, new);
+            unit.delete_inst(inst);
+        }
+    }
+}
+
+/// Split a signal of struct type into a struct of signals.
+fn split_signal(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    if !is_suitable(ctx, inst, unit) {
+        return false;
+    }
+    info!("BundleSplit [{}] {}", unit.name(), inst.dump(unit));
+    let signal = unit.inst_result(inst);
+    let init = unit[inst].args()[0];
+    let num_fields = unit.value_type(init).unwrap_struct().len();
+
+    // Create one signal per field, and bundle them up in a struct.
+    unit.insert_before(inst);
+    let fields: Vec<_> = (0..num_fields)
+        .map(|i| {
+            let init = unit.ins().ext_field(init, i);
+            unit.ins().sig(init)
+        })
+        .collect();
+    let bundle = unit.ins().strukt(fields.clone());
+    if let Some(name) = unit.get_name(signal).map(String::from) {
+        for (i, &field) in fields.iter().enumerate() {
+            unit.set_name(field, format!("{}.{}", name, i));
+        }
+        unit.set_name(bundle, name);
+    }
+
+    // Update the users of the signal.
+    let ty = unit.value_type(init);
+    split_uses(signal, &ty, &fields, bundle, unit);
+    unit.delete_inst(inst);
+    true
+}
+
+/// Check if a signal is a struct that can be split into its fields.
+fn is_suitable(_ctx: &PassContext, inst: Inst, unit: &Unit) -> bool {
+    let signal = unit.inst_result(inst);
+    if !unit.value_type(signal).unwrap_signal().is_struct() {
+        return false;
+    }
+    if unit.signal_resolution(inst).is_some() {
+        trace!("Skipping {} (has a resolution)", signal.dump(unit));
+        return false;
+    }
+    is_splittable(signal, unit)
+}
+
+/// Update the users of a signal of struct type `ty` to operate on the
+/// individual field signals, or the struct `bundle` of them.
+fn split_uses(signal: Value, ty: &Type, fields: &[Value], bundle: Value, unit: &mut UnitBuilder) {
+    let users: Vec<_> = unit.uses(signal).iter().cloned().collect();
+    for user in users {
+        unit.insert_before(user);
+        let data = unit[user].clone();
+        match data.opcode() {
+            Opcode::ExtField => {
+                unit.replace_value_within_inst(signal, bundle, user);
+            }
+            Opcode::Prb => {
+                let values = fields.iter().map(|&f| unit.ins().prb(f)).collect();
+                let value = match ty.struct_name() {
+                    Some(_) => unit.ins().named_strukt(ty.clone(), values),
+                    None => unit.ins().strukt(values),
+                };
+                let result = unit.inst_result(user);
+                if let Some(name) = unit.get_name(result).map(String::from) {
+                    unit.set_name(value, name);
+                }
+                unit.replace_use(result, value);
+                unit.delete_inst(user);
+            }
+            Opcode::Drv | Opcode::DrvCond => {
+                let args = data.args();
+                for (i, &field) in fields.iter().enumerate() {
+                    let value = unit.ins().ext_field(args[1], i);
+                    let new = if data.opcode() == Opcode::Drv {
+                        unit.ins().drv(field, value, args[2])
+                    } else {
+                        unit.ins().drv_cond(field, value, args[2], args[3])
+                    };
+                    unit.transfer_origins(user, new);
+                }
+                unit.delete_inst(user);
+            }
+            Opcode::Wait | Opcode::WaitTime => {
+                let mut args = vec![];
+                for &arg in data.args() {
+                    if arg == signal {
+                        args.extend(fields.iter().cloned());
+                    } else {
+                        args.push(arg);
+                    }
+                }
+                let bb = data.blocks()[0];
+                let new = if data.opcode() == Opcode::Wait {
+                    unit.ins().wait(bb, args)
+                } else {
+                    let time = args.remove(0);
+                    unit.ins().wait_time(bb, time, args)
+                };
+                unit.transfer_origins(user, new);
+                unit.delete_inst(user);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Check if a signal is a struct whose uses can be split up into its fields.
+fn is_splittable(signal: Value, unit: &Unit) -> bool {
+    let ty = unit.value_type(signal);
+    if !ty.is_signal() || !ty.unwrap_signal().is_struct() {
+        return false;
+    }
+    for u in unit.uses_detailed(signal) {
+        let opcode = unit[u.inst].opcode();
+        let ok = match u.kind {
+            UseKind::Sensitivity => true,
+            UseKind::Target => opcode == Opcode::Drv || opcode == Opcode::DrvCond,
+            _ => opcode == Opcode::ExtField || opcode == Opcode::Prb,
+        };
+        if !ok {
+            trace!(
+                "Skipping {} (used in {})",
+                signal.dump(unit),
+                u.inst.dump(unit)
+            );
+            return false;
+        }
+    }
+    true
+}
@@ -4,16 +4,45 @@
 
 use crate::ir::prelude::*;
 use crate::opt::prelude::*;
-use crate::{ir::InstData, ty::*, value::IntValue};
-use std::cmp::min;
+use crate::{
+    ir::InstData,
+    ty::*,
+    value::{EnumValue, IntValue, TimeValue},
+};
+use std::{cmp::min, collections::HashSet};
 
 /// Constant Folding
 ///
 /// This pass implements constant folding. It replaces instructions with
-/// constant arguments with the corresponding result.
+/// constant arguments with the corresponding result. Conditional branches on a
+/// constant become unconditional, and blocks which can no longer be reached
+/// are removed. Probes of a signal in an entity which is never driven yield
+/// the signal's initial value. A `mux` between two values selected by a
+/// comparison of the same two values becomes the corresponding `umin`,
+/// `umax`, `smin`, or `smax`.
 pub struct ConstFolding;
 
 impl Pass for ConstFolding {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        let mut modified = false;
+        let mut folded_branch = false;
+        let insts: Vec<_> = unit.all_insts().collect();
+        for inst in insts {
+            if !unit.is_inst_inserted(inst) {
+                continue;
+            }
+            if unit[inst].opcode() == Opcode::BrCond {
+                folded_branch |= fold_branch(unit, inst);
+            } else {
+                modified |= Self::run_on_inst(ctx, inst, unit);
+            }
+        }
+        if folded_branch {
+            prune_unreachable_blocks(unit);
+        }
+        modified | folded_branch
+    }
+
     fn run_on_inst(_ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
         run_on_inst(unit, inst)
     }
@@ -40,6 +69,7 @@ pub fn run_on_inst(unit: &mut UnitBuilder, inst: Inst) -> bool {
         Opcode::ExtField => fold_ext_field(unit, inst),
         Opcode::Shl | Opcode::Shr => fold_shift(unit, inst, &ty),
         Opcode::Mux => fold_mux(unit, inst),
+        Opcode::Prb => fold_prb(unit, inst),
         _ => match *data {
             InstData::Unary { opcode, args, .. } => fold_unary(unit, opcode, ty.clone(), args[0]),
             InstData::Binary { opcode, args, .. } => fold_binary(unit, opcode, ty.clone(), args),
@@ -77,6 +107,100 @@ pub fn run_on_value(unit: &mut UnitBuilder, value: Value) -> bool {
     }
 }
 
+/// Fold a conditional branch on a constant into an unconditional branch.
+///
+/// The branch's block is removed from the phi nodes of the target that is no
+/// longer taken.
+fn fold_branch(unit: &mut UnitBuilder, inst: Inst) -> bool {
+    let cond = unit[inst].args()[0];
+    let taken = match unit.get_const_int(cond) {
+        Some(imm) => !imm.is_zero() as usize,
+        None => return false,
+    };
+    let bbs = unit[inst].blocks().to_vec();
+    let head = unit.inst_block(inst).unwrap();
+    debug!(
+        "Replacing {} with br {}",
+        inst.dump(unit),
+        bbs[taken].dump(unit)
+    );
+    unit.insert_before(inst);
+    let br = unit.ins().br(bbs[taken]);
+    unit.transfer_origins(inst, br);
+    unit.delete_inst(inst);
+    if bbs[0] != bbs[1] {
+        remove_phi_edges(unit, bbs[1 - taken], head);
+    }
+    true
+}
+
+/// Remove the blocks which cannot be reached from the entry block.
+fn prune_unreachable_blocks(unit: &mut UnitBuilder) {
+    let entry = unit.entry();
+    let mut unreachable: HashSet<Block> = unit.blocks().collect();
+    let mut todo = vec![entry];
+    unreachable.remove(&entry);
+    while let Some(bb) = todo.pop() {
+        let term = unit.terminator(bb);
+        for &succ in unit[term].blocks() {
+            if unreachable.remove(&succ) {
+                todo.push(succ);
+            }
+        }
+    }
+    for &bb in &unreachable {
+        let term = unit.terminator(bb);
+        let succs: Vec<_> = unit[term].blocks().to_vec();
+        for succ in succs {
+            if !unreachable.contains(&succ) {
+                remove_phi_edges(unit, succ, bb);
+            }
+        }
+    }
+    for bb in unreachable {
+        debug!("Prune unreachable block {}", bb.dump(unit));
+        unit.delete_block(bb);
+    }
+}
+
+/// Remove the incoming values from a predecessor from the phi nodes of a block.
+///
+/// Phi nodes which are left with a single incoming value are replaced by that
+/// value. Phi nodes which are left without any incoming values are kept, since
+/// their block is unreachable.
+fn remove_phi_edges(unit: &mut UnitBuilder, bb: Block, pred: Block) {
+    let phis: Vec<_> = unit
+        .insts(bb)
+        .filter(|&inst| unit[inst].opcode() == Opcode::Phi)
+        .collect();
+    for phi in phis {
+        let (args, bbs): (Vec<Value>, Vec<Block>) = unit[phi]
+            .args()
+            .iter()
+            .cloned()
+            .zip(unit[phi].blocks().iter().cloned())
+            .filter(|&(_, b)| b != pred)
+            .unzip();
+        if args.is_empty() {
+            continue;
+        }
+        let result = unit.inst_result(phi);
+        let others: Vec<_> = args.iter().cloned().filter(|&a| a != result).collect();
+        let value = if !others.is_empty() && others.iter().all(|&a| a == others[0]) {
+            others[0]
+        } else {
+            unit.insert_before(phi);
+            let value = unit.ins().phi(args, bbs);
+            if let Some(name) = unit.get_name(result).map(String::from) {
+                unit.set_name(value, name);
+            }
+            value
+        };
+        unit.replace_use(result, value);
+        unit.delete_inst(phi);
+    }
+}
+
 /// Fold a unary instruction.
 fn fold_unary(unit: &mut UnitBuilder, op: Opcode, ty: Type, arg: Value) -> Option<Value> {
     if ty.is_int() {
@@ -95,13 +219,41 @@ fn fold_unary_int(unit: &mut UnitBuilder, op: Opcode, arg: Value) -> Option<Valu
 
 /// Fold a binary instruction.
 fn fold_binary(unit: &mut UnitBuilder, op: Opcode, ty: Type, args: [Value; 2]) -> Option<Value> {
-    if ty.is_int() {
+    if let (Some(a), Some(b)) = (unit.get_const_time(args[0]), unit.get_const_time(args[1])) {
+        let result = TimeValue::try_compare_op(op, a, b)?;
+        return Some(unit.ins().const_int(result));
+    }
+    if let (Some(a), Some(b)) = (unit.get_const_enum(args[0]), unit.get_const_enum(args[1])) {
+        let result = EnumValue::try_compare_op(op, a, b)?;
+        return Some(unit.ins().const_int(result));
+    }
+    if op.is_overflow() {
+        fold_overflow(unit, op, args)
+    } else if ty.is_int() {
         fold_binary_int(unit, op, ty.unwrap_int(), args)
     } else {
         None
     }
 }
 
+/// Fold an arithmetic instruction with an overflow flag.
+fn fold_overflow(unit: &mut UnitBuilder, op: Opcode, args: [Value; 2]) -> Option<Value> {
+    let imm0 = unit.get_const_int(args[0]);
+    let imm1 = unit.get_const_int(args[1]);
+    let (result, flag) = match (imm0, imm1) {
+        (Some(a), Some(b)) => {
+            let (result, flag) = IntValue::try_overflow_op(op, a, b)?;
+            (unit.ins().const_int(result), flag)
+        }
+        // Adding or subtracting zero never overflows.
+        (_, Some(b)) if b.is_zero() => (args[0], IntValue::zero(1)),
+        (Some(a), _) if a.is_zero() && op.is_commutative() => (args[1], IntValue::zero(1)),
+        _ => return None,
+    };
+    let flag = unit.ins().const_int(flag);
+    Some(unit.ins().strukt(vec![result, flag]))
+}
+
 /// Fold a binary instruction on integers.
 fn fold_binary_int(
     unit: &mut UnitBuilder,
@@ -112,30 +264,51 @@ fn fold_binary_int(
     let imm0 = unit.get_const_int(args[0]);
     let imm1 = unit.get_const_int(args[1]);
 
-    // Handle symmetric operations between a constant and a variable argument.
+    // Handle operations between a variable and a constant argument. The
+    // constant may only be on the left for commutative operations.
     let (arg_kon, arg_var) = match (imm0, imm1) {
         (None, Some(_)) => (imm1, args[0]),
-        (Some(_), None) => (imm0, args[1]),
+        (Some(_), None) if op.is_commutative() => (imm0, args[1]),
         _ => (None, args[0]),
     };
     if let Some(a) = arg_kon {
         match op {
-            Opcode::And | Opcode::Smul | Opcode::Umul if a.is_zero() => {
+            Opcode::And
+            | Opcode::Smul
+            | Opcode::Umul
+            | Opcode::Smulh
+            | Opcode::Umulh
+            | Opcode::Umin
+                if a.is_zero() =>
+            {
                 return Some(unit.ins().const_int(IntValue::zero(width)))
             }
-            Opcode::Or | Opcode::Xor | Opcode::Add | Opcode::Sub if a.is_zero() => {
+            Opcode::Or | Opcode::Xor | Opcode::Add | Opcode::Sub | Opcode::Umax if a.is_zero() => {
                 return Some(arg_var)
             }
             Opcode::Smul | Opcode::Umul if a.is_one() => return Some(arg_var),
-            Opcode::Or if a.is_all_ones() => {
+            Opcode::Umulh if a.is_one() => {
+                return Some(unit.ins().const_int(IntValue::zero(width)))
+            }
+            Opcode::Or | Opcode::Umax if a.is_all_ones() => {
                 return Some(unit.ins().const_int(IntValue::all_ones(width)))
             }
-            Opcode::And if a.is_all_ones() => return Some(arg_var),
+            Opcode::And | Opcode::Umin if a.is_all_ones() => return Some(arg_var),
             Opcode::Xor if a.is_all_ones() => return Some(unit.ins().not(arg_var)),
             _ => (),
         }
     }
 
+    // The minimum or maximum of a value and itself is the value.
+    if args[0] == args[1]
+        && matches!(
+            op,
+            Opcode::Umin | Opcode::Umax | Opcode::Smin | Opcode::Smax
+        )
+    {
+        return Some(args[0]);
+    }
+
     // Handle asymmetric operations between a variable argument on the left and
     // a constant argument on the right.
     let (arg_kon, arg_var) = match (imm0, imm1) {
@@ -289,6 +462,66 @@ fn fold_ext_field(unit: &mut UnitBuilder, inst: Inst) -> Option<Value> {
 fn fold_mux(unit: &mut UnitBuilder, inst: Inst) -> Option<Value> {
     let choices = unit[inst].args()[0];
     let sel = unit[inst].args()[1];
-    let const_sel = unit.get_const_int(sel)?.to_usize();
+    let const_sel = match unit.get_const_int(sel) {
+        Some(imm) => imm.to_usize(),
+        None => return fold_min_max(unit, choices, sel),
+    };
     Some(unit.ins().ext_field(choices, const_sel))
 }
+
+/// Recover a minimum or maximum from a mux between two values, selected by a
+/// comparison of the same two values.
+///
+/// For example, `mux [%b, %a], (ult %a, %b)` yields `umin %a, %b`.
+fn fold_min_max(unit: &mut UnitBuilder, choices: Value, sel: Value) -> Option<Value> {
+    let choices_inst = unit.get_value_inst(choices)?;
+    let cmp_inst = unit.get_value_inst(sel)?;
+    if unit[choices_inst].opcode() != Opcode::Array || unit[choices_inst].args().len() != 2 {
+        return None;
+    }
+    let (a, b) = match unit[cmp_inst] {
+        InstData::Binary { args, .. } if unit.value_type(args[0]).is_int() => (args[0], args[1]),
+        _ => return None,
+    };
+    let (if_false, if_true) = (unit[choices_inst].args()[0], unit[choices_inst].args()[1]);
+    // Whether the comparison is true if `a` is the smaller value.
+    let (a_smaller, signed) = match unit[cmp_inst].opcode() {
+        Opcode::Ult | Opcode::Ule => (true, false),
+        Opcode::Ugt | Opcode::Uge => (false, false),
+        Opcode::Slt | Opcode::Sle => (true, true),
+        Opcode::Sgt | Opcode::Sge => (false, true),
+        _ => return None,
+    };
+    let min = if (if_true, if_false) == (a, b) {
+        a_smaller
+    } else if (if_true, if_false) == (b, a) {
+        !a_smaller
+    } else {
+        return None;
+    };
+    Some(match (min, signed) {
+        (true, false) => unit.ins().umin(a, b),
+        (false, false) => unit.ins().umax(a, b),
+        (true, true) => unit.ins().smin(a, b),
+        (false, true) => unit.ins().smax(a, b),
+    })
+}
+
+/// Fold a probe of a signal which is never driven into the signal's initial
+/// value.
+fn fold_prb(unit: &mut UnitBuilder, inst: Inst) -> Option<Value> {
+    if !unit.is_entity() {
+        return None;
+    }
+    let signal = unit[inst].args()[0];
+    let sig_inst = unit.get_value_inst(signal)?;
+    if unit[sig_inst].opcode() != Opcode::Sig
+        || unit
+            .uses(signal)
+            .iter()
+            .any(|&user| unit[user].opcode() != Opcode::Prb)
+    {
+        return None;
+    }
+    Some(unit[sig_inst].args()[0])
+}
@@ -0,0 +1,71 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Constant Pooling
+
+use crate::{
+    ir::{prelude::*, InstData},
+    opt::prelude::*,
+};
+use std::collections::HashMap;
+
+/// Constant Pooling
+///
+/// This pass deduplicates the constants in a unit. It keeps one constant per
+/// type and value in a pool at the beginning of the entry block, replaces all
+/// uses of identical constants with the pooled one, and removes the
+/// duplicates. Constants annotated with `dont_optimize` are left in place, and
+/// so are don't-care constants, which must not stand in for concrete ones.
+pub struct ConstPooling;
+
+impl Pass for ConstPooling {
+    fn run_on_cfg(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("ConstPool [{}]", unit.name());
+        let mut modified = false;
+
+        // Gather the constants in layout order, and pick the first occurrence
+        // of each value as the pooled one.
+        let consts: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| {
+                let annotations = unit.inst_annotations(inst);
+                unit[inst].opcode().is_const()
+                    && annotations.is_optimizable()
+                    && !annotations.is_dont_care()
+            })
+            .collect();
+        let mut pool = HashMap::<InstData, Inst>::new();
+        let mut order = vec![];
+        for inst in consts {
+            match pool.get(&unit[inst]) {
+                Some(&pooled) => {
+                    debug!("Replace {} with {}", inst.dump(unit), pooled.dump(unit));
+                    let value = unit.inst_result(inst);
+                    let pooled_value = unit.inst_result(pooled);
+                    unit.replace_use(value, pooled_value);
+                    unit.transfer_origins(inst, pooled);
+                    unit.delete_inst(inst);
+                    modified = true;
+                }
+                None => {
+                    pool.insert(unit[inst].clone(), inst);
+                    order.push(inst);
+                }
+            }
+        }
+
+        // Move the pooled constants to the beginning of the entry block, such
+        // that they dominate all their uses.
+        let entry = unit.entry();
+        let mut leading = unit
+            .insts(entry)
+            .take_while(|&inst| unit[inst].opcode().is_const());
+        if order.iter().all(|&inst| leading.next() == Some(inst)) {
+            return modified;
+        }
+        for &inst in order.iter().rev() {
+            unit.remove_inst(inst);
+            unit.prepend_inst(inst, entry);
+        }
+        true
+    }
+}
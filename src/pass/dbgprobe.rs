@@ -0,0 +1,212 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Debug Probe Insertion
+
+use crate::{
+    ir::prelude::*,
+    opt::prelude::*,
+    ty::{has_zero, signal_ty},
+    value::TimeValue,
+};
+use num::Zero;
+use std::collections::HashMap;
+
+/// Debug Probe Insertion
+///
+/// This pass drives the results of selected instructions onto dedicated debug
+/// signals, such that they can be observed in a simulator. Comparing the traces
+/// before and after a pass helps to bisect miscompiles in long pipelines.
+///
+/// Entities receive local debug signals. Processes receive additional output
+/// ports, which are connected to local signals wherever the process is
+/// instantiated in the module. Processes instantiated by a frozen unit are
+/// skipped and reported, since their instances could not be connected.
+/// Functions cannot interact with signals and are left untouched.
+pub struct DebugProbeInsertion;
+
+/// A filter selecting the instructions to be probed.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeFilter {
+    /// Names of the values to be probed, without the leading `%`.
+    pub names: Vec<String>,
+    /// Mnemonics of the instructions to be probed, such as `add`.
+    pub opcodes: Vec<String>,
+}
+
+impl ProbeFilter {
+    /// Check if the filter selects no instructions at all.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.opcodes.is_empty()
+    }
+
+    /// Select instructions by value name (`%x`) or mnemonic (`add`).
+    pub fn add(&mut self, filter: &str) {
+        match filter.strip_prefix('%') {
+            Some(name) => self.names.push(name.to_string()),
+            None => self.opcodes.push(filter.to_string()),
+        }
+    }
+
+    /// Check if an instruction is selected by the filter.
+    pub fn matches(&self, unit: &Unit, inst: Inst) -> bool {
+        let name_matches = unit
+            .get_inst_result(inst)
+            .and_then(|value| unit.get_name(value))
+            .map(|name| self.names.iter().any(|n| n == name))
+            .unwrap_or(false);
+        name_matches || self.opcodes.contains(&unit[inst].opcode().to_string())
+    }
+}
+
+impl Pass for DebugProbeInsertion {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        // Processes instantiated by frozen units cannot receive additional
+        // outputs, since the instances could not be connected to them.
+        let mut frozen_parents = HashMap::new();
+        for unit in module.units().filter(|unit| !ctx.may_modify(unit.name())) {
+            for inst in unit.all_insts() {
+                if let Some(ext_unit) = unit[inst].get_ext_unit() {
+                    frozen_parents
+                        .entry(unit[ext_unit].name.clone())
+                        .or_insert_with(|| unit.name().clone());
+                }
+            }
+        }
+
+        // Insert the probes, and remember the processes whose signature has
+        // been extended with debug outputs.
+        let mut modified = false;
+        let mut extended = HashMap::new();
+        for mut unit in module.units_mut() {
+            if unit.is_process() && !ctx.probe_filter.is_empty() {
+                if let Some(parent) = frozen_parents.get(unit.name()) {
+                    ctx.skip_unit(
+                        unit.name(),
+                        "dbgprobe",
+                        format!("instantiated by frozen unit {}", parent),
+                    );
+                    continue;
+                }
+            }
+            if insert_probes(ctx, &mut unit) {
+                modified = true;
+                if unit.is_process() {
+                    let names: Vec<_> = unit
+                        .output_args()
+                        .map(|v| unit.get_name(v).map(String::from))
+                        .collect();
+                    extended.insert(unit.name().clone(), (unit.sig().clone(), names));
+                }
+            }
+        }
+
+        // Connect the new outputs wherever the processes are instantiated.
+        if !extended.is_empty() {
+            for mut unit in module.units_mut() {
+                connect_outputs(&mut unit, &extended);
+            }
+            let decls: Vec<_> = module.decls().collect();
+            for decl in decls {
+                if let Some((sig, _)) = extended.get(&module[decl].name) {
+                    module[decl].sig = sig.clone();
+                }
+            }
+        }
+        modified
+    }
+}
+
+/// Drive the selected values in a unit onto debug signals.
+fn insert_probes(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+    if unit.is_function() || ctx.probe_filter.is_empty() || !ctx.may_modify(unit.name()) {
+        return false;
+    }
+    let insts: Vec<_> = unit
+        .all_insts()
+        .filter(|&inst| ctx.probe_filter.matches(unit, inst))
+        .collect();
+    let mut modified = false;
+    for inst in insts {
+        let value = match unit.get_inst_result(inst) {
+            Some(value) => value,
+            None => continue,
+        };
+        let value_ty = unit.value_type(value);
+        let ty = if value_ty.is_signal() {
+            value_ty.unwrap_signal().clone()
+        } else {
+            value_ty.clone()
+        };
+        if !has_zero(&ty) {
+            trace!("Skipping {} (cannot probe {})", inst.dump(unit), value_ty);
+            continue;
+        }
+        info!("DbgProbe [{}] {}", unit.name(), inst.dump(unit));
+        let name = format!("dbg.{}", &value.dump(unit).to_string()[1..]);
+
+        // Insert after the instruction, or after all phi nodes of the block.
+        let bb = unit.inst_block(inst).unwrap();
+        match unit.insts(bb).find(|&i| !unit[i].opcode().is_phi()) {
+            Some(first) if unit[inst].opcode().is_phi() => unit.insert_before(first),
+            _ => unit.insert_after(inst),
+        }
+
+        // Create the debug signal.
+        let signal = if unit.is_entity() {
+            let init = unit.ins().const_zero(&ty);
+            unit.ins().sig(init)
+        } else {
+            unit.add_output(signal_ty(ty.clone()))
+        };
+        unit.set_name(signal, name);
+
+        // Drive the value onto the debug signal.
+        let value = if value_ty.is_signal() {
+            unit.ins().prb(value)
+        } else {
+            value
+        };
+        let delay = unit.ins().const_time(TimeValue::new(Zero::zero(), 0, 1));
+        unit.ins().drv(signal, value, delay);
+        modified = true;
+    }
+    modified
+}
+
+/// Connect the debug outputs added to instantiated processes to local signals.
+fn connect_outputs(
+    unit: &mut UnitBuilder,
+    extended: &HashMap<UnitName, (Signature, Vec<Option<String>>)>,
+) {
+    let ext_units: Vec<_> = unit
+        .extern_units()
+        .filter(|(_, data)| extended.contains_key(&data.name))
+        .map(|(ext_unit, _)| ext_unit)
+        .collect();
+    for ext_unit in ext_units {
+        let (sig, names) = extended[&unit[ext_unit].name].clone();
+        let num_outputs = unit[ext_unit].sig.outputs().count();
+        unit[ext_unit].sig = sig.clone();
+        let insts: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].get_ext_unit() == Some(ext_unit))
+            .collect();
+        for inst in insts {
+            unit.insert_before(inst);
+            let inputs = unit[inst].input_args().to_vec();
+            let mut outputs = unit[inst].output_args().to_vec();
+            for (arg, name) in sig.outputs().zip(names.iter()).skip(num_outputs) {
+                let ty = sig.arg_type(arg);
+                let init = unit.ins().const_zero(ty.unwrap_signal());
+                let signal = unit.ins().sig(init);
+                if let Some(name) = name {
+                    unit.set_name(signal, name.clone());
+                }
+                outputs.push(signal);
+            }
+            let new = unit.ins().inst(ext_unit, inputs, outputs);
+            unit.transfer_origins(inst, new);
+            unit.delete_inst(inst);
+        }
+    }
+}
@@ -9,6 +9,12 @@ use std::collections::{HashMap, HashSet};
 ///
 /// This pass implements dead code elimination. It removes instructions whose
 /// value is never used, trivial blocks, and blocks which cannot be reached.
+/// Phi nodes drop the entries of blocks which no longer branch to them, phi
+/// nodes which select the same value from all predecessors are replaced by
+/// that value, and phi nodes and instructions which only feed each other, such
+/// as an unused loop counter, are removed. Unused calls are only removed if
+/// they call a pure function, see `FunctionPurity`. Instructions and blocks
+/// annotated with `keep` or `dont_optimize` are left in place.
 pub struct DeadCodeElim;
 
 impl Pass for DeadCodeElim {
@@ -69,6 +75,7 @@ impl Pass for DeadCodeElim {
             // If this is the entry block, hoist the target up as the first block.
             if from == entry {
                 unit.swap_blocks(from, to);
+                unit.inherit_block_name(to, from);
             }
             modified |= true;
         }
@@ -78,6 +85,7 @@ impl Pass for DeadCodeElim {
             modified |= unit.prune_if_unused(inst);
         }
         modified |= prune_blocks(unit);
+        modified |= prune_phis(unit);
 
         // Detect trivially sequential blocks. We use a temporal predecessor
         // table here to avoid treating wait instructions as branches.
@@ -88,7 +96,7 @@ impl Pass for DeadCodeElim {
             let preds = pt.pred_set(bb);
             if preds.len() == 1 {
                 let pred = preds.iter().cloned().next().unwrap();
-                if pt.is_sole_succ(bb, pred) {
+                if pt.is_sole_succ(bb, pred) && is_mergeable(unit, bb, pred) {
                     let into = already_merged.get(&pred).cloned().unwrap_or(pred);
                     merge_blocks.push((bb, into));
                     already_merged.insert(bb, into);
@@ -119,6 +127,7 @@ impl Pass for DeadCodeElim {
             }
             unit.remove_inst(term);
             unit.replace_block_use(block, into);
+            unit.inherit_block_name(into, block);
             unit.delete_block(block);
         }
 
@@ -126,6 +135,17 @@ impl Pass for DeadCodeElim {
     }
 }
 
+/// Check if a block may be merged into its sole predecessor.
+fn is_mergeable(unit: &UnitBuilder, block: Block, into: Block) -> bool {
+    !unit.block_annotations(block).is_kept()
+        && unit
+            .inst_annotations(unit.terminator(into))
+            .is_optimizable()
+        && unit
+            .insts(block)
+            .all(|inst| unit.inst_annotations(inst).is_optimizable())
+}
+
 /// Check if a branch that terminates a block is trivial.
 fn check_branch_trivial(
     unit: &UnitBuilder,
@@ -141,6 +161,11 @@ fn check_branch_trivial(
     triv_br.insert(inst, None);
     trace!("Checking if trivial {}", inst.dump(&unit));
 
+    // Leave branches that must not be optimized untouched.
+    if !unit.inst_annotations(inst).is_optimizable() {
+        return None;
+    }
+
     // Now we know the block is empty. Check for a few common cases of trivial
     // branches.
     let data = &unit[inst];
@@ -186,9 +211,9 @@ fn check_block_retargetable(
         return None;
     }
 
-    // If the block is not trivially empty, it is retargetable but cannot be
-    // "jumped through".
-    if unit.first_inst(block) != unit.last_inst(block) {
+    // If the block is not trivially empty, or must be kept, it is retargetable
+    // but cannot be "jumped through".
+    if unit.first_inst(block) != unit.last_inst(block) || unit.block_annotations(block).is_kept() {
         triv_bb.insert(block, Some(block));
         return Some(block);
     }
@@ -205,12 +230,18 @@ fn check_block_retargetable(
 fn prune_blocks(unit: &mut UnitBuilder) -> bool {
     let mut modified = false;
 
-    // Find all blocks reachable from the entry point.
+    // Find all blocks reachable from the entry point, or from blocks that must
+    // be kept.
     let first_bb = unit.first_block().unwrap();
     let mut unreachable: HashSet<Block> = unit.blocks().collect();
-    let mut todo: Vec<Block> = Default::default();
+    let mut todo: Vec<Block> = unit
+        .blocks()
+        .filter(|&bb| unit.block_annotations(bb).is_kept())
+        .collect();
     todo.push(first_bb);
-    unreachable.remove(&first_bb);
+    for bb in &todo {
+        unreachable.remove(bb);
+    }
     while let Some(block) = todo.pop() {
         let term_inst = unit.terminator(block);
         for &bb in unit[term_inst].blocks() {
@@ -229,3 +260,118 @@ fn prune_blocks(unit: &mut UnitBuilder) -> bool {
 
     modified
 }
+
+/// Remove stale entries from phi nodes, replace trivial phi nodes by their
+/// value, and remove instructions that only feed each other.
+fn prune_phis(unit: &mut UnitBuilder) -> bool {
+    let mut modified = false;
+    let phis: Vec<Inst> = unit
+        .all_insts()
+        .filter(|&inst| unit[inst].opcode() == Opcode::Phi)
+        .filter(|&inst| !unit.inst_annotations(inst).is_kept())
+        .collect();
+
+    // Drop the entries for blocks which are no longer predecessors, e.g.
+    // because a conditional branch has been simplified. Keep at least one
+    // entry, such that the phi node remains well-formed.
+    let pt = unit.predtbl();
+    for &phi in &phis {
+        let preds = pt.pred_set(unit.inst_block(phi).unwrap());
+        let stale: Vec<Block> = unit[phi]
+            .blocks()
+            .iter()
+            .cloned()
+            .filter(|bb| !preds.contains(bb))
+            .collect();
+        if stale.len() == unit[phi].blocks().len() {
+            continue;
+        }
+        for bb in stale {
+            debug!("Dropping {} from {}", bb.dump(unit), phi.dump(unit));
+            unit.remove_block_from_inst(bb, phi);
+            modified = true;
+        }
+    }
+
+    // Replace phi nodes which select the same value from all predecessors,
+    // ignoring the phi node itself. This may turn the phi nodes which use
+    // them trivial as well.
+    let mut todo = phis;
+    let mut deleted = HashSet::new();
+    while let Some(phi) = todo.pop() {
+        if deleted.contains(&phi) {
+            continue;
+        }
+        let result = unit.inst_result(phi);
+        let mut values = unit[phi].args().iter().cloned().filter(|&v| v != result);
+        let value = match values.next() {
+            Some(value) if !value.is_invalid() && values.all(|v| v == value) => value,
+            _ => continue,
+        };
+        debug!(
+            "Replacing trivial {} with {}",
+            phi.dump(unit),
+            value.dump(unit)
+        );
+        todo.extend(
+            unit.uses(result)
+                .iter()
+                .cloned()
+                .filter(|&inst| inst != phi && unit[inst].opcode() == Opcode::Phi)
+                .filter(|&inst| !unit.inst_annotations(inst).is_kept()),
+        );
+        unit.replace_use(result, value);
+        unit.delete_inst(phi);
+        deleted.insert(phi);
+        modified = true;
+    }
+
+    // Find the instructions which are only used by each other, starting from
+    // the ones used elsewhere and marking their arguments as used.
+    let removable = |unit: &UnitBuilder, inst: Inst| {
+        unit.has_result(inst)
+            && !unit.inst_has_side_effects(inst)
+            && !unit.inst_annotations(inst).is_kept()
+    };
+    let candidates: HashSet<Inst> = unit
+        .all_insts()
+        .filter(|&inst| removable(unit, inst))
+        .collect();
+    let mut live: Vec<Inst> = candidates
+        .iter()
+        .cloned()
+        .filter(|&inst| {
+            unit.uses(unit.inst_result(inst))
+                .iter()
+                .any(|user| !candidates.contains(user))
+        })
+        .collect();
+    let mut used: HashSet<Inst> = live.iter().cloned().collect();
+    while let Some(inst) = live.pop() {
+        for &arg in unit[inst].args() {
+            if let Some(def) = unit.get_value_inst(arg) {
+                if candidates.contains(&def) && used.insert(def) {
+                    live.push(def);
+                }
+            }
+        }
+    }
+    let dead: Vec<Inst> = unit
+        .all_insts()
+        .filter(|inst| candidates.contains(inst) && !used.contains(inst))
+        .collect();
+    for &inst in &dead {
+        debug!(
+            "Removing {}, which only feeds dead instructions",
+            inst.dump(unit)
+        );
+        let result = unit.inst_result(inst);
+        unit.replace_use(result, Value::invalid());
+    }
+    for inst in dead {
+        unit.delete_inst(inst);
+        modified = true;
+    }
+
+    modified
+}
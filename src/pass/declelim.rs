@@ -0,0 +1,26 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Declaration Elimination
+
+use crate::{ir::prelude::*, opt::prelude::*};
+
+/// Declaration Elimination
+///
+/// This pass removes the declarations of a module which are redundant, since
+/// the module defines the unit or declares it multiple times, or which no
+/// unit refers to. Such declarations accumulate when modules are absorbed into
+/// one another.
+pub struct DeclarationElimination;
+
+impl Pass for DeclarationElimination {
+    fn run_on_module(_ctx: &PassContext, module: &mut Module) -> bool {
+        info!("DeclElim");
+        let merged = module.dedup_decls();
+        let removed = module.remove_unused_decls();
+        debug!(
+            "Merged {} duplicate and removed {} unused declarations",
+            merged, removed
+        );
+        merged + removed > 0
+    }
+}
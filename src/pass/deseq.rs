@@ -123,14 +123,8 @@ fn deseq_process(ctx: &PassContext, unit: &mut UnitBuilder) -> Option<UnitData>
         .collect();
 
     // Create a replacement entity.
-    let mut entity = UnitData::new(UnitKind::Entity, unit.name().clone(), unit.sig().clone());
+    let mut entity = UnitData::new_converted(UnitKind::Entity, unit.unit());
     let mut builder = UnitBuilder::new_anonymous(&mut entity);
-    for arg in unit.unit().sig().args() {
-        if let Some(name) = unit.get_name(unit.arg_value(arg)) {
-            let v = builder.arg_value(arg);
-            builder.set_name(v, name.to_string());
-        }
-    }
     let mut mig = Migrator::new(unit, &mut builder, &trg, tr0, tr1);
 
     // For each drive where we successfully and exhaustively identified the
@@ -140,8 +134,8 @@ fn deseq_process(ctx: &PassContext, unit: &mut UnitBuilder) -> Option<UnitData>
     for (inst, bb, trigs) in triggers {
         migrated &= mig.migrate_drive(inst, bb, &trigs);
     }
-    // crate::pass::ConstFolding::run_on_entity(ctx, &mut builder);
-    // crate::pass::DeadCodeElim::run_on_entity(ctx, &mut builder);
+    // crate::pass::ConstFolding::run_on_cfg(ctx, &mut builder);
+    // crate::pass::DeadCodeElim::run_on_cfg(ctx, &mut builder);
 
     // Check if all drives were migrated.
     // This will currently fail for any unconditional drives, since we don't yet
@@ -631,7 +625,8 @@ impl<'a, 'b> Migrator<'a, 'b> {
         }
 
         // Create the register instruction.
-        self.dst.ins().reg(mig_target, reg_triggers);
+        let reg = self.dst.ins().reg(mig_target, reg_triggers);
+        self.dst.add_origins(reg, self.src.origins(drive));
 
         // Drive the register value onto the output.
         self.migrated_drives.insert(drive);
@@ -0,0 +1,187 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Drive Peephole Optimization
+
+use crate::{ir::prelude::*, opt::prelude::*, value::IntValue};
+
+/// Drive Peephole Optimization
+///
+/// This pass simplifies the drive conditions and multiplexers produced by
+/// temporal code motion. It is intended to run after TCM has moved and
+/// coalesced the drives of a process.
+pub struct DrivePeephole;
+
+impl Pass for DrivePeephole {
+    fn run_on_inst(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+        if !unit.is_inst_inserted(inst) {
+            return false;
+        }
+        match unit[inst].opcode() {
+            Opcode::Mux => fold_const_mux(ctx, inst, unit),
+            Opcode::And => fold_and_true(ctx, inst, unit),
+            Opcode::Or => fold_exclusive_or(ctx, inst, unit),
+            Opcode::DrvCond => merge_drives(ctx, inst, unit),
+            _ => false,
+        }
+    }
+}
+
+/// Replace a `mux` of an array with a constant selector by the selected value.
+///
+/// `mux [%a, %b], 1` -> `%b`
+fn fold_const_mux(_ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    let array = unit[inst].args()[0];
+    let sel = unit[inst].args()[1];
+    let sel = match unit.get_const_int(sel) {
+        Some(sel) => sel.to_usize(),
+        None => return false,
+    };
+    let array_inst = match unit.get_value_inst(array) {
+        Some(array_inst) if unit[array_inst].opcode() == Opcode::Array => array_inst,
+        _ => return false,
+    };
+    let choice = match unit[array_inst].args().get(sel) {
+        Some(&choice) => choice,
+        None => return false,
+    };
+    replace(inst, choice, unit)
+}
+
+/// Replace an `and` with an all-ones operand by the other operand.
+///
+/// `and %a, 1` -> `%a`
+fn fold_and_true(_ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    let args = unit[inst].args();
+    let (a, b) = (args[0], args[1]);
+    if is_all_ones(unit, a) {
+        replace(inst, b, unit)
+    } else if is_all_ones(unit, b) {
+        replace(inst, a, unit)
+    } else {
+        false
+    }
+}
+
+/// Simplify an `or` of two mutually exclusive conditions.
+///
+/// `or %c, (not %c)` -> `1`
+/// `or (and %p, %c), (and %p, (not %c))` -> `%p`
+///
+/// These arise when TCM coalesces the drives in both arms of a `br_cond`.
+fn fold_exclusive_or(_ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    let args = unit[inst].args();
+    let (a, b) = (args[0], args[1]);
+    if is_complement(unit, a, b) {
+        // Only materialize the constant if the `or` is actually used.
+        if !unit.has_uses(unit.inst_result(inst)) {
+            return false;
+        }
+        unit.insert_before(inst);
+        let width = unit.value_type(a).unwrap_int();
+        let ones = unit.ins().const_int(IntValue::all_ones(width));
+        return replace(inst, ones, unit);
+    }
+    let (a, b) = match (split_and(unit, a), split_and(unit, b)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return false,
+    };
+    for &(pa, ca) in &[(a.0, a.1), (a.1, a.0)] {
+        for &(pb, cb) in &[(b.0, b.1), (b.1, b.0)] {
+            if pa == pb && is_complement(unit, ca, cb) {
+                return replace(inst, pa, unit);
+            }
+        }
+    }
+    false
+}
+
+/// Merge a conditional drive with a subsequent one of the same value.
+///
+/// `drv %s if %a, %v, %d` followed by `drv %s if %b, %v, %d` becomes
+/// `drv %s if (or %a, %b), %v, %d`, provided no other drive of `%s` or of a
+/// projection aliasing it (e.g. `extf`/`exts`) lies in between.
+fn merge_drives(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+    let args = unit[inst].args().to_vec();
+    let root = alias_root(unit, args[0]);
+    let mut next = unit.next_inst(inst);
+    while let Some(other) = next {
+        next = unit.next_inst(other);
+        let op = unit[other].opcode();
+        if op != Opcode::Drv && op != Opcode::DrvCond {
+            continue;
+        }
+        let other_args = unit[other].args().to_vec();
+        if other_args[0] != args[0] {
+            if alias_root(unit, other_args[0]) == root {
+                return false;
+            }
+            continue;
+        }
+        if op != Opcode::DrvCond || other_args[1..3] != args[1..3] {
+            return false;
+        }
+        debug!("Merge {} into {}", inst.dump(unit), other.dump(unit));
+        unit.insert_before(other);
+        let cond = unit.ins().or(args[3], other_args[3]);
+        let new = unit.ins().drv_cond(args[0], args[1], args[2], cond);
+        unit.transfer_origins(inst, new);
+        unit.transfer_origins(other, new);
+        unit.delete_inst(inst);
+        unit.delete_inst(other);
+        let cond_inst = unit.value_inst(cond);
+        fold_exclusive_or(ctx, cond_inst, unit);
+        return true;
+    }
+    false
+}
+
+/// Replace the result of an instruction with another value.
+fn replace(inst: Inst, to: Value, unit: &mut UnitBuilder) -> bool {
+    let value = unit.inst_result(inst);
+    debug!("Replace {} with {}", inst.dump(unit), to.dump(unit));
+    unit.replace_use(value, to) > 0
+}
+
+/// Resolve a signal to the signal it is a projection of.
+///
+/// Follows signal-typed operands of signal-typed instructions (e.g. `extf` and
+/// `exts`), the same way TCM builds its alias table.
+fn alias_root(unit: &Unit, mut signal: Value) -> Value {
+    while let Some(inst) = unit.get_value_inst(signal) {
+        match unit[inst]
+            .args()
+            .iter()
+            .find(|&&arg| unit.value_type(arg).is_signal())
+        {
+            Some(&arg) => signal = arg,
+            None => break,
+        }
+    }
+    signal
+}
+
+/// Check if a value is a constant with all bits set.
+fn is_all_ones(unit: &Unit, value: Value) -> bool {
+    unit.get_const_int(value)
+        .map(IntValue::is_all_ones)
+        .unwrap_or(false)
+}
+
+/// Check if one value is the `not` of the other.
+fn is_complement(unit: &Unit, a: Value, b: Value) -> bool {
+    let is_not_of = |x: Value, y: Value| match unit.get_value_inst(x) {
+        Some(inst) => unit[inst].opcode() == Opcode::Not && unit[inst].args()[0] == y,
+        None => false,
+    };
+    is_not_of(a, b) || is_not_of(b, a)
+}
+
+/// Get the operands of an `and` instruction.
+fn split_and(unit: &Unit, value: Value) -> Option<(Value, Value)> {
+    let inst = unit.get_value_inst(value)?;
+    if unit[inst].opcode() != Opcode::And {
+        return None;
+    }
+    let args = unit[inst].args();
+    Some((args[0], args[1]))
+}
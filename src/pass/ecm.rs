@@ -8,7 +8,8 @@ use std::collections::{HashMap, HashSet};
 /// Early Code Motion
 ///
 /// This moves all instructions as far upwards in the control flow graph as
-/// possible given the point of declaration of their arguments.
+/// possible given the point of declaration of their arguments. Only pure
+/// instructions are moved, including calls to pure functions.
 pub struct EarlyCodeMotion;
 
 impl Pass for EarlyCodeMotion {
@@ -73,15 +74,8 @@ fn move_instruction(
     dt: &DominatorTree,
     block_numbers: &HashMap<Block, usize>,
 ) -> bool {
-    // Some instructions we don't want to touch.
-    let op = unit[inst].opcode();
-    if op == Opcode::Ld
-        || op == Opcode::St
-        || op == Opcode::Prb
-        || op == Opcode::Drv
-        || op == Opcode::Phi
-        || op.is_terminator()
-    {
+    // Only move pure instructions.
+    if !unit.is_pure_inst(inst) {
         return false;
     }
     trace!("  Working on {}", inst.dump(&unit));
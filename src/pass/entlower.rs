@@ -0,0 +1,357 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Entity Lowering
+
+use crate::{
+    ir::prelude::*,
+    opt::prelude::*,
+    table::TableKey,
+    ty::Type,
+    value::{IntValue, TimeValue},
+};
+use num::Zero;
+use std::collections::{HashMap, HashSet};
+
+/// Entity Lowering
+///
+/// This pass implements demotion of entities to equivalent processes. This is
+/// the inverse of process lowering, and is useful for simulation-only flows
+/// that do not support the dataflow instructions `reg`, `del`, and `con`.
+pub struct EntityLowering;
+
+impl Pass for EntityLowering {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        if !unit.is_entity() || !is_suitable(ctx, unit) {
+            return false;
+        }
+        info!("EntLower [{}]", unit.name());
+        let process = lower_entity(unit);
+        *unit.data() = process;
+        true
+    }
+}
+
+/// Check if an entity is suitable for lowering to a process.
+fn is_suitable(_ctx: &PassContext, unit: &Unit) -> bool {
+    for inst in unit.all_insts() {
+        // Entity instantiations have no equivalent in a process.
+        if unit[inst].opcode() == Opcode::Inst {
+            trace!(
+                "Skipping {} ({} not allowed in process)",
+                unit.name(),
+                inst.dump(unit)
+            );
+            return false;
+        }
+
+        // Signal initializers are hoisted out of the body, so they must not
+        // depend on any probed signal values.
+        if unit[inst].opcode() == Opcode::Sig {
+            for dep in init_closure(unit, inst) {
+                if unit[dep].opcode() == Opcode::Prb {
+                    trace!(
+                        "Skipping {} ({} depends on a probe)",
+                        unit.name(),
+                        inst.dump(unit)
+                    );
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Collect the instructions that a signal and its initial value depend on.
+fn init_closure(unit: &Unit, inst: Inst) -> HashSet<Inst> {
+    let mut seen = HashSet::new();
+    let mut todo = vec![inst];
+    while let Some(inst) = todo.pop() {
+        if !seen.insert(inst) {
+            continue;
+        }
+        todo.extend(
+            unit[inst]
+                .args()
+                .iter()
+                .flat_map(|&arg| unit.get_value_inst(arg)),
+        );
+    }
+    seen
+}
+
+/// Convert an entity into an equivalent process.
+///
+/// The process consists of an `init` block which allocates signals and state,
+/// and a `body` block which re-evaluates the entity's dataflow and then waits
+/// for any of the probed signals to change.
+fn lower_entity(unit: &Unit) -> UnitData {
+    let mut process = UnitData::new_converted(UnitKind::Process, *unit);
+    let mut builder = UnitBuilder::new_anonymous(&mut process);
+    let init_bb = builder.named_block("init");
+    let body_bb = builder.named_block("body");
+
+    // Hoist all signals and their initial values into the init block.
+    let mut hoisted = HashSet::new();
+    for inst in unit.all_insts() {
+        if unit[inst].opcode() == Opcode::Sig {
+            hoisted.extend(init_closure(unit, inst));
+        }
+    }
+    let mut lowerer = Lowerer {
+        src: unit,
+        dst: builder,
+        values: HashMap::new(),
+        sensitivity: vec![],
+        init_term: Inst::invalid(),
+        body_bb,
+        inited: Value::invalid(),
+        delta: Value::invalid(),
+    };
+    lowerer.dst.append_to(init_bb);
+    for inst in unit.all_insts() {
+        if hoisted.contains(&inst) {
+            lowerer.clone_inst(inst);
+        }
+    }
+
+    // Allocate the state shared across evaluations of the body.
+    let zero = lowerer.dst.ins().const_int((1, 0));
+    lowerer.inited = lowerer.dst.ins().var(zero);
+    lowerer.dst.set_name(lowerer.inited, "inited".to_string());
+    lowerer.delta = lowerer
+        .dst
+        .ins()
+        .const_time(TimeValue::new(Zero::zero(), 1, 0));
+    lowerer.init_term = lowerer.dst.ins().br(body_bb);
+
+    // Lower the remaining instructions into the body.
+    lowerer.dst.append_to(body_bb);
+    for inst in unit.all_insts() {
+        if hoisted.contains(&inst) || unit[inst].opcode().is_terminator() {
+            continue;
+        }
+        match unit[inst].opcode() {
+            Opcode::Reg => lowerer.lower_reg(inst),
+            Opcode::Del | Opcode::DelCond => lowerer.lower_del(inst),
+            Opcode::Con => lowerer.lower_con(inst),
+            _ => lowerer.clone_inst(inst),
+        }
+    }
+    let one = lowerer.dst.ins().const_int((1, 1));
+    lowerer.dst.ins().st(lowerer.inited, one);
+    let sensitivity = lowerer.sensitivity.clone();
+    lowerer.dst.ins().wait(body_bb, sensitivity);
+    process
+}
+
+/// A helper to map entity instructions into a process.
+struct Lowerer<'a, 'b> {
+    src: &'a Unit<'a>,
+    dst: UnitBuilder<'b>,
+    /// Mapping from values in the entity to values in the process.
+    values: HashMap<Value, Value>,
+    /// Signals probed in the body, which the body must wait for.
+    sensitivity: Vec<Value>,
+    /// The terminator of the init block.
+    init_term: Inst,
+    /// The block that re-evaluates the entity.
+    body_bb: Block,
+    /// The `i1*` variable which indicates that the body ran at least once.
+    inited: Value,
+    /// A `0s 1d` delay used for the lowered `reg`, `del`, and `con`.
+    delta: Value,
+}
+
+impl Lowerer<'_, '_> {
+    /// Map a value of the entity to the corresponding value in the process.
+    fn map(&self, value: Value) -> Value {
+        if let Some(arg) = self.src.get_value_arg(value) {
+            return self.dst.arg_value(arg);
+        }
+        self.values[&value]
+    }
+
+    /// Clone an instruction verbatim, with its arguments remapped.
+    fn clone_inst(&mut self, inst: Inst) {
+        let mut data = self.src[inst].clone();
+        #[allow(deprecated)]
+        for arg in data.args_mut() {
+            *arg = self.map(*arg);
+        }
+        if data.opcode() == Opcode::Prb {
+            self.add_sensitivity(data.args()[0]);
+        }
+        let new_inst = self.dst.ins().build(data, self.src.inst_type(inst));
+        self.dst
+            .annotate_inst(new_inst, self.src.inst_annotations(inst));
+        if let Some(resolution) = self.src.signal_resolution(inst) {
+            self.dst.set_signal_resolution(new_inst, resolution.clone());
+        }
+        if let Some(value) = self.src.get_inst_result(inst) {
+            let new_value = self.dst.inst_result(new_inst);
+            if let Some(name) = self.src.get_name(value) {
+                self.dst.set_name(new_value, name.to_string());
+            }
+            self.values.insert(value, new_value);
+        }
+    }
+
+    /// Probe a signal and add it to the sensitivity list.
+    fn probe(&mut self, signal: Value) -> Value {
+        self.add_sensitivity(signal);
+        self.dst.ins().prb(signal)
+    }
+
+    fn add_sensitivity(&mut self, signal: Value) {
+        if !self.sensitivity.contains(&signal) {
+            self.sensitivity.push(signal);
+        }
+    }
+
+    /// Allocate a variable in the init block that persists across evaluations.
+    fn state_var(&mut self, ty: &Type) -> Value {
+        self.dst.insert_before(self.init_term);
+        let zero = self.dst.ins().const_zero(ty);
+        let var = self.dst.ins().var(zero);
+        self.dst.append_to(self.body_bb);
+        var
+    }
+
+    /// Lower a `reg` to a conditional drive.
+    ///
+    /// Edge triggers compare against the trigger value of the previous
+    /// evaluation, and are ignored during the first evaluation. An asynchronous
+    /// clear or preset takes precedence over the triggers, among which the
+    /// left-most one takes precedence. The initial value is driven during the
+    /// first evaluation if no trigger fires.
+    fn lower_reg(&mut self, inst: Inst) {
+        let target = self.map(self.src[inst].args()[0]);
+        let triggers: Vec<RegTrigger> = self.src[inst].triggers().collect();
+        let ctrl = self.src[inst].reg_controls();
+        let enable = ctrl.enable.map(|v| self.map(v));
+        let mut fires = vec![];
+        let ty = self.src.value_type(self.src[inst].args()[0]);
+        let ty = ty.unwrap_signal();
+        if let Some((mode, value)) = ctrl.clear {
+            let fire = self.level(mode, value);
+            let zero = self.dst.ins().const_zero(ty);
+            fires.push((zero, fire));
+        }
+        if let Some((mode, value)) = ctrl.preset {
+            let fire = self.level(mode, value);
+            let ones = self
+                .dst
+                .ins()
+                .const_int(IntValue::all_ones(ty.unwrap_int()));
+            fires.push((ones, fire));
+        }
+        for trig in triggers {
+            let data = self.map(trig.data);
+            let value = self.map(trig.trigger);
+            let fire = match trig.mode {
+                RegMode::Low => self.dst.ins().not(value),
+                RegMode::High => value,
+                mode => {
+                    let ty = self.src.value_type(trig.trigger);
+                    let prev_var = self.state_var(&ty);
+                    let prev = self.dst.ins().ld(prev_var);
+                    self.dst.ins().st(prev_var, value);
+                    let edge = match mode {
+                        RegMode::Rise => {
+                            let not_prev = self.dst.ins().not(prev);
+                            self.dst.ins().and(not_prev, value)
+                        }
+                        RegMode::Fall => {
+                            let not_value = self.dst.ins().not(value);
+                            self.dst.ins().and(prev, not_value)
+                        }
+                        _ => self.dst.ins().xor(prev, value),
+                    };
+                    let inited = self.dst.ins().ld(self.inited);
+                    self.dst.ins().and(edge, inited)
+                }
+            };
+            let fire = match trig.gate {
+                Some(gate) => {
+                    let gate = self.map(gate);
+                    self.dst.ins().and(fire, gate)
+                }
+                None => fire,
+            };
+            let fire = match enable {
+                Some(enable) => self.dst.ins().and(fire, enable),
+                None => fire,
+            };
+            fires.push((data, fire));
+        }
+        if let Some(init) = ctrl.init {
+            let init = self.map(init);
+            let inited = self.dst.ins().ld(self.inited);
+            let first = self.dst.ins().not(inited);
+            fires.push((init, first));
+        }
+
+        // Combine the triggers such that the left-most one takes precedence.
+        let (mut value, mut cond) = match fires.pop() {
+            Some(x) => x,
+            None => return,
+        };
+        for (data, fire) in fires.into_iter().rev() {
+            let choices = self.dst.ins().array(vec![value, data]);
+            value = self.dst.ins().mux(choices, fire);
+            cond = self.dst.ins().or(fire, cond);
+        }
+        self.dst.ins().drv_cond(target, value, self.delta, cond);
+    }
+
+    /// Compute whether a level-sensitive control is active.
+    fn level(&mut self, mode: RegMode, value: Value) -> Value {
+        let value = self.map(value);
+        match mode {
+            RegMode::Low => self.dst.ins().not(value),
+            _ => value,
+        }
+    }
+
+    /// Lower a `del` to a probe and a delayed drive.
+    fn lower_del(&mut self, inst: Inst) {
+        let args = self.src[inst].args();
+        let target = self.map(args[0]);
+        let source = self.map(args[1]);
+        let delay = self.map(args[2]);
+        let value = self.probe(source);
+        let drv = if self.src[inst].opcode() == Opcode::DelCond {
+            let cond = self.map(args[3]);
+            self.dst.ins().drv_cond(target, value, delay, cond)
+        } else {
+            self.dst.ins().drv(target, value, delay)
+        };
+        self.dst.annotate_inst(drv, self.src.inst_annotations(inst));
+    }
+
+    /// Lower a `con` to drives that propagate changes from one side to the
+    /// other.
+    fn lower_con(&mut self, inst: Inst) {
+        let args = self.src[inst].args();
+        let (a, b) = (self.map(args[0]), self.map(args[1]));
+        let ty = self.src.value_type(args[0]);
+        let ty = ty.unwrap_signal().clone();
+        let prev_a_var = self.state_var(&ty);
+        let prev_b_var = self.state_var(&ty);
+        let value_a = self.probe(a);
+        let value_b = self.probe(b);
+        let prev_a = self.dst.ins().ld(prev_a_var);
+        let prev_b = self.dst.ins().ld(prev_b_var);
+        let changed_a = self.dst.ins().neq(value_a, prev_a);
+        let changed_b = self.dst.ins().neq(value_b, prev_b);
+        let not_changed_a = self.dst.ins().not(changed_a);
+        let only_changed_b = self.dst.ins().and(changed_b, not_changed_a);
+        self.dst.ins().drv_cond(b, value_a, self.delta, changed_a);
+        self.dst
+            .ins()
+            .drv_cond(a, value_b, self.delta, only_changed_b);
+        self.dst.ins().st(prev_a_var, value_a);
+        self.dst.ins().st(prev_b_var, value_b);
+    }
+}
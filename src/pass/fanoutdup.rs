@@ -0,0 +1,112 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Fan-out Duplication
+
+use crate::{
+    analysis::{gate_estimate, FanoutTable},
+    ir::prelude::*,
+    opt::prelude::*,
+};
+
+/// Fan-out Duplication
+///
+/// This pass splits the uses of cheap computations with a high fan-out among
+/// copies of the computation, to relieve the nets which drive many sinks in
+/// hardware. A value whose load exceeds `FanoutLimits::max_load` is recomputed
+/// by as many copies as needed for each copy to drive at most that many
+/// operands, provided the instruction costs at most `max_gates` according to
+/// `gate_estimate`. The copies are inserted right after the original, such that
+/// they dominate the same uses. Instructions annotated with `dont_optimize`
+/// are left alone.
+pub struct FanoutDuplication;
+
+/// Thresholds that decide which instructions are duplicated.
+#[derive(Debug, Clone)]
+pub struct FanoutLimits {
+    /// Maximum load of a value before its instruction is duplicated, and of
+    /// each of the copies. See `Fanout::load`.
+    pub max_load: usize,
+    /// Maximum estimated number of gates of an instruction to be duplicated.
+    pub max_gates: usize,
+}
+
+impl Default for FanoutLimits {
+    fn default() -> Self {
+        Self {
+            max_load: 16,
+            max_gates: 64,
+        }
+    }
+}
+
+impl Pass for FanoutDuplication {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("FanoutDup [{}]", unit.name());
+        let limits = &ctx.fanout_limits;
+        let table = FanoutTable::new(unit);
+        let candidates: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| {
+                unit.get_inst_result(inst)
+                    .map(|value| table.get(value).load > limits.max_load)
+                    .unwrap_or(false)
+                    && unit.is_pure_inst(inst)
+                    && unit.inst_annotations(inst).is_optimizable()
+                    && gate_estimate(unit, inst)
+                        .map(|(_, gates)| gates <= limits.max_gates)
+                        .unwrap_or(false)
+            })
+            .collect();
+        let mut modified = false;
+        for inst in candidates {
+            modified |= duplicate(unit, inst, limits.max_load.max(1));
+        }
+        modified
+    }
+}
+
+/// Distribute the users of an instruction among copies of it, such that each
+/// drives a load of at most `max_load`.
+fn duplicate(unit: &mut UnitBuilder, inst: Inst, max_load: usize) -> bool {
+    let value = unit.inst_result(inst);
+    let mut users: Vec<_> = unit.uses(value).iter().cloned().collect();
+    users.sort();
+
+    // Fill the original first, then one copy after another, in the order the
+    // users were created.
+    let mut groups: Vec<Vec<Inst>> = vec![vec![]];
+    let mut load = 0;
+    for user in users {
+        let copies = unit.inst_replication(user).unwrap_or(1);
+        let operands = unit[user].args().iter().filter(|&&arg| arg == value);
+        let user_load = operands.count() * copies;
+        if load > 0 && load + user_load > max_load {
+            groups.push(vec![]);
+            load = 0;
+        }
+        groups.last_mut().unwrap().push(user);
+        load += user_load;
+    }
+    if groups.len() < 2 {
+        return false;
+    }
+    debug!("Duplicate {} {} times", inst.dump(unit), groups.len() - 1);
+
+    let data = unit[inst].clone();
+    let ty = unit.inst_type(inst);
+    let name = unit.get_name(value).map(String::from);
+    let origins = unit.origins(inst);
+    unit.insert_after(inst);
+    for group in groups.into_iter().skip(1) {
+        let copy = unit.ins().build(data.clone(), ty.clone());
+        unit.add_origins(copy, origins.iter().cloned());
+        let copy_value = unit.inst_result(copy);
+        if let Some(name) = &name {
+            unit.set_name(copy_value, name.clone());
+        }
+        for user in group {
+            unit.replace_value_within_inst(value, copy_value, user);
+        }
+    }
+    true
+}
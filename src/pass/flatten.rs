@@ -0,0 +1,281 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Hierarchy Flattening
+
+use crate::{
+    analysis::{ModuleStats, UnitStats},
+    ir::{prelude::*, Annotations, ExtUnit, ExtUnitData, InstData},
+    opt::prelude::*,
+    pass::replicate::expand_replication,
+    ty::Type,
+};
+use std::collections::HashMap;
+
+/// Hierarchy Flattening
+///
+/// This pass replaces `inst` instructions in entities with a copy of the
+/// instantiated entity. Whether an instance is flattened is decided based on
+/// the statistics of the module and the thresholds in `FlattenLimits`, such
+/// that large or frequently instantiated entities are kept as separate units.
+/// Replicated instances are expanded into one instance per copy before they
+/// are flattened, and count once per copy towards the limits.
+pub struct Flattening;
+
+/// Thresholds that decide whether an instance is flattened or a call inlined.
+#[derive(Debug, Clone)]
+pub struct FlattenLimits {
+    /// Maximum number of instructions of an entity or function to be flattened
+    /// or inlined.
+    pub max_unit_insts: usize,
+    /// Maximum number of places an entity or function may be instantiated or
+    /// called for it to be flattened or inlined. Each flattened instance or
+    /// inlined call duplicates the unit.
+    pub max_fanout: usize,
+    /// Maximum number of instructions a unit may grow to by flattening
+    /// instances or inlining calls into it.
+    pub max_parent_insts: usize,
+}
+
+impl Default for FlattenLimits {
+    fn default() -> Self {
+        Self {
+            max_unit_insts: 1000,
+            max_fanout: 16,
+            max_parent_insts: 100_000,
+        }
+    }
+}
+
+impl FlattenLimits {
+    /// Check if a unit with the given statistics may be flattened.
+    pub fn allows(&self, stats: &UnitStats) -> bool {
+        stats.num_insts <= self.max_unit_insts && stats.fanout <= self.max_fanout
+    }
+}
+
+impl Pass for Flattening {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        let stats = ctx.stats(module);
+        let templates: HashMap<UnitName, Template> = module
+            .entities()
+            .filter(|unit| ctx.flatten_limits.allows(&stats[unit.id()]))
+            .map(|unit| (unit.name().clone(), Template::new(unit)))
+            .collect();
+        if templates.is_empty() {
+            return false;
+        }
+
+        let parents: Vec<_> = module
+            .entities()
+            .filter(|unit| {
+                ctx.may_modify(unit.name())
+                    && unit
+                        .extern_units()
+                        .any(|(_, data)| templates.contains_key(&data.name))
+            })
+            .map(|unit| unit.id())
+            .collect();
+        let mut modified = false;
+        for parent in parents {
+            modified |= flatten_entity(ctx, &stats, &templates, &mut module.unit_mut(parent));
+        }
+        modified
+    }
+}
+
+/// Flatten the eligible instances within an entity.
+fn flatten_entity(
+    ctx: &PassContext,
+    stats: &ModuleStats,
+    templates: &HashMap<UnitName, Template>,
+    unit: &mut UnitBuilder,
+) -> bool {
+    let mut size = stats[unit.id()].num_insts;
+    let sim_only = unit.is_sim_only();
+
+    // Each site carries the names of the entities it has been copied out of,
+    // such that recursive instantiations are not flattened indefinitely.
+    let mut sites: Vec<(Inst, Vec<UnitName>)> = unit
+        .all_insts()
+        .filter(|&inst| unit[inst].opcode() == Opcode::Inst)
+        .map(|inst| (inst, vec![unit.name().clone()]))
+        .collect();
+    let mut modified = false;
+    while let Some((site, ancestry)) = sites.pop() {
+        let name = &unit[unit[site].get_ext_unit().unwrap()].name;
+        let template = match templates.get(name) {
+            Some(t) if !ancestry.contains(name) && (sim_only || !t.sim_only) => t,
+            _ => continue,
+        };
+        // Expand replicated instances into one instance per copy first, but
+        // only if all copies can be flattened.
+        if let Some(count) = unit.inst_replication(site) {
+            if size + count * template.insts.len() > ctx.flatten_limits.max_parent_insts {
+                debug!("Not expanding {} due to size limit", site.dump(unit));
+                continue;
+            }
+            debug!("Expanding {}", site.dump(unit));
+            for inst in expand_replication(unit, site) {
+                sites.push((inst, ancestry.clone()));
+            }
+            modified = true;
+            continue;
+        }
+        let new_size = size + template.insts.len() - 1;
+        if new_size > ctx.flatten_limits.max_parent_insts {
+            debug!("Not flattening {} due to size limit", site.dump(unit));
+            continue;
+        }
+        debug!("Flattening {}", site.dump(unit));
+        size = new_size;
+        let mut ancestry = ancestry;
+        ancestry.push(template.name.clone());
+        let prefix = ancestry[1..]
+            .iter()
+            .map(|name| name.get_name().unwrap_or("inst"))
+            .collect::<Vec<_>>()
+            .join(".");
+        for inst in template.instantiate(unit, site, &prefix) {
+            sites.push((inst, ancestry.clone()));
+        }
+        modified = true;
+    }
+    modified
+}
+
+/// A snapshot of an entity which can be copied into its instantiation sites.
+struct Template {
+    name: UnitName,
+    sim_only: bool,
+    inputs: Vec<Value>,
+    outputs: Vec<Value>,
+    insts: Vec<TemplateInst>,
+    ext_units: HashMap<ExtUnit, ExtUnitData>,
+}
+
+/// A snapshot of an instruction which can be copied into another unit.
+pub(crate) struct TemplateInst {
+    pub(crate) data: InstData,
+    pub(crate) ty: Type,
+    pub(crate) result: Option<Value>,
+    pub(crate) name: Option<String>,
+    pub(crate) origins: Vec<usize>,
+    pub(crate) annotations: Annotations,
+    pub(crate) branch_weights: Option<[u32; 2]>,
+    pub(crate) resolution: Option<Resolution>,
+    pub(crate) replication: Option<usize>,
+}
+
+impl TemplateInst {
+    /// Capture an instruction.
+    pub(crate) fn new(unit: Unit, inst: Inst) -> Self {
+        let result = unit.get_inst_result(inst);
+        Self {
+            data: unit[inst].clone(),
+            ty: unit.inst_type(inst),
+            result,
+            name: result.and_then(|v| unit.get_name(v)).map(String::from),
+            origins: unit.origins(inst).into_iter().collect(),
+            annotations: unit.inst_annotations(inst),
+            branch_weights: unit.branch_weights(inst),
+            resolution: unit.signal_resolution(inst).cloned(),
+            replication: unit.inst_replication(inst),
+        }
+    }
+}
+
+impl Template {
+    /// Capture an entity.
+    fn new(unit: Unit) -> Self {
+        let insts = unit
+            .all_insts()
+            .filter(|&inst| !unit[inst].opcode().is_terminator())
+            .map(|inst| TemplateInst::new(unit, inst))
+            .collect();
+        Self {
+            name: unit.name().clone(),
+            sim_only: unit.is_sim_only(),
+            inputs: unit.input_args().collect(),
+            outputs: unit.output_args().collect(),
+            insts,
+            ext_units: unit
+                .extern_units()
+                .map(|(ext, data)| (ext, data.clone()))
+                .collect(),
+        }
+    }
+
+    /// Replace an `inst` instruction with a copy of the template.
+    ///
+    /// The names of the copied values are prefixed with `prefix`. Returns the
+    /// `inst` instructions copied from the template.
+    fn instantiate(&self, unit: &mut UnitBuilder, site: Inst, prefix: &str) -> Vec<Inst> {
+        let mut values: HashMap<Value, Value> = HashMap::new();
+        values.extend(
+            self.inputs
+                .iter()
+                .cloned()
+                .zip(unit[site].input_args().to_vec()),
+        );
+        values.extend(
+            self.outputs
+                .iter()
+                .cloned()
+                .zip(unit[site].output_args().to_vec()),
+        );
+        let mut ext_units = HashMap::new();
+        let mut nested = vec![];
+
+        unit.insert_before(site);
+        for ti in &self.insts {
+            let mut data = ti.data.clone();
+            #[allow(deprecated)]
+            for arg in data.args_mut() {
+                *arg = values.get(arg).cloned().unwrap_or(*arg);
+            }
+            if let InstData::Call { unit: ext, .. } = &mut data {
+                let target = &self.ext_units[ext];
+                *ext = *ext_units
+                    .entry(*ext)
+                    .or_insert_with(|| find_or_add_extern(unit, target));
+            }
+            let inst = unit.ins().build(data, ti.ty.clone());
+            unit.add_origins(inst, ti.origins.iter().cloned());
+            unit.annotate_inst(inst, ti.annotations);
+            if let Some(resolution) = &ti.resolution {
+                unit.set_signal_resolution(inst, resolution.clone());
+            }
+            if let Some(count) = ti.replication {
+                unit.set_inst_replication(inst, count);
+            }
+            if let Some(result) = ti.result {
+                let value = unit.inst_result(inst);
+                if let Some(name) = &ti.name {
+                    unit.set_name(value, format!("{}.{}", prefix, name));
+                }
+                values.insert(result, value);
+            }
+            if unit[inst].opcode() == Opcode::Inst {
+                nested.push(inst);
+            }
+        }
+        unit.delete_inst(site);
+        nested
+    }
+}
+
+/// Find an external unit with the same name and signature, or declare one.
+pub(crate) fn find_or_add_extern(unit: &mut UnitBuilder, target: &ExtUnitData) -> ExtUnit {
+    let existing = unit
+        .extern_units()
+        .find(|(_, data)| data.name == target.name && data.sig == target.sig)
+        .map(|(ext, _)| ext);
+    match existing {
+        Some(ext) => ext,
+        None => {
+            let ext = unit.add_extern(target.name.clone(), target.sig.clone());
+            unit[ext].pure = target.pure;
+            ext
+        }
+    }
+}
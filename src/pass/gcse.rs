@@ -5,18 +5,24 @@
 use crate::{
     ir::{prelude::*, InstData},
     opt::prelude::*,
+    ty::Type,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap};
 
 /// Global Common Subexpression Elimination
 ///
 /// This pass implements global common subexpression elimination. It tries to
-/// eliminate redundant instructions.
+/// eliminate redundant instructions. Calls are only shared if they call a pure
+/// function, see `FunctionPurity`. Units with more instructions than
+/// `PassLimits::gcse_max_insts` are skipped.
 pub struct GlobalCommonSubexprElim;
 
 impl Pass for GlobalCommonSubexprElim {
-    fn run_on_cfg(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
         info!("GCSE [{}]", unit.name());
+        if ctx.exceeds_inst_limit(unit, "gcse", ctx.pass_limits.gcse_max_insts) {
+            return false;
+        }
 
         // Build the predecessor table and dominator tree.
         let pred = unit.predtbl();
@@ -37,17 +43,50 @@ impl Pass for GlobalCommonSubexprElim {
             }
         }
 
+        // Each call refers to its callee through an external unit of its own.
+        // Identify calls by the first external unit with the same name and
+        // signature instead, such that calls to the same function compare
+        // equal. Instructions are also told apart by their result type, since
+        // a struct and a named struct with the same fields are different.
+        let exts: Vec<_> = unit
+            .extern_units()
+            .map(|(ext, data)| (ext, data.clone()))
+            .collect();
+        let canonical_exts: HashMap<_, _> = exts
+            .iter()
+            .map(|(ext, data)| {
+                let first = exts
+                    .iter()
+                    .find(|(_, first)| first.name == data.name && first.sig == data.sig)
+                    .unwrap();
+                (*ext, first.0)
+            })
+            .collect();
+        let key = |unit: &UnitBuilder, inst: Inst| {
+            let mut data = unit[inst].clone();
+            if let InstData::Call { unit: ext, .. } = &mut data {
+                *ext = canonical_exts[ext];
+            }
+            (data, unit.inst_type(inst))
+        };
+
         // Perform GCSE.
         let mut modified = false;
-        let mut values = HashMap::<InstData, HashSet<Value>>::new();
+        // The candidates for each instruction are kept in an ordered set such
+        // that they are tried in the same order on every run.
+        let mut values = HashMap::<(InstData, Type), BTreeSet<Value>>::new();
         'outer: for inst in insts {
-            // Don't mess with instructions that produce no result or have side
-            // effects.
+            // Only consider pure instructions that produce a result. Signal
+            // reads can be shared within a temporal region, and phi nodes
+            // within a block. Instructions annotated with `dont_optimize` are
+            // neither replaced nor hoisted, and don't-care constants are kept
+            // apart from concrete ones.
             let opcode = unit[inst].opcode();
+            let is_signal_read = opcode.may_read_signals() && !opcode.has_side_effects();
             if !unit.has_result(inst)
-                || opcode == Opcode::Ld
-                || opcode == Opcode::Var
-                || opcode == Opcode::Sig
+                || !(unit.is_pure_inst(inst) || is_signal_read || opcode.is_phi())
+                || !unit.inst_annotations(inst).is_optimizable()
+                || unit.inst_annotations(inst).is_dont_care()
             {
                 continue;
             }
@@ -55,7 +94,7 @@ impl Pass for GlobalCommonSubexprElim {
             trace!("Examining {}", inst.dump(&unit));
 
             // Try the candidates.
-            if let Some(aliases) = values.get_mut(&unit[inst]) {
+            if let Some(aliases) = values.get_mut(&key(unit, inst)) {
                 'inner: for &cv in aliases.iter() {
                     trace!("  Trying {}", cv.dump(&unit));
                     let cv_inst = unit.value_inst(cv);
@@ -63,14 +102,16 @@ impl Pass for GlobalCommonSubexprElim {
                     let cv_bb = unit.inst_block(cv_inst).unwrap();
 
                     // Make sure that we don't merge `prb` instructions in
-                    // different temporal regions.
-                    if trg[inst_bb] != trg[cv_bb] {
+                    // different temporal regions, since the probed signal may
+                    // have changed in between. Pure values may be shared
+                    // across regions.
+                    if is_signal_read && trg[inst_bb] != trg[cv_bb] {
                         trace!("    Skipping because in other temporal region");
                         continue;
                     }
 
                     // Decide which dominator tree to use.
-                    let which_dt = if opcode == Opcode::Prb { &temp_dt } else { &dt };
+                    let which_dt = if is_signal_read { &temp_dt } else { &dt };
 
                     // Replace the current inst with the recorded value if the
                     // latter dominates the former.
@@ -127,6 +168,13 @@ impl Pass for GlobalCommonSubexprElim {
                     };
                     trace!("    Latest common dominator: {}", target_bb.dump(&unit));
 
+                    // Make sure that we don't hoist `prb` instructions out of
+                    // their temporal region.
+                    if is_signal_read && trg[target_bb] != trg[inst_bb] {
+                        trace!("    Skipping because dominator in other temporal region");
+                        continue;
+                    }
+
                     // Hoist the instruction up into the target block.
                     debug!(
                         "Hoist {} up into {}",
@@ -150,7 +198,7 @@ impl Pass for GlobalCommonSubexprElim {
             // Insert the instruction into the table.
             // trace!("Recording {}", inst.dump(&unit));
             values
-                .entry(unit[inst].clone())
+                .entry(key(unit, inst))
                 .or_insert_with(Default::default)
                 .insert(value);
         }
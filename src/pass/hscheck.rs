@@ -0,0 +1,25 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Handshake Checking
+
+use crate::{analysis::check_handshakes, ir::prelude::*, opt::prelude::*};
+
+/// Handshake Checking
+///
+/// This pass checks the ready/valid handshake bundles in the module against
+/// the rules of the protocol, see `check_handshakes`, and emits a warning for
+/// each violation. It never modifies the module, and is meant to be run after
+/// generators or passes which build or rewire handshake interfaces.
+pub struct HandshakeChecking;
+
+impl Pass for HandshakeChecking {
+    fn run_on_module(_ctx: &PassContext, module: &mut Module) -> bool {
+        info!("HandshakeCheck");
+        for unit in module.units() {
+            for violation in check_handshakes(module, &unit) {
+                warn!("{}: {}", unit.name(), violation.message(&unit));
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! If Conversion
+
+use crate::{analysis::PredecessorTable, ir::prelude::*, opt::prelude::*};
+use std::collections::HashSet;
+
+/// If Conversion
+///
+/// This pass converts small if/else diamonds and triangles into straight-line
+/// code. Phi nodes become `mux` instructions, and drives and stores in the
+/// branches become conditional on the branch condition.
+pub struct IfConversion;
+
+impl Pass for IfConversion {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("IfCvt [{}]", unit.name());
+        let mut modified = false;
+
+        // Converting a diamond turns it into a single block, which may in turn
+        // form the branch of an enclosing diamond. Keep going until no more
+        // diamonds are found.
+        while let Some(diamond) = find_diamond(ctx, unit) {
+            convert_diamond(ctx, unit, diamond);
+            modified |= true;
+        }
+        modified
+    }
+}
+
+/// The maximum number of instructions in a branch that is converted.
+const MAX_BRANCH_INSTS: usize = 8;
+
+/// A conditional branch which reconverges after at most one block.
+#[derive(Debug, Clone, Copy)]
+struct Diamond {
+    /// The block containing the conditional branch.
+    head: Block,
+    /// The blocks executed if the condition is 0 and 1, respectively. `None`
+    /// if the head branches directly to the merge block.
+    arms: [Option<Block>; 2],
+    /// The block where control flow reconverges.
+    merge: Block,
+}
+
+impl Diamond {
+    /// The block through which control flow enters the merge block if the
+    /// condition is 0 or 1.
+    fn incoming(&self, i: usize) -> Block {
+        self.arms[i].unwrap_or(self.head)
+    }
+}
+
+/// Find a diamond that can be converted.
+fn find_diamond(ctx: &PassContext, unit: &Unit) -> Option<Diamond> {
+    let pt = unit.predtbl();
+    for head in unit.blocks() {
+        let term = unit.terminator(head);
+        if unit[term].opcode() != Opcode::BrCond {
+            continue;
+        }
+        let targets = unit[term].blocks();
+        if targets[0] == targets[1] {
+            continue;
+        }
+
+        // Determine which targets are arms of the diamond, and where they
+        // reconverge.
+        let arms = [
+            arm_successor(ctx, unit, &pt, head, targets[0]),
+            arm_successor(ctx, unit, &pt, head, targets[1]),
+        ];
+        let diamond = match arms {
+            [Some(m0), Some(m1)] if m0 == m1 => Diamond {
+                head,
+                arms: [Some(targets[0]), Some(targets[1])],
+                merge: m0,
+            },
+            [Some(m0), _] if m0 == targets[1] => Diamond {
+                head,
+                arms: [Some(targets[0]), None],
+                merge: m0,
+            },
+            [_, Some(m1)] if m1 == targets[0] => Diamond {
+                head,
+                arms: [None, Some(targets[1])],
+                merge: m1,
+            },
+            _ => continue,
+        };
+
+        // Ensure that the merge block is only reached through the diamond.
+        let merge = diamond.merge;
+        let expected: HashSet<Block> = (0..2).map(|i| diamond.incoming(i)).collect();
+        if merge == head || merge == unit.entry() || *pt.pred_set(merge) != expected {
+            trace!(
+                "Skipping {} (merge block {} has other predecessors)",
+                head.dump(unit),
+                merge.dump(unit)
+            );
+            continue;
+        }
+        return Some(diamond);
+    }
+    None
+}
+
+/// Check if a block is a suitable arm of a diamond.
+///
+/// Returns the block to which the arm branches unconditionally.
+fn arm_successor(
+    _ctx: &PassContext,
+    unit: &Unit,
+    pt: &PredecessorTable,
+    head: Block,
+    arm: Block,
+) -> Option<Block> {
+    if arm == head || arm == unit.entry() || !pt.is_sole_pred(head, arm) {
+        return None;
+    }
+    let term = unit.terminator(arm);
+    if unit[term].opcode() != Opcode::Br {
+        return None;
+    }
+    let mut count = 0;
+    for inst in unit.insts(arm).filter(|&inst| inst != term) {
+        count += 1;
+        if count > MAX_BRANCH_INSTS || !is_convertible(unit[inst].opcode()) {
+            trace!(
+                "Skipping {} (cannot convert {})",
+                arm.dump(unit),
+                inst.dump(unit)
+            );
+            return None;
+        }
+    }
+    Some(unit[term].blocks()[0])
+}
+
+/// Check if an instruction can be executed unconditionally, or be made
+/// conditional.
+fn is_convertible(op: Opcode) -> bool {
+    if op.has_side_effects() {
+        is_predicable(op)
+    } else {
+        op.is_speculatable()
+    }
+}
+
+/// Convert a diamond into straight-line code.
+fn convert_diamond(_ctx: &PassContext, unit: &mut UnitBuilder, diamond: Diamond) {
+    let Diamond { head, arms, merge } = diamond;
+    debug!("Converting diamond at {}", head.dump(unit));
+    let head_term = unit.terminator(head);
+    let cond = unit[head_term].args()[0];
+
+    // Move the instructions of the arms into the head, making side effects
+    // conditional on the arm being taken.
+    for (i, &arm) in arms.iter().enumerate() {
+        let arm = match arm {
+            Some(arm) => arm,
+            None => continue,
+        };
+        let term = unit.terminator(arm);
+        let insts: Vec<_> = unit.insts(arm).filter(|&inst| inst != term).collect();
+        let needs_cond = insts
+            .iter()
+            .any(|&inst| unit[inst].opcode().has_side_effects());
+        let arm_cond = if needs_cond && i == 0 {
+            unit.insert_before(head_term);
+            unit.ins().not(cond)
+        } else {
+            cond
+        };
+        for inst in insts {
+            unit.remove_inst(inst);
+            unit.insert_inst_before(inst, head_term);
+            if unit[inst].opcode().has_side_effects() {
+                predicate_inst(unit, inst, arm_cond);
+            }
+        }
+    }
+
+    // Replace the phi nodes in the merge block with multiplexers.
+    let phis: Vec<_> = unit
+        .insts(merge)
+        .filter(|&inst| unit[inst].opcode() == Opcode::Phi)
+        .collect();
+    for phi in phis {
+        let values: Vec<Value> = (0..2)
+            .map(|i| {
+                let bb = diamond.incoming(i);
+                let index = unit[phi].blocks().iter().position(|&b| b == bb).unwrap();
+                unit[phi].args()[index]
+            })
+            .collect();
+        let result = unit.inst_result(phi);
+        let value = if values[0] == values[1] {
+            values[0]
+        } else {
+            unit.insert_before(head_term);
+            let choices = unit.ins().array(values);
+            let value = unit.ins().mux(choices, cond);
+            if let Some(name) = unit.get_name(result).map(String::from) {
+                unit.set_name(value, name);
+            }
+            value
+        };
+        unit.replace_use(result, value);
+        unit.delete_inst(phi);
+    }
+
+    // Remove the branch and the arms, and append the merge block to the head.
+    unit.delete_inst(head_term);
+    for &arm in arms.iter().flatten() {
+        unit.delete_block(arm);
+    }
+    while let Some(inst) = unit.first_inst(merge) {
+        unit.remove_inst(inst);
+        unit.append_inst(inst, head);
+    }
+    unit.replace_block_use(merge, head);
+    unit.inherit_block_name(head, merge);
+    unit.delete_block(merge);
+}
+
+/// Check if a side-effecting instruction has a conditional form.
+fn is_predicable(op: Opcode) -> bool {
+    op.is_conditional() || op.conditional().is_some()
+}
+
+/// Make a side-effecting instruction conditional on a value.
+fn predicate_inst(unit: &mut UnitBuilder, inst: Inst, cond: Value) {
+    let op = unit[inst].opcode();
+    let mut args = unit[inst].args().to_vec();
+    unit.insert_before(inst);
+    let cond = if op.is_conditional() {
+        let old = args.pop().unwrap();
+        unit.ins().and(old, cond)
+    } else {
+        cond
+    };
+    let new = match op.conditional().unwrap_or(op) {
+        Opcode::DrvCond => unit.ins().drv_cond(args[0], args[1], args[2], cond),
+        Opcode::StCond => unit.ins().st_cond(args[0], args[1], cond),
+        Opcode::DelCond => unit.ins().del_cond(args[0], args[1], args[2], cond),
+        _ => unreachable!(),
+    };
+    unit.transfer_origins(inst, new);
+    unit.delete_inst(inst);
+}
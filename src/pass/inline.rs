@@ -0,0 +1,400 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Function Inlining
+
+use crate::{
+    analysis::ModuleStats,
+    ir::{prelude::*, ExtUnit, ExtUnitData, InstData, Signature},
+    opt::prelude::*,
+    pass::flatten::{find_or_add_extern, TemplateInst},
+    ty::Type,
+};
+use std::collections::HashMap;
+
+/// Function Inlining
+///
+/// This pass replaces `call` instructions with a copy of the called function.
+/// Functions consisting of a single block are inlined as straight-line code
+/// into any unit. Functions with control flow are spliced into the control
+/// flow graph of the calling function or process: the block of the call is
+/// split, the blocks of the callee are copied in between, and each `ret` is
+/// replaced with a branch to the remainder of the block, which selects the
+/// returned value with a phi node. Entities only admit straight-line code.
+///
+/// Whether a call is inlined is decided based on the thresholds in
+/// `FlattenLimits`, like for hierarchy flattening. If the `PassContext` carries
+/// a profile, calls which have never executed are not inlined, and the most
+/// frequently executed calls are inlined first, such that they take precedence
+/// once the size limit is reached.
+pub struct FunctionInlining;
+
+impl Pass for FunctionInlining {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        let stats = ctx.stats(module);
+        let templates: HashMap<UnitName, Template> = module
+            .functions()
+            .filter(|unit| ctx.flatten_limits.allows(&stats[unit.id()]))
+            .map(|unit| (unit.name().clone(), Template::new(unit)))
+            .filter(|(_, template)| template.num_returns > 0)
+            .collect();
+        if templates.is_empty() {
+            return false;
+        }
+
+        let callers: Vec<_> = module
+            .units()
+            .filter(|unit| {
+                ctx.may_modify(unit.name())
+                    && unit
+                        .extern_units()
+                        .any(|(_, data)| templates.contains_key(&data.name))
+            })
+            .map(|unit| unit.id())
+            .collect();
+        let mut modified = false;
+        for caller in callers {
+            modified |= inline_calls(ctx, &stats, &templates, &mut module.unit_mut(caller));
+        }
+        modified
+    }
+}
+
+/// Inline the eligible calls within a unit.
+fn inline_calls(
+    ctx: &PassContext,
+    stats: &ModuleStats,
+    templates: &HashMap<UnitName, Template>,
+    unit: &mut UnitBuilder,
+) -> bool {
+    let mut size = stats[unit.id()].num_insts;
+    let sim_only = unit.is_sim_only();
+    let is_entity = unit.is_entity();
+
+    // Each site carries the names of the functions it has been copied out of,
+    // such that recursive calls are not inlined indefinitely.
+    let mut sites: Vec<(Inst, Vec<UnitName>)> = unit
+        .all_insts()
+        .filter(|&inst| unit[inst].opcode() == Opcode::Call)
+        .map(|inst| (inst, vec![unit.name().clone()]))
+        .collect();
+    sites.sort_by_key(|&(inst, _)| ctx.profile.inst_count(unit, inst));
+    let mut modified = false;
+    while let Some((site, ancestry)) = sites.pop() {
+        let ext = unit[site].get_ext_unit().unwrap();
+        let name = &unit[ext].name;
+        let template = match templates.get(name) {
+            Some(t)
+                if !ancestry.contains(name)
+                    && (sim_only || !t.sim_only)
+                    && (!is_entity || t.blocks.len() == 1)
+                    && t.sig == unit[ext].sig =>
+            {
+                t
+            }
+            _ => continue,
+        };
+        if ctx.profile.inst_count(unit, site) == Some(0) {
+            debug!("Not inlining {} (never executed)", site.dump(unit));
+            continue;
+        }
+        let new_size = size + template.num_insts - 1;
+        if new_size > ctx.flatten_limits.max_parent_insts {
+            debug!("Not inlining {} due to size limit", site.dump(unit));
+            continue;
+        }
+        debug!("Inlining {}", site.dump(unit));
+        size = new_size;
+        let mut ancestry = ancestry;
+        ancestry.push(template.name.clone());
+        let prefix = ancestry[1..]
+            .iter()
+            .map(|name| name.get_name().unwrap_or("call"))
+            .collect::<Vec<_>>()
+            .join(".");
+        for inst in template.inline(unit, site, &prefix) {
+            sites.push((inst, ancestry.clone()));
+        }
+        modified = true;
+    }
+    modified
+}
+
+/// A snapshot of a function which can be copied into its call sites.
+struct Template {
+    name: UnitName,
+    sig: Signature,
+    sim_only: bool,
+    inputs: Vec<Value>,
+    blocks: Vec<TemplateBlock>,
+    types: HashMap<Value, Type>,
+    num_insts: usize,
+    num_returns: usize,
+    ext_units: HashMap<ExtUnit, ExtUnitData>,
+}
+
+/// A block in a `Template`.
+struct TemplateBlock {
+    block: Block,
+    name: Option<String>,
+    insts: Vec<TemplateInst>,
+}
+
+impl Template {
+    /// Capture a function.
+    fn new(unit: Unit) -> Self {
+        let blocks: Vec<_> = unit
+            .blocks()
+            .map(|bb| TemplateBlock {
+                block: bb,
+                name: unit.get_block_name(bb).map(String::from),
+                insts: unit
+                    .insts(bb)
+                    .map(|inst| TemplateInst::new(unit, inst))
+                    .collect(),
+            })
+            .collect();
+        let insts = || blocks.iter().flat_map(|tb| tb.insts.iter());
+        Self {
+            name: unit.name().clone(),
+            sig: unit.sig().clone(),
+            sim_only: unit.is_sim_only(),
+            inputs: unit.input_args().collect(),
+            types: insts()
+                .flat_map(|ti| ti.result.map(|v| (v, ti.ty.clone())))
+                .collect(),
+            num_insts: insts().count(),
+            num_returns: insts().filter(|ti| ti.data.opcode().is_return()).count(),
+            ext_units: unit
+                .extern_units()
+                .map(|(ext, data)| (ext, data.clone()))
+                .collect(),
+            blocks,
+        }
+    }
+
+    /// Replace a `call` instruction with a copy of the template.
+    ///
+    /// The names of the copied values and blocks are prefixed with `prefix`.
+    /// Returns the `call` instructions copied from the template.
+    fn inline(&self, unit: &mut UnitBuilder, site: Inst, prefix: &str) -> Vec<Inst> {
+        let mut values: HashMap<Value, Value> = self
+            .inputs
+            .iter()
+            .cloned()
+            .zip(unit[site].input_args().to_vec())
+            .collect();
+        let mut placeholders: HashMap<Value, Value> = HashMap::new();
+        let mut ext_units = HashMap::new();
+        let mut returns = vec![];
+        let mut nested = vec![];
+
+        // Straight-line code goes right before the call. Otherwise split the
+        // block after the call and make room for the callee's blocks.
+        let site_bb = unit.inst_block(site).unwrap();
+        let mut blocks = HashMap::new();
+        let cont = if self.blocks.len() == 1 {
+            blocks.insert(self.blocks[0].block, site_bb);
+            None
+        } else {
+            let cont = unit.split_block(unit.next_inst(site).unwrap());
+            let br = unit.terminator(site_bb);
+            unit.delete_inst(br);
+            for tb in &self.blocks {
+                let bb = unit.block();
+                unit.detach_block(bb);
+                unit.insert_block_before(bb, cont);
+                if let Some(name) = &tb.name {
+                    unit.set_block_name(bb, format!("{}.{}", prefix, name));
+                }
+                blocks.insert(tb.block, bb);
+            }
+            Some(cont)
+        };
+
+        for tb in &self.blocks {
+            let bb = blocks[&tb.block];
+            match cont {
+                Some(_) => unit.append_to(bb),
+                None => unit.insert_before(site),
+            }
+            for ti in &tb.insts {
+                let mut data = ti.data.clone();
+                // Values defined further down are only used by phi nodes, and
+                // are replaced once their definition has been copied.
+                #[allow(deprecated)]
+                for arg in data.args_mut() {
+                    *arg = match values.get(arg) {
+                        Some(&value) => value,
+                        None => *placeholders
+                            .entry(*arg)
+                            .or_insert_with(|| unit.add_placeholder(self.types[arg].clone())),
+                    };
+                }
+                if data.opcode().is_return() {
+                    returns.push((data.args().first().cloned(), bb));
+                    if let Some(cont) = cont {
+                        unit.ins().br(cont);
+                    }
+                    continue;
+                }
+                #[allow(deprecated)]
+                for block in data.blocks_mut() {
+                    *block = blocks[block];
+                }
+                if let InstData::Call { unit: ext, .. } = &mut data {
+                    let target = &self.ext_units[ext];
+                    *ext = *ext_units
+                        .entry(*ext)
+                        .or_insert_with(|| find_or_add_extern(unit, target));
+                }
+                let inst = unit.ins().build(data, ti.ty.clone());
+                unit.add_origins(inst, ti.origins.iter().cloned());
+                unit.annotate_inst(inst, ti.annotations);
+                if let Some(weights) = ti.branch_weights {
+                    unit.set_branch_weights(inst, weights);
+                }
+                if let Some(resolution) = &ti.resolution {
+                    unit.set_signal_resolution(inst, resolution.clone());
+                }
+                if let Some(result) = ti.result {
+                    let value = unit.inst_result(inst);
+                    if let Some(name) = &ti.name {
+                        unit.set_name(value, format!("{}.{}", prefix, name));
+                    }
+                    if let Some(ph) = placeholders.remove(&result) {
+                        unit.replace_use(ph, value);
+                        unit.remove_placeholder(ph);
+                    }
+                    values.insert(result, value);
+                }
+                if unit[inst].opcode() == Opcode::Call {
+                    nested.push(inst);
+                }
+            }
+        }
+
+        // Enter the callee, and pick up the returned value after it.
+        if cont.is_some() {
+            unit.append_to(site_bb);
+            unit.ins().br(blocks[&self.blocks[0].block]);
+        }
+        if let Some(result) = unit.get_inst_result(site) {
+            let (args, bbs): (Vec<_>, Vec<_>) = returns
+                .into_iter()
+                .flat_map(|(value, bb)| value.map(|v| (v, bb)))
+                .unzip();
+            let value = match cont {
+                Some(cont) if args.len() > 1 => {
+                    unit.prepend_to(cont);
+                    let value = unit.ins().phi(args, bbs);
+                    if let Some(name) = unit.get_name(result).map(String::from) {
+                        unit.set_name(value, name);
+                    }
+                    value
+                }
+                _ => args[0],
+            };
+            unit.replace_use(result, value);
+        }
+        unit.delete_inst(site);
+        nested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{parse_module, write_module_string};
+
+    #[test]
+    fn inline_control_flow() {
+        let mut module = parse_module(
+            "
+            func @max (i32 %a, i32 %b) i32 {
+            entry:
+                %c = sgt i32 %a, %b
+                br %c, %lo, %hi
+            hi:
+                ret i32 %a
+            lo:
+                ret i32 %b
+            }
+            func @foo (i32 %x, i32 %y) i32 {
+            entry:
+                %z = call i32 @max (i32 %x, i32 %y)
+                %w = add i32 %z, %x
+                ret i32 %w
+            }
+            ",
+        )
+        .unwrap();
+        assert!(FunctionInlining::run_on_module(
+            &PassContext::default(),
+            &mut module
+        ));
+        module.verify();
+        let asm = write_module_string(&module);
+        let foo = &asm[asm.find("func @foo").unwrap()..];
+        assert!(!foo.contains("call"), "{}", foo);
+        assert!(
+            foo.contains("%z = phi i32 [%y, %max.lo], [%x, %max.hi]"),
+            "{}",
+            foo
+        );
+        assert!(foo.contains("%w = add i32 %z, %x"), "{}", foo);
+    }
+
+    #[test]
+    fn inline_straight_line_into_entity() {
+        let mut module = parse_module(
+            "
+            func @inc (i8 %a) i8 {
+            entry:
+                %one = const i8 1
+                %b = add i8 %a, %one
+                ret i8 %b
+            }
+            entity @top (i8$ %x) -> (i8$ %y) {
+                %xp = prb i8$ %x
+                %z = call i8 @inc (i8 %xp)
+                %t = const time 0s 1e
+                drv i8$ %y, %z, %t
+            }
+            ",
+        )
+        .unwrap();
+        assert!(FunctionInlining::run_on_module(
+            &PassContext::default(),
+            &mut module
+        ));
+        module.verify();
+        let asm = write_module_string(&module);
+        let top = &asm[asm.find("entity @top").unwrap()..];
+        assert!(top.contains("%inc.b = add i8 %xp, %inc.one"), "{}", top);
+        assert!(top.contains("drv i8$ %y, %inc.b, %t"), "{}", top);
+    }
+
+    #[test]
+    fn recursion_terminates() {
+        let mut module = parse_module(
+            "
+            func @fact (i32 %n) i32 {
+            entry:
+                %one = const i32 1
+                %done = ule i32 %n, %one
+                br %done, %rec, %base
+            base:
+                ret i32 %one
+            rec:
+                %m = sub i32 %n, %one
+                %r = call i32 @fact (i32 %m)
+                %p = umul i32 %n, %r
+                ret i32 %p
+            }
+            ",
+        )
+        .unwrap();
+        FunctionInlining::run_on_module(&PassContext::default(), &mut module);
+        module.verify();
+    }
+}
@@ -2,37 +2,49 @@
 
 //! Instruction Simplification
 
-use crate::ir::prelude::*;
+use crate::ir::{prelude::*, InstData};
 use crate::opt::prelude::*;
+use crate::value::IntValue;
 
 /// Instruction Simplification
 ///
 /// This pass implements various instruction combinations and simplifications.
+/// Nested slices, such as the ones used to probe or drive part of a signal,
+/// are collapsed into a single `exts` of the base value. Don't-care operands
+/// are replaced by whichever value makes an instruction trivial, such that
+/// `and %a, %dc` becomes `%a`, and `mux` ignores the choices which are
+/// don't-care.
 pub struct InstSimplification;
 
 impl Pass for InstSimplification {
     fn run_on_inst(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
         unit.insert_after(inst);
-        match unit[inst].opcode() {
-            // drv ... if 0 -> removed
-            // drv ... if 1 -> drv ...
-            Opcode::DrvCond => {
-                if let Some(konst) = unit.get_const_int(unit[inst].args()[3]) {
-                    if konst.is_one() {
-                        let signal = unit[inst].args()[0];
-                        let value = unit[inst].args()[1];
-                        let delay = unit[inst].args()[2];
-                        unit.ins().drv(signal, value, delay);
-                    }
-                    unit.delete_inst(inst);
+        // drv/st/del ... if 0 -> removed
+        // drv/st/del ... if 1 -> drv/st/del ...
+        if unit[inst].opcode().is_conditional() {
+            let (&cond, args) = unit[inst].args().split_last().unwrap();
+            if let Some(konst) = unit.get_const_int(cond) {
+                if konst.is_one() {
+                    let args = args.to_vec();
+                    let new = match unit[inst].opcode() {
+                        Opcode::DrvCond => unit.ins().drv(args[0], args[1], args[2]),
+                        Opcode::StCond => unit.ins().st(args[0], args[1]),
+                        Opcode::DelCond => unit.ins().del(args[0], args[1], args[2]),
+                        _ => unreachable!(),
+                    };
+                    unit.transfer_origins(inst, new);
                 }
+                unit.delete_inst(inst);
+                return true;
             }
-            _ => (),
         }
         let value = match unit.get_inst_result(inst) {
             Some(value) => value,
             None => return false,
         };
+        if simplify_dont_care(inst, value, unit) {
+            return true;
+        }
         match unit[inst].opcode() {
             // and %a, %a -> %a
             // or %a, %a -> %a
@@ -50,6 +62,7 @@ impl Pass for InstSimplification {
                 replace(inst, value, zero, unit)
             }
             Opcode::Mux => simplify_mux(ctx, inst, value, unit),
+            Opcode::ExtSlice => simplify_ext_slice(ctx, inst, value, unit),
             _ => false,
         }
     }
@@ -60,21 +73,91 @@ fn replace(from_inst: Inst, from_value: Value, to: Value, unit: &mut UnitBuilder
     unit.replace_use(from_value, to) > 0
 }
 
+fn simplify_ext_slice(
+    _ctx: &PassContext,
+    inst: Inst,
+    value: Value,
+    unit: &mut UnitBuilder,
+) -> bool {
+    let arg = unit[inst].args()[0];
+    let (offset, length) = (unit[inst].imms()[0], unit[inst].imms()[1]);
+
+    // exts %a, 0, N -> %a, if %a has N elements or bits
+    if offset == 0 && unit.value_type(arg) == unit.value_type(value) {
+        return replace(inst, value, arg, unit);
+    }
+
+    // exts (exts %a, o1, n1), o2, n2 -> exts %a, o1+o2, n2
+    if let Some(arg_inst) = unit.get_value_inst(arg) {
+        if unit[arg_inst].opcode() == Opcode::ExtSlice {
+            let base = unit[arg_inst].args()[0];
+            let base_offset = unit[arg_inst].imms()[0];
+            let new = unit.ins().ext_slice(base, base_offset + offset, length);
+            unit.transfer_origins(inst, unit.value_inst(new));
+            return replace(inst, value, new, unit);
+        }
+    }
+    false
+}
+
 fn simplify_mux(_ctx: &PassContext, inst: Inst, value: Value, unit: &mut UnitBuilder) -> bool {
     // Check if all options are identical, in which case simply replace us with
-    // the option directly.
+    // the option directly. Options which are don't-care may be assumed to be
+    // identical to the others.
     let array = unit[inst].args()[0];
     if let Some(array_inst) = unit.get_value_inst(array) {
-        let mut iter = unit[array_inst].args().iter().cloned();
-        let first = match iter.next() {
-            Some(first) => first,
+        let options = unit[array_inst].args();
+        let first = match options.first() {
+            Some(&first) => first,
             None => return false,
         };
-        let identical = iter.all(|a| a == first);
-        if identical {
-            return replace(inst, value, first, unit);
+        let mut cares = options.iter().cloned().filter(|&a| !unit.is_dont_care(a));
+        let chosen = cares.next().unwrap_or(first);
+        if cares.all(|a| a == chosen) {
+            return replace(inst, value, chosen, unit);
         }
     }
 
     false
 }
+
+fn simplify_dont_care(inst: Inst, value: Value, unit: &mut UnitBuilder) -> bool {
+    let args = match unit[inst] {
+        InstData::Binary { args, .. } => args,
+        _ => return false,
+    };
+    let (lhs_dc, rhs_dc) = (unit.is_dont_care(args[0]), unit.is_dont_care(args[1]));
+    if !lhs_dc && !rhs_dc {
+        return false;
+    }
+    match unit[inst].opcode() {
+        // eq %a, %dc -> dont_care
+        // neq %a, %dc -> dont_care
+        Opcode::Eq | Opcode::Neq => {
+            unit.insert_before(inst);
+            let dc = unit.ins().const_int(IntValue::zero(1));
+            unit.annotate_inst(unit.value_inst(dc), Annotations::DONT_CARE);
+            replace(inst, value, dc, unit)
+        }
+        // and %a, %dc -> %a, with %dc all ones
+        // or %a, %dc -> %a, with %dc zero
+        // ...
+        Opcode::And
+        | Opcode::Or
+        | Opcode::Xor
+        | Opcode::Add
+        | Opcode::Umul
+        | Opcode::Smul
+        | Opcode::Umin
+        | Opcode::Umax
+        | Opcode::Smin
+        | Opcode::Smax => {
+            let other = if rhs_dc { args[0] } else { args[1] };
+            replace(inst, value, other, unit)
+        }
+        // sub %a, %dc -> %a, with %dc zero
+        // [us]div %a, %dc -> %a, with %dc one
+        Opcode::Sub | Opcode::Udiv | Opcode::Sdiv if rhs_dc => replace(inst, value, args[0], unit),
+        _ => false,
+    }
+}
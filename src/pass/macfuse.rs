@@ -0,0 +1,50 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Multiply-Accumulate Fusion
+
+use crate::{ir::prelude::*, opt::prelude::*};
+
+/// Multiply-Accumulate Fusion
+///
+/// This pass identifies multiply-accumulate operations, namely an `add` of the
+/// product of a `umul` or `smul` which has no other uses, and marks both
+/// instructions with the `#mac` annotation. Backends and cost models may then
+/// treat the pair as one fused operation, e.g. to map it onto a DSP block. A
+/// chain of such additions, as in `a*b + (c*d + e)`, yields one fused pair per
+/// addition. If both operands of an `add` are products, only the first one is
+/// fused.
+pub struct MacFusion;
+
+impl Pass for MacFusion {
+    fn run_on_inst(_ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+        if unit[inst].opcode() != Opcode::Add
+            || !unit.inst_type(inst).is_int()
+            || unit.inst_annotations(inst).is_mac()
+            || !unit.inst_annotations(inst).is_optimizable()
+        {
+            return false;
+        }
+        let args = unit[inst].args().to_vec();
+        if args[0] == args[1] {
+            return false;
+        }
+        let mul = args.into_iter().find_map(|arg| {
+            let mul = unit.get_value_inst(arg)?;
+            let fusible = matches!(unit[mul].opcode(), Opcode::Umul | Opcode::Smul)
+                && unit.inst_block(mul) == unit.inst_block(inst)
+                && unit.uses(arg).len() == 1
+                && !unit.inst_annotations(mul).is_mac()
+                && unit.inst_annotations(mul).is_optimizable();
+            Some(mul).filter(|_| fusible)
+        });
+        match mul {
+            Some(mul) => {
+                debug!("Fusing {} into {}", mul.dump(unit), inst.dump(unit));
+                unit.annotate_inst(mul, Annotations::MAC);
+                unit.annotate_inst(inst, Annotations::MAC);
+                true
+            }
+            None => false,
+        }
+    }
+}
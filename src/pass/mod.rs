@@ -5,24 +5,167 @@
 //! This module implements various passes that analyze or mutate an LLHD
 //! intermediate representation.
 
+pub mod blockorder;
+pub mod bundlemerge;
+pub mod bundlesplit;
 pub mod cf;
 pub mod cfs;
+pub mod cpool;
+pub mod dbgprobe;
 pub mod dce;
+pub mod declelim;
 pub mod deseq;
+pub mod drvpeep;
 pub mod ecm;
+pub mod entlower;
+pub mod fanoutdup;
+pub mod flatten;
 pub mod gcse;
+pub mod hscheck;
+pub mod ifcvt;
+pub mod inline;
 pub mod insim;
+pub mod macfuse;
+pub mod partition;
 pub mod proclower;
+pub mod reassoc;
+pub mod replicate;
+pub mod sink;
 pub mod tcm;
 pub mod vtpp;
+pub mod waitrefine;
+pub mod widthlegal;
+pub mod wordlower;
 
+pub use blockorder::{BlockOrder, BlockOrdering};
+pub use bundlemerge::BundleMerging;
+pub use bundlesplit::BundleSplitting;
 pub use cf::ConstFolding;
 pub use cfs::ControlFlowSimplification;
+pub use cpool::ConstPooling;
+pub use dbgprobe::{DebugProbeInsertion, ProbeFilter};
 pub use dce::DeadCodeElim;
+pub use declelim::DeclarationElimination;
 pub use deseq::Desequentialization;
+pub use drvpeep::DrivePeephole;
 pub use ecm::EarlyCodeMotion;
+pub use entlower::EntityLowering;
+pub use fanoutdup::{FanoutDuplication, FanoutLimits};
+pub use flatten::{FlattenLimits, Flattening};
 pub use gcse::GlobalCommonSubexprElim;
+pub use hscheck::HandshakeChecking;
+pub use ifcvt::IfConversion;
+pub use inline::FunctionInlining;
 pub use insim::InstSimplification;
+pub use macfuse::MacFusion;
+pub use partition::{EntityPartitioning, PartitionLimits};
 pub use proclower::ProcessLowering;
+pub use reassoc::Reassociation;
+pub use replicate::InstanceReplication;
+pub use sink::CodeSinking;
 pub use tcm::TemporalCodeMotion;
 pub use vtpp::VarToPhiPromotion;
+pub use waitrefine::WaitRefinement;
+pub use widthlegal::{ExtensionPolicy, WidthLegalization};
+pub use wordlower::WordLowering;
+
+use crate::{ir::Module, opt::prelude::*};
+
+/// Run a pass on a module, given its command line name.
+///
+/// Returns whether the pass modified the module, or `None` if there is no pass
+/// with the given name. With the `tracing` feature, the pass runs within a
+/// `pass` span which records the name of the pass and whether it modified the
+/// module.
+pub fn run_by_name(name: &str, ctx: &PassContext, module: &mut Module) -> Option<bool> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!("pass", name, modified = tracing::field::Empty).entered();
+    let modified = match name {
+        "blockorder" => BlockOrdering::run_on_module(ctx, module),
+        "bundlemerge" => BundleMerging::run_on_module(ctx, module),
+        "bundlesplit" => BundleSplitting::run_on_module(ctx, module),
+        "cf" => ConstFolding::run_on_module(ctx, module),
+        "cfs" => ControlFlowSimplification::run_on_module(ctx, module),
+        "cpool" => ConstPooling::run_on_module(ctx, module),
+        "dbgprobe" => DebugProbeInsertion::run_on_module(ctx, module),
+        "dce" => DeadCodeElim::run_on_module(ctx, module),
+        "declelim" => DeclarationElimination::run_on_module(ctx, module),
+        "deseq" => Desequentialization::run_on_module(ctx, module),
+        "drvpeep" => DrivePeephole::run_on_module(ctx, module),
+        "ecm" => EarlyCodeMotion::run_on_module(ctx, module),
+        "entlower" => EntityLowering::run_on_module(ctx, module),
+        "fanoutdup" => FanoutDuplication::run_on_module(ctx, module),
+        "flatten" => Flattening::run_on_module(ctx, module),
+        "gcse" => GlobalCommonSubexprElim::run_on_module(ctx, module),
+        "hscheck" => HandshakeChecking::run_on_module(ctx, module),
+        "ifcvt" => IfConversion::run_on_module(ctx, module),
+        "inline" => FunctionInlining::run_on_module(ctx, module),
+        "insim" => InstSimplification::run_on_module(ctx, module),
+        "macfuse" => MacFusion::run_on_module(ctx, module),
+        "partition" => EntityPartitioning::run_on_module(ctx, module),
+        "proclower" => ProcessLowering::run_on_module(ctx, module),
+        "reassoc" => Reassociation::run_on_module(ctx, module),
+        "replicate" => InstanceReplication::run_on_module(ctx, module),
+        "sink" => CodeSinking::run_on_module(ctx, module),
+        "tcm" => TemporalCodeMotion::run_on_module(ctx, module),
+        "vtpp" => VarToPhiPromotion::run_on_module(ctx, module),
+        "waitrefine" => WaitRefinement::run_on_module(ctx, module),
+        "widthlegal" => WidthLegalization::run_on_module(ctx, module),
+        "wordlower" => WordLowering::run_on_module(ctx, module),
+        _ => return None,
+    };
+    #[cfg(feature = "tracing")]
+    span.record("modified", modified);
+    Some(modified)
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[test]
+    fn pass_and_unit_spans() {
+        let mut module = parse_module(
+            "
+            func @foo () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                ret i8 %b
+            }
+            ",
+        )
+        .unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || Writer(writer.clone()))
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_target(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            run_by_name("cf", &PassContext::default(), &mut module);
+        });
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("pass{name=\"cf\" modified=true}: close time.busy="));
+        assert!(
+            output.contains("pass{name=\"cf\"}:unit{name=@foo modified=true}: close time.busy=")
+        );
+    }
+
+    struct Writer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Writer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
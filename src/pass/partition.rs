@@ -0,0 +1,472 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Entity Partitioning
+
+use crate::{
+    ir::{prelude::*, InstData, Signature},
+    opt::prelude::*,
+    pass::flatten::{find_or_add_extern, TemplateInst},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Entity Partitioning
+///
+/// This pass splits entities with more instructions than allowed by
+/// `PartitionLimits` into several child entities, which the original entity
+/// instantiates, such that no single unit exceeds the memory and time budgets
+/// of later passes and backends.
+///
+/// Entities are only cut along signals, which keeps the behavior of the design
+/// exact: the signals and their projections remain in the parent and are
+/// passed to the children as ports. Constants and probes are copied into each
+/// child that uses them. The remaining instructions are grouped into clusters
+/// which are connected by values other than signals, and thus must end up in
+/// the same child. The clusters are then packed into children greedily,
+/// largest first, each into the child which already accesses the most of its
+/// signals and still has room for it. This keeps the number of signals
+/// crossing between children low. Clusters which alone exceed the limit
+/// become a child of their own.
+pub struct EntityPartitioning;
+
+/// Thresholds that decide whether and how an entity is partitioned.
+#[derive(Debug, Clone)]
+pub struct PartitionLimits {
+    /// Maximum number of instructions of an entity before it is partitioned,
+    /// and of each of the resulting child entities.
+    pub max_unit_insts: usize,
+}
+
+impl Default for PartitionLimits {
+    fn default() -> Self {
+        Self {
+            max_unit_insts: 100_000,
+        }
+    }
+}
+
+impl Pass for EntityPartitioning {
+    fn run_on_module(ctx: &PassContext, module: &mut Module) -> bool {
+        let stats = ctx.stats(module);
+        let limit = ctx.partition_limits.max_unit_insts;
+        let large: Vec<_> = module
+            .entities()
+            .filter(|unit| stats[unit.id()].num_insts > limit && ctx.may_modify(unit.name()))
+            .map(|unit| unit.id())
+            .collect();
+        let mut modified = false;
+        for id in large {
+            let parts = partition(module.unit(id), limit);
+            if parts.len() < 2 {
+                continue;
+            }
+            info!(
+                "Partition [{}] into {} entities",
+                module.unit(id).name(),
+                parts.len()
+            );
+            let children: Vec<_> = parts
+                .iter()
+                .enumerate()
+                .map(|(i, part)| extract_child(module, id, part, i))
+                .collect();
+            replace_parts(&mut module.unit_mut(id), &parts, children);
+            modified = true;
+        }
+        modified
+    }
+}
+
+/// A group of instructions to be moved into a child entity.
+struct Part {
+    /// The instructions, in the order of the original entity.
+    insts: Vec<Inst>,
+    /// The signals read by the instructions.
+    inputs: Vec<Value>,
+    /// The signals driven or connected by the instructions.
+    outputs: Vec<Value>,
+}
+
+/// Partition the instructions of an entity.
+///
+/// Returns the parts to be moved into child entities. The instructions not
+/// covered by any part remain in the entity.
+fn partition(unit: Unit, limit: usize) -> Vec<Part> {
+    let insts: Vec<Inst> = unit
+        .all_insts()
+        .filter(|&inst| !unit[inst].opcode().is_terminator())
+        .collect();
+    let is_signal = |value: Value| unit.value_type(value).is_signal();
+    let defining = |value: Value| {
+        unit.get_value_inst(value)
+            .filter(|&inst| !is_copied(&unit, inst))
+    };
+
+    // Signals stay in the parent, together with all values they are computed
+    // from.
+    let mut pinned = HashSet::new();
+    let mut todo: Vec<Inst> = insts
+        .iter()
+        .cloned()
+        .filter(|&inst| unit.get_inst_result(inst).map(is_signal).unwrap_or(false))
+        .collect();
+    while let Some(inst) = todo.pop() {
+        if pinned.insert(inst) {
+            todo.extend(
+                operands(&unit, inst)
+                    .filter(|&arg| !is_signal(arg))
+                    .flat_map(defining),
+            );
+        }
+    }
+
+    // Group the remaining instructions into clusters connected by values other
+    // than signals. Clusters which depend on a pinned value are pinned.
+    let movable: Vec<Inst> = insts
+        .iter()
+        .cloned()
+        .filter(|&inst| !pinned.contains(&inst) && !is_copied(&unit, inst))
+        .collect();
+    let index: HashMap<Inst, usize> = movable.iter().enumerate().map(|(i, &x)| (x, i)).collect();
+    let mut leader: Vec<usize> = (0..movable.len()).collect();
+    let find = |leader: &mut Vec<usize>, mut i: usize| {
+        while leader[i] != i {
+            leader[i] = leader[leader[i]];
+            i = leader[i];
+        }
+        i
+    };
+    let mut pinned_leaders = HashSet::new();
+    for (i, &inst) in movable.iter().enumerate() {
+        for arg in operands(&unit, inst) {
+            let def = match defining(arg) {
+                Some(def) if !is_signal(arg) => def,
+                _ => continue,
+            };
+            match index.get(&def) {
+                Some(&j) => {
+                    let (a, b) = (find(&mut leader, i), find(&mut leader, j));
+                    leader[a.max(b)] = a.min(b);
+                }
+                None => {
+                    pinned_leaders.insert(i);
+                }
+            }
+        }
+    }
+    let pinned_leaders: HashSet<usize> = pinned_leaders
+        .into_iter()
+        .map(|i| find(&mut leader, i))
+        .collect();
+    let mut clusters: HashMap<usize, Vec<Inst>> = HashMap::new();
+    for (i, &inst) in movable.iter().enumerate() {
+        let l = find(&mut leader, i);
+        if !pinned_leaders.contains(&l) {
+            clusters.entry(l).or_default().push(inst);
+        }
+    }
+
+    // Pack the clusters into parts, largest first.
+    let mut clusters: Vec<(usize, Vec<Inst>)> = clusters.into_iter().collect();
+    clusters.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+    let mut parts: Vec<(Vec<Inst>, HashSet<Value>)> = vec![];
+    for (_, cluster) in clusters {
+        let signals: HashSet<Value> = cluster
+            .iter()
+            .flat_map(|&inst| signal_args(&unit, inst))
+            .collect();
+        let best = parts
+            .iter()
+            .enumerate()
+            .filter(|(_, (insts, _))| insts.len() + cluster.len() <= limit)
+            .max_by(|(i, a), (j, b)| {
+                let a = a.1.intersection(&signals).count();
+                let b = b.1.intersection(&signals).count();
+                a.cmp(&b).then(j.cmp(i))
+            })
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => {
+                parts[i].0.extend(cluster);
+                parts[i].1.extend(signals);
+            }
+            None => parts.push((cluster, signals)),
+        }
+    }
+
+    // Determine the ports of each part.
+    let order: HashMap<Inst, usize> = insts.iter().enumerate().map(|(i, &x)| (x, i)).collect();
+    parts
+        .into_iter()
+        .map(|(mut insts, _)| {
+            insts.sort_by_key(|inst| order[inst]);
+            let mut driven = HashSet::new();
+            for &inst in &insts {
+                let data = &unit[inst];
+                match data.opcode() {
+                    Opcode::Drv | Opcode::DrvCond | Opcode::Del | Opcode::Reg => {
+                        driven.insert(data.args()[0]);
+                    }
+                    Opcode::Con => driven.extend(data.args().iter().cloned()),
+                    Opcode::Inst => driven.extend(data.output_args().iter().cloned()),
+                    _ => (),
+                }
+            }
+            let mut inputs = vec![];
+            let mut outputs = vec![];
+            let mut seen = HashSet::new();
+            for &inst in &insts {
+                for arg in signal_args(&unit, inst) {
+                    if seen.insert(arg) {
+                        if driven.contains(&arg) {
+                            outputs.push(arg);
+                        } else {
+                            inputs.push(arg);
+                        }
+                    }
+                }
+            }
+            Part {
+                insts,
+                inputs,
+                outputs,
+            }
+        })
+        .collect()
+}
+
+/// Check whether an instruction is copied into each unit that uses it, rather
+/// than moved into a child entity.
+fn is_copied(unit: &Unit, inst: Inst) -> bool {
+    unit[inst].opcode().is_const() || unit[inst].opcode() == Opcode::Prb
+}
+
+/// Collect the signals an instruction accesses, including the signals probed
+/// by the copied instructions it uses, in order.
+fn signal_args(unit: &Unit, inst: Inst) -> Vec<Value> {
+    let mut signals = vec![];
+    for arg in operands(unit, inst) {
+        if unit.value_type(arg).is_signal() {
+            signals.push(arg);
+        } else if let Some(def) = unit.get_value_inst(arg).filter(|&d| is_copied(unit, d)) {
+            signals.extend(operands(unit, def));
+        }
+    }
+    signals
+}
+
+/// Iterate over the operands of an instruction, skipping the absent ones,
+/// such as the gate of a `reg` trigger without one.
+fn operands(unit: &Unit, inst: Inst) -> std::vec::IntoIter<Value> {
+    let args: Vec<_> = unit[inst]
+        .args()
+        .iter()
+        .cloned()
+        .filter(|arg| !arg.is_invalid())
+        .collect();
+    args.into_iter()
+}
+
+/// Create a child entity with a copy of the instructions of a part.
+///
+/// Returns the name and signature of the child.
+fn extract_child(
+    module: &mut Module,
+    parent: UnitId,
+    part: &Part,
+    index: usize,
+) -> (UnitName, Signature) {
+    let unit = module.unit(parent);
+    let base = unit.name().get_name().unwrap_or("entity").to_string();
+    let mut name = UnitName::local(format!("{}.part{}", base, index));
+    let mut n = 0;
+    while module.lookup(&name).is_some() {
+        n += 1;
+        name = UnitName::local(format!("{}.part{}.{}", base, index, n));
+    }
+    let mut sig = Signature::new();
+    for &value in &part.inputs {
+        sig.add_input(unit.value_type(value));
+    }
+    for &value in &part.outputs {
+        sig.add_output(unit.value_type(value));
+    }
+
+    // Capture the instructions, and the constants and probes they use.
+    let mut copies = vec![];
+    let mut seen = HashSet::new();
+    for &inst in &part.insts {
+        for arg in operands(&unit, inst) {
+            match unit.get_value_inst(arg) {
+                Some(def) if is_copied(&unit, def) && seen.insert(def) => {
+                    copies.push(TemplateInst::new(unit, def))
+                }
+                _ => (),
+            }
+        }
+    }
+    let insts: Vec<_> = part
+        .insts
+        .iter()
+        .map(|&inst| TemplateInst::new(unit, inst))
+        .collect();
+    let ext_units: HashMap<_, _> = unit
+        .extern_units()
+        .map(|(ext, data)| (ext, data.clone()))
+        .collect();
+    let ports: Vec<_> = part
+        .inputs
+        .iter()
+        .chain(part.outputs.iter())
+        .map(|&value| (value, unit.get_name(value).map(String::from)))
+        .collect();
+
+    let mut data = UnitData::new(UnitKind::Entity, name.clone(), sig.clone());
+    data.sim_only = unit.is_sim_only();
+    let id = module.add_unit(data);
+    let mut child = module.unit_mut(id);
+    let mut values = HashMap::new();
+    for ((value, port_name), arg) in ports.into_iter().zip(child.args().collect::<Vec<_>>()) {
+        if let Some(port_name) = port_name {
+            child.set_name(arg, port_name);
+        }
+        values.insert(value, arg);
+    }
+    let term = child.terminator(child.entry());
+    child.insert_before(term);
+    let mut ext_map = HashMap::new();
+    for ti in copies.iter().chain(insts.iter()) {
+        let mut data = ti.data.clone();
+        #[allow(deprecated)]
+        for arg in data.args_mut() {
+            *arg = values.get(arg).cloned().unwrap_or(*arg);
+        }
+        if let InstData::Call { unit: ext, .. } = &mut data {
+            let target = &ext_units[ext];
+            *ext = *ext_map
+                .entry(*ext)
+                .or_insert_with(|| find_or_add_extern(&mut child, target));
+        }
+        let inst = child.ins().build(data, ti.ty.clone());
+        child.add_origins(inst, ti.origins.iter().cloned());
+        child.annotate_inst(inst, ti.annotations);
+        if let Some(resolution) = &ti.resolution {
+            child.set_signal_resolution(inst, resolution.clone());
+        }
+        if let Some(count) = ti.replication {
+            child.set_inst_replication(inst, count);
+        }
+        if let Some(result) = ti.result {
+            let value = child.inst_result(inst);
+            if let Some(name) = &ti.name {
+                child.set_name(value, name.clone());
+            }
+            values.insert(result, value);
+        }
+    }
+    (name, sig)
+}
+
+/// Replace the parts of an entity with instances of the child entities.
+fn replace_parts(unit: &mut UnitBuilder, parts: &[Part], children: Vec<(UnitName, Signature)>) {
+    let term = unit.terminator(unit.entry());
+    let mut copied = HashSet::new();
+    for part in parts {
+        for &inst in part.insts.iter().rev() {
+            copied.extend(
+                operands(unit, inst)
+                    .flat_map(|arg| unit.get_value_inst(arg))
+                    .filter(|&def| is_copied(unit, def)),
+            );
+            unit.delete_inst(inst);
+        }
+    }
+    unit.insert_before(term);
+    for (part, (name, sig)) in parts.iter().zip(children) {
+        let ext = unit.add_extern(name, sig);
+        unit.ins()
+            .inst(ext, part.inputs.clone(), part.outputs.clone());
+    }
+    for inst in copied {
+        unit.prune_if_unused(inst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::{parse_module, parse_time, write_module_string},
+        sim::{self, Stimulus, Trace},
+        trace::compare,
+        value::IntValue,
+    };
+
+    #[test]
+    fn split_along_signals() {
+        let input = "
+            entity @top (i8$ %a, i8$ %b, i1$ %clk) -> (i8$ %x, i8$ %y, i8$ %z) {
+                %zero = const i8 0
+                %t = const time 0s 1e
+                %s = sig i8 %zero
+                %ap = prb i8$ %a
+                %bp = prb i8$ %b
+                %sum = add i8 %ap, %bp
+                %prod = umul i8 %sum, %ap
+                drv i8$ %s, %prod, %t
+                %sp = prb i8$ %s
+                %diff = sub i8 %sp, %bp
+                drv i8$ %x, %diff, %t
+                %clkp = prb i1$ %clk
+                reg i8$ %y, [%sp, rise %clkp]
+                %one = const i8 1
+                %inc = add i8 %ap, %one
+                drv i8$ %z, %inc, %t
+            }
+        ";
+        let mut module = parse_module(input).unwrap();
+        let mut ctx = PassContext::default();
+        ctx.partition_limits.max_unit_insts = 6;
+        assert!(EntityPartitioning::run_on_module(&ctx, &mut module));
+        module.verify();
+        assert_eq!(module.entities().count(), 3);
+        let asm = write_module_string(&module);
+        let unit = |name| asm.split("\n\n").find(|u| u.contains(name)).unwrap();
+        let top = unit("entity @top");
+        assert!(top.contains("%s = sig i8 %zero"), "{}", top);
+        assert!(
+            top.contains("inst %top.part0 (i8$ %a, i8$ %b, i1$ %clk) -> (i8$ %s, i8$ %x, i8$ %y)"),
+            "{}",
+            asm
+        );
+        assert!(
+            top.contains("inst %top.part1 (i8$ %a) -> (i8$ %z)"),
+            "{}",
+            asm
+        );
+        assert!(!top.contains("add") && !top.contains("prb"), "{}", top);
+        let part1 = unit("entity %top.part1");
+        assert!(part1.contains("%ap = prb i8$ %a"), "{}", part1);
+        assert!(part1.contains("%inc = add i8 %ap, %one"), "{}", part1);
+
+        // The partitioned design behaves exactly like the original one.
+        let mut stimuli = Trace::default();
+        for (i, &(a, b)) in [(1, 2), (3, 4), (5, 6)].iter().enumerate() {
+            let changes = [
+                ("a", 8, a, 0),
+                ("b", 8, b, 0),
+                ("clk", 1, 1, 1),
+                ("clk", 1, 0, 2),
+            ];
+            for &(signal, width, value, t) in &changes {
+                stimuli.changes.push(Stimulus {
+                    signal: signal.to_string(),
+                    time: parse_time(format!("{}ns", 3 * i + t)).unwrap(),
+                    value: IntValue::from_usize(width, value).into(),
+                });
+            }
+        }
+        let expected = sim::run(&parse_module(input).unwrap(), stimuli.clone().into()).unwrap();
+        let actual = sim::run(&module, stimuli.into()).unwrap();
+        assert_eq!(compare(&actual, &expected), vec![]);
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Reassociation
+
+use crate::{
+    ir::{prelude::*, InstData},
+    opt::prelude::*,
+    value::IntValue,
+};
+use std::collections::HashMap;
+
+/// Reassociation
+///
+/// This pass normalizes expression trees of associative and commutative
+/// operations, namely `add`, `and`, `or`, `xor`, `umul`, and `smul`, such that
+/// equivalent expressions become syntactically identical and can be merged by
+/// GCSE. A tree consists of instructions with the same opcode, each of which
+/// is only used by the next one. Its leaves are sorted by rank, with the
+/// constants combined into one and moved to the right, and the tree is rebuilt
+/// as a chain `(((a op b) op c) op K)`. The rank of a value is the position of
+/// its definition in the unit, with arguments first. The operands of `eq` and
+/// `neq` are ordered by rank in the same way.
+///
+/// Running constant folding afterwards removes constants which have become
+/// neutral elements, such as `add %a, 0`.
+pub struct Reassociation;
+
+impl Pass for Reassociation {
+    fn run_on_cfg(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("Reassoc [{}]", unit.name());
+
+        // Rank the values by the position of their definition.
+        let mut ranks = HashMap::new();
+        for arg in unit.args() {
+            ranks.insert(arg, ranks.len());
+        }
+        for inst in unit.all_insts() {
+            if let Some(value) = unit.get_inst_result(inst) {
+                ranks.insert(value, ranks.len());
+            }
+        }
+
+        let insts: Vec<Inst> = unit.all_insts().collect();
+        let mut modified = false;
+        for inst in insts {
+            // Instructions may have been pruned as part of an earlier tree.
+            if !unit.is_inst_inserted(inst) || !unit.inst_annotations(inst).is_optimizable() {
+                continue;
+            }
+            let opcode = unit[inst].opcode();
+            if !unit.inst_type(inst).is_int() {
+                continue;
+            }
+            if opcode.is_associative() {
+                if !is_interior(unit, inst, opcode) {
+                    modified |= reassociate(unit, inst, &ranks);
+                }
+            } else if opcode.is_commutative() && unit[inst].args().len() == 2 {
+                let args = [unit[inst].args()[0], unit[inst].args()[1]];
+                if rank(unit, &ranks, args[1]) < rank(unit, &ranks, args[0]) {
+                    debug!("Swapping operands of {}", inst.dump(unit));
+                    let data = InstData::Binary {
+                        opcode,
+                        args: [args[1], args[0]],
+                    };
+                    unit.replace_inst_data(inst, data);
+                    modified = true;
+                }
+            }
+        }
+        modified
+    }
+}
+
+/// Determine the sort key of a value. Constants come last.
+fn rank(unit: &Unit, ranks: &HashMap<Value, usize>, value: Value) -> (bool, usize) {
+    let rank = ranks.get(&value).cloned().unwrap_or(usize::MAX);
+    (unit.get_const_int(value).is_some(), rank)
+}
+
+/// Check whether an instruction is part of the tree of its only user.
+fn is_interior(unit: &Unit, inst: Inst, opcode: Opcode) -> bool {
+    let value = unit.inst_result(inst);
+    let mut uses = unit.uses(value).iter();
+    let user = match (uses.next(), uses.next()) {
+        (Some(&user), None) => user,
+        _ => return false,
+    };
+    unit[user].opcode() == opcode
+        && unit.inst_block(user) == unit.inst_block(inst)
+        && unit[user]
+            .args()
+            .iter()
+            .filter(|&&arg| arg == value)
+            .count()
+            == 1
+        && unit.inst_annotations(inst).is_optimizable()
+        && unit.inst_annotations(user).is_optimizable()
+}
+
+/// Collect the leaves of the tree below a value, from left to right.
+///
+/// Returns whether the tree is a chain which leans to the left.
+fn collect_leaves(
+    unit: &Unit,
+    root: Inst,
+    opcode: Opcode,
+    value: Value,
+    leaves: &mut Vec<Value>,
+) -> bool {
+    let inst = match unit.get_value_inst(value) {
+        Some(inst)
+            if inst == root
+                || (unit[inst].opcode() == opcode && is_interior(unit, inst, opcode)) =>
+        {
+            inst
+        }
+        _ => {
+            leaves.push(value);
+            return true;
+        }
+    };
+    let args = [unit[inst].args()[0], unit[inst].args()[1]];
+    let left = collect_leaves(unit, root, opcode, args[0], leaves);
+    let num_leaves = leaves.len();
+    let right = collect_leaves(unit, root, opcode, args[1], leaves);
+    left && right && leaves.len() == num_leaves + 1
+}
+
+/// Rebuild the tree rooted at an instruction in normal form.
+fn reassociate(unit: &mut UnitBuilder, root: Inst, ranks: &HashMap<Value, usize>) -> bool {
+    let opcode = unit[root].opcode();
+    let ty = unit.inst_type(root);
+    let root_value = unit.inst_result(root);
+    let mut leaves = vec![];
+    let is_chain = collect_leaves(unit, root, opcode, root_value, &mut leaves);
+
+    // Sort the leaves and combine the constants.
+    let mut sorted = leaves.clone();
+    sorted.sort_by_key(|&value| rank(unit, ranks, value));
+    let num_vars = sorted
+        .iter()
+        .take_while(|&&value| unit.get_const_int(value).is_none())
+        .count();
+    let konst: Option<IntValue> = sorted[num_vars..]
+        .iter()
+        .map(|&value| unit.get_const_int(value).unwrap().clone())
+        .fold(None, |acc, k| match acc {
+            Some(acc) => IntValue::try_binary_op(opcode, &acc, &k),
+            None => Some(k),
+        });
+    if is_chain && sorted == leaves && sorted.len() - num_vars <= 1 {
+        return false;
+    }
+    debug!("Reassociating {}", root.dump(unit));
+
+    // Build the chain right before the root, and turn the root into its last
+    // link.
+    unit.insert_before(root);
+    sorted.truncate(num_vars);
+    if let Some(konst) = konst {
+        sorted.push(unit.ins().const_int(konst));
+    }
+    let old_args: Vec<Inst> = unit[root]
+        .args()
+        .iter()
+        .flat_map(|&arg| unit.get_value_inst(arg))
+        .collect();
+    if sorted.len() == 1 {
+        unit.replace_use(root_value, sorted[0]);
+        unit.prune_if_unused(root);
+        return true;
+    }
+    let last = sorted.pop().unwrap();
+    let mut acc = sorted[0];
+    for &leaf in &sorted[1..] {
+        let data = InstData::Binary {
+            opcode,
+            args: [acc, leaf],
+        };
+        let inst = unit.ins().build(data, ty.clone());
+        unit.transfer_origins(root, inst);
+        acc = unit.inst_result(inst);
+    }
+    let data = InstData::Binary {
+        opcode,
+        args: [acc, last],
+    };
+    unit.replace_inst_data(root, data);
+    for inst in old_args {
+        if unit.is_inst_inserted(inst) {
+            unit.prune_if_unused(inst);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::{parse_module, write_module_string},
+        pass::{ConstFolding, GlobalCommonSubexprElim},
+    };
+
+    #[test]
+    fn equivalent_expressions_merge() {
+        let mut module = parse_module(
+            "
+            func @foo (i8 %a, i8 %b, i8 %c) i1 {
+            entry:
+                %k1 = const i8 1
+                %k2 = const i8 2
+                %x0 = add i8 %k2, %c
+                %x1 = add i8 %b, %x0
+                %x = add i8 %x1, %a
+                %y0 = add i8 %a, %k1
+                %y1 = add i8 %c, %y0
+                %y2 = add i8 %y1, %k1
+                %y = add i8 %y2, %b
+                %z = eq i8 %y, %x
+                ret i1 %z
+            }
+            ",
+        )
+        .unwrap();
+        let ctx = PassContext::default();
+        assert!(Reassociation::run_on_module(&ctx, &mut module));
+        GlobalCommonSubexprElim::run_on_module(&ctx, &mut module);
+        ConstFolding::run_on_module(&ctx, &mut module);
+        module.verify();
+        let asm = write_module_string(&module);
+        assert_eq!(asm.matches("add i8").count(), 3, "{}", asm);
+        assert!(asm.contains("%z = eq i8 %x, %x"), "{}", asm);
+    }
+}
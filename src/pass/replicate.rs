@@ -0,0 +1,193 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Instance Replication
+
+use crate::{
+    ir::{prelude::*, ExtUnit},
+    opt::prelude::*,
+    ty::Type,
+};
+use std::collections::{BTreeSet, HashMap};
+
+/// Instance Replication
+///
+/// This pass folds identical instances of an entity into one replicated `inst`
+/// instruction. A group of instances is folded if they instantiate the same
+/// entity, and each of their arguments is either the same value in all of
+/// them, or element `k` of the same signal of an array, as in
+/// `extf i8$, [4 x i8]$ %a, k`, where each instance uses a different `k` and
+/// the array has one element per instance. This keeps large replicated
+/// structures compact until they are flattened, which expands replicated
+/// instances again.
+pub struct InstanceReplication;
+
+impl Pass for InstanceReplication {
+    fn run_on_cfg(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        if !unit.is_entity() {
+            return false;
+        }
+
+        // Instances of the same entity may refer to distinct but identical
+        // external units.
+        let exts: Vec<_> = unit
+            .extern_units()
+            .map(|(ext, data)| (ext, data.clone()))
+            .collect();
+        let canonical_ext = |ext: ExtUnit| {
+            let data = exts.iter().find(|(e, _)| *e == ext).unwrap();
+            exts.iter()
+                .find(|(_, first)| first.name == data.1.name && first.sig == data.1.sig)
+                .unwrap()
+                .0
+        };
+
+        // Group the instances by their entity and arguments, with each array
+        // element replaced by its array.
+        let mut groups: Vec<Vec<Inst>> = vec![];
+        let mut keys = HashMap::<(ExtUnit, Vec<Value>), usize>::new();
+        for inst in unit.all_insts() {
+            if unit[inst].opcode() != Opcode::Inst || unit.inst_replication(inst).is_some() {
+                continue;
+            }
+            let args = unit[inst]
+                .args()
+                .iter()
+                .map(|&arg| array_element(unit, arg).map(|(a, _)| a).unwrap_or(arg))
+                .collect();
+            let key = (canonical_ext(unit[inst].get_ext_unit().unwrap()), args);
+            let index = *keys.entry(key).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+            groups[index].push(inst);
+        }
+
+        let mut modified = false;
+        for group in groups {
+            if group.len() > 1 {
+                modified |= fold_group(unit, group);
+            }
+        }
+        modified
+    }
+}
+
+/// Fold a group of instances with the same entity and arguments into one
+/// replicated instance, if each of them connects to a different element of
+/// the arrays among the arguments.
+fn fold_group(unit: &mut UnitBuilder, group: Vec<Inst>) -> bool {
+    let count = group.len();
+    let num_args = unit[group[0]].args().len();
+
+    // Determine the element each instance connects to, and the array or
+    // shared value connected to each port.
+    let mut copies = vec![None; count];
+    let mut args = vec![];
+    for index in 0..num_args {
+        let values: Vec<_> = group.iter().map(|&inst| unit[inst].args()[index]).collect();
+        if values.iter().all(|&v| v == values[0]) {
+            args.push(values[0]);
+            continue;
+        }
+        for (copy, &value) in copies.iter_mut().zip(&values) {
+            let (array, element) = array_element(unit, value).unwrap();
+            let len = unit.value_type(array).unwrap_signal().unwrap_array().0;
+            if len != count || copy.map(|c| c != element).unwrap_or(false) {
+                return false;
+            }
+            *copy = Some(element);
+        }
+        args.push(array_element(unit, values[0]).unwrap().0);
+    }
+    let mut order: Vec<_> = group.iter().cloned().zip(copies).collect();
+    if order.iter().any(|(_, copy)| copy.is_some()) {
+        order.sort_by_key(|(_, copy)| *copy);
+        if order
+            .iter()
+            .enumerate()
+            .any(|(index, (_, copy))| *copy != Some(index))
+        {
+            return false;
+        }
+    }
+
+    // Replace the instances with a replicated one at the position of the
+    // last, such that all arguments are available.
+    debug!("Replicating {} {} times", group[0].dump(unit), count);
+    let first = group[0];
+    let last = *group.last().unwrap();
+    let ext = unit[first].get_ext_unit().unwrap();
+    let num_inputs = unit[first].input_args().len();
+    let outputs = args.split_off(num_inputs);
+    let annotations = unit.inst_annotations(first);
+    unit.insert_before(last);
+    let inst = unit.ins().inst(ext, args, outputs);
+    unit.set_inst_replication(inst, count);
+    unit.annotate_inst(inst, annotations);
+    let mut elements = BTreeSet::new();
+    for old in group {
+        let origins = unit.origins(old);
+        unit.add_origins(inst, origins);
+        elements.extend(
+            unit[old]
+                .args()
+                .iter()
+                .flat_map(|&arg| unit.get_value_inst(arg)),
+        );
+        unit.delete_inst(old);
+    }
+    for element in elements {
+        unit.prune_if_unused(element);
+    }
+    true
+}
+
+/// Replace a replicated `inst` instruction with one `inst` per copy.
+///
+/// Each copy connects to its element of the arguments that differ in type
+/// from their port. Returns the new instructions in the order of the copies.
+pub(crate) fn expand_replication(unit: &mut UnitBuilder, inst: Inst) -> Vec<Inst> {
+    let count = unit.inst_replication(inst).unwrap();
+    let ext = unit[inst].get_ext_unit().unwrap();
+    let sig = unit[ext].sig.clone();
+    let ports: Vec<Type> = sig.args().map(|arg| sig.arg_type(arg)).collect();
+    let num_inputs = unit[inst].input_args().len();
+    let args = unit[inst].args().to_vec();
+    let origins = unit.origins(inst);
+    let annotations = unit.inst_annotations(inst);
+
+    unit.insert_before(inst);
+    let mut copies = vec![];
+    for copy in 0..count {
+        let mut inputs = vec![];
+        for (&arg, port) in args.iter().zip(&ports) {
+            inputs.push(if unit.value_type(arg) == *port {
+                arg
+            } else {
+                unit.ins().ext_field(arg, copy)
+            });
+        }
+        let outputs = inputs.split_off(num_inputs);
+        let new = unit.ins().inst(ext, inputs, outputs);
+        unit.add_origins(new, origins.iter().cloned());
+        unit.annotate_inst(new, annotations);
+        copies.push(new);
+    }
+    unit.delete_inst(inst);
+    copies
+}
+
+/// Determine the signal of an array and the index of the element a value
+/// projects out of it, if any.
+fn array_element(unit: &Unit, value: Value) -> Option<(Value, usize)> {
+    let inst = unit.get_value_inst(value)?;
+    if unit[inst].opcode() != Opcode::ExtField {
+        return None;
+    }
+    let array = unit[inst].args()[0];
+    let ty = unit.value_type(array);
+    if !ty.is_signal() || !ty.unwrap_signal().is_array() {
+        return None;
+    }
+    Some((array, unit[inst].imms()[0]))
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Code Sinking
+
+use crate::{analysis::PredecessorTable, ir::prelude::*, opt::prelude::*};
+use std::collections::HashSet;
+
+/// Code Sinking
+///
+/// This pass moves pure instructions with a single user into the user's
+/// block, right before the user. This shortens the live ranges of values, in
+/// particular across temporal regions, and is the counterpart to the hoisting
+/// done by GCSE and ECM. Instructions are not sunk into loops they are not
+/// already part of, into phi nodes, or if they are annotated with
+/// `dont_optimize`. If the `PassContext` carries a profile, instructions are
+/// also not sunk into blocks which have executed more often than their own.
+pub struct CodeSinking;
+
+impl Pass for CodeSinking {
+    fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        info!("Sink [{}]", unit.name());
+        let mut modified = false;
+        let pt = unit.predtbl();
+
+        // Visit the instructions in reverse order, such that chains of
+        // instructions sink together.
+        let insts: Vec<_> = unit.all_insts().collect();
+        for inst in insts.into_iter().rev() {
+            let opcode = unit[inst].opcode();
+            if !opcode.is_pure()
+                || opcode.is_const()
+                || !unit.has_result(inst)
+                || !unit.inst_annotations(inst).is_optimizable()
+            {
+                continue;
+            }
+
+            // Only consider instructions with a single user in another block.
+            let value = unit.inst_result(inst);
+            let uses = unit.uses(value);
+            if uses.len() != 1 {
+                continue;
+            }
+            let user = uses.iter().cloned().next().unwrap();
+            if unit[user].opcode() == Opcode::Phi {
+                continue;
+            }
+            let src_bb = unit.inst_block(inst).unwrap();
+            let dst_bb = unit.inst_block(user).unwrap();
+            if src_bb == dst_bb {
+                continue;
+            }
+
+            // Do not sink into a loop which does not contain the instruction,
+            // since it would be executed more often.
+            if is_in_loop_without(&pt, dst_bb, src_bb) {
+                trace!("Skipping {} (would sink into loop)", inst.dump(unit));
+                continue;
+            }
+            let counts = (
+                ctx.profile.block_count(unit, src_bb),
+                ctx.profile.block_count(unit, dst_bb),
+            );
+            if let (Some(src), Some(dst)) = counts {
+                if dst > src {
+                    trace!(
+                        "Skipping {} (would sink into hotter block)",
+                        inst.dump(unit)
+                    );
+                    continue;
+                }
+            }
+
+            debug!("Sink {} into {}", inst.dump(unit), dst_bb.dump(unit));
+            unit.remove_inst(inst);
+            unit.insert_inst_before(inst, user);
+            modified = true;
+        }
+        modified
+    }
+}
+
+/// Check whether `bb` lies on a cycle which does not pass through `without`.
+fn is_in_loop_without(pt: &PredecessorTable, bb: Block, without: Block) -> bool {
+    let mut seen = HashSet::new();
+    let mut todo: Vec<_> = pt.succ(bb).collect();
+    while let Some(next) = todo.pop() {
+        if next == bb {
+            return true;
+        }
+        if next != without && seen.insert(next) {
+            todo.extend(pt.succ(next));
+        }
+    }
+    false
+}
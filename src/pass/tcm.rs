@@ -5,28 +5,38 @@
 use crate::{
     analysis::{DominatorTree, TemporalRegion, TemporalRegionGraph},
     ir::prelude::*,
-    ir::InstData,
     opt::prelude::*,
     value::IntValue,
 };
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 /// Temporal Code Motion
 ///
 /// This pass rearranges temporal instructions. It does the following:
 ///
-/// - Merge multiple identical waits into one (in a new block).
+/// - Merge multiple equivalent waits into one (in a new block). Waits are
+///   equivalent if they resume at the same block after the same time, and
+///   observe the same signals up to aliases, duplicates, and projections of
+///   signals which are observed as a whole. Waits with a time never merge
+///   with waits without one.
 /// - Move `prb` instructions up to the top of the time region.
 /// - Move `drv` instructions down to the end of the time region, where
 ///   possible. Failure to do so hints at conditionally-driven signals, such as
 ///   storage elements.
 ///
+/// Instructions annotated with `dont_optimize` are neither moved nor merged.
+/// Units with more instructions than `PassLimits::tcm_max_insts` are skipped,
+/// and drives are no longer moved once `PassLimits::tcm_max_dom_steps` is
+/// exhausted.
 pub struct TemporalCodeMotion;
 
 impl Pass for TemporalCodeMotion {
     fn run_on_cfg(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
         info!("TCM [{}]", unit.name());
+        if ctx.exceeds_inst_limit(unit, "tcm", ctx.pass_limits.tcm_max_insts) {
+            return false;
+        }
         let mut modified = false;
 
         // Build the temporal region graph.
@@ -47,6 +57,7 @@ impl Pass for TemporalCodeMotion {
                 for inst in unit.insts(bb) {
                     if unit[inst].opcode() == Opcode::Prb
                         && unit.get_value_inst(unit[inst].args()[0]).is_none()
+                        && unit.inst_annotations(inst).is_optimizable()
                     {
                         // Check if the new prb location would dominate its old
                         // location temporally.
@@ -88,11 +99,16 @@ impl Pass for TemporalCodeMotion {
                 trace!("Skipping {} for wait merge (single wait inst)", tr.id);
                 continue;
             }
-            let mut merge = HashMap::<&InstData, Vec<Inst>>::new();
+            let mut merge = HashMap::<WaitKey, Vec<Inst>>::new();
             for inst in tr.tail_insts() {
-                merge.entry(&unit[inst]).or_default().push(inst);
+                if unit.inst_annotations(inst).is_optimizable() {
+                    merge.entry(wait_key(unit, inst)).or_default().push(inst);
+                }
             }
-            let merge: Vec<_> = merge.into_iter().map(|(_, is)| is).collect();
+            // Sort the groups such that blocks are created in the same order
+            // on every run.
+            let mut merge: Vec<_> = merge.into_iter().map(|(_, is)| is).collect();
+            merge.sort();
             for insts in merge {
                 if insts.len() <= 1 {
                     trace!("Skipping {} (no equivalents)", insts[0].dump(&unit));
@@ -138,6 +154,56 @@ impl Pass for TemporalCodeMotion {
     }
 }
 
+/// The opcode, target block, time, and canonical observed signals of a wait,
+/// which are equal for equivalent waits.
+type WaitKey = (Opcode, Block, Option<Value>, Vec<Value>);
+
+/// Determine the key under which a wait is merged with equivalent ones.
+fn wait_key(unit: &Unit, inst: Inst) -> WaitKey {
+    let data = &unit[inst];
+    let (time, signals) = match data.opcode() {
+        Opcode::WaitTime => (Some(data.args()[0]), &data.args()[1..]),
+        _ => (None, data.args()),
+    };
+    (
+        data.opcode(),
+        data.blocks()[0],
+        time,
+        observed_signals(unit, signals),
+    )
+}
+
+/// Canonicalize the signals observed by a wait.
+///
+/// Aliases are replaced with the signal they alias, and duplicates removed.
+/// Projections of a signal which is observed as a whole are dropped, since
+/// every change of the projection is a change of the whole signal.
+fn observed_signals(unit: &Unit, signals: &[Value]) -> Vec<Value> {
+    let unalias = |mut value: Value| {
+        while let Some(inst) = unit.get_value_inst(value) {
+            if unit[inst].opcode() != Opcode::Alias {
+                break;
+            }
+            value = unit[inst].args()[0];
+        }
+        value
+    };
+    let signals: BTreeSet<Value> = signals.iter().map(|&v| unalias(v)).collect();
+    let covered = |mut value: Value| {
+        while let Some(inst) = unit.get_value_inst(value) {
+            if !matches!(unit[inst].opcode(), Opcode::ExtField | Opcode::ExtSlice) {
+                break;
+            }
+            value = unalias(unit[inst].args()[0]);
+            if signals.contains(&value) {
+                return true;
+            }
+        }
+        false
+    };
+    signals.iter().cloned().filter(|&v| !covered(v)).collect()
+}
+
 /// Introduce auxiliary exit blocks if multiple edges leave a temporal region
 /// into the same target block in a different region. This is needed to ensure
 /// that drives have a dedicated block to be pushed down into ahead of the next
@@ -158,11 +224,11 @@ fn add_aux_blocks(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
 
         // Gather a list of predecessor instructions per region, which branch
         // into this block.
-        let mut insts_by_region = HashMap::<TemporalRegion, Vec<Inst>>::new();
+        let mut insts_by_region = BTreeMap::<TemporalRegion, Vec<Inst>>::new();
         for pred in pt.pred(bb) {
             let pred_tr = trg[pred];
-            if pred_tr != tr {
-                let inst = unit.terminator(pred);
+            let inst = unit.terminator(pred);
+            if pred_tr != tr && unit.inst_annotations(inst).is_optimizable() {
                 insts_by_region.entry(pred_tr).or_default().push(inst);
             }
         }
@@ -201,7 +267,7 @@ fn push_drives(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
     // aliases (e.g. extf/exts) of another. As we encounter drives, keep track
     // of their sequential dependency.
     let mut aliases = HashMap::<Value, Value>::new();
-    let mut drv_seq = HashMap::<Value, Vec<Inst>>::new();
+    let mut drv_seq = BTreeMap::<Value, Vec<Inst>>::new();
     for &bb in dt.blocks_post_order().iter().rev() {
         trace!("Checking {} for aliases", bb.dump(&unit));
         for inst in unit.insts(bb) {
@@ -238,12 +304,21 @@ fn push_drives(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
     let trg = unit.trg();
 
     // Try to migrate drive instructions into the tails of their respective
-    // temporal regions.
-    for (&signal, drives) in &drv_seq {
+    // temporal regions. Walking up the dominator tree is bounded by a budget
+    // of steps for the entire unit.
+    let mut steps = ctx.pass_limits.tcm_max_dom_steps;
+    'signals: for (&signal, drives) in &drv_seq {
         trace!("Moving drives on signal {}", signal.dump(&unit));
         // TODO: Don't directly move drives, but track if move is possible and what
         // the conditions are. Then do post-processing down below.
         for &drive in drives.iter().rev() {
+            // Drives that must not be optimized cannot be moved, and neither
+            // can the drives before them.
+            if !unit.inst_annotations(drive).is_optimizable() {
+                trace!("  Skipping {} (dont_optimize)", drive.dump(&unit));
+                break;
+            }
+
             // Skip drives that are already in the right place.
             let drive_bb = unit.inst_block(drive).unwrap();
             if trg.is_tail(drive_bb) {
@@ -257,8 +332,19 @@ fn push_drives(ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
 
             // Perform the move.
             // trace!("  Checking {}", drive.dump(&unit));
-            let moved = push_drive(ctx, drive, unit, &dt, &trg);
+            let moved = push_drive(ctx, drive, unit, &dt, &trg, &mut steps);
             modified |= moved;
+            if steps == 0 {
+                ctx.skip_unit(
+                    unit.name(),
+                    "tcm",
+                    format!(
+                        "dominator walk exceeds limit of {} steps",
+                        ctx.pass_limits.tcm_max_dom_steps
+                    ),
+                );
+                break 'signals;
+            }
 
             // If the move was not possible, abort all other drives since we
             // cannot move over them.
@@ -282,6 +368,7 @@ fn push_drive(
     unit: &mut UnitBuilder,
     dt: &DominatorTree,
     trg: &TemporalRegionGraph,
+    steps: &mut usize,
 ) -> bool {
     let src_bb = unit.inst_block(drive).unwrap();
     let tr = trg[src_bb];
@@ -293,6 +380,17 @@ fn push_drive(
     for dst_bb in trg[tr].tail_blocks() {
         // trace!("    Will have to move to {}", dst_bb.dump(&unit));
 
+        // Tail blocks that branch back into the region may execute multiple
+        // times, which would repeat the drive.
+        if trg[tr].tail_continues(dst_bb) && !unit[unit.terminator(dst_bb)].opcode().is_temporal() {
+            trace!(
+                "  Skipping {} ({} continues within the region)",
+                drive.dump(unit),
+                dst_bb.dump(unit)
+            );
+            return false;
+        }
+
         // First check if all arguments of the drive instruction dominate the
         // destination block. If not, the move is not possible.
         for &arg in unit[drive].args() {
@@ -315,6 +413,11 @@ fn push_drive(
         let mut dst_finger = dst_bb;
         let mut conds = Vec::<(Value, bool)>::new();
         while src_finger != dst_finger {
+            if *steps == 0 {
+                trace!("  Skipping {} (dominator walk limit)", drive.dump(unit));
+                return false;
+            }
+            *steps -= 1;
             let i1 = dt.block_order(src_finger);
             let i2 = dt.block_order(dst_finger);
             if i1 < i2 {
@@ -377,7 +480,7 @@ fn push_drive(
         // together in reverse order to reflect the CFG, which allows for most
         // of these conditions to be shared.
         unit.prepend_to(dst_bb);
-        let mut cond = unit.ins().const_int(IntValue::all_ones(1));
+        let mut cond = unit.pooled_const_int(IntValue::all_ones(1));
         for (value, polarity) in conds.into_iter().rev() {
             let value = match polarity {
                 true => value,
@@ -397,7 +500,8 @@ fn push_drive(
         let signal = args[0];
         let value = args[1];
         let delay = args[2];
-        unit.ins().drv_cond(signal, value, delay, cond);
+        let new = unit.ins().drv_cond(signal, value, delay, cond);
+        unit.transfer_origins(drive, new);
     }
 
     // Remove the old drive instruction.
@@ -410,7 +514,7 @@ fn coalesce_drives(_ctx: &PassContext, block: Block, unit: &mut UnitBuilder) ->
     let mut modified = false;
 
     // Group the drives by delay.
-    let mut delay_groups = HashMap::<Value, Vec<Inst>>::new();
+    let mut delay_groups = BTreeMap::<Value, Vec<Inst>>::new();
     for inst in unit.insts(block) {
         if let Opcode::Drv | Opcode::DrvCond = unit[inst].opcode() {
             let delay = unit[inst].args()[2];
@@ -419,13 +523,20 @@ fn coalesce_drives(_ctx: &PassContext, block: Block, unit: &mut UnitBuilder) ->
     }
 
     // Coalesce each delay group individually. Split the instructions into runs
-    // of drives to the exact same signal.
+    // of drives to the exact same signal, or the same slice of a signal.
+    // Drives that must not be optimized form a run of their own.
     for (delay, drives) in delay_groups {
         let runs: Vec<_> = drives
             .into_iter()
-            .group_by(|&inst| unit[inst].args()[0])
+            .group_by(|&inst| {
+                let fixed = Some(inst).filter(|&i| !unit.inst_annotations(i).is_optimizable());
+                (slice_key(unit, unit[inst].args()[0]), fixed)
+            })
             .into_iter()
-            .map(|(target, drives)| (target, drives.collect::<Vec<_>>()))
+            .map(|(_, drives)| {
+                let drives = drives.collect::<Vec<_>>();
+                (unit[drives[0]].args()[0], drives)
+            })
             .collect();
         for (target, drives) in runs {
             if drives.len() <= 1 {
@@ -443,6 +554,7 @@ fn coalesce_drives(_ctx: &PassContext, block: Block, unit: &mut UnitBuilder) ->
             unit.insert_before(first);
             let mut cond = drive_cond(unit, first);
             let mut value = unit[first].args()[1];
+            let mut origins = unit.origins(first);
             unit.delete_inst(first);
 
             // Accumulate subsequent drive conditions and values, and remove.
@@ -457,11 +569,13 @@ fn coalesce_drives(_ctx: &PassContext, block: Block, unit: &mut UnitBuilder) ->
                     let vs = unit.ins().array(vec![value, v]);
                     value = unit.ins().mux(vs, c);
                 }
+                origins.extend(unit.origins(drive));
                 unit.delete_inst(drive);
             }
 
             // Build the final drive.
-            unit.ins().drv_cond(target, value, delay, cond);
+            let new = unit.ins().drv_cond(target, value, delay, cond);
+            unit.add_origins(new, origins);
             modified = true;
         }
     }
@@ -474,10 +588,89 @@ fn coalesce_drives(_ctx: &PassContext, block: Block, unit: &mut UnitBuilder) ->
     modified
 }
 
+/// Identify the part of a signal that is accessed through a chain of `exts`
+/// and `extf` instructions.
+///
+/// Returns the accessed signal, together with the offsets and lengths of the
+/// slices and fields along the way. Nested slices are combined, such that
+/// different chains which access the same part of a signal yield the same
+/// result.
+fn slice_key(unit: &UnitBuilder, mut signal: Value) -> (Value, Vec<(Opcode, usize, usize)>) {
+    let mut path = vec![];
+    while let Some(inst) = unit.get_value_inst(signal) {
+        let opcode = unit[inst].opcode();
+        if opcode != Opcode::ExtSlice && opcode != Opcode::ExtField {
+            break;
+        }
+        let imms = unit[inst].imms();
+        match path.last_mut() {
+            Some((Opcode::ExtSlice, offset, _)) if opcode == Opcode::ExtSlice => {
+                *offset += imms[0];
+            }
+            _ => path.push((opcode, imms[0], imms[1])),
+        }
+        signal = unit[inst].args()[0];
+    }
+    path.reverse();
+    (signal, path)
+}
+
 fn drive_cond(unit: &mut UnitBuilder, inst: Inst) -> Value {
     if unit[inst].opcode() == Opcode::DrvCond {
         unit[inst].args()[3]
     } else {
-        unit.ins().const_int(IntValue::all_ones(1))
+        unit.pooled_const_int(IntValue::all_ones(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembly::parse_module,
+        opt::{Pass, PassContext},
+        pass::{GlobalCommonSubexprElim, TemporalCodeMotion},
+    };
+
+    #[test]
+    fn deterministic() {
+        let input = "
+            proc @foo (i1$ %c, i1$ %d, i8$ %a, i8$ %b) -> (i8$ %q, i8$ %r, i8$ %s) {
+            entry:
+                %cp = prb i1$ %c
+                %t0 = const time 0s 1e
+                %t1 = const time 1ns
+                br %cp, %no, %yes
+            yes:
+                %ap = prb i8$ %a
+                drv i8$ %q, %ap, %t0
+                drv i8$ %r, %ap, %t1
+                %dp = prb i1$ %d
+                br %dp, %skip, %more
+            more:
+                %bp = prb i8$ %b
+                drv i8$ %s, %bp, %t0
+                drv i8$ %q, %bp, %t1
+                wait %entry, %c, %d, %a, %b
+            skip:
+                wait %entry, %c, %d, %a, %b
+            no:
+                %bq = prb i8$ %b
+                %aq = prb i8$ %a
+                drv i8$ %r, %bq, %t0
+                drv i8$ %s, %aq, %t1
+                wait %entry, %c, %d, %a, %b
+            }
+        ";
+        let run = || {
+            let mut module = parse_module(input).unwrap();
+            let ctx = PassContext::default();
+            TemporalCodeMotion::run_on_module(&ctx, &mut module);
+            GlobalCommonSubexprElim::run_on_module(&ctx, &mut module);
+            module.dump().to_string()
+        };
+        let first = run();
+        for _ in 0..8 {
+            assert_eq!(run(), first);
+        }
     }
 }
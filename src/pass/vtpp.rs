@@ -17,6 +17,25 @@ impl Pass for VarToPhiPromotion {
         info!("VTPP [{}]", unit.name());
         let mut modified = false;
 
+        // Conditional stores keep the variable's current value if their
+        // condition is 0. Turn them into regular stores of a `mux` between the
+        // current and the new value, such that they can be promoted as well.
+        let cond_stores: Vec<_> = unit
+            .all_insts()
+            .filter(|&inst| unit[inst].opcode() == Opcode::StCond)
+            .collect();
+        for inst in cond_stores {
+            let args = unit[inst].args().to_vec();
+            unit.insert_before(inst);
+            let current = unit.ins().ld(args[0]);
+            let choices = unit.ins().array(vec![current, args[1]]);
+            let value = unit.ins().mux(choices, args[2]);
+            let new = unit.ins().st(args[0], value);
+            unit.transfer_origins(inst, new);
+            unit.delete_inst(inst);
+            modified |= true;
+        }
+
         // Build the predecessor table and dominator tree.
         let pt = unit.predtbl();
 
@@ -0,0 +1,140 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Wait Refinement
+
+use crate::{
+    ir::{prelude::*, InstData},
+    opt::prelude::*,
+};
+use std::collections::HashSet;
+
+/// Wait Refinement
+///
+/// This pass narrows the signals observed by a `wait` instruction to the ones
+/// that are probed after the process resumes, and replaces a `wait` that is
+/// left without any signals or time with a `halt`. Waking up on a signal which
+/// is never probed re-executes the process with the same inputs, which
+/// produces the same drives and thus cannot be observed.
+///
+/// This only holds if the process carries no state from one activation to
+/// the next. The pass therefore only considers processes with a single
+/// `wait`, whose target block has no phi nodes, and where the code executed
+/// after resuming neither calls other units nor stores to memory allocated
+/// before the `wait`.
+pub struct WaitRefinement;
+
+impl Pass for WaitRefinement {
+    fn run_on_cfg(_ctx: &PassContext, unit: &mut UnitBuilder) -> bool {
+        if !unit.is_process() {
+            return false;
+        }
+        info!("WaitRefine [{}]", unit.name());
+
+        // Find the only wait in the process.
+        let mut waits = unit
+            .all_insts()
+            .filter(|&inst| matches!(unit[inst].opcode(), Opcode::Wait | Opcode::WaitTime));
+        let wait = match (waits.next(), waits.next()) {
+            (Some(wait), None) => wait,
+            _ => return false,
+        };
+        if !unit.inst_annotations(wait).is_optimizable() {
+            return false;
+        }
+
+        // Determine the blocks executed after resuming, and make sure they do
+        // not depend on the previous activation.
+        let target = unit[wait].blocks()[0];
+        if unit.insts(target).any(|inst| unit[inst].opcode().is_phi()) {
+            return false;
+        }
+        let pt = unit.predtbl();
+        let mut region = HashSet::new();
+        let mut todo = vec![target];
+        while let Some(bb) = todo.pop() {
+            if region.insert(bb) {
+                todo.extend(pt.succ(bb));
+            }
+        }
+        let region_insts: Vec<_> = region.iter().flat_map(|&bb| unit.insts(bb)).collect();
+        let mut probed = HashSet::new();
+        for &inst in &region_insts {
+            match unit[inst].opcode() {
+                Opcode::Call => return false,
+                Opcode::St | Opcode::StCond => {
+                    let defined_in_region = unit
+                        .get_value_inst(unit[inst].args()[0])
+                        .and_then(|def| unit.inst_block(def))
+                        .map(|bb| region.contains(&bb))
+                        .unwrap_or(false);
+                    if !defined_in_region {
+                        return false;
+                    }
+                }
+                Opcode::Prb => signal_roots(unit, unit[inst].args()[0], &mut probed),
+                _ => (),
+            }
+        }
+
+        // Keep the observed signals which share a root with a probed signal.
+        let (time, signals) = match unit[wait].opcode() {
+            Opcode::WaitTime => (Some(unit[wait].args()[0]), &unit[wait].args()[1..]),
+            _ => (None, unit[wait].args()),
+        };
+        let kept: Vec<Value> = signals
+            .iter()
+            .cloned()
+            .filter(|&signal| {
+                let mut roots = HashSet::new();
+                signal_roots(unit, signal, &mut roots);
+                !roots.is_disjoint(&probed)
+            })
+            .collect();
+        if kept.len() == signals.len() {
+            return false;
+        }
+
+        if kept.is_empty() && time.is_none() {
+            debug!("Replacing {} with halt", wait.dump(unit));
+            unit.insert_before(wait);
+            unit.ins().halt();
+            unit.delete_inst(wait);
+        } else {
+            debug!("Narrowing {}", wait.dump(unit));
+            let args = time.into_iter().chain(kept).collect();
+            let data = InstData::Wait {
+                opcode: unit[wait].opcode(),
+                bbs: [target],
+                args,
+            };
+            unit.replace_inst_data(wait, data);
+        }
+        true
+    }
+}
+
+/// Collect the signals a signal is derived from.
+///
+/// Fields and slices of a signal are traced back to the signal they are
+/// extracted from. Signals computed in any other way are their own root, in
+/// addition to the roots of their signal operands.
+fn signal_roots(unit: &Unit, signal: Value, roots: &mut HashSet<Value>) {
+    let inst = match unit.get_value_inst(signal) {
+        Some(inst) => inst,
+        None => {
+            roots.insert(signal);
+            return;
+        }
+    };
+    match unit[inst].opcode() {
+        Opcode::ExtField | Opcode::ExtSlice => (),
+        _ => {
+            roots.insert(signal);
+        }
+    }
+    for &arg in unit[inst].args() {
+        if unit.value_type(arg).is_signal() {
+            signal_roots(unit, arg, roots);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Width Legalization
+
+use crate::{
+    ir::{prelude::*, InstData},
+    opt::prelude::*,
+    ty::int_ty,
+    value::IntValue,
+};
+
+/// Width Legalization
+///
+/// This pass finds binary arithmetic, bitwise, and comparison instructions
+/// whose integer operands have different widths, and either reports them or
+/// extends the narrower operands according to the `ExtensionPolicy`.
+pub struct WidthLegalization;
+
+/// How operands of mismatching width are extended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtensionPolicy {
+    /// Report the mismatch as an error and leave the instruction unchanged.
+    #[default]
+    Report,
+    /// Zero-extend the narrower operands.
+    ZeroExtend,
+    /// Sign-extend the narrower operands.
+    SignExtend,
+    /// Sign-extend the operands of signed instructions, such as `smul` or
+    /// `slt`, and zero-extend all others.
+    MatchOpcode,
+}
+
+impl ExtensionPolicy {
+    /// Check whether the operands of an instruction are sign-extended.
+    pub fn is_signed(self, opcode: Opcode) -> bool {
+        self == ExtensionPolicy::SignExtend
+            || (self == ExtensionPolicy::MatchOpcode && SIGNED_OPCODES.contains(&opcode))
+    }
+}
+
+impl std::str::FromStr for ExtensionPolicy {
+    type Err = String;
+
+    /// Parse a policy as given on the command line, e.g. `zero`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "report" => Ok(ExtensionPolicy::Report),
+            "zero" => Ok(ExtensionPolicy::ZeroExtend),
+            "sign" => Ok(ExtensionPolicy::SignExtend),
+            "opcode" => Ok(ExtensionPolicy::MatchOpcode),
+            _ => Err(format!("unknown extension policy `{}`", s)),
+        }
+    }
+}
+
+/// The instructions that interpret their operands as signed integers.
+static SIGNED_OPCODES: &[Opcode] = &[
+    Opcode::Smul,
+    Opcode::Sdiv,
+    Opcode::Smod,
+    Opcode::Srem,
+    Opcode::Smin,
+    Opcode::Smax,
+    Opcode::Slt,
+    Opcode::Sgt,
+    Opcode::Sle,
+    Opcode::Sge,
+];
+
+impl Pass for WidthLegalization {
+    fn run_on_inst(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+        let opcode = unit[inst].opcode();
+        let compare = match opcode {
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Smul
+            | Opcode::Sdiv
+            | Opcode::Smod
+            | Opcode::Srem
+            | Opcode::Umul
+            | Opcode::Udiv
+            | Opcode::Umod
+            | Opcode::Urem
+            | Opcode::Umin
+            | Opcode::Umax
+            | Opcode::Smin
+            | Opcode::Smax
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor => false,
+            Opcode::Eq
+            | Opcode::Neq
+            | Opcode::Slt
+            | Opcode::Sgt
+            | Opcode::Sle
+            | Opcode::Sge
+            | Opcode::Ult
+            | Opcode::Ugt
+            | Opcode::Ule
+            | Opcode::Uge => true,
+            _ => return false,
+        };
+
+        // Only consider instructions whose operands are all integers, but of
+        // different widths.
+        let args = unit[inst].args().to_vec();
+        if args.len() != 2 || !args.iter().all(|&arg| unit.value_type(arg).is_int()) {
+            return false;
+        }
+        let widths: Vec<_> = args
+            .iter()
+            .map(|&arg| unit.value_type(arg).unwrap_int())
+            .collect();
+        if widths[0] == widths[1] {
+            return false;
+        }
+        if ctx.extension_policy == ExtensionPolicy::Report {
+            error!(
+                "Operand widths of `{}` do not match (i{} and i{})",
+                inst.dump(unit),
+                widths[0],
+                widths[1]
+            );
+            return false;
+        }
+
+        // Extend all operands to the widest one, and for arithmetic the result
+        // type as well.
+        let result_ty = unit.inst_type(inst);
+        let mut width = widths[0].max(widths[1]);
+        if !compare {
+            width = width.max(result_ty.unwrap_int());
+        }
+        let signed = ctx.extension_policy.is_signed(opcode);
+        unit.insert_before(inst);
+        let args: Vec<_> = args
+            .into_iter()
+            .map(|arg| extend(unit, arg, width, signed))
+            .collect();
+        let data = InstData::Binary {
+            opcode,
+            args: [args[0], args[1]],
+        };
+        let ty = if compare {
+            result_ty.clone()
+        } else {
+            int_ty(width)
+        };
+        let new = unit.ins().build(data, ty);
+        let mut new_value = unit.inst_result(new);
+        if !compare && result_ty.unwrap_int() < width {
+            new_value = unit.ins().ext_slice(new_value, 0, result_ty.unwrap_int());
+        }
+        unit.transfer_origins(inst, new);
+        let old_value = unit.inst_result(inst);
+        unit.replace_use(old_value, new_value);
+        unit.delete_inst(inst);
+        true
+    }
+}
+
+/// Extend an integer value to a given width.
+fn extend(unit: &mut UnitBuilder, value: Value, width: usize, signed: bool) -> Value {
+    let narrow = unit.value_type(value).unwrap_int();
+    if narrow == width {
+        return value;
+    }
+    let base = if signed && narrow > 0 {
+        let sign = unit.ins().ext_slice(value, narrow - 1, 1);
+        let zeros = unit.ins().const_int(IntValue::zero(width));
+        let ones = unit.ins().const_int(IntValue::all_ones(width));
+        let choices = unit.ins().array(vec![zeros, ones]);
+        unit.ins().mux(choices, sign)
+    } else {
+        unit.ins().const_int(IntValue::zero(width))
+    };
+    unit.ins().ins_slice(base, value, 0, narrow)
+}
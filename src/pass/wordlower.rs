@@ -0,0 +1,422 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Word Lowering
+
+use crate::{
+    ir::{prelude::*, InstData},
+    opt::prelude::*,
+    ty::{int_ty, TypeKind},
+};
+
+/// Word Lowering
+///
+/// This pass decomposes integer operations wider than a word into a network
+/// of operations on individual words, for backends which cannot handle
+/// arbitrary widths. The word width is given by `PassContext::word_width`; a
+/// width of 1 yields a bit-level network. The operands are split into words
+/// with `exts`, and the words of the result are reassembled with `inss`, such
+/// that wide values only remain in the wiring.
+///
+/// - `and`, `or`, `xor`, and `not` operate on each word separately.
+/// - `add`, `sub`, and `neg` propagate a carry from the lowest word upwards.
+/// - `eq` and `neq` combine the comparisons of the words.
+/// - Relational comparisons compare the words from the top down, with the top
+///   word compared as signed for the signed comparisons.
+/// - `umul` and `smul` add up the products of words row by row, with the upper
+///   half of each product computed by `umulh` and carried into the next word.
+///
+/// Division, remainder, shifts, and other operations are left untouched.
+pub struct WordLowering;
+
+impl Pass for WordLowering {
+    fn run_on_inst(ctx: &PassContext, inst: Inst, unit: &mut UnitBuilder) -> bool {
+        let word = ctx.word_width.unwrap_or(64).max(1);
+        let data = &unit[inst];
+        let opcode = data.opcode();
+        if !matches!(data, InstData::Unary { .. } | InstData::Binary { .. })
+            || !unit.inst_annotations(inst).is_optimizable()
+        {
+            return false;
+        }
+        let args = data.args().to_vec();
+        let width = match *unit.value_type(args[0]) {
+            TypeKind::IntType(w) if w > word => w,
+            _ => return false,
+        };
+        unit.insert_before(inst);
+        let mut lower = Lowering { unit, word };
+        let result = match opcode {
+            Opcode::Not => {
+                let a = lower.split(args[0], width, word);
+                let words = a.into_iter().map(|a| lower.unit.ins().not(a)).collect();
+                lower.join(words, width)
+            }
+            Opcode::And | Opcode::Or | Opcode::Xor => {
+                let a = lower.split(args[0], width, word);
+                let b = lower.split(args[1], width, word);
+                let words = a
+                    .into_iter()
+                    .zip(b)
+                    .map(|(a, b)| lower.binary(opcode, a, b))
+                    .collect();
+                lower.join(words, width)
+            }
+            Opcode::Add => {
+                let a = lower.split(args[0], width, word);
+                let b = lower.split(args[1], width, word);
+                let words = lower.add(&a, &b);
+                lower.join(words, width)
+            }
+            Opcode::Sub => {
+                let a = lower.split(args[0], width, word);
+                let b = lower.split(args[1], width, word);
+                let words = lower.sub(&a, &b);
+                lower.join(words, width)
+            }
+            Opcode::Neg => {
+                let b = lower.split(args[0], width, word);
+                let a = lower.zeros(&b);
+                let words = lower.sub(&a, &b);
+                lower.join(words, width)
+            }
+            Opcode::Umul | Opcode::Smul => {
+                let a = lower.split(args[0], width, word);
+                let b = lower.split(args[1], width, word);
+                let words = lower.mul(&a, &b);
+                lower.join(words, width)
+            }
+            Opcode::Eq | Opcode::Neq => {
+                let a = lower.split(args[0], width, word);
+                let b = lower.split(args[1], width, word);
+                let words: Vec<_> = a
+                    .into_iter()
+                    .zip(b)
+                    .map(|(a, b)| lower.binary(opcode, a, b))
+                    .collect();
+                let combine = match opcode {
+                    Opcode::Eq => Opcode::And,
+                    _ => Opcode::Or,
+                };
+                words[1..]
+                    .iter()
+                    .fold(words[0], |acc, &x| lower.binary(combine, acc, x))
+            }
+            Opcode::Ult | Opcode::Slt | Opcode::Ugt | Opcode::Sgt => {
+                let (a, b) = match opcode {
+                    Opcode::Ult | Opcode::Slt => (args[0], args[1]),
+                    _ => (args[1], args[0]),
+                };
+                let signed = matches!(opcode, Opcode::Slt | Opcode::Sgt);
+                let a = lower.split(a, width, word);
+                let b = lower.split(b, width, word);
+                lower.less_than(&a, &b, signed)
+            }
+            Opcode::Ule | Opcode::Sle | Opcode::Uge | Opcode::Sge => {
+                let (a, b) = match opcode {
+                    Opcode::Ule | Opcode::Sle => (args[1], args[0]),
+                    _ => (args[0], args[1]),
+                };
+                let signed = matches!(opcode, Opcode::Sle | Opcode::Sge);
+                let a = lower.split(a, width, word);
+                let b = lower.split(b, width, word);
+                let lt = lower.less_than(&a, &b, signed);
+                lower.unit.ins().not(lt)
+            }
+            _ => return false,
+        };
+        debug!("Lowering {} to words", inst.dump(unit));
+        let value = unit.inst_result(inst);
+        if let Some(name) = unit.get_name(value).map(String::from) {
+            unit.set_name(result, name);
+        }
+        if let Some(new) = unit.get_value_inst(result) {
+            unit.transfer_origins(inst, new);
+        }
+        unit.replace_use(value, result);
+        unit.prune_if_unused(inst);
+        true
+    }
+}
+
+/// A helper to build the word-level network in place of an instruction.
+struct Lowering<'a, 'b> {
+    unit: &'a mut UnitBuilder<'b>,
+    word: usize,
+}
+
+impl Lowering<'_, '_> {
+    /// Split a value into pieces of the given width, starting at the lowest
+    /// bit. The last piece may be narrower.
+    fn split(&mut self, value: Value, width: usize, piece: usize) -> Vec<Value> {
+        (0..width)
+            .step_by(piece)
+            .map(|lo| {
+                let len = piece.min(width - lo);
+                self.unit.ins().ext_slice(value, lo, len)
+            })
+            .collect()
+    }
+
+    /// Concatenate pieces into a value of the given width.
+    fn join(&mut self, pieces: Vec<Value>, width: usize) -> Value {
+        let mut acc = self.unit.ins().const_zero(&int_ty(width));
+        let mut lo = 0;
+        for piece in pieces {
+            let len = self.width(piece);
+            acc = self.unit.ins().ins_slice(acc, piece, lo, len);
+            lo += len;
+        }
+        acc
+    }
+
+    /// Zero-extend a value to a wider integer.
+    fn extend(&mut self, value: Value, width: usize) -> Value {
+        let from = self.width(value);
+        if from == width {
+            return value;
+        }
+        let zero = self.unit.ins().const_zero(&int_ty(width));
+        self.unit.ins().ins_slice(zero, value, 0, from)
+    }
+
+    /// Create zeros of the same widths as the given pieces.
+    fn zeros(&mut self, pieces: &[Value]) -> Vec<Value> {
+        pieces
+            .iter()
+            .map(|&piece| {
+                let ty = self.unit.value_type(piece);
+                self.unit.ins().const_zero(&ty)
+            })
+            .collect()
+    }
+
+    fn width(&self, value: Value) -> usize {
+        self.unit.value_type(value).unwrap_int()
+    }
+
+    fn binary(&mut self, opcode: Opcode, a: Value, b: Value) -> Value {
+        let mut ins = self.unit.ins();
+        match opcode {
+            Opcode::And => ins.and(a, b),
+            Opcode::Or => ins.or(a, b),
+            Opcode::Xor => ins.xor(a, b),
+            Opcode::Add => ins.add(a, b),
+            Opcode::Sub => ins.sub(a, b),
+            Opcode::Umul => ins.umul(a, b),
+            Opcode::Umulh => ins.umulh(a, b),
+            Opcode::Eq => ins.eq(a, b),
+            Opcode::Neq => ins.neq(a, b),
+            Opcode::Ult => ins.ult(a, b),
+            Opcode::Slt => ins.slt(a, b),
+            _ => unreachable!("{} in word lowering", opcode),
+        }
+    }
+
+    /// Add two numbers of pieces with equal widths, dropping the final carry.
+    fn add(&mut self, a: &[Value], b: &[Value]) -> Vec<Value> {
+        let n = a.len();
+        let mut carry = None;
+        let mut sums = vec![];
+        for (i, (&a, &b)) in a.iter().zip(b).enumerate() {
+            let t = self.binary(Opcode::Add, a, b);
+            let last = i + 1 == n;
+            let (sum, c) = match carry {
+                None => (t, (!last).then(|| self.binary(Opcode::Ult, t, a))),
+                Some(c) => {
+                    let width = self.width(t);
+                    let c = self.extend(c, width);
+                    let s = self.binary(Opcode::Add, t, c);
+                    let c = if last {
+                        None
+                    } else {
+                        let c1 = self.binary(Opcode::Ult, t, a);
+                        let c2 = self.binary(Opcode::Ult, s, t);
+                        Some(self.binary(Opcode::Or, c1, c2))
+                    };
+                    (s, c)
+                }
+            };
+            sums.push(sum);
+            carry = c;
+        }
+        sums
+    }
+
+    /// Subtract two numbers of pieces with equal widths, dropping the final
+    /// borrow.
+    fn sub(&mut self, a: &[Value], b: &[Value]) -> Vec<Value> {
+        let n = a.len();
+        let mut borrow = None;
+        let mut diffs = vec![];
+        for (i, (&a, &b)) in a.iter().zip(b).enumerate() {
+            let t = self.binary(Opcode::Sub, a, b);
+            let last = i + 1 == n;
+            let (diff, c) = match borrow {
+                None => (t, (!last).then(|| self.binary(Opcode::Ult, a, b))),
+                Some(c) => {
+                    let width = self.width(t);
+                    let c = self.extend(c, width);
+                    let d = self.binary(Opcode::Sub, t, c);
+                    let c = if last {
+                        None
+                    } else {
+                        let c1 = self.binary(Opcode::Ult, a, b);
+                        let c2 = self.binary(Opcode::Ult, t, c);
+                        Some(self.binary(Opcode::Or, c1, c2))
+                    };
+                    (d, c)
+                }
+            };
+            diffs.push(diff);
+            borrow = c;
+        }
+        diffs
+    }
+
+    /// Multiply two numbers of pieces with equal widths, keeping as many
+    /// pieces as the operands have.
+    ///
+    /// The pieces are zero-extended to full words. Each word of `b` yields a
+    /// row of the words of `a` scaled by it, which is added to the result
+    /// shifted by the position of the word. Within a row, the upper half of
+    /// each product is carried into the next word, which avoids any
+    /// intermediate wider than a word.
+    fn mul(&mut self, a: &[Value], b: &[Value]) -> Vec<Value> {
+        let word = self.word;
+        let n = a.len();
+        let top_width = self.width(a[n - 1]);
+        let a: Vec<_> = a.iter().map(|&a| self.extend(a, word)).collect();
+        let mut result: Option<Vec<Value>> = None;
+        for (i, &bi) in b.iter().enumerate() {
+            let bi = self.extend(bi, word);
+            let mut row = vec![];
+            let mut carry: Option<Value> = None;
+            for (j, &aj) in a[..n - i].iter().enumerate() {
+                let lo = self.binary(Opcode::Umul, aj, bi);
+                let hi = if i + j + 1 < n {
+                    Some(self.binary(Opcode::Umulh, aj, bi))
+                } else {
+                    None
+                };
+                let (digit, next) = match (carry, hi) {
+                    (None, hi) => (lo, hi),
+                    (Some(c), None) => (self.binary(Opcode::Add, lo, c), None),
+                    // The upper half of a product is at most two less than
+                    // the largest word, so it absorbs the carry of the sum.
+                    (Some(c), Some(hi)) => {
+                        let sum = self.binary(Opcode::Add, lo, c);
+                        let overflow = self.binary(Opcode::Ult, sum, lo);
+                        let overflow = self.extend(overflow, word);
+                        (sum, Some(self.binary(Opcode::Add, hi, overflow)))
+                    }
+                };
+                row.push(digit);
+                carry = next;
+            }
+            result = Some(match result {
+                None => row,
+                Some(mut acc) => {
+                    let sum = self.add(&acc[i..], &row);
+                    acc.truncate(i);
+                    acc.extend(sum);
+                    acc
+                }
+            });
+        }
+        let mut result = result.unwrap();
+        if top_width < word {
+            result[n - 1] = self.unit.ins().ext_slice(result[n - 1], 0, top_width);
+        }
+        result
+    }
+
+    /// Compare two numbers of pieces with equal widths.
+    fn less_than(&mut self, a: &[Value], b: &[Value], signed: bool) -> Value {
+        let n = a.len();
+        let mut lt = None;
+        for (i, (&a, &b)) in a.iter().zip(b).enumerate() {
+            let op = if signed && i + 1 == n {
+                Opcode::Slt
+            } else {
+                Opcode::Ult
+            };
+            let word_lt = self.binary(op, a, b);
+            lt = Some(match lt {
+                None => word_lt,
+                Some(lt) => {
+                    let eq = self.binary(Opcode::Eq, a, b);
+                    let below = self.binary(Opcode::And, eq, lt);
+                    self.binary(Opcode::Or, word_lt, below)
+                }
+            });
+        }
+        lt.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembly::parse_module, pass::ConstFolding, value::IntValue};
+    use num::BigUint;
+
+    /// Lower an operation on constants to words, fold the result, and compare
+    /// it to the folded original.
+    fn check(op: &str, ty: &str, a: &BigUint, b: &BigUint, word: usize) {
+        let width = 40;
+        let module = |word: Option<usize>| {
+            let mut module = parse_module(format!(
+                "
+                func @f () {} {{
+                entry:
+                    %a = const i{} {}
+                    %b = const i{} {}
+                    %r = {} i{} %a, %b
+                    ret {} %r
+                }}
+                ",
+                ty, width, a, width, b, op, width, ty
+            ))
+            .unwrap();
+            let mut ctx = PassContext::default();
+            if let Some(word) = word {
+                ctx.word_width = Some(word);
+                assert!(WordLowering::run_on_module(&ctx, &mut module));
+            }
+            ConstFolding::run_on_module(&ctx, &mut module);
+            module.verify();
+            let unit = module.units().next().unwrap();
+            let ret = unit.terminator(unit.blocks().last().unwrap());
+            unit.get_const_int(unit[ret].args()[0])
+                .cloned()
+                .unwrap_or_else(|| panic!("{} not folded", op))
+        };
+        let expected: IntValue = module(None);
+        assert_eq!(
+            module(Some(word)),
+            expected,
+            "{} with {}-bit words",
+            op,
+            word
+        );
+    }
+
+    #[test]
+    fn arithmetic_matches_wide_operations() {
+        let a = BigUint::parse_bytes(b"d3a9c2f17b", 16).unwrap();
+        let b = BigUint::parse_bytes(b"5f0e17c3a2", 16).unwrap();
+        for &word in &[1, 17] {
+            for op in &["add", "sub", "and", "xor", "umul", "smul"] {
+                check(op, "i40", &a, &b, word);
+                check(op, "i40", &b, &a, word);
+            }
+            for op in &[
+                "eq", "neq", "ult", "ule", "ugt", "uge", "slt", "sle", "sgt", "sge",
+            ] {
+                check(op, "i1", &a, &b, word);
+                check(op, "i1", &b, &a, word);
+                check(op, "i1", &a, &a, word);
+            }
+        }
+    }
+}
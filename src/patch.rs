@@ -0,0 +1,452 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Binary patches of LLHD units.
+//!
+//! This module implements edit scripts that describe changes to the units of a
+//! module, such as inserting, removing, or replacing instructions, and renaming
+//! units. A `Patch` can be serialized into a compact binary format, shipped
+//! elsewhere, and applied to a copy of the same base module. This allows IR
+//! deltas to be exchanged without transferring entire modules.
+//!
+//! Every unit patch records a fingerprint of the unit it was created against,
+//! and the binary format carries a version number. Both are checked before a
+//! patch is applied.
+//!
+//! ```
+//! use llhd::{assembly::parse_module, int_ty, ir::{InstData, Opcode}, patch::*, value::IntValue};
+//!
+//! let input = "func @foo () i32 {\n%entry:\n    %a = const i32 1\n    ret i32 %a\n}\n";
+//! let mut module = parse_module(input).unwrap();
+//! let unit = module.units().next().unwrap();
+//! let a = unit.first_inst(unit.entry()).unwrap();
+//!
+//! let mut up = UnitPatch::new(unit);
+//! up.replace_inst(a, InstData::ConstInt { opcode: Opcode::ConstInt, imm: IntValue::from_usize(32, 2) });
+//! let mut patch = Patch::new();
+//! patch.add(up);
+//!
+//! let bytes = patch.to_bytes();
+//! Patch::from_bytes(&bytes).unwrap().apply(&mut module).unwrap();
+//! ```
+
+use crate::{
+    ir::{prelude::*, InstData, LinkedUnit, Module},
+    table::TableKey,
+    ty::Type,
+};
+use std::collections::HashMap;
+
+/// The magic number at the beginning of every binary patch.
+pub const PATCH_MAGIC: [u8; 4] = *b"LLHP";
+
+/// The version of the binary patch format.
+///
+/// Patches with a newer version than this are rejected.
+pub const PATCH_VERSION: u16 = 1;
+
+/// The first index used for instructions and values created by a patch.
+///
+/// Indices at or above this refer to the instructions and values inserted by
+/// the patch itself, and are mapped to the actual ones upon application.
+const PLACEHOLDER_BASE: usize = 1 << 31;
+
+/// A set of changes to the units of a module.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Patch {
+    units: Vec<UnitPatch>,
+}
+
+impl Patch {
+    /// Create an empty patch.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the changes to a unit to the patch.
+    pub fn add(&mut self, unit: UnitPatch) {
+        self.units.push(unit);
+    }
+
+    /// Return an iterator over the unit patches.
+    pub fn units(&self) -> impl Iterator<Item = &UnitPatch> {
+        self.units.iter()
+    }
+
+    /// Serialize the patch into its binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PATCH_MAGIC.to_vec();
+        bytes.extend_from_slice(&PATCH_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("patch serialization failed");
+        bytes
+    }
+
+    /// Deserialize a patch from its binary format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 6 || bytes[0..4] != PATCH_MAGIC {
+            return Err("not an LLHD patch".to_string());
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > PATCH_VERSION {
+            return Err(format!(
+                "unsupported patch version {}; expected at most {}",
+                version, PATCH_VERSION
+            ));
+        }
+        bincode::deserialize(&bytes[6..]).map_err(|e| format!("malformed patch: {}", e))
+    }
+
+    /// Apply the patch to a module.
+    ///
+    /// The fingerprints of all patched units are checked before any change is
+    /// made. If applying the edits to a unit fails, that unit is left
+    /// unchanged, but units patched before it keep their modifications.
+    pub fn apply(&self, module: &mut Module) -> Result<(), String> {
+        for unit in &self.units {
+            unit.check(module)?;
+        }
+        for unit in &self.units {
+            unit.apply(module)?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of changes to a single unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnitPatch {
+    /// The name of the patched unit.
+    unit: UnitName,
+    /// The fingerprint of the unit the patch was created against.
+    base: u64,
+    /// The edits to perform, in order.
+    edits: Vec<Edit>,
+    /// The number of instructions inserted by the patch.
+    num_insts: usize,
+    /// The number of values created by the patch.
+    num_values: usize,
+}
+
+/// A single change to a unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Edit {
+    /// Insert a new instruction.
+    InsertInst {
+        /// The placeholder for the instruction.
+        inst: Inst,
+        /// The placeholder for the instruction's result, if it has one.
+        result: Option<Value>,
+        /// Where to insert the instruction.
+        pos: EditPos,
+        /// The instruction data.
+        data: InstData,
+        /// The result type.
+        #[serde(deserialize_with = "crate::ty::deserialize")]
+        ty: Type,
+    },
+    /// Remove an instruction whose result is no longer used.
+    RemoveInst(Inst),
+    /// Replace the data of an instruction.
+    ReplaceInst(Inst, InstData),
+    /// Rename the unit.
+    Rename(UnitName),
+}
+
+/// The position of an inserted instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditPos {
+    /// At the end of a block.
+    Append(Block),
+    /// At the beginning of a block.
+    Prepend(Block),
+    /// After an instruction.
+    After(Inst),
+    /// Before an instruction.
+    Before(Inst),
+}
+
+impl UnitPatch {
+    /// Create an empty patch against a unit.
+    pub fn new(base: Unit) -> Self {
+        Self {
+            unit: base.name().clone(),
+            base: fingerprint(base),
+            edits: vec![],
+            num_insts: 0,
+            num_values: 0,
+        }
+    }
+
+    /// The name of the patched unit.
+    pub fn unit(&self) -> &UnitName {
+        &self.unit
+    }
+
+    /// The edits in the patch.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Insert a new instruction.
+    ///
+    /// Returns placeholders for the instruction and its result, which may be
+    /// used in subsequent edits. The result is `None` if `ty` is void.
+    pub fn insert_inst(&mut self, pos: EditPos, data: InstData, ty: Type) -> (Inst, Option<Value>) {
+        let inst = Inst::new(PLACEHOLDER_BASE + self.num_insts);
+        self.num_insts += 1;
+        let result = if ty.is_void() {
+            None
+        } else {
+            self.num_values += 1;
+            Some(Value::new(PLACEHOLDER_BASE + self.num_values - 1))
+        };
+        self.edits.push(Edit::InsertInst {
+            inst,
+            result,
+            pos,
+            data,
+            ty,
+        });
+        (inst, result)
+    }
+
+    /// Remove an instruction.
+    pub fn remove_inst(&mut self, inst: Inst) {
+        self.edits.push(Edit::RemoveInst(inst));
+    }
+
+    /// Replace the data of an instruction.
+    pub fn replace_inst(&mut self, inst: Inst, data: InstData) {
+        self.edits.push(Edit::ReplaceInst(inst, data));
+    }
+
+    /// Rename the unit.
+    pub fn rename(&mut self, name: UnitName) {
+        self.edits.push(Edit::Rename(name));
+    }
+
+    /// Check whether the patch can be applied to a module.
+    fn check(&self, module: &Module) -> Result<UnitId, String> {
+        let id = match module.lookup(&self.unit) {
+            Some(LinkedUnit::Def(id)) => id,
+            _ => return Err(format!("unit {} not found", self.unit)),
+        };
+        if fingerprint(module.unit(id)) != self.base {
+            return Err(format!(
+                "unit {} differs from the base of the patch",
+                self.unit
+            ));
+        }
+        Ok(id)
+    }
+
+    /// Apply the patch to a module.
+    ///
+    /// If applying the edits fails, the unit is left unchanged.
+    pub fn apply(&self, module: &mut Module) -> Result<(), String> {
+        let id = self.check(module)?;
+        let mut rename = None;
+
+        // Apply the edits to a copy of the unit, which only replaces the
+        // original once all edits have succeeded.
+        let mut data = module[id].clone();
+        let mut unit = UnitBuilder::new(id, &mut data);
+        let mut mapper = Mapper::default();
+        for edit in &self.edits {
+            let result = match edit {
+                Edit::Rename(name) => {
+                    rename = Some(name.clone());
+                    Ok(())
+                }
+                _ => mapper.apply(&mut unit, edit),
+            };
+            if let Err(e) = result {
+                return Err(format!("cannot patch {}: {}", self.unit, e));
+            }
+        }
+        module[id] = data;
+        if let Some(name) = rename {
+            if module.lookup(&name).is_some() {
+                return Err(format!(
+                    "cannot rename {}; {} already exists",
+                    self.unit, name
+                ));
+            }
+            module.rename_unit(id, name);
+        }
+        Ok(())
+    }
+}
+
+/// Tracks the instructions and values created for placeholders.
+#[derive(Default)]
+struct Mapper {
+    insts: HashMap<Inst, Inst>,
+    values: HashMap<Value, Value>,
+}
+
+impl Mapper {
+    /// Apply an edit to a unit.
+    fn apply(&mut self, unit: &mut UnitBuilder, edit: &Edit) -> Result<(), String> {
+        match *edit {
+            Edit::InsertInst {
+                inst,
+                result,
+                pos,
+                ref data,
+                ref ty,
+            } => {
+                match pos {
+                    EditPos::Append(bb) => unit.append_to(self.block(unit, bb)?),
+                    EditPos::Prepend(bb) => unit.prepend_to(self.block(unit, bb)?),
+                    EditPos::After(other) => unit.insert_after(self.inst(unit, other)?),
+                    EditPos::Before(other) => unit.insert_before(self.inst(unit, other)?),
+                }
+                let data = self.data(unit, data)?;
+                let new = unit.build_inst(data, ty.clone());
+                self.insts.insert(inst, new);
+                if let Some(result) = result {
+                    self.values.insert(result, unit.inst_result(new));
+                }
+            }
+            Edit::RemoveInst(inst) => {
+                let inst = self.inst(unit, inst)?;
+                if unit.has_result(inst) && unit.has_uses(unit.inst_result(inst)) {
+                    return Err(format!("{} is still in use", inst.dump(unit)));
+                }
+                unit.delete_inst(inst);
+            }
+            Edit::ReplaceInst(inst, ref data) => {
+                let inst = self.inst(unit, inst)?;
+                let data = self.data(unit, data)?;
+                unit.replace_inst_data(inst, data);
+            }
+            Edit::Rename(..) => (),
+        }
+        Ok(())
+    }
+
+    /// Resolve an instruction.
+    fn inst(&self, unit: &UnitBuilder, inst: Inst) -> Result<Inst, String> {
+        let resolved = match self.insts.get(&inst) {
+            Some(&resolved) => resolved,
+            None if inst.index() < PLACEHOLDER_BASE => inst,
+            None => return Err(format!("instruction {} does not exist", inst)),
+        };
+        if !unit.is_inst_inserted(resolved) {
+            return Err(format!("instruction {} does not exist", inst));
+        }
+        Ok(resolved)
+    }
+
+    /// Resolve a block.
+    fn block(&self, unit: &UnitBuilder, bb: Block) -> Result<Block, String> {
+        if !unit.is_block_inserted(bb) {
+            return Err(format!("block {} does not exist", bb));
+        }
+        Ok(bb)
+    }
+
+    /// Resolve the placeholders and check the blocks in instruction data.
+    fn data(&self, unit: &UnitBuilder, data: &InstData) -> Result<InstData, String> {
+        let mut data = data.clone();
+        #[allow(deprecated)]
+        for arg in data.args_mut() {
+            if arg.index() >= PLACEHOLDER_BASE {
+                *arg = *self
+                    .values
+                    .get(arg)
+                    .ok_or_else(|| format!("value {} does not exist", arg))?;
+            }
+        }
+        for &bb in data.blocks() {
+            self.block(unit, bb)?;
+        }
+        Ok(data)
+    }
+}
+
+/// Compute the fingerprint of a unit.
+///
+/// This is a hash of the unit's textual representation, used to check that a
+/// patch is applied to the same unit it was created against.
+pub fn fingerprint(unit: Unit) -> u64 {
+    // FNV-1a, which unlike the std hashers is stable across builds.
+    unit.to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembly::parse_module, int_ty, ir::Opcode, value::IntValue};
+
+    const INPUT: &str = "func @foo (i32 %x) i32 {\n%entry:\n    %a = const i32 1\n    %b = add i32 %x, %a\n    ret i32 %b\n}\n";
+
+    #[test]
+    fn roundtrip_and_apply() {
+        let mut module = parse_module(INPUT).unwrap();
+        let unit = module.units().next().unwrap();
+        let entry = unit.entry();
+        let insts: Vec<_> = unit.insts(entry).collect();
+        let x = unit.input_arg(0);
+
+        let mut up = UnitPatch::new(unit);
+        let (_, two) = up.insert_inst(
+            EditPos::Prepend(entry),
+            InstData::ConstInt {
+                opcode: Opcode::ConstInt,
+                imm: IntValue::from_usize(32, 2),
+            },
+            int_ty(32),
+        );
+        up.replace_inst(
+            insts[1],
+            InstData::Binary {
+                opcode: Opcode::Umul,
+                args: [x, two.unwrap()],
+            },
+        );
+        up.remove_inst(insts[0]);
+        up.rename(UnitName::global("bar"));
+        let mut patch = Patch::new();
+        patch.add(up);
+
+        let patch = Patch::from_bytes(&patch.to_bytes()).unwrap();
+        patch.apply(&mut module).unwrap();
+        let unit = module.units().next().unwrap();
+        assert_eq!(unit.name(), &UnitName::global("bar"));
+        assert_eq!(
+            unit.insts(entry)
+                .map(|inst| unit[inst].opcode())
+                .collect::<Vec<_>>(),
+            vec![Opcode::ConstInt, Opcode::Umul, Opcode::RetValue]
+        );
+
+        // The base has changed, so the patch no longer applies.
+        assert!(patch.apply(&mut module).is_err());
+    }
+
+    #[test]
+    fn reject_incompatible() {
+        let mut module = parse_module(INPUT).unwrap();
+        let unit = module.units().next().unwrap();
+        let mut up = UnitPatch::new(unit);
+        up.remove_inst(unit.first_inst(unit.entry()).unwrap());
+        let mut patch = Patch::new();
+        patch.add(up);
+
+        // Removing an instruction that is still used fails and leaves the
+        // unit unchanged.
+        let before = module.dump().to_string();
+        assert!(patch.apply(&mut module).is_err());
+        assert_eq!(module.dump().to_string(), before);
+
+        let mut bytes = patch.to_bytes();
+        bytes[4] = PATCH_VERSION as u8 + 1;
+        assert!(Patch::from_bytes(&bytes).is_err());
+        assert!(Patch::from_bytes(b"nope").is_err());
+    }
+}
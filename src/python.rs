@@ -0,0 +1,586 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Python bindings for scripting.
+//!
+//! This module exposes modules, units, blocks, instructions, and values to
+//! Python, together with the netlist builder, the optimization passes, and
+//! the verifier, such that the IR can be analyzed and transformed by scripts.
+//! It is only available with the `python` feature. An extension module that
+//! can be imported as `llhd` is built with:
+//!
+//! ```text
+//! cargo rustc --release --lib --features python,pyo3/extension-module --crate-type cdylib
+//! cp target/release/libllhd.so llhd.so
+//! ```
+//!
+//! The following script prints the opcodes of all instructions:
+//!
+//! ```text
+//! import llhd
+//! module = llhd.Module.load("design.llhd")
+//! module.run_passes(["cf", "dce"])
+//! for unit in module.units:
+//!     print(unit.kind, unit.name)
+//!     for inst in unit.insts:
+//!         print("   ", inst.opcode, [str(arg) for arg in inst.args])
+//! ```
+//!
+//! Units, blocks, instructions, and values keep the module they belong to
+//! alive. Accessing them after they have been removed from the module, for
+//! example by a pass, raises a `ValueError`.
+
+// The `#[pymethods]` expansion converts `PyResult` errors into themselves.
+#![allow(clippy::useless_conversion)]
+
+use crate::{
+    assembly::{parse_module, parse_type, write_module_string},
+    ir::{self, prelude::*},
+    netlist::NetlistBuilder,
+    opt::prelude::*,
+    pass::run_by_name,
+    ty::Type,
+    verifier::Verifier,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// Parse a unit name such as `@foo` or `%foo`. Names without a sigil are
+/// global.
+fn parse_unit_name(name: &str) -> UnitName {
+    if let Some(name) = name.strip_prefix('@') {
+        UnitName::global(name)
+    } else if let Some(name) = name.strip_prefix('%') {
+        UnitName::local(name)
+    } else {
+        UnitName::global(name)
+    }
+}
+
+fn parse_py_type(ty: &str) -> PyResult<Type> {
+    parse_type(ty).map_err(PyValueError::new_err)
+}
+
+/// An LLHD module.
+#[pyclass(name = "Module")]
+pub struct PyLlhdModule {
+    module: Module,
+}
+
+impl PyLlhdModule {
+    /// Get a unit of the module, or fail if it has been removed.
+    fn unit(&self, id: UnitId) -> PyResult<Unit<'_>> {
+        self.module
+            .units()
+            .find(|u| u.id() == id)
+            .ok_or_else(|| PyValueError::new_err("unit has been removed from the module"))
+    }
+}
+
+#[pymethods]
+impl PyLlhdModule {
+    /// Create a new empty module.
+    #[new]
+    fn new() -> Self {
+        Self {
+            module: Module::new(),
+        }
+    }
+
+    /// Parse a module in assembly format.
+    #[staticmethod]
+    fn parse(src: &str) -> PyResult<Self> {
+        let module = parse_module(src).map_err(PyValueError::new_err)?;
+        Ok(Self { module })
+    }
+
+    /// Read a module in assembly format from a file.
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Self> {
+        let src = std::fs::read_to_string(path)?;
+        Self::parse(&src)
+    }
+
+    fn __str__(&self) -> String {
+        write_module_string(&self.module)
+    }
+
+    /// The units defined in the module.
+    #[getter]
+    fn units(slf: &Bound<Self>) -> Vec<PyUnit> {
+        let module = slf.clone().unbind();
+        slf.borrow()
+            .module
+            .units()
+            .map(|u| PyUnit {
+                module: module.clone_ref(slf.py()),
+                id: u.id(),
+            })
+            .collect()
+    }
+
+    /// Find a unit by name, e.g. `@foo`. Returns `None` if the module does not
+    /// define such a unit.
+    fn unit_by_name(slf: &Bound<Self>, name: &str) -> Option<PyUnit> {
+        let id = match slf.borrow().module.lookup(&parse_unit_name(name)) {
+            Some(ir::LinkedUnit::Def(id)) => id,
+            _ => return None,
+        };
+        Some(PyUnit {
+            module: slf.clone().unbind(),
+            id,
+        })
+    }
+
+    /// Remove a unit from the module.
+    fn remove_unit(&mut self, unit: &PyUnit) -> PyResult<()> {
+        self.unit(unit.id)?;
+        self.module.remove_unit(unit.id);
+        Ok(())
+    }
+
+    /// Declare an external unit with the given input and output types, e.g.
+    /// `module.declare("@inv", ["i1$"], ["i1$"])`.
+    fn declare(&mut self, name: &str, inputs: Vec<String>, outputs: Vec<String>) -> PyResult<()> {
+        let mut sig = Signature::new();
+        for ty in inputs {
+            sig.add_input(parse_py_type(&ty)?);
+        }
+        for ty in outputs {
+            sig.add_output(parse_py_type(&ty)?);
+        }
+        self.module.declare(parse_unit_name(name), sig);
+        Ok(())
+    }
+
+    /// Resolve the references between the units of the module.
+    fn link(&mut self) {
+        self.module.link();
+    }
+
+    /// Verify the integrity of the module, raising a `ValueError` describing
+    /// the problems if it is invalid.
+    fn verify(&self) -> PyResult<()> {
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&self.module);
+        verifier
+            .finish()
+            .map_err(|errs| PyValueError::new_err(errs.to_string()))
+    }
+
+    /// Run a pass given by its `llhd-opt` name, e.g. `cf`. Returns whether the
+    /// pass modified the module.
+    fn run_pass(&mut self, name: &str) -> PyResult<bool> {
+        run_by_name(name, &PassContext::default(), &mut self.module)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown pass `{}`", name)))
+    }
+
+    /// Run a sequence of passes. Returns whether any pass modified the module.
+    fn run_passes(&mut self, names: Vec<String>) -> PyResult<bool> {
+        let mut modified = false;
+        for name in names {
+            modified |= self.run_pass(&name)?;
+        }
+        Ok(modified)
+    }
+}
+
+/// A unit in a module.
+#[pyclass(name = "Unit")]
+pub struct PyUnit {
+    module: Py<PyLlhdModule>,
+    id: UnitId,
+}
+
+impl PyUnit {
+    fn with<R>(&self, py: Python, f: impl FnOnce(Unit) -> R) -> PyResult<R> {
+        let module = self.module.borrow(py);
+        Ok(f(module.unit(self.id)?))
+    }
+
+    fn wrap_values(&self, py: Python, values: impl IntoIterator<Item = Value>) -> Vec<PyValue> {
+        values
+            .into_iter()
+            .map(|value| PyValue {
+                module: self.module.clone_ref(py),
+                unit: self.id,
+                value,
+            })
+            .collect()
+    }
+
+    fn wrap_insts(&self, py: Python, insts: impl IntoIterator<Item = Inst>) -> Vec<PyInst> {
+        insts
+            .into_iter()
+            .map(|inst| PyInst {
+                module: self.module.clone_ref(py),
+                unit: self.id,
+                inst,
+            })
+            .collect()
+    }
+}
+
+#[pymethods]
+impl PyUnit {
+    /// The name of the unit, e.g. `@foo`.
+    #[getter]
+    fn name(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| u.name().to_string())
+    }
+
+    /// The kind of the unit, one of `func`, `proc`, or `entity`.
+    #[getter]
+    fn kind(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| u.kind().to_string())
+    }
+
+    /// The input arguments of the unit.
+    #[getter]
+    fn inputs(&self, py: Python) -> PyResult<Vec<PyValue>> {
+        let args: Vec<_> = self.with(py, |u| u.input_args().collect())?;
+        Ok(self.wrap_values(py, args))
+    }
+
+    /// The output arguments of the unit.
+    #[getter]
+    fn outputs(&self, py: Python) -> PyResult<Vec<PyValue>> {
+        let args: Vec<_> = self.with(py, |u| u.output_args().collect())?;
+        Ok(self.wrap_values(py, args))
+    }
+
+    /// The blocks of the unit, in order.
+    #[getter]
+    fn blocks(&self, py: Python) -> PyResult<Vec<PyBlock>> {
+        let blocks: Vec<_> = self.with(py, |u| u.blocks().collect())?;
+        Ok(blocks
+            .into_iter()
+            .map(|block| PyBlock {
+                module: self.module.clone_ref(py),
+                unit: self.id,
+                block,
+            })
+            .collect())
+    }
+
+    /// The instructions of all blocks of the unit, in order.
+    #[getter]
+    fn insts(&self, py: Python) -> PyResult<Vec<PyInst>> {
+        let insts: Vec<_> = self.with(py, |u| u.all_insts().collect())?;
+        Ok(self.wrap_insts(py, insts))
+    }
+
+    fn __str__(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| u.to_string())
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| format!("<llhd.Unit {} {}>", u.kind(), u.name()))
+    }
+}
+
+/// A block in a unit.
+#[pyclass(name = "Block")]
+pub struct PyBlock {
+    module: Py<PyLlhdModule>,
+    unit: UnitId,
+    block: Block,
+}
+
+impl PyBlock {
+    fn with<R>(&self, py: Python, f: impl FnOnce(Unit) -> R) -> PyResult<R> {
+        let module = self.module.borrow(py);
+        let unit = module.unit(self.unit)?;
+        if !unit.blocks().any(|bb| bb == self.block) {
+            return Err(PyValueError::new_err(
+                "block has been removed from the unit",
+            ));
+        }
+        Ok(f(unit))
+    }
+}
+
+#[pymethods]
+impl PyBlock {
+    /// The name of the block, or `None` if it is unnamed.
+    #[getter]
+    fn name(&self, py: Python) -> PyResult<Option<String>> {
+        self.with(py, |u| u.get_block_name(self.block).map(String::from))
+    }
+
+    /// The instructions of the block, in order.
+    #[getter]
+    fn insts(&self, py: Python) -> PyResult<Vec<PyInst>> {
+        let insts: Vec<_> = self.with(py, |u| u.insts(self.block).collect())?;
+        Ok(insts
+            .into_iter()
+            .map(|inst| PyInst {
+                module: self.module.clone_ref(py),
+                unit: self.unit,
+                inst,
+            })
+            .collect())
+    }
+
+    fn __str__(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| self.block.dump(&u).to_string())
+    }
+}
+
+/// An instruction in a unit.
+#[pyclass(name = "Inst")]
+pub struct PyInst {
+    module: Py<PyLlhdModule>,
+    unit: UnitId,
+    inst: Inst,
+}
+
+impl PyInst {
+    fn with<R>(&self, py: Python, f: impl FnOnce(Unit) -> R) -> PyResult<R> {
+        let module = self.module.borrow(py);
+        let unit = module.unit(self.unit)?;
+        if !unit.all_insts().any(|inst| inst == self.inst) {
+            return Err(PyValueError::new_err(
+                "instruction has been removed from the unit",
+            ));
+        }
+        Ok(f(unit))
+    }
+
+    fn value(&self, py: Python, value: Value) -> PyValue {
+        PyValue {
+            module: self.module.clone_ref(py),
+            unit: self.unit,
+            value,
+        }
+    }
+}
+
+#[pymethods]
+impl PyInst {
+    /// The opcode of the instruction, e.g. `add`.
+    #[getter]
+    fn opcode(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| u[self.inst].opcode().to_string())
+    }
+
+    /// The value arguments of the instruction.
+    #[getter]
+    fn args(&self, py: Python) -> PyResult<Vec<PyValue>> {
+        let args = self.with(py, |u| u[self.inst].args().to_vec())?;
+        Ok(args.into_iter().map(|v| self.value(py, v)).collect())
+    }
+
+    /// The value produced by the instruction, or `None` if it produces none.
+    #[getter]
+    fn result(&self, py: Python) -> PyResult<Option<PyValue>> {
+        let result = self.with(py, |u| u.get_inst_result(self.inst))?;
+        Ok(result.map(|v| self.value(py, v)))
+    }
+
+    fn __str__(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| self.inst.dump(&u).to_string())
+    }
+}
+
+/// A value in a unit.
+#[pyclass(name = "Value")]
+pub struct PyValue {
+    module: Py<PyLlhdModule>,
+    unit: UnitId,
+    value: Value,
+}
+
+impl PyValue {
+    fn with<R>(&self, py: Python, f: impl FnOnce(Unit) -> R) -> PyResult<R> {
+        let module = self.module.borrow(py);
+        let unit = module.unit(self.unit)?;
+        let is_arg = unit.args().any(|v| v == self.value);
+        let is_result = || {
+            unit.all_insts()
+                .any(|inst| unit.get_inst_result(inst) == Some(self.value))
+        };
+        if !is_arg && !is_result() {
+            return Err(PyValueError::new_err(
+                "value has been removed from the unit",
+            ));
+        }
+        Ok(f(unit))
+    }
+}
+
+#[pymethods]
+impl PyValue {
+    /// The name of the value without the `%` sigil, or `None` if it is
+    /// unnamed.
+    #[getter]
+    fn name(&self, py: Python) -> PyResult<Option<String>> {
+        self.with(py, |u| u.get_name(self.value).map(String::from))
+    }
+
+    /// The type of the value in assembly format, e.g. `i8$`.
+    #[getter]
+    fn ty(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| u.value_type(self.value).to_string())
+    }
+
+    fn __str__(&self, py: Python) -> PyResult<String> {
+        self.with(py, |u| self.value.dump(&u).to_string())
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.module.is(&other.module) && self.unit == other.unit && self.value == other.value
+    }
+}
+
+/// A builder for an entity described as a netlist.
+///
+/// See `netlist::NetlistBuilder` for details.
+#[pyclass(name = "Netlist")]
+pub struct PyNetlist {
+    builder: NetlistBuilder,
+}
+
+#[pymethods]
+impl PyNetlist {
+    /// Create a new netlist for an entity with the given name, e.g. `@top`.
+    #[new]
+    fn new(name: &str) -> Self {
+        Self {
+            builder: NetlistBuilder::new(parse_unit_name(name)),
+        }
+    }
+
+    /// Add an input port carrying values of the given type, e.g. `i8`.
+    fn input(&mut self, name: &str, ty: &str) -> PyResult<()> {
+        self.builder.input(name, parse_py_type(ty)?);
+        Ok(())
+    }
+
+    /// Add an output port carrying values of the given type, e.g. `i8`.
+    fn output(&mut self, name: &str, ty: &str) -> PyResult<()> {
+        self.builder.output(name, parse_py_type(ty)?);
+        Ok(())
+    }
+
+    /// Declare a local net carrying values of the given type, e.g. `i8`.
+    fn net(&mut self, name: &str, ty: &str) -> PyResult<()> {
+        self.builder.net(name, parse_py_type(ty)?);
+        Ok(())
+    }
+
+    /// Instantiate a cell, connecting its ports to the given nets in order.
+    fn cell(&mut self, unit: &str, inputs: Vec<String>, outputs: Vec<String>) {
+        let inputs: Vec<_> = inputs.iter().map(String::as_str).collect();
+        let outputs: Vec<_> = outputs.iter().map(String::as_str).collect();
+        self.builder.cell(parse_unit_name(unit), &inputs, &outputs);
+    }
+
+    /// Connect two nets, such that they become one.
+    fn connect(&mut self, a: &str, b: &str) {
+        self.builder.connect(a, b);
+    }
+
+    /// Build the entity and add it to a module.
+    fn build(&self, module: &Bound<PyLlhdModule>) -> PyResult<PyUnit> {
+        let id = self
+            .builder
+            .build(&mut module.borrow_mut().module)
+            .map_err(PyValueError::new_err)?;
+        Ok(PyUnit {
+            module: module.clone().unbind(),
+            id,
+        })
+    }
+}
+
+/// The `llhd` Python module.
+#[pymodule]
+fn llhd(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyLlhdModule>()?;
+    m.add_class::<PyUnit>()?;
+    m.add_class::<PyBlock>()?;
+    m.add_class::<PyInst>()?;
+    m.add_class::<PyValue>()?;
+    m.add_class::<PyNetlist>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    fn run(script: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let llhd = PyModule::new_bound(py, "llhd").unwrap();
+            self::llhd(&llhd).unwrap();
+            let globals = PyDict::new_bound(py);
+            globals.set_item("llhd", llhd).unwrap();
+            py.run_bound(script, Some(&globals), None)
+                .map_err(|e| e.print(py))
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn inspect_and_transform() {
+        run(r#"
+m = llhd.Module.parse("""
+func @foo (i8 %x) i8 {
+entry:
+    %a = const i8 1
+    %b = add i8 %a, %a
+    %y = add i8 %x, %b
+    ret i8 %y
+}
+""")
+[u] = m.units
+assert (u.kind, u.name) == ("func", "@foo")
+assert [(v.name, v.ty) for v in u.inputs] == [("x", "i8")]
+assert [i.opcode for i in u.insts] == ["const", "add", "add", "ret"]
+[bb] = u.blocks
+assert bb.name == "entry"
+add = bb.insts[2]
+assert [str(v) for v in add.args] == ["%x", "%b"]
+assert add.args[0] == u.inputs[0]
+assert add.result.name == "y"
+assert u.insts[3].result is None
+b = u.insts[1]
+assert m.run_passes(["cf", "dce"])
+assert [i.opcode for i in u.insts] == ["const", "add", "ret"]
+try:
+    b.opcode
+    assert False
+except ValueError:
+    pass
+try:
+    m.run_pass("nope")
+    assert False
+except ValueError as e:
+    assert str(e) == "unknown pass `nope`"
+m.verify()
+assert "%b = const i8 2" in str(m)
+assert m.unit_by_name("@bar") is None
+m.remove_unit(m.unit_by_name("@foo"))
+assert m.units == []
+"#);
+    }
+
+    #[test]
+    fn build_netlist() {
+        run(r#"
+m = llhd.Module()
+m.declare("@inv", ["i1$"], ["i1$"])
+nl = llhd.Netlist("@top")
+nl.input("a", "i1")
+nl.output("z", "i1")
+nl.cell("@inv", ["a"], ["n"])
+nl.cell("@inv", ["n"], ["z"])
+top = nl.build(m)
+m.link()
+m.verify()
+assert top.kind == "entity"
+assert [i.opcode for i in top.insts].count("inst") == 2
+"#);
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Test case reduction
+//!
+//! This module implements a delta debugging reducer, which repeatedly
+//! simplifies a module for as long as a predicate still holds. The predicate
+//! usually checks that a bug still reproduces, such as a pass crashing or
+//! producing output that does not verify, which turns a large design into a
+//! small reproducer for a bug report. `llhd-reduce` runs the reducer with an
+//! external command as the predicate.
+//!
+//! ```
+//! use llhd::{assembly::{parse_module, write_module_string}, reduce::Reducer};
+//!
+//! let module = parse_module("
+//!     func @unused () void {
+//!     entry:
+//!         ret
+//!     }
+//!     func @foo (i8 %a) i8 {
+//!     entry:
+//!         %b = const i8 42
+//!         %c = xor i8 %a, %b
+//!         ret i8 %c
+//!     }
+//! ").unwrap();
+//! let reduced = Reducer::new(|m| write_module_string(m).contains("xor")).reduce(module);
+//! let text = write_module_string(&reduced);
+//! assert!(!text.contains("@unused"));
+//! assert!(text.contains("const i8 0"));
+//! ```
+
+use crate::{
+    ir::{prelude::*, InstData},
+    ty::has_zero,
+    value::{IntValue, TimeValue},
+    verifier::Verifier,
+};
+
+/// A delta debugging reducer of modules.
+///
+/// The reducer tries the following simplifications, in chunks of decreasing
+/// size, until none of them applies anymore:
+///
+/// - Units are replaced by declarations, and unused declarations removed.
+/// - Blocks are removed, turning conditional branches to them into
+///   unconditional ones.
+/// - Instructions are removed, with their uses replaced by zero constants.
+/// - Constants are shrunk to zero or one.
+///
+/// If the original module verifies, candidates which do not are discarded
+/// without consulting the predicate, such that the reduction does not drift
+/// towards a different failure.
+pub struct Reducer<F> {
+    interesting: F,
+    require_valid: bool,
+    tests: usize,
+}
+
+impl<F: FnMut(&Module) -> bool> Reducer<F> {
+    /// Create a reducer which keeps the modules for which `interesting`
+    /// returns true.
+    pub fn new(interesting: F) -> Self {
+        Self {
+            interesting,
+            require_valid: false,
+            tests: 0,
+        }
+    }
+
+    /// Reduce a module.
+    ///
+    /// The module is returned unchanged if it is not interesting in the first
+    /// place.
+    pub fn reduce(&mut self, mut module: Module) -> Module {
+        self.require_valid = verify(&module);
+        if !self.test(&module) {
+            return module;
+        }
+        let mut round = 0;
+        loop {
+            round += 1;
+            debug!("Reduction round {}", round);
+            let mut modified = false;
+            modified |= self.remove_units(&mut module);
+            modified |= self.remove_blocks(&mut module);
+            modified |= self.remove_insts(&mut module);
+            modified |= self.shrink_consts(&mut module);
+            if !modified {
+                break;
+            }
+        }
+        info!("Reduced in {} rounds with {} tests", round, self.tests);
+        module
+    }
+
+    /// Get the number of candidates tested so far.
+    pub fn tests(&self) -> usize {
+        self.tests
+    }
+
+    /// Check whether a candidate is interesting.
+    fn test(&mut self, module: &Module) -> bool {
+        if self.require_valid && !verify(module) {
+            return false;
+        }
+        self.tests += 1;
+        (self.interesting)(module)
+    }
+
+    /// Apply an edit to chunks of items, starting with all items and halving
+    /// the chunk size, and keep the edits which leave the module interesting.
+    /// The edit returns whether it changed the module.
+    ///
+    /// Returns whether any edit was kept.
+    fn apply_chunked<T: Clone>(
+        &mut self,
+        module: &mut Module,
+        mut items: Vec<T>,
+        edit: impl Fn(&mut Module, &[T]) -> bool,
+    ) -> bool {
+        let mut modified = false;
+        let mut chunk = items.len();
+        while chunk > 0 && !items.is_empty() {
+            let mut i = 0;
+            while i < items.len() {
+                let end = (i + chunk).min(items.len());
+                let mut candidate = module.clone();
+                let changed = edit(&mut candidate, &items[i..end]);
+                let _ = candidate.relink();
+                if changed && self.test(&candidate) {
+                    *module = candidate;
+                    items.drain(i..end);
+                    modified = true;
+                } else {
+                    i = end;
+                }
+            }
+            chunk /= 2;
+        }
+        modified
+    }
+
+    /// Replace units by declarations, and remove unused declarations.
+    fn remove_units(&mut self, module: &mut Module) -> bool {
+        let names: Vec<UnitName> = module.units().map(|unit| unit.name().clone()).collect();
+        let mut modified = self.apply_chunked(module, names, |module, names| {
+            for name in names {
+                let unit = module.units().find(|unit| unit.name() == name).unwrap();
+                let (id, sig) = (unit.id(), unit.sig().clone());
+                module.remove_unit(id);
+                module.declare(name.clone(), sig);
+            }
+            true
+        });
+        let mut candidate = module.clone();
+        if candidate.remove_unused_decls() > 0 && self.test(&candidate) {
+            *module = candidate;
+            modified = true;
+        }
+        modified
+    }
+
+    /// Remove blocks other than the entry.
+    fn remove_blocks(&mut self, module: &mut Module) -> bool {
+        let blocks: Vec<(UnitName, Block)> = module
+            .units()
+            .flat_map(|unit| {
+                let entry = unit.first_block();
+                unit.blocks()
+                    .filter(move |&bb| Some(bb) != entry)
+                    .map(move |bb| (unit.name().clone(), bb))
+            })
+            .collect();
+        self.apply_chunked(module, blocks, |module, blocks| {
+            let mut changed = false;
+            for (name, bb) in blocks {
+                changed |= unit_mut(module, name).remove_block(*bb).is_ok();
+            }
+            changed
+        })
+    }
+
+    /// Remove instructions, replacing the uses of their results with zeros.
+    ///
+    /// Terminators are kept, as are instructions whose result is used but
+    /// cannot be replaced by a constant, such as signals. Constants are left
+    /// to `shrink_consts`, and arrays and structs, which zeros are built from,
+    /// to the removal of their fields.
+    fn remove_insts(&mut self, module: &mut Module) -> bool {
+        let mut insts: Vec<(UnitName, Inst)> = vec![];
+        for unit in module.units() {
+            for inst in unit.all_insts() {
+                let removable = match unit.get_inst_result(inst) {
+                    _ if unit[inst].opcode().is_terminator() => false,
+                    None => true,
+                    Some(value) if unit.has_uses(value) => {
+                        let opcode = unit[inst].opcode();
+                        !opcode.is_const()
+                            && !matches!(
+                                opcode,
+                                Opcode::ArrayUniform | Opcode::Array | Opcode::Struct
+                            )
+                            && has_zero(&unit.value_type(value))
+                    }
+                    Some(_) => true,
+                };
+                if removable {
+                    insts.push((unit.name().clone(), inst));
+                }
+            }
+        }
+        self.apply_chunked(module, insts, |module, insts| {
+            for (name, inst) in insts {
+                remove_inst(&mut unit_mut(module, name), *inst);
+            }
+            true
+        })
+    }
+
+    /// Replace integer and time constants by zero, or integers by one.
+    fn shrink_consts(&mut self, module: &mut Module) -> bool {
+        let mut modified = false;
+        for &one in &[false, true] {
+            let mut consts: Vec<(UnitName, Inst)> = vec![];
+            for unit in module.units() {
+                for inst in unit.all_insts() {
+                    let shrinkable = match &unit[inst] {
+                        InstData::ConstInt { imm, .. } => {
+                            if one {
+                                !imm.is_one() && !imm.is_zero()
+                            } else {
+                                !imm.is_zero()
+                            }
+                        }
+                        InstData::ConstTime { imm, .. } => !one && !imm.is_zero(),
+                        _ => false,
+                    };
+                    if shrinkable {
+                        consts.push((unit.name().clone(), inst));
+                    }
+                }
+            }
+            modified |= self.apply_chunked(module, consts, |module, consts| {
+                for (name, inst) in consts {
+                    let mut unit = unit_mut(module, name);
+                    let data = match &unit[*inst] {
+                        InstData::ConstInt { opcode, imm } if one => InstData::ConstInt {
+                            opcode: *opcode,
+                            imm: IntValue::from_usize(imm.width, 1),
+                        },
+                        InstData::ConstInt { opcode, imm } => InstData::ConstInt {
+                            opcode: *opcode,
+                            imm: IntValue::zero(imm.width),
+                        },
+                        InstData::ConstTime { opcode, .. } => InstData::ConstTime {
+                            opcode: *opcode,
+                            imm: TimeValue::zero(),
+                        },
+                        _ => unreachable!(),
+                    };
+                    unit[*inst] = data;
+                }
+                true
+            });
+        }
+        modified
+    }
+}
+
+/// Check whether a module verifies.
+fn verify(module: &Module) -> bool {
+    let mut verifier = Verifier::new();
+    verifier.verify_module(module);
+    verifier.finish().is_ok()
+}
+
+/// Get a builder for the unit with the given name.
+fn unit_mut<'a>(module: &'a mut Module, name: &UnitName) -> UnitBuilder<'a> {
+    let id = module
+        .units()
+        .find(|unit| unit.name() == name)
+        .unwrap()
+        .id();
+    module.unit_mut(id)
+}
+
+/// Remove an instruction, replacing the uses of its result with a zero
+/// constant at the beginning of the entry block. External units which are no
+/// longer called are removed as well.
+fn remove_inst(unit: &mut UnitBuilder, inst: Inst) {
+    if let Some(value) = unit.get_inst_result(inst) {
+        if unit.has_uses(value) {
+            let ty = unit.value_type(value);
+            let entry = unit.entry();
+            unit.prepend_to(entry);
+            let zero = unit.ins().const_zero(&ty);
+            unit.replace_use(value, zero);
+        }
+    }
+    let ext = unit[inst].get_ext_unit();
+    unit.delete_inst(inst);
+    if let Some(ext) = ext {
+        if !unit
+            .all_insts()
+            .any(|inst| unit[inst].get_ext_unit() == Some(ext))
+        {
+            unit.remove_extern(ext);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{parse_module, write_module_string};
+
+    #[test]
+    fn reduce_to_reproducer() {
+        let module = parse_module(
+            "
+            func @helper (i32 %x) i32 {
+            entry:
+                %one = const i32 1
+                %y = add i32 %x, %one
+                ret i32 %y
+            }
+            func @foo (i1 %c, i32 %a) i32 {
+            entry:
+                br %c, %then, %else
+            then:
+                %b = call i32 @helper (i32 %a)
+                %k = const i32 7
+                %d = umul i32 %b, %k
+                br %else
+            else:
+                %e = phi i32 [%a, %entry], [%d, %then]
+                ret i32 %e
+            }
+            ",
+        )
+        .unwrap();
+
+        // Look for a multiplication with a constant.
+        let mut reducer = Reducer::new(|module: &Module| {
+            module.units().any(|unit| {
+                unit.all_insts().any(|inst| {
+                    unit[inst].opcode() == Opcode::Umul
+                        && unit[inst]
+                            .args()
+                            .iter()
+                            .any(|&arg| unit.get_const_int(arg).is_some())
+                })
+            })
+        });
+        let reduced = reducer.reduce(module);
+        reduced.verify();
+        assert!(reducer.tests() > 0);
+        assert_eq!(
+            write_module_string(&reduced),
+            indoc::indoc! {"
+                func @foo (i1 %c, i32 %a) i32 {
+                entry:
+                    %e = const i32 0
+                    %b = const i32 0
+                    br %c, %then, %else
+                then:
+                    %k = const i32 0
+                    %d = umul i32 %b, %k
+                    br %else
+                else:
+                    ret i32 %e
+                }
+            "}
+        );
+    }
+
+    #[test]
+    fn uninteresting_input_is_kept() {
+        let input = "
+            func @foo () void {
+            entry:
+                ret
+            }
+        ";
+        let module = parse_module(input).unwrap();
+        let reduced = Reducer::new(|_: &Module| false).reduce(module);
+        assert_eq!(
+            write_module_string(&reduced),
+            write_module_string(&parse_module(input).unwrap())
+        );
+    }
+}
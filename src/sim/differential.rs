@@ -0,0 +1,446 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Differential testing of passes
+//!
+//! This module implements a test harness that simulates a design before and
+//! after a pass pipeline under random stimuli, and reports the first point at
+//! which the ports of the top-level entity behave differently. Failing
+//! stimuli are shrunk to a small set of changes that still exposes the
+//! difference, which makes miscompiles in passes like TCM easy to reproduce.
+//!
+//! ```
+//! use llhd::sim::Differential;
+//!
+//! let module = llhd::assembly::parse_module("
+//!     entity @top (i4$ %a, i4$ %b) -> (i4$ %y) {
+//!         %ap = prb i4$ %a
+//!         %bp = prb i4$ %b
+//!         %x = xor i4 %ap, %bp
+//!         %z = xor i4 %x, %bp
+//!         %t = const time 0s 1e
+//!         drv i4$ %y, %z, %t
+//!     }
+//! ").unwrap();
+//! Differential::new(&["insim", "cf", "dce"]).check(&module).unwrap();
+//! ```
+
+use crate::{
+    ir::{Module, Unit},
+    pass::run_by_name,
+    sim::{engine::find_top, engine::value_name, Engine, Stimuli, Stimulus, Trace},
+    ty::{Type, TypeKind},
+    value::{ArrayValue, EnumValue, IntValue, StructValue, TimeValue},
+    verifier::Verifier,
+};
+use num::{BigRational, BigUint};
+use std::collections::HashMap;
+
+/// A differential test of a pass pipeline.
+///
+/// Each run applies `changes` random values to the input ports of the
+/// top-level entity, at random multiples of `step` before `step * steps`.
+/// The observable behaviour of a design is the value of each port of the
+/// top-level entity at the end of every point in time; the delta and epsilon
+/// steps in which the values settle may differ.
+#[derive(Debug, Clone)]
+pub struct Differential {
+    /// The passes to run, by their `llhd-opt` name.
+    pub passes: Vec<String>,
+    /// The name of the top-level entity, without the leading `@`.
+    pub top: Option<String>,
+    /// The number of random stimuli to simulate.
+    pub runs: usize,
+    /// The number of changes in each stimulus.
+    pub changes: usize,
+    /// The interval between the points in time at which changes occur.
+    pub step: TimeValue,
+    /// The number of steps simulated.
+    pub steps: usize,
+    /// The seed of the random stimuli.
+    pub seed: u64,
+}
+
+/// A difference between the behaviour of a design before and after a pass
+/// pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The shrunk stimuli which expose the difference.
+    pub stimuli: Vec<Stimulus>,
+    /// The time at which the simulation ends.
+    pub until: TimeValue,
+    /// The port which differs, without the leading `%`.
+    pub signal: String,
+    /// The first point in time at which the port differs.
+    pub time: TimeValue,
+    /// The value of the port before the passes.
+    pub before: Option<crate::Value>,
+    /// The value of the port after the passes.
+    pub after: Option<crate::Value>,
+}
+
+impl Differential {
+    /// Create a differential test of a pass pipeline, with 20 runs of 20
+    /// changes each over 100ns.
+    pub fn new(passes: &[&str]) -> Self {
+        Self {
+            passes: passes.iter().map(|p| p.to_string()).collect(),
+            top: None,
+            runs: 20,
+            changes: 20,
+            step: TimeValue::new(BigRational::new(1.into(), 1_000_000_000.into()), 0, 0),
+            steps: 100,
+            seed: 1,
+        }
+    }
+
+    /// Check that the passes preserve the behaviour of a design.
+    ///
+    /// Fails with a description of the mismatch and the stimuli that expose
+    /// it, or if the design cannot be simulated before or after the passes.
+    pub fn check(&self, module: &Module) -> Result<(), String> {
+        match self.run(module)? {
+            Some(mismatch) => Err(mismatch.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the passes on a copy of a design and compare their behaviour.
+    pub fn run(&self, module: &Module) -> Result<Option<Mismatch>, String> {
+        let mut optimized = module.clone();
+        optimized.link();
+        let ctx = crate::opt::PassContext::default();
+        for pass in &self.passes {
+            run_by_name(pass, &ctx, &mut optimized)
+                .ok_or_else(|| format!("unknown pass `{}`", pass))?;
+        }
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&optimized);
+        verifier
+            .finish()
+            .map_err(|errs| format!("output of the passes does not verify:\n{}", errs))?;
+        self.compare(module, &optimized)
+    }
+
+    /// Compare the behaviour of two designs under random stimuli.
+    ///
+    /// Returns the first mismatch found, with its stimuli shrunk.
+    pub fn compare(&self, before: &Module, after: &Module) -> Result<Option<Mismatch>, String> {
+        let top = find_top(before, self.top.as_deref())?;
+        let ports = ports(top);
+        let inputs: Vec<_> = top
+            .input_args()
+            .map(|arg| {
+                (
+                    value_name(top, arg),
+                    top.value_type(arg).unwrap_signal().clone(),
+                )
+            })
+            .collect();
+        let until = self.time(self.steps);
+        let mut rng = Rng::new(self.seed);
+        for _ in 0..self.runs {
+            let changes: Vec<_> = if inputs.is_empty() {
+                vec![]
+            } else {
+                (0..self.changes)
+                    .map(|_| {
+                        let (signal, ty) = &inputs[rng.below(inputs.len())];
+                        Stimulus {
+                            signal: signal.clone(),
+                            time: self.time(rng.below(self.steps)),
+                            value: random_value(&mut rng, ty),
+                        }
+                    })
+                    .collect()
+            };
+            if self
+                .simulate(before, after, &ports, &changes, &until)?
+                .is_some()
+            {
+                return self.shrink(before, after, &ports, changes, until).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Simulate both designs and find the first difference of their ports.
+    fn simulate(
+        &self,
+        before: &Module,
+        after: &Module,
+        ports: &[String],
+        changes: &[Stimulus],
+        until: &TimeValue,
+    ) -> Result<Option<Difference>, String> {
+        let run = |module: &Module| -> Result<Trace, String> {
+            let mut stimuli = Stimuli::new();
+            stimuli.changes = changes.to_vec();
+            stimuli.until(until.clone());
+            Engine::new(module, self.top.as_deref())?.run(stimuli)
+        };
+        let trace_before = run(before).map_err(|e| format!("before the passes: {}", e))?;
+        let trace_after = run(after).map_err(|e| format!("after the passes: {}", e))?;
+        Ok(first_difference(ports, &trace_before, &trace_after))
+    }
+
+    /// Remove changes from failing stimuli for as long as the designs still
+    /// differ, and end the simulation right after the difference.
+    fn shrink(
+        &self,
+        before: &Module,
+        after: &Module,
+        ports: &[String],
+        mut changes: Vec<Stimulus>,
+        mut until: TimeValue,
+    ) -> Result<Mismatch, String> {
+        let mut chunk = std::cmp::max(changes.len() / 2, 1);
+        loop {
+            let mut removed = false;
+            let mut index = 0;
+            while index < changes.len() {
+                let mut candidate = changes.clone();
+                candidate.drain(index..std::cmp::min(index + chunk, changes.len()));
+                if self
+                    .simulate(before, after, ports, &candidate, &until)?
+                    .is_some()
+                {
+                    changes = candidate;
+                    removed = true;
+                } else {
+                    index += chunk;
+                }
+            }
+            if !removed {
+                if chunk == 1 {
+                    break;
+                }
+                chunk /= 2;
+            }
+        }
+        changes.sort_by(|a, b| a.time.cmp(&b.time));
+        let diff = self
+            .simulate(before, after, ports, &changes, &until)?
+            .expect("shrunk stimuli no longer fail");
+        let end = TimeValue::new(diff.time.time().clone(), 0, 0);
+        if self
+            .simulate(before, after, ports, &changes, &end)?
+            .is_some()
+        {
+            until = end;
+        }
+        Ok(Mismatch {
+            stimuli: changes,
+            until,
+            signal: diff.signal,
+            time: diff.time,
+            before: diff.before,
+            after: diff.after,
+        })
+    }
+
+    /// Compute the time of a number of steps.
+    fn time(&self, steps: usize) -> TimeValue {
+        TimeValue::new(
+            self.step.time() * BigRational::from_integer(steps.into()),
+            0,
+            0,
+        )
+    }
+}
+
+impl std::fmt::Display for Mismatch {
+    /// Describe the mismatch, followed by the stimuli which expose it in the
+    /// format read by `parse_stimuli`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = |v: &Option<crate::Value>| match v {
+            Some(v) => v.to_string(),
+            None => "no value".to_string(),
+        };
+        writeln!(
+            f,
+            "%{} differs at {}: {} before the passes, {} after",
+            self.signal,
+            self.time,
+            value(&self.before),
+            value(&self.after)
+        )?;
+        writeln!(f, "stimuli:")?;
+        for change in &self.stimuli {
+            writeln!(f, "{} %{} {}", change.time, change.signal, change.value)?;
+        }
+        write!(f, "until {}", self.until)
+    }
+}
+
+/// The first difference between the ports in two traces.
+struct Difference {
+    signal: String,
+    time: TimeValue,
+    before: Option<crate::Value>,
+    after: Option<crate::Value>,
+}
+
+/// Get the names of the ports of an entity.
+fn ports(unit: Unit) -> Vec<String> {
+    unit.args().map(|arg| value_name(unit, arg)).collect()
+}
+
+/// Find the earliest point in time at which a port differs between two
+/// traces.
+fn first_difference(ports: &[String], before: &Trace, after: &Trace) -> Option<Difference> {
+    let before = settle(before);
+    let after = settle(after);
+    let empty = vec![];
+    let mut first: Option<Difference> = None;
+    for port in ports {
+        let b = before.get(port.as_str()).unwrap_or(&empty);
+        let a = after.get(port.as_str()).unwrap_or(&empty);
+        let index = match (0..std::cmp::max(b.len(), a.len())).find(|&i| b.get(i) != a.get(i)) {
+            Some(index) => index,
+            None => continue,
+        };
+        let time = match (b.get(index), a.get(index)) {
+            (Some((tb, _)), Some((ta, _))) => std::cmp::min(tb, ta),
+            (Some((t, _)), None) | (None, Some((t, _))) => t,
+            (None, None) => unreachable!(),
+        };
+        if first.as_ref().map(|d| d.time.time() <= time) == Some(true) {
+            continue;
+        }
+        let at = |changes: &Vec<(BigRational, crate::Value)>| {
+            changes
+                .iter()
+                .take_while(|(t, _)| t <= time)
+                .last()
+                .map(|(_, v)| v.clone())
+        };
+        first = Some(Difference {
+            signal: port.clone(),
+            time: TimeValue::new(time.clone(), 0, 0),
+            before: at(b),
+            after: at(a),
+        });
+    }
+    first
+}
+
+/// Reduce the changes in a trace to the value of each signal at the end of
+/// every point in time at which it changes.
+fn settle(trace: &Trace) -> HashMap<&str, Vec<(BigRational, crate::Value)>> {
+    let mut settled: HashMap<&str, Vec<(BigRational, crate::Value)>> = HashMap::new();
+    for change in &trace.changes {
+        let changes = settled.entry(change.signal.as_str()).or_default();
+        if let Some((time, value)) = changes.last_mut() {
+            if time == change.time.time() {
+                *value = change.value.clone();
+                continue;
+            }
+        }
+        changes.push((change.time.time().clone(), change.value.clone()));
+    }
+    for changes in settled.values_mut() {
+        changes.dedup_by(|later, earlier| later.1 == earlier.1);
+    }
+    settled
+}
+
+/// Generate a random value of a type.
+fn random_value(rng: &mut Rng, ty: &Type) -> crate::Value {
+    match **ty {
+        TypeKind::IntType(width) => {
+            let words: Vec<_> = (0..width.div_ceil(32)).map(|_| rng.next() as u32).collect();
+            IntValue::from_unsigned(width, BigUint::from_slice(&words)).into()
+        }
+        TypeKind::EnumType(size) => EnumValue::new(size, rng.below(size)).into(),
+        TypeKind::ArrayType(length, ref ty) => {
+            ArrayValue::new((0..length).map(|_| random_value(rng, ty)).collect()).into()
+        }
+        TypeKind::StructType(ref tys) | TypeKind::NamedStructType(_, ref tys) => {
+            StructValue::new(tys.iter().map(|ty| random_value(rng, ty)).collect()).into()
+        }
+        _ => crate::Value::zero(ty),
+    }
+}
+
+/// A xorshift random number generator, such that failing stimuli can be
+/// reproduced from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    #[test]
+    fn passes_preserve_behaviour() {
+        let module = parse_module(
+            "
+            entity @top (i1$ %clk, i8$ %a) -> (i8$ %q) {
+                %zero = const i8 0
+                %ap = prb i8$ %a
+                %x = add i8 %ap, %zero
+                %y = umul i8 %x, %ap
+                %clkp = prb i1$ %clk
+                reg i8$ %q, [%y, rise %clkp]
+            }
+            ",
+        )
+        .unwrap();
+        Differential::new(&["insim", "cf", "gcse", "dce"])
+            .check(&module)
+            .unwrap();
+        assert!(Differential::new(&["nope"]).check(&module).is_err());
+    }
+
+    #[test]
+    fn mismatches_are_shrunk() {
+        let design = |op| {
+            parse_module(format!(
+                "
+                entity @top (i4$ %a, i4$ %b) -> (i4$ %y) {{
+                    %ap = prb i4$ %a
+                    %bp = prb i4$ %b
+                    %x = {} i4 %ap, %bp
+                    %t = const time 1ns
+                    drv i4$ %y, %x, %t
+                }}
+                ",
+                op
+            ))
+            .unwrap()
+        };
+        let mismatch = Differential::new(&[])
+            .compare(&design("and"), &design("or"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.stimuli.len(), 1);
+        assert_eq!(mismatch.signal, "y");
+        assert_eq!(mismatch.time, advance(&mismatch.stimuli[0].time));
+        assert_eq!(mismatch.until, mismatch.time);
+        assert_eq!(mismatch.before, Some(IntValue::from_usize(4, 0).into()));
+        assert_eq!(mismatch.after, Some(mismatch.stimuli[0].value.clone()));
+        assert!(mismatch.to_string().contains("\nuntil "));
+    }
+
+    fn advance(time: &TimeValue) -> TimeValue {
+        let ns = BigRational::new(1.into(), 1_000_000_000.into());
+        TimeValue::new(time.time() + ns, 0, 0)
+    }
+}
@@ -0,0 +1,926 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Simulation engine
+//!
+//! This module implements an event-driven simulation of a hierarchy of
+//! entities. Whenever a signal changes, all entity instances are re-evaluated,
+//! and the drives and registers within them schedule new signal changes.
+
+use crate::{
+    ir::{prelude::*, LinkedUnit, RegMode},
+    sim::{Stimuli, Stimulus, Trace},
+    ty::Type,
+    value::{ArrayValue, EnumValue, IntValue, StructValue, TimeValue},
+};
+use num::Zero;
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+/// The maximum number of delta and epsilon steps at a single point in time,
+/// after which the design is considered to oscillate.
+const MAX_STEPS: usize = 10_000;
+
+/// A projection from a signal to a part of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Proj {
+    /// A field of a struct or element of an array.
+    Field(usize),
+    /// A slice of an integer or array.
+    Slice(usize, usize),
+}
+
+/// A reference to a signal, or a part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SignalRef {
+    id: usize,
+    path: Vec<Proj>,
+}
+
+/// The instance and instruction of the drive that changes a signal, or `None`
+/// for stimuli.
+type Driver = Option<(usize, Inst)>;
+
+/// A signal in the simulated design.
+#[derive(Debug)]
+struct Signal {
+    name: String,
+    value: crate::Value,
+    /// How the values of the drivers are combined, if not by taking the most
+    /// recent one.
+    resolution: Option<Resolution>,
+    /// The value each driver drives onto the signal, if it has a resolution.
+    /// Stimuli count as one driver.
+    drivers: Vec<(Driver, IntValue)>,
+}
+
+/// A pending change of a signal, with the instance and instruction of the
+/// drive that scheduled it, if any.
+type Event = (SignalRef, crate::Value, Driver);
+
+/// An instance of an entity in the simulated design.
+struct Instance<'a> {
+    unit: Unit<'a>,
+    /// The signal each signal-typed value refers to.
+    signals: HashMap<Value, SignalRef>,
+    /// The trigger values of each register during the last evaluation.
+    triggers: HashMap<Inst, Vec<crate::Value>>,
+}
+
+/// The state of a simulation.
+pub struct Engine<'a> {
+    module: &'a Module,
+    signals: Vec<Signal>,
+    instances: Vec<Instance<'a>>,
+    queue: BTreeMap<TimeValue, Vec<Event>>,
+    now: TimeValue,
+    trace: Trace,
+}
+
+/// The state of a simulation at a point in time.
+///
+/// A snapshot holds the value of every signal, the values the drivers of
+/// resolved signals drive, the pending signal changes, and the trigger values
+/// each register has last seen. It may be serialized to checkpoint a long
+/// simulation, and restored into an engine elaborated from the same design to
+/// continue from that point. The values of the signals may be changed before
+/// restoring a snapshot to inject state into the design, and take effect once
+/// the simulation continues.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The time at which the snapshot was taken.
+    pub time: TimeValue,
+    /// The name and value of each signal in the design.
+    pub signals: Vec<(String, crate::Value)>,
+    drivers: Vec<Vec<(Driver, IntValue)>>,
+    events: Vec<(TimeValue, Vec<Event>)>,
+    triggers: Vec<Vec<(Inst, Vec<crate::Value>)>>,
+}
+
+impl Snapshot {
+    /// Get the value of a signal.
+    pub fn value(&self, signal: &str) -> Option<&crate::Value> {
+        self.signals
+            .iter()
+            .find(|(name, _)| name == signal)
+            .map(|(_, value)| value)
+    }
+
+    /// Change the value of a signal.
+    ///
+    /// The value must be of the signal's type. For signals with a resolution,
+    /// the value holds until one of the drivers changes.
+    pub fn set_value(&mut self, signal: &str, value: crate::Value) -> Result<(), String> {
+        let slot = self
+            .signals
+            .iter_mut()
+            .find(|(name, _)| name == signal)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("unknown signal %{}", signal))?;
+        if slot.ty() != value.ty() {
+            return Err(format!(
+                "cannot set %{} of type {} to {}",
+                signal,
+                slot.ty(),
+                value
+            ));
+        }
+        *slot = value;
+        Ok(())
+    }
+}
+
+impl<'a> Engine<'a> {
+    /// Elaborate the design in a module.
+    pub fn new(module: &'a Module, top: Option<&str>) -> Result<Self, String> {
+        let top = find_top(module, top)?;
+        let mut engine = Self {
+            module,
+            signals: vec![],
+            instances: vec![],
+            queue: BTreeMap::new(),
+            now: TimeValue::zero(),
+            trace: Trace::default(),
+        };
+        let mut ports = HashMap::new();
+        for arg in top.args() {
+            let name = value_name(top, arg);
+            let value = crate::Value::zero(top.value_type(arg).unwrap_signal());
+            ports.insert(arg, engine.add_signal(name, value));
+        }
+        engine.elaborate(top, "", ports)?;
+        Ok(engine)
+    }
+
+    /// Get the signals of the elaborated design, with their name and type.
+    pub fn signals(&self) -> impl Iterator<Item = (&str, Type)> + '_ {
+        self.signals.iter().map(|s| (s.name.as_str(), s.value.ty()))
+    }
+
+    /// Simulate the design under a set of stimuli.
+    pub fn run(mut self, stimuli: Stimuli) -> Result<Trace, String> {
+        self.simulate(stimuli)
+    }
+
+    /// Continue the simulation under a set of stimuli.
+    ///
+    /// Simulates from the current time until `stimuli.until`, or until no
+    /// more signal changes are pending. Changes scheduled after the end time
+    /// remain pending, such that the simulation may be continued by another
+    /// call. Generated changes before the current time are skipped. All
+    /// instances are evaluated with the current values of the signals first.
+    /// The returned trace starts with the values of all signals at the
+    /// current time. The top-level entity in `stimuli.top` is ignored.
+    pub fn simulate(&mut self, mut stimuli: Stimuli) -> Result<Trace, String> {
+        if !stimuli.generators.is_empty() && stimuli.until.is_none() {
+            return Err("stimuli with generators require an end time".to_string());
+        }
+        for change in std::mem::take(&mut stimuli.changes) {
+            if change.time < self.now {
+                return Err(format!(
+                    "change of %{} at {} lies before the current time {}",
+                    change.signal, change.time, self.now
+                ));
+            }
+            let target = self.lookup_signal(&change.signal)?;
+            self.schedule(change.time, target, change.value, None);
+        }
+        for (signal, generator) in std::mem::take(&mut stimuli.generators) {
+            let target = self.lookup_signal(&signal)?;
+            let until = stimuli.until.as_ref().unwrap();
+            for (time, value) in (0..).map_while(generator) {
+                if time.time() > until.time() {
+                    break;
+                }
+                if time >= self.now {
+                    self.schedule(time, target.clone(), value, None);
+                }
+            }
+        }
+
+        // Record the current state, then process events in order until there
+        // are none left or the end time has been reached.
+        for signal in &self.signals {
+            self.trace.changes.push(Stimulus {
+                signal: signal.name.clone(),
+                time: self.now.clone(),
+                value: signal.value.clone(),
+            });
+        }
+        self.eval_all()?;
+        let mut steps = 0;
+        while let Some(time) = self.queue.keys().next().cloned() {
+            if let Some(until) = &stimuli.until {
+                if time.time() > until.time() {
+                    break;
+                }
+            }
+            if time.time() == self.now.time() {
+                steps += 1;
+                if steps > MAX_STEPS {
+                    return Err(format!("design does not settle at {}", time));
+                }
+            } else {
+                steps = 0;
+            }
+            let events = self.queue.remove(&time).unwrap();
+            self.now = time;
+            if self.apply(events) {
+                self.eval_all()?;
+            }
+        }
+        Ok(std::mem::take(&mut self.trace))
+    }
+
+    /// Get the current simulation time.
+    pub fn now(&self) -> &TimeValue {
+        &self.now
+    }
+
+    /// Capture the state of the simulation.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.now.clone(),
+            signals: self
+                .signals
+                .iter()
+                .map(|s| (s.name.clone(), s.value.clone()))
+                .collect(),
+            drivers: self.signals.iter().map(|s| s.drivers.clone()).collect(),
+            events: self
+                .queue
+                .iter()
+                .map(|(time, events)| (time.clone(), events.clone()))
+                .collect(),
+            triggers: self
+                .instances
+                .iter()
+                .map(|instance| {
+                    let mut triggers: Vec<_> = instance
+                        .triggers
+                        .iter()
+                        .map(|(&inst, values)| (inst, values.clone()))
+                        .collect();
+                    triggers.sort_by_key(|&(inst, _)| inst);
+                    triggers
+                })
+                .collect(),
+        }
+    }
+
+    /// Restore the state of the simulation from a snapshot.
+    ///
+    /// The snapshot must have been taken from an engine elaborated from the
+    /// same design. The simulation continues from the time of the snapshot.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        let mismatch = || Err("snapshot does not match the design".to_string());
+        if snapshot.signals.len() != self.signals.len()
+            || snapshot.drivers.len() != self.signals.len()
+            || snapshot.triggers.len() != self.instances.len()
+        {
+            return mismatch();
+        }
+        for (signal, (name, value)) in self.signals.iter().zip(&snapshot.signals) {
+            if signal.name != *name || signal.value.ty() != value.ty() {
+                return mismatch();
+            }
+        }
+        let valid_ref = |signal: &SignalRef| signal.id < self.signals.len();
+        let valid_driver = |driver: &Driver| {
+            driver
+                .map(|(index, inst)| {
+                    index < self.instances.len()
+                        && self.instances[index].unit.is_inst_inserted(inst)
+                })
+                .unwrap_or(true)
+        };
+        let valid = snapshot
+            .drivers
+            .iter()
+            .flatten()
+            .all(|(d, _)| valid_driver(d))
+            && snapshot
+                .events
+                .iter()
+                .flat_map(|(_, events)| events)
+                .all(|(signal, _, driver)| valid_ref(signal) && valid_driver(driver))
+            && snapshot
+                .triggers
+                .iter()
+                .zip(&self.instances)
+                .all(|(triggers, instance)| {
+                    triggers
+                        .iter()
+                        .all(|&(inst, _)| instance.unit.is_inst_inserted(inst))
+                });
+        if !valid {
+            return mismatch();
+        }
+
+        for (signal, ((_, value), drivers)) in self
+            .signals
+            .iter_mut()
+            .zip(snapshot.signals.iter().zip(&snapshot.drivers))
+        {
+            signal.value = value.clone();
+            signal.drivers = drivers.clone();
+        }
+        self.queue = snapshot.events.iter().cloned().collect();
+        for (instance, triggers) in self.instances.iter_mut().zip(&snapshot.triggers) {
+            instance.triggers = triggers.iter().cloned().collect();
+        }
+        self.now = snapshot.time.clone();
+        self.trace = Trace::default();
+        Ok(())
+    }
+
+    /// Add a signal to the design.
+    fn add_signal(&mut self, name: String, value: crate::Value) -> SignalRef {
+        self.signals.push(Signal {
+            name,
+            value,
+            resolution: None,
+            drivers: vec![],
+        });
+        SignalRef {
+            id: self.signals.len() - 1,
+            path: vec![],
+        }
+    }
+
+    /// Find a signal by name.
+    fn lookup_signal(&self, name: &str) -> Result<SignalRef, String> {
+        self.signals
+            .iter()
+            .position(|s| s.name == name)
+            .map(|id| SignalRef { id, path: vec![] })
+            .ok_or_else(|| format!("unknown signal %{}", name))
+    }
+
+    /// Create an instance of an entity, and recursively the entities it
+    /// instantiates.
+    fn elaborate(
+        &mut self,
+        unit: Unit<'a>,
+        path: &str,
+        signals: HashMap<Value, SignalRef>,
+    ) -> Result<(), String> {
+        if !unit.is_entity() {
+            return Err(format!(
+                "{} cannot be simulated; lower it first",
+                unit.name()
+            ));
+        }
+        let index = self.instances.len();
+        self.instances.push(Instance {
+            unit,
+            signals,
+            triggers: HashMap::new(),
+        });
+        let mut values: HashMap<Value, crate::Value> = HashMap::new();
+        let mut names = HashMap::new();
+        for inst in unit.all_insts() {
+            let data = &unit[inst];
+            match data.opcode() {
+                Opcode::Sig => {
+                    let result = unit.inst_result(inst);
+                    let name = qualify(path, &value_name(unit, result));
+                    let init = values[&data.args()[0]].clone();
+                    let signal = self.add_signal(name, init);
+                    match unit.signal_resolution(inst) {
+                        None | Some(Resolution::LastWrite) => (),
+                        Some(Resolution::Function(func)) => {
+                            return Err(format!(
+                                "resolution function {} of {} cannot be simulated",
+                                func, self.signals[signal.id].name
+                            ))
+                        }
+                        Some(resolution) => {
+                            self.signals[signal.id].resolution = Some(resolution.clone())
+                        }
+                    }
+                    self.instances[index].signals.insert(result, signal);
+                }
+                Opcode::ExtField | Opcode::ExtSlice if unit.inst_type(inst).is_signal() => {
+                    let mut signal = self.instances[index].signal(data.args()[0])?;
+                    signal.path.push(match data.opcode() {
+                        Opcode::ExtField => Proj::Field(data.imms()[0]),
+                        _ => Proj::Slice(data.imms()[0], data.imms()[1]),
+                    });
+                    self.instances[index]
+                        .signals
+                        .insert(unit.inst_result(inst), signal);
+                }
+                Opcode::Inst => {
+                    let name = &unit[data.get_ext_unit().unwrap()].name;
+                    let callee = match self.module.lookup(name) {
+                        Some(LinkedUnit::Def(id)) => self.module.unit(id),
+                        _ => return Err(format!("{} is not defined", name)),
+                    };
+                    let mut child = qualify(path, &sanitize(&callee.name().to_string()));
+                    let count = names.entry(child.clone()).or_insert(0);
+                    *count += 1;
+                    if *count > 1 {
+                        child = format!("{}#{}", child, count);
+                    }
+                    // Each copy of a replicated instance connects to one
+                    // element of the arguments that do not match their port.
+                    let copies = unit.inst_replication(inst);
+                    for copy in 0..copies.unwrap_or(1) {
+                        let mut ports = HashMap::new();
+                        let args = callee.input_args().chain(callee.output_args());
+                        let actuals = data.input_args().iter().chain(data.output_args());
+                        for (arg, &actual) in args.zip(actuals) {
+                            let mut signal = self.instances[index].signal(actual)?;
+                            if copies.is_some() && unit.value_type(actual) != callee.value_type(arg)
+                            {
+                                signal.path.push(Proj::Field(copy));
+                            }
+                            ports.insert(arg, signal);
+                        }
+                        match copies {
+                            Some(_) => {
+                                self.elaborate(callee, &format!("{}[{}]", child, copy), ports)?
+                            }
+                            None => self.elaborate(callee, &child, ports)?,
+                        }
+                    }
+                }
+                Opcode::Drv | Opcode::DrvCond | Opcode::Del | Opcode::DelCond | Opcode::Reg => (),
+                _ => {
+                    if let Some(value) = self.compute(index, inst, &values)? {
+                        values.insert(unit.inst_result(inst), value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-evaluate all instances.
+    fn eval_all(&mut self) -> Result<(), String> {
+        for index in 0..self.instances.len() {
+            self.eval(index)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluate an instance, scheduling the signal changes it causes.
+    fn eval(&mut self, index: usize) -> Result<(), String> {
+        let unit = self.instances[index].unit;
+        let mut values: HashMap<Value, crate::Value> = HashMap::new();
+        for inst in unit.all_insts() {
+            let data = &unit[inst];
+            match data.opcode() {
+                Opcode::Sig | Opcode::Inst => (),
+                Opcode::ExtField | Opcode::ExtSlice if unit.inst_type(inst).is_signal() => (),
+                Opcode::Drv | Opcode::DrvCond | Opcode::Del | Opcode::DelCond => {
+                    let args = data.args();
+                    let conditional = matches!(data.opcode(), Opcode::DrvCond | Opcode::DelCond);
+                    if conditional && !values[&args[3]].is_one() {
+                        continue;
+                    }
+                    let target = self.instances[index].signal(args[0])?;
+                    let value = match data.opcode() {
+                        Opcode::Del | Opcode::DelCond => {
+                            self.read(&self.instances[index].signal(args[1])?)
+                        }
+                        _ => values[&args[1]].clone(),
+                    };
+                    let delay = values[&args[2]].unwrap_time().clone();
+                    let time = advance(&self.now, &delay);
+                    if unit.inst_annotations(inst).is_inertial() {
+                        self.schedule_inertial((index, inst), time, target, value);
+                    } else {
+                        self.schedule(time, target, value, Some((index, inst)));
+                    }
+                }
+                Opcode::Reg => self.eval_reg(index, inst, &values)?,
+                _ => {
+                    if let Some(value) = self.compute(index, inst, &values)? {
+                        values.insert(unit.inst_result(inst), value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate a register, scheduling a change of its signal if it triggers.
+    fn eval_reg(
+        &mut self,
+        index: usize,
+        inst: Inst,
+        values: &HashMap<Value, crate::Value>,
+    ) -> Result<(), String> {
+        let unit = self.instances[index].unit;
+        let data = &unit[inst];
+        let ctrl = data.reg_controls();
+        let triggers: Vec<_> = data.triggers().collect();
+
+        // Compare the triggers, clear, and preset against their values during
+        // the last evaluation to detect edges.
+        let mut modes: Vec<_> = triggers.iter().map(|t| (t.mode, t.trigger)).collect();
+        modes.extend(ctrl.clear);
+        modes.extend(ctrl.preset);
+        let levels: Vec<_> = modes.iter().map(|(_, v)| values[v].clone()).collect();
+        let prev = self.instances[index]
+            .triggers
+            .insert(inst, levels.clone())
+            .unwrap_or_else(|| levels.clone());
+        let fired: Vec<_> = modes
+            .iter()
+            .zip(prev.iter().zip(&levels))
+            .map(|(&(mode, _), (prev, now))| triggered(mode, prev, now))
+            .collect();
+
+        let ty = unit.value_type(data.args()[0]);
+        let ty = ty.unwrap_signal();
+        let num = triggers.len();
+        let value = if ctrl.clear.is_some() && fired[num] {
+            Some(crate::Value::zero(ty))
+        } else if ctrl.preset.is_some() && fired[num + ctrl.clear.is_some() as usize] {
+            if !ty.is_int() {
+                return Err(format!("cannot preset {}", inst.dump(&unit)));
+            }
+            Some(IntValue::all_ones(ty.unwrap_int()).into())
+        } else if ctrl.enable.map(|v| values[&v].is_one()).unwrap_or(true) {
+            triggers
+                .iter()
+                .zip(&fired)
+                .find(|(t, &fired)| fired && t.gate.map(|g| values[&g].is_one()).unwrap_or(true))
+                .map(|(t, _)| values[&t.data].clone())
+        } else {
+            None
+        };
+        if let Some(value) = value {
+            let target = self.instances[index].signal(data.args()[0])?;
+            let time = advance(&self.now, &TimeValue::zero());
+            self.schedule(time, target, value, Some((index, inst)));
+        }
+        Ok(())
+    }
+
+    /// Compute the value of a side-effect free instruction.
+    fn compute(
+        &self,
+        index: usize,
+        inst: Inst,
+        values: &HashMap<Value, crate::Value>,
+    ) -> Result<Option<crate::Value>, String> {
+        let unit = self.instances[index].unit;
+        let data = &unit[inst];
+        let args: Vec<_> = data.args().iter().map(|a| values.get(a)).collect();
+        let arg = |i: usize| -> &crate::Value { args[i].unwrap() };
+        let unsupported = || Err(format!("{} cannot be simulated", inst.dump(&unit)));
+        Ok(Some(match data.opcode() {
+            Opcode::Halt => return Ok(None),
+            Opcode::ConstInt | Opcode::ConstTime | Opcode::ConstEnum => {
+                unit.get_const(unit.inst_result(inst)).unwrap()
+            }
+            Opcode::Alias => arg(0).clone(),
+            Opcode::ArrayUniform => ArrayValue::new_uniform(data.imms()[0], arg(0).clone()).into(),
+            Opcode::Array => {
+                ArrayValue::new((0..args.len()).map(|i| arg(i).clone()).collect()).into()
+            }
+            Opcode::Struct => {
+                StructValue::new((0..args.len()).map(|i| arg(i).clone()).collect()).into()
+            }
+            Opcode::Not | Opcode::Neg | Opcode::Popcount | Opcode::Clz | Opcode::Ctz => {
+                match arg(0) {
+                    crate::Value::Int(a) => IntValue::unary_op(data.opcode(), a).into(),
+                    _ => return unsupported(),
+                }
+            }
+            Opcode::Eq | Opcode::Neq => {
+                let equal = arg(0) == arg(1);
+                IntValue::from_usize(1, (equal == (data.opcode() == Opcode::Eq)) as usize).into()
+            }
+            op => match (op, args.as_slice()) {
+                (Opcode::Prb, _) => self.read(&self.instances[index].signal(data.args()[0])?),
+                (Opcode::Shl, _) | (Opcode::Shr, _) => match (arg(0), arg(1), arg(2)) {
+                    (
+                        crate::Value::Int(base),
+                        crate::Value::Int(hidden),
+                        crate::Value::Int(amount),
+                    ) => shift(op == Opcode::Shl, base, hidden, amount).into(),
+                    _ => return unsupported(),
+                },
+                (Opcode::Mux, _) => {
+                    let sel = arg(1).unwrap_int().to_usize();
+                    let choices = arg(0).unwrap_array();
+                    choices.extract_field(min(sel, choices.0.len() - 1))
+                }
+                (Opcode::ExtField, _) | (Opcode::ExtSlice, _) => {
+                    let proj = match op {
+                        Opcode::ExtField => Proj::Field(data.imms()[0]),
+                        _ => Proj::Slice(data.imms()[0], data.imms()[1]),
+                    };
+                    extract(arg(0), proj)
+                }
+                (Opcode::InsField, _) | (Opcode::InsSlice, _) => {
+                    let proj = match op {
+                        Opcode::InsField => Proj::Field(data.imms()[0]),
+                        _ => Proj::Slice(data.imms()[0], data.imms()[1]),
+                    };
+                    let mut value = arg(0).clone();
+                    insert(&mut value, &[proj], arg(1).clone());
+                    value
+                }
+                (_, [Some(crate::Value::Int(a)), Some(crate::Value::Int(b))])
+                    if op.is_overflow() =>
+                {
+                    let (v, flag) = IntValue::try_overflow_op(op, a, b).unwrap();
+                    StructValue::new(vec![v.into(), flag.into()]).into()
+                }
+                (_, [Some(crate::Value::Int(a)), Some(crate::Value::Int(b))]) => {
+                    match IntValue::try_binary_op(op, a, b)
+                        .or_else(|| IntValue::try_compare_op(op, a, b))
+                    {
+                        Some(v) => v.into(),
+                        None => return unsupported(),
+                    }
+                }
+                (_, [Some(crate::Value::Time(a)), Some(crate::Value::Time(b))]) => {
+                    match TimeValue::try_compare_op(op, a, b) {
+                        Some(v) => v.into(),
+                        None => return unsupported(),
+                    }
+                }
+                (_, [Some(crate::Value::Enum(a)), Some(crate::Value::Enum(b))]) => {
+                    match EnumValue::try_compare_op(op, a, b) {
+                        Some(v) => v.into(),
+                        None => return unsupported(),
+                    }
+                }
+                _ => return unsupported(),
+            },
+        }))
+    }
+
+    /// Read the current value of a signal.
+    fn read(&self, signal: &SignalRef) -> crate::Value {
+        signal
+            .path
+            .iter()
+            .fold(self.signals[signal.id].value.clone(), |value, &proj| {
+                extract(&value, proj)
+            })
+    }
+
+    /// Schedule a change of a signal by a driver.
+    fn schedule(
+        &mut self,
+        time: TimeValue,
+        signal: SignalRef,
+        value: crate::Value,
+        driver: Driver,
+    ) {
+        self.queue
+            .entry(time)
+            .or_default()
+            .push((signal, value, driver));
+    }
+
+    /// Schedule a change of a signal by an inertial drive.
+    ///
+    /// The pending changes of the same drive to a different value are
+    /// cancelled, such that pulses shorter than the delay are suppressed.
+    fn schedule_inertial(
+        &mut self,
+        driver: (usize, Inst),
+        time: TimeValue,
+        signal: SignalRef,
+        value: crate::Value,
+    ) {
+        for events in self.queue.values_mut() {
+            events.retain(|(_, v, d)| *d != Some(driver) || *v == value);
+        }
+        self.queue.retain(|_, events| !events.is_empty());
+        self.queue
+            .entry(time)
+            .or_default()
+            .push((signal, value, Some(driver)));
+    }
+
+    /// Record the value a driver drives onto a signal with a resolution, and
+    /// compute the resulting value of the signal.
+    ///
+    /// The bits a driver has not driven yet do not affect the result, i.e. are
+    /// zero for `wired_or` and one for `wired_and`.
+    fn resolve(&mut self, signal: &SignalRef, value: crate::Value, driver: Driver) -> crate::Value {
+        let sig = &mut self.signals[signal.id];
+        let width = sig.value.unwrap_int().width;
+        let (op, neutral) = match sig.resolution {
+            Some(Resolution::WiredOr) => (Opcode::Or, IntValue::zero(width)),
+            _ => (Opcode::And, IntValue::all_ones(width)),
+        };
+        let index = match sig.drivers.iter().position(|(d, _)| *d == driver) {
+            Some(index) => index,
+            None => {
+                sig.drivers.push((driver, neutral));
+                sig.drivers.len() - 1
+            }
+        };
+        let mut driven = sig.drivers[index].1.clone().into();
+        insert(&mut driven, &signal.path, value);
+        sig.drivers[index].1 = driven.unwrap_int().clone();
+        let mut values = sig.drivers.iter().map(|(_, value)| value);
+        let first = values.next().unwrap().clone();
+        values
+            .fold(first, |acc, value| {
+                IntValue::try_binary_op(op, &acc, value).unwrap()
+            })
+            .into()
+    }
+
+    /// Apply a set of signal changes, returning whether any signal changed.
+    fn apply(&mut self, events: Vec<Event>) -> bool {
+        let mut changed = vec![];
+        for (signal, value, driver) in events {
+            let new = match self.signals[signal.id].resolution {
+                Some(_) => self.resolve(&signal, value, driver),
+                None => {
+                    let mut new = self.signals[signal.id].value.clone();
+                    insert(&mut new, &signal.path, value);
+                    new
+                }
+            };
+            if new != self.signals[signal.id].value {
+                self.signals[signal.id].value = new;
+                changed.push(signal.id);
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        for &id in &changed {
+            self.trace.changes.push(Stimulus {
+                signal: self.signals[id].name.clone(),
+                time: self.now.clone(),
+                value: self.signals[id].value.clone(),
+            });
+        }
+        !changed.is_empty()
+    }
+}
+
+impl Instance<'_> {
+    /// Find the signal a value refers to.
+    fn signal(&self, value: Value) -> Result<SignalRef, String> {
+        self.signals.get(&value).cloned().ok_or_else(|| {
+            format!(
+                "{} in {} is not a signal that can be simulated",
+                value.dump(&self.unit),
+                self.unit.name()
+            )
+        })
+    }
+}
+
+/// Find the entity to be simulated.
+///
+/// Without an explicit choice, this is the only entity not instantiated by any
+/// other entity.
+pub(super) fn find_top<'a>(module: &'a Module, top: Option<&str>) -> Result<Unit<'a>, String> {
+    if let Some(top) = top {
+        return match module.lookup(&UnitName::global(top)) {
+            Some(LinkedUnit::Def(id)) if module.unit(id).is_entity() => Ok(module.unit(id)),
+            _ => Err(format!("entity @{} not found", top)),
+        };
+    }
+    let mut instantiated = HashSet::new();
+    for unit in module.entities() {
+        for inst in unit.all_insts() {
+            if let Some(ext) = unit[inst].get_ext_unit() {
+                instantiated.insert(unit[ext].name.clone());
+            }
+        }
+    }
+    let tops: Vec<_> = module
+        .entities()
+        .filter(|u| !instantiated.contains(u.name()))
+        .collect();
+    match tops.as_slice() {
+        [top] => Ok(*top),
+        [] => Err("no entity to simulate".to_string()),
+        _ => Err(format!(
+            "multiple candidates for the top entity ({}); select one",
+            tops.iter()
+                .map(|u| u.name().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Compute the time at which a change with a delay takes effect.
+///
+/// Zero delays take effect in the next delta step.
+fn advance(now: &TimeValue, delay: &TimeValue) -> TimeValue {
+    if !delay.time().is_zero() {
+        TimeValue::new(now.time() + delay.time(), delay.delta(), delay.epsilon())
+    } else if delay.delta() > 0 {
+        TimeValue::new(
+            now.time().clone(),
+            now.delta() + delay.delta(),
+            delay.epsilon(),
+        )
+    } else if delay.epsilon() > 0 {
+        TimeValue::new(
+            now.time().clone(),
+            now.delta(),
+            now.epsilon() + delay.epsilon(),
+        )
+    } else {
+        TimeValue::new(now.time().clone(), now.delta() + 1, 0)
+    }
+}
+
+/// Check whether a register trigger fires.
+fn triggered(mode: RegMode, prev: &crate::Value, now: &crate::Value) -> bool {
+    match mode {
+        RegMode::Low => now.is_zero(),
+        RegMode::High => now.is_one(),
+        RegMode::Rise => prev.is_zero() && now.is_one(),
+        RegMode::Fall => prev.is_one() && now.is_zero(),
+        RegMode::Both => prev != now,
+    }
+}
+
+/// Compute a shift, with the same semantics as constant folding.
+fn shift(left: bool, base: &IntValue, hidden: &IntValue, amount: &IntValue) -> IntValue {
+    let (bw, hw) = (base.width, hidden.width);
+    let amount = if amount.value > hw.into() {
+        hw
+    } else {
+        amount.to_usize()
+    };
+    let mut full = IntValue::zero(bw + hw);
+    if left {
+        full.insert_slice(0, hw, hidden);
+        full.insert_slice(hw, bw, base);
+        full.extract_slice(hw - amount, bw)
+    } else {
+        full.insert_slice(0, bw, base);
+        full.insert_slice(bw, hw, hidden);
+        full.extract_slice(amount, bw)
+    }
+}
+
+/// Extract a part of a value.
+fn extract(value: &crate::Value, proj: Proj) -> crate::Value {
+    match (value, proj) {
+        (crate::Value::Int(v), Proj::Slice(off, len)) => v.extract_slice(off, len).into(),
+        (crate::Value::Array(v), Proj::Slice(off, len)) => v.extract_slice(off, len).into(),
+        (crate::Value::Array(v), Proj::Field(idx)) => v.extract_field(idx),
+        (crate::Value::Struct(v), Proj::Field(idx)) => v.extract_field(idx),
+        _ => panic!("cannot extract {:?} from {}", proj, value),
+    }
+}
+
+/// Replace a part of a value.
+fn insert(value: &mut crate::Value, path: &[Proj], new: crate::Value) {
+    let (proj, rest) = match path.split_first() {
+        Some(x) => x,
+        None => {
+            *value = new;
+            return;
+        }
+    };
+    let mut part = extract(value, *proj);
+    insert(&mut part, rest, new);
+    match (value, *proj, part) {
+        (crate::Value::Int(v), Proj::Slice(off, len), crate::Value::Int(part)) => {
+            v.insert_slice(off, len, &part)
+        }
+        (crate::Value::Array(v), Proj::Slice(off, len), crate::Value::Array(part)) => {
+            v.insert_slice(off, len, &part)
+        }
+        (crate::Value::Array(v), Proj::Field(idx), part) => v.insert_field(idx, part),
+        (crate::Value::Struct(v), Proj::Field(idx), part) => v.insert_field(idx, part),
+        (value, proj, _) => panic!("cannot insert {:?} into {}", proj, value),
+    }
+}
+
+/// Get the name of a value, or a placeholder if it has none.
+pub(super) fn value_name(unit: Unit, value: Value) -> String {
+    match unit.get_name(value) {
+        Some(name) => name.to_string(),
+        None => format!("{}", value),
+    }
+}
+
+/// Prefix a name with the path of its instance.
+fn qualify(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// Strip the sigil from a unit name.
+fn sanitize(name: &str) -> String {
+    name.trim_start_matches(['@', '%']).to_string()
+}
@@ -0,0 +1,299 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Simulation
+//!
+//! This module implements the simulation of structural designs, driven by a
+//! set of stimuli that describe how the ports of the top-level entity change
+//! over time. This allows simple testbenches to be expressed without writing
+//! LLHD processes. The state of a simulation may be captured in a `Snapshot`
+//! and restored later, to checkpoint long simulations or inject state.
+//! Simulating a design before and after a pass pipeline with `Differential`
+//! catches passes which change its behaviour.
+
+mod differential;
+mod engine;
+mod stimulus;
+
+pub use self::differential::{Differential, Mismatch};
+pub use self::engine::{Engine, Snapshot};
+pub use self::stimulus::*;
+
+use crate::{ir::Module, value::TimeValue};
+
+/// Simulate a module under a set of stimuli.
+///
+/// The top-level entity is given by `stimuli.top`, or otherwise is the only
+/// entity not instantiated by another one. The simulation ends at
+/// `stimuli.until`, or once no more signal changes are pending.
+pub fn run(module: &Module, stimuli: Stimuli) -> Result<Trace, String> {
+    Engine::new(module, stimuli.top.as_deref())?.run(stimuli)
+}
+
+/// The changes of the signals observed during a simulation.
+///
+/// Signals in the top-level entity are named as in the IR, and signals in
+/// nested instances are prefixed with the names of the instantiated entities,
+/// e.g. `adder.sum`, and the index of the copy for replicated instances, e.g.
+/// `lane[3].sum`.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    /// The changes in the order they occurred, starting with the initial
+    /// value of every signal.
+    pub changes: Vec<Stimulus>,
+}
+
+impl Trace {
+    /// Get the value of a signal at a point in time.
+    pub fn value_at(&self, signal: &str, time: &TimeValue) -> Option<&crate::Value> {
+        self.changes
+            .iter()
+            .take_while(|c| c.time <= *time)
+            .filter(|c| c.signal == signal)
+            .last()
+            .map(|c| &c.value)
+    }
+
+    /// Get the value of a signal at the end of the simulation.
+    pub fn final_value(&self, signal: &str) -> Option<&crate::Value> {
+        self.changes
+            .iter()
+            .rev()
+            .find(|c| c.signal == signal)
+            .map(|c| &c.value)
+    }
+}
+
+impl From<Trace> for Stimuli {
+    /// Use the changes in a trace as stimuli, for example to replay the
+    /// inputs recorded by another simulator. Only the changes of the signals
+    /// which the design does not drive itself should be retained.
+    fn from(trace: Trace) -> Self {
+        Stimuli {
+            changes: trace.changes,
+            ..Default::default()
+        }
+    }
+}
+
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{} %{} {}", change.time, change.signal, change.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembly::parse_time, value::IntValue};
+
+    #[test]
+    fn counter() {
+        let module = crate::assembly::parse_module(
+            "
+            entity @inc (i8$ %a) -> (i8$ %b) {
+                %ap = prb i8$ %a
+                %one = const i8 1
+                %sum = add i8 %ap, %one
+                %t = const time 0s 1e
+                drv i8$ %b, %sum, %t
+            }
+            entity @counter (i1$ %clk, i1$ %rst) -> (i8$ %q) {
+                %zero = const i8 0
+                %next = sig i8 %zero
+                inst @inc (i8$ %q) -> (i8$ %next)
+                %clkp = prb i1$ %clk
+                %rstp = prb i1$ %rst
+                %nextp = prb i8$ %next
+                reg i8$ %q clear high %rstp, [%nextp, rise %clkp]
+            }
+            ",
+        )
+        .unwrap();
+        let stimuli = parse_stimuli(
+            "
+            clock %clk 5ns
+            0s   %rst i1 1
+            12ns %rst i1 0
+            until 100ns
+            ",
+        )
+        .unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let q = |t| trace.value_at("q", &parse_time(t).unwrap()).cloned();
+        assert_eq!(q("10ns"), Some(IntValue::from_usize(8, 0).into()));
+        assert_eq!(q("20ns"), Some(IntValue::from_usize(8, 1).into()));
+        assert_eq!(q("35ns"), Some(IntValue::from_usize(8, 2).into()));
+        assert_eq!(
+            trace.final_value("inc.b"),
+            None,
+            "ports of instances are not separate signals"
+        );
+        assert_eq!(
+            trace.final_value("q"),
+            Some(&IntValue::from_usize(8, 9).into())
+        );
+    }
+
+    #[test]
+    fn inertial_and_transport_delays() {
+        let module = crate::assembly::parse_module(
+            "
+            entity @top (i1$ %a) -> (i1$ %t, i1$ %i, i1$ %d) {
+                %ap = prb i1$ %a
+                %delay = const time 5ns
+                drv i1$ %t, %ap, %delay
+                drv i1$ %i, %ap, %delay #inertial
+                del i1$ %d, %a, %delay
+            }
+            ",
+        )
+        .unwrap();
+        let stimuli =
+            parse_stimuli("10ns %a i1 1\n12ns %a i1 0\n20ns %a i1 1\nuntil 50ns").unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let at = |s, t| trace.value_at(s, &parse_time(t).unwrap()).cloned();
+        let one = Some(IntValue::from_usize(1, 1).into());
+        let zero = Some(IntValue::from_usize(1, 0).into());
+        assert_eq!(at("t", "16ns"), one);
+        assert_eq!(at("d", "16ns"), one);
+        assert_eq!(at("i", "16ns"), zero, "pulse shorter than delay");
+        assert_eq!(at("t", "18ns"), zero);
+        assert_eq!(at("i", "26ns"), one);
+    }
+
+    #[test]
+    fn resolved_drivers() {
+        let module = crate::assembly::parse_module(
+            "
+            entity @top (i4$ %a, i4$ %b) -> () {
+                %ap = prb i4$ %a
+                %bp = prb i4$ %b
+                %zero = const i4 0
+                %delay = const time 0s 1e
+                %or = sig i4 %zero resolve wired_or
+                %and = sig i4 %zero resolve wired_and
+                drv i4$ %or, %ap, %delay
+                drv i4$ %or, %bp, %delay
+                drv i4$ %and, %ap, %delay
+                drv i4$ %and, %bp, %delay
+            }
+            ",
+        )
+        .unwrap();
+        let stimuli = parse_stimuli("10ns %a i4 3\n20ns %b i4 6\nuntil 50ns").unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let at = |s, t| trace.value_at(s, &parse_time(t).unwrap()).cloned();
+        let int = |v| Some(IntValue::from_usize(4, v).into());
+        assert_eq!(at("or", "15ns"), int(3));
+        assert_eq!(at("and", "15ns"), int(0));
+        assert_eq!(at("or", "25ns"), int(7));
+        assert_eq!(at("and", "25ns"), int(2));
+    }
+
+    #[test]
+    fn replicated_instances() {
+        let module = crate::assembly::parse_module(
+            "
+            entity @lane (i4$ %a, i4$ %b) -> (i4$ %y) {
+                %ap = prb i4$ %a
+                %bp = prb i4$ %b
+                %x = xor i4 %ap, %bp
+                %delay = const time 0s 1e
+                drv i4$ %y, %x, %delay
+            }
+            entity @top (i4$ %a) -> () {
+                %zero = const i4 0
+                %one = const i4 1
+                %two = const i4 2
+                %bv = [i4 %one, %two]
+                %yv = [2 x i4 %zero]
+                %b = sig [2 x i4] %bv
+                %y = sig [2 x i4] %yv
+                inst @lane (i4$ %a, i4$ %b) -> (i4$ %y) replicate 2
+            }
+            ",
+        )
+        .unwrap();
+        let stimuli = parse_stimuli("10ns %a i4 3\nuntil 50ns").unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let at = |s, t| {
+            trace
+                .value_at(s, &parse_time(t).unwrap())
+                .map(|v| v.to_string())
+        };
+        assert_eq!(at("y", "5ns").as_deref(), Some("[i4 1, i4 2]"));
+        assert_eq!(at("y", "15ns").as_deref(), Some("[i4 2, i4 1]"));
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let module = crate::assembly::parse_module(
+            "
+            entity @counter (i1$ %clk, i1$ %rst) -> (i8$ %q) {
+                %zero = const i8 0
+                %one = const i8 1
+                %next = sig i8 %zero
+                %qp = prb i8$ %q
+                %sum = add i8 %qp, %one
+                %t = const time 0s 1e
+                drv i8$ %next, %sum, %t
+                %clkp = prb i1$ %clk
+                %rstp = prb i1$ %rst
+                %nextp = prb i8$ %next
+                reg i8$ %q clear high %rstp, [%nextp, rise %clkp]
+            }
+            ",
+        )
+        .unwrap();
+        let first = "clock %clk 5ns\n0s %rst i1 1\n12ns %rst i1 0\nuntil 50ns";
+        let second = "clock %clk 5ns\nuntil 100ns";
+        let q = |v| Some(IntValue::from_usize(8, v).into());
+
+        // Simulate the first half and checkpoint the state.
+        let mut engine = Engine::new(&module, None).unwrap();
+        let trace = engine.simulate(parse_stimuli(first).unwrap()).unwrap();
+        assert_eq!(trace.final_value("q").cloned(), q(4));
+        let snapshot = engine.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot, engine.snapshot());
+        assert_eq!(snapshot.value("q").cloned(), q(4));
+
+        // Continuing in the same engine and restarting from the checkpoint
+        // yield the same result as an uninterrupted simulation.
+        let trace = engine.simulate(parse_stimuli(second).unwrap()).unwrap();
+        assert_eq!(trace.final_value("q").cloned(), q(9));
+        let mut engine = Engine::new(&module, None).unwrap();
+        engine.restore(&snapshot).unwrap();
+        assert_eq!(engine.now(), &snapshot.time);
+        let trace = engine.simulate(parse_stimuli(second).unwrap()).unwrap();
+        assert_eq!(trace.final_value("q").cloned(), q(9));
+
+        // Injected state takes effect when the simulation continues.
+        let mut injected = snapshot.clone();
+        injected
+            .set_value("q", IntValue::from_usize(8, 100).into())
+            .unwrap();
+        assert!(injected
+            .set_value("q", IntValue::from_usize(4, 1).into())
+            .is_err());
+        assert!(injected
+            .set_value("x", IntValue::from_usize(8, 1).into())
+            .is_err());
+        let mut engine = Engine::new(&module, None).unwrap();
+        engine.restore(&injected).unwrap();
+        let trace = engine.simulate(parse_stimuli(second).unwrap()).unwrap();
+        assert_eq!(trace.final_value("q").cloned(), q(105));
+        assert!(engine
+            .simulate(parse_stimuli("10ns %rst i1 1").unwrap())
+            .is_err());
+
+        // Snapshots only restore into the same design.
+        let other = crate::assembly::parse_module("entity @top (i1$ %clk) -> () {}").unwrap();
+        let mut engine = Engine::new(&other, None).unwrap();
+        assert!(engine.restore(&snapshot).is_err());
+    }
+}
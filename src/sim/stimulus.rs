@@ -0,0 +1,210 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Stimulus descriptions
+//!
+//! This module implements the description of the values applied to the ports
+//! of a design during simulation, either as a list of changes or as generator
+//! closures, and a small text format to read them from a file.
+
+use crate::{
+    assembly::parse_time,
+    value::{EnumValue, IntValue, TimeValue},
+};
+use num::{BigRational, BigUint, One};
+
+/// A change of a signal to a new value at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stimulus {
+    /// The name of the signal, without the leading `%`.
+    pub signal: String,
+    /// The time at which the change occurs.
+    pub time: TimeValue,
+    /// The new value of the signal.
+    pub value: crate::Value,
+}
+
+/// A closure producing a sequence of changes of a signal.
+///
+/// The closure is called with the number of changes produced so far, and
+/// returns the time and value of the next change, or `None` if there are no
+/// more changes.
+pub type Generator = Box<dyn FnMut(usize) -> Option<(TimeValue, crate::Value)>>;
+
+/// The stimuli applied to a design during simulation.
+#[derive(Default)]
+pub struct Stimuli {
+    /// The name of the top-level entity, without the leading `@`.
+    pub top: Option<String>,
+    /// The time at which the simulation ends.
+    pub until: Option<TimeValue>,
+    /// The individual changes of signals.
+    pub changes: Vec<Stimulus>,
+    /// The generated changes of signals.
+    pub generators: Vec<(String, Generator)>,
+}
+
+impl Stimuli {
+    /// Create an empty set of stimuli.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Change a signal to a value at a point in time.
+    pub fn set(
+        &mut self,
+        signal: impl Into<String>,
+        time: TimeValue,
+        value: impl Into<crate::Value>,
+    ) -> &mut Self {
+        self.changes.push(Stimulus {
+            signal: signal.into(),
+            time,
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Change a signal according to a generator closure.
+    pub fn generate(
+        &mut self,
+        signal: impl Into<String>,
+        generator: impl FnMut(usize) -> Option<(TimeValue, crate::Value)> + 'static,
+    ) -> &mut Self {
+        self.generators.push((signal.into(), Box::new(generator)));
+        self
+    }
+
+    /// Toggle an `i1` signal every `half_period`, starting at zero.
+    pub fn clock(&mut self, signal: impl Into<String>, half_period: TimeValue) -> &mut Self {
+        let step = half_period.time().clone();
+        self.generate(signal, move |i| {
+            let time = TimeValue::new(&step * BigRational::from_integer(i.into()), 0, 0);
+            Some((time, IntValue::from_usize(1, i % 2).into()))
+        })
+    }
+
+    /// Set the time at which the simulation ends.
+    pub fn until(&mut self, time: TimeValue) -> &mut Self {
+        self.until = Some(time);
+        self
+    }
+
+    /// Select the top-level entity to be simulated.
+    pub fn top(&mut self, name: impl Into<String>) -> &mut Self {
+        self.top = Some(name.into());
+        self
+    }
+}
+
+impl std::fmt::Debug for Stimuli {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Stimuli")
+            .field("top", &self.top)
+            .field("until", &self.until)
+            .field("changes", &self.changes)
+            .field(
+                "generators",
+                &self.generators.iter().map(|(s, _)| s).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Parse stimuli.
+///
+/// Each line of the `input` contains one of the following, where `#` starts a
+/// comment that extends to the end of the line:
+///
+/// - `<time> %<signal> <value>` changes a signal at a point in time, where the
+///   value is given as an integer `iN <int>` or enum `nN <int>` constant.
+/// - `clock %<signal> <half-period>` toggles an `i1` signal periodically.
+/// - `until <time>` ends the simulation at a point in time.
+/// - `top @<name>` selects the entity to be simulated.
+///
+/// For example:
+///
+/// ```text
+/// top @counter
+/// clock %clk 5ns
+/// 0s   %rst i1 1
+/// 12ns %rst i1 0
+/// until 100ns
+/// ```
+pub fn parse_stimuli(input: impl AsRef<str>) -> Result<Stimuli, String> {
+    let mut stimuli = Stimuli::new();
+    for (index, line) in input.as_ref().lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let words: Vec<_> = line.split_whitespace().collect();
+        let err = |msg: String| format!("line {}: {}", index + 1, msg);
+        match words.as_slice() {
+            [] => (),
+            ["top", name] => {
+                stimuli.top(strip_sigil(name, '@').map_err(err)?);
+            }
+            ["until", time] => {
+                stimuli.until(parse_time(time).map_err(err)?);
+            }
+            ["clock", signal, time] => {
+                let signal = strip_sigil(signal, '%').map_err(err)?;
+                stimuli.clock(signal, parse_time(time).map_err(err)?);
+            }
+            [time, signal, ty, value] => {
+                let time = parse_time(time).map_err(err)?;
+                let signal = strip_sigil(signal, '%').map_err(err)?;
+                let value = parse_value(ty, value).map_err(err)?;
+                stimuli.set(signal, time, value);
+            }
+            _ => return Err(err(format!("cannot parse `{}`", line.trim()))),
+        }
+    }
+    Ok(stimuli)
+}
+
+/// Strip the `%` or `@` from a name.
+fn strip_sigil(name: &str, sigil: char) -> Result<&str, String> {
+    match name.strip_prefix(sigil) {
+        Some(name) if !name.is_empty() => Ok(name),
+        _ => Err(format!("expected `{}name`, found `{}`", sigil, name)),
+    }
+}
+
+/// Parse an integer or enum constant.
+fn parse_value(ty: &str, value: &str) -> Result<crate::Value, String> {
+    let invalid = || format!("invalid value `{} {}`", ty, value);
+    let size = ty[1..].parse::<usize>().map_err(|_| invalid())?;
+    let value = value.parse::<BigUint>().map_err(|_| invalid())?;
+    match ty.chars().next() {
+        Some('i') if value < BigUint::one() << size => {
+            Ok(IntValue::from_unsigned(size, value).into())
+        }
+        Some('n') => {
+            let value = value.to_string().parse().map_err(|_| invalid())?;
+            EnumValue::try_new(size, value)
+                .map(Into::into)
+                .ok_or_else(invalid)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let stimuli = parse_stimuli(
+            "top @foo\n# reset\n0s %rst i1 1\n10ns %rst i1 0  # release\nclock %clk 5ns\nuntil 1us\n",
+        )
+        .unwrap();
+        assert_eq!(stimuli.top.as_deref(), Some("foo"));
+        assert_eq!(stimuli.changes.len(), 2);
+        assert_eq!(stimuli.changes[1].signal, "rst");
+        assert_eq!(stimuli.changes[1].time, parse_time("10ns").unwrap());
+        assert_eq!(stimuli.changes[1].value, IntValue::from_usize(1, 0).into());
+        assert_eq!(stimuli.generators.len(), 1);
+        assert_eq!(stimuli.until, Some(parse_time("1us").unwrap()));
+        assert!(parse_stimuli("0s %a i1 2").is_err());
+        assert!(parse_stimuli("0s a i1 0").is_err());
+    }
+}
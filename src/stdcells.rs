@@ -0,0 +1,349 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Generators for common hardware units.
+//!
+//! This module provides functions that build frequently used entities, such as
+//! synchronizers, FIFOs, arbiters, and gray-code counters, parameterized by
+//! their width and depth. All generated units use the same IR idioms: state is
+//! held in signals updated by `reg` instructions on the rising edge of the
+//! `clk` input, resets are asynchronous and active-high on the `rst` input, and
+//! combinational outputs are driven with a delay of one epsilon step.
+//!
+//! ```
+//! use llhd::{ir::{Module, UnitName}, stdcells};
+//!
+//! let mut module = Module::new();
+//! module.add_unit(stdcells::synchronizer(UnitName::global("sync"), 8, 2));
+//! module.add_unit(stdcells::fifo(UnitName::global("fifo"), 8, 16));
+//! ```
+
+use crate::{
+    ir::prelude::*,
+    ty::{array_ty, int_ty, signal_ty, Type},
+    value::{IntValue, TimeValue},
+};
+use num::{BigRational, Zero};
+
+/// Create a synchronizer.
+///
+/// The entity has the ports `(i1$ %clk, iN$ %d) -> (iN$ %q)`, where `N` is the
+/// `width`. The input is passed through a chain of `stages` registers, the last
+/// of which is the output. Panics if `width` or `stages` is zero.
+pub fn synchronizer(name: UnitName, width: usize, stages: usize) -> UnitData {
+    assert!(width > 0, "synchronizer must be at least 1 bit wide");
+    assert!(stages > 0, "synchronizer must have at least 1 stage");
+    let ty = int_ty(width);
+    let mut data = entity(
+        name,
+        &[("clk", int_ty(1)), ("d", ty.clone())],
+        &[("q", ty.clone())],
+    );
+    {
+        let mut unit = UnitBuilder::new_anonymous(&mut data);
+        let clk = unit.input_arg(0);
+        let clk = unit.ins().prb(clk);
+        let mut prev = unit.input_arg(1);
+        for i in 0..stages {
+            let target = if i + 1 == stages {
+                unit.output_arg(0)
+            } else {
+                state(&mut unit, &format!("s{}", i), &ty)
+            };
+            let value = unit.ins().prb(prev);
+            clocked(&mut unit, target, value, clk, RegControls::default());
+            prev = target;
+        }
+    }
+    data
+}
+
+/// Create a gray-code counter.
+///
+/// The entity has the ports `(i1$ %clk, i1$ %rst, i1$ %en) -> (iN$ %q)`, where
+/// `N` is the `width`. A binary count is incremented on every rising clock
+/// edge while `en` is high, and `q` holds its gray-code encoding. Both are
+/// cleared while `rst` is high. Panics if `width` is zero.
+pub fn gray_counter(name: UnitName, width: usize) -> UnitData {
+    assert!(width > 0, "gray counter must be at least 1 bit wide");
+    let ty = int_ty(width);
+    let mut data = entity(
+        name,
+        &[("clk", int_ty(1)), ("rst", int_ty(1)), ("en", int_ty(1))],
+        &[("q", ty.clone())],
+    );
+    {
+        let mut unit = UnitBuilder::new_anonymous(&mut data);
+        let ports = probe_inputs(&mut unit);
+        let (clk, rst, en) = (ports[0], ports[1], ports[2]);
+        let ctrl = RegControls {
+            enable: Some(en),
+            clear: Some((RegMode::High, rst)),
+            ..Default::default()
+        };
+
+        let bin = state(&mut unit, "bin", &ty);
+        let binp = unit.ins().prb(bin);
+        let one = unit.ins().const_int(IntValue::from_usize(width, 1));
+        let next = unit.ins().name("next").add(binp, one);
+        clocked(&mut unit, bin, next, clk, ctrl);
+
+        let zero = unit.ins().const_zero(&ty);
+        let half = unit.ins().shr(next, zero, one);
+        let gray = unit.ins().name("gray").xor(next, half);
+        let q = unit.output_arg(0);
+        clocked(&mut unit, q, gray, clk, ctrl);
+    }
+    data
+}
+
+/// Create a fixed-priority arbiter.
+///
+/// The entity has the ports `(iN$ %req) -> (iN$ %gnt)`, where `N` is the number
+/// of requesters. The grant is combinational and one-hot: of all requests that
+/// are high, the one with the lowest index is granted. Panics if `n` is zero.
+pub fn arbiter(name: UnitName, n: usize) -> UnitData {
+    assert!(n > 0, "arbiter must have at least 1 requester");
+    let mut data = entity(name, &[("req", int_ty(n))], &[("gnt", int_ty(n))]);
+    {
+        let mut unit = UnitBuilder::new_anonymous(&mut data);
+        let req = probe_inputs(&mut unit)[0];
+        let lowest = unit.ins().neg(req);
+        let gnt = unit.ins().and(req, lowest);
+        let delay = epsilon(&mut unit);
+        let output = unit.output_arg(0);
+        unit.ins().drv(output, gnt, delay);
+    }
+    data
+}
+
+/// Create a synchronous FIFO.
+///
+/// The entity has the ports
+/// `(i1$ %clk, i1$ %rst, i1$ %wr_en, iN$ %din, i1$ %rd_en) -> (iN$ %dout, i1$
+/// %empty, i1$ %full)`, where `N` is the `width`. On a rising clock edge, `din`
+/// is pushed if `wr_en` is high and the FIFO is not full, and the head is
+/// popped if `rd_en` is high and the FIFO is not empty. `dout` always shows
+/// the head. The `rst` input empties the FIFO. Panics if `width` is zero or
+/// `depth` is not a power of two greater than one.
+pub fn fifo(name: UnitName, width: usize, depth: usize) -> UnitData {
+    assert!(width > 0, "FIFO must be at least 1 bit wide");
+    assert!(
+        depth > 1 && depth.is_power_of_two(),
+        "FIFO depth must be a power of two greater than 1"
+    );
+    let ty = int_ty(width);
+    let addr_bits = depth.trailing_zeros() as usize;
+    let ptr_ty = int_ty(addr_bits + 1);
+    let mut data = entity(
+        name,
+        &[
+            ("clk", int_ty(1)),
+            ("rst", int_ty(1)),
+            ("wr_en", int_ty(1)),
+            ("din", ty.clone()),
+            ("rd_en", int_ty(1)),
+        ],
+        &[
+            ("dout", ty.clone()),
+            ("empty", int_ty(1)),
+            ("full", int_ty(1)),
+        ],
+    );
+    {
+        let mut unit = UnitBuilder::new_anonymous(&mut data);
+        let ports = probe_inputs(&mut unit);
+        let (clk, rst, wr_en, din, rd_en) = (ports[0], ports[1], ports[2], ports[3], ports[4]);
+
+        // The pointers carry one bit more than needed to address the memory,
+        // which distinguishes a full from an empty FIFO.
+        let mem = state(&mut unit, "mem", &array_ty(depth, ty.clone()));
+        let wptr = state(&mut unit, "wptr", &ptr_ty);
+        let rptr = state(&mut unit, "rptr", &ptr_ty);
+        let wp = unit.ins().prb(wptr);
+        let rp = unit.ins().prb(rptr);
+        let empty = unit.ins().name("is_empty").eq(wp, rp);
+        let diff = unit.ins().xor(wp, rp);
+        let wrapped = unit
+            .ins()
+            .const_int(IntValue::from_usize(addr_bits + 1, depth));
+        let full = unit.ins().name("is_full").eq(diff, wrapped);
+
+        // Advance the pointers.
+        let not_full = unit.ins().not(full);
+        let do_wr = unit.ins().name("do_wr").and(wr_en, not_full);
+        let not_empty = unit.ins().not(empty);
+        let do_rd = unit.ins().name("do_rd").and(rd_en, not_empty);
+        let one = unit.ins().const_int(IntValue::from_usize(addr_bits + 1, 1));
+        for &(ptr, value, enable) in &[(wptr, wp, do_wr), (rptr, rp, do_rd)] {
+            let next = unit.ins().add(value, one);
+            let ctrl = RegControls {
+                enable: Some(enable),
+                clear: Some((RegMode::High, rst)),
+                ..Default::default()
+            };
+            clocked(&mut unit, ptr, next, clk, ctrl);
+        }
+
+        // Write into the memory element addressed by the write pointer.
+        let waddr = unit.ins().name("waddr").ext_slice(wp, 0, addr_bits);
+        for i in 0..depth {
+            let index = unit.ins().const_int(IntValue::from_usize(addr_bits, i));
+            let selected = unit.ins().eq(waddr, index);
+            let enable = unit.ins().and(do_wr, selected);
+            let element = unit.ins().ext_field(mem, i);
+            let ctrl = RegControls {
+                enable: Some(enable),
+                ..Default::default()
+            };
+            clocked(&mut unit, element, din, clk, ctrl);
+        }
+
+        // Drive the outputs.
+        let raddr = unit.ins().name("raddr").ext_slice(rp, 0, addr_bits);
+        let memp = unit.ins().prb(mem);
+        let head = unit.ins().mux(memp, raddr);
+        let delay = epsilon(&mut unit);
+        for (i, &value) in [head, empty, full].iter().enumerate() {
+            let output = unit.output_arg(i);
+            unit.ins().drv(output, value, delay);
+        }
+    }
+    data
+}
+
+/// Create an entity whose ports are signals with the given names and types.
+fn entity(name: UnitName, inputs: &[(&str, Type)], outputs: &[(&str, Type)]) -> UnitData {
+    let mut sig = Signature::new();
+    for (_, ty) in inputs {
+        sig.add_input(signal_ty(ty.clone()));
+    }
+    for (_, ty) in outputs {
+        sig.add_output(signal_ty(ty.clone()));
+    }
+    let mut data = UnitData::new(UnitKind::Entity, name, sig);
+    let mut unit = UnitBuilder::new_anonymous(&mut data);
+    let args: Vec<_> = unit.input_args().chain(unit.output_args()).collect();
+    for ((name, _), arg) in inputs.iter().chain(outputs).zip(args) {
+        unit.set_name(arg, name.to_string());
+    }
+    data
+}
+
+/// Probe all input ports of an entity.
+fn probe_inputs(unit: &mut UnitBuilder) -> Vec<Value> {
+    let args: Vec<_> = unit.input_args().collect();
+    args.into_iter().map(|arg| unit.ins().prb(arg)).collect()
+}
+
+/// Create a local signal initialized to zero.
+fn state(unit: &mut UnitBuilder, name: &str, ty: &Type) -> Value {
+    let init = unit.ins().const_zero(ty);
+    unit.ins().name(name).sig(init)
+}
+
+/// Store a value into a signal on the rising edge of a clock.
+fn clocked(unit: &mut UnitBuilder, target: Value, data: Value, clk: Value, ctrl: RegControls) {
+    let trigger = RegTrigger {
+        data,
+        mode: RegMode::Rise,
+        trigger: clk,
+        gate: None,
+    };
+    unit.ins().reg_ctrl(target, vec![trigger], ctrl);
+}
+
+/// Create the delay of combinational outputs.
+fn epsilon(unit: &mut UnitBuilder) -> Value {
+    unit.ins()
+        .const_time(TimeValue::new(BigRational::zero(), 0, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::parse_time,
+        sim::{parse_stimuli, run},
+        verifier::Verifier,
+    };
+
+    fn module(data: UnitData) -> Module {
+        let mut module = Module::new();
+        module.add_unit(data);
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&module);
+        verifier.finish().unwrap();
+        module
+    }
+
+    #[test]
+    fn generated_units_verify() {
+        for stages in 1..4 {
+            module(synchronizer(UnitName::global("sync"), 4, stages));
+        }
+        for width in 1..4 {
+            module(gray_counter(UnitName::global("gray"), width));
+            module(arbiter(UnitName::global("arb"), width));
+        }
+        module(fifo(UnitName::global("fifo"), 1, 2));
+        module(fifo(UnitName::global("fifo"), 8, 16));
+    }
+
+    #[test]
+    fn gray_counter_sequence() {
+        let module = module(gray_counter(UnitName::global("gray"), 3));
+        let stimuli = parse_stimuli(
+            "
+            clock %clk 5ns
+            0s   %rst i1 1
+            0s   %en i1 1
+            12ns %rst i1 0
+            until 100ns
+            ",
+        )
+        .unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let q = |t: &str| trace.value_at("q", &parse_time(t).unwrap()).cloned();
+        let expected = [0, 1, 3, 2, 6, 7, 5, 4, 0];
+        for (i, &gray) in expected.iter().enumerate() {
+            let time = format!("{}ns", 10 + i * 10);
+            assert_eq!(
+                q(&time),
+                Some(IntValue::from_usize(3, gray).into()),
+                "at {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn fifo_order() {
+        let module = module(fifo(UnitName::global("fifo"), 8, 2));
+        let stimuli = parse_stimuli(
+            "
+            clock %clk 5ns
+            0s   %rst i1 1
+            12ns %rst i1 0
+            12ns %wr_en i1 1
+            12ns %din i8 10
+            22ns %din i8 20
+            32ns %din i8 30
+            42ns %wr_en i1 0
+            42ns %rd_en i1 1
+            52ns %rd_en i1 0
+            72ns %rd_en i1 1
+            until 100ns
+            ",
+        )
+        .unwrap();
+        let trace = run(&module, stimuli).unwrap();
+        let at = |signal, t: &str| trace.value_at(signal, &parse_time(t).unwrap()).cloned();
+        let int = |width, value| Some(IntValue::from_usize(width, value).into());
+        assert_eq!(at("empty", "10ns"), int(1, 1));
+        assert_eq!(at("full", "40ns"), int(1, 1));
+        assert_eq!(at("dout", "40ns"), int(8, 10));
+        assert_eq!(at("full", "60ns"), int(1, 0));
+        assert_eq!(at("dout", "60ns"), int(8, 20));
+        assert_eq!(at("empty", "90ns"), int(1, 1));
+    }
+}
@@ -127,11 +127,16 @@ impl<I: TableKey, V> PrimaryTable<I, V> {
         I::new(index)
     }
 
-    /// Remove an entry from the table.
+    /// Remove an entry from the table and return it.
     ///
     /// Panics if the entry does not exist.
-    pub fn remove(&mut self, key: I) {
-        self.storage.remove(&key.index()).expect("key not in table");
+    pub fn remove(&mut self, key: I) -> V {
+        self.storage.remove(&key.index()).expect("key not in table")
+    }
+
+    /// Get an entry from the table, if one exists.
+    pub fn get(&self, key: I) -> Option<&V> {
+        self.storage.get(&key.index())
     }
 
     /// Return an iterator over the keys and values in the table.
@@ -308,6 +313,11 @@ impl<I: TableKey, V: Default> PrimaryTable2<I, V> {
         std::mem::replace(&mut self.storage[id as usize], Default::default())
     }
 
+    /// Check whether an entry exists in the table.
+    pub fn contains(&self, key: I) -> bool {
+        key.index() <= u32::MAX as usize && self.used.contains(key.index() as u32)
+    }
+
     /// Get the number of entries for which storage is allocated.
     pub fn capacity(&self) -> usize {
         self.storage.len()
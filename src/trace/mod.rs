@@ -0,0 +1,227 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Waveform traces
+//!
+//! This module implements utilities to work with the traces of signal changes
+//! produced by the simulator, such as comparing the traces of a design before
+//! and after optimization to check that its behavior is preserved, and reading
+//! traces recorded by other simulators from VCD files.
+
+mod vcd;
+
+pub use self::vcd::*;
+
+use crate::{sim::Trace, value::TimeValue, Value};
+use num::BigRational;
+use std::collections::BTreeMap;
+
+/// The first point in time at which a signal differs between two traces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The name of the signal, without the leading `%`.
+    pub signal: String,
+    /// The time at which the signal differs. Delta and epsilon steps are
+    /// always zero.
+    pub time: TimeValue,
+    /// The value of the signal in the first trace, or `None` if the signal
+    /// has no value yet.
+    pub left: Option<Value>,
+    /// The value of the signal in the second trace, or `None` if the signal
+    /// has no value yet.
+    pub right: Option<Value>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = |v: &Option<Value>| match v {
+            Some(v) => v.to_string(),
+            None => "no value".to_string(),
+        };
+        write!(
+            f,
+            "%{} differs at {}: {} vs {}",
+            self.signal,
+            self.time,
+            value(&self.left),
+            value(&self.right)
+        )
+    }
+}
+
+/// Compare two traces and report the first divergence of each signal.
+///
+/// Signals are compared by the value they settle to at the end of each point
+/// in real time, such that traces which only differ in the delta and epsilon
+/// steps at which changes occur, or in the order of changes within the same
+/// point in real time, are considered equal. A signal which only appears in
+/// one of the traces diverges where it first changes. The divergences are
+/// sorted by signal name; an empty result means the traces are equivalent.
+pub fn compare(a: &Trace, b: &Trace) -> Vec<Divergence> {
+    let a = settled_values(a);
+    let b = settled_values(b);
+    let mut signals: Vec<&str> = a.keys().chain(b.keys()).cloned().collect();
+    signals.sort();
+    signals.dedup();
+
+    let empty = vec![];
+    signals
+        .into_iter()
+        .flat_map(|signal| {
+            let a = a.get(signal).unwrap_or(&empty);
+            let b = b.get(signal).unwrap_or(&empty);
+            first_divergence(a, b).map(|(time, left, right)| Divergence {
+                signal: signal.to_string(),
+                time: TimeValue::new(time.clone(), 0, 0),
+                left: left.cloned(),
+                right: right.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// The values a signal settles to at the end of each point in real time.
+type Settled<'a> = Vec<(&'a BigRational, &'a Value)>;
+
+/// Determine the settled values of every signal in a trace.
+fn settled_values(trace: &Trace) -> BTreeMap<&str, Settled<'_>> {
+    let mut signals = BTreeMap::<&str, Settled>::new();
+    for change in &trace.changes {
+        let values = signals.entry(change.signal.as_str()).or_default();
+        match values.last_mut() {
+            Some((time, value)) if *time == change.time.time() => *value = &change.value,
+            _ => values.push((change.time.time(), &change.value)),
+        }
+    }
+    signals
+}
+
+/// Find the first point in real time at which two sequences of settled values
+/// differ.
+fn first_divergence<'a>(
+    a: &[(&'a BigRational, &'a Value)],
+    b: &[(&'a BigRational, &'a Value)],
+) -> Option<(&'a BigRational, Option<&'a Value>, Option<&'a Value>)> {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    let (mut left, mut right) = (None, None);
+    loop {
+        let time = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => std::cmp::min(x.0, y.0),
+            (Some(x), None) => x.0,
+            (None, Some(y)) => y.0,
+            (None, None) => return None,
+        };
+        if let Some(&(_, value)) = a.next_if(|x| x.0 == time) {
+            left = Some(value);
+        }
+        if let Some(&(_, value)) = b.next_if(|y| y.0 == time) {
+            right = Some(value);
+        }
+        if left != right {
+            return Some((time, left, right));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::parse_time,
+        sim::{self, Stimulus},
+        value::IntValue,
+    };
+
+    fn trace(changes: &[(&str, &str, usize)]) -> Trace {
+        Trace {
+            changes: changes
+                .iter()
+                .map(|&(time, signal, value)| Stimulus {
+                    signal: signal.to_string(),
+                    time: parse_time(time).unwrap(),
+                    value: IntValue::from_usize(8, value).into(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn delta_tolerance() {
+        let a = trace(&[
+            ("0s", "a", 0),
+            ("0s", "b", 0),
+            ("1ns", "a", 1),
+            ("1ns 1d", "b", 1),
+            ("2ns", "a", 2),
+        ]);
+        let b = trace(&[
+            ("0s", "b", 0),
+            ("0s 1e", "a", 0),
+            ("1ns", "b", 5),
+            ("1ns 2d", "b", 1),
+            ("1ns 3d", "a", 1),
+            ("2ns 1d", "a", 2),
+        ]);
+        assert_eq!(compare(&a, &b), vec![]);
+    }
+
+    #[test]
+    fn divergence_per_signal() {
+        let a = trace(&[
+            ("0s", "a", 0),
+            ("0s", "b", 0),
+            ("1ns", "a", 1),
+            ("2ns", "b", 2),
+            ("3ns", "a", 3),
+        ]);
+        let b = trace(&[
+            ("0s", "a", 0),
+            ("0s", "b", 0),
+            ("1ns", "a", 1),
+            ("2ns", "b", 3),
+            ("3ns", "c", 1),
+            ("4ns", "a", 3),
+        ]);
+        let divs = compare(&a, &b);
+        assert_eq!(
+            divs.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+            vec![
+                "%a differs at 3ns: i8 3 vs i8 1",
+                "%b differs at 2ns: i8 2 vs i8 3",
+                "%c differs at 3ns: no value vs i8 1",
+            ]
+        );
+        assert_eq!(divs[0].time, parse_time("3ns").unwrap());
+    }
+
+    #[test]
+    fn optimization_preserves_trace() {
+        let input = "
+            entity @top (i8$ %a) -> (i8$ %b) {
+                %ap = prb i8$ %a
+                %one = const i8 1
+                %two = add i8 %one, %one
+                %sum = add i8 %ap, %two
+                %t = const time 0s 1e
+                drv i8$ %b, %sum, %t
+            }
+        ";
+        let stimuli = "
+            0s  %a i8 1
+            2ns %a i8 5
+            until 5ns
+        ";
+        let mut module = crate::assembly::parse_module(input).unwrap();
+        let before = sim::run(&module, sim::parse_stimuli(stimuli).unwrap()).unwrap();
+        crate::pass::run_by_name("cf", &Default::default(), &mut module);
+        crate::pass::run_by_name("dce", &Default::default(), &mut module);
+        let after = sim::run(&module, sim::parse_stimuli(stimuli).unwrap()).unwrap();
+        assert_eq!(compare(&before, &after), vec![]);
+        let expected = trace(&[
+            ("0s", "a", 1),
+            ("0s", "b", 3),
+            ("2ns", "a", 5),
+            ("2ns", "b", 7),
+        ]);
+        assert_eq!(compare(&before, &expected), vec![]);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! VCD import
+//!
+//! This module implements a reader for Value Change Dump files, which maps the
+//! variables in the dump onto the signals of a design, such that waveforms
+//! recorded by other simulators can serve as stimuli or golden reference.
+
+use crate::{
+    ir::Module,
+    sim::{Engine, Stimulus, Trace},
+    ty::{Type, TypeKind},
+    value::{EnumValue, IntValue, TimeUnit, TimeValue},
+};
+use num::{BigRational, BigUint, ToPrimitive};
+use std::collections::HashMap;
+
+/// A variable declared in a VCD file.
+struct Var {
+    /// The identifier code of the variable.
+    id: String,
+    /// The names of the scopes containing the variable, and its own name.
+    path: Vec<String>,
+    /// The number of bits of the variable.
+    size: usize,
+}
+
+/// Read a VCD file and map its variables onto the signals of a design.
+///
+/// The design is elaborated as for simulation, starting at the entity `top`,
+/// or otherwise the only entity not instantiated by another one. Variables are
+/// matched to signals by their hierarchical name below a common scope, such
+/// that `tb.dut.adder.sum` maps to the signal `adder.sum` if `tb.dut`
+/// corresponds to the top entity. The scope is chosen such that the most
+/// variables match a signal; variables that match none are ignored.
+///
+/// Integer and enum signals are supported. Bits that are `x` or `z` in the
+/// dump are read as zero. Times are in seconds unless the file declares a
+/// `$timescale`.
+pub fn read_vcd(
+    input: impl AsRef<str>,
+    module: &Module,
+    top: Option<&str>,
+) -> Result<Trace, String> {
+    let mut tokens = input.as_ref().split_whitespace();
+    let mut next = |what: &str| {
+        tokens
+            .next()
+            .ok_or_else(|| format!("unexpected end of VCD file; expected {}", what))
+    };
+
+    // Parse the header.
+    let mut scale = BigRational::from_integer(1.into());
+    let mut scopes = vec![];
+    let mut vars = vec![];
+    loop {
+        match next("$enddefinitions")? {
+            "$timescale" => {
+                let mut spec = String::new();
+                loop {
+                    match next("$end")? {
+                        "$end" => break,
+                        t => spec.push_str(t),
+                    }
+                }
+                scale = parse_timescale(&spec)?;
+            }
+            "$scope" => {
+                next("scope type")?;
+                scopes.push(next("scope name")?.to_string());
+                expect_end(&mut next)?;
+            }
+            "$upscope" => {
+                scopes.pop();
+                expect_end(&mut next)?;
+            }
+            "$var" => {
+                next("variable type")?;
+                let size = next("variable size")?;
+                let size = size
+                    .parse()
+                    .map_err(|_| format!("invalid variable size `{}`", size))?;
+                let id = next("variable identifier")?.to_string();
+                let mut path = scopes.clone();
+                path.push(next("variable name")?.to_string());
+                // Skip an optional bit range such as `[7:0]`.
+                while next("$end")? != "$end" {}
+                vars.push(Var { id, path, size });
+            }
+            "$enddefinitions" => {
+                expect_end(&mut next)?;
+                break;
+            }
+            t if t.starts_with('$') => while next("$end")? != "$end" {},
+            t => return Err(format!("unexpected `{}` in VCD header", t)),
+        }
+    }
+
+    // Map the variables onto the signals of the design.
+    let engine = Engine::new(module, top)?;
+    let signals: HashMap<&str, Type> = engine.signals().collect();
+    let root = find_root(&vars, &signals)
+        .ok_or_else(|| "no variable in the VCD file matches a signal".to_string())?;
+    let mut mapped = HashMap::<&str, Vec<(String, Type)>>::new();
+    for var in &vars {
+        if !var.path.starts_with(root) {
+            continue;
+        }
+        let name = var.path[root.len()..].join(".");
+        let ty = match signals.get(name.as_str()) {
+            Some(ty) => ty.clone(),
+            None => continue,
+        };
+        match *ty {
+            TypeKind::IntType(width) if width == var.size => (),
+            TypeKind::EnumType(_) => (),
+            _ => {
+                return Err(format!(
+                    "variable {} of {} bits cannot be read into signal %{} of type {}",
+                    var.path.join("."),
+                    var.size,
+                    name,
+                    ty
+                ))
+            }
+        }
+        mapped.entry(&var.id).or_default().push((name, ty));
+    }
+
+    // Parse the value changes.
+    let mut trace = Trace::default();
+    let mut time = TimeValue::new(BigRational::from_integer(0.into()), 0, 0);
+    while let Some(token) = tokens.next() {
+        let (bits, id) = match token.chars().next().unwrap() {
+            '#' => {
+                let steps: BigUint = token[1..]
+                    .parse()
+                    .map_err(|_| format!("invalid time `{}`", token))?;
+                time = TimeValue::new(&scale * BigRational::from_integer(steps.into()), 0, 0);
+                continue;
+            }
+            '$' => continue,
+            'b' | 'B' => {
+                let id = tokens
+                    .next()
+                    .ok_or_else(|| format!("missing identifier after `{}`", token))?;
+                (&token[1..], id)
+            }
+            '0' | '1' | 'x' | 'X' | 'z' | 'Z' => (&token[..1], &token[1..]),
+            _ => return Err(format!("unsupported value change `{}`", token)),
+        };
+        for (signal, ty) in mapped.get(id).into_iter().flatten() {
+            trace.changes.push(Stimulus {
+                signal: signal.clone(),
+                time: time.clone(),
+                value: parse_value(bits, ty)?,
+            });
+        }
+    }
+    Ok(trace)
+}
+
+/// Consume the `$end` which closes a declaration.
+fn expect_end<'a>(next: &mut impl FnMut(&str) -> Result<&'a str, String>) -> Result<(), String> {
+    match next("$end")? {
+        "$end" => Ok(()),
+        t => Err(format!("expected `$end`, found `{}`", t)),
+    }
+}
+
+/// Parse a timescale such as `1ns` or `10 ps` into seconds.
+fn parse_timescale(spec: &str) -> Result<BigRational, String> {
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (num, unit) = spec.split_at(split);
+    let num: usize = num
+        .parse()
+        .map_err(|_| format!("invalid timescale `{}`", spec))?;
+    let unit = unit
+        .strip_suffix('s')
+        .and_then(TimeUnit::from_prefix)
+        .ok_or_else(|| format!("invalid timescale `{}`", spec))?;
+    Ok(unit.scale() * BigRational::from_integer(num.into()))
+}
+
+/// Find the scope which corresponds to the top entity, given by the path of
+/// scope names.
+fn find_root<'a>(vars: &'a [Var], signals: &HashMap<&str, Type>) -> Option<&'a [String]> {
+    let mut best = None;
+    let mut best_count = 0;
+    let mut candidates: Vec<&[String]> = vars
+        .iter()
+        .flat_map(|v| (0..v.path.len()).map(move |n| &v.path[..n]))
+        .collect();
+    candidates.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+    candidates.dedup();
+    for root in candidates {
+        let count = vars
+            .iter()
+            .filter(|v| {
+                v.path.starts_with(root)
+                    && signals.contains_key(v.path[root.len()..].join(".").as_str())
+            })
+            .count();
+        if count > best_count {
+            best = Some(root);
+            best_count = count;
+        }
+    }
+    best
+}
+
+/// Parse the bits of a value change into a value of a given type.
+fn parse_value(bits: &str, ty: &Type) -> Result<crate::Value, String> {
+    let digits: String = bits
+        .chars()
+        .map(|c| match c {
+            'x' | 'X' | 'z' | 'Z' => '0',
+            c => c,
+        })
+        .collect();
+    let value = BigUint::parse_bytes(digits.as_bytes(), 2)
+        .ok_or_else(|| format!("invalid value `{}`", bits))?;
+    match **ty {
+        TypeKind::IntType(width) if value.bits() <= width => {
+            Ok(IntValue::from((width, value)).into())
+        }
+        TypeKind::EnumType(size) => match value.to_usize() {
+            Some(v) if v < size => Ok(EnumValue::new(size, v).into()),
+            _ => Err(format!("value `{}` out of range for type {}", bits, ty)),
+        },
+        _ => Err(format!("value `{}` out of range for type {}", bits, ty)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembly::{parse_module, parse_time},
+        sim::{self, Stimuli},
+        trace::compare,
+    };
+
+    const VCD: &str = "
+        $date today $end
+        $timescale 1 ns $end
+        $scope module tb $end
+        $var wire 1 # clk $end
+        $scope module top $end
+        $var wire 8 ! a [7:0] $end
+        $var wire 8 \" b [7:0] $end
+        $upscope $end
+        $upscope $end
+        $enddefinitions $end
+        #0
+        $dumpvars
+        b1 !
+        b10 \"
+        x#
+        $end
+        #2
+        b101 !
+        b110 \"
+        1#
+    ";
+
+    #[test]
+    fn golden_reference_and_stimulus() {
+        let module = parse_module(
+            "
+            entity @top (i8$ %a) -> (i8$ %b) {
+                %ap = prb i8$ %a
+                %one = const i8 1
+                %sum = add i8 %ap, %one
+                %t = const time 0s 1e
+                drv i8$ %b, %sum, %t
+            }
+            ",
+        )
+        .unwrap();
+        let golden = read_vcd(VCD, &module, None).unwrap();
+        assert_eq!(golden.changes.len(), 4);
+        assert_eq!(golden.changes[2].signal, "a");
+        assert_eq!(golden.changes[2].time, parse_time("2ns").unwrap());
+        assert_eq!(golden.changes[2].value, IntValue::from_usize(8, 5).into());
+
+        let mut stimulus = golden.clone();
+        stimulus.changes.retain(|c| c.signal == "a");
+        let trace = sim::run(&module, Stimuli::from(stimulus)).unwrap();
+        assert_eq!(compare(&trace, &golden), vec![]);
+    }
+
+    #[test]
+    fn mismatching_width() {
+        let module = parse_module("entity @top (i4$ %a) -> () {}").unwrap();
+        assert_eq!(
+            read_vcd(VCD, &module, None).err().unwrap(),
+            "variable tb.top.a of 8 bits cannot be read into signal %a of type i4"
+        );
+    }
+
+    #[test]
+    fn timescales() {
+        let ns = parse_time("1ns").unwrap();
+        assert_eq!(parse_timescale("1ns").unwrap(), ns.time().clone());
+        let ps = parse_time("100ps").unwrap();
+        assert_eq!(parse_timescale("100ps").unwrap(), ps.time().clone());
+        assert!(parse_timescale("1 parsec").is_err());
+    }
+}
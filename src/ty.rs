@@ -3,7 +3,12 @@
 //! Types of values.
 
 use itertools::Itertools;
-use std::sync::Arc;
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock, RwLock, Weak},
+};
 
 pub use self::TypeKind::*;
 
@@ -11,7 +16,11 @@ pub use self::TypeKind::*;
 pub type Type = Arc<TypeKind>;
 
 /// The different kinds of types.
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
+///
+/// Types are compared structurally. Hashing a type only considers the kind and
+/// length of the type and of the types directly nested in it, which keeps
+/// hashing cheap for deeply nested types.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TypeKind {
     /// The `void` type.
     VoidType,
@@ -22,17 +31,62 @@ pub enum TypeKind {
     /// Enumerated types like `n42`.
     EnumType(usize),
     /// Pointer types like `i32*`.
-    PointerType(Type),
+    PointerType(#[serde(deserialize_with = "deserialize")] Type),
     /// Signal types like `i32$`.
-    SignalType(Type),
+    SignalType(#[serde(deserialize_with = "deserialize")] Type),
     /// Array types like `[4 x i32]`.
-    ArrayType(usize, Type),
+    ArrayType(usize, #[serde(deserialize_with = "deserialize")] Type),
     /// Struct types like `{i8, i32}`.
-    StructType(Vec<Type>),
+    StructType(#[serde(deserialize_with = "deserialize_vec")] Vec<Type>),
+    /// Named struct types like `%pair`, defined as `%pair = type {i8, i32}`.
+    ///
+    /// Unlike other types, named struct types are only equal if they have the
+    /// same name, even if their fields are the same.
+    NamedStructType(
+        String,
+        #[serde(deserialize_with = "deserialize_vec")] Vec<Type>,
+    ),
     /// Function types like `(i32) void`.
-    FuncType(Vec<Type>, Type),
+    FuncType(
+        #[serde(deserialize_with = "deserialize_vec")] Vec<Type>,
+        #[serde(deserialize_with = "deserialize")] Type,
+    ),
     /// Entity types like `(i8, i8; i32)`.
-    EntityType(Vec<Type>, Vec<Type>),
+    EntityType(
+        #[serde(deserialize_with = "deserialize_vec")] Vec<Type>,
+        #[serde(deserialize_with = "deserialize_vec")] Vec<Type>,
+    ),
+}
+
+impl Hash for TypeKind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_shallow(self, state);
+        match *self {
+            PointerType(ref ty) | SignalType(ref ty) | ArrayType(_, ref ty) => {
+                hash_shallow(ty, state)
+            }
+            StructType(ref fields) => fields.iter().for_each(|ty| hash_shallow(ty, state)),
+            NamedStructType(ref name, ref fields) => {
+                name.hash(state);
+                fields.iter().for_each(|ty| hash_shallow(ty, state));
+            }
+            FuncType(ref args, ref ret) => {
+                args.iter().for_each(|ty| hash_shallow(ty, state));
+                hash_shallow(ret, state);
+            }
+            EntityType(ref ins, ref outs) => {
+                ins.iter().for_each(|ty| hash_shallow(ty, state));
+                outs.iter().for_each(|ty| hash_shallow(ty, state));
+            }
+            VoidType | TimeType | IntType(..) | EnumType(..) => (),
+        }
+    }
+}
+
+/// Hash the kind and length of a type, but none of its nested types.
+fn hash_shallow<H: Hasher>(ty: &TypeKind, state: &mut H) {
+    std::mem::discriminant(ty).hash(state);
+    ty.len().hash(state);
 }
 
 impl std::fmt::Display for TypeKind {
@@ -46,6 +100,7 @@ impl std::fmt::Display for TypeKind {
             SignalType(ref ty) => write!(f, "{}$", ty),
             ArrayType(l, ref ty) => write!(f, "[{} x {}]", l, ty),
             StructType(ref tys) => write!(f, "{{{}}}", tys.iter().format(", ")),
+            NamedStructType(ref name, _) => write!(f, "%{}", name),
             FuncType(ref args, ref ret) => write!(f, "({}) {}", args.iter().format(", "), ret),
             EntityType(ref ins, ref outs) => write!(
                 f,
@@ -107,7 +162,7 @@ impl TypeKind {
     /// struct. E.g. yields the `[i8, i16]` in `{i8, i16}`.
     pub fn unwrap_struct(&self) -> &[Type] {
         match *self {
-            StructType(ref fields) => fields,
+            StructType(ref fields) | NamedStructType(_, ref fields) => fields,
             _ => panic!("unwrap_struct called on {}", self),
         }
     }
@@ -186,14 +241,22 @@ impl TypeKind {
         }
     }
 
-    /// Check if this is a struct type.
+    /// Check if this is a struct type, named or not.
     pub fn is_struct(&self) -> bool {
         match *self {
-            StructType(..) => true,
+            StructType(..) | NamedStructType(..) => true,
             _ => false,
         }
     }
 
+    /// Get the name of a named struct type, or `None` for all other types.
+    pub fn struct_name(&self) -> Option<&str> {
+        match *self {
+            NamedStructType(ref name, _) => Some(name),
+            _ => None,
+        }
+    }
+
     /// Check if this is a func type.
     pub fn is_func(&self) -> bool {
         match *self {
@@ -222,59 +285,263 @@ impl TypeKind {
     pub fn len(&self) -> usize {
         match *self {
             IntType(l) | EnumType(l) | ArrayType(l, _) => l,
-            StructType(ref f) => f.len(),
+            StructType(ref f) | NamedStructType(_, ref f) => f.len(),
             _ => 0,
         }
     }
 }
 
+/// The number of independently locked shards of the type interner.
+const NUM_SHARDS: usize = 16;
+
+/// A shard of the type interner.
+///
+/// Maps the hash of a type to the types with that hash. The shard only holds
+/// weak references, such that types no longer used anywhere are freed.
+#[derive(Default)]
+struct Shard {
+    types: HashMap<u64, Vec<Weak<TypeKind>>>,
+    /// The number of entries after the last removal of freed types.
+    live: usize,
+    /// The current number of entries.
+    len: usize,
+}
+
+impl Shard {
+    /// Find an interned type.
+    fn get(&self, hash: u64, kind: &TypeKind) -> Option<Type> {
+        self.types
+            .get(&hash)?
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|ty| **ty == *kind)
+    }
+
+    /// Add a type, occasionally dropping the entries of freed types.
+    fn insert(&mut self, hash: u64, ty: &Type) {
+        if self.len >= 2 * self.live.max(64) {
+            self.types.retain(|_, tys| {
+                tys.retain(|ty| ty.strong_count() > 0);
+                !tys.is_empty()
+            });
+            self.len = self.types.values().map(Vec::len).sum();
+            self.live = self.len;
+        }
+        self.types.entry(hash).or_default().push(Arc::downgrade(ty));
+        self.len += 1;
+    }
+}
+
+/// Intern a type.
+///
+/// Structurally identical types share a single allocation, such that equality
+/// checks on them are resolved by comparing pointers. Types are still hashed
+/// and compared structurally, such that types which have been allocated
+/// elsewhere behave the same as interned ones. All type construction
+/// functions in this module go through the interner, as does deserialization.
+/// The interner is sharded to keep threads constructing types from contending
+/// for a single lock, and does not keep types alive that are no longer used.
+pub fn intern(kind: TypeKind) -> Type {
+    let kind = intern_nested(kind);
+    let hash = hash_kind(&kind);
+    let shard = shard(hash);
+    if let Some(ty) = shard
+        .read()
+        .expect("type interner poisoned")
+        .get(hash, &kind)
+    {
+        return ty;
+    }
+    let mut shard = shard.write().expect("type interner poisoned");
+    if let Some(ty) = shard.get(hash, &kind) {
+        return ty;
+    }
+    let ty = Type::new(kind);
+    shard.insert(hash, &ty);
+    ty
+}
+
+/// Get the interner shard responsible for a hash.
+fn shard(hash: u64) -> &'static RwLock<Shard> {
+    static SHARDS: OnceLock<Vec<RwLock<Shard>>> = OnceLock::new();
+    &SHARDS.get_or_init(|| (0..NUM_SHARDS).map(|_| Default::default()).collect())
+        [hash as usize % NUM_SHARDS]
+}
+
+/// Compute the hash under which a type is interned.
+fn hash_kind(kind: &TypeKind) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    kind.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Check whether a type is the allocation held by the interner.
+fn is_interned(ty: &Type) -> bool {
+    let hash = hash_kind(ty);
+    shard(hash)
+        .read()
+        .expect("type interner poisoned")
+        .types
+        .get(&hash)
+        .map(|tys| tys.iter().any(|t| t.as_ptr() == Arc::as_ptr(ty)))
+        .unwrap_or(false)
+}
+
+/// Make sure the types nested in a type are interned.
+///
+/// Types may have been allocated outside the interner, in which case they are
+/// replaced by their interned equivalent.
+fn intern_nested(kind: TypeKind) -> TypeKind {
+    fn canonical(ty: Type) -> Type {
+        if is_interned(&ty) {
+            ty
+        } else {
+            intern((*ty).clone())
+        }
+    }
+    fn canonical_vec(tys: Vec<Type>) -> Vec<Type> {
+        tys.into_iter().map(canonical).collect()
+    }
+    match kind {
+        PointerType(ty) => PointerType(canonical(ty)),
+        SignalType(ty) => SignalType(canonical(ty)),
+        ArrayType(l, ty) => ArrayType(l, canonical(ty)),
+        StructType(fields) => StructType(canonical_vec(fields)),
+        NamedStructType(name, fields) => NamedStructType(name, canonical_vec(fields)),
+        FuncType(args, ret) => FuncType(canonical_vec(args), canonical(ret)),
+        EntityType(ins, outs) => EntityType(canonical_vec(ins), canonical_vec(outs)),
+        kind => kind,
+    }
+}
+
+/// Deserialize an interned type.
+///
+/// Use as `#[serde(deserialize_with = "crate::ty::deserialize")]` on fields
+/// holding a type.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Type, D::Error> {
+    TypeKind::deserialize(deserializer).map(intern)
+}
+
+/// Deserialize a list of interned types.
+pub(crate) fn deserialize_vec<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Type>, D::Error> {
+    Vec::<TypeKind>::deserialize(deserializer).map(|kinds| kinds.into_iter().map(intern).collect())
+}
+
+/// Deserialize an optional interned type.
+pub(crate) fn deserialize_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Type>, D::Error> {
+    Option::<TypeKind>::deserialize(deserializer).map(|kind| kind.map(intern))
+}
+
 /// Create a void type.
 pub fn void_ty() -> Type {
-    Type::new(VoidType)
+    intern(VoidType)
 }
 
 /// Create a time type.
 pub fn time_ty() -> Type {
-    Type::new(TimeType)
+    intern(TimeType)
 }
 
 /// Create an integer type of the requested size.
 pub fn int_ty(size: usize) -> Type {
-    Type::new(IntType(size))
+    intern(IntType(size))
 }
 
 /// Create an enum type of the requested size.
 pub fn enum_ty(size: usize) -> Type {
-    Type::new(EnumType(size))
+    intern(EnumType(size))
 }
 
 /// Create a pointer type with the requested data type.
 pub fn pointer_ty(ty: Type) -> Type {
-    Type::new(PointerType(ty))
+    intern(PointerType(ty))
 }
 
 /// Create a signal type with the requested data type.
 pub fn signal_ty(ty: Type) -> Type {
-    Type::new(SignalType(ty))
+    intern(SignalType(ty))
 }
 
 /// Create a array type. `size` is the number of elements in the array, and `ty`
 /// the type of each individual element.
 pub fn array_ty(size: usize, ty: Type) -> Type {
-    Type::new(ArrayType(size, ty))
+    intern(ArrayType(size, ty))
 }
 
 /// Create a struct type. `fields` is an list of types, one for each field.
 pub fn struct_ty(fields: Vec<Type>) -> Type {
-    Type::new(StructType(fields))
+    intern(StructType(fields))
+}
+
+/// Create a named struct type. `name` is the name of the type without the
+/// leading `%`, and `fields` a list of types, one for each field.
+pub fn named_struct_ty(name: impl Into<String>, fields: Vec<Type>) -> Type {
+    intern(NamedStructType(name.into(), fields))
 }
 
 /// Create a function type with the given arguments and return type.
 pub fn func_ty(args: Vec<Type>, ret: Type) -> Type {
-    Type::new(FuncType(args, ret))
+    intern(FuncType(args, ret))
 }
 
 /// Create an entity type with the given input and output arguments.
 pub fn entity_ty(ins: Vec<Type>, outs: Vec<Type>) -> Type {
-    Type::new(EntityType(ins, outs))
+    intern(EntityType(ins, outs))
+}
+
+/// Check whether `InstBuilder::const_zero` can create a zero value of a type.
+pub(crate) fn has_zero(ty: &Type) -> bool {
+    match **ty {
+        TimeType | IntType(_) | EnumType(_) => true,
+        ArrayType(_, ref ty) => has_zero(ty),
+        StructType(ref tys) | NamedStructType(_, ref tys) => tys.iter().all(has_zero),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_types_share_allocation() {
+        let a = struct_ty(vec![int_ty(8), signal_ty(int_ty(32))]);
+        let b = struct_ty(vec![int_ty(8), signal_ty(int_ty(32))]);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&int_ty(8), &int_ty(9)));
+    }
+
+    #[test]
+    fn deserialized_types_are_interned() {
+        let ty = struct_ty(vec![int_ty(8), signal_ty(int_ty(32))]);
+        let json = serde_json::to_string(&ty).unwrap();
+        let mut de = serde_json::Deserializer::from_str(&json);
+        let back = deserialize(&mut de).unwrap();
+        assert!(Arc::ptr_eq(&ty, &back));
+    }
+
+    #[test]
+    fn foreign_nested_types_are_interned() {
+        let foreign = Type::new(IntType(7));
+        let ty = signal_ty(foreign.clone());
+        assert!(Arc::ptr_eq(ty.unwrap_signal(), &int_ty(7)));
+        assert!(!Arc::ptr_eq(&foreign, &int_ty(7)));
+    }
+
+    #[test]
+    fn foreign_types_hash_like_interned_ones() {
+        let hash = |ty: &Type| {
+            let mut hasher = DefaultHasher::new();
+            ty.hash(&mut hasher);
+            hasher.finish()
+        };
+        let foreign = Type::new(SignalType(Type::new(IntType(8))));
+        let interned = signal_ty(int_ty(8));
+        assert_eq!(foreign, interned);
+        assert_eq!(hash(&foreign), hash(&interned));
+    }
 }
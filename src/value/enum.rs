@@ -0,0 +1,111 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Enum values
+//!
+//! This module implements enumerated values, such as the states of an FSM.
+
+use crate::{
+    ir::prelude::*,
+    ty::{enum_ty, Type},
+    value::IntValue,
+};
+use std::fmt::{Debug, Display};
+
+/// An enum value.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EnumValue {
+    /// The number of states of the enum.
+    pub size: usize,
+    /// The selected state, in the range `0..size`.
+    pub value: usize,
+}
+
+impl EnumValue {
+    /// Create a new enum value.
+    ///
+    /// Panics if `value` is not less than `size`.
+    pub fn new(size: usize, value: usize) -> Self {
+        assert!(value < size, "enum value {} out of range n{}", value, size);
+        Self { size, value }
+    }
+
+    /// Create a new enum value, or `None` if `value` is out of range.
+    pub fn try_new(size: usize, value: usize) -> Option<Self> {
+        if value < size {
+            Some(Self { size, value })
+        } else {
+            None
+        }
+    }
+
+    /// Create a value in the first state of an enum.
+    pub fn zero(size: usize) -> Self {
+        Self::new(size, 0)
+    }
+
+    /// Check if the value is in the first state.
+    pub fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+
+    /// Get the type of the value.
+    pub fn ty(&self) -> Type {
+        enum_ty(self.size)
+    }
+
+    /// Execute a comparison opcode.
+    ///
+    /// The states of an enum are unordered, so only `eq` and `neq` are
+    /// supported.
+    pub fn try_compare_op(op: Opcode, lhs: &EnumValue, rhs: &EnumValue) -> Option<IntValue> {
+        let v = match op {
+            Opcode::Eq => lhs == rhs,
+            Opcode::Neq => lhs != rhs,
+            _ => return None,
+        };
+        Some(IntValue::from_usize(1, v as usize))
+    }
+}
+
+impl Display for EnumValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "n{} {}", self.size, self.value)
+    }
+}
+
+impl Debug for EnumValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl From<(usize, usize)> for EnumValue {
+    fn from(v: (usize, usize)) -> Self {
+        Self::new(v.0, v.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare() {
+        let a = EnumValue::new(4, 1);
+        let b = EnumValue::new(4, 3);
+        assert!(EnumValue::try_compare_op(Opcode::Neq, &a, &b)
+            .unwrap()
+            .is_one());
+        assert!(EnumValue::try_compare_op(Opcode::Eq, &a, &b)
+            .unwrap()
+            .is_zero());
+        assert!(EnumValue::try_compare_op(Opcode::Ult, &a, &b).is_none());
+        assert!(EnumValue::try_new(4, 4).is_none());
+    }
+
+    #[test]
+    fn parse_out_of_range() {
+        let input = "func @foo () n4 {\nentry:\n    %a = const n4 4\n    ret n4 %a\n}\n";
+        assert!(crate::assembly::parse_module(input).is_err());
+    }
+}
@@ -164,6 +164,30 @@ impl IntValue {
         let v = &max - &self.value;
         IntValue::from_unsigned(self.width, v)
     }
+
+    /// Compute `popcount`.
+    pub fn popcount(&self) -> IntValue {
+        let ones: u32 = self
+            .value
+            .to_bytes_le()
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum();
+        IntValue::from_usize(self.width, ones as usize)
+    }
+
+    /// Compute `clz`.
+    pub fn clz(&self) -> IntValue {
+        IntValue::from_usize(self.width, self.width - self.value.bits())
+    }
+
+    /// Compute `ctz`.
+    pub fn ctz(&self) -> IntValue {
+        let zeros = (0..self.width)
+            .find(|&i| self.extract_slice(i, 1).is_one())
+            .unwrap_or(self.width);
+        IntValue::from_usize(self.width, zeros)
+    }
 }
 
 /// Binary operators.
@@ -198,6 +222,11 @@ impl IntValue {
         IntValue::from_unsigned(self.width, &self.value * &other.value)
     }
 
+    /// Compute `umulh`.
+    pub fn umulh(&self, other: &Self) -> IntValue {
+        IntValue::from_unsigned(self.width, (&self.value * &other.value) >> self.width)
+    }
+
     /// Compute `udiv`.
     pub fn udiv(&self, other: &Self) -> IntValue {
         IntValue::from_unsigned(self.width, &self.value / &other.value)
@@ -218,6 +247,12 @@ impl IntValue {
         IntValue::from_signed(self.width, self.to_signed() * other.to_signed())
     }
 
+    /// Compute `smulh`.
+    pub fn smulh(&self, other: &Self) -> IntValue {
+        let product = self.to_signed() * other.to_signed();
+        IntValue::from_signed(self.width, product >> self.width)
+    }
+
     /// Compute `sdiv`.
     pub fn sdiv(&self, other: &Self) -> IntValue {
         IntValue::from_signed(self.width, self.to_signed() / other.to_signed())
@@ -238,6 +273,73 @@ impl IntValue {
     pub fn srem(&self, other: &Self) -> IntValue {
         IntValue::from_signed(self.width, self.to_signed() % other.to_signed())
     }
+
+    /// Compute `umin`.
+    pub fn umin(&self, other: &Self) -> IntValue {
+        if other.ult(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Compute `umax`.
+    pub fn umax(&self, other: &Self) -> IntValue {
+        if other.ugt(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Compute `smin`.
+    pub fn smin(&self, other: &Self) -> IntValue {
+        if other.slt(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Compute `smax`.
+    pub fn smax(&self, other: &Self) -> IntValue {
+        if other.sgt(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+}
+
+/// Binary operators which detect overflow.
+impl IntValue {
+    /// Compute `uaddo`, returning the sum and the carry out.
+    pub fn uaddo(&self, other: &Self) -> (IntValue, bool) {
+        let sum = &self.value + &other.value;
+        let carry = sum.bits() > self.width;
+        (IntValue::from_unsigned(self.width, sum), carry)
+    }
+
+    /// Compute `saddo`, returning the sum and whether it overflowed.
+    pub fn saddo(&self, other: &Self) -> (IntValue, bool) {
+        let exact = self.to_signed() + other.to_signed();
+        let sum = IntValue::from_signed(self.width, exact.clone());
+        let overflow = sum.to_signed() != exact;
+        (sum, overflow)
+    }
+
+    /// Compute `usubo`, returning the difference and the borrow out.
+    pub fn usubo(&self, other: &Self) -> (IntValue, bool) {
+        (self.sub(other), self.value < other.value)
+    }
+
+    /// Compute `ssubo`, returning the difference and whether it overflowed.
+    pub fn ssubo(&self, other: &Self) -> (IntValue, bool) {
+        let exact = self.to_signed() - other.to_signed();
+        let diff = IntValue::from_signed(self.width, exact.clone());
+        let overflow = diff.to_signed() != exact;
+        (diff, overflow)
+    }
 }
 
 /// Comparisons.
@@ -311,6 +413,9 @@ impl IntValue {
         Some(match op {
             Opcode::Not => arg.not(),
             Opcode::Neg => arg.neg(),
+            Opcode::Popcount => arg.popcount(),
+            Opcode::Clz => arg.clz(),
+            Opcode::Ctz => arg.ctz(),
             _ => return None,
         })
     }
@@ -333,13 +438,19 @@ impl IntValue {
             Opcode::Or => lhs.or(rhs),
             Opcode::Xor => lhs.xor(rhs),
             Opcode::Smul => lhs.smul(rhs),
+            Opcode::Smulh => lhs.smulh(rhs),
             Opcode::Sdiv => lhs.sdiv(rhs),
             Opcode::Smod => lhs.smod(rhs),
             Opcode::Srem => lhs.srem(rhs),
             Opcode::Umul => lhs.umul(rhs),
+            Opcode::Umulh => lhs.umulh(rhs),
             Opcode::Udiv => lhs.udiv(rhs),
             Opcode::Umod => lhs.umod(rhs),
             Opcode::Urem => lhs.urem(rhs),
+            Opcode::Umin => lhs.umin(rhs),
+            Opcode::Umax => lhs.umax(rhs),
+            Opcode::Smin => lhs.smin(rhs),
+            Opcode::Smax => lhs.smax(rhs),
             _ => return None,
         })
     }
@@ -352,6 +463,24 @@ impl IntValue {
         }
     }
 
+    /// Execute a binary opcode which detects overflow.
+    ///
+    /// Returns the result and the carry, borrow, or overflow flag as an `i1`.
+    pub fn try_overflow_op(
+        op: Opcode,
+        lhs: &IntValue,
+        rhs: &IntValue,
+    ) -> Option<(IntValue, IntValue)> {
+        let (v, flag) = match op {
+            Opcode::Uaddo => lhs.uaddo(rhs),
+            Opcode::Saddo => lhs.saddo(rhs),
+            Opcode::Usubo => lhs.usubo(rhs),
+            Opcode::Ssubo => lhs.ssubo(rhs),
+            _ => return None,
+        };
+        Some((v, IntValue::from_usize(1, flag as usize)))
+    }
+
     /// Execute a comparison opcode.
     pub fn try_compare_op(op: Opcode, lhs: &IntValue, rhs: &IntValue) -> Option<IntValue> {
         // trace!("{} ({}, {})", op, lhs, rhs);
@@ -455,4 +584,60 @@ mod tests {
         assert_eq!(cn.srem(&b), IntValue::from_isize(8, 0));
         assert_eq!(cn.srem(&bn), IntValue::from_isize(8, 0));
     }
+
+    #[test]
+    fn overflow() {
+        let a = IntValue::from_usize(8, 100);
+        let b = IntValue::from_usize(8, 200);
+        let an = a.neg();
+
+        assert_eq!(a.uaddo(&a), (IntValue::from_usize(8, 200), false));
+        assert_eq!(a.uaddo(&b), (IntValue::from_usize(8, 44), true));
+        assert_eq!(a.saddo(&an), (IntValue::from_usize(8, 0), false));
+        assert_eq!(a.saddo(&a), (IntValue::from_isize(8, -56), true));
+        assert_eq!(an.saddo(&an), (IntValue::from_isize(8, 56), true));
+        assert_eq!(b.usubo(&a), (IntValue::from_usize(8, 100), false));
+        assert_eq!(a.usubo(&b), (IntValue::from_isize(8, -100), true));
+        assert_eq!(a.ssubo(&a), (IntValue::from_usize(8, 0), false));
+        assert_eq!(an.ssubo(&a), (IntValue::from_isize(8, 56), true));
+    }
+
+    #[test]
+    fn min_max() {
+        let a = IntValue::from_usize(8, 100);
+        let b = IntValue::from_usize(8, 200);
+
+        assert_eq!(a.umin(&b), a);
+        assert_eq!(a.umax(&b), b);
+        assert_eq!(a.smin(&b), b);
+        assert_eq!(a.smax(&b), a);
+        assert_eq!(b.smin(&a), b);
+        assert_eq!(b.umax(&a), b);
+    }
+
+    #[test]
+    fn mul_high() {
+        let a = IntValue::from_usize(8, 100);
+        let b = IntValue::from_usize(8, 200);
+        let n = IntValue::from_isize(8, -3);
+
+        assert_eq!(a.umulh(&b), IntValue::from_usize(8, 78));
+        assert_eq!(a.smulh(&b), IntValue::from_isize(8, -22));
+        assert_eq!(a.smulh(&a), IntValue::from_usize(8, 39));
+        assert_eq!(n.smulh(&a), IntValue::from_isize(8, -2));
+        assert_eq!(n.umulh(&n), IntValue::from_usize(8, 250));
+    }
+
+    #[test]
+    fn count_bits() {
+        let a = IntValue::from_usize(12, 0b0001_0110_1000);
+        let z = IntValue::zero(12);
+
+        assert_eq!(a.popcount(), IntValue::from_usize(12, 4));
+        assert_eq!(a.clz(), IntValue::from_usize(12, 3));
+        assert_eq!(a.ctz(), IntValue::from_usize(12, 3));
+        assert_eq!(z.popcount(), IntValue::from_usize(12, 0));
+        assert_eq!(z.clz(), IntValue::from_usize(12, 12));
+        assert_eq!(z.ctz(), IntValue::from_usize(12, 12));
+    }
 }
@@ -4,10 +4,109 @@
 //!
 //! This module implements time arithmetic.
 
-use crate::ty::{time_ty, Type};
-use num::{traits::*, BigInt, BigRational};
+use crate::{
+    ir::Opcode,
+    ty::{time_ty, Type},
+    value::IntValue,
+};
+use num::{traits::*, BigInt, BigRational, Integer};
 use std::fmt::{Debug, Display};
 
+/// An SI unit of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimeUnit {
+    /// Attoseconds, `as`.
+    Atto,
+    /// Femtoseconds, `fs`.
+    Femto,
+    /// Picoseconds, `ps`.
+    Pico,
+    /// Nanoseconds, `ns`.
+    Nano,
+    /// Microseconds, `us`.
+    Micro,
+    /// Milliseconds, `ms`.
+    Milli,
+    /// Seconds, `s`.
+    Second,
+    /// Kiloseconds, `ks`.
+    Kilo,
+    /// Megaseconds, `Ms`.
+    Mega,
+    /// Gigaseconds, `Gs`.
+    Giga,
+    /// Teraseconds, `Ts`.
+    Tera,
+    /// Petaseconds, `Ps`.
+    Peta,
+    /// Exaseconds, `Es`.
+    Exa,
+}
+
+impl TimeUnit {
+    /// All units, from the smallest to the largest.
+    pub const ALL: [TimeUnit; 13] = [
+        TimeUnit::Atto,
+        TimeUnit::Femto,
+        TimeUnit::Pico,
+        TimeUnit::Nano,
+        TimeUnit::Micro,
+        TimeUnit::Milli,
+        TimeUnit::Second,
+        TimeUnit::Kilo,
+        TimeUnit::Mega,
+        TimeUnit::Giga,
+        TimeUnit::Tera,
+        TimeUnit::Peta,
+        TimeUnit::Exa,
+    ];
+
+    /// Get the SI prefix of the unit, e.g. `n` for nanoseconds.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            TimeUnit::Atto => "a",
+            TimeUnit::Femto => "f",
+            TimeUnit::Pico => "p",
+            TimeUnit::Nano => "n",
+            TimeUnit::Micro => "u",
+            TimeUnit::Milli => "m",
+            TimeUnit::Second => "",
+            TimeUnit::Kilo => "k",
+            TimeUnit::Mega => "M",
+            TimeUnit::Giga => "G",
+            TimeUnit::Tera => "T",
+            TimeUnit::Peta => "P",
+            TimeUnit::Exa => "E",
+        }
+    }
+
+    /// Find the unit with a given SI prefix.
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|u| u.prefix() == prefix)
+    }
+
+    /// Get the power of ten of the unit, relative to seconds.
+    pub fn exponent(self) -> i32 {
+        (self as i32 - TimeUnit::Second as i32) * 3
+    }
+
+    /// Get the duration of the unit in seconds.
+    pub fn scale(self) -> BigRational {
+        let magnitude = BigInt::from(10).pow(self.exponent().unsigned_abs());
+        if self.exponent() < 0 {
+            BigRational::new(One::one(), magnitude)
+        } else {
+            BigRational::from_integer(magnitude)
+        }
+    }
+}
+
+impl Display for TimeUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}s", self.prefix())
+    }
+}
+
 /// A constant time value.
 #[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TimeValue {
@@ -62,6 +161,39 @@ impl TimeValue {
     pub fn is_zero(&self) -> bool {
         self.time.is_zero() && self.delta.is_zero() && self.epsilon.is_zero()
     }
+
+    /// Create a time from a real value given in a unit.
+    ///
+    /// For example, `from_unit(3/2, TimeUnit::Nano)` yields `1.5ns`.
+    pub fn from_unit(value: BigRational, unit: TimeUnit) -> Self {
+        Self::new(value * unit.scale(), 0, 0)
+    }
+
+    /// Express the real time value as a multiple of a unit.
+    ///
+    /// For example, `1.5ns` normalized to `TimeUnit::Pico` yields `1500`.
+    /// Delta and epsilon steps are not considered.
+    pub fn normalize_to(&self, unit: TimeUnit) -> BigRational {
+        &self.time / unit.scale()
+    }
+
+    /// Execute a comparison opcode, if it applies to times.
+    ///
+    /// Times are ordered by their real time value first, then by delta steps,
+    /// and then by epsilon steps. The signed and unsigned comparisons are
+    /// equivalent.
+    pub fn try_compare_op(op: Opcode, lhs: &TimeValue, rhs: &TimeValue) -> Option<IntValue> {
+        let v = match op {
+            Opcode::Eq => lhs == rhs,
+            Opcode::Neq => lhs != rhs,
+            Opcode::Ult | Opcode::Slt => lhs < rhs,
+            Opcode::Ugt | Opcode::Sgt => lhs > rhs,
+            Opcode::Ule | Opcode::Sle => lhs <= rhs,
+            Opcode::Uge | Opcode::Sge => lhs >= rhs,
+            _ => return None,
+        };
+        Some(IntValue::from_usize(1, v as usize))
+    }
 }
 
 impl Display for TimeValue {
@@ -87,35 +219,30 @@ fn write_ratio_as_si(ratio: &BigRational, f: &mut std::fmt::Formatter) -> std::f
     if ratio.is_zero() {
         return write!(f, "0s");
     }
-    let prefices = ["", "m", "u", "n", "p", "f", "a"];
-    let mut scaled = ratio.clone();
-    let mut prefix = 0;
-    let mut shift = 0;
-    while prefix + 1 < prefices.len() && shift < 9 {
-        if scaled >= One::one() {
-            if scaled.is_integer() {
-                break;
-            } else {
-                shift += 3;
-            }
-        } else {
-            prefix += 1;
-        }
-        scaled = scaled * BigRational::from_integer(BigInt::from(1000));
-    }
-    let rounded = format!("{}", scaled.round());
-    if shift > 0 {
-        write!(
-            f,
-            "{}.{}{}s",
-            &rounded[..rounded.len() - shift],
-            &rounded[rounded.len() - shift..],
-            prefices[prefix]
-        )?;
+    if ratio.is_negative() {
+        write!(f, "-")?;
+    }
+    let ratio = ratio.abs();
+
+    // Pick the largest unit in which the value is at least one, and print up
+    // to nine fractional digits.
+    let unit = TimeUnit::ALL
+        .iter()
+        .rev()
+        .cloned()
+        .find(|unit| ratio >= unit.scale())
+        .unwrap_or(TimeUnit::Atto);
+    let precision = BigInt::from(1_000_000_000);
+    let fixed = (ratio / unit.scale() * BigRational::from_integer(precision.clone()))
+        .round()
+        .to_integer();
+    let (int, frac) = fixed.div_rem(&precision);
+    if frac.is_zero() {
+        write!(f, "{}{}", int, unit)
     } else {
-        write!(f, "{}{}s", rounded, prefices[prefix])?;
+        let frac = format!("{:09}", frac);
+        write!(f, "{}.{}{}", int, frac.trim_end_matches('0'), unit)
     }
-    Ok(())
 }
 
 #[cfg(test)]
@@ -171,5 +298,39 @@ mod tests {
         assert_eq!(make(5, 1000, 0, 0), "5ms");
 
         assert_eq!(make(1, 3, 0, 0), "333.333333333ms");
+
+        assert_eq!(make(3, 2000000000, 0, 0), "1.5ns");
+        assert_eq!(make(1000, 1, 0, 0), "1ks");
+        assert_eq!(make(1500, 1, 0, 0), "1.5ks");
+        assert_eq!(make(1000000000000000000, 1, 0, 0), "1Es");
+    }
+
+    #[test]
+    fn time_units() {
+        let ns = TimeValue::from_unit(BigRational::new(3.into(), 2.into()), TimeUnit::Nano);
+        assert_eq!(format!("{}", ns), "1.5ns");
+        assert_eq!(
+            ns.normalize_to(TimeUnit::Pico),
+            BigRational::from_integer(1500.into())
+        );
+        for &unit in TimeUnit::ALL.iter() {
+            assert_eq!(TimeUnit::from_prefix(unit.prefix()), Some(unit));
+            let one = TimeValue::from_unit(One::one(), unit);
+            assert_eq!(format!("{}", one), format!("1{}", unit));
+        }
+        let ps = TimeValue::from_unit(BigRational::from_integer(200.into()), TimeUnit::Pico);
+        assert!(ps < ns);
+        let lt = TimeValue::try_compare_op(Opcode::Ult, &ps, &ns).unwrap();
+        assert!(lt.is_one());
+    }
+
+    #[test]
+    fn time_parsing() {
+        let ns = crate::assembly::parse_time("1.5ns").unwrap();
+        assert_eq!(ns.time(), &BigRational::new(3.into(), 2000000000.into()));
+        let ks = crate::assembly::parse_time("2ks 1d").unwrap();
+        assert_eq!(ks.time(), &BigRational::from_integer(2000.into()));
+        assert_eq!(ks.delta(), 1);
+        assert!(crate::assembly::parse_time("-1ns").is_err());
     }
 }
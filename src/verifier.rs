@@ -7,10 +7,11 @@
 //! have terminators, and types line up.
 
 use crate::{
-    ir::{prelude::*, InstData, UnitFlags, ValueData},
-    ty::{array_ty, int_ty, pointer_ty, signal_ty, time_ty, void_ty, Type},
+    ir::{prelude::*, InstData, LinkedUnit, UnitFlags, ValueData},
+    ty::{array_ty, int_ty, pointer_ty, signal_ty, struct_ty, time_ty, void_ty, Type},
 };
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Display,
     ops::{Deref, DerefMut},
 };
@@ -38,6 +39,109 @@ impl Verifier {
         for unit in module.units() {
             self.verify_unit(unit);
         }
+        self.verify_sim_only_uses(module, None);
+        self.verify_resolution_functions(module, None);
+    }
+
+    /// Verify the integrity of the given units of a `Module`.
+    ///
+    /// Only the listed units are checked, together with the uses of them in
+    /// other units. This is useful to re-verify a module after a pass or
+    /// frontend touched a small part of it, e.g. the units reported by
+    /// `Module::dirty_units`. Units that are no longer in the module are
+    /// ignored.
+    pub fn verify_changed(&mut self, module: &Module, units: &[UnitId]) {
+        let changed: HashSet<UnitId> = units.iter().cloned().collect();
+        for unit in module.units().filter(|unit| changed.contains(&unit.id())) {
+            self.verify_unit(unit);
+        }
+        self.verify_sim_only_uses(module, Some(&changed));
+        self.verify_resolution_functions(module, Some(&changed));
+    }
+
+    /// Verify that synthesizable units do not use simulation-only units.
+    ///
+    /// If a set of changed units is given, only uses where either the user or
+    /// the used unit has changed are checked.
+    fn verify_sim_only_uses(&mut self, module: &Module, changed: Option<&HashSet<UnitId>>) {
+        let is_changed = |unit: UnitId| changed.map(|c| c.contains(&unit)).unwrap_or(true);
+        let sim_only: HashMap<&UnitName, UnitId> = module
+            .units()
+            .filter(|unit| unit.is_sim_only())
+            .map(|unit| (unit.name(), unit.id()))
+            .collect();
+        if sim_only.is_empty() {
+            return;
+        }
+        for unit in module.units().filter(|unit| !unit.is_sim_only()) {
+            for inst in unit.all_insts() {
+                let ext = match unit[inst].get_ext_unit() {
+                    Some(ext) => ext,
+                    None => continue,
+                };
+                let used = match sim_only.get(&unit[ext].name) {
+                    Some(&used) => used,
+                    None => continue,
+                };
+                if is_changed(unit.id()) || is_changed(used) {
+                    self.errors.push(VerifierError {
+                        code: ErrorCode::SimOnlyUse,
+                        unit: Some(format!("{} {}", unit.kind(), unit.name())),
+                        object: Some(ErrorObject::Inst(inst.dump(&unit).to_string())),
+                        location: unit.location_hint(inst),
+                        message: format!(
+                            "synthesizable unit uses simulation-only unit {}",
+                            unit[ext].name
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Verify that the resolution functions of signals are functions of
+    /// signature `(T, T) T`, where `T` is the type the signal carries.
+    ///
+    /// If a set of changed units is given, only the signals in these units are
+    /// checked.
+    fn verify_resolution_functions(&mut self, module: &Module, changed: Option<&HashSet<UnitId>>) {
+        let is_changed = |unit: UnitId| changed.map(|c| c.contains(&unit)).unwrap_or(true);
+        for unit in module.units().filter(|unit| is_changed(unit.id())) {
+            for inst in unit.all_insts() {
+                let name = match unit.signal_resolution(inst) {
+                    Some(Resolution::Function(name)) => name,
+                    _ => continue,
+                };
+                let ty = unit.inst_type(inst);
+                if !ty.is_signal() {
+                    continue;
+                }
+                let mut expected = Signature::new();
+                expected.add_input(ty.unwrap_signal().clone());
+                expected.add_input(ty.unwrap_signal().clone());
+                expected.set_return_type(ty.unwrap_signal().clone());
+                let message = match module.lookup(name) {
+                    None => format!("resolution function {} does not exist", name),
+                    Some(LinkedUnit::Def(id)) if !module.unit(id).is_function() => {
+                        format!("resolution function {} is not a function", name)
+                    }
+                    Some(linked) if *module.symbol_sig(linked) != expected => format!(
+                        "resolution function {} has signature {}, but must be {}",
+                        name,
+                        module.symbol_sig(linked),
+                        expected
+                    ),
+                    Some(_) => continue,
+                };
+                self.errors.push(VerifierError {
+                    code: ErrorCode::InvalidResolution,
+                    unit: Some(format!("{} {}", unit.kind(), unit.name())),
+                    object: Some(ErrorObject::Inst(inst.dump(&unit).to_string())),
+                    location: unit.location_hint(inst),
+                    message,
+                });
+            }
+        }
     }
 
     /// Verify the integrity of a `UnitData`.
@@ -59,8 +163,10 @@ impl Verifier {
         let domtree = unit.domtree();
         if unit.first_block().is_none() {
             self.errors.push(VerifierError {
+                code: ErrorCode::NoEntryBlock,
                 unit: self.unit_name.clone(),
                 object: None,
+                location: None,
                 message: format!("layout has no entry block"),
             });
         }
@@ -68,8 +174,10 @@ impl Verifier {
             // Check that the block has at least one instruction.
             if unit.first_inst(bb).is_none() {
                 self.errors.push(VerifierError {
+                    code: ErrorCode::EmptyBlock,
                     unit: self.unit_name.clone(),
-                    object: Some(bb.to_string()),
+                    object: Some(ErrorObject::Block(bb.to_string())),
+                    location: None,
                     message: format!("block is empty"),
                 })
             }
@@ -78,18 +186,21 @@ impl Verifier {
                 // Check that there are no terminator instructions in the middle
                 // of the block.
                 if unit[inst].opcode().is_terminator() && Some(inst) != unit.last_inst(bb) {
-                    self.errors.push(VerifierError {
-                        unit: self.unit_name.clone(),
-                        object: Some(inst.dump(&unit).to_string()),
-                        message: format!("terminator must be at the end of block {}", bb),
-                    });
+                    self.inst_error(
+                        unit,
+                        inst,
+                        ErrorCode::MisplacedTerminator,
+                        format!("terminator must be at the end of block {}", bb),
+                    );
                 }
 
                 // Check that the last instruction in the block is a terminator.
                 if Some(inst) == unit.last_inst(bb) && !unit[inst].opcode().is_terminator() {
                     self.errors.push(VerifierError {
+                        code: ErrorCode::MisplacedTerminator,
                         unit: self.unit_name.clone(),
-                        object: Some(bb.to_string()),
+                        object: Some(ErrorObject::Block(bb.to_string())),
+                        location: unit.location_hint(inst),
                         message: format!(
                             "last instruction `{}` must be a terminator",
                             inst.dump(&unit)
@@ -104,11 +215,12 @@ impl Verifier {
                 if let Some(result) = unit.get_inst_result(inst) {
                     for &u in unit.uses(result) {
                         if !domtree.inst_dominates_inst(&unit, inst, u) {
-                            self.errors.push(VerifierError {
-                                unit: self.unit_name.clone(),
-                                object: Some(inst.dump(&unit).to_string()),
-                                message: format!("does not dominate use in `{}`", u.dump(&unit),),
-                            });
+                            self.inst_error(
+                                unit,
+                                inst,
+                                ErrorCode::DominanceViolation,
+                                format!("does not dominate use in `{}`", u.dump(&unit),),
+                            );
                         }
                     }
                 }
@@ -140,6 +252,17 @@ impl Verifier {
         }
     }
 
+    /// Report an error caused by an instruction in the current unit.
+    fn inst_error(&mut self, unit: Unit, inst: Inst, code: ErrorCode, message: String) {
+        self.errors.push(VerifierError {
+            code,
+            unit: self.unit_name.clone(),
+            object: Some(ErrorObject::Inst(inst.dump(&unit).to_string())),
+            location: unit.location_hint(inst),
+            message,
+        });
+    }
+
     /// Verify the integrity of a single instruction.
     pub fn verify_inst(&mut self, inst: Inst, unit: Unit) {
         InstVerifier {
@@ -188,17 +311,28 @@ impl<'a> InstVerifier<'a> {
         self.unit.is_block_inserted(block)
     }
 
+    /// Report an error caused by an instruction.
+    ///
+    /// Type mismatches are annotated with the operands the opcode expects.
+    fn error(&mut self, inst: Inst, code: ErrorCode, mut message: String) {
+        let unit = self.unit;
+        if code == ErrorCode::TypeMismatch {
+            message = format!("{} ({})", message, unit[inst].opcode().describe());
+        }
+        self.verifier.inst_error(unit, inst, code, message);
+    }
+
     /// Verify the integrity of a single instruction.
     pub fn verify_inst(&mut self, inst: Inst) {
         let unit = self.unit;
 
         // Check that the instruction may appear in the surrounding unit.
         if !unit[inst].opcode().valid_in().contains(self.flags) {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&unit).to_string()),
-                message: format!("{} may not appear in this unit", unit[inst].opcode()),
-            });
+            self.error(
+                inst,
+                ErrorCode::InvalidInUnit,
+                format!("{} may not appear in this unit", unit[inst].opcode()),
+            );
         }
 
         // Check that none of the arguments are invalid, and all have a
@@ -212,37 +346,37 @@ impl<'a> InstVerifier<'a> {
                     continue;
                 }
                 args_invalid = true;
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&unit).to_string()),
-                    message: format!("{} uses invalid value", unit[inst].opcode()),
-                });
+                self.error(
+                    inst,
+                    ErrorCode::UndefinedOperand,
+                    format!("{} uses invalid value", unit[inst].opcode()),
+                );
                 continue;
             }
             if !self.is_value_defined(value) {
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&unit).to_string()),
-                    message: format!("value {} has no definition", value.dump(&unit)),
-                });
+                self.error(
+                    inst,
+                    ErrorCode::UndefinedOperand,
+                    format!("value {} has no definition", value.dump(&unit)),
+                );
             }
         }
         for &block in unit[inst].blocks() {
             if block.is_invalid() {
                 args_invalid = true;
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&unit).to_string()),
-                    message: format!("{} uses invalid block", unit[inst].opcode()),
-                });
+                self.error(
+                    inst,
+                    ErrorCode::UndefinedOperand,
+                    format!("{} uses invalid block", unit[inst].opcode()),
+                );
                 continue;
             }
             if !self.is_block_defined(block) {
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&unit).to_string()),
-                    message: format!("block {} has no definition", block.dump(&unit)),
-                });
+                self.error(
+                    inst,
+                    ErrorCode::UndefinedOperand,
+                    format!("block {} has no definition", block.dump(&unit)),
+                );
             }
         }
         if args_invalid {
@@ -254,6 +388,16 @@ impl<'a> InstVerifier<'a> {
         match unit[inst].opcode() {
             Opcode::ConstInt => {}
             Opcode::ConstTime => {}
+            Opcode::ConstEnum => {
+                let imm = unit[inst].get_const_enum().unwrap();
+                if imm.value >= imm.size {
+                    self.error(
+                        inst,
+                        ErrorCode::OutOfBounds,
+                        format!("enum value {} out of range n{}", imm.value, imm.size),
+                    );
+                }
+            }
             Opcode::Alias => {}
             Opcode::ArrayUniform => {}
             Opcode::Array => {
@@ -269,7 +413,7 @@ impl<'a> InstVerifier<'a> {
                 self.verify_bitwise_compatible_ty(inst);
                 self.verify_args_match_inst_ty(inst);
             }
-            Opcode::Neg => {
+            Opcode::Neg | Opcode::Popcount | Opcode::Clz | Opcode::Ctz => {
                 self.assert_inst_unary(inst);
                 self.verify_arith_compatible_ty(inst);
                 self.verify_args_match_inst_ty(inst);
@@ -277,25 +421,44 @@ impl<'a> InstVerifier<'a> {
             Opcode::Add
             | Opcode::Sub
             | Opcode::Smul
+            | Opcode::Smulh
             | Opcode::Sdiv
             | Opcode::Smod
             | Opcode::Srem
             | Opcode::Umul
+            | Opcode::Umulh
             | Opcode::Udiv
             | Opcode::Umod
-            | Opcode::Urem => {
+            | Opcode::Urem
+            | Opcode::Umin
+            | Opcode::Umax
+            | Opcode::Smin
+            | Opcode::Smax => {
                 self.assert_inst_binary(inst);
                 self.verify_arith_compatible_ty(inst);
-                self.verify_args_match_inst_ty(inst);
+                if self.verify_int_widths(inst) {
+                    self.verify_args_match_inst_ty(inst);
+                }
+            }
+            Opcode::Uaddo | Opcode::Saddo | Opcode::Usubo | Opcode::Ssubo => {
+                self.assert_inst_binary(inst);
+                self.verify_overflow_inst(inst);
             }
             Opcode::And | Opcode::Or | Opcode::Xor => {
                 self.assert_inst_binary(inst);
                 self.verify_bitwise_compatible_ty(inst);
-                self.verify_args_match_inst_ty(inst);
+                if self.verify_int_widths(inst) {
+                    self.verify_args_match_inst_ty(inst);
+                }
+            }
+            Opcode::Eq | Opcode::Neq => {
+                self.assert_inst_binary(inst);
+                self.verify_bool_ty(inst);
+                if self.verify_int_widths(inst) {
+                    self.verify_arg_tys_match(inst);
+                }
             }
-            Opcode::Eq
-            | Opcode::Neq
-            | Opcode::Slt
+            Opcode::Slt
             | Opcode::Sgt
             | Opcode::Sle
             | Opcode::Sge
@@ -305,7 +468,10 @@ impl<'a> InstVerifier<'a> {
             | Opcode::Uge => {
                 self.assert_inst_binary(inst);
                 self.verify_bool_ty(inst);
-                self.verify_arg_tys_match(inst);
+                if self.verify_int_widths(inst) {
+                    self.verify_arg_tys_match(inst);
+                }
+                self.verify_args_ordered(inst);
             }
             Opcode::Shl | Opcode::Shr => {
                 self.assert_inst_ternary(inst);
@@ -350,11 +516,23 @@ impl<'a> InstVerifier<'a> {
                 );
                 self.verify_arg_matches_ty(inst, unit[inst].args()[2], &time_ty());
             }
+            Opcode::DelCond => {
+                self.assert_inst_quaternary(inst);
+                self.verify_arg_ty_is_signal(inst, unit[inst].args()[0]);
+                self.verify_arg_matches_ty(
+                    inst,
+                    unit[inst].args()[1],
+                    &unit.value_type(unit[inst].args()[0]),
+                );
+                self.verify_arg_matches_ty(inst, unit[inst].args()[2], &time_ty());
+                self.verify_arg_matches_ty(inst, unit[inst].args()[3], &int_ty(1));
+            }
             Opcode::Call => {
                 // TODO: properly check argument types match the declaration
             }
             Opcode::Inst => {
                 // TODO: properly check argument types match the declaration
+                self.verify_inst_replication(inst);
             }
             Opcode::Sig => {
                 self.assert_inst_unary(inst);
@@ -384,6 +562,10 @@ impl<'a> InstVerifier<'a> {
                 self.assert_inst_binary(inst);
                 self.verify_st_inst(inst);
             }
+            Opcode::StCond => {
+                self.assert_inst_ternary(inst);
+                self.verify_st_inst(inst);
+            }
             Opcode::Halt => {}
             Opcode::Ret => {
                 self.assert_inst_nullary(inst);
@@ -566,11 +748,50 @@ impl<'a> InstVerifier<'a> {
                 .map(|&arg| self.unit.value_type(arg).to_string())
                 .collect();
             let tys: String = tys.join(", ");
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("argument types must match (but are {})", tys),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("argument types must match (but are {})", tys),
+            );
+        }
+    }
+
+    /// Verify that the integer arguments of an instruction have equal widths.
+    ///
+    /// Returns `false` if a width mismatch was reported, in which case the
+    /// more general type checks are skipped to avoid duplicate errors.
+    fn verify_int_widths(&mut self, inst: Inst) -> bool {
+        let widths: Vec<_> = self.unit()[inst]
+            .args()
+            .iter()
+            .map(|&arg| self.unit.value_type(arg))
+            .filter(|ty| ty.is_int())
+            .map(|ty| ty.unwrap_int())
+            .collect();
+        if widths.len() < 2 || widths.iter().all(|&w| w == widths[0]) {
+            return true;
+        }
+        let tys: Vec<_> = widths.iter().map(|w| format!("i{}", w)).collect();
+        self.error(
+            inst,
+            ErrorCode::WidthMismatch,
+            format!("operand widths must match (but are {})", tys.join(", ")),
+        );
+        false
+    }
+
+    /// Verify that the arguments of an instruction have an ordering.
+    ///
+    /// The states of an enum are unordered and can only be compared for
+    /// equality.
+    fn verify_args_ordered(&mut self, inst: Inst) {
+        let ty = self.unit.value_type(self.unit()[inst].args()[0]);
+        if ty.is_enum() {
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("{} values can only be compared with eq and neq", ty),
+            );
         }
     }
 
@@ -578,11 +799,29 @@ impl<'a> InstVerifier<'a> {
     fn verify_arg_ty_is_signal(&mut self, inst: Inst, arg: Value) {
         let ty = self.unit.value_type(arg);
         if !ty.is_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("argument {} type must be a signal (but is {})", arg, ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("argument {} type must be a signal (but is {})", arg, ty),
+            );
+        }
+    }
+
+    /// Verify that an instruction with an overflow flag operates on integers
+    /// and returns a `{iN, i1}` struct.
+    fn verify_overflow_inst(&mut self, inst: Inst) {
+        let ty = self.unit.value_type(self.unit()[inst].args()[0]);
+        if !ty.is_int() {
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("argument type must be iN (but is {})", ty),
+            );
+            return;
+        }
+        if self.verify_int_widths(inst) {
+            self.verify_arg_tys_match(inst);
+            self.verify_inst_ty(inst, &struct_ty(vec![ty, int_ty(1)]));
         }
     }
 
@@ -604,14 +843,14 @@ impl<'a> InstVerifier<'a> {
     fn verify_arg_matches_ty(&mut self, inst: Inst, arg: Value, ty: &Type) {
         let arg_ty = self.unit.value_type(arg);
         if arg_ty != *ty {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "argument {} must be of type {} (but is {})",
                     arg, ty, arg_ty,
                 ),
-            });
+            );
         }
     }
 
@@ -621,11 +860,11 @@ impl<'a> InstVerifier<'a> {
         if ty.is_int() && ty.unwrap_int() == 1 {
             return;
         }
-        self.verifier.errors.push(VerifierError {
-            unit: self.verifier.unit_name.clone(),
-            object: Some(inst.dump(&self.unit).to_string()),
-            message: format!("return type must be i1 (but is {})", ty),
-        });
+        self.error(
+            inst,
+            ErrorCode::TypeMismatch,
+            format!("return type must be i1 (but is {})", ty),
+        );
     }
 
     /// Verify that an instruction's return type is compatible with bitwise
@@ -644,22 +883,22 @@ impl<'a> InstVerifier<'a> {
         if ty.is_signal() && ty.unwrap_signal().is_int() {
             return;
         }
-        self.verifier.errors.push(VerifierError {
-            unit: self.verifier.unit_name.clone(),
-            object: Some(inst.dump(&self.unit).to_string()),
-            message: format!("return type must be iN or iN$ (but is {})", ty),
-        });
+        self.error(
+            inst,
+            ErrorCode::TypeMismatch,
+            format!("return type must be iN or iN$ (but is {})", ty),
+        );
     }
 
     /// Verify that an instruction produces a result of a given type.
     fn verify_inst_ty(&mut self, inst: Inst, ty: &Type) {
         let inst_ty = self.unit.inst_type(inst);
         if inst_ty != *ty {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("return type must be {} (but is {})", ty, inst_ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("return type must be {} (but is {})", ty, inst_ty),
+            );
         }
     }
 
@@ -672,28 +911,28 @@ impl<'a> InstVerifier<'a> {
         self.verify_arg_matches_ty(inst, base, &ty);
         let amount_ty = self.unit.value_type(amount);
         if !amount_ty.is_int() && !(amount_ty.is_signal() && amount_ty.unwrap_signal().is_int()) {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "type of shift amount must be iN or iN$ (but is {})",
                     amount_ty
                 ),
-            });
+            );
         }
         let base_ty = self.unit.value_type(base);
         let hidden_ty = self.unit.value_type(hidden);
         if base_ty.is_signal() != hidden_ty.is_signal()
             || base_ty.is_pointer() != hidden_ty.is_pointer()
         {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "shift base and hidden value types must be compatible (but are {} and {})",
                     base_ty, hidden_ty
                 ),
-            });
+            );
         }
         let (base_inner_ty, hidden_inner_ty) = if ty.is_signal() {
             (ty.unwrap_signal(), hidden_ty.unwrap_signal())
@@ -711,14 +950,14 @@ impl<'a> InstVerifier<'a> {
         {
             return;
         }
-        self.verifier.errors.push(VerifierError {
-            unit: self.verifier.unit_name.clone(),
-            object: Some(inst.dump(&self.unit).to_string()),
-            message: format!(
+        self.error(
+            inst,
+            ErrorCode::TypeMismatch,
+            format!(
                 "shift base and hidden value types must be compatible (but are {} and {})",
                 base_ty, hidden_ty
             ),
-        });
+        );
     }
 
     /// Verify that the types of a mux instruction line up.
@@ -727,23 +966,23 @@ impl<'a> InstVerifier<'a> {
         let array = self.unit()[inst].args()[0];
         let array_ty = self.unit.value_type(array);
         if !array_ty.is_array() || array_ty.unwrap_array().1 != &ty {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "array {} element and return type {} must agree",
                     array_ty, ty
                 ),
-            });
+            );
         }
         let sel = self.unit()[inst].args()[1];
         let sel_ty = self.unit.value_type(sel);
         if !sel_ty.is_int() && !(sel_ty.is_signal() && sel_ty.unwrap_signal().is_int()) {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type of selector must be iN or iN$ (but is {})", sel_ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type of selector must be iN or iN$ (but is {})", sel_ty),
+            );
         }
     }
 
@@ -770,6 +1009,66 @@ impl<'a> InstVerifier<'a> {
                 self.verify_arg_matches_ty(inst, arg, &int_ty(1));
             }
         }
+        self.verify_reg_controls(inst, &ty);
+    }
+
+    /// Verify the optional controls of a reg instruction.
+    fn verify_reg_controls(&mut self, inst: Inst, ty: &Type) {
+        let (num_args, num_modes, controls) = match &self.unit()[inst] {
+            InstData::Reg {
+                args,
+                modes,
+                controls,
+                ..
+            } => (args.len(), modes.len(), controls.clone()),
+            _ => return,
+        };
+        let mut errors = vec![];
+        if num_args != 1 + 3 * num_modes + controls.len() {
+            errors.push(format!(
+                "reg with {} triggers and {} controls must have {} arguments (but has {})",
+                num_modes,
+                controls.len(),
+                1 + 3 * num_modes + controls.len(),
+                num_args
+            ));
+        }
+        let rank = |control: &RegControl| match control {
+            RegControl::Init => 0,
+            RegControl::Enable => 1,
+            RegControl::Clear(_) => 2,
+            RegControl::Preset(_) => 3,
+        };
+        if controls.windows(2).any(|w| rank(&w[0]) >= rank(&w[1])) {
+            errors.push(
+                "reg controls must be unique and ordered as init, enable, clear, preset"
+                    .to_string(),
+            );
+        }
+        for (control, arg) in self.unit()[inst].control_args().collect::<Vec<_>>() {
+            match control {
+                RegControl::Init => {
+                    self.verify_arg_matches_ty(inst, arg, ty);
+                    self.verify_constant_init(inst, arg);
+                }
+                RegControl::Enable => self.verify_arg_matches_ty(inst, arg, &int_ty(1)),
+                RegControl::Clear(mode) | RegControl::Preset(mode) => {
+                    self.verify_arg_matches_ty(inst, arg, &int_ty(1));
+                    if mode != RegMode::Low && mode != RegMode::High {
+                        errors.push(format!("{} must be level-sensitive", control));
+                    }
+                    if !ty.is_int() {
+                        errors.push(format!(
+                            "{} requires an integer register (but stores {})",
+                            control, ty
+                        ));
+                    }
+                }
+            }
+        }
+        for message in errors {
+            self.error(inst, ErrorCode::InvalidRegControls, message);
+        }
     }
 
     /// Determine the field type for an insf/extf instruction.
@@ -788,28 +1087,39 @@ impl<'a> InstVerifier<'a> {
             match target_ty.unwrap_struct().get(field) {
                 Some(ty) => Some(ty.clone()),
                 None => {
-                    self.verifier.errors.push(VerifierError {
-                        unit: self.verifier.unit_name.clone(),
-                        object: Some(inst.dump(&self.unit).to_string()),
-                        message: format!(
+                    self.error(
+                        inst,
+                        ErrorCode::OutOfBounds,
+                        format!(
                             "field index {} out of bounds of struct type {}",
                             field, target_ty
                         ),
-                    });
+                    );
                     None
                 }
             }
         } else if target_ty.is_array() {
-            Some(target_ty.unwrap_array().1.clone())
+            let (array_len, elem_ty) = target_ty.unwrap_array();
+            if field >= array_len {
+                self.error(
+                    inst,
+                    ErrorCode::OutOfBounds,
+                    format!(
+                        "field index {} out of bounds of array type {}",
+                        field, target_ty
+                    ),
+                );
+            }
+            Some(elem_ty.clone())
         } else {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "target must be of struct or array type (but is {})",
                     target_ty
                 ),
-            });
+            );
             None
         };
         ty.map(wrap)
@@ -831,39 +1141,39 @@ impl<'a> InstVerifier<'a> {
         let ty = if target_ty.is_array() {
             let (array_len, elem_ty) = target_ty.unwrap_array();
             if array_len < offset + length {
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&self.unit).to_string()),
-                    message: format!(
+                self.error(
+                    inst,
+                    ErrorCode::OutOfBounds,
+                    format!(
                         "access {}..{} out of array bounds 0..{}",
                         offset,
                         offset + length,
                         array_len
                     ),
-                });
+                );
             }
             Some(array_ty(length, elem_ty.clone()))
         } else if target_ty.is_int() {
             let size = target_ty.unwrap_int();
             if size < offset + length {
-                self.verifier.errors.push(VerifierError {
-                    unit: self.verifier.unit_name.clone(),
-                    object: Some(inst.dump(&self.unit).to_string()),
-                    message: format!(
+                self.error(
+                    inst,
+                    ErrorCode::OutOfBounds,
+                    format!(
                         "access {}..{} out of integer bounds 0..{}",
                         offset,
                         offset + length,
                         size
                     ),
-                });
+                );
             }
             Some(int_ty(length))
         } else {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("target must be of array or iN type (but is {})", target_ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("target must be of array or iN type (but is {})", target_ty),
+            );
             None
         };
         ty.map(wrap)
@@ -913,13 +1223,95 @@ impl<'a> InstVerifier<'a> {
     fn verify_sig_inst(&mut self, inst: Inst) {
         let ty = self.unit.inst_type(inst);
         if !ty.is_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a signal", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a signal", ty),
+            );
         }
         self.verify_args_match_ty(inst, ty.unwrap_signal());
+        let init = self.unit()[inst].args()[0];
+        self.verify_constant_init(inst, init);
+        match self.unit.signal_resolution(inst) {
+            Some(res @ Resolution::WiredOr) | Some(res @ Resolution::WiredAnd)
+                if !ty.unwrap_signal().is_int() =>
+            {
+                self.error(
+                    inst,
+                    ErrorCode::InvalidResolution,
+                    format!("{} resolution requires an integer signal, got {}", res, ty),
+                );
+            }
+            _ => (),
+        }
+    }
+
+    /// Verify that the arguments of a replicated instance connect to each copy,
+    /// i.e. are of the port's type or a signal of an array of one element per
+    /// copy.
+    fn verify_inst_replication(&mut self, inst: Inst) {
+        let count = match self.unit.inst_replication(inst) {
+            Some(count) => count,
+            None => return,
+        };
+        let unit = self.unit;
+        let sig = &unit[unit[inst].get_ext_unit().unwrap()].sig;
+        let args = unit[inst]
+            .input_args()
+            .iter()
+            .chain(unit[inst].output_args());
+        for (&arg, port) in args.zip(sig.args()) {
+            let port = sig.arg_type(port);
+            let ty = unit.value_type(arg);
+            if ty == port {
+                continue;
+            }
+            if port.is_signal() && ty == signal_ty(array_ty(count, port.unwrap_signal().clone())) {
+                continue;
+            }
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
+                    "argument {} of type {} does not connect to port of type {} in {} copies",
+                    arg.dump(&unit),
+                    ty,
+                    port,
+                    count
+                ),
+            );
+        }
+    }
+
+    /// Verify that an initial value in an entity is known before simulation
+    /// starts, i.e. does not depend on the value of a signal.
+    fn verify_constant_init(&mut self, inst: Inst, init: Value) {
+        if self.unit.is_entity() && !self.is_constant(init) {
+            self.error(
+                inst,
+                ErrorCode::NonConstantInit,
+                format!("initial value {} must be constant", init.dump(&self.unit)),
+            );
+        }
+    }
+
+    /// Check if a value is computed only from constants.
+    fn is_constant(&self, value: Value) -> bool {
+        if value.is_invalid() {
+            return false;
+        }
+        match self.unit.get_value_inst(value) {
+            Some(inst) => {
+                let opcode = self.unit()[inst].opcode();
+                opcode.is_const()
+                    || opcode.is_pure()
+                        && self.unit()[inst]
+                            .args()
+                            .iter()
+                            .all(|&arg| self.is_constant(arg))
+            }
+            None => false,
+        }
     }
 
     /// Verify that the types of a prb instruction line up.
@@ -927,18 +1319,18 @@ impl<'a> InstVerifier<'a> {
         let ty = self.unit.inst_type(inst);
         let arg_ty = self.unit.value_type(self.unit()[inst].args()[0]);
         if !arg_ty.is_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a signal", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a signal", ty),
+            );
         }
         if ty != *arg_ty.unwrap_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be signal of return type {}", arg_ty, ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be signal of return type {}", arg_ty, ty),
+            );
         }
     }
 
@@ -947,21 +1339,21 @@ impl<'a> InstVerifier<'a> {
         let ty = self.unit.value_type(self.unit()[inst].args()[1]);
         let arg_ty = self.unit.value_type(self.unit()[inst].args()[0]);
         if !arg_ty.is_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a signal", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a signal", ty),
+            );
         }
         if ty != *arg_ty.unwrap_signal() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "drive target type {} must be signal of driven value type {}",
                     arg_ty, ty
                 ),
-            });
+            );
         }
         self.verify_arg_matches_ty(inst, self.unit()[inst].args()[2], &time_ty());
         if self.unit()[inst].opcode() == Opcode::DrvCond {
@@ -973,11 +1365,11 @@ impl<'a> InstVerifier<'a> {
     fn verify_var_inst(&mut self, inst: Inst) {
         let ty = self.unit.inst_type(inst);
         if !ty.is_pointer() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a pointer", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a pointer", ty),
+            );
         }
         self.verify_args_match_ty(inst, ty.unwrap_pointer());
     }
@@ -987,18 +1379,18 @@ impl<'a> InstVerifier<'a> {
         let ty = self.unit.inst_type(inst);
         let arg_ty = self.unit.value_type(self.unit()[inst].args()[0]);
         if !arg_ty.is_pointer() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a pointer", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a pointer", ty),
+            );
         }
         if ty != *arg_ty.unwrap_pointer() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be pointer of return type {}", arg_ty, ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be pointer of return type {}", arg_ty, ty),
+            );
         }
     }
 
@@ -1007,21 +1399,24 @@ impl<'a> InstVerifier<'a> {
         let ty = self.unit.value_type(self.unit()[inst].args()[1]);
         let arg_ty = self.unit.value_type(self.unit()[inst].args()[0]);
         if !arg_ty.is_pointer() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!("type {} must be a pointer", ty),
-            });
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!("type {} must be a pointer", ty),
+            );
         }
         if ty != *arg_ty.unwrap_pointer() {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "store target type {} must be pointer of stored value type {}",
                     arg_ty, ty
                 ),
-            });
+            );
+        }
+        if self.unit()[inst].opcode() == Opcode::StCond {
+            self.verify_arg_matches_ty(inst, self.unit()[inst].args()[2], &int_ty(1));
         }
     }
 
@@ -1030,25 +1425,109 @@ impl<'a> InstVerifier<'a> {
     fn verify_return_type(&mut self, inst: Inst, ty: &Type) {
         let func_ty = self.return_type.clone().unwrap_or_else(void_ty);
         if func_ty != *ty {
-            self.verifier.errors.push(VerifierError {
-                unit: self.verifier.unit_name.clone(),
-                object: Some(inst.dump(&self.unit).to_string()),
-                message: format!(
+            self.error(
+                inst,
+                ErrorCode::TypeMismatch,
+                format!(
                     "requires function to have return type {} (but has {})",
                     ty, func_ty
                 ),
-            });
+            );
+        }
+    }
+}
+
+/// The kind of a verification error.
+///
+/// Each kind has a stable error code that tools can match on, see `code()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// A unit has no entry block.
+    NoEntryBlock,
+    /// A block contains no instructions.
+    EmptyBlock,
+    /// A terminator appears in the middle of a block, or a block does not end
+    /// in a terminator.
+    MisplacedTerminator,
+    /// A value does not dominate one of its uses.
+    DominanceViolation,
+    /// An instruction may not appear in the kind of unit it is in.
+    InvalidInUnit,
+    /// An instruction uses an invalid or undefined value or block.
+    UndefinedOperand,
+    /// The types of an instruction's arguments or result do not line up.
+    TypeMismatch,
+    /// A field or slice access lies outside of its target type.
+    OutOfBounds,
+    /// The controls of a `reg` instruction are malformed.
+    InvalidRegControls,
+    /// A synthesizable unit calls or instantiates a simulation-only unit.
+    SimOnlyUse,
+    /// The integer operands of an instruction have different widths.
+    WidthMismatch,
+    /// The initial value of a signal or register in an entity depends on the
+    /// value of a signal.
+    NonConstantInit,
+    /// The resolution of a signal does not fit the type it carries, or its
+    /// resolution function does not exist or has the wrong signature.
+    InvalidResolution,
+}
+
+impl ErrorCode {
+    /// Get the stable error code, e.g. `E0007`.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::NoEntryBlock => "E0001",
+            ErrorCode::EmptyBlock => "E0002",
+            ErrorCode::MisplacedTerminator => "E0003",
+            ErrorCode::DominanceViolation => "E0004",
+            ErrorCode::InvalidInUnit => "E0005",
+            ErrorCode::UndefinedOperand => "E0006",
+            ErrorCode::TypeMismatch => "E0007",
+            ErrorCode::OutOfBounds => "E0008",
+            ErrorCode::InvalidRegControls => "E0009",
+            ErrorCode::SimOnlyUse => "E0010",
+            ErrorCode::WidthMismatch => "E0011",
+            ErrorCode::NonConstantInit => "E0012",
+            ErrorCode::InvalidResolution => "E0013",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// The object that caused a verification error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorObject {
+    /// A basic block, given by its name.
+    Block(String),
+    /// An instruction, given in human-readable form.
+    Inst(String),
+}
+
+impl Display for ErrorObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErrorObject::Block(x) | ErrorObject::Inst(x) => write!(f, "{}", x),
         }
     }
 }
 
 /// A verification error.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VerifierError {
+    /// The kind of error.
+    pub code: ErrorCode,
     /// The unit within which caused the error.
     pub unit: Option<String>,
     /// The object which caused the error.
-    pub object: Option<String>,
+    pub object: Option<ErrorObject>,
+    /// The byte offset of the object in the input file, if known.
+    pub location: Option<usize>,
     /// The error message.
     pub message: String,
 }
@@ -1067,7 +1546,7 @@ impl Display for VerifierError {
 }
 
 /// A list of verification errors.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct VerifierErrors(pub Vec<VerifierError>);
 
 impl Deref for VerifierErrors {
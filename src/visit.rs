@@ -0,0 +1,317 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Visitors over the units of a module.
+//!
+//! The `Visitor` and `VisitorMut` traits walk the units and declarations of a
+//! module, the arguments and blocks of each unit in layout order, the
+//! instructions of each block, and the operands of each instruction. Each
+//! `visit_*` method defaults to the corresponding `walk_*` function, which
+//! visits the children of the node. Implementors override the methods for the
+//! nodes they are interested in, and call the `walk_*` function from there if
+//! the walk should continue into the children.
+//!
+//! Entities consist of a single block terminated by a `halt`, both of which
+//! are implicit in the assembly. The walkers visit neither, but go straight to
+//! the instructions of an entity.
+//!
+//! ```
+//! use llhd::{assembly::parse_module, ir::prelude::*, visit::Visitor};
+//!
+//! struct CountInsts(usize);
+//!
+//! impl Visitor for CountInsts {
+//!     fn visit_inst(&mut self, _unit: Unit, _inst: Inst) {
+//!         self.0 += 1;
+//!     }
+//! }
+//!
+//! let module = parse_module("entity @foo (i1$ %a) -> () { %b = prb i1$ %a }").unwrap();
+//! let mut count = CountInsts(0);
+//! count.visit_module(&module);
+//! assert_eq!(count.0, 1);
+//! ```
+
+use crate::ir::prelude::*;
+
+/// A visitor over the units of a module.
+pub trait Visitor {
+    /// Visit a module.
+    fn visit_module(&mut self, module: &Module) {
+        walk_module(self, module)
+    }
+
+    /// Visit a unit.
+    fn visit_unit(&mut self, unit: Unit) {
+        walk_unit(self, unit)
+    }
+
+    /// Visit a declaration.
+    #[allow(unused_variables)]
+    fn visit_decl(&mut self, module: &Module, decl: DeclId) {}
+
+    /// Visit an argument of a unit.
+    #[allow(unused_variables)]
+    fn visit_arg(&mut self, unit: Unit, arg: Value) {}
+
+    /// Visit a block of a function or process.
+    fn visit_block(&mut self, unit: Unit, bb: Block) {
+        walk_block(self, unit, bb)
+    }
+
+    /// Visit an instruction.
+    fn visit_inst(&mut self, unit: Unit, inst: Inst) {
+        walk_inst(self, unit, inst)
+    }
+
+    /// Visit a value used as an argument of an instruction.
+    #[allow(unused_variables)]
+    fn visit_operand(&mut self, unit: Unit, inst: Inst, value: Value) {}
+}
+
+/// Visit the units and then the declarations of a module.
+pub fn walk_module<V: Visitor + ?Sized>(visitor: &mut V, module: &Module) {
+    for unit in module.units() {
+        visitor.visit_unit(unit);
+    }
+    for decl in module.decls() {
+        visitor.visit_decl(module, decl);
+    }
+}
+
+/// Visit the arguments of a unit, followed by its blocks or, for entities,
+/// its instructions.
+pub fn walk_unit<V: Visitor + ?Sized>(visitor: &mut V, unit: Unit) {
+    for arg in unit.args() {
+        visitor.visit_arg(unit, arg);
+    }
+    if unit.is_entity() {
+        for inst in entity_insts(unit) {
+            visitor.visit_inst(unit, inst);
+        }
+    } else {
+        for bb in unit.blocks() {
+            visitor.visit_block(unit, bb);
+        }
+    }
+}
+
+/// Visit the instructions of a block.
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, unit: Unit, bb: Block) {
+    for inst in unit.insts(bb) {
+        visitor.visit_inst(unit, inst);
+    }
+}
+
+/// Visit the operands of an instruction.
+pub fn walk_inst<V: Visitor + ?Sized>(visitor: &mut V, unit: Unit, inst: Inst) {
+    for &value in unit[inst].args() {
+        visitor.visit_operand(unit, inst, value);
+    }
+}
+
+/// A visitor over the units of a module which may modify them.
+///
+/// The walkers take a snapshot of the blocks and instructions before visiting
+/// them, and skip the ones that have been removed in the meantime. Blocks and
+/// instructions added during the walk are not visited.
+pub trait VisitorMut {
+    /// Visit a module.
+    ///
+    /// Marks all units as dirty.
+    fn visit_module(&mut self, module: &mut Module) {
+        walk_module_mut(self, module)
+    }
+
+    /// Visit a unit.
+    fn visit_unit(&mut self, unit: &mut UnitBuilder) {
+        walk_unit_mut(self, unit)
+    }
+
+    /// Visit a declaration.
+    #[allow(unused_variables)]
+    fn visit_decl(&mut self, module: &mut Module, decl: DeclId) {}
+
+    /// Visit an argument of a unit.
+    #[allow(unused_variables)]
+    fn visit_arg(&mut self, unit: &mut UnitBuilder, arg: Value) {}
+
+    /// Visit a block of a function or process.
+    fn visit_block(&mut self, unit: &mut UnitBuilder, bb: Block) {
+        walk_block_mut(self, unit, bb)
+    }
+
+    /// Visit an instruction.
+    fn visit_inst(&mut self, unit: &mut UnitBuilder, inst: Inst) {
+        walk_inst_mut(self, unit, inst)
+    }
+
+    /// Visit a value used as an argument of an instruction.
+    #[allow(unused_variables)]
+    fn visit_operand(&mut self, unit: &mut UnitBuilder, inst: Inst, value: Value) {}
+}
+
+/// Visit the units and then the declarations of a module, allowing
+/// modifications.
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(visitor: &mut V, module: &mut Module) {
+    for mut unit in module.units_mut() {
+        visitor.visit_unit(&mut unit);
+    }
+    let decls: Vec<_> = module.decls().collect();
+    for decl in decls {
+        visitor.visit_decl(module, decl);
+    }
+}
+
+/// Visit the arguments of a unit, followed by its blocks or, for entities,
+/// its instructions, allowing modifications.
+pub fn walk_unit_mut<V: VisitorMut + ?Sized>(visitor: &mut V, unit: &mut UnitBuilder) {
+    let args: Vec<_> = unit.args().collect();
+    for arg in args {
+        visitor.visit_arg(unit, arg);
+    }
+    if unit.is_entity() {
+        let insts: Vec<_> = entity_insts(**unit).collect();
+        for inst in insts {
+            if unit.is_inst_inserted(inst) {
+                visitor.visit_inst(unit, inst);
+            }
+        }
+    } else {
+        let blocks: Vec<_> = unit.blocks().collect();
+        for bb in blocks {
+            if unit.is_block_inserted(bb) {
+                visitor.visit_block(unit, bb);
+            }
+        }
+    }
+}
+
+/// Visit the instructions of a block, allowing modifications.
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, unit: &mut UnitBuilder, bb: Block) {
+    let insts: Vec<_> = unit.insts(bb).collect();
+    for inst in insts {
+        if unit.is_inst_inserted(inst) {
+            visitor.visit_inst(unit, inst);
+        }
+    }
+}
+
+/// Visit the operands of an instruction, allowing modifications.
+pub fn walk_inst_mut<V: VisitorMut + ?Sized>(visitor: &mut V, unit: &mut UnitBuilder, inst: Inst) {
+    let args = unit[inst].args().to_vec();
+    for value in args {
+        visitor.visit_operand(unit, inst, value);
+    }
+}
+
+/// The instructions of an entity, without the implicit `halt`.
+fn entity_insts<'a>(unit: Unit<'a>) -> impl Iterator<Item = Inst> + 'a {
+    unit.all_insts()
+        .filter(move |&inst| unit[inst].opcode() != Opcode::Halt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_module;
+
+    /// Records the nodes visited by a walk.
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl Visitor for Trace {
+        fn visit_unit(&mut self, unit: Unit) {
+            self.0.push(format!("unit {}", unit.name()));
+            walk_unit(self, unit);
+        }
+
+        fn visit_decl(&mut self, module: &Module, decl: DeclId) {
+            self.0.push(format!("decl {}", module[decl].name));
+        }
+
+        fn visit_arg(&mut self, unit: Unit, arg: Value) {
+            self.0.push(format!("arg {}", arg.dump(&unit)));
+        }
+
+        fn visit_block(&mut self, unit: Unit, bb: Block) {
+            self.0.push(format!("block {}", bb.dump(&unit)));
+            walk_block(self, unit, bb);
+        }
+
+        fn visit_inst(&mut self, unit: Unit, inst: Inst) {
+            self.0.push(format!("inst {}", unit[inst].opcode()));
+            walk_inst(self, unit, inst);
+        }
+
+        fn visit_operand(&mut self, unit: Unit, _inst: Inst, value: Value) {
+            self.0.push(format!("operand {}", value.dump(&unit)));
+        }
+    }
+
+    #[test]
+    fn walk_entities_and_functions() {
+        let module = parse_module(
+            "
+            func @f (i8 %x) i8 {
+            %entry:
+                br %next
+            %next:
+                ret i8 %x
+            }
+            entity @e (i8$ %s) -> () {
+                %v = prb i8$ %s
+            }
+            declare @g () -> ()
+            ",
+        )
+        .unwrap();
+        let mut trace = Trace::default();
+        trace.visit_module(&module);
+        assert_eq!(
+            trace.0,
+            vec![
+                "unit @f",
+                "arg %x",
+                "block %entry",
+                "inst br",
+                "block %next",
+                "inst ret",
+                "operand %x",
+                "unit @e",
+                "arg %s",
+                "inst prb",
+                "operand %s",
+                "decl @g",
+            ]
+        );
+    }
+
+    /// Removes unused constants.
+    struct PruneConsts;
+
+    impl VisitorMut for PruneConsts {
+        fn visit_inst(&mut self, unit: &mut UnitBuilder, inst: Inst) {
+            if unit[inst].opcode() == Opcode::ConstInt {
+                unit.prune_if_unused(inst);
+            }
+        }
+    }
+
+    #[test]
+    fn walk_and_modify() {
+        let mut module = parse_module(
+            "
+            entity @e (i8$ %s) -> () {
+                %a = const i8 1
+                %b = const i8 2
+                %t = const time 0s
+                drv i8$ %s, %b, %t
+            }
+            ",
+        )
+        .unwrap();
+        PruneConsts.visit_module(&mut module);
+        let unit = module.units().next().unwrap();
+        assert_eq!(entity_insts(unit).count(), 3);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! JavaScript entry points for WebAssembly.
+//!
+//! This module exposes the parser, the writer, the verifier, and the
+//! optimization passes to JavaScript via `wasm-bindgen`, such that LLHD
+//! assembly can be explored in the browser. It is only available with the
+//! `wasm` feature. Since the `wasm32-unknown-unknown` target has neither
+//! threads nor a file system, the default features must be disabled:
+//!
+//! ```text
+//! cargo build --release --lib --target wasm32-unknown-unknown --no-default-features --features wasm
+//! wasm-bindgen --target web target/wasm32-unknown-unknown/release/llhd.wasm --out-dir pkg
+//! ```
+//!
+//! The resulting package is used as follows:
+//!
+//! ```text
+//! import init, { Module } from "./pkg/llhd.js";
+//! await init();
+//! const module = Module.parse(source);
+//! module.verify();
+//! module.runPasses("cf,dce");
+//! console.log(module.unitNames(), module.print());
+//! ```
+//!
+//! Errors are thrown as exceptions carrying a message.
+
+use crate::{
+    assembly::{parse_module, write_module_string},
+    ir,
+    opt::prelude::*,
+    pass::run_by_name,
+    verifier::Verifier,
+};
+use wasm_bindgen::prelude::*;
+
+/// An LLHD module.
+#[wasm_bindgen(js_name = Module)]
+pub struct WasmModule {
+    module: ir::Module,
+}
+
+#[wasm_bindgen(js_class = Module)]
+impl WasmModule {
+    /// Parse a module in assembly format.
+    pub fn parse(src: &str) -> Result<WasmModule, JsValue> {
+        let module = parse_module(src).map_err(|e| JsValue::from_str(&e))?;
+        Ok(Self { module })
+    }
+
+    /// Print the module in assembly format.
+    pub fn print(&self) -> String {
+        write_module_string(&self.module)
+    }
+
+    /// Verify the integrity of the module, throwing the list of problems if
+    /// it is invalid.
+    pub fn verify(&self) -> Result<(), JsValue> {
+        let mut verifier = Verifier::new();
+        verifier.verify_module(&self.module);
+        verifier
+            .finish()
+            .map_err(|errs| JsValue::from_str(&errs.to_string()))
+    }
+
+    /// Run a pipeline of passes, given by their `llhd-opt` names in a
+    /// comma-separated list. Returns whether any pass modified the module.
+    #[wasm_bindgen(js_name = runPasses)]
+    pub fn run_passes(&mut self, passes: &str) -> Result<bool, JsValue> {
+        let ctx = PassContext::default();
+        let mut modified = false;
+        for name in passes.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            modified |= run_by_name(name, &ctx, &mut self.module)
+                .ok_or_else(|| JsValue::from_str(&format!("unknown pass `{}`", name)))?;
+        }
+        Ok(modified)
+    }
+
+    /// The names of the units defined in the module, e.g. `@foo`.
+    #[wasm_bindgen(js_name = unitNames)]
+    pub fn unit_names(&self) -> Vec<String> {
+        self.module.units().map(|u| u.name().to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_optimize_print() {
+        let mut module = WasmModule::parse(
+            "
+            func @foo () i8 {
+            entry:
+                %a = const i8 1
+                %b = add i8 %a, %a
+                ret i8 %b
+            }
+            ",
+        )
+        .unwrap();
+        assert!(module.verify().is_ok());
+        assert_eq!(module.run_passes("cf, dce").ok(), Some(true));
+        assert_eq!(module.unit_names(), vec!["@foo"]);
+        assert!(module.print().contains("%b = const i8 2"));
+    }
+}
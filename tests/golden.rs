@@ -0,0 +1,59 @@
+// Copyright (c) 2017-2020 Fabian Schuiki
+
+//! Golden-file tests of the optimization passes.
+//!
+//! Runs the tests in `test/opt` for the passes listed below in-process, and
+//! checks their output against the embedded `CHECK` directives.
+
+use std::path::Path;
+
+/// Run all tests in a subdirectory of `test/opt`.
+fn run_dir(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test/opt")
+        .join(name);
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|e| e == "llhd").unwrap_or(false))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no tests in {}", dir.display());
+    let failures: Vec<_> = paths
+        .iter()
+        .filter_map(|path| llhd::filecheck::run_file(path).err())
+        .collect();
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} tests failed:\n\n{}",
+            failures.len(),
+            paths.len(),
+            failures.join("\n")
+        );
+    }
+}
+
+#[test]
+fn cf() {
+    run_dir("cf");
+}
+
+#[test]
+fn dce() {
+    run_dir("dce");
+}
+
+#[test]
+fn gcse() {
+    run_dir("gcse");
+}
+
+#[test]
+fn insim() {
+    run_dir("insim");
+}
+
+#[test]
+fn tcm() {
+    run_dir("tcm");
+}